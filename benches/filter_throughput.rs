@@ -0,0 +1,74 @@
+// Times bgzf_filter_with over a large synthetic family file for a rare term, with and
+// without the substring pre-filter, after checking both give the same output.
+//
+//     cargo bench --bench filter_throughput
+//
+// TE_IDX_BENCH_RECORDS sets the records of the family file, 2,000,000 by default.
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use noodles::bgzf;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use te_idx::options::FilterOptions;
+use te_idx::{bgzf_filter_with, filter_worker_count, ASSEMBLY_DIR, INTERNAL_PROFILE};
+use tempfile::TempDir;
+
+// BED column of the sequence name
+const SEQ_COLUMN: usize = 1;
+
+fn filter_throughput(c: &mut Criterion) {
+    let records: usize = std::env::var("TE_IDX_BENCH_RECORDS")
+        .map_or(2_000_000, |s| s.parse().expect("Invalid Record Count"));
+
+    let working_dir = TempDir::new().expect("Error Creating Working Directory");
+    let data_directory = working_dir.path().display().to_string();
+    let assembly = "throughput".to_string();
+    let fam = "DF000000001".to_string();
+    let fam_dir = format!("{}/{}/{}", data_directory, assembly, ASSEMBLY_DIR);
+    create_dir_all(&fam_dir).expect("Can't Create Dir");
+    let mut writer = bgzf::MultithreadedWriter::with_worker_count(
+        filter_worker_count(u64::MAX),
+        File::create(format!("{}/{}.bed.bgz", fam_dir, fam)).expect("Can't Create File"),
+    );
+    // One record in ten thousand is on the rare sequence
+    for i in 0..records {
+        let seq = if i % 10_000 == 0 { "chrRare" } else { "chr1" };
+        let start = 100 + i as u64 * 10;
+        writeln!(
+            writer,
+            "{seq}\t{start}\t{}\t{fam}\t250\t+\t1.0\t1\t300\t1\t300\t1e-30\t1\t10.5\tTEST\t1000000\t300M\tCAF",
+            start + 299
+        )
+        .expect("Unable To Write Line");
+    }
+    writer.finish().expect("Unable To Finish File");
+
+    let filter = |prefilter: bool| -> Vec<u8> {
+        let options = FilterOptions::builder()
+            .term(SEQ_COLUMN, "chrRare")
+            .profile(INTERNAL_PROFILE)
+            .prefilter(prefilter)
+            .build();
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            &assembly,
+            ASSEMBLY_DIR,
+            &fam,
+            &mut output,
+            &options,
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        output
+    };
+    assert_eq!(filter(false), filter(true), "Pre-Filter Changed The Output");
+
+    let mut group = c.benchmark_group("bgzf_filter_with");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(records as u64));
+    group.bench_function("split every line", |b| b.iter(|| filter(false)));
+    group.bench_function("pre-filtered", |b| b.iter(|| filter(true)));
+    group.finish();
+}
+
+criterion_group!(benches, filter_throughput);
+criterion_main!(benches);
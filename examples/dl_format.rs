@@ -0,0 +1,34 @@
+// Converts the BED records of a family file to the download format, naming each record's
+// sequence and adding the family's model length.
+//
+//     cargo run --example dl_format
+use noodles::bgzf;
+use std::fs::File;
+use std::io::BufRead;
+use te_idx::fixtures::prepare_synthetic_assembly;
+use te_idx::{bgz_path, Annotation, Formattable, ASSEMBLY_DIR};
+use tempfile::TempDir;
+
+fn main() {
+    let working_dir = TempDir::new().expect("Error Creating Working Directory");
+    let assembly = "synthetic".to_string();
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(&assembly, working_dir.path().display().to_string())
+            .expect("Preparing The Synthetic Assembly Failed");
+
+    let (fam, _, model_length) = &synth.families[0];
+    let fam_file = bgz_path(
+        format!("{}/{}/{}", data_directory, assembly, ASSEMBLY_DIR),
+        fam,
+    );
+    let reader = bgzf::Reader::new(File::open(&fam_file).expect("Could Not Open Family File"));
+    for line in reader.lines().take(10) {
+        let line = line.expect("Unable To Read Line");
+        let hit = Annotation::from_bed(&line.split('\t').collect());
+        println!(
+            "{}",
+            hit.to_dl_fmt(hit.seq_acc(), &model_length.to_string())
+                .join("\t")
+        );
+    }
+}
@@ -0,0 +1,38 @@
+// Filters the synthetic fixture assembly's file for one family down to its NRPH hits, in the
+// download format.
+//
+//     cargo run --example filter_family -- [family]
+use std::env::args;
+use te_idx::fixtures::prepare_synthetic_assembly;
+use te_idx::options::FilterOptions;
+use te_idx::{bgzf_filter_with, ASSEMBLY_DIR};
+use tempfile::TempDir;
+
+// BED column of the NRPH flag
+const NRPH_COLUMN: usize = 13;
+
+fn main() {
+    let fam = args().nth(1).unwrap_or("DF000000002".to_string());
+
+    let working_dir = TempDir::new().expect("Error Creating Working Directory");
+    let assembly = "synthetic".to_string();
+    let (_, data_directory) =
+        prepare_synthetic_assembly(&assembly, working_dir.path().display().to_string())
+            .expect("Preparing The Synthetic Assembly Failed");
+
+    let mut output = Vec::new();
+    let options = FilterOptions::builder()
+        .term(NRPH_COLUMN, "1")
+        .dl_fmt(true)
+        .build();
+    bgzf_filter_with(
+        &assembly,
+        ASSEMBLY_DIR,
+        &fam,
+        &mut output,
+        &options,
+        &data_directory,
+    )
+    .expect("Filtering Failed");
+    print!("{}", String::from_utf8_lossy(&output));
+}
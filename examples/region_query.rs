@@ -0,0 +1,49 @@
+// Builds the synthetic fixture assembly in a temp dir, indexes it and searches a region.
+//
+//     cargo run --example region_query -- [chrom] [start] [end]
+use serde_json::Value;
+use std::env::args;
+use te_idx::fixtures::prepare_synthetic_assembly;
+use te_idx::options::IdxQueryOptions;
+use te_idx::{idx_query_with, ASSEMBLY_DIR};
+use tempfile::TempDir;
+
+fn main() {
+    let args: Vec<String> = args().collect();
+    let chrom = args.get(1).cloned().unwrap_or("chr1".to_string());
+    let start = args
+        .get(2)
+        .map_or(20000, |s| s.parse().expect("Invalid Start"));
+    let end = args
+        .get(3)
+        .map_or(60000, |s| s.parse().expect("Invalid End"));
+
+    let working_dir = TempDir::new().expect("Error Creating Working Directory");
+    let assembly = "synthetic".to_string();
+    let (_, data_directory) =
+        prepare_synthetic_assembly(&assembly, working_dir.path().display().to_string())
+            .expect("Preparing The Synthetic Assembly Failed");
+
+    let json = idx_query_with(
+        &assembly,
+        ASSEMBLY_DIR,
+        &chrom,
+        start,
+        end,
+        &IdxQueryOptions::default(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let hits: Vec<Value> = serde_json::from_str(&json).expect("Cannot Deserialize");
+    println!("{} Hits In {}:{}-{}", hits.len(), chrom, start, end);
+    for hit in hits {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            hit["accession"].as_str().unwrap_or_default(),
+            hit["seq_start"],
+            hit["seq_end"],
+            hit["strand"].as_str().unwrap_or_default(),
+            hit["bit_score"]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::read_to_string;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::paths::path_string;
+
+// An allowlist.json in the data directory limits the query functions to the assemblies it
+// names, and to the listed data types of those that have a data_types list:
+//   {"assemblies": {"hg38": {"data_types": ["assembly_alignments", "sequences"]}, "mm39": {}}}
+// Without the file every assembly can be queried. Prep commands ignore it.
+pub const ALLOWLIST_FILE: &str = "allowlist.json";
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AllowedAssembly {
+    #[serde(default)]
+    pub data_types: Option<Vec<String>>, // Every data type when absent
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Allowlist {
+    pub assemblies: BTreeMap<String, AllowedAssembly>,
+}
+
+impl Allowlist {
+    // The data directory's allowlist, if it has one. A malformed file is an error rather
+    // than no restriction.
+    pub fn load(data_directory: impl AsRef<Path>) -> io::Result<Option<Allowlist>> {
+        let data_directory = &path_string(data_directory);
+        let path = format!("{}/{}", data_directory, ALLOWLIST_FILE);
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+        match serde_json::from_str(&read_to_string(&path)?) {
+            Ok(allowlist) => Ok(Some(allowlist)),
+            Err(e) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Malformed Allowlist {} - {}", path, e),
+            )),
+        }
+    }
+
+    pub fn allows(&self, assembly: &str, data_type: Option<&str>) -> bool {
+        match (self.assemblies.get(assembly), data_type) {
+            (None, _) => false,
+            (Some(allowed), Some(data_type)) => match &allowed.data_types {
+                Some(data_types) => data_types.iter().any(|d| d == data_type),
+                None => true,
+            },
+            (Some(_), None) => true,
+        }
+    }
+}
+
+// An assembly, or one of its data types, left out of the allowlist. The message is the same
+// whether or not it exists, so a rejection says nothing about what the data directory holds.
+#[derive(Debug)]
+pub struct NotPermitted {
+    pub assembly: String,
+    pub data_type: Option<String>,
+}
+
+impl fmt::Display for NotPermitted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.data_type {
+            Some(data_type) => write!(
+                f,
+                "{} Of Assembly {} Is Not Available",
+                data_type, self.assembly
+            ),
+            None => write!(f, "Assembly {} Is Not Available", self.assembly),
+        }
+    }
+}
+
+impl Error for NotPermitted {}
+
+// Fails with a PermissionDenied error carrying NotPermitted unless the allowlist, if any,
+// permits the assembly and data type. A data_type of None checks the assembly alone.
+pub fn check_allowed(
+    assembly: &str,
+    data_type: Option<&str>,
+    data_directory: impl AsRef<Path>,
+) -> io::Result<()> {
+    let data_directory = &path_string(data_directory);
+    match Allowlist::load(data_directory)? {
+        Some(allowlist) if !allowlist.allows(assembly, data_type) => {
+            // An assembly that is not listed at all is rejected as a whole
+            let data_type = match allowlist.assemblies.contains_key(assembly) {
+                true => data_type.map(|d| d.to_string()),
+                false => None,
+            };
+            Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                NotPermitted {
+                    assembly: assembly.to_string(),
+                    data_type,
+                },
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// check_allowed, then a NotFound error if the assembly folder does not exist. Only listed
+// assemblies get this far, so only those can be found missing.
+pub fn check_assembly(
+    assembly: &str,
+    data_type: Option<&str>,
+    data_directory: impl AsRef<Path>,
+) -> io::Result<()> {
+    let data_directory = &path_string(data_directory);
+    check_allowed(assembly, data_type, data_directory)?;
+    let assembly_path = format!("{}/{}", data_directory, assembly);
+    if !Path::new(&assembly_path).is_dir() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("Assembly \"{}\" Does Not Exist", assembly_path),
+        ));
+    }
+    Ok(())
+}
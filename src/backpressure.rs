@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::io::{self, Error, ErrorKind, Write};
+use std::mem;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Writes to slow destinations such as NFS are handed to a writer thread with a cap on the
+// bytes in flight between them, so producing records stalls when the destination falls
+// behind instead of buffering without bound.
+
+pub const MAX_BUFFERED_ENV: &str = "TE_IDX_MAX_BUFFERED_MB";
+pub const DEFAULT_MAX_BUFFERED_MB: u64 = 64;
+
+// Largest chunk handed to the writer thread, smaller for small caps so several fit
+const MAX_CHUNK_BYTES: usize = 64 * 1024;
+
+// The in-flight cap asked for in MB, by argument or environment, else the default
+pub fn configured_max_buffered_bytes(max_buffered_mb: Option<u64>) -> u64 {
+    let mb = max_buffered_mb
+        .or_else(|| {
+            std::env::var(MAX_BUFFERED_ENV)
+                .ok()
+                .and_then(|mb| mb.parse().ok())
+        })
+        .unwrap_or(DEFAULT_MAX_BUFFERED_MB);
+    mb.max(1) * 1024 * 1024
+}
+
+// What a BoundedWriter held back. Summed over several writers with add.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct BufferStats {
+    pub cap_bytes: u64,
+    pub peak_bytes: u64, // Most bytes in flight at once, never above cap_bytes
+    pub written_bytes: u64,
+    pub stalls: u64, // Chunks that waited for the destination to catch up
+}
+
+impl BufferStats {
+    pub fn add(&mut self, other: &BufferStats) {
+        self.cap_bytes = self.cap_bytes.max(other.cap_bytes);
+        self.peak_bytes = self.peak_bytes.max(other.peak_bytes);
+        self.written_bytes += other.written_bytes;
+        self.stalls += other.stalls;
+    }
+}
+
+#[derive(Default)]
+struct InFlight {
+    bytes: u64,
+    stats: BufferStats,
+    failed: Option<(ErrorKind, String)>, // The writer thread's error, reported to the producer
+}
+
+type Shared = Arc<(Mutex<InFlight>, Condvar)>;
+
+pub struct BoundedWriter<W: Write + Send + 'static> {
+    chunks: Option<Sender<Vec<u8>>>,
+    in_flight: Shared,
+    pending: Vec<u8>,
+    chunk_bytes: usize,
+    handle: Option<JoinHandle<io::Result<W>>>,
+}
+
+impl<W: Write + Send + 'static> BoundedWriter<W> {
+    pub fn new(inner: W, cap_bytes: u64) -> BoundedWriter<W> {
+        let cap_bytes = cap_bytes.max(1);
+        let in_flight: Shared = Arc::new((
+            Mutex::new(InFlight {
+                stats: BufferStats {
+                    cap_bytes,
+                    ..BufferStats::default()
+                },
+                ..InFlight::default()
+            }),
+            Condvar::new(),
+        ));
+        let (chunks, received) = channel::<Vec<u8>>();
+        let shared = Arc::clone(&in_flight);
+        let handle = thread::spawn(move || {
+            let mut inner = inner;
+            let mut result = Ok(());
+            for chunk in received {
+                // After an error the rest is only drained, so the producer is never left waiting
+                if result.is_ok() {
+                    result = inner.write_all(&chunk);
+                }
+                let (lock, written) = &*shared;
+                let mut state = lock.lock().unwrap();
+                state.bytes -= chunk.len() as u64;
+                match &result {
+                    Ok(()) => state.stats.written_bytes += chunk.len() as u64,
+                    Err(e) if state.failed.is_none() => {
+                        state.failed = Some((e.kind(), e.to_string()));
+                    }
+                    Err(_) => {}
+                }
+                written.notify_all();
+            }
+            result?;
+            inner.flush()?;
+            Ok(inner)
+        });
+        BoundedWriter {
+            chunks: Some(chunks),
+            in_flight,
+            pending: Vec::new(),
+            chunk_bytes: ((cap_bytes / 4) as usize).clamp(1, MAX_CHUNK_BYTES),
+            handle: Some(handle),
+        }
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.in_flight.0.lock().unwrap().stats
+    }
+
+    fn failure(state: &InFlight) -> Option<Error> {
+        state
+            .failed
+            .as_ref()
+            .map(|(kind, message)| Error::new(*kind, message.to_string()))
+    }
+
+    // Hands a chunk to the writer thread once it fits under the cap
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        let len = chunk.len() as u64;
+        let (lock, written) = &*self.in_flight;
+        let mut state = lock.lock().unwrap();
+        let mut stalled = false;
+        while state.bytes > 0 && state.bytes + len > state.stats.cap_bytes {
+            if state.failed.is_some() {
+                break;
+            }
+            stalled = true;
+            state = written.wait(state).unwrap();
+        }
+        if let Some(e) = Self::failure(&state) {
+            return Err(e);
+        }
+        state.bytes += len;
+        state.stats.peak_bytes = state.stats.peak_bytes.max(state.bytes);
+        state.stats.stalls += u64::from(stalled);
+        drop(state);
+        self.chunks
+            .as_ref()
+            .unwrap()
+            .send(chunk)
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "Writer Thread Stopped"))
+    }
+
+    fn send_pending(&mut self, all: bool) -> io::Result<()> {
+        while self.pending.len() >= self.chunk_bytes || (all && !self.pending.is_empty()) {
+            let rest = self
+                .pending
+                .split_off(self.chunk_bytes.min(self.pending.len()));
+            let chunk = mem::replace(&mut self.pending, rest);
+            self.send(chunk)?;
+        }
+        Ok(())
+    }
+
+    // Writes what is left, waits for the writer thread and returns the destination, flushed,
+    // with what was buffered on the way
+    pub fn finish(mut self) -> io::Result<(W, BufferStats)> {
+        self.send_pending(true)?;
+        self.chunks = None;
+        let inner = self
+            .handle
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|_| Error::other("Writer Thread Panicked"))??;
+        Ok((inner, self.stats()))
+    }
+}
+
+impl<W: Write + Send + 'static> Write for BoundedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.send_pending(false)?;
+        Ok(buf.len())
+    }
+
+    // Waits until everything written so far has reached the destination
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_pending(true)?;
+        let (lock, written) = &*self.in_flight;
+        let mut state = lock.lock().unwrap();
+        while state.bytes > 0 && state.failed.is_none() {
+            state = written.wait(state).unwrap();
+        }
+        match Self::failure(&state) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+// Writers dropped without finish, such as those behind a Box<dyn Write>, still write what is
+// left. Errors then go unreported, as for any writer dropped unflushed.
+impl<W: Write + Send + 'static> Drop for BoundedWriter<W> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = self.send_pending(true);
+            self.chunks = None;
+            let _ = handle.join();
+        }
+    }
+}
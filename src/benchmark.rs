@@ -0,0 +1,213 @@
+use noodles::bgzf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, Result};
+use std::path::Path;
+
+use crate::allowlist::check_assembly;
+use crate::paths::path_string;
+use crate::records::bed_extent;
+use crate::{bgz_path, check_half_open, ASSEMBLY_DIR, BENCHMARK_DIR};
+
+pub const DEFAULT_MIN_OVERLAP: f64 = 0.5;
+
+// Only the columns shared by assembly and benchmark BED files are needed here
+struct Interval {
+    low: u64, // 1-based and fully closed, low <= high whatever the strand
+    high: u64,
+    bit_score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BenchmarkReport {
+    pub family: String,
+    pub min_reciprocal_overlap: f64,
+    pub benchmark_hits: usize,
+    pub assembly_hits: usize,
+    pub recovered: usize, // Benchmark hits matched by at least one assembly hit
+    pub missed: usize,    // Benchmark hits without a matching assembly hit
+    pub assembly_only: usize, // Assembly hits matching no benchmark hit
+    pub matched_pairs: usize, // Each recovered hit paired with its largest overlap
+    pub bit_score_pearson: Option<f64>,
+    pub bit_score_spearman: Option<f64>,
+}
+
+// Family records keyed by (sequence, strand), sorted by low coordinate
+type IntervalGroups = HashMap<(String, String), Vec<Interval>>;
+
+fn load_intervals(bed_file: &str) -> Result<(IntervalGroups, usize)> {
+    let reader = bgzf::Reader::new(File::open(bed_file)?);
+    let mut groups: IntervalGroups = HashMap::new();
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (low, high) = match bed_extent(&fields) {
+            Some(extent) => extent,
+            None => panic!("Invalid Coordinates In {} - {}", bed_file, line),
+        };
+        groups
+            .entry((fields[0].to_string(), fields[5].to_string()))
+            .or_default()
+            .push(Interval {
+                low,
+                high,
+                bit_score: fields[4].parse().unwrap_or(f64::NAN),
+            });
+        count += 1;
+    }
+    for intervals in groups.values_mut() {
+        intervals.sort_by_key(|i| i.low);
+    }
+    Ok((groups, count))
+}
+
+fn reciprocal_overlap(a: &Interval, b: &Interval) -> f64 {
+    let overlap = (a.high.min(b.high) + 1).saturating_sub(a.low.max(b.low)) as f64;
+    let a_len = (a.high - a.low + 1) as f64;
+    let b_len = (b.high - b.low + 1) as f64;
+    (overlap / a_len).min(overlap / b_len)
+}
+
+fn pearson(pairs: &[(f64, f64)]) -> Option<f64> {
+    let n = pairs.len() as f64;
+    if pairs.len() < 2 {
+        return None;
+    }
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let cov: f64 = pairs.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let var_x: f64 = pairs.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let var_y: f64 = pairs.iter().map(|p| (p.1 - mean_y).powi(2)).sum();
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+// Average ranks, so tied scores share a rank
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for idx in &order[i..=j] {
+            ranks[*idx] = (i + j) as f64 / 2.0 + 1.0;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+fn spearman(pairs: &[(f64, f64)]) -> Option<f64> {
+    let x = ranks(&pairs.iter().map(|p| p.0).collect::<Vec<f64>>());
+    let y = ranks(&pairs.iter().map(|p| p.1).collect::<Vec<f64>>());
+    pearson(&x.into_iter().zip(y).collect::<Vec<(f64, f64)>>())
+}
+
+// Compares the benchmark annotations of a family against its full assembly annotations.
+// Hits match when they are on the same sequence and strand and overlap by at least
+// min_overlap of both their lengths. Score correlations use each recovered benchmark hit
+// and its assembly hit with the largest overlap, skipping unparsable scores.
+pub fn benchmark_report(
+    assembly: &str,
+    fam: &str,
+    min_overlap: f64,
+    data_directory: impl AsRef<Path>,
+) -> Result<BenchmarkReport> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(ASSEMBLY_DIR), data_directory)?;
+    check_assembly(assembly, Some(BENCHMARK_DIR), data_directory)?;
+    let assembly_path = format!("{}/{}", data_directory, assembly);
+    if !Path::new(&assembly_path).exists() {
+        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+    }
+    if min_overlap.is_nan() || min_overlap <= 0.0 || min_overlap > 1.0 {
+        panic!("Minimum Overlap Must Be Within (0, 1], Got {}", min_overlap);
+    }
+    let bench_file = bgz_path(format!("{}/{}", assembly_path, BENCHMARK_DIR), fam);
+    let assembly_file = bgz_path(format!("{}/{}", assembly_path, ASSEMBLY_DIR), fam);
+    match (
+        Path::new(&bench_file).exists(),
+        Path::new(&assembly_file).exists(),
+    ) {
+        (true, true) => {}
+        (false, true) => panic!(
+            "Family {} Has No {} In Assembly {}, Only {} - Nothing To Compare",
+            fam, BENCHMARK_DIR, assembly_path, ASSEMBLY_DIR
+        ),
+        (true, false) => panic!(
+            "Family {} Has No {} In Assembly {}, Only {} - Nothing To Compare",
+            fam, ASSEMBLY_DIR, assembly_path, BENCHMARK_DIR
+        ),
+        (false, false) => panic!("Family {} Not Found In Assembly {}", fam, assembly_path),
+    }
+    check_half_open(assembly, ASSEMBLY_DIR, data_directory)?;
+    check_half_open(assembly, BENCHMARK_DIR, data_directory)?;
+
+    let (bench, benchmark_hits) = load_intervals(&bench_file)?;
+    let (full, assembly_hits) = load_intervals(&assembly_file)?;
+
+    let mut recovered = 0;
+    let mut assembly_matched = 0;
+    let mut pairs: Vec<(f64, f64)> = Vec::new();
+    for (key, full_intervals) in &full {
+        let bench_intervals = match bench.get(key) {
+            Some(intervals) => intervals,
+            None => continue,
+        };
+        let mut full_matched = vec![false; full_intervals.len()];
+        // Longest assembly hit bounds how far back an overlapping hit can start
+        let max_len = full_intervals
+            .iter()
+            .map(|i| i.high - i.low)
+            .max()
+            .unwrap_or(0);
+        for b in bench_intervals {
+            let first = full_intervals.partition_point(|i| i.low + max_len < b.low);
+            let mut best: Option<(f64, usize)> = None;
+            for (idx, a) in full_intervals.iter().enumerate().skip(first) {
+                if a.low > b.high {
+                    break;
+                }
+                let overlap = reciprocal_overlap(a, b);
+                if overlap >= min_overlap {
+                    full_matched[idx] = true;
+                    if best.is_none_or(|(o, _)| overlap > o) {
+                        best = Some((overlap, idx));
+                    }
+                }
+            }
+            if let Some((_, idx)) = best {
+                recovered += 1;
+                let pair = (b.bit_score, full_intervals[idx].bit_score);
+                if !pair.0.is_nan() && !pair.1.is_nan() {
+                    pairs.push(pair);
+                }
+            }
+        }
+        assembly_matched += full_matched.iter().filter(|m| **m).count();
+    }
+
+    Ok(BenchmarkReport {
+        family: fam.to_string(),
+        min_reciprocal_overlap: min_overlap,
+        benchmark_hits,
+        assembly_hits,
+        recovered,
+        missed: benchmark_hits - recovered,
+        assembly_only: assembly_hits - assembly_matched,
+        matched_pairs: pairs.len(),
+        bit_score_pearson: pearson(&pairs),
+        bit_score_spearman: spearman(&pairs),
+    })
+}
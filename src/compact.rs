@@ -0,0 +1,201 @@
+use noodles::bgzf;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+use crate::idx::{self, RecordOrder};
+use crate::paths::{join_path, path_string, stored_path};
+use crate::status::StatusFile;
+use crate::{
+    bgz_path, check_partition_key, contig_rank, contig_ranks, lock_bgz_file, partition_key_label,
+    remove_family_listing, sorted_file, BedSorter, DEFAULT_SORT_BUFFER_BYTES,
+};
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CompactedFile {
+    pub key: String,
+    pub records: usize,
+    pub out_of_order: usize, // Records found before one they sort ahead of
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CompactReport {
+    pub files: Vec<CompactedFile>,
+    pub indexed: bool, // Whether the index was rebuilt
+}
+
+// Rewrites the BED file of key, or every BED file of the data type when key is None, sorted as
+// prep-beds sorts them and in fresh bgzf blocks. Files grown by append_records end with the
+// appended records out of order and in short blocks of their own, which spreads the records of
+// a region over the file. Each file is rewritten whole under the append lock and replaces the
+// old one by a rename. The index of the data type, if built, is rebuilt once all are written;
+// compacting every file marks the data type sorted again, see beds_sorted.
+pub fn compact_family(
+    assembly: &str,
+    data_type: &str,
+    key: &Option<String>,
+    data_directory: impl AsRef<Path>,
+    status: &Option<StatusFile>,
+) -> Result<CompactReport> {
+    let data_directory = &path_string(data_directory);
+    let bgz_dir = join_path(data_directory, &[assembly, data_type]);
+    if !Path::new(&bgz_dir).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Data \"{}\" Does Not Exist", bgz_dir),
+        ));
+    }
+    let files: Vec<(String, String)> = match key {
+        Some(key) => {
+            check_partition_key(data_type, key)?;
+            let path = bgz_path(&bgz_dir, key);
+            if !Path::new(&path).exists() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "{} {} Has No File In {}",
+                        partition_key_label(data_type),
+                        key,
+                        bgz_dir
+                    ),
+                ));
+            }
+            vec![(key.clone(), path)]
+        }
+        None => {
+            let mut names = idx::stored_bgz_names(&bgz_dir)?;
+            names.sort();
+            names
+                .iter()
+                .map(|name| {
+                    let key = idx::stored_accession(name).to_string();
+                    (key, stored_path(&bgz_dir, name))
+                })
+                .collect()
+        }
+    };
+
+    let ranks = contig_ranks(assembly, data_directory);
+    if let Some(status) = status {
+        status.phase(
+            &format!("compact {}", data_type),
+            Some(files.len() as u64),
+            "files",
+        );
+    }
+    let mut report = CompactReport::default();
+    for (done, (key, path)) in files.iter().enumerate() {
+        let lock = lock_bgz_file(path)?;
+        let compacted = compact_file(key, path, &ranks, data_directory);
+        drop(lock);
+        let compacted = compacted?;
+        println!(
+            "\t{} ({}/{}): {} Records, {} Out Of Order, {} -> {} Bytes",
+            key,
+            done + 1,
+            files.len(),
+            compacted.records,
+            compacted.out_of_order,
+            compacted.bytes_before,
+            compacted.bytes_after
+        );
+        report.files.push(compacted);
+        if let Some(status) = status {
+            status.progress(done as u64 + 1);
+        }
+    }
+    // The listing holds the sizes of the files replaced, the next build_idx lists them again
+    remove_family_listing(assembly, data_type, data_directory)?;
+    if key.is_none() {
+        File::create(sorted_file(assembly, data_type, data_directory))?;
+    }
+
+    let (_, index_file) = idx::idx_paths(assembly, data_type, data_directory);
+    if Path::new(&index_file).exists() {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            idx::prep_idx(assembly, data_type, false, data_directory)
+                .map_err(|e| Error::other(e.to_string()))?;
+        if let Some(status) = status {
+            status.phase(
+                &format!("build-idx {}", data_type),
+                Some(filenames.len() as u64),
+                "files",
+            );
+            contig_index.set_build_progress(status.build_progress());
+        }
+        idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+            .map_err(|e| Error::other(e.to_string()))?;
+        report.indexed = true;
+    }
+    Ok(report)
+}
+
+// The order prep-beds sorts records in, see BedSorter
+fn record_order<'a>(ranks: &HashMap<String, usize>, line: &'a str) -> (usize, RecordOrder<'a>) {
+    let record = RecordOrder::new(line);
+    (contig_rank(ranks, record.contig()), record)
+}
+
+// Rewrites one BED file sorted, comment lines kept at its head. Records too many to sort in
+// memory are sorted through scratch files as prep-beds does.
+fn compact_file(
+    key: &str,
+    path: &str,
+    ranks: &HashMap<String, usize>,
+    data_directory: &str,
+) -> Result<CompactedFile> {
+    let mut compacted = CompactedFile {
+        key: key.to_string(),
+        bytes_before: fs::metadata(path)?.len(),
+        ..CompactedFile::default()
+    };
+    let no_scratch = None;
+    let mut sorter = BedSorter {
+        ranks,
+        buffer_bytes: DEFAULT_SORT_BUFFER_BYTES,
+        scratch: (&no_scratch, data_directory),
+        buffered: Vec::new(),
+        bytes: 0,
+        runs: Vec::new(),
+    };
+    let mut header = Vec::new();
+    let mut previous = String::new();
+    let reader = BufReader::new(bgzf::Reader::new(File::open(path)?));
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            header.push(line);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        if compacted.records > 0 && record_order(ranks, &line) < record_order(ranks, &previous) {
+            compacted.out_of_order += 1;
+        }
+        compacted.records += 1;
+        previous.clone_from(&line);
+        sorter.add(line)?;
+    }
+
+    let dir = Path::new(path).parent().unwrap();
+    let mut out_f = tempfile::NamedTempFile::new_in(dir)?;
+    // Temp files are private, keep the permissions of the file being replaced
+    out_f
+        .as_file()
+        .set_permissions(fs::metadata(path)?.permissions())?;
+    let mut writer = bgzf::Writer::new(out_f.as_file_mut());
+    for line in &header {
+        writeln!(writer, "{}", line)?;
+    }
+    sorter.write_sorted(&mut writer)?;
+    writer.finish()?;
+    out_f.as_file().sync_all()?;
+    out_f.persist(path).map_err(|e| e.error)?;
+    compacted.bytes_after = fs::metadata(path)?.len();
+    Ok(compacted)
+}
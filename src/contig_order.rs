@@ -0,0 +1,218 @@
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::paths::{join_path, path_string};
+use crate::{AssemblyData, SequenceInfo, ASSEMBLY_DATA_FILE, SEQUENCE_DIR, SEQUENCE_FILE};
+
+// The order contigs come in wherever an output covers several of them: the BED files of
+// prep-beds and compact, iter_assembly and dump, family-combined and defragment. The keys of
+// the sequences JSON come sorted by name, chr10 before chr2, so by default chromosomes come in
+// karyotype order, numbered ones by number then the others, mitochondrial last, followed by
+// the remaining scaffolds longest first. A contig_order.txt in the assembly folder, one
+// contig per line, puts the contigs it lists first in its order instead.
+pub const CONTIG_ORDER_FILE: &str = "contig_order.txt";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ContigOrder {
+    pub assembly: String,
+    pub order_file: Option<String>, // The contig_order.txt read, None for the default order
+    pub contigs: Vec<String>,       // Sequence accessions, first to last
+}
+
+impl ContigOrder {
+    // Rank of each contig, the first 0
+    pub fn ranks(&self) -> HashMap<String, usize> {
+        self.contigs
+            .iter()
+            .enumerate()
+            .map(|(rank, contig)| (contig.clone(), rank))
+            .collect()
+    }
+}
+
+// Compares names with runs of digits taken as numbers, chr2 before chr10. Names equal that
+// way, chr01 and chr1, fall back to comparing as strings.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_rest, mut b_rest) = (a, b);
+    while !a_rest.is_empty() && !b_rest.is_empty() {
+        let (a_chunk, a_tail) = split_chunk(a_rest);
+        let (b_chunk, b_tail) = split_chunk(b_rest);
+        let a_digits = a_chunk.starts_with(|c: char| c.is_ascii_digit());
+        let b_digits = b_chunk.starts_with(|c: char| c.is_ascii_digit());
+        let ordering = match (a_digits, b_digits) {
+            (true, true) => {
+                let (a_number, b_number) = (
+                    a_chunk.trim_start_matches('0'),
+                    b_chunk.trim_start_matches('0'),
+                );
+                a_number
+                    .len()
+                    .cmp(&b_number.len())
+                    .then_with(|| a_number.cmp(b_number))
+            }
+            _ => a_chunk.cmp(b_chunk),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        (a_rest, b_rest) = (a_tail, b_tail);
+    }
+    a_rest.len().cmp(&b_rest.len()).then_with(|| a.cmp(b))
+}
+
+// The leading run of digits or of anything else, and the rest
+fn split_chunk(name: &str) -> (&str, &str) {
+    let digits = name.starts_with(|c: char| c.is_ascii_digit());
+    let end = name
+        .find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(name.len());
+    name.split_at(end)
+}
+
+// Where a chromosome name comes among chromosomes, None for scaffolds. An optional chr prefix
+// and then a number or a single letter, X, Y, Z, W and so on, or M or MT.
+fn chromosome_class(name: &str) -> Option<u8> {
+    let bare = match name.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("chr") => &name[3..],
+        _ => name,
+    };
+    if bare.eq_ignore_ascii_case("M") || bare.eq_ignore_ascii_case("MT") {
+        Some(2)
+    } else if !bare.is_empty() && bare.chars().all(|c| c.is_ascii_digit()) {
+        Some(0)
+    } else if bare.len() == 1 && bare.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+// Chromosomes in karyotype order, by their accession when it is a chromosome name and by their
+// id otherwise, then scaffolds longest first, those without a length last. Ties go by
+// natural_cmp of the accession.
+pub fn default_contig_order(sequences: &[(String, SequenceInfo)]) -> Vec<String> {
+    let mut chromosomes = Vec::new();
+    let mut scaffolds = Vec::new();
+    for (acc, info) in sequences {
+        let named = [Some(acc.as_str()), info.id.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|name| chromosome_class(name).map(|class| (class, name)));
+        match named {
+            Some((class, name)) => chromosomes.push((class, name, acc)),
+            None => scaffolds.push((Reverse(info.length), acc)),
+        }
+    }
+    chromosomes.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then_with(|| natural_cmp(a.1, b.1))
+            .then_with(|| natural_cmp(a.2, b.2))
+    });
+    // None is below any length, reversed it sorts after them
+    scaffolds.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| natural_cmp(a.1, b.1)));
+    chromosomes
+        .into_iter()
+        .map(|(_, _, acc)| acc.clone())
+        .chain(scaffolds.into_iter().map(|(_, acc)| acc.clone()))
+        .collect()
+}
+
+// The order of the given sequences of an assembly, the contigs its contig_order.txt lists
+// first when it has one. The file names contigs by accession or id, one per line, blank lines
+// and # comments skipped. A contig it lists twice or that is not in the sequences is an error.
+pub fn contig_order_of(
+    assembly: &str,
+    sequences: &[(String, SequenceInfo)],
+    data_directory: impl AsRef<Path>,
+) -> Result<ContigOrder> {
+    let data_directory = &path_string(data_directory);
+    let default = default_contig_order(sequences);
+    let order_file = join_path(data_directory, &[assembly, CONTIG_ORDER_FILE]);
+    if !Path::new(&order_file).exists() {
+        return Ok(ContigOrder {
+            assembly: assembly.to_string(),
+            order_file: None,
+            contigs: default,
+        });
+    }
+    let mut accessions: HashMap<&str, &str> = HashMap::new();
+    for (acc, info) in sequences {
+        if let Some(id) = &info.id {
+            accessions.insert(id, acc);
+        }
+    }
+    // An accession is itself, whatever id another sequence has
+    for (acc, _) in sequences {
+        accessions.insert(acc, acc);
+    }
+    let mut contigs = Vec::new();
+    let mut listed = HashSet::new();
+    for (number, line) in read_to_string(&order_file)?.lines().enumerate() {
+        let name = line.trim();
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+        let Some(acc) = accessions.get(name) else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} Line {}: Contig {} Is Not In The Sequences Of {}",
+                    order_file,
+                    number + 1,
+                    name,
+                    assembly
+                ),
+            ));
+        };
+        if !listed.insert(acc.to_string()) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} Line {}: Contig {} Is Listed More Than Once",
+                    order_file,
+                    number + 1,
+                    name
+                ),
+            ));
+        }
+        contigs.push(acc.to_string());
+    }
+    contigs.extend(default.into_iter().filter(|acc| !listed.contains(acc)));
+    Ok(ContigOrder {
+        assembly: assembly.to_string(),
+        order_file: Some(order_file),
+        contigs,
+    })
+}
+
+// The sequences of a prepared assembly, from its sequences JSON or the consolidated file, an
+// error when it has neither rather than an assembly of no sequences
+pub(crate) fn load_sequences(assembly: &str, data_directory: &str) -> Result<AssemblyData> {
+    let sequences_file = join_path(
+        data_directory,
+        &[
+            assembly,
+            SEQUENCE_DIR,
+            &format!("{}{}", assembly, SEQUENCE_FILE),
+        ],
+    );
+    let consolidated_file = join_path(data_directory, &[assembly, ASSEMBLY_DATA_FILE]);
+    if !Path::new(&sequences_file).exists() && !Path::new(&consolidated_file).exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Assembly {} Has No Sequences JSON", assembly),
+        ));
+    }
+    AssemblyData::load(assembly, data_directory)
+}
+
+// The contig order of a prepared assembly, from its sequences JSON
+pub fn contig_order(assembly: &str, data_directory: impl AsRef<Path>) -> Result<ContigOrder> {
+    let data_directory = &path_string(data_directory);
+    let assembly_data = load_sequences(assembly, data_directory)?;
+    contig_order_of(assembly, assembly_data.sequence_info(), data_directory)
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::idx::bed_fields;
+use crate::paths::path_string;
+use crate::records::bed_extent;
+use crate::{iter_assembly, ASSEMBLY_DIR};
+
+pub const DEFAULT_WINDOW_BP: u64 = 1000;
+pub const DEFAULT_TOP_N: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PairCount {
+    pub family_a: String, // family_a sorts before family_b
+    pub family_b: String,
+    pub count: u64,
+    pub a_first: u64, // Co-occurrences with the family_a hit upstream
+    pub b_first: u64,
+    pub expected: f64, // Count expected if families were placed independently of each other
+    pub enrichment: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CooccurrenceReport {
+    pub window_bp: u64,
+    pub hits: u64,
+    pub families: usize,
+    pub windowed_pairs: u64, // Co-occurrences of any two hits of different families
+    pub pruned_pairs: u64,   // Distinct pairs dropped to keep the counts under max_pairs
+    pub pairs: Vec<PairCount>,
+}
+
+pub const COOCCURRENCE_TSV_HEADER: &str =
+    "#family_a\tfamily_b\tcount\ta_first\tb_first\texpected\tenrichment";
+
+impl CooccurrenceReport {
+    pub fn tsv_lines(&self) -> Vec<String> {
+        let mut lines = vec![COOCCURRENCE_TSV_HEADER.to_string()];
+        for pair in &self.pairs {
+            lines.push(format!(
+                "{}\t{}\t{}\t{}\t{}\t{:.3}\t{:.3}",
+                pair.family_a,
+                pair.family_b,
+                pair.count,
+                pair.a_first,
+                pair.b_first,
+                pair.expected,
+                pair.enrichment
+            ));
+        }
+        lines
+    }
+}
+
+// A hit still able to co-occur with the ones after it
+struct WindowHit {
+    family: u32,
+    high: u64,
+}
+
+// Counts of a pair of family ids, lower id first, by which of them was upstream
+type PairCounts = HashMap<(u32, u32), [u64; 2]>;
+
+// Drops the pairs seen least often, until at most half of max_pairs are left, so that
+// pruning is not repeated on every new pair. Returns the number of pairs dropped.
+fn prune_pairs(pairs: &mut PairCounts, max_pairs: usize) -> u64 {
+    let before = pairs.len();
+    let mut floor = 1;
+    while pairs.len() > max_pairs / 2 {
+        pairs.retain(|_, counts| counts[0] + counts[1] > floor);
+        floor += 1;
+    }
+    (before - pairs.len()) as u64
+}
+
+// Counts how often hits of two different families lie within window_bp of each other,
+// overlapping and nested hits included. Every contig is streamed in position order, keeping
+// only the hits that are still in reach of the next one. Pairs are ranked by count and
+// compared with the count expected from the family frequencies alone: with P co-occurrences
+// in all and families making up fractions p_a and p_b of the hits, 2 * P * p_a * p_b.
+// When families is given, hits of other families are left out. With max_pairs set, the
+// rarest pairs are dropped whenever more distinct pairs than that are being counted.
+pub fn cooccurrence(
+    assembly: &str,
+    window_bp: u64,
+    families: &Option<Vec<String>>,
+    nrph: &bool,
+    top_n: usize,
+    max_pairs: Option<usize>,
+    data_directory: impl AsRef<Path>,
+) -> Result<CooccurrenceReport> {
+    let data_directory = &path_string(data_directory);
+    let records = iter_assembly(assembly, ASSEMBLY_DIR, &None, nrph, data_directory)?;
+
+    let mut family_ids: HashMap<String, u32> = HashMap::new();
+    let mut family_hits: Vec<u64> = Vec::new();
+    let mut pairs: PairCounts = HashMap::new();
+    let mut window: Vec<WindowHit> = Vec::new();
+    let mut contig = String::new();
+    let (mut hits, mut windowed_pairs, mut pruned_pairs) = (0, 0, 0);
+    for record in records {
+        let record = record?;
+        let fields = bed_fields(&record);
+        let Some((low, high)) = bed_extent(&fields).filter(|_| fields.len() >= 4) else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid Record In {} - {}", assembly, record),
+            ));
+        };
+        let family = fields[3];
+        if let Some(families) = families {
+            if !families.iter().any(|f| f == family) {
+                continue;
+            }
+        }
+        if fields[0] != contig {
+            contig = fields[0].to_string();
+            window.clear();
+        }
+        let id = match family_ids.get(family) {
+            Some(id) => *id,
+            None => {
+                let id = family_ids.len() as u32;
+                family_ids.insert(family.to_string(), id);
+                family_hits.push(0);
+                id
+            }
+        };
+        family_hits[id as usize] += 1;
+        hits += 1;
+
+        window.retain(|hit| hit.high.saturating_add(window_bp) >= low);
+        for hit in window.iter().filter(|hit| hit.family != id) {
+            let (key, upstream) = if hit.family < id {
+                ((hit.family, id), 0)
+            } else {
+                ((id, hit.family), 1)
+            };
+            pairs.entry(key).or_default()[upstream] += 1;
+            windowed_pairs += 1;
+        }
+        window.push(WindowHit { family: id, high });
+        if let Some(max_pairs) = max_pairs {
+            if pairs.len() > max_pairs {
+                pruned_pairs += prune_pairs(&mut pairs, max_pairs);
+            }
+        }
+    }
+
+    let mut names = vec![String::new(); family_ids.len()];
+    for (name, id) in family_ids {
+        names[id as usize] = name;
+    }
+    let mut ranked: Vec<((u32, u32), [u64; 2])> = pairs.into_iter().collect();
+    ranked.sort_by_key(|((a, b), counts)| {
+        let (a, b) = (&names[*a as usize], &names[*b as usize]);
+        (
+            Reverse(counts[0] + counts[1]),
+            a.min(b).clone(),
+            a.max(b).clone(),
+        )
+    });
+    ranked.truncate(top_n);
+
+    let frequency = |id: u32| family_hits[id as usize] as f64 / hits as f64;
+    let pairs = ranked
+        .into_iter()
+        .map(|((a, b), counts)| {
+            let count = counts[0] + counts[1];
+            let expected = 2.0 * windowed_pairs as f64 * frequency(a) * frequency(b);
+            let (a_first, b_first) = (counts[0], counts[1]);
+            let (a, b, a_first, b_first) = if names[a as usize] <= names[b as usize] {
+                (a, b, a_first, b_first)
+            } else {
+                (b, a, b_first, a_first)
+            };
+            PairCount {
+                family_a: names[a as usize].clone(),
+                family_b: names[b as usize].clone(),
+                count,
+                a_first,
+                b_first,
+                expected,
+                enrichment: count as f64 / expected,
+            }
+        })
+        .collect();
+
+    Ok(CooccurrenceReport {
+        window_bp,
+        hits,
+        families: names.len(),
+        windowed_pairs,
+        pruned_pairs,
+        pairs,
+    })
+}
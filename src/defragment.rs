@@ -0,0 +1,360 @@
+use noodles::bgzf;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, Error, ErrorKind, Lines, Result};
+use std::path::Path;
+
+use crate::allowlist::check_assembly;
+use crate::idx::STRAND_COLUMN;
+use crate::paths::path_string;
+use crate::records::export_coordinates;
+use crate::{beds_sorted, bgz_path, check_half_open, contig_rank, contig_ranks, ASSEMBLY_DIR};
+
+pub const DEFAULT_MAX_GAP_BP: u64 = 200;
+pub const DEFAULT_MODEL_TOLERANCE: u64 = 20;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Fragment {
+    pub seq_start: u64, // As exported, greater than seq_end on the minus strand
+    pub seq_end: u64,
+    pub model_start: u64,
+    pub model_end: u64,
+}
+
+impl Fragment {
+    fn low(&self) -> u64 {
+        self.seq_start.min(self.seq_end)
+    }
+
+    fn high(&self) -> u64 {
+        self.seq_start.max(self.seq_end)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Insertion {
+    pub sequence: String,
+    pub strand: String,
+    pub start: u64, // Fully closed, start <= end whatever the strand
+    pub end: u64,
+    pub model_start: u64,
+    pub model_end: u64,
+    pub genome_coverage: u64, // Bases of the insertion covered by a fragment
+    pub model_coverage: u64,  // Model positions covered by a fragment
+    pub members: Vec<Fragment>, // In model order
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DefragmentReport {
+    pub family: String,
+    pub max_gap_bp: u64,
+    pub model_tolerance: u64,
+    pub hits: usize,
+    pub joined_hits: usize, // Hits that are members of an insertion
+    pub insertions: Vec<Insertion>,
+}
+
+pub const DEFRAGMENT_TSV_HEADER: &str = "#sequence\tstart\tend\tstrand\tmodel_start\tmodel_end\tgenome_coverage\tmodel_coverage\tmembers";
+
+impl DefragmentReport {
+    // One line per insertion, members as seq_start-seq_end:model_start-model_end
+    pub fn tsv_lines(&self) -> Vec<String> {
+        let mut lines = vec![DEFRAGMENT_TSV_HEADER.to_string()];
+        for ins in &self.insertions {
+            let members: Vec<String> = ins
+                .members
+                .iter()
+                .map(|m| {
+                    format!(
+                        "{}-{}:{}-{}",
+                        m.seq_start, m.seq_end, m.model_start, m.model_end
+                    )
+                })
+                .collect();
+            lines.push(format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                ins.sequence,
+                ins.start,
+                ins.end,
+                ins.strand,
+                ins.model_start,
+                ins.model_end,
+                ins.genome_coverage,
+                ins.model_coverage,
+                members.join(",")
+            ));
+        }
+        lines
+    }
+}
+
+// Records of one sequence keyed by strand
+type StrandFragments = BTreeMap<String, Vec<Fragment>>;
+
+// The sequence, strand and fragment of a record line
+fn parse_fragment(bed_file: &str, line: &str) -> Result<(String, String, Fragment)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let Some(strand) = fields.get(STRAND_COLUMN).copied() else {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("No Strand Column In {} - {}", bed_file, line),
+        ));
+    };
+    let seq = fields
+        .get(1)
+        .zip(fields.get(2))
+        .and_then(|(start, end)| export_coordinates(start, end, strand));
+    let model: Vec<u64> = [9, 10]
+        .iter()
+        .filter_map(|i| fields.get(*i).and_then(|f| f.parse().ok()))
+        .collect();
+    let (Some((seq_start, seq_end)), &[model_a, model_b]) = (seq, model.as_slice()) else {
+        panic!("Invalid Coordinates In {} - {}", bed_file, line);
+    };
+    let fragment = Fragment {
+        seq_start,
+        seq_end,
+        model_start: model_a.min(model_b),
+        model_end: model_a.max(model_b),
+    };
+    Ok((fields[0].to_string(), strand.to_string(), fragment))
+}
+
+// Reads a family file one run of records of the same sequence at a time, so only the
+// fragments of one sequence are held. A sorted file holds one run per sequence.
+struct SequenceRuns {
+    bed_file: String,
+    lines: Lines<bgzf::Reader<File>>,
+    next: Option<(String, String, Fragment)>, // First record of the following run
+    records: usize,
+}
+
+impl SequenceRuns {
+    fn open(bed_file: &str) -> Result<SequenceRuns> {
+        Ok(SequenceRuns {
+            bed_file: bed_file.to_string(),
+            lines: bgzf::Reader::new(File::open(bed_file)?).lines(),
+            next: None,
+            records: 0,
+        })
+    }
+
+    fn next_record(&mut self) -> Result<Option<(String, String, Fragment)>> {
+        if let Some(record) = self.next.take() {
+            return Ok(Some(record));
+        }
+        for line in self.lines.by_ref() {
+            let line = line?;
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            self.records += 1;
+            return parse_fragment(&self.bed_file, &line).map(Some);
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for SequenceRuns {
+    type Item = Result<(String, StrandFragments)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (sequence, strand, fragment) = match self.next_record() {
+            Ok(record) => record?,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut strands = StrandFragments::new();
+        strands.entry(strand).or_default().push(fragment);
+        loop {
+            match self.next_record() {
+                Ok(Some(record)) if record.0 != sequence => {
+                    self.next = Some(record);
+                    break;
+                }
+                Ok(Some((_, strand, fragment))) => {
+                    strands.entry(strand).or_default().push(fragment)
+                }
+                Ok(None) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok((sequence, strands)))
+    }
+}
+
+// Bases covered by a set of fully closed intervals
+fn covered(mut intervals: Vec<(u64, u64)>) -> u64 {
+    intervals.sort();
+    let mut total = 0;
+    let mut reach = 0; // Highest base counted so far
+    for (low, high) in intervals {
+        let from = low.max(reach + 1);
+        if high >= from {
+            total += high - from + 1;
+            reach = high;
+        }
+    }
+    total
+}
+
+fn insertion(sequence: &str, strand: &str, members: Vec<Fragment>) -> Insertion {
+    Insertion {
+        sequence: sequence.to_string(),
+        strand: strand.to_string(),
+        start: members.iter().map(|m| m.low()).min().unwrap(),
+        end: members.iter().map(|m| m.high()).max().unwrap(),
+        model_start: members.iter().map(|m| m.model_start).min().unwrap(),
+        model_end: members.iter().map(|m| m.model_end).max().unwrap(),
+        genome_coverage: covered(members.iter().map(|m| (m.low(), m.high())).collect()),
+        model_coverage: covered(
+            members
+                .iter()
+                .map(|m| (m.model_start, m.model_end))
+                .collect(),
+        ),
+        members,
+    }
+}
+
+// Chains the fragments of one sequence and strand. Fragments are walked in model direction,
+// ascending position on the plus strand and descending on the minus strand. Each joins the
+// collinear open chain whose model end is closest to its model start, then the one ending
+// nearest before it. A chain closes once the walk is more than max_gap_bp past its end.
+fn chain_fragments(
+    mut fragments: Vec<Fragment>,
+    minus: bool,
+    max_gap_bp: u64,
+    model_tolerance: u64,
+) -> Vec<Vec<Fragment>> {
+    // Position along the model direction, so both strands chain the same way
+    let along = |f: &Fragment| -> (i64, i64) {
+        if minus {
+            (-(f.high() as i64), -(f.low() as i64))
+        } else {
+            (f.low() as i64, f.high() as i64)
+        }
+    };
+    fragments.sort_by_key(|f| along(f));
+    let mut open: Vec<Vec<Fragment>> = Vec::new();
+    let mut closed: Vec<Vec<Fragment>> = Vec::new();
+    for fragment in fragments {
+        let (start, _) = along(&fragment);
+        let (still_open, done): (Vec<_>, Vec<_>) = open
+            .into_iter()
+            .partition(|chain| along(chain.last().unwrap()).1 + max_gap_bp as i64 >= start - 1);
+        closed.extend(done);
+        open = still_open;
+        let best = open
+            .iter_mut()
+            .filter(|chain| {
+                let last = chain.last().unwrap();
+                fragment.model_start + model_tolerance >= last.model_end
+            })
+            .min_by_key(|chain| {
+                let last = chain.last().unwrap();
+                (
+                    fragment.model_start.abs_diff(last.model_end),
+                    Reverse(along(last).1),
+                )
+            });
+        match best {
+            Some(chain) => chain.push(fragment),
+            None => open.push(vec![fragment]),
+        }
+    }
+    closed.extend(open);
+    closed
+}
+
+// Joins fragmented hits of a family into putative full-length insertions. Hits join when
+// they are on the same sequence and strand, at most max_gap_bp apart, and collinear on the
+// model: each next fragment starts no more than model_tolerance before the previous one
+// ends. Only chains of two or more hits are reported, in contig order then position. The
+// records are chained a sequence at a time as they are read when the BED files are sorted,
+// see beds_sorted. A file records were appended to is grouped by sequence whole first.
+pub fn defragment(
+    assembly: &str,
+    fam: &str,
+    max_gap_bp: u64,
+    model_tolerance: u64,
+    data_directory: impl AsRef<Path>,
+) -> Result<DefragmentReport> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(ASSEMBLY_DIR), data_directory)?;
+    let assembly_path = format!("{}/{}", data_directory, assembly);
+    if !Path::new(&assembly_path).exists() {
+        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+    }
+    check_half_open(assembly, ASSEMBLY_DIR, data_directory)?;
+    let fam_file = bgz_path(format!("{}/{}", assembly_path, ASSEMBLY_DIR), fam);
+    if !Path::new(&fam_file).exists() {
+        panic!("Family {} Not Found In Assembly {}", fam, assembly_path);
+    }
+
+    let mut insertions = Vec::new();
+    let mut chain_sequence = |sequence: &str, strands: StrandFragments| {
+        for (strand, fragments) in strands {
+            let chains = chain_fragments(fragments, strand == "-", max_gap_bp, model_tolerance);
+            for members in chains.into_iter().filter(|chain| chain.len() > 1) {
+                insertions.push(insertion(sequence, &strand, members));
+            }
+        }
+    };
+    let mut runs = SequenceRuns::open(&fam_file)?;
+    if beds_sorted(assembly, ASSEMBLY_DIR, data_directory) {
+        let mut seen = HashSet::new();
+        for run in runs.by_ref() {
+            let (sequence, strands) = run?;
+            if !seen.insert(sequence.clone()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} Is Not Sorted By Sequence, {} Comes Twice - run compact",
+                        fam_file, sequence
+                    ),
+                ));
+            }
+            chain_sequence(&sequence, strands);
+        }
+    } else {
+        let mut sequences: BTreeMap<String, StrandFragments> = BTreeMap::new();
+        for run in runs.by_ref() {
+            let (sequence, strands) = run?;
+            let grouped = sequences.entry(sequence).or_default();
+            for (strand, fragments) in strands {
+                grouped.entry(strand).or_default().extend(fragments);
+            }
+        }
+        for (sequence, strands) in sequences {
+            chain_sequence(&sequence, strands);
+        }
+    }
+    let hits = runs.records;
+    // Contigs in the assembly's contig order, see contig_order
+    let ranks = contig_ranks(assembly, data_directory);
+    insertions.sort_by(|a, b| {
+        (
+            contig_rank(&ranks, &a.sequence),
+            &a.sequence,
+            a.start,
+            &a.strand,
+        )
+            .cmp(&(
+                contig_rank(&ranks, &b.sequence),
+                &b.sequence,
+                b.start,
+                &b.strand,
+            ))
+    });
+
+    Ok(DefragmentReport {
+        family: fam.to_string(),
+        max_gap_bp,
+        model_tolerance,
+        hits,
+        joined_hits: insertions.iter().map(|i| i.members.len()).sum(),
+        insertions,
+    })
+}
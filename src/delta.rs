@@ -0,0 +1,240 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, create_dir_all, File};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::idx::{install_index, is_versioned_index, FAMILY_LISTING_SUFFIX};
+use crate::package::{file_digest, MANIFEST_FILE};
+use crate::paths::{join_path, path_string, stored_path};
+use crate::INDEX_DATA_TYPES;
+
+// A release delta is a folder holding what a mirror of the previous release of an assembly
+// needs to become the new one: the files added or changed, under files/, the paths removed,
+// in DELETED, and a MANIFEST of every file of the new release, "sha256<TAB>bytes<TAB>path".
+// Paths are relative to the assembly folder with "/" between components. An index is
+// compared and shipped as <data_type>_idx.dat, whichever versioned file it links to.
+pub const DELTA_FILES_DIR: &str = "files";
+pub const DELETED_FILE: &str = "DELETED";
+
+// Checksum and size by path relative to an assembly folder
+pub type AssemblyManifest = BTreeMap<String, (String, u64)>;
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct DeltaReport {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+    pub bytes: u64, // Size of the files added or changed
+}
+
+// Checksums every file of an assembly folder. Versioned index files are left out, their
+// content is listed under the <data_type>_idx.dat linking to them, and so are the links of an
+// index being published.
+pub fn assembly_manifest(assembly_dir: &str) -> Result<AssemblyManifest> {
+    if !Path::new(assembly_dir).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Assembly Folder {} Not Found", assembly_dir),
+        ));
+    }
+    let mut manifest = AssemblyManifest::new();
+    for entry in WalkDir::new(assembly_dir).sort_by_file_name() {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy();
+        if entry.file_type().is_dir() || is_versioned_index(&name) || name.ends_with(".link") {
+            continue;
+        }
+        // Followed, so a linked index is read as the file it links to
+        if !fs::metadata(entry.path())?.is_file() {
+            continue;
+        }
+        let rel: Vec<String> = entry
+            .path()
+            .strip_prefix(assembly_dir)
+            .unwrap()
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        manifest.insert(rel.join("/"), file_digest(&entry.path().to_string_lossy())?);
+    }
+    Ok(manifest)
+}
+
+// Copies a file keeping its modification time, which indexes record for every bgz file
+fn copy_file(from: &str, to: &str) -> Result<()> {
+    fs::copy(from, to)?;
+    let modified = fs::metadata(from)?.modified()?;
+    File::options().write(true).open(to)?.set_modified(modified)
+}
+
+fn read_lines(path: &str) -> Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn is_index(rel: &str) -> bool {
+    INDEX_DATA_TYPES
+        .iter()
+        .any(|data_type| rel == format!("{}_idx.dat", data_type))
+}
+
+// Writes the delta from the previous release of an assembly to the new one into out_dir,
+// which must be empty or absent. Files are compared by checksum.
+pub fn release_delta(
+    old_assembly_dir: impl AsRef<Path>,
+    new_assembly_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+) -> Result<DeltaReport> {
+    let old_assembly_dir = &path_string(old_assembly_dir);
+    let new_assembly_dir = &path_string(new_assembly_dir);
+    let out_dir = &path_string(out_dir);
+    let old = assembly_manifest(old_assembly_dir)?;
+    let new = assembly_manifest(new_assembly_dir)?;
+    if Path::new(out_dir).exists() && fs::read_dir(out_dir)?.next().is_some() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Delta Folder {} Is Not Empty", out_dir),
+        ));
+    }
+    let files_dir = join_path(out_dir, &[DELTA_FILES_DIR]);
+    create_dir_all(&files_dir)?;
+
+    let mut report = DeltaReport::default();
+    let mut manifest = String::new();
+    for (rel, (digest, bytes)) in &new {
+        manifest.push_str(&format!("{}\t{}\t{}\n", digest, bytes, rel));
+        match old.get(rel) {
+            Some(previous) if previous == &(digest.to_string(), *bytes) => {
+                report.unchanged += 1;
+                continue;
+            }
+            Some(_) => report.changed.push(rel.to_string()),
+            None => report.added.push(rel.to_string()),
+        }
+        let target = stored_path(&files_dir, rel);
+        create_dir_all(Path::new(&target).parent().unwrap())?;
+        copy_file(&stored_path(new_assembly_dir, rel), &target)?;
+        report.bytes += bytes;
+    }
+    report.deleted = old
+        .keys()
+        .filter(|rel| !new.contains_key(*rel))
+        .cloned()
+        .collect();
+
+    let mut deleted = report.deleted.join("\n");
+    if !deleted.is_empty() {
+        deleted.push('\n');
+    }
+    fs::write(join_path(out_dir, &[DELETED_FILE]), deleted)?;
+    // Written last, a delta without a MANIFEST is incomplete
+    fs::write(join_path(out_dir, &[MANIFEST_FILE]), manifest)?;
+    Ok(report)
+}
+
+// Applies a release_delta to the previous release in assembly_dir. Nothing is changed unless
+// every file shipped matches the MANIFEST and every other file it lists is already in
+// assembly_dir with its checksum. Data files are then renamed into place, the indexes swapped
+// to as build-idx swaps them, and the deleted paths removed last, so an index never points at
+// files that are not there yet.
+pub fn apply_delta(
+    assembly_dir: impl AsRef<Path>,
+    delta_dir: impl AsRef<Path>,
+) -> Result<DeltaReport> {
+    let assembly_dir = &path_string(assembly_dir);
+    let delta_dir = &path_string(delta_dir);
+    let manifest_file = join_path(delta_dir, &[MANIFEST_FILE]);
+    if !Path::new(&manifest_file).exists() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("No {} In Delta {}", MANIFEST_FILE, delta_dir),
+        ));
+    }
+    let mut manifest = AssemblyManifest::new();
+    let mut problems = Vec::new();
+    for line in read_lines(&manifest_file)? {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields[..] {
+            [digest, bytes, rel] if bytes.parse::<u64>().is_ok() => {
+                manifest.insert(
+                    rel.to_string(),
+                    (digest.to_string(), bytes.parse().unwrap()),
+                );
+            }
+            _ => problems.push(format!("Malformed Manifest Line \"{}\"", line)),
+        }
+    }
+    let deleted = read_lines(&join_path(delta_dir, &[DELETED_FILE]))?;
+    let files_dir = join_path(delta_dir, &[DELTA_FILES_DIR]);
+    let shipped = assembly_manifest(&files_dir)?;
+    let current = assembly_manifest(assembly_dir)?;
+
+    let mut report = DeltaReport::default();
+    for (rel, found) in &shipped {
+        match manifest.get(rel) {
+            Some(expected) if expected == found => {}
+            Some(_) => problems.push(format!("Checksum Mismatch For {}", rel)),
+            None => problems.push(format!("{} Not Listed In Manifest", rel)),
+        }
+    }
+    for (rel, expected) in &manifest {
+        if shipped.contains_key(rel) {
+            if current.contains_key(rel) {
+                report.changed.push(rel.to_string());
+            } else {
+                report.added.push(rel.to_string());
+            }
+            report.bytes += expected.1;
+            continue;
+        }
+        match current.get(rel) {
+            Some(found) if found == expected => report.unchanged += 1,
+            Some(_) => problems.push(format!(
+                "{} Differs From The Release The Delta Was Made From",
+                rel
+            )),
+            None => problems.push(format!("Missing {}", rel)),
+        }
+    }
+    for rel in deleted.iter().filter(|rel| manifest.contains_key(*rel)) {
+        problems.push(format!("{} Is Both Deleted And Listed In Manifest", rel));
+    }
+    if !problems.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, problems.join(", ")));
+    }
+
+    // Family listings number files as their index does, so go in after it
+    let mut order: Vec<&String> = shipped.keys().collect();
+    order.sort_by_key(|rel| match rel {
+        rel if is_index(rel) => 1,
+        rel if rel.ends_with(FAMILY_LISTING_SUFFIX) => 2,
+        _ => 0,
+    });
+    for rel in order {
+        let (source, target) = (stored_path(&files_dir, rel), stored_path(assembly_dir, rel));
+        create_dir_all(Path::new(&target).parent().unwrap())?;
+        if is_index(rel) {
+            install_index(&source, &target)?;
+            continue;
+        }
+        let partial = format!("{}.delta", target);
+        copy_file(&source, &partial)?;
+        fs::rename(&partial, &target).inspect_err(|_| {
+            let _ = fs::remove_file(&partial);
+        })?;
+    }
+    for rel in &deleted {
+        match fs::remove_file(stored_path(assembly_dir, rel)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    report.deleted = deleted;
+    Ok(report)
+}
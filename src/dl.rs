@@ -0,0 +1,116 @@
+use std::borrow::Cow;
+use std::io::{Result, Write};
+
+// Writes the download format, and the other tab separated exports: a "#" header of the
+// column names, then one line per record. Fields are sanitized rather than quoted, as the
+// readers of Dfam downloads split lines on tabs without unquoting: every tab, newline and
+// carriage return in a field is replaced by a space, so a field from curation holding one,
+// such as an odd family name, cannot add columns or rows. Nothing else is changed.
+const SANITIZED: [char; 3] = ['\t', '\n', '\r'];
+const REPLACEMENT: &str = " ";
+
+// A field as written, borrowed unless it had to be sanitized
+pub fn sanitize_field(field: &str) -> Cow<'_, str> {
+    match field.contains(SANITIZED) {
+        true => Cow::Owned(field.replace(SANITIZED, REPLACEMENT)),
+        false => Cow::Borrowed(field),
+    }
+}
+
+// Sanitized fields joined into a line, without its newline
+pub fn join_fields<'f>(fields: impl IntoIterator<Item = &'f str>) -> String {
+    fields
+        .into_iter()
+        .map(sanitize_field)
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+pub struct DlWriter<'a> {
+    writer: &'a mut dyn Write,
+    columns: Vec<String>,
+    keep: Option<Vec<usize>>, // Indices of the columns written, every column when None
+    trailing: Vec<String>,    // Written after the columns of every record
+    trailing_columns: Vec<String>,
+    records: usize,
+}
+
+impl<'a> DlWriter<'a> {
+    pub fn new(writer: &'a mut dyn Write, columns: &[&str]) -> DlWriter<'a> {
+        DlWriter {
+            writer,
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            keep: None,
+            trailing: Vec::new(),
+            trailing_columns: Vec::new(),
+            records: 0,
+        }
+    }
+
+    // Only these columns are written, by their index in the columns, in this order
+    pub fn select(mut self, keep: &[usize]) -> Self {
+        self.keep = Some(keep.to_vec());
+        self
+    }
+
+    // Columns with the same fields for every record, e.g. the metadata of a family's file
+    pub fn trailing(mut self, columns: &[&str], fields: Vec<String>) -> Self {
+        self.trailing_columns = columns.iter().map(|c| c.to_string()).collect();
+        self.trailing = fields;
+        self
+    }
+
+    // The names of the columns written, in order, empty for a selected column it does not have
+    // as its fields are in write_record
+    pub fn header(&self) -> Vec<&str> {
+        let selected: Vec<&str> = match &self.keep {
+            Some(keep) => keep
+                .iter()
+                .map(|i| self.columns.get(*i).map_or("", String::as_str))
+                .collect(),
+            None => self.columns.iter().map(String::as_str).collect(),
+        };
+        selected
+            .into_iter()
+            .chain(self.trailing_columns.iter().map(String::as_str))
+            .collect()
+    }
+
+    pub fn write_header(&mut self) -> Result<()> {
+        let header = join_fields(self.header());
+        writeln!(self.writer, "#{}", header)
+    }
+
+    // Writes the selected fields of a record and the trailing ones, a selected column the
+    // record does not have is written empty
+    pub fn write_record<S: AsRef<str>>(&mut self, fields: &[S]) -> Result<()> {
+        let field = |i: usize| fields.get(i).map_or("", AsRef::as_ref);
+        let selected: Vec<&str> = match &self.keep {
+            Some(keep) => keep.iter().map(|i| field(*i)).collect(),
+            None => fields.iter().map(AsRef::as_ref).collect(),
+        };
+        let line = join_fields(
+            selected
+                .into_iter()
+                .chain(self.trailing.iter().map(String::as_str)),
+        );
+        self.records += 1;
+        writeln!(self.writer, "{}", line)
+    }
+
+    // Writes a line of another format as it is, counted as a record, e.g. a BED line
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        self.records += 1;
+        writeln!(self.writer, "{}", line)
+    }
+
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    // Flushes the writer, returning the number of records written
+    pub fn finish(self) -> Result<usize> {
+        self.writer.flush()?;
+        Ok(self.records)
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+
+// An nhmmer e-value, kept as written. nhmmer prints values far below the smallest f64, such
+// as 2.3e-400, which parse to 0 or to a subnormal that has lost its digits. The value is held
+// as its significant decimal digits and exponent, 0.<digits> x 10^exponent, so e-values
+// compare exactly however small, and 1e-3 equals 0.001.
+#[derive(Debug, Clone)]
+pub struct EValue {
+    text: String,
+    digits: String, // No leading or trailing zeros, empty for 0
+    exponent: i64,
+}
+
+impl EValue {
+    // Plain or scientific notation, "2.3e-101", "1E+03", "0.001", "12". E-values are never
+    // negative, a sign other than "+", or anything else, is None.
+    pub fn parse(text: &str) -> Option<EValue> {
+        let trimmed = text.trim();
+        let unsigned = trimmed.strip_prefix('+').unwrap_or(trimmed);
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(at) => (&unsigned[..at], &unsigned[at + 1..]),
+            None => (unsigned, "0"),
+        };
+        let exponent: i64 = match exponent.strip_prefix('+') {
+            Some(rest) if !rest.starts_with(['+', '-']) => rest.parse().ok()?,
+            Some(_) => return None,
+            None => exponent.parse().ok()?,
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        // 0.<digits> x 10^exponent, with the leading zeros of the mantissa moved to the exponent
+        let all = format!("{}{}", whole, fraction);
+        let leading = all.len() - all.trim_start_matches('0').len();
+        let digits = all.trim_matches('0').to_string();
+        let exponent = match digits.is_empty() {
+            true => 0,
+            false => exponent.checked_add(whole.len() as i64 - leading as i64)?,
+        };
+        Some(EValue {
+            text: trimmed.to_string(),
+            digits,
+            exponent,
+        })
+    }
+
+    // The value as written
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    // The nearest f64, 0 for values below its range
+    pub fn to_f64(&self) -> f64 {
+        self.text.parse().unwrap_or(f64::NAN)
+    }
+
+    // Whether the f64 holds the value as closely as a double can, rather than having lost
+    // digits to the subnormal range, or the whole value to 0 or infinity
+    pub fn is_representable(&self) -> bool {
+        let value = self.to_f64();
+        match self.digits.is_empty() {
+            true => value == 0.0,
+            false => value.is_normal(),
+        }
+    }
+
+    // A JSON number when representable, else the string as written. Legacy output is always
+    // the string.
+    pub fn to_json(&self, legacy: bool) -> Value {
+        match !legacy && self.is_representable() {
+            true => Value::from(self.to_f64()),
+            false => Value::String(self.text.clone()),
+        }
+    }
+}
+
+impl fmt::Display for EValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for EValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json(false).serialize(serializer)
+    }
+}
+
+impl Ord for EValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.digits.is_empty(), other.digits.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // The same number of digits before the point, then digit by digit
+            (false, false) => self
+                .exponent
+                .cmp(&other.exponent)
+                .then_with(|| self.digits.cmp(&other.digits)),
+        }
+    }
+}
+
+impl PartialOrd for EValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for EValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for EValue {}
@@ -0,0 +1,112 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::idx::unversioned_acc;
+use crate::paths::join_path;
+use crate::{FAMILIES_DIR, FAMILIES_FILE};
+
+// Classification and clades of the families of an assembly, from the <assembly>-families.json
+// some exports carry: {"assembly", "version", "data": {<accession>: {"name", "classification",
+// "clades": [...]}}}, or the data object alone. The file is optional, an assembly without it
+// has no metadata for any family.
+pub const FAMILY_META_COLUMNS: [&str; 2] = ["classification", "clades"];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FamilyMeta {
+    pub name: Option<String>,
+    pub classification: Option<String>,
+    pub clades: Option<Vec<String>>,
+}
+
+// The families JSON of an assembly read once, for every record a query joins metadata onto
+#[derive(Debug, Default)]
+pub struct FamilyMetadata {
+    families: HashMap<String, FamilyMeta>, // By accession without its version
+}
+
+// The families JSON of a prepared assembly
+pub fn families_file(assembly: &str, data_directory: &str) -> String {
+    join_path(
+        data_directory,
+        &[
+            assembly,
+            FAMILIES_DIR,
+            &format!("{}{}", assembly, FAMILIES_FILE),
+        ],
+    )
+}
+
+impl FamilyMetadata {
+    // Reads the families JSON of an assembly, empty when the assembly has none. Fields of the
+    // wrong type are read as missing.
+    pub fn load(assembly: &str, data_directory: &str) -> Result<FamilyMetadata> {
+        let path = families_file(assembly, data_directory);
+        if !Path::new(&path).exists() {
+            return Ok(FamilyMetadata::default());
+        }
+        let json: Value = serde_json::from_str(&read_to_string(&path)?).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{} Is Not Valid JSON - {}", path, e),
+            )
+        })?;
+        let data = json.get("data").unwrap_or(&json);
+        let Some(data) = data.as_object() else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} Is Not An Object Of Families", path),
+            ));
+        };
+        let text = |entry: &Value, field: &str| entry[field].as_str().map(str::to_string);
+        let families = data
+            .iter()
+            .map(|(accession, entry)| {
+                let clades = entry["clades"].as_array().map(|clades| {
+                    clades
+                        .iter()
+                        .filter_map(|clade| clade.as_str().map(str::to_string))
+                        .collect()
+                });
+                let meta = FamilyMeta {
+                    name: text(entry, "name"),
+                    classification: text(entry, "classification"),
+                    clades,
+                };
+                (unversioned_acc(accession).to_string(), meta)
+            })
+            .collect();
+        Ok(FamilyMetadata { families })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+
+    // The metadata of a family, its accession's version ignored
+    pub fn get(&self, accession: &str) -> Option<&FamilyMeta> {
+        self.families.get(unversioned_acc(accession))
+    }
+
+    // Sets the classification and clades of a JSON record's family, null when unknown
+    pub fn join(&self, accession: &str, record: &mut Value) {
+        let meta = self.get(accession);
+        record["classification"] = json!(meta.and_then(|m| m.classification.as_ref()));
+        record["clades"] = json!(meta.and_then(|m| m.clades.as_ref()));
+    }
+
+    // The classification and clades of a family as TSV fields, clades comma separated and
+    // both empty when unknown
+    pub fn fields(&self, accession: &str) -> [String; 2] {
+        let meta = self.get(accession);
+        [
+            meta.and_then(|m| m.classification.clone())
+                .unwrap_or_default(),
+            meta.and_then(|m| m.clades.as_ref())
+                .map(|clades| clades.join(","))
+                .unwrap_or_default(),
+        ]
+    }
+}
@@ -0,0 +1,283 @@
+use noodles::bgzf;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{
+    BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write,
+};
+use std::path::Path;
+
+use crate::allowlist::check_assembly;
+use crate::idx::{NRPH_COLUMN, STRAND_COLUMN};
+use crate::paths::path_string;
+use crate::records::bed_extent;
+use crate::{bgz_path, check_half_open, ASSEMBLY_DIR};
+
+// Genome sequence is optional. Exporting hit sequences needs an uncompressed <assembly>.fa in
+// the assembly's genome folder, with a samtools faidx .fai next to it or one built on open.
+pub const GENOME_DIR: &str = "genome";
+pub const GENOME_FILE: &str = ".fa";
+const FASTA_LINE_WIDTH: usize = 60;
+
+// One .fai line: sequence length, offset of its first base, bases and bytes per full line
+struct FaiEntry {
+    length: u64,
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+}
+
+pub struct GenomeFasta {
+    file: File,
+    index: HashMap<String, FaiEntry>,
+}
+
+impl GenomeFasta {
+    pub fn open(fasta_path: impl AsRef<Path>) -> Result<GenomeFasta> {
+        let fasta_path = &path_string(fasta_path);
+        let file = File::open(fasta_path)?;
+        let fai_path = format!("{}.fai", fasta_path);
+        let index = if Path::new(&fai_path).exists() {
+            read_fai(&fai_path)?
+        } else {
+            build_fai(&file)?
+        };
+        Ok(GenomeFasta { file, index })
+    }
+
+    pub fn contig_length(&self, name: &str) -> Option<u64> {
+        self.index.get(name).map(|entry| entry.length)
+    }
+
+    // Bases start to end of a contig, 1-based and fully closed
+    pub fn fetch(&mut self, name: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        let entry = match self.index.get(name) {
+            Some(entry) => entry,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Contig {} Not Found In Genome FASTA", name),
+                ))
+            }
+        };
+        if start == 0 || start > end || end > entry.length {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Region {}:{}-{} Is Outside The Contig Of {} bp",
+                    name, start, end, entry.length
+                ),
+            ));
+        }
+        let byte = |pos: u64| {
+            entry.offset + (pos / entry.line_bases) * entry.line_width + pos % entry.line_bases
+        };
+        let first = byte(start - 1);
+        let mut seq = vec![0; (byte(end - 1) - first + 1) as usize];
+        self.file.seek(SeekFrom::Start(first))?;
+        self.file.read_exact(&mut seq)?;
+        seq.retain(|b| !b.is_ascii_whitespace());
+        Ok(seq)
+    }
+}
+
+fn read_fai(fai_path: &str) -> Result<HashMap<String, FaiEntry>> {
+    let mut index = HashMap::new();
+    for line in BufReader::new(File::open(fai_path)?).lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let numbers: Vec<u64> = fields
+            .iter()
+            .skip(1)
+            .take(4)
+            .filter_map(|f| f.parse().ok())
+            .collect();
+        if numbers.len() != 4 || numbers[2] == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Malformed FAI Line In {} - {}", fai_path, line),
+            ));
+        }
+        index.insert(
+            fields[0].to_string(),
+            FaiEntry {
+                length: numbers[0],
+                offset: numbers[1],
+                line_bases: numbers[2],
+                line_width: numbers[3],
+            },
+        );
+    }
+    Ok(index)
+}
+
+// The .fai samtools faidx would write, for FASTA files without one. Every line of a sequence
+// but its last must have the same length.
+fn build_fai(file: &File) -> Result<HashMap<String, FaiEntry>> {
+    let mut index = HashMap::new();
+    let mut reader = BufReader::new(file);
+    let mut current: Option<(String, FaiEntry)> = None;
+    let mut offset = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let width = reader.read_until(b'\n', &mut line)? as u64;
+        if width == 0 {
+            break;
+        }
+        offset += width;
+        if line.starts_with(b">") {
+            if let Some((name, entry)) = current.take() {
+                index.insert(name, entry);
+            }
+            let header = String::from_utf8_lossy(&line[1..]);
+            let name = header.split_whitespace().next().unwrap_or("").to_string();
+            current = Some((
+                name,
+                FaiEntry {
+                    length: 0,
+                    offset,
+                    line_bases: 0,
+                    line_width: 0,
+                },
+            ));
+        } else if let Some((_, entry)) = current.as_mut() {
+            let bases = line.iter().filter(|b| !b.is_ascii_whitespace()).count() as u64;
+            if entry.line_bases == 0 {
+                entry.line_bases = bases;
+                entry.line_width = width;
+            }
+            entry.length += bases;
+        }
+    }
+    if let Some((name, entry)) = current {
+        index.insert(name, entry);
+    }
+    Ok(index)
+}
+
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'a' => b't',
+        b't' => b'a',
+        b'c' => b'g',
+        b'g' => b'c',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        b'r' => b'y',
+        b'y' => b'r',
+        b'k' => b'm',
+        b'm' => b'k',
+        b'b' => b'v',
+        b'v' => b'b',
+        b'd' => b'h',
+        b'h' => b'd',
+        other => other,
+    }
+}
+
+// Keeps case, IUPAC codes are complemented and anything else is left as is
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|base| complement(*base)).collect()
+}
+
+pub fn genome_fasta_path(assembly: &str, data_directory: impl AsRef<Path>) -> String {
+    let data_directory = &path_string(data_directory);
+    format!(
+        "{}/{}/{}/{}{}",
+        data_directory, assembly, GENOME_DIR, assembly, GENOME_FILE
+    )
+}
+
+// Writes the genomic sequence of every hit of a family as FASTA, reverse complemented on the
+// minus strand. Records are named <seq_name>:<start>-<end>(<strand>) after the region fetched,
+// 1-based and fully closed, which includes flank_bp bases each side clamped to the contig.
+// An out_path ending in .gz or .bgz is bgzf compressed. Returns the number of records written.
+pub fn export_family_fasta(
+    assembly: &str,
+    fam: &str,
+    nrph: &bool,
+    flank_bp: u64,
+    out_path: impl AsRef<Path>,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let out_path = &path_string(out_path);
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(ASSEMBLY_DIR), data_directory)?;
+    let assembly_path = format!("{}/{}", data_directory, assembly);
+    if !Path::new(&assembly_path).exists() {
+        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+    }
+    check_half_open(assembly, ASSEMBLY_DIR, data_directory)?;
+    let fam_file = bgz_path(format!("{}/{}", assembly_path, ASSEMBLY_DIR), fam);
+    if !Path::new(&fam_file).exists() {
+        panic!("Family {} Not Found In Assembly {}", fam, assembly_path);
+    }
+    let fasta_path = genome_fasta_path(assembly, data_directory);
+    if !Path::new(&fasta_path).exists() {
+        panic!(
+            "Genome FASTA {} Not Found, One Must Be Configured To Export Sequences",
+            fasta_path
+        );
+    }
+    let mut genome = GenomeFasta::open(&fasta_path)?;
+
+    let out_f = File::create(out_path)?;
+    let mut writer: Box<dyn Write> = if out_path.ends_with(".gz") || out_path.ends_with(".bgz") {
+        Box::new(bgzf::Writer::new(out_f))
+    } else {
+        Box::new(BufWriter::new(out_f))
+    };
+    let reader = bgzf::Reader::new(File::open(&fam_file)?);
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if *nrph && fields.get(NRPH_COLUMN) != Some(&"1") {
+            continue;
+        }
+        let Some((low, high)) = bed_extent(&fields) else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Malformed Record In {} - {}", fam_file, line),
+            ));
+        };
+        let seq_name = fields[0];
+        let contig_length = match genome.contig_length(seq_name) {
+            Some(length) => length,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "Contig {} Not Found In Genome FASTA {}",
+                        seq_name, fasta_path
+                    ),
+                ))
+            }
+        };
+        let start = low.saturating_sub(flank_bp).max(1);
+        let end = (high + flank_bp).min(contig_length);
+        let strand = fields.get(STRAND_COLUMN).copied().unwrap_or("+");
+        let mut seq = genome.fetch(seq_name, start, end)?;
+        if strand == "-" {
+            seq = reverse_complement(&seq);
+        }
+        writeln!(writer, ">{}:{}-{}({})", seq_name, start, end, strand)?;
+        for chunk in seq.chunks(FASTA_LINE_WIDTH) {
+            writer.write_all(chunk)?;
+            writer.write_all(b"\n")?;
+        }
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
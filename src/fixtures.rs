@@ -0,0 +1,302 @@
+use serde_json::{json, Map, Value};
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+
+use crate::backpressure::configured_max_buffered_bytes;
+use crate::idx::TILE_SIZE;
+use crate::paths::path_string;
+use crate::{prepare_assembly, ASSEMBLY_FILE, MASKS_FILE, MOD_LEN_FILE, SEQUENCE_FILE};
+
+// Synthetic export data for smoke tests. Everything is derived from a fixed seed so the
+// same assembly is produced on every run and expectations can be computed from it.
+
+const FIXTURE_SEED: u64 = 0x7e1d_2024;
+const CONTIG_TILES: [(&str, u64); 2] = [("chr1", 10), ("chr2", 8)];
+const FAMILIES: [(&str, &str, u64); 4] = [
+    ("DF000000001", "L1_Syn", 6000),
+    ("DF000000002", "AluS_Syn", 300),
+    ("DF000000003", "MER_Syn", 800),
+    ("DF000000004", "LTR_Syn", 1200),
+];
+const HITS_PER_TILE: u64 = 55;
+const MASKS_PER_TILE: u64 = 3;
+const REPEAT_STRS: [&str; 4] = ["A", "TC", "AAAT", "CAG"];
+
+// Small LCG, good enough for spreading fixture values and free of extra dependencies
+struct FixtureRng(u64);
+
+impl FixtureRng {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+}
+
+pub struct SyntheticHit {
+    pub seq_acc: String,
+    pub fam_acc: String,
+    pub family_name: String,
+    pub bit_score: f64,
+    pub strand: String,
+    pub seq_start: u64, // Greater than seq_end on the minus strand, as in the exports
+    pub seq_end: u64,
+    pub model_start: u64,
+    pub model_end: u64,
+    pub nrph: bool,
+}
+
+impl SyntheticHit {
+    // Overlap with a 1-based, fully closed region, as queries give it, on the extent of the
+    // hit whatever its strand
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.seq_start.min(self.seq_end) <= end && self.seq_start.max(self.seq_end) >= start
+    }
+}
+
+pub struct SyntheticMask {
+    pub seq_acc: String,
+    pub seq_start: u64,
+    pub seq_end: u64,
+    pub repeat_str: String,
+}
+
+impl SyntheticMask {
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.seq_start <= end && self.seq_end >= start
+    }
+}
+
+pub struct SyntheticAssembly {
+    pub assembly: String,
+    pub contigs: Vec<(String, u64)>,
+    pub families: Vec<(String, String, u64)>,
+    pub hits: Vec<SyntheticHit>,
+    pub masks: Vec<SyntheticMask>,
+}
+
+// Two contigs with a few families spread over every tile (~1000 hits). Hits never cross
+// a tile boundary.
+pub fn synthetic_assembly(assembly: &str) -> SyntheticAssembly {
+    let tile_size = TILE_SIZE as u64;
+    let mut rng = FixtureRng(FIXTURE_SEED);
+    let mut contigs = Vec::new();
+    let mut hits = Vec::new();
+    let mut masks = Vec::new();
+
+    for (contig, tiles) in CONTIG_TILES {
+        contigs.push((contig.to_string(), tiles * tile_size));
+        for tile in 0..tiles {
+            let slot = tile_size / HITS_PER_TILE;
+            for i in 0..HITS_PER_TILE {
+                let (fam_acc, family_name, model_len) = FAMILIES[rng.range(0, 4) as usize];
+                let length = rng.range(40, slot / 2);
+                let low = tile * tile_size + i * slot + rng.range(1, slot - length);
+                let high = low + length - 1;
+                let model_start = rng.range(1, model_len - length);
+                let strand = if rng.range(0, 3) == 0 { "-" } else { "+" };
+                let (seq_start, seq_end) = if strand == "-" {
+                    (high, low)
+                } else {
+                    (low, high)
+                };
+                hits.push(SyntheticHit {
+                    seq_acc: contig.to_string(),
+                    fam_acc: fam_acc.to_string(),
+                    family_name: family_name.to_string(),
+                    bit_score: rng.range(200, 50000) as f64 / 100.0,
+                    strand: strand.to_string(),
+                    seq_start,
+                    seq_end,
+                    model_start,
+                    model_end: model_start + length - 1,
+                    nrph: rng.range(0, 10) < 3,
+                });
+            }
+            let slot = tile_size / MASKS_PER_TILE;
+            for i in 0..MASKS_PER_TILE {
+                let repeat_str = REPEAT_STRS[rng.range(0, 4) as usize];
+                let length = rng.range(20, 200);
+                let seq_start = tile * tile_size + i * slot + rng.range(1, slot - length);
+                masks.push(SyntheticMask {
+                    seq_acc: contig.to_string(),
+                    seq_start,
+                    seq_end: seq_start + length,
+                    repeat_str: repeat_str.to_string(),
+                });
+            }
+        }
+    }
+
+    SyntheticAssembly {
+        assembly: assembly.to_string(),
+        contigs,
+        families: FAMILIES
+            .iter()
+            .map(|(acc, name, len)| (acc.to_string(), name.to_string(), *len))
+            .collect(),
+        hits,
+        masks,
+    }
+}
+
+// Writes the assembly as the exports prepare_assembly expects to find in
+// <export_directory>/<assembly>/: full region and mask TSVs plus the two JSON files
+pub fn write_synthetic_export(
+    synth: &SyntheticAssembly,
+    export_directory: impl AsRef<Path>,
+) -> Result<()> {
+    let export_directory = &path_string(export_directory);
+    let export_dir = format!("{}/{}", export_directory, synth.assembly);
+    create_dir_all(&export_dir)?;
+
+    // prep_beds needs the annotation TSV grouped by family
+    let mut hits: Vec<&SyntheticHit> = synth.hits.iter().collect();
+    hits.sort_by(|a, b| a.fam_acc.cmp(&b.fam_acc));
+    let seq_lens: Map<String, Value> = synth
+        .contigs
+        .iter()
+        .map(|(name, len)| (name.clone(), json!(len)))
+        .collect();
+    let mut out = BufWriter::new(File::create(format!(
+        "{}/{}{}",
+        export_dir, synth.assembly, ASSEMBLY_FILE
+    ))?);
+    writeln!(out, "# Synthetic export for {}", synth.assembly)?;
+    for hit in hits {
+        let length = hit.seq_start.abs_diff(hit.seq_end) + 1;
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{:.2}\t{:.1e}\t0.1\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}M\t12.5\t{}\tCAF",
+            hit.seq_acc,
+            hit.fam_acc,
+            hit.family_name,
+            hit.bit_score,
+            10f64.powf(-hit.bit_score / 10.0),
+            hit.model_start,
+            hit.model_end,
+            hit.strand,
+            hit.seq_start,
+            hit.seq_end,
+            hit.seq_start,
+            hit.seq_end,
+            seq_lens[&hit.seq_acc],
+            length,
+            if hit.nrph { 1 } else { 0 },
+        )?;
+    }
+    out.flush()?;
+
+    let mut out = BufWriter::new(File::create(format!(
+        "{}/{}{}",
+        export_dir, synth.assembly, MASKS_FILE
+    ))?);
+    for mask in &synth.masks {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            mask.seq_acc,
+            mask.seq_start,
+            mask.seq_end,
+            mask.repeat_str,
+            mask.repeat_str.len()
+        )?;
+    }
+    out.flush()?;
+
+    let sequences: Map<String, Value> = synth
+        .contigs
+        .iter()
+        .map(|(name, len)| {
+            (
+                name.clone(),
+                json!({"length": len, "description": "", "is_genomic": 1}),
+            )
+        })
+        .collect();
+    let model_lengths: Map<String, Value> = synth
+        .families
+        .iter()
+        .map(|(acc, _, len)| (acc.clone(), json!({ "length": len })))
+        .collect();
+    for (file, data) in [(SEQUENCE_FILE, sequences), (MOD_LEN_FILE, model_lengths)] {
+        let out = File::create(format!("{}/{}{}", export_dir, synth.assembly, file))?;
+        serde_json::to_writer(
+            out,
+            &json!({"assembly": synth.assembly, "version": "synthetic", "data": data}),
+        )?;
+    }
+    Ok(())
+}
+
+// Random bases for each contig, lowercase in every tenth stretch of ten as if soft masked
+pub fn synthetic_genome(contigs: &[(String, u64)]) -> Vec<(String, String)> {
+    let mut rng = FixtureRng(FIXTURE_SEED);
+    contigs
+        .iter()
+        .map(|(name, length)| {
+            let seq = (0..*length)
+                .map(|pos| {
+                    let base = b"ACGT"[rng.range(0, 4) as usize];
+                    if (pos / 10) % 10 == 9 {
+                        base.to_ascii_lowercase() as char
+                    } else {
+                        base as char
+                    }
+                })
+                .collect();
+            (name.clone(), seq)
+        })
+        .collect()
+}
+
+// Writes records as FASTA with line_width bases per line
+pub fn write_fasta(
+    path: impl AsRef<Path>,
+    records: &[(String, String)],
+    line_width: usize,
+) -> Result<()> {
+    let path = &path_string(path);
+    let mut out = BufWriter::new(File::create(path)?);
+    for (name, seq) in records {
+        writeln!(out, ">{} synthetic", name)?;
+        for line in seq.as_bytes().chunks(line_width) {
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    out.flush()
+}
+
+// Generates the assembly, writes its exports under <working_directory>/exports and prepares
+// and indexes it under <working_directory>/data, which is returned as the data directory
+pub fn prepare_synthetic_assembly(
+    assembly: &str,
+    working_directory: impl AsRef<Path>,
+) -> Result<(SyntheticAssembly, String)> {
+    let working_directory = &path_string(working_directory);
+    let export_directory = format!("{}/exports", working_directory);
+    let data_directory = format!("{}/data", working_directory);
+    create_dir_all(&data_directory)?;
+    let synth = synthetic_assembly(assembly);
+    write_synthetic_export(&synth, &export_directory)?;
+    prepare_assembly(
+        assembly,
+        &data_directory,
+        &export_directory,
+        false,
+        &None,
+        &None,
+        configured_max_buffered_bytes(None),
+        &None,
+        false,
+    )?;
+    Ok((synth, data_directory))
+}
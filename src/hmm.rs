@@ -0,0 +1,137 @@
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+
+// Model lengths read from HMMER profile files, for assemblies annotated by nhmmer runs of our
+// own rather than exported from Dfam, which have no model_lengths JSON. Only the NAME, ACC and
+// LENG lines of each model's header are read.
+pub const HMM_EXTENSION: &str = "hmm";
+
+#[derive(Debug, PartialEq)]
+pub struct HmmModel {
+    pub name: String,
+    pub accession: Option<String>, // As written, with its version
+    pub length: u64,
+}
+
+impl HmmModel {
+    // The model_lengths key: the accession without its version, or the name of a model
+    // without one, as families are named in the BED files
+    pub fn key(&self) -> &str {
+        match &self.accession {
+            Some(accession) => accession.split('.').next().unwrap(),
+            None => &self.name,
+        }
+    }
+}
+
+// The models of a HMMER text file, every model of a multi-model file. Files written by
+// hmmpress (.h3f, .h3i, .h3m, .h3p) are binary and refused, the .hmm they came from is read.
+pub fn read_hmm_file(path: &str) -> Result<Vec<HmmModel>> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+    let mut models = Vec::new();
+    let mut model: Option<(Option<String>, Option<String>, Option<u64>)> = None;
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line =
+            line.map_err(|e| invalid(format!("{} Is Not A HMMER Text File - {}", path, e)))?;
+        if line.starts_with("HMMER") {
+            model = Some((None, None, None));
+            continue;
+        }
+        if i == 0 {
+            return Err(invalid(format!("{} Is Not A HMMER Text File", path)));
+        }
+        let Some((name, accession, length)) = &mut model else {
+            continue;
+        };
+        let (tag, value) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+        match tag {
+            "NAME" => *name = Some(value.trim().to_string()),
+            "ACC" => *accession = Some(value.trim().to_string()),
+            "LENG" => {
+                *length = Some(value.trim().parse().map_err(|_| {
+                    invalid(format!("Invalid LENG \"{}\" In {}", value.trim(), path))
+                })?)
+            }
+            "//" => {
+                let (name, accession, length) = model.take().unwrap();
+                let name = name.ok_or_else(|| {
+                    invalid(format!(
+                        "Model {} Of {} Has No NAME",
+                        models.len() + 1,
+                        path
+                    ))
+                })?;
+                let length = length
+                    .ok_or_else(|| invalid(format!("Model {} Of {} Has No LENG", name, path)))?;
+                models.push(HmmModel {
+                    name,
+                    accession,
+                    length,
+                });
+            }
+            _ => {}
+        }
+    }
+    if model.is_some() {
+        return Err(invalid(format!("{} Ends Inside A Model", path)));
+    }
+    Ok(models)
+}
+
+// The models of a .hmm file, or of every .hmm file of a folder in name order, other files
+// such as those of hmmpress left out
+pub fn read_hmm_models(hmm_path: &str) -> Result<Vec<HmmModel>> {
+    if !Path::new(hmm_path).is_dir() {
+        return read_hmm_file(hmm_path);
+    }
+    let mut files: Vec<String> = fs::read_dir(hmm_path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == HMM_EXTENSION))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No .{} Files In {}", HMM_EXTENSION, hmm_path),
+        ));
+    }
+    let mut models = Vec::new();
+    for file in files {
+        models.extend(read_hmm_file(&file)?);
+    }
+    Ok(models)
+}
+
+// Writes the model_lengths JSON of an assembly, as the Dfam export has it, from the models of
+// a .hmm file or folder: {"assembly", "version", "data": {<key>: {"length", "name"}}}. A key
+// given to two models is an error. Returns the number of models.
+pub fn generate_model_lengths(hmm_path: &str, assembly: &str, out_json: &str) -> Result<usize> {
+    let models = read_hmm_models(hmm_path)?;
+    let mut data = Map::new();
+    for model in &models {
+        let entry = json!({ "length": model.length, "name": model.name });
+        if data.insert(model.key().to_string(), entry).is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Model {} Appears More Than Once In {}",
+                    model.key(),
+                    hmm_path
+                ),
+            ));
+        }
+    }
+    let model_lengths = json!({
+        "assembly": assembly,
+        "version": "hmm",
+        "data": Value::Object(data),
+    });
+    // Renamed into place so a reader never sees a partial file
+    let tmp_file = format!("{}.tmp", out_json);
+    serde_json::to_writer(File::create(&tmp_file)?, &model_lengths)?;
+    fs::rename(&tmp_file, out_json)?;
+    Ok(models.len())
+}
@@ -0,0 +1,432 @@
+use axum::body::Body;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderName, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tower_http::cors::{Any, CorsLayer};
+
+use crate::idx::DEFAULT_MAX_REGION;
+use crate::options::IdxQueryOptions;
+use crate::paths::path_string;
+use crate::querylog::QueryLogger;
+use crate::{
+    family_summary, get_chrom_id, idx_query_into, list_assemblies, list_families, query_etag,
+    FamilyPage, FamilySummary, ASSEMBLY_DIR, INDEX_DATA_TYPES,
+};
+
+// Read-only JSON routes over the query functions, for browsers and demos without the Node
+// layer. There is no authentication, the server is meant to run behind a proxy.
+//   GET /assemblies                                  list_assemblies
+//   GET /{assembly}/annotations?chrom=&start=&end=   idx_query of assembly_alignments,
+//       &family=&nrph=                               chrom by accession or id, family a
+//                                                    comma separated list. Answered with an
+//                                                    ETag, 304 for an If-None-Match of it, and
+//                                                    to HEAD with the hits counted in
+//                                                    X-Total-Count, see query_etag. The hits
+//                                                    are streamed, see streamed
+//   GET /{assembly}/families?data_type=&prefix=      list_families, data_type defaults to
+//       &limit=&offset=                              assembly_alignments, pages of at most
+//                                                    MAX_FAMILY_PAGE
+//   GET /{assembly}/families/{acc}/summary           family_summary
+//   GET /{assembly}/sequences/{query}                get_chrom_id
+pub const DEFAULT_BIND: &str = "127.0.0.1:8080";
+pub const MAX_FAMILY_PAGE: usize = 1000;
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+// Annotation hits are sent in chunks of about this size as they are formatted, at most
+// BODY_CHUNKS of them waiting for a slow client before the query stalls
+const BODY_CHUNK_BYTES: usize = 64 * 1024;
+const BODY_CHUNKS: usize = 4;
+
+#[derive(Clone)]
+struct ServerState {
+    data_directory: String,
+    query_log: Option<QueryLogger>,
+}
+
+// A failed request, answered with its status and {"error": <message>}
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+// Assemblies left out by the allowlist are not found, as if they did not exist
+impl From<Error> for ApiError {
+    fn from(e: Error) -> ApiError {
+        let status = match e.kind() {
+            ErrorKind::NotFound | ErrorKind::PermissionDenied => StatusCode::NOT_FOUND,
+            ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+            ErrorKind::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError(status, e.to_string())
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+// Runs a library call on the blocking pool. Bad options and missing data are errors of the
+// library call, a panic is a bug and answered as an internal error instead of dropping the
+// connection.
+async fn blocking<T: Send + 'static>(
+    query: impl FnOnce() -> Result<T> + Send + 'static,
+) -> ApiResult<T> {
+    joined(tokio::task::spawn_blocking(query)).await
+}
+
+async fn joined<T>(task: JoinHandle<Result<T>>) -> ApiResult<T> {
+    match task.await {
+        Ok(result) => result.map_err(ApiError::from),
+        Err(e) => {
+            let message = match e.try_into_panic() {
+                Ok(panic) => panic
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or_else(|| "Query Failed".to_string()),
+                Err(e) => e.to_string(),
+            };
+            Err(ApiError(StatusCode::INTERNAL_SERVER_ERROR, message))
+        }
+    }
+}
+
+// JSON the library has already serialized, passed through as it is
+fn json_body(body: impl Into<Body>) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], body.into()).into_response()
+}
+
+// Sends what is written to a response body in chunks. Dropped before finish, after a failed
+// or panicking query, it ends the body with an error so the response is cut off rather than
+// passed off as complete.
+struct ChannelWriter {
+    tx: mpsc::Sender<Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    sent: bool,
+    finished: bool,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(BODY_CHUNK_BYTES));
+        self.sent = true;
+        // The client went away, the query stops at its next write
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| Error::new(ErrorKind::BrokenPipe, "Client Disconnected"))
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.send()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.chunk.extend_from_slice(buf);
+        if self.chunk.len() >= BODY_CHUNK_BYTES {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ChannelWriter {
+    fn drop(&mut self) {
+        if self.sent && !self.finished {
+            let cut = Error::new(ErrorKind::Interrupted, "Query Failed While Responding");
+            let _ = self.tx.blocking_send(Err(cut));
+        }
+    }
+}
+
+// Runs a query writing its response on the blocking pool and streams what it writes. The
+// status is only settled by the first chunk, a query failing before writing anything, as
+// idx_query_into does for bad options, missing data and timeouts, is answered with its error.
+async fn streamed(
+    query: impl FnOnce(&mut ChannelWriter) -> Result<()> + Send + 'static,
+) -> ApiResult<Body> {
+    let (tx, mut rx) = mpsc::channel(BODY_CHUNKS);
+    let task = tokio::task::spawn_blocking(move || {
+        let mut writer = ChannelWriter {
+            tx,
+            chunk: Vec::with_capacity(BODY_CHUNK_BYTES),
+            sent: false,
+            finished: false,
+        };
+        query(&mut writer)?;
+        writer.finish()
+    });
+    match rx.recv().await {
+        Some(first) => {
+            let rest = ReceiverStream::new(rx);
+            Ok(Body::from_stream(tokio_stream::once(first).chain(rest)))
+        }
+        None => {
+            joined(task).await?;
+            Err(ApiError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Query Wrote No Response".to_string(),
+            ))
+        }
+    }
+}
+
+async fn assemblies(State(state): State<ServerState>) -> ApiResult<Json<Vec<String>>> {
+    let assemblies = blocking(move || list_assemblies(false, &state.data_directory)).await?;
+    Ok(Json(assemblies))
+}
+
+#[derive(Deserialize)]
+struct AnnotationParams {
+    chrom: String,
+    start: u64,
+    end: u64,
+    family: Option<String>, // Comma separated accessions
+    #[serde(default)]
+    nrph: bool,
+}
+
+// How an annotations request is answered: not modified, the count of a HEAD request or the
+// hits of a query on the resolved contig
+enum Annotations {
+    NotModified,
+    Total(u64),
+    Hits(String, Box<IdxQueryOptions>),
+}
+
+// Whether an If-None-Match header names the etag, weak or strong, or is *
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag).trim_matches('"') == etag)
+}
+
+async fn annotations(
+    State(state): State<ServerState>,
+    Path(assembly): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+    params: std::result::Result<Query<AnnotationParams>, QueryRejection>,
+) -> ApiResult<Response> {
+    let Query(params) = params.map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.body_text()))?;
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (start, end) = (params.start, params.end);
+    let data_directory = state.data_directory.clone();
+    let query_assembly = assembly.clone();
+    let (etag, answer) = blocking(move || {
+        let chrom = get_chrom_id(&assembly, &params.chrom, &state.data_directory)?;
+        if chrom == "-1" {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Sequence {} Not Found In {}", params.chrom, assembly),
+            ));
+        }
+        let options = IdxQueryOptions {
+            max_region: Some(DEFAULT_MAX_REGION),
+            family: params
+                .family
+                .map(|family| family.split(',').map(str::to_string).collect()),
+            nrph: params.nrph,
+            query_log: state.query_log,
+            ..IdxQueryOptions::default()
+        };
+        // The etag is worked out before the search, a cached response still current is not
+        // searched for again
+        let etag = query_etag(
+            &assembly,
+            ASSEMBLY_DIR,
+            &chrom,
+            params.start,
+            params.end,
+            &options,
+            &state.data_directory,
+        )?;
+        if if_none_match.is_some_and(|tags| etag_matches(&tags, &etag)) {
+            return Ok((etag, Annotations::NotModified));
+        }
+        let answer = match method {
+            Method::HEAD => {
+                let mut head = Vec::new();
+                idx_query_into(
+                    &assembly,
+                    ASSEMBLY_DIR,
+                    &chrom,
+                    params.start,
+                    params.end,
+                    &IdxQueryOptions {
+                        head: true,
+                        ..options
+                    },
+                    &state.data_directory,
+                    &mut head,
+                )?;
+                let head: Value = serde_json::from_slice(&head)?;
+                Annotations::Total(head["total"].as_u64().unwrap_or_default())
+            }
+            _ => Annotations::Hits(chrom, Box::new(options)),
+        };
+        Ok((etag, answer))
+    })
+    .await?;
+    let etag = [(header::ETAG, format!("\"{}\"", etag))];
+    Ok(match answer {
+        Annotations::NotModified => (StatusCode::NOT_MODIFIED, etag).into_response(),
+        Annotations::Total(total) => (
+            etag,
+            [(
+                HeaderName::from_static(TOTAL_COUNT_HEADER),
+                total.to_string(),
+            )],
+        )
+            .into_response(),
+        // Written into the body as the hits are formatted, never held whole
+        Annotations::Hits(chrom, options) => {
+            let body = streamed(move |out| {
+                idx_query_into(
+                    &query_assembly,
+                    ASSEMBLY_DIR,
+                    &chrom,
+                    start,
+                    end,
+                    &options,
+                    &data_directory,
+                    out,
+                )
+            })
+            .await?;
+            (etag, json_body(body)).into_response()
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct FamilyListParams {
+    data_type: Option<String>,
+    prefix: Option<String>,
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+async fn families(
+    State(state): State<ServerState>,
+    Path(assembly): Path<String>,
+    params: std::result::Result<Query<FamilyListParams>, QueryRejection>,
+) -> ApiResult<Json<FamilyPage>> {
+    let Query(params) = params.map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.body_text()))?;
+    let data_type = params.data_type.unwrap_or(ASSEMBLY_DIR.to_string());
+    if !INDEX_DATA_TYPES.contains(&data_type.as_str()) {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("Unknown Data Type {}", data_type),
+        ));
+    }
+    let limit = params.limit.unwrap_or(MAX_FAMILY_PAGE).min(MAX_FAMILY_PAGE);
+    let page = blocking(move || {
+        list_families(
+            &assembly,
+            &data_type,
+            params.prefix.as_deref(),
+            Some(limit),
+            params.offset,
+            &state.data_directory,
+        )
+    })
+    .await?;
+    Ok(Json(page))
+}
+
+async fn family(
+    State(state): State<ServerState>,
+    Path((assembly, accession)): Path<(String, String)>,
+) -> ApiResult<Json<FamilySummary>> {
+    let summary =
+        blocking(move || family_summary(&assembly, &accession, &state.data_directory)).await?;
+    Ok(Json(summary))
+}
+
+async fn sequence(
+    State(state): State<ServerState>,
+    Path((assembly, query)): Path<(String, String)>,
+) -> ApiResult<Json<String>> {
+    let accession =
+        blocking(
+            move || match get_chrom_id(&assembly, &query, &state.data_directory)?.as_str() {
+                "-1" => Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Sequence {} Not Found In {}", query, assembly),
+                )),
+                accession => Ok(accession.to_string()),
+            },
+        )
+        .await?;
+    Ok(Json(accession))
+}
+
+pub fn router(
+    data_directory: impl AsRef<std::path::Path>,
+    query_log: &Option<QueryLogger>,
+) -> Router {
+    let data_directory = &path_string(data_directory);
+    Router::new()
+        .route("/assemblies", get(assemblies))
+        .route("/:assembly/annotations", get(annotations))
+        .route("/:assembly/families", get(families))
+        .route("/:assembly/families/:accession/summary", get(family))
+        .route("/:assembly/sequences/:query", get(sequence))
+        .layer(
+            CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods([Method::GET, Method::HEAD])
+                .expose_headers([header::ETAG, HeaderName::from_static(TOTAL_COUNT_HEADER)]),
+        )
+        .with_state(ServerState {
+            data_directory: data_directory.to_string(),
+            query_log: query_log.clone(),
+        })
+}
+
+// Serves the routes on listener until the process is stopped. The listener is bound by the
+// caller, so tests can bind port 0 and read the port back before serving.
+pub fn serve(
+    listener: TcpListener,
+    data_directory: impl AsRef<std::path::Path>,
+    query_log: &Option<QueryLogger>,
+) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    listener.set_nonblocking(true)?;
+    let app = router(data_directory, query_log);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            axum::serve(listener, app).await
+        })
+}
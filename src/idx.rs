@@ -1,21 +1,140 @@
 use byteorder::{LittleEndian, ReadBytesExt};
 use log::{debug, error, info, warn, Level, LevelFilter, Metadata, Record};
 use noodles::bgzf;
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::prelude::*;
 use std::io::{Seek, SeekFrom};
 use std::path::Path;
 use std::process::exit;
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::allowlist::check_allowed;
+use crate::paths::{join_path, path_string, stored_path};
+pub use crate::records::bed_fields;
+use crate::records::BedRecordStream;
 
 // static MY_LOGGER: MyLogger = MyLogger;
 pub const TILE_SIZE: u32 = 16384;
+// BED column holding the strand of annotation records, masks have none
+pub const STRAND_COLUMN: usize = 5;
+// BED columns of the scores used by the threshold filters
+pub const BIT_SCORE_COLUMN: usize = 4;
+pub const E_VALUE_COLUMN: usize = 11;
+// BED column of the NRPH flag of assembly annotations
+pub const NRPH_COLUMN: usize = 12;
+// BED column of the nhmmer bias of annotation and benchmark records
+pub const BIAS_COLUMN: usize = 6;
+// BED column of the family accession of annotation and benchmark records, as exported, with or
+// without its version
+pub const FAMILY_COLUMN: usize = 3;
+
+// The order of records in every sorted output: contig, the low and high ends of the record
+// whatever its strand, family accession, bit score highest first, then the raw line. Records
+// at the same position sort the same way whichever file, run or tile they were read from.
+// Masks sort by repeat_str and repeat_length in place of family and bit score. Unparsable
+// coordinates count as 0 and unparsable scores sort last.
+#[derive(Debug)]
+pub struct RecordOrder<'a> {
+    contig: &'a str,
+    low: u64,
+    high: u64,
+    family: &'a str,
+    bit_score: Option<f64>,
+    line: &'a str,
+}
+
+impl<'a> RecordOrder<'a> {
+    pub fn new(line: &'a str) -> RecordOrder<'a> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let fields = bed_fields(line);
+        let column = |column: usize| fields.get(column).copied().unwrap_or_default();
+        let coordinate = |i: usize| column(i).parse::<u64>().unwrap_or(0);
+        let (start, end) = (coordinate(1), coordinate(2));
+        RecordOrder {
+            contig: fields[0],
+            low: start.min(end),
+            high: start.max(end),
+            family: column(3),
+            bit_score: column(BIT_SCORE_COLUMN)
+                .parse::<f64>()
+                .ok()
+                .filter(|score| !score.is_nan()),
+            line,
+        }
+    }
+
+    pub fn contig(&self) -> &'a str {
+        self.contig
+    }
+}
+
+impl Ord for RecordOrder<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.contig, self.low, self.high, self.family)
+            .cmp(&(other.contig, other.low, other.high, other.family))
+            .then_with(|| match (self.bit_score, other.bit_score) {
+                (Some(a), Some(b)) => b.total_cmp(&a),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            })
+            .then_with(|| self.line.cmp(other.line))
+    }
+}
+
+impl PartialOrd for RecordOrder<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RecordOrder<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for RecordOrder<'_> {}
+
+// Sorts records by RecordOrder, their contigs first ordered by contig_rank
+pub fn sort_records(lines: &mut Vec<String>, contig_rank: impl Fn(&str) -> usize) {
+    let mut order: Vec<usize> = (0..lines.len()).collect();
+    {
+        let keys: Vec<(usize, RecordOrder)> = lines
+            .iter()
+            .map(|line| {
+                let key = RecordOrder::new(line);
+                (contig_rank(key.contig), key)
+            })
+            .collect();
+        order.sort_unstable_by(|a, b| keys[*a].cmp(&keys[*b]));
+    }
+    let mut unsorted: Vec<Option<String>> = std::mem::take(lines).into_iter().map(Some).collect();
+    lines.extend(order.into_iter().map(|i| unsorted[i].take().unwrap()));
+}
+
+// Whether the bias of a record is at most max_bias, None when it is empty or not a number.
+// Callers decide whether such records pass, see --strict-bias.
+pub fn bias_within(fields: &[&str], max_bias: f64) -> Option<bool> {
+    fields
+        .get(BIAS_COLUMN)
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| !v.is_nan())
+        .map(|bias| bias <= max_bias)
+}
 
+// Set with MY_LOGGER above when debugging
+#[allow(dead_code)]
 struct MyLogger;
 
 impl log::Log for MyLogger {
@@ -25,7 +144,7 @@ impl log::Log for MyLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            println!("{} - {}", record.level(), record.args());
+            eprintln!("{} - {}", record.level(), record.args());
         }
     }
     fn flush(&self) {}
@@ -33,8 +152,221 @@ impl log::Log for MyLogger {
 // Magic number for this index format (6-bytes)
 const MAGIC_NUMBER: &[u8] = b"#R_IDX";
 
-// File format version (2-bytes)
-const FORMAT_VERSION: u16 = 0;
+// File format version (2-bytes). Version 0 stored names in 40 byte fields, version 1 stores
+// them with their length. Both are read, only the current version is written.
+pub const FORMAT_VERSION: u16 = 1;
+
+// Format version of indexes built with long-range overflow lists, version 1 with a list of
+// ranges per contig stored once instead of in every tile. Only written when an index has any,
+// see ContigIndex::set_overflow_tiles.
+pub const OVERFLOW_FORMAT_VERSION: u16 = 2;
+
+// Format version of deterministic indexes, version 2 with a content hash of each bgz file in
+// place of its modification time, so rebuilding over the same files writes the same bytes.
+// The overflow range counts are always stored, zero when there are none. Only written when
+// asked for, see ContigIndex::set_deterministic.
+pub const HASHED_FORMAT_VERSION: u16 = 3;
+
+// Oldest and newest format versions read
+pub const MIN_FORMAT_VERSION: u16 = 0;
+pub const MAX_FORMAT_VERSION: u16 = HASHED_FORMAT_VERSION;
+
+// Name field width of format version 0
+const V0_NAME_BYTES: usize = 40;
+
+// Structural counts (contigs, files, tiles per contig, ranges per tile) are stored as u32,
+// anything larger cannot be written in this format version
+pub const MAX_INDEX_COUNT: u64 = u32::MAX as u64;
+
+// Contig and bgz file names are stored after a u16 length, file names relative to the data
+// type folder
+pub const MAX_FILE_NAME_BYTES: usize = u16::MAX as usize;
+
+// Duplication factor, ranges stored in tiles per range indexed, above which building an index
+// warns and suggests a larger tile size
+pub const DEFAULT_MAX_DUPLICATION: f64 = 4.0;
+
+// Default longest region idx-query searches, a little over the longest human chromosome
+pub const DEFAULT_MAX_REGION: u64 = 250_000_000;
+
+// Why a query region was refused, see check_region
+#[derive(Debug, PartialEq)]
+pub enum RegionError {
+    ZeroStart,
+    ZeroEnd,
+    StartNotBeforeEnd { start: u64, end: u64 },
+    TooLong { length: u64, max_region: u64 },
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegionError::ZeroStart => write!(f, "Invalid Start 0, Coordinates Are 1-Based"),
+            RegionError::ZeroEnd => write!(f, "Invalid End 0, Coordinates Are 1-Based"),
+            RegionError::StartNotBeforeEnd { start, end } => write!(
+                f,
+                "Invalid Region, Start {} Must Be Less Than End {}",
+                start, end
+            ),
+            RegionError::TooLong { length, max_region } => write!(
+                f,
+                "Region Of {} bp Is Longer Than The Maximum Of {} bp",
+                length, max_region
+            ),
+        }
+    }
+}
+
+impl Error for RegionError {}
+
+/// Checks a query region before any searching: 1-based, start before end, and no longer
+/// than max_region when given.
+///
+/// ```
+/// use te_idx::idx::{check_region, RegionError};
+///
+/// assert_eq!(check_region(1, 1000, Some(5000)), Ok(()));
+/// assert_eq!(check_region(0, 1000, None), Err(RegionError::ZeroStart));
+/// assert!(matches!(check_region(1, 10001, Some(5000)), Err(RegionError::TooLong { .. })));
+/// ```
+pub fn check_region(start: u64, end: u64, max_region: Option<u64>) -> Result<(), RegionError> {
+    if start == 0 {
+        return Err(RegionError::ZeroStart);
+    }
+    if end == 0 {
+        return Err(RegionError::ZeroEnd);
+    }
+    if start >= end {
+        return Err(RegionError::StartNotBeforeEnd { start, end });
+    }
+    match max_region {
+        Some(max_region) if end - start > max_region => Err(RegionError::TooLong {
+            length: end - start,
+            max_region,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Ranges name their bgz file by its position in the index's file list, as bed_idx. Two entries
+// stored with one name, e.g. names cut to the 40 bytes of format version 0 sharing a prefix,
+// would have searches read one file for the ranges of both, so an index holding any is
+// neither written nor read.
+fn check_unique_file_names<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    index_file: &str,
+) -> io::Result<()> {
+    let mut seen = HashSet::new();
+    let mut collisions = BTreeSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            collisions.insert(name);
+        }
+    }
+    if collisions.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Index {} Stores BGZ File Names More Than Once, Their Ranges Would Be Read From One File: {}",
+            index_file,
+            collisions.into_iter().collect::<Vec<_>>().join(", ")
+        ),
+    ))
+}
+
+// Converts a structural count to its on-disk u32 field, erroring instead of truncating
+pub fn index_count_field(count: usize, field: &str) -> io::Result<u32> {
+    u32::try_from(count).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} Count {} Exceeds The Index Format Limit Of {}",
+                field, count, MAX_INDEX_COUNT
+            ),
+        )
+    })
+}
+
+// File positions of each tile's range data in a format version 0 index,
+// range_data_offsets[contig][tile]. The counts are u32 on disk but the offsets are u64, the
+// range data may extend well past 4 GB
+pub fn range_data_offsets(
+    file_count: u32,
+    tile_counts: &[u32],
+    range_counts: &[Vec<u32>],
+) -> Vec<Vec<u64>> {
+    // In format version 0 the range data starts at: 20+(files*56)+(Contigs*44)+(Tiles*4)
+    let mut tile_start: u64 = 20 + (file_count as u64 * 56) + (tile_counts.len() as u64 * 44);
+    for c in tile_counts {
+        tile_start += *c as u64 * 4;
+    }
+    tile_offsets_from(tile_start, range_counts)
+}
+
+// range_data_offsets of range data starting at tile_start
+fn tile_offsets_from(mut tile_start: u64, range_counts: &[Vec<u32>]) -> Vec<Vec<u64>> {
+    let mut offsets = Vec::with_capacity(range_counts.len());
+    for r_counts in range_counts {
+        let mut tile_offsets = Vec::with_capacity(r_counts.len());
+        for r_count in r_counts {
+            tile_offsets.push(tile_start);
+            tile_start += *r_count as u64 * 28;
+        }
+        offsets.push(tile_offsets);
+    }
+    offsets
+}
+
+// Filesystem metadata calls made for a search, listing the bgz files and checking them against
+// the index, and the time they took. The listing counts a call per file found.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MetadataStats {
+    pub calls: u64,
+    pub time: Duration,
+    pub open_retries: u64, // Retried opens of the index, see open_index
+}
+
+// How many tiles the ranges of an index being built span. Each range is stored in every tile
+// it overlaps, unless it is moved to its contig's overflow list, where it is stored once.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildStats {
+    pub ranges: u64,
+    pub stored_ranges: u64, // Ranges written, a range once per tile it is stored in
+    pub overflow_ranges: u64,
+    pub max_span_tiles: u64,
+    pub total_span_tiles: u64,
+}
+
+impl BuildStats {
+    pub fn mean_span_tiles(&self) -> f64 {
+        match self.ranges {
+            0 => 0.0,
+            ranges => self.total_span_tiles as f64 / ranges as f64,
+        }
+    }
+
+    // Ranges written per range indexed, 1 when no range is stored more than once
+    pub fn duplication_factor(&self) -> f64 {
+        match self.ranges {
+            0 => 1.0,
+            ranges => self.stored_ranges as f64 / ranges as f64,
+        }
+    }
+
+    // Smallest power of two multiple of tile_size expected to bring the duplication factor
+    // down to max_duplication. The copies past the first of each range shrink in proportion
+    // to the tile size.
+    pub fn suggested_tile_size(&self, tile_size: u32, max_duplication: f64) -> u32 {
+        let needed = (self.duplication_factor() - 1.0) / (max_duplication - 1.0).max(f64::EPSILON);
+        let mut suggested = tile_size as u64;
+        while (suggested as f64) < tile_size as f64 * needed && suggested < u32::MAX as u64 / 2 {
+            suggested *= 2;
+        }
+        suggested as u32
+    }
+}
 
 #[derive(Debug)]
 pub struct ContigIndex {
@@ -43,22 +375,137 @@ pub struct ContigIndex {
     contig_count: u32,
     tile_counts: Vec<u32>,
     range_counts: Vec<Vec<u32>>,
-    range_data_index: Vec<Vec<u32>>,
+    range_data_index: Vec<Vec<u64>>,
     contigs: Vec<Contig>,
     contig_lookup: HashMap<String, u32>,
+    bulk_read_limit: usize,
+    chunk_size: u64,
+    // Bias threshold of searches, see set_max_bias
+    max_bias: Option<f64>,
+    strict_bias: bool,
+    // Family versions compared by searches, see acc_matches
+    exact_version: bool,
+    tile_reads: AtomicUsize,
+    file_warnings: Vec<String>,
+    // Indexed files found deleted by check_bgz_files, their ranges are skipped by searches
+    missing_files: HashSet<u32>,
+    // Matching ranges skipped per missing file by the last search_idx, by file name
+    skipped_ranges: Vec<(String, usize)>,
+    // Set for queries, saving the index is then an error instead of a write
+    read_only: bool,
+    // Ranges spanning more tiles than this go to their contig's overflow list when building
+    overflow_tiles: Option<usize>,
+    max_duplication: f64,
+    build_stats: BuildStats,
+    // Files added to an index being built without a record, indexed with no ranges
+    empty_files: Vec<String>,
+    // Re-read every record at its position after indexing its file, see set_verify_positions
+    verify_positions: bool,
+    verified_records: u64,
+    // Searches skip checking the bgz files against the index, see prep_search
+    assume_immutable: bool,
+    metadata_stats: MetadataStats,
+    // overflow_ranges[contig] of an index being searched, read with the header, sorted by start
+    overflow_ranges: Vec<Vec<ContigRange>>,
+    build_progress: Option<BuildProgress>,
+    // Content hashes in place of modification times, see HASHED_FORMAT_VERSION
+    deterministic: bool,
+    // Modification time of an index being searched, files older cannot have changed since
+    index_mod_time: Option<SystemTime>,
+    open_retry: OpenRetry,
+}
+
+// Called by build_idx with the number of files indexed so far, after each file
+#[derive(Clone)]
+pub struct BuildProgress(pub Arc<dyn Fn(u64) + Send + Sync>);
+
+impl fmt::Debug for BuildProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BuildProgress")
+    }
+}
+
+// Most range data a search reads at once, consecutive tiles are read together up to this
+pub const DEFAULT_BULK_READ_LIMIT: usize = 64 * 1024 * 1024;
+
+// Longest span searched at once, longer queries are searched in consecutive chunks of it so
+// whole chromosome queries read one chunk of tiles and records at a time
+pub const DEFAULT_CHUNK_SIZE: u64 = 10_000_000;
+
+// Size of a ContigRange in the index file
+const RANGE_RECORD_BYTES: usize = 28;
+
+// Range data read for consecutive tiles of a contig, see read_tiles
+struct TileWindow {
+    first_tile: usize,
+    // Start of each tile's data in the buffer, followed by the end of the last one
+    tile_offsets: Vec<usize>,
+    buffer: Vec<u8>,
+}
+
+impl TileWindow {
+    fn contains(&self, tile: usize) -> bool {
+        tile >= self.first_tile && tile < self.first_tile + self.tile_offsets.len() - 1
+    }
+
+    fn tile(&self, tile: usize) -> ContigTile {
+        let i = tile - self.first_tile;
+        decode_tile(&self.buffer[self.tile_offsets[i]..self.tile_offsets[i + 1]])
+    }
+}
+
+// Decodes ContigRanges laid out as in the index file
+fn decode_tile(buffer: &[u8]) -> ContigTile {
+    let u64_at = |bytes: &[u8], start: usize| {
+        u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap())
+    };
+    ContigTile {
+        contig_ranges: buffer
+            .chunks_exact(RANGE_RECORD_BYTES)
+            .map(|record| ContigRange {
+                bed_idx: u32::from_le_bytes(record[0..4].try_into().unwrap()),
+                start_bp: u64_at(record, 4),
+                end_bp: u64_at(record, 12),
+                bgzf_pos: u64_at(record, 20),
+            })
+            .collect(),
+    }
 }
 
 #[derive(Debug)]
 struct BGZFile {
     name: String,
-    mod_time: f64,
+    mod_time: f64,             // 0 in a deterministic index
+    content_hash: Option<u64>, // Only in a deterministic index, see content_hash
     bytes: u64,
 }
 
+// Only the tiles holding ranges are kept while building, a sparse contig of a large
+// assembly would otherwise hold hundreds of thousands of empty tiles. The tiles in between
+// are written out with a count of zero.
 #[derive(Debug)]
 struct Contig {
     name: String,
-    contig_tiles: Vec<ContigTile>,
+    tile_count: usize, // Tiles up to and including the last one a range overlaps
+    contig_tiles: BTreeMap<usize, ContigTile>,
+    overflow_ranges: Vec<ContigRange>,
+}
+
+impl Contig {
+    fn new(name: &str) -> Contig {
+        Contig {
+            name: name.to_string(),
+            tile_count: 0,
+            contig_tiles: BTreeMap::new(),
+            overflow_ranges: Vec::new(),
+        }
+    }
+
+    fn tile_ranges(&self, tile: usize) -> &[ContigRange] {
+        self.contig_tiles
+            .get(&tile)
+            .map_or(&[], |tile| &tile.contig_ranges)
+    }
 }
 
 #[derive(Debug)]
@@ -78,11 +525,29 @@ impl ContigTile {
 #[repr(C)]
 pub struct ContigRange {
     bed_idx: u32,  // Identifies the source BED file for this entry
-    start_bp: u64, // region start bp (zero-based)
-    end_bp: u64,   // region end bp (zero-based, half-open)
+    start_bp: u64, // region start bp (zero-based), the record's chromStart
+    end_bp: u64,   // region end bp (zero-based, half-open), the record's chromEnd
     bgzf_pos: u64, // Byte position within bgz compressed BED file
 }
 
+impl ContigRange {
+    // Ranges are stored with start_bp <= end_bp, but indexes built before minus strand
+    // records were ordered hold them as in the BED file, start after end
+    fn low(&self) -> u64 {
+        self.start_bp.min(self.end_bp)
+    }
+
+    fn high(&self) -> u64 {
+        self.start_bp.max(self.end_bp)
+    }
+
+    // Whether the range can hold a record overlapping q_start..q_end, zero-based and
+    // half-open as the range is, checked before the record is read
+    fn overlaps(&self, q_start: u64, q_end: u64) -> bool {
+        self.low() < q_end && self.high() > q_start
+    }
+}
+
 fn read_u16_from_file(file: &mut File) -> io::Result<u16> {
     let mut buffer = [0; 2];
     file.read_exact(&mut buffer)?;
@@ -95,6 +560,35 @@ fn read_u32_from_file(file: &mut File) -> io::Result<u32> {
     Ok(u32::from_le_bytes(buffer))
 }
 
+// A contig or bgz file name as stored by format version f_ver
+fn read_index_name(file: &mut File, f_ver: u16) -> io::Result<String> {
+    let length = match f_ver {
+        0 => V0_NAME_BYTES,
+        _ => read_u16_from_file(file)? as usize,
+    };
+    let mut buffer = vec![0; length];
+    file.read_exact(&mut buffer)?;
+    // Version 0 names shorter than the field are null padded
+    if let Some(index) = buffer.iter().position(|&x| x == b'\0') {
+        buffer.truncate(index);
+    }
+    Ok(String::from_utf8_lossy(&buffer).to_string())
+}
+
+fn write_index_name(file: &mut impl Write, name: &str) -> io::Result<()> {
+    let length = u16::try_from(name.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Name {} Is Longer Than The Index Format Limit Of {} Bytes",
+                name, MAX_FILE_NAME_BYTES
+            ),
+        )
+    })?;
+    file.write_all(&length.to_le_bytes())?;
+    file.write_all(name.as_bytes())
+}
+
 // fn read_u64_from_file(file: &mut File) -> io::Result<u64> {
 //     let mut buffer = [0; 8];
 //     file.read_exact(&mut buffer)?;
@@ -107,16 +601,230 @@ fn read_u32_from_file(file: &mut File) -> io::Result<u32> {
 //     Ok(String::from_utf8_lossy(&buffer).to_string())
 // }
 
+// A search stopped by its timeout or its cancellation token, with the hits found so far
+#[derive(Debug)]
+pub struct SearchTimeout {
+    pub partial_results: Vec<String>,
+    pub elapsed: Duration,
+    pub cancelled: bool,
+}
+
+impl fmt::Display for SearchTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Search {} After {:.3}s With {} Partial Results",
+            if self.cancelled {
+                "Cancelled"
+            } else {
+                "Timed Out"
+            },
+            self.elapsed.as_secs_f64(),
+            self.partial_results.len()
+        )
+    }
+}
+
+impl Error for SearchTimeout {}
+
+// A bgz file named by the index is gone, in a search that asked to fail instead of skipping
+#[derive(Debug)]
+pub struct MissingBgzFile {
+    pub name: String,
+}
+
+impl fmt::Display for MissingBgzFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BGZ File {} Is Missing Since The Index Was Created, Rebuild The Index",
+            self.name
+        )
+    }
+}
+
+impl Error for MissingBgzFile {}
+
+// A family accession without its .<version>
+pub fn unversioned_acc(acc: &str) -> &str {
+    acc.split('.').next().unwrap()
+}
+
+// Whether a family accession of a record or file name, candidate, is one a query asks for. Every
+// family comparison goes through here, so versioned and unversioned accessions match the same
+// way in every command. Versions are ignored by default. With exact_version a query with a
+// version only matches that version, while one without still matches every version.
+pub fn acc_matches(query: &str, candidate: &str, exact_version: bool) -> bool {
+    let (query, candidate) = (query.trim(), candidate.trim());
+    if exact_version && query.contains('.') {
+        return query == candidate;
+    }
+    unversioned_acc(query) == unversioned_acc(candidate)
+}
+
+// The families of a query as records are compared with them, see acc_matches, sorted and
+// deduplicated. None, or no families at all, keeps every family.
+pub fn query_families(q_family: &Option<Vec<String>>) -> Option<Vec<String>> {
+    let mut families: Vec<String> = q_family
+        .iter()
+        .flatten()
+        .map(|family| family.trim().to_string())
+        .filter(|family| !family.is_empty())
+        .collect();
+    families.sort();
+    families.dedup();
+    Some(families).filter(|families| !families.is_empty())
+}
+
+// Whether a bgz file can hold records of the query families, from its name. Only the files of
+// the families have to be read, a short-circuit before any record is.
+fn family_file(name: &str, q_family: &Option<Vec<String>>, exact_version: bool) -> bool {
+    match q_family {
+        Some(families) => families
+            .iter()
+            .any(|family| acc_matches(family, stored_accession(name), exact_version)),
+        None => true,
+    }
+}
+
+// Whether a range is read for the first time in a chunked search, see search_chunks. The
+// range is remembered with its end, until no later chunk can reach it.
+fn first_sight(seen: &mut HashMap<(u32, u64), u64>, range: &ContigRange) -> bool {
+    seen.insert((range.bed_idx, range.bgzf_pos), range.high())
+        .is_none()
+}
+
+// Score thresholds of a search. The bias threshold is set on the index, see set_max_bias.
+#[derive(Default)]
+struct ScoreThresholds {
+    min_bit_score: Option<f64>,
+    max_e_value: Option<f64>,
+    max_bias: Option<f64>,
+    strict_bias: bool,
+}
+
+// q_family holds query_families, compared as acc_matches does. Records whose score can't be
+// parsed fail the threshold and are counted in unparsable, except for the bias, which only
+// fails them when strict. Records without a BED range, see bed_range, are dropped and counted
+// the same way rather than failing the query.
+#[allow(clippy::too_many_arguments)]
+fn filter_line(
+    line: &str,
+    q_start: &u64,
+    q_family: &Option<Vec<String>>,
+    q_exact_version: bool,
+    q_nrph: &bool,
+    q_strand: &Option<String>,
+    q_scores: &ScoreThresholds,
+    unparsable: &mut usize,
+) -> bool {
+    let fields = bed_fields(line);
+    // q_start is zero-based, a record ending at it ends on the base before
+    let Some((_, end)) = bed_range(&fields) else {
+        *unparsable += 1;
+        return false;
+    };
+    if end <= *q_start {
+        return false;
+    }
+    if let Some(families) = q_family {
+        let acc = fields[FAMILY_COLUMN];
+        if !families
+            .iter()
+            .any(|family| acc_matches(family, acc, q_exact_version))
+        {
+            return false;
+        };
+    }
+    if q_strand.is_some() && fields.get(STRAND_COLUMN).copied() != q_strand.as_deref() {
+        return false;
+    }
+    let score = |column: usize| {
+        fields
+            .get(column)
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| !v.is_nan())
+    };
+    if let Some(min_bit_score) = q_scores.min_bit_score {
+        match score(BIT_SCORE_COLUMN) {
+            Some(bit_score) if bit_score >= min_bit_score => {}
+            Some(_) => return false,
+            None => {
+                *unparsable += 1;
+                return false;
+            }
+        }
+    }
+    if let Some(max_e_value) = q_scores.max_e_value {
+        match score(E_VALUE_COLUMN) {
+            Some(e_value) if e_value <= max_e_value => {}
+            Some(_) => return false,
+            None => {
+                *unparsable += 1;
+                return false;
+            }
+        }
+    }
+    if let Some(max_bias) = q_scores.max_bias {
+        match bias_within(&fields, max_bias) {
+            Some(true) => {}
+            Some(false) => return false,
+            None if q_scores.strict_bias => {
+                *unparsable += 1;
+                return false;
+            }
+            None => {}
+        }
+    }
+    if *q_nrph {
+        return fields.get(NRPH_COLUMN) == Some(&"1");
+    }
+    true
+}
+
 impl ContigIndex {
+    fn new(read_only: bool) -> ContigIndex {
+        ContigIndex {
+            tile_size: TILE_SIZE,
+            bgz_files: Vec::new(),
+            contig_count: 0,
+            contig_lookup: HashMap::new(),
+            tile_counts: Vec::new(),
+            range_counts: Vec::new(),
+            range_data_index: Vec::new(),
+            contigs: Vec::new(),
+            bulk_read_limit: DEFAULT_BULK_READ_LIMIT,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_bias: None,
+            strict_bias: false,
+            exact_version: false,
+            tile_reads: AtomicUsize::new(0),
+            file_warnings: Vec::new(),
+            missing_files: HashSet::new(),
+            skipped_ranges: Vec::new(),
+            read_only,
+            overflow_tiles: None,
+            max_duplication: DEFAULT_MAX_DUPLICATION,
+            build_stats: BuildStats::default(),
+            empty_files: Vec::new(),
+            verify_positions: false,
+            verified_records: 0,
+            assume_immutable: false,
+            metadata_stats: MetadataStats::default(),
+            overflow_ranges: Vec::new(),
+            build_progress: None,
+            deterministic: false,
+            index_mod_time: None,
+            open_retry: configured_open_retry(),
+        }
+    }
+
     fn get_or_insert_contig(&mut self, contig_name: &str) -> &mut Contig {
         // Check if contig name is already defined in the lookup table
         if !self.contig_lookup.contains_key(contig_name) {
             // Missing, add the new contig
             let contig_idx = self.contigs.len() as u32;
-            self.contigs.push(Contig {
-                name: contig_name.to_string(),
-                contig_tiles: Vec::new(),
-            });
+            self.contigs.push(Contig::new(contig_name));
             self.contig_lookup
                 .insert(contig_name.to_string(), contig_idx);
             return &mut self.contigs[contig_idx as usize];
@@ -138,279 +846,529 @@ impl ContigIndex {
     ) {
         let first_tile_idx = (start_bp / u64::from(self.tile_size)) as usize;
         let last_tile_idx = ((end_bp - 1) / u64::from(self.tile_size)) as usize;
+        let span_tiles = last_tile_idx - first_tile_idx + 1;
+        let overflow = self.overflow_tiles.is_some_and(|tiles| span_tiles > tiles);
+        let stats = &mut self.build_stats;
+        stats.ranges += 1;
+        stats.max_span_tiles = stats.max_span_tiles.max(span_tiles as u64);
+        stats.total_span_tiles += span_tiles as u64;
+        if overflow {
+            stats.overflow_ranges += 1;
+            stats.stored_ranges += 1;
+        } else {
+            stats.stored_ranges += span_tiles as u64;
+        }
         let contig = self.get_or_insert_contig(contig_name);
-        // Initialize any missing tiles up and including last_tile_idx if not already present.
-        for _ in 0..((last_tile_idx + 1).saturating_sub(contig.contig_tiles.len())) {
-            contig.contig_tiles.push(ContigTile::new());
+        contig.tile_count = contig.tile_count.max(last_tile_idx + 1);
+        if overflow {
+            contig.overflow_ranges.push(ContigRange {
+                bed_idx,
+                start_bp,
+                end_bp,
+                bgzf_pos,
+            });
+            return;
         }
         for tile_idx in first_tile_idx..(last_tile_idx + 1) {
-            if let Some(tile) = contig.contig_tiles.get_mut(tile_idx) {
-                let new_contig_range = ContigRange {
-                    bed_idx,
-                    start_bp,
-                    end_bp,
-                    bgzf_pos,
-                };
-                tile.contig_ranges.push(new_contig_range);
-            }
+            let new_contig_range = ContigRange {
+                bed_idx,
+                start_bp,
+                end_bp,
+                bgzf_pos,
+            };
+            contig
+                .contig_tiles
+                .entry(tile_idx)
+                .or_insert_with(ContigTile::new)
+                .contig_ranges
+                .push(new_contig_range);
         }
     }
 
-    fn init_search(&mut self, file_path: &str) {
-        let mut file = match File::open(file_path) {
-            Ok(file) => file,
-            Err(e) => panic!("Error Opening File - {:?}", e),
-        };
+    // Ranges spanning more than tiles tiles are stored once, in a per contig overflow list
+    // every search of the contig reads, instead of in each tile. Indexes holding any are
+    // written in OVERFLOW_FORMAT_VERSION.
+    pub fn set_overflow_tiles(&mut self, tiles: usize) {
+        self.overflow_tiles = Some(tiles);
+    }
+
+    // Duplication factor above which building warns, DEFAULT_MAX_DUPLICATION by default
+    pub fn set_max_duplication(&mut self, max_duplication: f64) {
+        self.max_duplication = max_duplication;
+    }
+
+    // Tile spans of the ranges added to an index being built
+    pub fn build_stats(&self) -> &BuildStats {
+        &self.build_stats
+    }
+
+    // Files without records among those added to an index being built, such as families left
+    // without hits by filtered or appended data
+    pub fn empty_files(&self) -> &Vec<String> {
+        &self.empty_files
+    }
+
+    // Warning for an index whose ranges are stored more than max_duplication times over on
+    // average, with a tile size that would keep them under it
+    pub fn duplication_warning(&self) -> Option<String> {
+        let duplication = self.build_stats.duplication_factor();
+        if duplication <= self.max_duplication {
+            return None;
+        }
+        Some(format!(
+            "Ranges Are Stored {:.1} Times Over On Average (Maximum Span {} Tiles), Consider A \
+             Tile Size Of {} Instead Of {} Or An Overflow List For Long Ranges",
+            duplication,
+            self.build_stats.max_span_tiles,
+            self.build_stats
+                .suggested_tile_size(self.tile_size, self.max_duplication),
+            self.tile_size
+        ))
+    }
+
+    // Seek to every record of a file once it is indexed and check the line read there is the
+    // one indexed, failing the build on the first that is not
+    pub fn set_verify_positions(&mut self, verify_positions: bool) {
+        self.verify_positions = verify_positions;
+    }
+
+    // Metadata calls made for searches of the index so far
+    pub fn metadata_stats(&self) -> MetadataStats {
+        self.metadata_stats
+    }
+
+    // Records read back at their positions by a build with set_verify_positions
+    pub fn verified_records(&self) -> u64 {
+        self.verified_records
+    }
+
+    // Reports the files indexed as an index is built, for progress displays
+    pub fn set_build_progress(&mut self, progress: BuildProgress) {
+        self.build_progress = Some(progress);
+    }
+
+    // Builds an index that is the same bytes whenever it is built over the same files, their
+    // content hashes stored in place of their modification times
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    // Tile size of an index about to be built, TILE_SIZE by default
+    pub fn set_tile_size(&mut self, tile_size: u32) {
+        self.tile_size = tile_size;
+    }
+
+    // Tiles holding ranges, and all tiles up to the last holding a range, of every contig of
+    // an index being built
+    pub fn built_tiles(&self) -> (usize, usize) {
+        self.contigs.iter().fold((0, 0), |(held, total), contig| {
+            (held + contig.contig_tiles.len(), total + contig.tile_count)
+        })
+    }
+
+    // Opens an index to search and reads its header. The file is searched through the same
+    // handle, so a swap of the index after the open cannot mix two versions. The retries the
+    // open took are counted in the metadata stats and warned about with the file warnings.
+    fn init_search(&mut self, file_path: &str) -> Result<File, Box<dyn Error>> {
+        let (mut file, retries) =
+            open_index(file_path, &self.open_retry).map_err(|e| -> Box<dyn Error> {
+                match e.get_ref().is_some_and(|inner| inner.is::<NotIndexed>()) {
+                    true => e.into_inner().unwrap(),
+                    false => Box::new(e),
+                }
+            })?;
+        self.metadata_stats.open_retries += retries as u64;
+        self.read_header(&mut file)?;
+        self.check_file_names(file_path)?;
+        Ok(file)
+    }
+
+    fn check_file_names(&self, index_file: &str) -> io::Result<()> {
+        check_unique_file_names(self.bgz_files.iter().map(|f| f.name.as_str()), index_file)
+    }
 
+    // Reads everything but the range data, which is read per tile while searching. Indexes
+    // of a newer format version than this build writes are refused.
+    fn read_header(&mut self, file: &mut File) -> io::Result<()> {
         // Read the magic number (6-bytes)
         let mut buffer = [0; 6];
-        file.read_exact(&mut buffer).unwrap();
+        file.read_exact(&mut buffer)?;
         if buffer != MAGIC_NUMBER {
             warn!("Magic number did not match.");
         }
 
         // Read the file format version (2-bytes, little-endian)
-        let f_ver = read_u16_from_file(&mut file).unwrap();
-        if f_ver != FORMAT_VERSION {
-            warn!(
-                "Incompatible file version {}, expected {}",
-                f_ver, FORMAT_VERSION
-            );
+        let f_ver = read_u16_from_file(file)?;
+        if f_ver > MAX_FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Index Format Version {} Is Newer Than The Supported {}, Upgrade te_idx",
+                    f_ver, MAX_FORMAT_VERSION
+                ),
+            ));
         }
+        self.deterministic = f_ver >= HASHED_FORMAT_VERSION;
+        self.index_mod_time = file.metadata().and_then(|m| m.modified()).ok();
 
         // Read the tile_size used in this index (u32, little-endian)
-        self.tile_size = read_u32_from_file(&mut file).unwrap();
+        self.tile_size = read_u32_from_file(file)?;
         info!("Round trip tile size = {}", self.tile_size);
 
         // Read the contig count in this index (u32, little-endian)
-        self.contig_count = read_u32_from_file(&mut file).unwrap();
+        self.contig_count = read_u32_from_file(file)?;
         info!("Contig count = {}", self.contig_count);
 
         // Read the file count in this index (u32, little-endian)
-        let file_count = read_u32_from_file(&mut file).unwrap();
+        let file_count = read_u32_from_file(file)?;
         info!("File count = {}", file_count);
 
         // Read the tile counts (tile_counts[contig], u32, little-endian)
         self.tile_counts = vec![0; self.contig_count as usize];
-        file.read_u32_into::<LittleEndian>(&mut self.tile_counts)
-            .unwrap();
-
-        // The range data starts at: 20+(files*56)+(Contigs*44)+(Tiles*4)
-        let mut range_data_start = 20 + (file_count * 56) + (self.contig_count * 44);
-        for c in &self.tile_counts {
-            range_data_start += c * 4;
-        }
+        file.read_u32_into::<LittleEndian>(&mut self.tile_counts)?;
 
         // Read the range counts (range_counts[contig][tile], u32, little-endian)
-        let mut tile_start = range_data_start;
         for contig_idx in 0..self.contig_count {
-            // build range_counts
             let mut r_counts = vec![0; self.tile_counts[contig_idx as usize] as usize];
-            file.read_u32_into::<LittleEndian>(&mut r_counts).unwrap();
+            file.read_u32_into::<LittleEndian>(&mut r_counts)?;
             self.range_counts.push(r_counts);
-
-            // build range_data_index[contig][tile] = file_byte_position
-            self.range_data_index.push(Vec::new());
-            for tile_idx in 0..self.tile_counts[contig_idx as usize] {
-                self.range_data_index[contig_idx as usize].push(tile_start);
-                tile_start += self.range_counts[contig_idx as usize][tile_idx as usize] * 28;
-            }
         }
 
-        // Read the contig name strings into a buffer
-        let mut buffer = vec![0; (40 * self.contig_count) as usize];
-        file.read_exact(&mut buffer).unwrap();
-
         // TODO: Also make a hash from this for reverse lookups
-        // Process the buffer as 40-character strings
-        let mut offset = 0;
         for n in 0..self.contig_count {
-            let string_slice = &buffer[offset..offset + 40];
-            // Find the index of the null byte
-            let null_byte_index = string_slice.iter().position(|&x| x == b'\0');
-            // Create a new string up to the null byte index
-            let string = match null_byte_index {
-                Some(index) => String::from_utf8_lossy(&string_slice[..index]).to_string(),
-                None => String::from_utf8_lossy(string_slice).to_string(),
-            };
-
-            self.contig_lookup.insert(string.clone(), n as u32);
-            offset += 40;
+            let name = read_index_name(file, f_ver)?;
+            self.contig_lookup.insert(name, n);
         }
 
         // Finally read in the filenames and stats
-        let mut buffer = vec![0; (56 * file_count) as usize];
-        file.read_exact(&mut buffer).unwrap();
-        for i in 0..file_count {
-            let mut start = (i * 56) as usize;
-
-            let string_slice = &buffer[start..start + 40];
-            // Find the index of the null byte
-            let null_byte_index = string_slice.iter().position(|&x| x == b'\0');
-            // Create a new string up to the null byte index
-            let string = match null_byte_index {
-                Some(index) => String::from_utf8_lossy(&string_slice[..index]).to_string(),
-                None => String::from_utf8_lossy(string_slice).to_string(),
-            };
+        for _ in 0..file_count {
+            let name = read_index_name(file, f_ver)?;
+            let mut stats = [0; 16];
+            file.read_exact(&mut stats)?;
+            let stamp: [u8; 8] = stats[0..8].try_into().unwrap();
+            self.bgz_files.push(BGZFile {
+                name,
+                mod_time: match self.deterministic {
+                    true => 0.0,
+                    false => f64::from_le_bytes(stamp),
+                },
+                content_hash: self.deterministic.then(|| u64::from_le_bytes(stamp)),
+                bytes: u64::from_le_bytes(stats[8..16].try_into().unwrap()),
+            });
+        }
 
-            start += 40;
-
-            let value1 = f64::from_le_bytes([
-                buffer[start],
-                buffer[start + 1],
-                buffer[start + 2],
-                buffer[start + 3],
-                buffer[start + 4],
-                buffer[start + 5],
-                buffer[start + 6],
-                buffer[start + 7],
-            ]);
-
-            let value2 = u64::from_le_bytes([
-                buffer[start + 8],
-                buffer[start + 9],
-                buffer[start + 10],
-                buffer[start + 11],
-                buffer[start + 12],
-                buffer[start + 13],
-                buffer[start + 14],
-                buffer[start + 15],
-            ]);
-
-            let my_struct = BGZFile {
-                name: string,
-                mod_time: value1,
-                bytes: value2,
-            };
-            self.bgz_files.push(my_struct);
+        // The overflow lists are read whole, every search of a contig needs its list
+        self.overflow_ranges = vec![Vec::new(); self.contig_count as usize];
+        if f_ver >= OVERFLOW_FORMAT_VERSION {
+            let mut o_counts = vec![0; self.contig_count as usize];
+            file.read_u32_into::<LittleEndian>(&mut o_counts)?;
+            for (contig_idx, o_count) in o_counts.iter().enumerate() {
+                let mut buffer = vec![0; *o_count as usize * RANGE_RECORD_BYTES];
+                file.read_exact(&mut buffer)?;
+                self.overflow_ranges[contig_idx] = decode_tile(&buffer).contig_ranges;
+            }
         }
-        // NEW: BGZFiles starts at: 20+(Contigs*44)+(Tiles*4)
-        // NEW: ContigRanges starts at: 20+(Files*56)+(Contigs*44)+(Tiles*4)
+
+        // build range_data_index[contig][tile] = file_byte_position, the range data follows
+        // the names, which are of fixed width in version 0, and the overflow lists
+        self.range_data_index = match f_ver {
+            0 => range_data_offsets(file_count, &self.tile_counts, &self.range_counts),
+            _ => tile_offsets_from(file.stream_position()?, &self.range_counts),
+        };
+        Ok(())
     }
 
-    fn read_tile(
-        &mut self,
+    // Reads the range data of first_tile and as many of the following tiles, up to
+    // last_tile, as fit under bulk_read_limit. The tiles of a contig are stored back to back
+    // so this is a single read, the first tile is read whatever its size.
+    fn read_tiles(
+        &self,
         file: &mut File,
         contig: u32,
-        tile: usize,
-    ) -> Result<ContigTile, &'static str> {
-        let range_count = self.range_counts[contig as usize][tile as usize];
-        let mut buffer = vec![0; (28 * range_count) as usize];
-        let byte_pos = self.range_data_index[contig as usize][tile as usize];
+        first_tile: usize,
+        last_tile: usize,
+    ) -> io::Result<TileWindow> {
+        let range_counts = &self.range_counts[contig as usize];
+        let mut tile_offsets = vec![0];
+        let mut bytes = 0;
+        for (i, count) in range_counts[first_tile..=last_tile].iter().enumerate() {
+            let tile_bytes = *count as usize * RANGE_RECORD_BYTES;
+            if i > 0 && bytes + tile_bytes > self.bulk_read_limit {
+                break;
+            }
+            bytes += tile_bytes;
+            tile_offsets.push(bytes);
+        }
+        let byte_pos = self.range_data_index[contig as usize][first_tile];
         info!(
-            "read_tile: expecting {} ranges, byte_pos {}",
-            range_count, byte_pos
+            "read_tiles: tiles {} to {}, {} bytes at byte_pos {}",
+            first_tile,
+            first_tile + tile_offsets.len() - 2,
+            bytes,
+            byte_pos
         );
-        let _ = file.seek(SeekFrom::Start(byte_pos as u64));
-        file.read_exact(&mut buffer).unwrap();
-
-        let mut c_tile = ContigTile {
-            contig_ranges: Vec::with_capacity(range_count as usize),
-        };
+        let mut buffer = vec![0; bytes];
+        file.seek(SeekFrom::Start(byte_pos))?;
+        file.read_exact(&mut buffer)?;
+        self.tile_reads.fetch_add(1, Ordering::Relaxed);
+        Ok(TileWindow {
+            first_tile,
+            tile_offsets,
+            buffer,
+        })
+    }
 
-        for i in 0..range_count {
-            let start = (i * 28) as usize;
-
-            let value0 = u32::from_le_bytes([
-                buffer[start],
-                buffer[start + 1],
-                buffer[start + 2],
-                buffer[start + 3],
-            ]);
-
-            let value1 = u64::from_le_bytes([
-                buffer[start + 4],
-                buffer[start + 5],
-                buffer[start + 6],
-                buffer[start + 7],
-                buffer[start + 8],
-                buffer[start + 9],
-                buffer[start + 10],
-                buffer[start + 11],
-            ]);
-
-            let value2 = u64::from_le_bytes([
-                buffer[start + 12],
-                buffer[start + 13],
-                buffer[start + 14],
-                buffer[start + 15],
-                buffer[start + 16],
-                buffer[start + 17],
-                buffer[start + 18],
-                buffer[start + 19],
-            ]);
-
-            let value3 = u64::from_le_bytes([
-                buffer[start + 20],
-                buffer[start + 21],
-                buffer[start + 22],
-                buffer[start + 23],
-                buffer[start + 24],
-                buffer[start + 25],
-                buffer[start + 26],
-                buffer[start + 27],
-            ]);
-
-            let my_struct = ContigRange {
-                bed_idx: value0,
-                start_bp: value1,
-                end_bp: value2,
-                bgzf_pos: value3,
-            };
-            c_tile.contig_ranges.push(my_struct);
+    // The tile from the current window, reading a new window starting at it when needed
+    fn load_tile(
+        &self,
+        file: &mut File,
+        window: &mut Option<TileWindow>,
+        contig: u32,
+        tile: usize,
+        last_tile: usize,
+    ) -> io::Result<ContigTile> {
+        if !window.as_ref().is_some_and(|w| w.contains(tile)) {
+            *window = Some(self.read_tiles(file, contig, tile, last_tile)?);
         }
+        Ok(window.as_ref().unwrap().tile(tile))
+    }
 
-        Ok(c_tile)
+    // Number of range data reads made by searches of this index
+    pub fn tile_reads(&self) -> usize {
+        self.tile_reads.load(Ordering::Relaxed)
     }
 
-    // TODO: deprecate filenames and store in index
-    fn search(
-        &mut self,
+    // Caps the range data read at once, 0 reads every tile on its own
+    pub fn set_bulk_read_limit(&mut self, bytes: usize) {
+        self.bulk_read_limit = bytes;
+    }
+
+    // Span searched at once by search_idx, longer queries are searched in chunks of it, 0
+    // searches any span at once
+    pub fn set_chunk_size(&mut self, chunk_size: u64) {
+        self.chunk_size = chunk_size;
+    }
+
+    // Retries of the index open by search_idx, configured_open_retry by default
+    pub fn set_open_retry(&mut self, open_retry: OpenRetry) {
+        self.open_retry = open_retry;
+    }
+
+    // Leaves out records with a bias above max_bias from searches. Records without a numeric
+    // bias are kept, unless strict.
+    pub fn set_max_bias(&mut self, max_bias: Option<f64>, strict: bool) {
+        self.max_bias = max_bias;
+        self.strict_bias = strict;
+    }
+
+    // Matches the query families of searches to the version when they give one, rather than
+    // ignoring versions, see acc_matches
+    pub fn set_exact_version(&mut self, exact_version: bool) {
+        self.exact_version = exact_version;
+    }
+
+    // Searches q_start..q_end in consecutive chunks of at most q_chunk_size bp, handing each
+    // chunk's records to emit as soon as it is searched, so only one chunk of records is held.
+    // Chunks overlap by 1 bp, and a record found by an earlier chunk, known by its bed_idx and
+    // bgzf_pos, is not read again, so the records are those of one search of the whole span,
+    // in RecordOrder. A timeout covers every chunk, the error holds the records of the chunk
+    // that was stopped.
+    #[allow(clippy::too_many_arguments)]
+    fn search_chunks(
+        &self,
         i_file: &mut File,
-        bgz_dir: &String,
-        q_contig: &String,
+        bgz_dir: &str,
+        q_contig: &str,
         q_start: u64,
         q_end: u64,
-        q_family: &Option<String>,
+        q_family: &Option<Vec<String>>,
         q_nrph: bool,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
-        fn filter_line(
-            line: &String,
-            q_start: &u64,
-            q_family: &Option<String>,
-            q_nrph: &bool,
-        ) -> bool {
-            let fields = line.split_whitespace().collect::<Vec<&str>>();
-            let end: u64 = fields[2].parse().unwrap();
-            if end - 1 < *q_start {
-                return false;
-            }
-            if q_family.is_some() {
-                let acc: &str = fields[3].split(".").collect::<Vec<&str>>()[0];
-                if q_family.as_ref().unwrap() != acc {
-                    return false;
-                };
+        q_strand: &Option<String>,
+        q_min_bit_score: Option<f64>,
+        q_max_e_value: Option<f64>,
+        q_timeout: Option<Duration>,
+        q_cancel: &Option<Arc<AtomicBool>>,
+        q_strict: bool,
+        q_skipped: &mut HashMap<u32, usize>,
+        q_chunk_size: u64,
+        emit: &mut dyn FnMut(Vec<String>),
+    ) -> Result<(), Box<dyn Error>> {
+        let chunk_size = match q_chunk_size {
+            0 => q_end - q_start,
+            chunk_size => chunk_size,
+        };
+        // Chunks past the contig's last tile hold no ranges, one search would stop there too
+        let indexed_end = self
+            .contig_lookup
+            .get(q_contig)
+            .map(|idx| u64::from(self.tile_counts[*idx as usize]) * u64::from(self.tile_size));
+        let started = Instant::now();
+        let mut seen: HashMap<(u32, u64), u64> = HashMap::new();
+        let mut chunk_start = q_start;
+        while chunk_start < q_end {
+            if chunk_start > q_start && indexed_end.is_some_and(|end| chunk_start >= end) {
+                break;
             }
-            if *q_nrph == true {
-                match fields.get(12) {
-                    Some(l) => match l {
-                        &"1" => return true,
-                        _ => return false,
-                    },
-                    None => return false,
+            let next_start = chunk_start.saturating_add(chunk_size).min(q_end);
+            let timeout = q_timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
+            let results = self.search_window(
+                i_file,
+                bgz_dir,
+                q_contig,
+                chunk_start,
+                (next_start + 1).min(q_end),
+                q_family,
+                q_nrph,
+                q_strand,
+                q_min_bit_score,
+                q_max_e_value,
+                timeout,
+                q_cancel,
+                q_strict,
+                q_skipped,
+                &mut seen,
+            );
+            match results {
+                Ok(results) => emit(results),
+                Err(e) => {
+                    return Err(match e.downcast::<SearchTimeout>() {
+                        Ok(stopped) => Box::new(SearchTimeout {
+                            elapsed: started.elapsed(),
+                            ..*stopped
+                        }),
+                        Err(e) => e,
+                    })
                 }
             }
-            return true;
+            // Only records reaching past the next chunk's start can be found by it again
+            seen.retain(|_, high| *high > next_start);
+            chunk_start = next_start;
         }
+        Ok(())
+    }
+
+    // The records of search_chunks together, a stopped search keeps those of earlier chunks
+    // in its partial results. The region is 1-based and fully closed, as queries give it, and
+    // searched as the zero-based, half-open q_start - 1..q_end of the ranges.
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        i_file: &mut File,
+        bgz_dir: &str,
+        q_contig: &str,
+        q_start: u64,
+        q_end: u64,
+        q_family: &Option<Vec<String>>,
+        q_nrph: bool,
+        q_strand: &Option<String>,
+        q_min_bit_score: Option<f64>,
+        q_max_e_value: Option<f64>,
+        q_timeout: Option<Duration>,
+        q_cancel: &Option<Arc<AtomicBool>>,
+        q_strict: bool,
+        q_skipped: &mut HashMap<u32, usize>,
+        q_chunk_size: u64,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut results = Vec::new();
+        let searched = self.search_chunks(
+            i_file,
+            bgz_dir,
+            q_contig,
+            q_start.saturating_sub(1),
+            q_end,
+            q_family,
+            q_nrph,
+            q_strand,
+            q_min_bit_score,
+            q_max_e_value,
+            q_timeout,
+            q_cancel,
+            q_strict,
+            q_skipped,
+            q_chunk_size,
+            &mut |chunk| results.extend(chunk),
+        );
+        match searched {
+            Ok(()) => Ok(results),
+            Err(e) => match e.downcast::<SearchTimeout>() {
+                Ok(mut stopped) => {
+                    results.append(&mut stopped.partial_results);
+                    stopped.partial_results = results;
+                    Err(stopped)
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    // TODO: deprecate filenames and store in index
+    #[allow(clippy::too_many_arguments)]
+    fn search_window(
+        &self,
+        i_file: &mut File,
+        bgz_dir: &str,
+        q_contig: &str,
+        q_start: u64,
+        q_end: u64,
+        q_family: &Option<Vec<String>>,
+        q_nrph: bool,
+        q_strand: &Option<String>,
+        q_min_bit_score: Option<f64>,
+        q_max_e_value: Option<f64>,
+        q_timeout: Option<Duration>,
+        q_cancel: &Option<Arc<AtomicBool>>,
+        q_strict: bool,
+        q_skipped: &mut HashMap<u32, usize>,
+        q_seen: &mut HashMap<(u32, u64), u64>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        // A strict search fails before reading anything when a file is known to be missing
+        if q_strict {
+            if let Some(bed_idx) = self.missing_files.iter().min() {
+                return Err(Box::new(MissingBgzFile {
+                    name: self.bgz_files[*bed_idx as usize].name.clone(),
+                }));
+            }
+        }
+
+        let q_family = &query_families(q_family);
+
+        // TODO: Return if cannot identify contig
+        let q_contig_idx: u32 = match self.contig_lookup.get(q_contig) {
+            Some(id) => *id,
+            None => panic!("{}", format!("Contig {q_contig} Not Found")),
+        };
+
+        let mut results: Vec<String> = Vec::new();
+        let q_scores = ScoreThresholds {
+            min_bit_score: q_min_bit_score,
+            max_e_value: q_max_e_value,
+            max_bias: self.max_bias,
+            strict_bias: self.strict_bias,
+        };
+        let mut unparsable: usize = 0;
+
+        // Checked between tiles and before every record fetch, so a dense query gives up
+        // within one record read of its timeout or of the token being set
+        let started = Instant::now();
+        let stopped = |results: &mut Vec<String>| -> Option<Box<dyn Error>> {
+            let cancelled = q_cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+            if cancelled || q_timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                warn!("Search of {}:{}-{} stopped early", q_contig, q_start, q_end);
+                return Some(Box::new(SearchTimeout {
+                    partial_results: std::mem::take(results),
+                    elapsed: started.elapsed(),
+                    cancelled,
+                }));
+            }
+            None
+        };
 
-        // TODO: Return if cannot identify contig
-        let q_contig_idx: u32 = match self.contig_lookup.get(q_contig) {
-            Some(id) => *id,
-            None => panic!("{}", format!("Contig {q_contig} Not Found")),
-        };
-
-        let mut results: Vec<String> = Vec::new();
-
         // Determine the start/end tiles this range could possibly overlap
         let start_tile = (q_start / self.tile_size as u64) as usize;
         let mut end_tile = ((q_end - 1) / self.tile_size as u64) as usize;
@@ -420,7 +1378,7 @@ impl ContigIndex {
         // null results.
         //   TODO: This should probably be an error as with having
         //         an end outside the index.
-        if start_tile > self.tile_counts[q_contig_idx as usize] as usize {
+        if start_tile >= self.tile_counts[q_contig_idx as usize] as usize {
             let error_str = "Start Position Outside Of Indexed Size";
             error!("{}", error_str);
             return Err(error_str.into());
@@ -438,16 +1396,13 @@ impl ContigIndex {
         );
         let mut hits = 0;
 
-        //  This could be adaptive based on how many tiles it needs to access
-        //     e.g if its less than 1000 read in a block and if it's greater
-        //         than read record by record as they did.
-
-        let mut range_count = self.range_counts[q_contig_idx as usize][start_tile as usize];
+        // Tiles are read in windows of consecutive tiles, see read_tiles
+        let mut window = None;
+        let mut range_count = self.range_counts[q_contig_idx as usize][start_tile];
         info!("search: range_count {}", range_count);
         if range_count > 0 {
             let range_data = self
-                .read_tile(i_file, q_contig_idx, start_tile)
-                .unwrap()
+                .load_tile(i_file, &mut window, q_contig_idx, start_tile, end_tile)?
                 .contig_ranges;
             if range_data[0].start_bp < q_end {
                 // TODO: This has to be the right-most variant as we only need to guarantee
@@ -470,118 +1425,302 @@ impl ContigIndex {
                         right = mid;
                     }
                 }
-                // Left is not inclusive so we can use directly in Rust range 0..left
-                let mut ranges = Vec::new();
-                for r_idx in (0..left).rev() {
-                    if range_data[r_idx as usize].start_bp < q_end {
-                        ranges.push(range_data[r_idx as usize].clone());
-                    } else {
-                        break;
+                // Left is not inclusive so we can use directly in Rust range 0..left. Every
+                // range before it starts before the query end, but those ending before the
+                // query start cannot overlap and are dropped before their record is read.
+                let ranges: Vec<&ContigRange> = range_data[..left as usize]
+                    .iter()
+                    .filter(|range| range.overlaps(q_start, q_end))
+                    .collect();
+                info!(
+                    "search: first tile scanned {} ranges, {} overlap",
+                    left,
+                    ranges.len()
+                );
+                for range in ranges {
+                    if let Some(e) = stopped(&mut results) {
+                        return Err(e);
                     }
-                }
-                // q_family = Some("DF000000005")
-                // self.bgz_files[range.bed_idx as usize].name = "DF000000263.bed.bgz"
-                // ContigRange { bed_idx: 218, start_bp: 44990894, end_bp: 44990556, bgzf_pos: 249641047067 }
-                for range in ranges.iter().rev() {
-                    // If a family is specified in the query, it is only necessary to consider
-                    // hits to a specific bed_idx file.  This is a short-circuit optimization.
-                    if let Some(q_family) = q_family {
-                        if !self.bgz_files[range.bed_idx as usize]
-                            .name
-                            .contains(q_family)
-                        {
-                            continue;
-                        }
+                    // If families are specified in the query, it is only necessary to consider
+                    // hits to their bed_idx files.  This is a short-circuit optimization.
+                    if !family_file(
+                        &self.bgz_files[range.bed_idx as usize].name,
+                        q_family,
+                        self.exact_version,
+                    ) {
+                        continue;
+                    }
+                    if !first_sight(q_seen, range) {
+                        continue;
                     }
                     // This is surprisingly fast despite having to open/abandon a bgzf file per
                     // annotation.  Pre-grouping the annotations by family/start might speed up
                     // retreival, however then it would need to be resorted by contig/start for
                     // output -- all in memory -- should experiment.
-                    let bgz_file = format!(
-                        "{}/{}",
-                        bgz_dir, self.bgz_files[range.bed_idx as usize].name
-                    );
-                    let mut reader = File::open(&bgz_file).map(bgzf::Reader::new).unwrap();
-                    reader
-                        .seek(bgzf::VirtualPosition::from(range.bgzf_pos))
-                        .expect("Could Not Seek");
-                    let mut line = String::new();
-                    reader.read_line(&mut line).unwrap();
-                    if filter_line(&line, &q_start, &q_family, &q_nrph) {
+                    let Some(mut records) = self.open_range(bgz_dir, range, q_strict, q_skipped)?
+                    else {
+                        continue;
+                    };
+                    let line = records.read_record().unwrap().unwrap_or_default().line;
+                    if filter_line(
+                        &line,
+                        &q_start,
+                        q_family,
+                        self.exact_version,
+                        &q_nrph,
+                        q_strand,
+                        &q_scores,
+                        &mut unparsable,
+                    ) {
                         results.push(line);
                         hits += 1;
                     }
                 }
             }
-            if end_tile > start_tile {
-                let mut tile_start_bp = (self.tile_size as u64) * ((start_tile + 1) as u64);
-                for t_idx in (start_tile + 1)..=end_tile {
-                    range_count = self.range_counts[q_contig_idx as usize][t_idx];
-                    if range_count > 0 {
-                        let range_data = self
-                            .read_tile(i_file, q_contig_idx, t_idx)
-                            .unwrap()
-                            .contig_ranges;
-                        if range_data[0].start_bp < q_end {
-                            // A binary search is not needed here as we know that the query spans
-                            // more than one tile and that either this tile needs to be evaluated
-                            // fully (middle tile), or contains hits on the left side and simply
-                            // needs a break condition when it has past the last annotation
-                            // (end tile).
-                            for r_idx in 0..range_count {
-                                if range_data[r_idx as usize].start_bp < (tile_start_bp as u64) {
+        }
+        if end_tile > start_tile {
+            let mut tile_start_bp = (self.tile_size as u64) * ((start_tile + 1) as u64);
+            for t_idx in (start_tile + 1)..=end_tile {
+                if let Some(e) = stopped(&mut results) {
+                    return Err(e);
+                }
+                range_count = self.range_counts[q_contig_idx as usize][t_idx];
+                if range_count > 0 {
+                    let range_data = self
+                        .load_tile(i_file, &mut window, q_contig_idx, t_idx, end_tile)?
+                        .contig_ranges;
+                    if range_data[0].start_bp < q_end {
+                        // A binary search is not needed here as we know that the query spans
+                        // more than one tile and that either this tile needs to be evaluated
+                        // fully (middle tile), or contains hits on the left side and simply
+                        // needs a break condition when it has past the last annotation
+                        // (end tile).
+                        for r_idx in 0..range_count {
+                            if range_data[r_idx as usize].start_bp < tile_start_bp {
+                                continue;
+                            }
+
+                            // If families are specified in the query, it is only necessary to consider
+                            // hits to their bed_idx files.  This is a short-circuit optimization.
+                            let bed_idx = range_data[r_idx as usize].bed_idx;
+                            if !family_file(
+                                &self.bgz_files[bed_idx as usize].name,
+                                q_family,
+                                self.exact_version,
+                            ) {
+                                continue;
+                            }
+
+                            if range_data[r_idx as usize].start_bp < q_end {
+                                if !range_data[r_idx as usize].overlaps(q_start, q_end) {
                                     continue;
                                 }
-
-                                // If a family is specified in the query, it is only necessary to consider
-                                // hits to a specific bed_idx file.  This is a short-circuit optimization.
-                                if let Some(q_family) = q_family {
-                                    if !self.bgz_files[range_data[r_idx as usize].bed_idx as usize]
-                                        .name
-                                        .contains(q_family)
-                                    {
-                                        continue;
-                                    }
+                                if let Some(e) = stopped(&mut results) {
+                                    return Err(e);
                                 }
-
-                                if range_data[r_idx as usize].start_bp < q_end {
-                                    let bgz_file = format!(
-                                        "{}/{}",
-                                        bgz_dir,
-                                        self.bgz_files[range_data[r_idx as usize].bed_idx as usize]
-                                            .name
-                                    );
-                                    let mut reader =
-                                        File::open(&bgz_file).map(bgzf::Reader::new).unwrap();
-                                    reader
-                                        .seek(bgzf::VirtualPosition::from(
-                                            range_data[r_idx as usize].bgzf_pos,
-                                        ))
-                                        .unwrap();
-                                    let mut line = String::new();
-                                    reader.read_line(&mut line).unwrap();
-                                    if filter_line(&line, &q_start, &q_family, &q_nrph) {
-                                        results.push(line);
-                                        hits += 1;
-                                    }
-                                } else {
-                                    info!(
-                                        "Breaking because {} >= {}",
-                                        range_data[r_idx as usize].start_bp, q_end
-                                    );
-                                    break;
+                                if !first_sight(q_seen, &range_data[r_idx as usize]) {
+                                    continue;
+                                }
+                                let Some(mut records) = self.open_range(
+                                    bgz_dir,
+                                    &range_data[r_idx as usize],
+                                    q_strict,
+                                    q_skipped,
+                                )?
+                                else {
+                                    continue;
+                                };
+                                let line = records.read_record().unwrap().unwrap_or_default().line;
+                                if filter_line(
+                                    &line,
+                                    &q_start,
+                                    q_family,
+                                    self.exact_version,
+                                    &q_nrph,
+                                    q_strand,
+                                    &q_scores,
+                                    &mut unparsable,
+                                ) {
+                                    results.push(line);
+                                    hits += 1;
                                 }
+                            } else {
+                                info!(
+                                    "Breaking because {} >= {}",
+                                    range_data[r_idx as usize].start_bp, q_end
+                                );
+                                break;
                             }
                         }
                     }
-                    tile_start_bp += self.tile_size as u64;
                 }
+                tile_start_bp += self.tile_size as u64;
             }
         }
+
+        // Long ranges kept in the contig's overflow list are in none of the tiles. Their
+        // records are added to the tile hits, which are then put in RecordOrder.
+        let overflow: Vec<&ContigRange> = self.overflow_ranges[q_contig_idx as usize]
+            .iter()
+            .filter(|range| range.overlaps(q_start, q_end))
+            .collect();
+        let mut overflow_hits = 0;
+        for range in overflow {
+            if let Some(e) = stopped(&mut results) {
+                return Err(e);
+            }
+            if !family_file(
+                &self.bgz_files[range.bed_idx as usize].name,
+                q_family,
+                self.exact_version,
+            ) {
+                continue;
+            }
+            if !first_sight(q_seen, range) {
+                continue;
+            }
+            let Some(mut records) = self.open_range(bgz_dir, range, q_strict, q_skipped)? else {
+                continue;
+            };
+            let line = records.read_record().unwrap().unwrap_or_default().line;
+            if filter_line(
+                &line,
+                &q_start,
+                q_family,
+                self.exact_version,
+                &q_nrph,
+                q_strand,
+                &q_scores,
+                &mut unparsable,
+            ) {
+                results.push(line);
+                overflow_hits += 1;
+            }
+        }
+        hits += overflow_hits;
+        sort_records(&mut results, |_| 0);
         info!("Total overlaps: {}", hits);
+        if unparsable > 0 {
+            warn!(
+                "{} records with unparsable coordinates or an unparsable score were left out",
+                unparsable
+            );
+        }
         Ok(results)
     }
 
+    // Sanity checking index vs file system
+    // Compares the bgz files on disk with the ones indexed. Differences are logged and kept
+    // in file_warnings, for callers that return them alongside the results.
+    fn check_bgz_files(&mut self, filenames: &Vec<String>, bgz_dir: &str) {
+        let mut warnings = Vec::new();
+        let mut missing = HashSet::new();
+        let mut f_lookup = HashSet::new();
+        for filename in filenames {
+            f_lookup.insert(filename);
+        }
+        let (mut calls, started) = (0, Instant::now());
+        for (bed_idx, ifile) in self.bgz_files.iter().enumerate() {
+            if !f_lookup.contains(&ifile.name) {
+                missing.insert(bed_idx as u32);
+                warnings.push(format!("It appears that {} has been deleted from the alignments folder since the index was created!", ifile.name));
+            } else {
+                f_lookup.remove(&ifile.name);
+                let bgz_file = stored_path(bgz_dir, &ifile.name);
+                // Get metadata for the file
+                let metadata = fs::metadata(bgz_file.clone()).unwrap();
+                calls += 1;
+                // Obtain modification time
+                let modification_time = metadata.modified().unwrap();
+                // Convert modification time to a more readable format
+                let mod_time = modification_time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap();
+                // Obtain file size
+                let file_size = metadata.len();
+                if file_size != ifile.bytes {
+                    warnings.push(format!("It appears that {} has been modified since the index was created. Byte size difference index={}, file={}", ifile.name, ifile.bytes, file_size));
+                } else if let Some(indexed_hash) = ifile.content_hash {
+                    // Only files written after the index are read to be hashed
+                    let newer = self
+                        .index_mod_time
+                        .is_none_or(|index_time| modification_time > index_time);
+                    if newer {
+                        match content_hash(&bgz_file) {
+                            Ok(hash) if hash == indexed_hash => {}
+                            Ok(hash) => warnings.push(format!("It appears that {} has been modified since the index was created. Content hash difference index={:016x}, file={:016x}", ifile.name, indexed_hash, hash)),
+                            Err(e) => warnings.push(format!("It appears that {} has been modified since the index was created. It could not be hashed - {}", ifile.name, e)),
+                        }
+                    }
+                } else if mod_time.as_secs_f64() != ifile.mod_time {
+                    warnings.push(format!("It appears that {} has been modified since the index was created. Modification time difference index={:?}, file={:?}", ifile.name, ifile.mod_time, mod_time));
+                }
+            }
+        }
+        self.metadata_stats.calls += calls;
+        self.metadata_stats.time += started.elapsed();
+        for fsfile in &f_lookup {
+            warnings.push(format!(
+                "It appears that {} has been added since the index was created!",
+                fsfile
+            ));
+        }
+        for warning in &warnings {
+            warn!("{}", warning);
+        }
+        self.file_warnings = warnings;
+        self.missing_files = missing;
+    }
+
+    // Matching ranges of missing files skipped by the last search_idx, by file name
+    pub fn skipped_ranges(&self) -> &Vec<(String, usize)> {
+        &self.skipped_ranges
+    }
+
+    // Records positioned at a range's record. A missing bgz file fails a strict search,
+    // otherwise the range is counted in skipped and None returned.
+    fn open_range(
+        &self,
+        bgz_dir: &str,
+        range: &ContigRange,
+        strict: bool,
+        skipped: &mut HashMap<u32, usize>,
+    ) -> Result<Option<BedRecordStream<bgzf::Reader<File>>>, Box<dyn Error>> {
+        let name = &self.bgz_files[range.bed_idx as usize].name;
+        // Files seen missing before the search are not tried again for every range
+        if !self.missing_files.contains(&range.bed_idx) {
+            match BedRecordStream::open(&stored_path(bgz_dir, name)) {
+                Ok(mut records) => {
+                    records.seek(range.bgzf_pos)?;
+                    return Ok(Some(records));
+                }
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                Err(_) => {}
+            }
+        }
+        if strict {
+            return Err(Box::new(MissingBgzFile { name: name.clone() }));
+        }
+        *skipped.entry(range.bed_idx).or_insert(0) += 1;
+        Ok(None)
+    }
+
+    // Warnings from the last check of the bgz files against the index
+    pub fn file_warnings(&self) -> &Vec<String> {
+        &self.file_warnings
+    }
+
+    // Saving an index opened for queries is an error instead of a write
+    fn check_writable(&self, file_path: &str) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Index {} Not Written, It Was Opened Read-Only", file_path),
+            ));
+        }
+        Ok(())
+    }
+
     //
     // Perhaps (niavely) I didn't use Serde to do this. I was
     // worried that it's serialization mechanism was too opaque
@@ -597,15 +1736,24 @@ impl ContigIndex {
     //   4      u32    little-endian   BGZ File Count (F) **NEW**
     //  C*4     [u32]  little-endian   Per contig tile counts (T)
     //  T*4     [u32]  little-endian   Per tile range counts (R)
-    //  C*40    chars                  Per contig 40 char name
-    //  F*56    BGZ_File (see details) **NEW**
+    //  C*N     Name                   Per contig name
+    //  F*(N+16) BGZ_File (see details) **NEW**
+    //  C*4     [u32]  little-endian   Per contig overflow range counts (O), version 2 only
+    //  O*28    ContigRanges of the overflow lists in contig order, version 2 only
     //  R*28    ContigRanges (see details) in contig,tile order
     //
-    // BGZ_File Structure (56 bytes)
+    // Name Structure (2+L bytes, 40 bytes null padded in format version 0)
+    //  Bytes   Type   Byte_order      Description
+    //  -----   -----  --------------  -------------------------
+    //   2      u16    little-endian   L - Name length in bytes
+    //   L      chars                  name
+    //
+    // BGZ_File Structure (N+16 bytes)
     //  Bytes   Type   Byte_order      Description
     //  -----   -----  --------------  -------------------------
-    //   40     chars                  name - Filename
-    //   8      f64    little-endian   mod_time - Seconds since modification
+    //   N      Name                   name - Filename
+    //   8      f64    little-endian   mod_time - Seconds since modification, or in version 3
+    //                                   u64 content_hash - see content_hash
     //   8      u64    little-endian   bytes - File size in bytes
     //
     // ContigRanges Structure (28 bytes)
@@ -616,76 +1764,118 @@ impl ContigIndex {
     //   8      u64    little-endian   end_bp - Range end (zero-based, half-open)
     //   8      u64    little-endian   bgzf_pos - Virtual pos for start of record
     //
-    // BGZFiles starts at: 20+(Contigs*44)+(Tiles*4)
-    // ContigRanges starts at: 20+(Files*56)+(Contigs*44)+(Tiles*4)
+    // BGZFiles starts at: 20+(Contigs*4)+(Tiles*4)+contig names
+    // ContigRanges starts at: the end of the last BGZ_File, or of the overflow lists in format
+    // version 2, in format version 0 at 20+(Files*56)+(Contigs*44)+(Tiles*4)
+    //
+    // The overflow lists hold ranges spanning more tiles than the overflow_tiles the index was
+    // built with, each stored once and sorted by start. Searches read them with the header.
+    // Version 2 is only written when a list holds any range, otherwise the index is version 1.
+    // Version 3 is version 2 with content hashes, written for deterministic indexes.
+    //
+    // Counts are limited to MAX_INDEX_COUNT, offsets into the file are computed as u64.
+    // TODO: The next format version could widen the per tile range counts to u64
     //
     // Save the ContigIndex to a binary file
     #[allow(dead_code)]
     fn save_index(&self, file_path: &str) -> std::io::Result<()> {
+        self.check_writable(file_path)?;
+        // Check every count fits its field before anything is written
+        let contig_count = index_count_field(self.contigs.len(), "Contig")?;
+        let file_count = index_count_field(self.bgz_files.len(), "BGZ File")?;
+        check_unique_file_names(self.bgz_files.iter().map(|f| f.name.as_str()), file_path)?;
+        let mut tile_counts = Vec::with_capacity(self.contigs.len());
+        let mut range_counts = Vec::new();
+        for contig in &self.contigs {
+            tile_counts.push(index_count_field(
+                contig.tile_count,
+                &format!("Tile ({})", contig.name),
+            )?);
+            for tile in 0..contig.tile_count {
+                range_counts.push(index_count_field(
+                    contig.tile_ranges(tile).len(),
+                    &format!("Range ({})", contig.name),
+                )?);
+            }
+        }
+
+        let mut overflow_counts = Vec::with_capacity(self.contigs.len());
+        for contig in &self.contigs {
+            overflow_counts.push(index_count_field(
+                contig.overflow_ranges.len(),
+                &format!("Overflow Range ({})", contig.name),
+            )?);
+        }
+        let f_ver = match (
+            self.deterministic,
+            overflow_counts.iter().any(|count| *count > 0),
+        ) {
+            (true, _) => HASHED_FORMAT_VERSION,
+            (false, true) => OVERFLOW_FORMAT_VERSION,
+            (false, false) => FORMAT_VERSION,
+        };
+
         let fobj = File::create(file_path)?;
         let mut file = io::BufWriter::new(fobj);
 
         // Write the magic number for this filetype (6-bytes)
-        file.write_all(&MAGIC_NUMBER)?;
+        file.write_all(MAGIC_NUMBER)?;
         // Write the file format version (2-bytes, little-endian)
-        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&f_ver.to_le_bytes())?;
 
         // Write tile_size to the file
         file.write_all(&self.tile_size.to_le_bytes())?;
 
         // Write the number of contigs
-        file.write_all(&(self.contigs.len() as u32).to_le_bytes())?;
+        file.write_all(&contig_count.to_le_bytes())?;
 
         // Write the number of bgz files
-        file.write_all(&(self.bgz_files.len() as u32).to_le_bytes())?;
+        file.write_all(&file_count.to_le_bytes())?;
 
         // For each contig write the number of tiles it contains to the file
-        for contig in &self.contigs {
-            file.write_all(&(contig.contig_tiles.len() as u32).to_le_bytes())?;
+        for tile_count in &tile_counts {
+            file.write_all(&tile_count.to_le_bytes())?;
         }
 
         // For each contig/tile write out how many ranges are contained
-        for contig in &self.contigs {
-            for tile in &contig.contig_tiles {
-                file.write_all(&(tile.contig_ranges.len() as u32).to_le_bytes())?;
-            }
+        for range_count in &range_counts {
+            file.write_all(&range_count.to_le_bytes())?;
         }
 
         for contig in &self.contigs {
-            let mut name_vec = vec![0; 40];
-            let mut i = 0;
-            // TODO: Warn user of name length limitation if name is longer
-            //       and make this a program constant tied to the format version
-            for byte in contig.name.as_bytes().iter() {
-                name_vec[i] = *byte;
-                i += 1;
-                if i == 40 {
-                    break;
-                }
-            }
-            file.write_all(&name_vec)?;
+            write_index_name(&mut file, &contig.name)?;
         }
 
         for bgz_file in &self.bgz_files {
-            let mut name_vec = vec![0; 40];
-            let mut i = 0;
-            // TODO: Warn user of name length limitation if name is longer
-            //       and make this a program constant tied to the format version
             // TODO: Consider extending with CRC for file change detection
-            for byte in bgz_file.name.as_bytes().iter() {
-                name_vec[i] = *byte;
-                i += 1;
-                if i == 40 {
-                    break;
+            write_index_name(&mut file, &bgz_file.name)?;
+            match bgz_file.content_hash {
+                Some(hash) if f_ver >= HASHED_FORMAT_VERSION => {
+                    file.write_all(&hash.to_le_bytes())?
                 }
+                _ => file.write_all(&bgz_file.mod_time.to_le_bytes())?,
             }
-            file.write_all(&name_vec)?;
-            file.write_all(&bgz_file.mod_time.to_le_bytes())?;
             file.write_all(&bgz_file.bytes.to_le_bytes())?;
         }
 
+        if f_ver >= OVERFLOW_FORMAT_VERSION {
+            for overflow_count in &overflow_counts {
+                file.write_all(&overflow_count.to_le_bytes())?;
+            }
+            for contig in &self.contigs {
+                let mut sorted_ranges = contig.overflow_ranges.clone();
+                sorted_ranges.sort_by_key(|r| r.start_bp);
+                for range in &sorted_ranges {
+                    file.write_all(&range.bed_idx.to_le_bytes())?;
+                    file.write_all(&range.start_bp.to_le_bytes())?;
+                    file.write_all(&range.end_bp.to_le_bytes())?;
+                    file.write_all(&range.bgzf_pos.to_le_bytes())?;
+                }
+            }
+        }
+
         for contig in &self.contigs {
-            for tile in &contig.contig_tiles {
+            for tile in contig.contig_tiles.values() {
                 let mut sorted_ranges = tile.contig_ranges.clone();
                 sorted_ranges.sort_by_key(|r| r.start_bp);
 
@@ -704,6 +1894,7 @@ impl ContigIndex {
     // Save the ContigIndex to a binary file
     #[allow(dead_code)]
     fn save_igd_format(&self, file_path: &str) -> std::io::Result<()> {
+        self.check_writable(file_path)?;
         let fobj = File::create(file_path)?;
         let mut file = io::BufWriter::new(fobj);
 
@@ -711,7 +1902,7 @@ impl ContigIndex {
         file.write_all(&self.tile_size.to_le_bytes())?;
 
         // Write gType = 1
-        let g_type = 1 as i32;
+        let g_type = 1_i32;
         file.write_all(&g_type.to_le_bytes())?;
 
         // Write the number of contigs
@@ -719,14 +1910,14 @@ impl ContigIndex {
 
         // For each contig write the number of tiles it contains to the file
         for contig in &self.contigs {
-            info!("contig: {} = {}", contig.name, contig.contig_tiles.len());
-            file.write_all(&(contig.contig_tiles.len() as u32).to_le_bytes())?;
+            info!("contig: {} = {}", contig.name, contig.tile_count);
+            file.write_all(&(contig.tile_count as u32).to_le_bytes())?;
         }
 
         // For each contig/tile write out how many ranges are contained
         for contig in &self.contigs {
-            for tile in &contig.contig_tiles {
-                file.write_all(&(tile.contig_ranges.len() as u32).to_le_bytes())?;
+            for tile in 0..contig.tile_count {
+                file.write_all(&(contig.tile_ranges(tile).len() as u32).to_le_bytes())?;
             }
         }
 
@@ -745,14 +1936,14 @@ impl ContigIndex {
 
         for contig in &self.contigs {
             // Write the number of tiles in the contig
-            file.write_all(&(contig.contig_tiles.len() as u32).to_le_bytes())?;
+            file.write_all(&(contig.tile_count as u32).to_le_bytes())?;
 
-            for tile in &contig.contig_tiles {
+            for tile in contig.contig_tiles.values() {
                 let mut sorted_ranges = tile.contig_ranges.clone();
                 //sorted_ranges.sort_by_key(|r| std::cmp::Reverse(r.start_bp));
                 sorted_ranges.sort_by_key(|r| r.start_bp);
 
-                let padding = 0 as i32;
+                let padding = 0_i32;
                 for range in &sorted_ranges {
                     // Write the ContigRange fields
                     file.write_all(&range.bed_idx.to_le_bytes())?;
@@ -775,29 +1966,45 @@ impl ContigIndex {
 }
 
 // #[allow(dead_code)]
+fn bgz_file_name(path: &Path) -> Option<String> {
+    if path.is_file() {
+        path.file_name()?
+            .to_str()
+            .filter(|s| s.ends_with(".bgz"))
+            .map(|s| s.to_owned())
+    } else {
+        None
+    }
+}
+
+fn bgz_file_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        if let Some(name) = bgz_file_name(&entry?.path()) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+// The bgz file names, the bgz folder, the index and the index file path of a data type
+pub type PreparedIndex = (Vec<String>, String, ContigIndex, String);
+
 pub fn prep_idx(
-    proj_dir: &String,
-    data_type: &String,
-) -> Result<(Vec<String>, String, ContigIndex, String), Box<dyn Error>> {
+    assembly: &str,
+    data_type: &str,
+    read_only: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<PreparedIndex, Box<dyn Error>> {
+    let data_directory = &path_string(data_directory);
     // Initial instantiation
-    let contig_index = ContigIndex {
-        tile_size: TILE_SIZE,
-        bgz_files: Vec::new(),
-        contig_count: 0,
-        contig_lookup: HashMap::new(),
-        tile_counts: Vec::new(),
-        range_counts: Vec::new(),
-        range_data_index: Vec::new(),
-        contigs: Vec::new(),
-    };
+    let contig_index = ContigIndex::new(read_only);
 
     // TODO: Command line parameter
     // The full directory takes ~4.4 minutes to index
     // The minimal beds take ~57sec to index
 
-    // From the project directory several things can be assumed:
-    let index_file = format!("{}/{}_idx.dat", proj_dir, data_type);
-    let bgz_dir = format!("{}/{}", proj_dir, data_type);
+    let (bgz_dir, index_file) = idx_paths(assembly, data_type, data_directory);
     if !Path::new(&bgz_dir).exists() {
         eprintln!(
             "Directory \"{}\" Does Not Exist - Aborting Indexing",
@@ -806,35 +2013,331 @@ pub fn prep_idx(
         exit(1)
     }
 
-    // let mut filenames: Vec<String> = Vec::new();
-    let entries = fs::read_dir(&bgz_dir)?;
-    let filenames: Vec<String> = entries
-        .filter_map(|entry| {
-            let path = entry.ok()?.path();
-            if path.is_file() {
-                path.file_name()?
-                    .to_str()
-                    .filter(|s| s.ends_with(".bgz"))
-                    .map(|s| s.to_owned())
-            } else {
-                None
+    let filenames = stored_bgz_names(&bgz_dir)?;
+
+    Ok((filenames, bgz_dir, contig_index, index_file))
+}
+
+// The folder of a data type's bgz files and its index file. From the project directory
+// several things can be assumed.
+pub fn idx_paths(assembly: &str, data_type: &str, data_directory: &str) -> (String, String) {
+    let proj_dir = join_path(data_directory, &[assembly]);
+    let index_file = join_path(&proj_dir, &[&format!("{}_idx.dat", data_type)]);
+    (join_path(&proj_dir, &[data_type]), index_file)
+}
+
+// prep_idx for a search. Data assumed immutable, as on a read-only production mount, is
+// neither listed nor checked against the index, so searching it makes no metadata calls for
+// the bgz files it does not read, and its filenames are empty.
+pub fn prep_search(
+    assembly: &str,
+    data_type: &str,
+    assume_immutable: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<PreparedIndex, Box<dyn Error>> {
+    let data_directory = &path_string(data_directory);
+    if !assume_immutable {
+        let started = Instant::now();
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, true, data_directory)?;
+        contig_index.metadata_stats.calls += filenames.len() as u64;
+        contig_index.metadata_stats.time += started.elapsed();
+        return Ok((filenames, bgz_dir, contig_index, index_file));
+    }
+    let mut contig_index = ContigIndex::new(true);
+    contig_index.assume_immutable = true;
+    let (bgz_dir, index_file) = idx_paths(assembly, data_type, data_directory);
+    Ok((Vec::new(), bgz_dir, contig_index, index_file))
+}
+
+// The BED files of a data type folder, named relative to it as they are stored in an index.
+// Data types with many files keep them in shard folders one level down.
+pub fn stored_bgz_names(bgz_dir: &str) -> io::Result<Vec<String>> {
+    let mut filenames = Vec::new();
+    for entry in fs::read_dir(bgz_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let shard = path.file_name().unwrap().to_string_lossy().to_string();
+            // Stored with "/" on every platform, see stored_path
+            for name in bgz_file_names(&path)? {
+                filenames.push(format!("{}/{}", shard, name));
+            }
+        } else if let Some(name) = bgz_file_name(&path) {
+            filenames.push(name);
+        }
+    }
+    // In name order whatever order the filesystem lists them in, so files keep their bed_idx
+    // and the index its bytes from one build to the next
+    filenames.sort();
+    Ok(filenames)
+}
+
+// The accession a stored BED file name is keyed by, "<shard>/<acc>.bed.bgz" or "<acc>.bed.bgz"
+pub fn stored_accession(name: &str) -> &str {
+    let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    file_name
+        .strip_suffix(".bed.bgz")
+        .or_else(|| file_name.strip_suffix(".bgz"))
+        .unwrap_or(file_name)
+}
+
+// build_idx lists the files it indexed next to the index as <data_type>_families.idx, so the
+// families of a data type can be listed without reading its folder. One line per file, sorted
+// by accession: the accession, its records, the file's bytes and its bed_idx in the index.
+pub const FAMILY_LISTING_SUFFIX: &str = "_families.idx";
+pub const FAMILY_LISTING_HEADER: &str = "#accession\trecords\tbytes\tbed_idx";
+
+// The family listing of an index, <data_type>_families.idx for <data_type>_idx.dat
+pub fn family_listing_file(index_file: &str) -> String {
+    format!(
+        "{}{}",
+        index_file.strip_suffix("_idx.dat").unwrap_or(index_file),
+        FAMILY_LISTING_SUFFIX
+    )
+}
+
+// Written beside the listing and renamed over it, readers see the old or the new listing
+fn write_family_listing(
+    listing_file: &str,
+    entries: &mut [(String, u64, u64, u32)],
+) -> io::Result<()> {
+    entries.sort();
+    let temp = format!("{}.{}.tmp", listing_file, std::process::id());
+    let written = (|| -> io::Result<()> {
+        let mut out = io::BufWriter::new(File::create(&temp)?);
+        writeln!(out, "{}", FAMILY_LISTING_HEADER)?;
+        for (accession, records, bytes, bed_idx) in entries.iter() {
+            writeln!(out, "{}\t{}\t{}\t{}", accession, records, bytes, bed_idx)?;
+        }
+        out.flush()?;
+        fs::rename(&temp, listing_file)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+// Indexes are written to a new <data_type>_idx.<timestamp>.dat and <data_type>_idx.dat is
+// then swapped for a symlink to it in a single rename. Queries holding the old file open keep
+// reading it, later opens follow the link to the new one. A regular <data_type>_idx.dat, as
+// written by earlier releases, is read as it is until the next rebuild replaces it.
+fn versioned_index_file(index_file: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!("{}.{}.dat", index_file.trim_end_matches(".dat"), nanos)
+}
+
+// Points index_file at versioned, a file in the same folder. The link is relative so the data
+// directory can be moved.
+#[cfg(unix)]
+fn publish_index(versioned: &str, index_file: &str) -> io::Result<()> {
+    let target = Path::new(versioned).file_name().unwrap();
+    let link = format!("{}.{}.link", index_file, std::process::id());
+    let _ = fs::remove_file(&link);
+    std::os::unix::fs::symlink(target, &link)?;
+    fs::rename(&link, index_file).inspect_err(|_| {
+        let _ = fs::remove_file(&link);
+    })
+}
+
+// Without symlinks the new index is renamed into place, replacing the previous one
+#[cfg(not(unix))]
+fn publish_index(versioned: &str, index_file: &str) -> io::Result<()> {
+    fs::rename(versioned, index_file)
+}
+
+// A reader opening an index while it is swapped can find it missing for a moment, as where the
+// new index is renamed over the old or the link's old target is cleaned away under a reader on
+// NFS (ESTALE). Opens failing that way are retried, with a delay doubled for each retry, before
+// the index is taken to be missing. $TE_IDX_OPEN_ATTEMPTS and $TE_IDX_OPEN_RETRY_MS override
+// the defaults.
+pub const OPEN_ATTEMPTS_ENV: &str = "TE_IDX_OPEN_ATTEMPTS";
+pub const OPEN_RETRY_MS_ENV: &str = "TE_IDX_OPEN_RETRY_MS";
+pub const DEFAULT_OPEN_ATTEMPTS: u32 = 5;
+pub const DEFAULT_OPEN_RETRY_MS: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpenRetry {
+    pub attempts: u32,   // Opens tried in all, 1 never retries
+    pub delay: Duration, // Before the first retry
+}
+
+impl Default for OpenRetry {
+    fn default() -> Self {
+        OpenRetry {
+            attempts: DEFAULT_OPEN_ATTEMPTS,
+            delay: Duration::from_millis(DEFAULT_OPEN_RETRY_MS),
+        }
+    }
+}
+
+// The retries of index opens, the defaults unless set in the environment. Values that are
+// not numbers are ignored.
+pub fn configured_open_retry() -> OpenRetry {
+    let env = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+    };
+    let default = OpenRetry::default();
+    OpenRetry {
+        attempts: env(OPEN_ATTEMPTS_ENV).map_or(default.attempts, |n| n.clamp(1, 100) as u32),
+        delay: env(OPEN_RETRY_MS_ENV).map_or(default.delay, Duration::from_millis),
+    }
+}
+
+// The index could not be opened within the retries, it is not there
+#[derive(Debug)]
+pub struct NotIndexed {
+    pub index_file: String,
+    pub attempts: u32,
+}
+
+impl fmt::Display for NotIndexed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Index {} Not Found In {} Attempts, It May Not Have Been Built",
+            self.index_file, self.attempts
+        )
+    }
+}
+
+impl Error for NotIndexed {}
+
+// Failures of an open that can pass while an index is swapped
+fn transient_open_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+// Opens an index, retrying transient failures, and returns it with the retries it took. An
+// index still missing once the attempts are spent is a NotFound error holding NotIndexed,
+// other failures are returned as they are.
+pub fn open_index(index_file: &str, retry: &OpenRetry) -> io::Result<(File, u32)> {
+    let attempts = retry.attempts.max(1);
+    let mut delay = retry.delay;
+    let mut retries = 0;
+    loop {
+        match File::open(index_file) {
+            Ok(file) => {
+                if retries > 0 {
+                    warn!("Index {} Opened After {} Retries", index_file, retries);
+                }
+                return Ok((file, retries));
             }
+            Err(e) if !transient_open_error(&e) => return Err(e),
+            Err(_) if retries + 1 >= attempts => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    NotIndexed {
+                        index_file: index_file.to_string(),
+                        attempts,
+                    },
+                ))
+            }
+            Err(_) => {
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                retries += 1;
+            }
+        }
+    }
+}
+
+// Installs a copy of index as the versioned file index_file is swapped to, as build_idx does
+// with the index it writes
+pub fn install_index(index: &str, index_file: &str) -> io::Result<()> {
+    let versioned = versioned_index_file(index_file);
+    fs::copy(index, &versioned)?;
+    publish_index(&versioned, index_file).inspect_err(|_| {
+        let _ = fs::remove_file(&versioned);
+    })
+}
+
+// Whether a file name is that of a versioned index file, <data_type>_idx.<timestamp>.dat
+pub fn is_versioned_index(name: &str) -> bool {
+    name.strip_suffix(".dat")
+        .and_then(|rest| rest.rsplit_once('.'))
+        .is_some_and(|(stem, stamp)| {
+            stem.ends_with("_idx") && !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit())
         })
-        .collect();
+}
 
-    Ok((filenames, bgz_dir, contig_index, index_file))
+// Superseded index files younger than this are kept by clean-indexes, for queries still
+// reading them
+pub const DEFAULT_INDEX_RETENTION_MINS: u64 = 60;
+
+// Versioned files of index_file other than the one it points at, last modified longer ago
+// than retention, are removed. Returns the removed files. A regular index_file points at none.
+pub fn clean_indexes(index_file: impl AsRef<Path>, retention: Duration) -> io::Result<Vec<String>> {
+    let index_file = &path_string(index_file);
+    let path = Path::new(index_file);
+    let folder = path.parent().unwrap_or(Path::new("."));
+    let stem = format!(
+        "{}.",
+        path.file_name()
+            .unwrap()
+            .to_string_lossy()
+            .trim_end_matches(".dat")
+    );
+    let current = fs::read_link(path)
+        .ok()
+        .and_then(|target| target.file_name().map(|n| n.to_string_lossy().to_string()));
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let versioned = name
+            .strip_prefix(&stem)
+            .and_then(|rest| rest.strip_suffix(".dat"))
+            .is_some_and(|stamp| !stamp.is_empty() && stamp.bytes().all(|b| b.is_ascii_digit()));
+        if !versioned || current.as_ref() == Some(&name) {
+            continue;
+        }
+        let age = entry
+            .metadata()?
+            .modified()?
+            .elapsed()
+            .unwrap_or(Duration::ZERO);
+        if age >= retention {
+            fs::remove_file(entry.path())?;
+            removed.push(entry.path().to_string_lossy().to_string());
+        }
+    }
+    removed.sort();
+    Ok(removed)
 }
 
 #[allow(dead_code)]
 pub fn build_idx(
     filenames: &Vec<String>,
-    bgz_dir: &String,
+    bgz_dir: impl AsRef<Path>,
     contig_index: &mut ContigIndex,
-    index_file: &String,
+    index_file: impl AsRef<Path>,
 ) -> Result<(), Box<dyn Error>> {
+    let bgz_dir = &path_string(bgz_dir);
+    let index_file = &path_string(index_file);
+    // The file number is stored with every range as bed_idx
+    index_count_field(filenames.len(), "BGZ File")?;
+    // Names are stored in fixed width fields, a cut name would point at another file
+    if let Some(name) = filenames.iter().find(|f| f.len() > MAX_FILE_NAME_BYTES) {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "BGZ File Name {} Is Longer Than The Index Format Limit Of {} Bytes",
+                name, MAX_FILE_NAME_BYTES
+            ),
+        )));
+    }
     let mut fidx = 0;
+    let mut listing = Vec::with_capacity(filenames.len());
     for filename in filenames {
-        let bgz_file = format!("{}/{}", bgz_dir, filename);
+        let bgz_file = stored_path(bgz_dir, filename);
         // Get metadata for the file
         let metadata = fs::metadata(&bgz_file).unwrap();
         // Obtain modification time
@@ -851,55 +2354,346 @@ pub fn build_idx(
         );
 
         // TODO: validate that the file is in BED format before including it
-        contig_index.bgz_files.push(BGZFile {
-            name: filename.clone(),
-            mod_time: mod_time.as_secs_f64(),
-            bytes: file_size,
-        });
-
-        let mut reader = File::open(bgz_file).map(bgzf::Reader::new).unwrap();
-        let mut line = String::new();
-
-        let mut virt_pos = u64::from(reader.virtual_position());
-
-        while reader.read_line(&mut line).unwrap() > 0 {
-            let fields: Vec<&str> = line.trim_end().split_whitespace().collect();
-            contig_index.add_contig_range(
-                fields[0],
-                fidx,
-                fields[1].parse::<u64>().unwrap(),
-                fields[2].parse::<u64>().unwrap(),
-                virt_pos,
-            );
+        contig_index
+            .bgz_files
+            .push(match contig_index.deterministic {
+                true => BGZFile {
+                    name: filename.clone(),
+                    mod_time: 0.0,
+                    content_hash: Some(content_hash(&bgz_file)?),
+                    bytes: file_size,
+                },
+                false => BGZFile {
+                    name: filename.clone(),
+                    mod_time: mod_time.as_secs_f64(),
+                    content_hash: None,
+                    bytes: file_size,
+                },
+            });
+
+        let mut records = 0;
+        let mut positions = Vec::new();
+        for record in BedRecordStream::open(&bgz_file).unwrap() {
+            let record = record.unwrap();
+            records += 1;
+            let fields = record.fields();
+            // Taken before the line is read, where a seek to it starts reading
+            let virtual_position = record.virtual_position.unwrap();
+            let (start_bp, end_bp) = bed_range(&fields).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid Coordinates In {} Line {}, chromStart Must Be Below chromEnd - {}",
+                        bgz_file,
+                        records,
+                        record.line.trim_end()
+                    ),
+                )
+            })?;
+            contig_index.add_contig_range(fields[0], fidx, start_bp, end_bp, virtual_position);
+            if contig_index.verify_positions {
+                positions.push((virtual_position, line_hash(&record.line)));
+            }
 
             // TODO: flush when memory fills.  IGD saves each tile to a file and appends data as it
             // continues.
             // I didn't initially implement this because our use-case doesn't typically challenge the
             // memory of most systems.
-
-            line.clear();
-            virt_pos = u64::from(reader.virtual_position());
         }
+        if contig_index.verify_positions {
+            contig_index.verified_records += verify_record_positions(&bgz_file, &positions)?;
+        }
+        if records == 0 {
+            warn!(
+                "BGZ File {} Has No Records, Indexed Without Ranges",
+                bgz_file
+            );
+            contig_index.empty_files.push(filename.clone());
+        }
+        listing.push((
+            stored_accession(filename).to_string(),
+            records,
+            file_size,
+            fidx,
+        ));
         fidx += 1;
+        if let Some(BuildProgress(progress)) = &contig_index.build_progress {
+            progress(fidx as u64);
+        }
     }
 
-    let _ = contig_index.save_index(&index_file);
+    let stats = contig_index.build_stats();
+    info!(
+        "Indexed {} ranges, mean span {:.2} tiles, max span {} tiles, duplication {:.2}, {} in overflow lists",
+        stats.ranges,
+        stats.mean_span_tiles(),
+        stats.max_span_tiles,
+        stats.duplication_factor(),
+        stats.overflow_ranges
+    );
+    if let Some(warning) = contig_index.duplication_warning() {
+        warn!("{}", warning);
+    }
+
+    let versioned = versioned_index_file(index_file);
+    if let Err(e) = contig_index.save_index(&versioned) {
+        let _ = fs::remove_file(&versioned);
+        return Err(Box::new(e));
+    }
+    publish_index(&versioned, index_file)?;
+    write_family_listing(&family_listing_file(index_file), &mut listing)?;
     Ok(())
 }
 
-#[allow(dead_code)]
+// The chromStart and chromEnd of a BED record, None unless both are numbers and the record
+// covers at least one base. Files of data types prepared before BED coordinates, see
+// records::bed_coordinates, have minus strand records with start after end and fail here.
+fn bed_range(fields: &[&str]) -> Option<(u64, u64)> {
+    let start_bp = fields.get(1)?.parse::<u64>().ok()?;
+    let end_bp = fields.get(2)?.parse::<u64>().ok()?;
+    (start_bp < end_bp).then_some((start_bp, end_bp))
+}
+
+// The first 8 bytes of the SHA-256 of a bgz file, as a little-endian u64, which a
+// deterministic index stores for each file in place of its modification time
+pub fn content_hash(bgz_file: &str) -> io::Result<u64> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(bgz_file)?, &mut hasher)?;
+    Ok(u64::from_le_bytes(
+        hasher.finalize()[0..8].try_into().unwrap(),
+    ))
+}
+
+pub fn line_hash(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Seeks to each virtual position of a bgz file, in turn, and checks the record read there has
+// the line_hash given for it. positions are (virtual position, line_hash) of the file's
+// records. Returns the records checked.
+pub fn verify_record_positions(bgz_file: &str, positions: &[(u64, u64)]) -> io::Result<u64> {
+    let mut records = BedRecordStream::open(bgz_file)?;
+    for (n, (position, hash)) in positions.iter().enumerate() {
+        records.seek(*position)?;
+        let record = records.read_record()?;
+        if record.as_ref().map(|r| line_hash(&r.line)) != Some(*hash) {
+            let pos = bgzf::VirtualPosition::from(*position);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Record {} Of {} Reads Back Differently At Virtual Position {} ({}:{}), Found {:?}",
+                    n + 1,
+                    bgz_file,
+                    position,
+                    pos.compressed(),
+                    pos.uncompressed(),
+                    record.map(|r| r.line)
+                ),
+            ));
+        }
+    }
+    Ok(positions.len() as u64)
+}
+
+// Format version of an index file, from its header
+pub fn index_format_version(index_file: impl AsRef<Path>) -> io::Result<u16> {
+    let index_file = &path_string(index_file);
+    let mut file = File::open(index_file)?;
+    let mut magic = [0; 6];
+    file.read_exact(&mut magic)?;
+    if magic != MAGIC_NUMBER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} Is Not An Index File", index_file),
+        ));
+    }
+    read_u16_from_file(&mut file)
+}
+
+// Reads the header of an index and checks its bgz file names are unique and the file is as
+// long as its range counts say, the range data itself is not read. Returns the format version.
+pub fn check_index(index_file: impl AsRef<Path>) -> io::Result<u16> {
+    let index_file = &path_string(index_file);
+    let version = index_format_version(index_file)?;
+    let mut contig_index = ContigIndex::new(true);
+    let mut file = File::open(index_file)?;
+    contig_index.read_header(&mut file)?;
+    contig_index.check_file_names(index_file)?;
+    let header_end = file.stream_position()?;
+    let range_bytes: u64 = contig_index
+        .range_counts
+        .iter()
+        .flatten()
+        .map(|count| *count as u64 * RANGE_RECORD_BYTES as u64)
+        .sum();
+    let expected = match version {
+        0 => range_data_offsets(
+            contig_index.bgz_files.len() as u32,
+            &contig_index.tile_counts,
+            &[vec![0]],
+        )[0][0],
+        _ => header_end,
+    } + range_bytes;
+    let length = file.metadata()?.len();
+    if length != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Index {} Is {} Bytes, Its Header Describes {}",
+                index_file, length, expected
+            ),
+        ));
+    }
+    Ok(version)
+}
+
+// Ranges stored in an index, counted from its header: once per tile for ranges spanning
+// several tiles, once for those in an overflow list
+pub fn index_range_count(index_file: impl AsRef<Path>) -> io::Result<u64> {
+    let index_file = &path_string(index_file);
+    let mut contig_index = ContigIndex::new(true);
+    let mut file = File::open(index_file)?;
+    contig_index.read_header(&mut file)?;
+    let tiled: u64 = contig_index
+        .range_counts
+        .iter()
+        .flatten()
+        .map(|count| *count as u64)
+        .sum();
+    let overflow: u64 = contig_index
+        .overflow_ranges
+        .iter()
+        .map(|ranges| ranges.len() as u64)
+        .sum();
+    Ok(tiled + overflow)
+}
+
+// Whether an index holds records of a contig, from its header
+pub fn index_has_contig(index_file: impl AsRef<Path>, contig: &str) -> io::Result<bool> {
+    let index_file = &path_string(index_file);
+    let mut contig_index = ContigIndex::new(true);
+    let (mut file, _) = open_index(index_file, &contig_index.open_retry)?;
+    contig_index.read_header(&mut file)?;
+    Ok(contig_index.contig_lookup.contains_key(contig))
+}
+
+// Every tile's ranges, the overflow lists and the file table of an index, as read back for
+// comparison
+type IndexContents = (
+    u32,
+    Vec<(String, Vec<Vec<ContigRange>>, Vec<ContigRange>)>,
+    Vec<(String, u64, u64)>,
+);
+
+// Reads a whole index of any supported format version into memory, including the ranges
+// searches read per tile, ready to be saved again
+fn read_whole_index(index_file: &str) -> io::Result<ContigIndex> {
+    let mut contig_index = ContigIndex::new(false);
+    let mut file = File::open(index_file)?;
+    contig_index.read_header(&mut file)?;
+    let mut names = vec![String::new(); contig_index.contig_count as usize];
+    for (name, id) in &contig_index.contig_lookup {
+        names[*id as usize] = name.to_string();
+    }
+    for (id, name) in names.into_iter().enumerate() {
+        let mut contig = Contig::new(&name);
+        contig.tile_count = contig_index.tile_counts[id] as usize;
+        let mut window = None;
+        for tile in 0..contig.tile_count {
+            if contig_index.range_counts[id][tile] == 0 {
+                continue;
+            }
+            let loaded = contig_index.load_tile(
+                &mut file,
+                &mut window,
+                id as u32,
+                tile,
+                contig.tile_count - 1,
+            )?;
+            contig.contig_tiles.insert(tile, loaded);
+        }
+        contig.overflow_ranges = contig_index.overflow_ranges[id].clone();
+        contig_index.contigs.push(contig);
+    }
+    Ok(contig_index)
+}
+
+fn index_contents(contig_index: &ContigIndex) -> IndexContents {
+    (
+        contig_index.tile_size,
+        contig_index
+            .contigs
+            .iter()
+            .map(|contig| {
+                let tiles = (0..contig.tile_count)
+                    .map(|tile| {
+                        let mut ranges = contig.tile_ranges(tile).to_vec();
+                        ranges.sort_by_key(|r| r.start_bp);
+                        ranges
+                    })
+                    .collect();
+                let mut overflow = contig.overflow_ranges.clone();
+                overflow.sort_by_key(|r| r.start_bp);
+                (contig.name.to_string(), tiles, overflow)
+            })
+            .collect(),
+        contig_index
+            .bgz_files
+            .iter()
+            .map(|f| {
+                let stamp = f.content_hash.unwrap_or(f.mod_time.to_bits());
+                (f.name.to_string(), stamp, f.bytes)
+            })
+            .collect(),
+    )
+}
+
+// Rewrites an index in the current format version to out_file, or OVERFLOW_FORMAT_VERSION for
+// one with overflow lists and HASHED_FORMAT_VERSION for a deterministic one, without reading
+// the bgz files it points at. The recorded file sizes and modification times or hashes are kept, so changes made to the bgz files before the rewrite are
+// still reported afterwards. The new file is read back and must hold the same contigs, ranges
+// and files as the old one.
+pub fn upgrade_index(
+    index_file: impl AsRef<Path>,
+    out_file: impl AsRef<Path>,
+) -> Result<(), Box<dyn Error>> {
+    let index_file = &path_string(index_file);
+    let out_file = &path_string(out_file);
+    let contig_index = read_whole_index(index_file)?;
+    contig_index.save_index(out_file)?;
+    if index_contents(&read_whole_index(out_file)?) != index_contents(&contig_index) {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Upgraded Index {} Does Not Match {}", out_file, index_file),
+        )));
+    }
+    Ok(())
+}
+
+#[allow(dead_code, clippy::too_many_arguments)]
 pub fn search_idx(
     filenames: &Vec<String>,
-    bgz_dir: &String,
+    bgz_dir: impl AsRef<Path>,
     contig_index: &mut ContigIndex,
-    index_file: &String,
-    q_contig: &String,
+    index_file: impl AsRef<Path>,
+    q_contig: &str,
     start: u64,
     end: u64,
-    family: &Option<String>,
+    family: &Option<Vec<String>>,
     nrph: bool,
+    strand: &Option<String>,
+    min_bit_score: Option<f64>,
+    max_e_value: Option<f64>,
+    timeout: Option<Duration>,
+    cancel: &Option<Arc<AtomicBool>>,
+    strict: bool,
     prod: bool,
 ) -> Result<Vec<String>, Box<dyn Error>> {
+    let bgz_dir = &path_string(bgz_dir);
+    let index_file = &path_string(index_file);
+    check_region(start, end, None)?;
     // log::set_logger(&MY_LOGGER).unwrap();
     if prod {
         log::set_max_level(LevelFilter::Warn);
@@ -908,44 +2702,830 @@ pub fn search_idx(
     }
 
     debug!("Loading index");
-    contig_index.init_search(&index_file);
+    let mut i_file = contig_index.init_search(index_file)?;
 
-    // Sanity checking index vs file system
-    let mut f_lookup = HashSet::new();
-    for filename in filenames {
-        f_lookup.insert(filename);
+    if !contig_index.assume_immutable {
+        contig_index.check_bgz_files(filenames, bgz_dir);
     }
-    for ifile in &contig_index.bgz_files {
-        if !f_lookup.contains(&ifile.name) {
-            warn!("It appears that {} has been deleted from the alignments folder since the index was created!", ifile.name);
-        } else {
-            f_lookup.remove(&ifile.name);
-            let bgz_file = format!("{}/{}", bgz_dir, ifile.name);
-            // Get metadata for the file
-            let metadata = fs::metadata(bgz_file.clone()).unwrap();
-            // Obtain modification time
-            let modification_time = metadata.modified().unwrap();
-            // Convert modification time to a more readable format
-            let mod_time = modification_time
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap();
-            // Obtain file size
-            let file_size = metadata.len();
-            if file_size != ifile.bytes {
-                warn!("It appears that {} has been modified since the index was created. Byte size difference index={}, file={}", ifile.name, ifile.bytes, file_size);
-            } else if mod_time.as_secs_f64() != ifile.mod_time {
-                warn!("It appears that {} has been modified since the index was created. Modification time difference index={:?}, file={:?}", ifile.name, ifile.mod_time, mod_time);
-            }
-        }
-    }
-    for fsfile in &f_lookup {
+    debug!(
+        "{} Metadata Calls Took {:?}",
+        contig_index.metadata_stats.calls, contig_index.metadata_stats.time
+    );
+    if contig_index.metadata_stats.open_retries > 0 {
+        let retries = contig_index.metadata_stats.open_retries;
+        contig_index.file_warnings.push(format!(
+            "The index was opened after {} retries, it was being replaced",
+            retries
+        ));
+    }
+    debug!("Searching...");
+    let mut skipped = HashMap::new();
+    let results = contig_index.search(
+        &mut i_file,
+        bgz_dir,
+        q_contig,
+        start,
+        end,
+        family,
+        nrph,
+        strand,
+        min_bit_score,
+        max_e_value,
+        timeout,
+        cancel,
+        strict,
+        &mut skipped,
+        contig_index.chunk_size,
+    );
+    let mut skipped_ranges: Vec<(String, usize)> = skipped
+        .into_iter()
+        .map(|(bed_idx, ranges)| {
+            (
+                contig_index.bgz_files[bed_idx as usize].name.clone(),
+                ranges,
+            )
+        })
+        .collect();
+    skipped_ranges.sort();
+    for (name, ranges) in &skipped_ranges {
+        warn!("Skipped {} Matching Ranges Of Missing {}", ranges, name);
+    }
+    contig_index.skipped_ranges = skipped_ranges;
+    results
+}
+
+// Searches without the index, for when it is missing or cannot be read: every bgz file of the
+// folder that can hold the query families is read whole, on as many threads as there are
+// cores, and its records on q_contig filtered as search_idx filters them, so the results are
+// those of an indexed search in the same order. The bias and version settings are those of
+// contig_index. Returns the results and the number of files read. Much slower than searching
+// the index, and neither timed out nor cancelled.
+#[allow(clippy::too_many_arguments)]
+pub fn scan_search(
+    contig_index: &ContigIndex,
+    bgz_dir: &str,
+    q_contig: &str,
+    q_start: u64,
+    q_end: u64,
+    q_family: &Option<Vec<String>>,
+    q_nrph: bool,
+    q_strand: &Option<String>,
+    q_min_bit_score: Option<f64>,
+    q_max_e_value: Option<f64>,
+) -> io::Result<(Vec<String>, usize)> {
+    // Zero-based and half-open as the ranges, see ContigIndex::search
+    let q_start = q_start.saturating_sub(1);
+    let q_family = &query_families(q_family);
+    let q_scores = ScoreThresholds {
+        min_bit_score: q_min_bit_score,
+        max_e_value: q_max_e_value,
+        max_bias: contig_index.max_bias,
+        strict_bias: contig_index.strict_bias,
+    };
+    let exact_version = contig_index.exact_version;
+    let names: Vec<String> = stored_bgz_names(bgz_dir)?
+        .into_iter()
+        .filter(|name| family_file(name, q_family, exact_version))
+        .collect();
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .clamp(1, names.len().max(1));
+    let next_file = AtomicUsize::new(0);
+    let unparsable = AtomicUsize::new(0);
+    let scan_files = || -> io::Result<Vec<String>> {
+        let mut results = Vec::new();
+        let mut file_unparsable = 0;
+        while let Some(name) = names.get(next_file.fetch_add(1, Ordering::Relaxed)) {
+            for record in BedRecordStream::open(&stored_path(bgz_dir, name))? {
+                let line = record?.line;
+                let fields = bed_fields(&line);
+                if fields[0] != q_contig {
+                    continue;
+                }
+                let Some((low, high)) = bed_range(&fields) else {
+                    continue;
+                };
+                // The ranges an indexed search reads, see ContigRange::overlaps
+                if low >= q_end || high <= q_start {
+                    continue;
+                }
+                if filter_line(
+                    &line,
+                    &q_start,
+                    q_family,
+                    exact_version,
+                    &q_nrph,
+                    q_strand,
+                    &q_scores,
+                    &mut file_unparsable,
+                ) {
+                    results.push(line);
+                }
+            }
+        }
+        unparsable.fetch_add(file_unparsable, Ordering::Relaxed);
+        Ok(results)
+    };
+    let scanned: Vec<io::Result<Vec<String>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| scope.spawn(scan_files)).collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Scan Thread Panicked"))
+            .collect()
+    });
+    let mut results = Vec::new();
+    for scanned in scanned {
+        results.extend(scanned?);
+    }
+    sort_records(&mut results, |_| 0);
+    let unparsable = unparsable.into_inner();
+    if unparsable > 0 {
         warn!(
-            "It appears that {} has been added since the index was created!",
-            fsfile
+            "{} records with an unparsable score were left out by the score thresholds",
+            unparsable
         );
     }
-    let mut i_file = File::open(index_file).unwrap();
-    debug!("Searching...");
-    let results = contig_index.search(&mut i_file, &bgz_dir, &q_contig, start, end, family, nrph);
-    return results;
+    Ok((results, names.len()))
+}
+
+// Every record of whole contigs in RecordOrder, read lazily a tile at a time so callers can
+// stop early. Ranges are stored in every tile they overlap, in later tiles the ones starting
+// before the tile were already returned and are skipped. The records of a tile are read and
+// sorted together. Readers are kept per bgz file for the life of the iterator.
+pub struct RecordIter {
+    contig_index: ContigIndex,
+    i_file: File,
+    bgz_dir: String,
+    family: Option<Vec<String>>, // query_families of the family asked for
+    nrph: bool,
+    contigs: Vec<u32>,
+    next_contig: usize,
+    contig: Option<u32>,
+    tile: usize,
+    ranges: std::vec::IntoIter<ContigRange>,
+    records: std::vec::IntoIter<String>,
+    window: Option<TileWindow>,
+    readers: HashMap<u32, BedRecordStream<bgzf::Reader<File>>>,
+}
+
+impl RecordIter {
+    // Ranges of the next non-empty tile that start in it, false once every contig is done
+    fn next_tile(&mut self) -> io::Result<bool> {
+        loop {
+            let Some(contig) = self.contig else {
+                let Some(contig) = self.contigs.get(self.next_contig) else {
+                    return Ok(false);
+                };
+                self.contig = Some(*contig);
+                self.next_contig += 1;
+                self.tile = 0;
+                self.window = None;
+                continue;
+            };
+            let tile_count = self.contig_index.tile_counts[contig as usize] as usize;
+            if self.tile >= tile_count {
+                self.contig = None;
+                continue;
+            }
+            let tile = self.tile;
+            self.tile += 1;
+            let tile_size = self.contig_index.tile_size as u64;
+            let tile_start_bp = tile as u64 * tile_size;
+            // Overflow ranges are returned with the tile they start in
+            let overflow = &self.contig_index.overflow_ranges[contig as usize];
+            let first = overflow.partition_point(|range| range.start_bp < tile_start_bp);
+            let last = overflow.partition_point(|range| range.start_bp < tile_start_bp + tile_size);
+            if self.contig_index.range_counts[contig as usize][tile] == 0 && first == last {
+                continue;
+            }
+            let mut ranges: Vec<ContigRange> = overflow[first..last].to_vec();
+            if self.contig_index.range_counts[contig as usize][tile] > 0 {
+                ranges.extend(
+                    self.contig_index
+                        .load_tile(
+                            &mut self.i_file,
+                            &mut self.window,
+                            contig,
+                            tile,
+                            tile_count - 1,
+                        )?
+                        .contig_ranges
+                        .into_iter()
+                        .filter(|range| tile == 0 || range.start_bp >= tile_start_bp),
+                );
+            }
+            if first < last {
+                ranges.sort_by_key(|range| range.start_bp);
+            }
+            if !ranges.is_empty() {
+                self.ranges = ranges.into_iter();
+                return Ok(true);
+            }
+        }
+    }
+
+    fn read_record(&mut self, range: &ContigRange) -> io::Result<String> {
+        let name = &self.contig_index.bgz_files[range.bed_idx as usize].name;
+        let records = match self.readers.entry(range.bed_idx) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(BedRecordStream::open(&stored_path(&self.bgz_dir, name))?)
+            }
+        };
+        // Records of a contig are mostly consecutive in their file, only seek when they are not.
+        // Positions are compared as build_idx recorded them, see BedRecordStream::virtual_position.
+        if records.virtual_position() != Some(range.bgzf_pos) {
+            records.seek(range.bgzf_pos)?;
+        }
+        match records.read_record()? {
+            Some(record) => Ok(record.line.trim_end_matches('\n').to_string()),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "No Record At Virtual Position {} Of {}",
+                    range.bgzf_pos, name
+                ),
+            )),
+        }
+    }
+
+    // The records of the ranges of the tile that pass the filters, in RecordOrder
+    fn read_tile(&mut self) -> io::Result<Vec<String>> {
+        let mut records = Vec::new();
+        for range in std::mem::take(&mut self.ranges) {
+            // As in searches, a family only needs the ranges of its own files
+            if !family_file(
+                &self.contig_index.bgz_files[range.bed_idx as usize].name,
+                &self.family,
+                self.contig_index.exact_version,
+            ) {
+                continue;
+            }
+            let line = self.read_record(&range)?;
+            if filter_line(
+                &line,
+                &0,
+                &self.family,
+                self.contig_index.exact_version,
+                &self.nrph,
+                &None,
+                &ScoreThresholds::default(),
+                &mut 0,
+            ) {
+                records.push(line);
+            }
+        }
+        sort_records(&mut records, |_| 0);
+        Ok(records)
+    }
+}
+
+impl Iterator for RecordIter {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        loop {
+            if let Some(line) = self.records.next() {
+                return Some(Ok(line));
+            }
+            match self.next_tile() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+            match self.read_tile() {
+                Ok(records) => self.records = records.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// Iterates the records of the given contigs of a data type, in that order. Contigs missing
+// from the index are an error, or skipped with skip_missing.
+pub fn iter_records(
+    assembly: &str,
+    data_type: &str,
+    contigs: &[String],
+    skip_missing: bool,
+    family: &Option<String>,
+    nrph: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<RecordIter, Box<dyn Error>> {
+    let data_directory = &path_string(data_directory);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, true, data_directory)?;
+    let mut i_file = File::open(&index_file)?;
+    contig_index.read_header(&mut i_file)?;
+    contig_index.check_file_names(&index_file)?;
+    contig_index.check_bgz_files(&filenames, &bgz_dir);
+    let mut contig_ids = Vec::new();
+    for contig in contigs {
+        match contig_index.contig_lookup.get(contig) {
+            Some(id) => contig_ids.push(*id),
+            None if skip_missing => {}
+            None => return Err(format!("Contig {} Not Found", contig).into()),
+        }
+    }
+    Ok(RecordIter {
+        contig_index,
+        i_file,
+        bgz_dir,
+        family: query_families(&family.clone().map(|family| vec![family])),
+        nrph,
+        contigs: contig_ids,
+        next_contig: 0,
+        contig: None,
+        tile: 0,
+        ranges: Vec::new().into_iter(),
+        records: Vec::new().into_iter(),
+        window: None,
+        readers: HashMap::new(),
+    })
+}
+
+// Intervals of a data type whose records are plain intervals, such as masks, overlapping
+// low..=high of a contig, 1-based and fully closed as queries give regions. They come from the
+// range data of the index alone, read through the same tile windows and contig lookup as a
+// search, without opening any bgz file. Returned 1-based and fully closed too, (start, end)
+// per record, in no particular order and possibly repeated. A contig the index does not have
+// has none.
+pub fn search_intervals(
+    assembly: &str,
+    data_type: &str,
+    q_contig: &str,
+    low: u64,
+    high: u64,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<(u64, u64)>, Box<dyn Error>> {
+    let data_directory = &path_string(data_directory);
+    let (_, _, mut contig_index, index_file) = prep_idx(assembly, data_type, true, data_directory)?;
+    let mut i_file = File::open(&index_file)?;
+    contig_index.read_header(&mut i_file)?;
+    let contig = match contig_index.contig_lookup.get(q_contig) {
+        Some(id) => *id,
+        None => return Ok(Vec::new()),
+    };
+    let tile_count = contig_index.tile_counts[contig as usize] as usize;
+    let tile_size = contig_index.tile_size as u64;
+    // A range is kept in the tiles of start_bp to end_bp - 1, base low is zero-based low - 1
+    let first_tile = (low.saturating_sub(1) / tile_size) as usize;
+    let last_tile = ((high / tile_size) as usize).min(tile_count.saturating_sub(1));
+    let mut intervals = Vec::new();
+    let mut window = None;
+    for tile in first_tile..=last_tile.max(first_tile) {
+        if tile >= tile_count || contig_index.range_counts[contig as usize][tile] == 0 {
+            continue;
+        }
+        let ranges = contig_index
+            .load_tile(&mut i_file, &mut window, contig, tile, last_tile)?
+            .contig_ranges;
+        intervals.extend(
+            ranges
+                .iter()
+                .filter(|range| range.start_bp < high && range.end_bp >= low)
+                .map(|range| (range.start_bp + 1, range.end_bp)),
+        );
+    }
+    intervals.extend(
+        contig_index.overflow_ranges[contig as usize]
+            .iter()
+            .filter(|range| range.start_bp < high && range.end_bp >= low)
+            .map(|range| (range.start_bp + 1, range.end_bp)),
+    );
+    Ok(intervals)
+}
+
+// A range drawn by sample_ranges, named by its contig and file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledRange {
+    pub contig: String,
+    pub file: String,
+    pub start_bp: u64,
+    pub end_bp: u64,
+    pub bgzf_pos: u64,
+}
+
+// Splitmix64, a seeded generator for sampling without extra dependencies
+struct SampleRng(u64);
+
+impl SampleRng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in 0..n
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+// Draws up to n distinct ranges of a data type's index uniformly at random, the same ones for
+// the same seed and index. A tile is picked in proportion to its range count, from the header
+// alone, then a range within it, reading only the tiles picked. A range spanning several tiles
+// is stored in each, so a draw only counts in the tile the range starts in and is otherwise
+// drawn again, which keeps every range equally likely. A contig's overflow list is drawn from
+// as one more tile. When n is most of the index, or draws keep landing on ranges already
+// taken, every range is read and n of them chosen instead. Returned in index order.
+pub fn sample_ranges(
+    assembly: &str,
+    data_type: &str,
+    n: usize,
+    seed: u64,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<SampledRange>, Box<dyn Error>> {
+    let data_directory = &path_string(data_directory);
+    let (_, _, mut contig_index, index_file) = prep_idx(assembly, data_type, true, data_directory)?;
+    let mut i_file = File::open(&index_file)?;
+    contig_index.read_header(&mut i_file)?;
+    let tile_size = contig_index.tile_size as u64;
+
+    // (contig, tile or None for the overflow list, ranges up to and including it)
+    let mut strata: Vec<(u32, Option<usize>, u64)> = Vec::new();
+    let mut total = 0;
+    for contig in 0..contig_index.contig_count {
+        let counts = &contig_index.range_counts[contig as usize];
+        for (tile, count) in counts.iter().enumerate().filter(|(_, c)| **c > 0) {
+            total += *count as u64;
+            strata.push((contig, Some(tile), total));
+        }
+        let overflow = contig_index.overflow_ranges[contig as usize].len() as u64;
+        if overflow > 0 {
+            total += overflow;
+            strata.push((contig, None, total));
+        }
+    }
+    if n == 0 || total == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut tiles: HashMap<(u32, usize), Vec<ContigRange>> = HashMap::new();
+    let mut load = |contig_index: &ContigIndex,
+                    i_file: &mut File,
+                    contig: u32,
+                    tile: usize|
+     -> io::Result<Vec<ContigRange>> {
+        if let Some(ranges) = tiles.get(&(contig, tile)) {
+            return Ok(ranges.clone());
+        }
+        let ranges = contig_index
+            .load_tile(i_file, &mut None, contig, tile, tile)?
+            .contig_ranges;
+        tiles.insert((contig, tile), ranges.clone());
+        Ok(ranges)
+    };
+    let starts_in = |range: &ContigRange, tile: usize| range.low() / tile_size == tile as u64;
+
+    let mut rng = SampleRng(seed);
+    let mut taken: Vec<(u32, ContigRange)> = Vec::new();
+    let mut seen: HashSet<(u32, u64)> = HashSet::new();
+    // Beyond half the stored ranges rejection would mostly draw ranges already taken
+    let mut attempts = match (n as u64).saturating_mul(2) < total {
+        true => (n as u64).saturating_mul(20) + 1000,
+        false => 0,
+    };
+    while taken.len() < n && attempts > 0 {
+        attempts -= 1;
+        let draw = rng.below(total);
+        let stratum = strata.partition_point(|(_, _, end)| *end <= draw);
+        let (contig, tile, _) = strata[stratum];
+        let first = if stratum == 0 {
+            0
+        } else {
+            strata[stratum - 1].2
+        };
+        let offset = (draw - first) as usize;
+        let range = match tile {
+            Some(tile) => {
+                let ranges = load(&contig_index, &mut i_file, contig, tile)?;
+                match ranges.get(offset) {
+                    Some(range) if starts_in(range, tile) => range.clone(),
+                    _ => continue,
+                }
+            }
+            None => contig_index.overflow_ranges[contig as usize][offset].clone(),
+        };
+        if seen.insert((range.bed_idx, range.bgzf_pos)) {
+            taken.push((contig, range));
+        }
+    }
+    if taken.len() < n {
+        // Every range once, from the tile it starts in, then a partial shuffle picks n
+        let mut all = Vec::new();
+        for (contig, tile, _) in &strata {
+            match tile {
+                Some(tile) => all.extend(
+                    load(&contig_index, &mut i_file, *contig, *tile)?
+                        .into_iter()
+                        .filter(|range| starts_in(range, *tile))
+                        .map(|range| (*contig, range)),
+                ),
+                None => all.extend(
+                    contig_index.overflow_ranges[*contig as usize]
+                        .iter()
+                        .map(|range| (*contig, range.clone())),
+                ),
+            }
+        }
+        let keep = n.min(all.len());
+        for i in 0..keep {
+            let j = i + rng.below((all.len() - i) as u64) as usize;
+            all.swap(i, j);
+        }
+        all.truncate(keep);
+        taken = all;
+    }
+
+    let mut names = vec![String::new(); contig_index.contig_count as usize];
+    for (name, id) in &contig_index.contig_lookup {
+        names[*id as usize] = name.to_string();
+    }
+    taken.sort_by(|a, b| (a.0, a.1.low(), &a.1).cmp(&(b.0, b.1.low(), &b.1)));
+    Ok(taken
+        .into_iter()
+        .map(|(contig, range)| SampledRange {
+            contig: names[contig as usize].clone(),
+            file: contig_index
+                .bgz_files
+                .get(range.bed_idx as usize)
+                .map_or(String::new(), |f| f.name.clone()),
+            start_bp: range.start_bp,
+            end_bp: range.end_bp,
+            bgzf_pos: range.bgzf_pos,
+        })
+        .collect())
+}
+
+// Writes the ranges of an index as TSV, every tile of every contig or only those of contig
+// and tile, one window of tiles read at a time as searches do. With summary only the number
+// of ranges in each tile is written, empty tiles included. A range spanning several tiles is
+// listed in each. A contig's overflow list follows its tiles, with overflow in the tile
+// column, unless a tile was asked for. Returns the number of lines written after the header.
+pub fn dump_index(
+    assembly: &str,
+    data_type: &str,
+    contig: &Option<String>,
+    tile: &Option<usize>,
+    summary: bool,
+    writer: &mut impl Write,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize, Box<dyn Error>> {
+    let data_directory = &path_string(data_directory);
+    let (_, _, mut contig_index, index_file) = prep_idx(assembly, data_type, true, data_directory)?;
+    let mut i_file = File::open(&index_file)?;
+    contig_index.read_header(&mut i_file)?;
+    let mut names = vec![String::new(); contig_index.contig_count as usize];
+    for (name, id) in &contig_index.contig_lookup {
+        names[*id as usize] = name.to_string();
+    }
+    let contig_ids: Vec<u32> = match contig {
+        Some(name) => match contig_index.contig_lookup.get(name) {
+            Some(id) => vec![*id],
+            None => return Err(format!("Contig {} Not Found In {}", name, index_file).into()),
+        },
+        None => (0..contig_index.contig_count).collect(),
+    };
+    if let (Some(name), Some(tile)) = (contig, tile) {
+        let tile_count = contig_index.tile_counts[contig_ids[0] as usize] as usize;
+        if *tile >= tile_count {
+            return Err(format!(
+                "Tile {} Out Of Range, Contig {} Has {} Tiles",
+                tile, name, tile_count
+            )
+            .into());
+        }
+    }
+
+    match summary {
+        true => writeln!(writer, "#contig\ttile\tranges")?,
+        false => writeln!(writer, "#contig\ttile\tstart\tend\tbed_idx\tfile\tbgzf_pos")?,
+    }
+    let mut lines = 0;
+    for id in contig_ids {
+        let tile_count = contig_index.tile_counts[id as usize] as usize;
+        let tiles = match tile {
+            Some(tile) if *tile < tile_count => *tile..*tile + 1,
+            Some(_) => continue,
+            None => 0..tile_count,
+        };
+        let name = &names[id as usize];
+        let last_tile = tiles.end.saturating_sub(1);
+        let mut window = None;
+        for t in tiles {
+            if summary {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}",
+                    name, t, contig_index.range_counts[id as usize][t]
+                )?;
+                lines += 1;
+                continue;
+            }
+            if contig_index.range_counts[id as usize][t] == 0 {
+                continue;
+            }
+            let ranges = contig_index
+                .load_tile(&mut i_file, &mut window, id, t, last_tile)?
+                .contig_ranges;
+            for range in ranges {
+                let file = contig_index
+                    .bgz_files
+                    .get(range.bed_idx as usize)
+                    .map_or("", |f| f.name.as_str());
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    name, t, range.start_bp, range.end_bp, range.bed_idx, file, range.bgzf_pos
+                )?;
+                lines += 1;
+            }
+        }
+        let overflow = &contig_index.overflow_ranges[id as usize];
+        if tile.is_some() || overflow.is_empty() {
+            continue;
+        }
+        if summary {
+            writeln!(writer, "{}\toverflow\t{}", name, overflow.len())?;
+            lines += 1;
+            continue;
+        }
+        for range in overflow {
+            let file = contig_index
+                .bgz_files
+                .get(range.bed_idx as usize)
+                .map_or("", |f| f.name.as_str());
+            writeln!(
+                writer,
+                "{}\toverflow\t{}\t{}\t{}\t{}\t{}",
+                name, range.start_bp, range.end_bp, range.bed_idx, file, range.bgzf_pos
+            )?;
+            lines += 1;
+        }
+    }
+    Ok(lines)
+}
+
+// An index loaded once and kept open for many queries. The index file is re-stated at most
+// once per check interval and reloaded when its size or modification time changed, so a
+// release replacing the files is picked up without reopening the handle. Releases should
+// replace the index by renaming a new file over it: queries already running keep the
+// previous index and its open file, new queries only see the new one once fully loaded.
+pub struct IndexHandle {
+    assembly: String,
+    data_type: String,
+    data_directory: String,
+    check_interval: Duration,
+    loaded: RwLock<Arc<LoadedIndex>>,
+    last_check: Mutex<Instant>,
+    generation: AtomicU64,
+    chunk_size: u64,
+}
+
+struct LoadedIndex {
+    contig_index: ContigIndex,
+    bgz_dir: String,
+    i_file: Mutex<File>,
+    stamp: (SystemTime, u64),
+}
+
+fn load_index(
+    assembly: &str,
+    data_type: &str,
+    data_directory: &str,
+) -> Result<LoadedIndex, Box<dyn Error>> {
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, true, data_directory)?;
+    let (mut i_file, _) = open_index(&index_file, &contig_index.open_retry)?;
+    // Stat the open file rather than the path, the path may be replaced again meanwhile
+    let metadata = i_file.metadata()?;
+    contig_index.read_header(&mut i_file)?;
+    contig_index.check_file_names(&index_file)?;
+    contig_index.check_bgz_files(&filenames, &bgz_dir);
+    Ok(LoadedIndex {
+        contig_index,
+        bgz_dir,
+        i_file: Mutex::new(i_file),
+        stamp: (metadata.modified()?, metadata.len()),
+    })
+}
+
+impl IndexHandle {
+    pub fn open(
+        assembly: &str,
+        data_type: &str,
+        data_directory: impl AsRef<Path>,
+        check_interval: Duration,
+    ) -> Result<IndexHandle, Box<dyn Error>> {
+        let data_directory = &path_string(data_directory);
+        check_allowed(assembly, Some(data_type), data_directory)?;
+        let loaded = load_index(assembly, data_type, data_directory)?;
+        Ok(IndexHandle {
+            assembly: assembly.to_string(),
+            data_type: data_type.to_string(),
+            data_directory: data_directory.to_string(),
+            check_interval,
+            loaded: RwLock::new(Arc::new(loaded)),
+            last_check: Mutex::new(Instant::now()),
+            generation: AtomicU64::new(0),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        })
+    }
+
+    // Span searched at once, see ContigIndex::set_chunk_size
+    pub fn set_chunk_size(&mut self, chunk_size: u64) {
+        self.chunk_size = chunk_size;
+    }
+
+    // Number of reloads since the handle was opened
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    // Reloads the index if the file changed since it was loaded. Only one caller stats and
+    // reloads at a time, the others carry on with the current index.
+    fn refresh(&self) {
+        let mut last_check = match self.last_check.try_lock() {
+            Ok(last_check) => last_check,
+            Err(_) => return,
+        };
+        if last_check.elapsed() < self.check_interval {
+            return;
+        }
+        *last_check = Instant::now();
+
+        let index_file = join_path(
+            &self.data_directory,
+            &[&self.assembly, &format!("{}_idx.dat", self.data_type)],
+        );
+        let stamp = match fs::metadata(&index_file).and_then(|m| Ok((m.modified()?, m.len()))) {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                warn!("Cannot Stat {}, Keeping Loaded Index - {:?}", index_file, e);
+                return;
+            }
+        };
+        if stamp == self.loaded.read().unwrap().stamp {
+            return;
+        }
+        match load_index(&self.assembly, &self.data_type, &self.data_directory) {
+            Ok(loaded) => {
+                *self.loaded.write().unwrap() = Arc::new(loaded);
+                let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+                info!("Reloaded {} (generation {})", index_file, generation);
+            }
+            Err(e) => warn!(
+                "Reloading {} Failed, Keeping Loaded Index - {:?}",
+                index_file, e
+            ),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn search(
+        &self,
+        q_contig: &str,
+        start: u64,
+        end: u64,
+        family: &Option<Vec<String>>,
+        nrph: bool,
+        strand: &Option<String>,
+        min_bit_score: Option<f64>,
+        max_e_value: Option<f64>,
+        timeout: Option<Duration>,
+        cancel: &Option<Arc<AtomicBool>>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        check_region(start, end, None)?;
+        self.refresh();
+        let loaded = Arc::clone(&self.loaded.read().unwrap());
+        let mut i_file = loaded.i_file.lock().unwrap();
+        let mut skipped = HashMap::new();
+        let results = loaded.contig_index.search(
+            &mut i_file,
+            &loaded.bgz_dir,
+            q_contig,
+            start,
+            end,
+            family,
+            nrph,
+            strand,
+            min_bit_score,
+            max_e_value,
+            timeout,
+            cancel,
+            false,
+            &mut skipped,
+            self.chunk_size,
+        );
+        for (bed_idx, ranges) in skipped {
+            warn!(
+                "Skipped {} Matching Ranges Of Missing {}",
+                ranges, loaded.contig_index.bgz_files[bed_idx as usize].name
+            );
+        }
+        results
+    }
 }
@@ -0,0 +1,189 @@
+use noodles::bgzf;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+use crate::idx::{self, bed_fields};
+use crate::paths::{join_path, path_string, stored_path};
+use crate::{column_count_fits, output_columns, ASSEMBLY_DIR};
+
+// Column layouts prepared BED files had before the current one, known by their number of
+// columns. columns names the current columns each holds, in its order, the ones it lacks are
+// read as empty. The current layout is output_columns.
+pub struct BedLayout {
+    pub name: &'static str,
+    pub data_type: &'static str,
+    pub columns: &'static [&'static str],
+}
+
+pub const LEGACY_LAYOUTS: [BedLayout; 1] = [BedLayout {
+    // Releases before alignments were kept, without the cigar and caf columns
+    name: "pre-caf",
+    data_type: ASSEMBLY_DIR,
+    columns: &[
+        "seq_id",
+        "seq_start",
+        "seq_end",
+        "family_accession",
+        "hit_bit_score",
+        "strand",
+        "bias",
+        "ali_start",
+        "ali_end",
+        "model_start",
+        "model_end",
+        "hit_evalue_score",
+        "nrph_hit",
+        "divergence",
+        "*family_name",
+        "seq_len",
+    ],
+}];
+
+impl BedLayout {
+    // The fields of a record of this layout in the current layout, missing ones empty
+    pub fn current_fields<'a>(&self, fields: &[&'a str]) -> Vec<&'a str> {
+        output_columns(self.data_type, false)
+            .iter()
+            .map(|column| {
+                self.columns
+                    .iter()
+                    .position(|c| c == column)
+                    .and_then(|i| fields.get(i).copied())
+                    .unwrap_or("")
+            })
+            .collect()
+    }
+}
+
+// The legacy layout of a record, if it has one. The count of columns has to match and the
+// record has to read sensibly in the layout: records that lost empty fields can have the same
+// count, and are misaligned rather than old, see misaligned_columns.
+pub fn detect_layout(fields: &[&str], data_type: &str) -> Option<&'static BedLayout> {
+    LEGACY_LAYOUTS.iter().find(|layout| {
+        if layout.data_type != data_type || layout.columns.len() != fields.len() {
+            return false;
+        }
+        let column = |name: &str| {
+            let i = layout.columns.iter().position(|c| *c == name);
+            i.and_then(|i| fields.get(i).copied())
+        };
+        let integer =
+            |name: &str| column(name).is_none_or(|f| f.is_empty() || f.parse::<u64>().is_ok());
+        column("strand").is_none_or(|f| ["+", "-"].contains(&f))
+            && column("nrph_hit").is_none_or(|f| ["0", "1"].contains(&f))
+            && ["seq_start", "seq_end", "ali_start", "ali_end", "seq_len"]
+                .iter()
+                .all(|name| integer(name))
+    })
+}
+
+pub const CURRENT_LAYOUT: &str = "current";
+
+// The layout of a BED file, by its first record: the name of a legacy layout, "current", or
+// None when the file is empty or its first record fits neither
+pub fn file_layout(path: &str, data_type: &str) -> Result<Option<&'static str>> {
+    let reader = BufReader::new(bgzf::Reader::new(File::open(path)?));
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields = bed_fields(&line);
+        if let Some(layout) = detect_layout(&fields, data_type) {
+            return Ok(Some(layout.name));
+        }
+        let current = column_count_fits(data_type, fields.len());
+        return Ok(current.then_some(CURRENT_LAYOUT));
+    }
+    Ok(None)
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct LayoutRepair {
+    pub files: usize,
+    pub repaired: BTreeMap<String, String>, // File name to the layout it was in
+    pub records: usize,                     // Records rewritten
+    pub indexed: bool,                      // Whether the index was rebuilt
+}
+
+// Rewrites the BED files of a data type that are in a legacy layout into the current one,
+// missing columns left empty, and rebuilds the index when any were. A file is rewritten whole
+// and replaces the old one by a rename, every record must be in the layout of its first.
+pub fn repair_layout(
+    assembly: &str,
+    data_type: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<LayoutRepair> {
+    let data_directory = &path_string(data_directory);
+    let bgz_dir = join_path(data_directory, &[assembly, data_type]);
+    if !Path::new(&bgz_dir).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Data \"{}\" Does Not Exist", bgz_dir),
+        ));
+    }
+    let mut names = idx::stored_bgz_names(&bgz_dir)?;
+    names.sort();
+
+    let mut report = LayoutRepair {
+        files: names.len(),
+        ..LayoutRepair::default()
+    };
+    for name in names {
+        let path = stored_path(&bgz_dir, &name);
+        let Some(layout) = file_layout(&path, data_type)? else {
+            continue;
+        };
+        let Some(layout) = LEGACY_LAYOUTS.iter().find(|l| l.name == layout) else {
+            continue;
+        };
+        let dir = Path::new(&path).parent().unwrap();
+        let mut out_f = tempfile::NamedTempFile::new_in(dir)?;
+        out_f
+            .as_file()
+            .set_permissions(fs::metadata(&path)?.permissions())?;
+        let mut writer = bgzf::Writer::new(out_f.as_file_mut());
+        let reader = BufReader::new(bgzf::Reader::new(File::open(&path)?));
+        for line in reader.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                writer.write_all(format!("{}\n", line).as_bytes())?;
+                continue;
+            }
+            let fields = bed_fields(&line);
+            if fields.len() != layout.columns.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} Is In The {} Layout Of {} Columns, But Has A Record Of {}: {}",
+                        name,
+                        layout.name,
+                        layout.columns.len(),
+                        fields.len(),
+                        line
+                    ),
+                ));
+            }
+            writer
+                .write_all(format!("{}\n", layout.current_fields(&fields).join("\t")).as_bytes())?;
+            report.records += 1;
+        }
+        writer.finish()?;
+        out_f.as_file().sync_all()?;
+        out_f.persist(&path).map_err(|e| e.error)?;
+        report.repaired.insert(name, layout.name.to_string());
+    }
+
+    if !report.repaired.is_empty() {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            idx::prep_idx(assembly, data_type, false, data_directory)
+                .map_err(|e| Error::other(e.to_string()))?;
+        idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+            .map_err(|e| Error::other(e.to_string()))?;
+        report.indexed = true;
+    }
+    Ok(report)
+}
@@ -1,50 +1,254 @@
+use flate2::{Compression, GzBuilder};
+use log::warn;
 use noodles::bgzf;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::fs::{copy, create_dir_all, read_to_string, File, OpenOptions};
-use std::io::{stdout, BufRead, BufReader, Result, Write};
+use sha2::{Digest, Sha256};
+use std::cell::OnceCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fs::{copy, create_dir_all, read_to_string, rename, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{stdout, BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
 use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
-use tempfile::tempfile;
+use std::time::{Duration, Instant, SystemTime};
+use tempfile::TempPath;
 use walkdir::WalkDir;
 
+use allowlist::{check_allowed, check_assembly, Allowlist};
+use backpressure::{BoundedWriter, BufferStats};
+use dl::DlWriter;
+use evalue::EValue;
+use migrate::{check_layout, read_assembly_meta};
+use options::{ActionOptions, FilterOptions, IdxQueryOptions, PrepOptions};
+use paths::{join_path, path_string, stored_path};
+use querylog::{logged, logged_with, QueryLogger};
+use records::{bed_extent, BedRecordStream};
+use status::StatusFile;
+
+pub mod allowlist;
+pub mod backpressure;
+pub mod benchmark;
+pub mod compact;
+pub mod contig_order;
+pub mod cooccurrence;
+pub mod defragment;
+pub mod delta;
+pub mod dl;
+pub mod evalue;
+pub mod families;
+pub mod fasta;
+pub mod fixtures;
+pub mod hmm;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod idx;
+pub mod layout;
+pub mod manifest;
+pub mod migrate;
+pub mod options;
+pub mod package;
+pub mod paths;
+pub mod plan;
+pub mod querylog;
+pub mod records;
+pub mod redundancy;
+pub mod remap;
+pub mod roots;
+pub mod sample;
+pub mod selftest;
+pub mod seqlens;
+pub mod status;
+pub mod usage;
+
+pub const DATA_DIR: &str = "/usr/local/Dfam-warehouse/releases/annotations";
+pub const EXPORT_DIR: &str = "/usr/local/Dfam-warehouse/releases/annotations_exports";
 
-pub const DATA_DIR: &'static str = "/usr/local/Dfam-warehouse/releases/annotations";
-pub const EXPORT_DIR: &'static str = "/usr/local/Dfam-warehouse/releases/annotations_exports";
-
-pub const ASSEMBLY_DIR: &'static str = "assembly_alignments";
-pub const ASSEMBLY_FILE: &'static str = "-byacc-full_region.tsv";
-pub const BENCHMARK_DIR: &'static str = "benchmark_alignments";
-pub const BENCHMARK_FILE: &'static str = "-byacc-bench_region.tsv";
-pub const MASKS_DIR: &'static str = "masks";
-pub const MASKS_FILE: &'static str = "-mask.tsv";
-pub const MOD_LEN_DIR: &'static str = "model_lengths";
-pub const MOD_LEN_FILE: &'static str = "-model_lengths.json";
-pub const SEQUENCE_DIR: &'static str = "sequences";
-pub const SEQUENCE_FILE: &'static str = "-sequences.json";
-
-const DATA_ELEMENTS: [&str; 5] = [
+pub const ASSEMBLY_DIR: &str = "assembly_alignments";
+pub const ASSEMBLY_FILE: &str = "-byacc-full_region.tsv";
+pub const BENCHMARK_DIR: &str = "benchmark_alignments";
+pub const BENCHMARK_FILE: &str = "-byacc-bench_region.tsv";
+pub const MASKS_DIR: &str = "masks";
+pub const MASKS_FILE: &str = "-mask.tsv";
+pub const MOD_LEN_DIR: &str = "model_lengths";
+pub const MOD_LEN_FILE: &str = "-model_lengths.json";
+pub const SEQUENCE_DIR: &str = "sequences";
+pub const SEQUENCE_FILE: &str = "-sequences.json";
+// Optional, families are given no metadata without it, see families::FamilyMetadata
+pub const FAMILIES_DIR: &str = "families";
+pub const FAMILIES_FILE: &str = "-families.json";
+pub const SOURCE_HEADER_FILE: &str = ".source_header.txt";
+// Optional single file holding both JSON data types, next to the data type folders
+pub const ASSEMBLY_DATA_FILE: &str = "assembly_data.json";
+pub const ASSEMBLY_DATA_VERSION: u64 = 1;
+
+pub const DATA_ELEMENTS: [&str; 6] = [
     ASSEMBLY_DIR,
     BENCHMARK_DIR,
     MASKS_DIR,
     MOD_LEN_DIR,
     SEQUENCE_DIR,
+    FAMILIES_DIR,
 ];
 pub const INDEX_DATA_TYPES: [&str; 3] = [ASSEMBLY_DIR, BENCHMARK_DIR, MASKS_DIR];
 pub const JSON_DATA_TYPES: [&str; 2] = [MOD_LEN_DIR, SEQUENCE_DIR];
 
-trait Formattable {
+// The record field each index data type's BED files are split by, every <key>.bed.bgz
+// holding the records of one key. Annotations are split by family, masks by sequence.
+pub const FAM_ACC_KEY: &str = "fam_acc";
+pub const SEQ_ACC_KEY: &str = "seq_acc";
+pub const PARTITION_KEYS: [(&str, &str); 3] = [
+    (ASSEMBLY_DIR, FAM_ACC_KEY),
+    (BENCHMARK_DIR, FAM_ACC_KEY),
+    (MASKS_DIR, SEQ_ACC_KEY),
+];
+
+pub fn partition_key(data_type: &str) -> &'static str {
+    match PARTITION_KEYS.iter().find(|(dt, _)| *dt == data_type) {
+        Some((_, key)) => key,
+        None => panic!("{} Is Not An Indexed Data Type", data_type),
+    }
+}
+
+// Dfam family accessions, DF followed by digits
+fn is_family_accession(key: &str) -> bool {
+    key.len() > 2 && key.starts_with("DF") && key[2..].bytes().all(|b| b.is_ascii_digit())
+}
+
+// Rejects a family accession given as the key of a data type split by sequence, which
+// could only ever be reported as a missing file
+pub fn check_partition_key(data_type: &str, key: &str) -> Result<()> {
+    if partition_key(data_type) == SEQ_ACC_KEY && is_family_accession(key) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} Is A Family Accession, But {} Files Are Split By Sequence - Pass A Sequence Accession ({}) As The Key",
+                key, data_type, SEQ_ACC_KEY
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Names a key in messages, by what its data type is split by
+fn partition_key_label(data_type: &str) -> &'static str {
+    match partition_key(data_type) {
+        SEQ_ACC_KEY => "Sequence",
+        _ => "Family",
+    }
+}
+
+// JSON values for numeric columns. Legacy output keeps every value as the raw string,
+// otherwise malformed values become null with a warning rather than failing the query.
+fn json_int(field: &str, val: &str, legacy: bool) -> Value {
+    if legacy {
+        return Value::String(val.to_string());
+    }
+    match val.trim().parse::<i64>() {
+        Ok(n) => Value::from(n),
+        Err(_) => {
+            warn!("Could not parse {} \"{}\" as an integer", field, val);
+            Value::Null
+        }
+    }
+}
+
+fn json_float(field: &str, val: &str, legacy: bool) -> Value {
+    if legacy {
+        return Value::String(val.to_string());
+    }
+    match val.trim().parse::<f64>() {
+        Ok(n) if n.is_finite() => Value::from(n),
+        _ => {
+            warn!("Could not parse {} \"{}\" as a number", field, val);
+            Value::Null
+        }
+    }
+}
+
+// E-values too small for a double are kept as their string, see EValue::to_json
+fn json_e_value(val: &str, legacy: bool) -> Value {
+    if legacy {
+        return Value::String(val.to_string());
+    }
+    match EValue::parse(val) {
+        Some(e_value) => e_value.to_json(false),
+        None => {
+            warn!("Could not parse e_value \"{}\" as a number", val);
+            Value::Null
+        }
+    }
+}
+
+/// Conversions between the export TSV, BED and download layouts of one record.
+///
+/// ```
+/// use te_idx::{Annotation, Formattable};
+///
+/// let bed = "chr1\t1000\t1200\tDF000000001\t104.0\t+\t0.0\t1000\t1200\t55\t262\t1.3e-26\t1\t12.5\tMIR\t248956422\t200M\tcaf";
+/// let hit = Annotation::from_bed(&bed.split('\t').collect());
+/// assert_eq!(hit.fam_acc(), "DF000000001");
+///
+/// // BED columns are 0-based and half open, records keep the 1-based exported coordinates
+/// assert_eq!((hit.seq_start(), hit.seq_end()), ("1001", "1200"));
+///
+/// // The download format names the sequence and adds the model length
+/// let dl = hit.to_dl_fmt("chr1", "262");
+/// assert_eq!(dl[..4], ["chr1", "DF000000001", "MIR", "104.0"]);
+/// assert_eq!(dl[7], "262");
+///
+/// // The export TSV puts the family before the coordinates
+/// let tsv = hit.to_export_tsv();
+/// assert_eq!(tsv[..3], ["chr1", "DF000000001", "MIR"]);
+/// assert_eq!(Annotation::from_export_tsv(&tsv).to_bed_fmt(), bed.split('\t').collect::<Vec<_>>());
+/// ```
+#[allow(clippy::ptr_arg)] // Callers collect the fields into a Vec here, kept as it was
+pub trait Formattable {
     fn from_export_tsv(tsv_line: &Vec<&str>) -> Self;
     fn from_bed(bed_line: &Vec<&str>) -> Self;
-    fn to_json(&self) -> serde_json::Value;
+    fn to_json(&self, legacy: bool) -> serde_json::Value;
     fn to_bed_fmt(&self) -> Vec<&str>;
+    // The export TSV columns, in the order from_export_tsv reads them
+    fn to_export_tsv(&self) -> Vec<&str>;
     fn to_dl_fmt(&self, seq_name: &str, hmm_len: &str) -> Vec<String>;
-    fn to_filter_fmt(&self) -> Vec<String>;
+    // The partition key of the record, naming the BED file it belongs in
     fn get_acc(&self) -> String;
 }
 
+// A column of a prepared BED record, empty when a misaligned record is short of it
+fn bed_field(bed_line: &[&str], column: usize) -> String {
+    bed_line
+        .get(column)
+        .map_or(String::new(), |f| f.to_string())
+}
+
+// The BED chromStart and chromEnd of exported coordinates, see records::bed_coordinates.
+// Coordinates that do not convert are kept as they are, prep_beds refuses their records.
+fn bed_columns(seq_start: &str, seq_end: &str) -> (String, String) {
+    match records::bed_coordinates(seq_start, seq_end) {
+        Some((start, end)) => (start.to_string(), end.to_string()),
+        None => (seq_start.to_string(), seq_end.to_string()),
+    }
+}
+
+// The exported seq_start and seq_end of a prepared BED record on a strand, columns that are
+// not BED coordinates as they are
+fn export_columns(bed_line: &[&str], strand: &str) -> (String, String) {
+    let (start, end) = (bed_field(bed_line, 1), bed_field(bed_line, 2));
+    match records::export_coordinates(&start, &end, strand) {
+        Some((seq_start, seq_end)) => (seq_start.to_string(), seq_end.to_string()),
+        None => (start, end),
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Annotation {
     seq_acc: String,     // Dfamseq accession for sequences in assembly (1..)
@@ -58,16 +262,56 @@ pub struct Annotation {
     strand: String,      // '+' or '-'
     ali_start: String,   // nhmmer "envelope" start
     ali_end: String,     // nhmmer "envelope" end
-    seq_start: String,   // Dfamseq sequence start
-    seq_end: String,     // Dfamseq sequence end
+    seq_start: String,   // Dfamseq sequence start (1-based, fully closed)
+    seq_end: String,     // Dfamseq sequence end, below seq_start on the minus strand
+    #[serde(skip)]
+    chrom_start: String, // BED chromStart (0-based, half open), the lower end less one
+    #[serde(skip)]
+    chrom_end: String, // BED chromEnd, the higher end
     seq_len: String,     // The length of the Dfamseq
     cigar: String,       // CIGAR string of sequence alignment
     kimura_div: String, // Kimura percent divergence ( only in full_region, not in benchmark_region )
     nrph_hit: String,   // NRPH (1 or 0) (only in full_region, not in benchmark_region)
     caf: String,        // Compressed Alignment Format (CAF) (only in full_region...)
 }
+impl Annotation {
+    pub fn seq_acc(&self) -> &str {
+        &self.seq_acc
+    }
+
+    pub fn fam_acc(&self) -> &str {
+        &self.fam_acc
+    }
+
+    pub fn family_name(&self) -> &str {
+        &self.family_name
+    }
+
+    pub fn bit_score(&self) -> &str {
+        &self.bit_score
+    }
+
+    pub fn e_value(&self) -> &str {
+        &self.e_value
+    }
+
+    pub fn strand(&self) -> &str {
+        &self.strand
+    }
+
+    // Exported coordinates, seq_start is greater than seq_end on the minus strand
+    pub fn seq_start(&self) -> &str {
+        &self.seq_start
+    }
+
+    pub fn seq_end(&self) -> &str {
+        &self.seq_end
+    }
+}
+
 impl Formattable for Annotation {
     fn from_export_tsv(tsv_line: &Vec<&str>) -> Self {
+        let (chrom_start, chrom_end) = bed_columns(tsv_line[11], tsv_line[12]);
         Self {
             seq_acc: tsv_line[0].to_string(),
             fam_acc: tsv_line[1].to_string(),
@@ -82,6 +326,8 @@ impl Formattable for Annotation {
             ali_end: tsv_line[10].to_string(),
             seq_start: tsv_line[11].to_string(),
             seq_end: tsv_line[12].to_string(),
+            chrom_start,
+            chrom_end,
             seq_len: tsv_line[13].to_string(),
             cigar: tsv_line[14].to_string(),
             kimura_div: tsv_line[15].to_string(),
@@ -90,11 +336,34 @@ impl Formattable for Annotation {
         }
     }
 
-    fn to_bed_fmt(&self) -> Vec<&str> {
+    fn to_export_tsv(&self) -> Vec<&str> {
         vec![
             &self.seq_acc,
+            &self.fam_acc,
+            &self.family_name,
+            &self.bit_score,
+            &self.e_value,
+            &self.bias,
+            &self.model_start,
+            &self.model_end,
+            &self.strand,
+            &self.ali_start,
+            &self.ali_end,
             &self.seq_start,
             &self.seq_end,
+            &self.seq_len,
+            &self.cigar,
+            &self.kimura_div,
+            &self.nrph_hit,
+            &self.caf,
+        ]
+    }
+
+    fn to_bed_fmt(&self) -> Vec<&str> {
+        vec![
+            &self.seq_acc,
+            &self.chrom_start,
+            &self.chrom_end,
             &self.fam_acc,
             &self.bit_score,
             &self.strand,
@@ -114,43 +383,45 @@ impl Formattable for Annotation {
     }
 
     fn from_bed(bed_line: &Vec<&str>) -> Self {
+        let (seq_start, seq_end) = export_columns(bed_line, &bed_field(bed_line, 5));
         Self {
-            seq_acc: bed_line[0].to_string(),
-            seq_start: bed_line[1].to_string(),
-            seq_end: bed_line[2].to_string(),
-            fam_acc: bed_line[3].to_string(),
-            bit_score: bed_line[4].to_string(),
-            strand: bed_line[5].to_string(),
-            bias: bed_line[6].to_string(),
-            ali_start: bed_line[7].to_string(),
-            ali_end: bed_line[8].to_string(),
-            model_start: bed_line[9].to_string(),
-            model_end: bed_line[10].to_string(),
-            e_value: bed_line[11].to_string(),
-            nrph_hit: bed_line[12].to_string(),
-            kimura_div: bed_line[13].to_string(),
-            family_name: bed_line[14].to_string(),
-            seq_len: bed_line[15].to_string(),
-            cigar: bed_line[16].to_string(),
-            caf: bed_line[17].to_string(),
-        }
-    }
-
-    fn to_json(&self) -> serde_json::Value {
+            seq_acc: bed_field(bed_line, 0),
+            seq_start,
+            seq_end,
+            chrom_start: bed_field(bed_line, 1),
+            chrom_end: bed_field(bed_line, 2),
+            fam_acc: bed_field(bed_line, 3),
+            bit_score: bed_field(bed_line, 4),
+            strand: bed_field(bed_line, 5),
+            bias: bed_field(bed_line, 6),
+            ali_start: bed_field(bed_line, 7),
+            ali_end: bed_field(bed_line, 8),
+            model_start: bed_field(bed_line, 9),
+            model_end: bed_field(bed_line, 10),
+            e_value: bed_field(bed_line, 11),
+            nrph_hit: bed_field(bed_line, 12),
+            kimura_div: bed_field(bed_line, 13),
+            family_name: bed_field(bed_line, 14),
+            seq_len: bed_field(bed_line, 15),
+            cigar: bed_field(bed_line, 16),
+            caf: bed_field(bed_line, 17),
+        }
+    }
+
+    fn to_json(&self, legacy: bool) -> serde_json::Value {
         json!({
             "sequence": self.seq_acc,
             "accession": self.fam_acc,
-            "bit_score": self.bit_score,
-            "e_value": self.e_value,
-            "seq_start": self.seq_start,
-            "seq_end": self.seq_end,
+            "bit_score": json_float("bit_score", &self.bit_score, legacy),
+            "e_value": json_e_value(&self.e_value, legacy),
+            "bias": json_float("bias", &self.bias, legacy),
+            "seq_start": json_int("seq_start", &self.seq_start, legacy),
+            "seq_end": json_int("seq_end", &self.seq_end, legacy),
             "strand": self.strand,
-            "ali_start": self.ali_start,
-            "ali_end": self.ali_end,
-            "model_start": self.model_start,
-            "model_end": self.model_end,
-            "bit_score": self.bit_score,
-            "e_value": self.e_value,
+            "ali_start": json_int("ali_start", &self.ali_start, legacy),
+            "ali_end": json_int("ali_end", &self.ali_end, legacy),
+            "model_start": json_int("model_start", &self.model_start, legacy),
+            "model_end": json_int("model_end", &self.model_end, legacy),
         })
     }
 
@@ -173,27 +444,6 @@ impl Formattable for Annotation {
         ]
     }
 
-    fn to_filter_fmt(&self) -> Vec<String> {
-        vec![
-            self.seq_acc.to_string(),
-            self.seq_start.to_string(),
-            self.seq_end.to_string(),
-            self.fam_acc.to_string(),
-            self.bit_score.to_string(),
-            self.strand.to_string(),
-            self.bias.to_string(),
-            self.ali_start.to_string(),
-            self.ali_end.to_string(),
-            self.model_start.to_string(),
-            self.model_end.to_string(),
-            self.e_value.to_string(),
-            self.nrph_hit.to_string(),
-            self.kimura_div.to_string(),
-            self.family_name.to_string(),
-            self.seq_len.to_string(),
-        ]
-    }
-
     fn get_acc(&self) -> String {
         self.fam_acc.clone()
     }
@@ -212,8 +462,12 @@ pub struct BenchMarkAnnotation {
     strand: String,      // '+' or '-'
     ali_start: String,   // nhmmer "envelope" start
     ali_end: String,     // nhmmer "envelope" end
-    seq_start: String,   // Dfamseq sequence start
-    seq_end: String,     // Dfamseq sequence end
+    seq_start: String,   // Dfamseq sequence start (1-based, fully closed)
+    seq_end: String,     // Dfamseq sequence end, below seq_start on the minus strand
+    #[serde(skip)]
+    chrom_start: String, // BED chromStart (0-based, half open), the lower end less one
+    #[serde(skip)]
+    chrom_end: String, // BED chromEnd, the higher end
     seq_len: String,     // The length of the Dfamseq
     cigar: String,       // CIGAR string of sequence alignment
 }
@@ -225,6 +479,7 @@ impl Formattable for BenchMarkAnnotation {
         } else {
             String::new()
         };
+        let (chrom_start, chrom_end) = bed_columns(tsv_line[11], tsv_line[12]);
         Self {
             seq_acc: tsv_line[0].to_string(),
             fam_acc: tsv_line[1].to_string(),
@@ -239,34 +494,56 @@ impl Formattable for BenchMarkAnnotation {
             ali_end: tsv_line[10].to_string(),
             seq_start: tsv_line[11].to_string(),
             seq_end: tsv_line[12].to_string(),
+            chrom_start,
+            chrom_end,
             seq_len: tsv_line[13].to_string(),
             cigar,
         }
     }
 
-    fn to_json(&self) -> serde_json::Value {
+    // Always 15 columns, an export without the cigar column gets it back empty
+    fn to_export_tsv(&self) -> Vec<&str> {
+        vec![
+            &self.seq_acc,
+            &self.fam_acc,
+            &self.family_name,
+            &self.bit_score,
+            &self.e_value,
+            &self.bias,
+            &self.model_start,
+            &self.model_end,
+            &self.strand,
+            &self.ali_start,
+            &self.ali_end,
+            &self.seq_start,
+            &self.seq_end,
+            &self.seq_len,
+            &self.cigar,
+        ]
+    }
+
+    fn to_json(&self, legacy: bool) -> serde_json::Value {
         json!({
             "sequence": self.seq_acc,
             "accession": self.fam_acc,
-            "bit_score": self.bit_score,
-            "e_value": self.e_value,
-            "seq_start": self.seq_start,
-            "seq_end": self.seq_end,
+            "bit_score": json_float("bit_score", &self.bit_score, legacy),
+            "e_value": json_e_value(&self.e_value, legacy),
+            "bias": json_float("bias", &self.bias, legacy),
+            "seq_start": json_int("seq_start", &self.seq_start, legacy),
+            "seq_end": json_int("seq_end", &self.seq_end, legacy),
             "strand": self.strand,
-            "ali_start": self.ali_start,
-            "ali_end": self.ali_end,
-            "model_start": self.model_start,
-            "model_end": self.model_end,
-            "bit_score": self.bit_score,
-            "e_value": self.e_value,
+            "ali_start": json_int("ali_start", &self.ali_start, legacy),
+            "ali_end": json_int("ali_end", &self.ali_end, legacy),
+            "model_start": json_int("model_start", &self.model_start, legacy),
+            "model_end": json_int("model_end", &self.model_end, legacy),
         })
     }
 
     fn to_bed_fmt(&self) -> Vec<&str> {
         vec![
             &self.seq_acc,
-            &self.seq_start,
-            &self.seq_end,
+            &self.chrom_start,
+            &self.chrom_end,
             &self.fam_acc,
             &self.bit_score,
             &self.strand,
@@ -283,22 +560,25 @@ impl Formattable for BenchMarkAnnotation {
     }
 
     fn from_bed(bed_line: &Vec<&str>) -> Self {
+        let (seq_start, seq_end) = export_columns(bed_line, &bed_field(bed_line, 5));
         Self {
-            seq_acc: bed_line[0].to_string(),
-            seq_start: bed_line[1].to_string(),
-            seq_end: bed_line[2].to_string(),
-            fam_acc: bed_line[3].to_string(),
-            bit_score: bed_line[4].to_string(),
-            strand: bed_line[5].to_string(),
-            bias: bed_line[6].to_string(),
-            ali_start: bed_line[7].to_string(),
-            ali_end: bed_line[8].to_string(),
-            model_start: bed_line[9].to_string(),
-            model_end: bed_line[10].to_string(),
-            e_value: bed_line[11].to_string(),
-            family_name: bed_line[13].to_string(),
-            seq_len: bed_line[14].to_string(),
-            cigar: bed_line[15].to_string(),
+            seq_acc: bed_field(bed_line, 0),
+            seq_start,
+            seq_end,
+            chrom_start: bed_field(bed_line, 1),
+            chrom_end: bed_field(bed_line, 2),
+            fam_acc: bed_field(bed_line, 3),
+            bit_score: bed_field(bed_line, 4),
+            strand: bed_field(bed_line, 5),
+            bias: bed_field(bed_line, 6),
+            ali_start: bed_field(bed_line, 7),
+            ali_end: bed_field(bed_line, 8),
+            model_start: bed_field(bed_line, 9),
+            model_end: bed_field(bed_line, 10),
+            e_value: bed_field(bed_line, 11),
+            family_name: bed_field(bed_line, 12),
+            seq_len: bed_field(bed_line, 13),
+            cigar: bed_field(bed_line, 14),
         }
     }
 
@@ -321,71 +601,113 @@ impl Formattable for BenchMarkAnnotation {
         ]
     }
 
-    fn to_filter_fmt(&self) -> Vec<String> {
-        vec![
-            self.seq_acc.to_string(),
-            self.seq_start.to_string(),
-            self.seq_end.to_string(),
-            self.fam_acc.to_string(),
-            self.bit_score.to_string(),
-            self.strand.to_string(),
-            self.bias.to_string(),
-            self.ali_start.to_string(),
-            self.ali_end.to_string(),
-            self.model_start.to_string(),
-            self.model_end.to_string(),
-            self.e_value.to_string(),
-            self.family_name.to_string(),
-            self.seq_len.to_string(),
-        ]
-    }
-
     fn get_acc(&self) -> String {
         self.fam_acc.clone()
     }
 }
 
+// Newer mask exports add the period of the repeat unit and a class such as Simple_repeat or
+// Low_complexity as columns 6 and 7. Both are kept at the end of the BED record, after the
+// columns older files have, and only when the record has them.
 #[derive(Serialize, Deserialize)]
 struct MaskHit {
     seq_acc: String,
-    seq_start: String,
+    seq_start: String, // Masks are on the plus strand, seq_start is the lower
     seq_end: String,
+    #[serde(skip)]
+    chrom_start: String,
+    #[serde(skip)]
+    chrom_end: String,
     repeat_str: String,
     repeat_length: String,
+    period: Option<String>,
+    repeat_class: Option<String>,
+}
+
+impl MaskHit {
+    // Both class columns or neither, from the 6th and 7th fields of a record
+    fn class_columns(line: &[&str]) -> (Option<String>, Option<String>) {
+        match line.len() >= MASK_COLUMNS.len() {
+            true => (Some(line[5].to_string()), Some(line[6].to_string())),
+            false => (None, None),
+        }
+    }
 }
 
 impl Formattable for MaskHit {
     fn from_export_tsv(tsv_line: &Vec<&str>) -> Self {
+        let (period, repeat_class) = MaskHit::class_columns(tsv_line);
+        let (chrom_start, chrom_end) = bed_columns(tsv_line[1], tsv_line[2]);
         Self {
             seq_acc: tsv_line[0].to_string(),
             seq_start: tsv_line[1].to_string(),
             seq_end: tsv_line[2].to_string(),
+            chrom_start,
+            chrom_end,
             repeat_str: tsv_line[3].to_string(),
             repeat_length: tsv_line[4].to_string(),
+            period,
+            repeat_class,
         }
     }
-    fn to_json(&self) -> serde_json::Value {
-        json!({
+
+    fn to_export_tsv(&self) -> Vec<&str> {
+        let mut tsv: Vec<&str> = vec![
+            &self.seq_acc,
+            &self.seq_start,
+            &self.seq_end,
+            &self.repeat_str,
+            &self.repeat_length,
+        ];
+        if let (Some(period), Some(repeat_class)) = (&self.period, &self.repeat_class) {
+            tsv.extend([period.as_str(), repeat_class.as_str()]);
+        }
+        tsv
+    }
+
+    fn to_json(&self, legacy: bool) -> serde_json::Value {
+        let mut json = json!({
             "seq_acc": self.seq_acc,
-            "seq_start": self.seq_start,
-            "seq_end": self.seq_end,
+            "seq_start": json_int("seq_start", &self.seq_start, legacy),
+            "seq_end": json_int("seq_end", &self.seq_end, legacy),
             "repeat_str": self.repeat_str,
-            "repeat_length": self.repeat_length,
-        })
+            "repeat_length": json_int("repeat_length", &self.repeat_length, legacy),
+        });
+        if let (Some(period), Some(repeat_class)) = (&self.period, &self.repeat_class) {
+            json["period"] = json_int("period", period, legacy);
+            json["repeat_class"] = json!(repeat_class);
+        }
+        json
     }
 
     fn to_bed_fmt(&self) -> Vec<&str> {
-        vec![
+        let mut bed: Vec<&str> = vec![
             &self.seq_acc,
-            &self.seq_start,
-            &self.seq_end,
+            &self.chrom_start,
+            &self.chrom_end,
             &self.repeat_str,
             &self.repeat_length,
-        ]
+        ];
+        if let (Some(period), Some(repeat_class)) = (&self.period, &self.repeat_class) {
+            bed.extend([period.as_str(), repeat_class.as_str()]);
+        }
+        bed
     }
 
     fn from_bed(bed_line: &Vec<&str>) -> Self {
-        MaskHit::from_export_tsv(&bed_line)
+        let (period, repeat_class) = MaskHit::class_columns(bed_line);
+        let (seq_start, seq_end) = export_columns(bed_line, "+");
+        Self {
+            seq_acc: bed_field(bed_line, 0),
+            seq_start,
+            seq_end,
+            chrom_start: bed_field(bed_line, 1),
+            chrom_end: bed_field(bed_line, 2),
+            repeat_str: bed_field(bed_line, 3),
+            repeat_length: bed_field(bed_line, 4),
+            period,
+            repeat_class,
+        }
     }
 
     fn to_dl_fmt(&self, _seq_name: &str, _hmm_len: &str) -> Vec<String> {
@@ -398,16 +720,6 @@ impl Formattable for MaskHit {
         ]
     }
 
-    fn to_filter_fmt(&self) -> Vec<String> {
-        vec![
-            self.seq_acc.to_string(),
-            self.seq_start.to_string(),
-            self.seq_end.to_string(),
-            self.repeat_str.to_string(),
-            self.repeat_length.to_string(),
-        ]
-    }
-
     fn get_acc(&self) -> String {
         self.seq_acc.clone()
     }
@@ -451,11 +763,11 @@ impl FormattableLine {
         }
     }
 
-    fn to_json(&self) -> serde_json::Value {
+    fn to_json(&self, legacy: bool) -> serde_json::Value {
         match self {
-            FormattableLine::Annotation(annotation) => annotation.to_json(),
-            FormattableLine::BenchMarkAnnotation(benchmark) => benchmark.to_json(),
-            FormattableLine::MaskHit(mask_hit) => mask_hit.to_json(),
+            FormattableLine::Annotation(annotation) => annotation.to_json(legacy),
+            FormattableLine::BenchMarkAnnotation(benchmark) => benchmark.to_json(legacy),
+            FormattableLine::MaskHit(mask_hit) => mask_hit.to_json(legacy),
         }
     }
 
@@ -467,11 +779,11 @@ impl FormattableLine {
         }
     }
 
-    fn to_filter_fmt(&self) -> Vec<String> {
+    fn to_export_tsv(&self) -> Vec<&str> {
         match self {
-            FormattableLine::Annotation(annotation) => annotation.to_filter_fmt(),
-            FormattableLine::BenchMarkAnnotation(benchmark) => benchmark.to_filter_fmt(),
-            FormattableLine::MaskHit(mask_hit) => mask_hit.to_filter_fmt(),
+            FormattableLine::Annotation(annotation) => annotation.to_export_tsv(),
+            FormattableLine::BenchMarkAnnotation(benchmark) => benchmark.to_export_tsv(),
+            FormattableLine::MaskHit(mask_hit) => mask_hit.to_export_tsv(),
         }
     }
 
@@ -482,469 +794,4529 @@ impl FormattableLine {
             FormattableLine::MaskHit(mask_hit) => mask_hit.get_acc(),
         }
     }
-}
 
-pub fn bgzf_filter(
-    assembly: &String,
-    data_type: &String,
-    fam: &String,
-    position: &usize,
-    term: &Option<String>,
-    outfile: &Option<String>,
-    dl_fmt: bool,
-    data_directory: &String,
-) -> Result<()> {
-    let assembly_path: String = format!("{}/{}/{}", &data_directory, &assembly, &data_type);
-    if !Path::new(&assembly_path).exists() {
-        panic!("Data \"{}\" Does Not Exist", assembly_path);
+    // Fails for an exported record whose coordinates do not convert to BED ones, which the
+    // BED files and the index could not place
+    fn check_coordinates(&self, line: &str) -> Result<()> {
+        let (seq_start, seq_end) = match self {
+            FormattableLine::Annotation(a) => (&a.seq_start, &a.seq_end),
+            FormattableLine::BenchMarkAnnotation(b) => (&b.seq_start, &b.seq_end),
+            FormattableLine::MaskHit(m) => (&m.seq_start, &m.seq_end),
+        };
+        match records::bed_coordinates(seq_start, seq_end) {
+            Some(_) => Ok(()),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Record Without 1-Based Sequence Coordinates, {}..{}: {}",
+                    seq_start, seq_end, line
+                ),
+            )),
+        }
     }
-    let fam_file: String = format!("{}/{}.bed.bgz", &assembly_path, &fam);
-    if !Path::new(&fam_file).exists() {
-        panic!("Family {} Not Found In Assembly {}", &fam, assembly_path);
+}
+
+// Looks for <fam>.bed.bgz under the other family keyed data types of an assembly, so a
+// missing family can point at the --data-type it was probably meant for
+fn family_data_type_hint(assembly_path: &str, fam: &str, data_type: &str) -> Option<&'static str> {
+    PARTITION_KEYS
+        .into_iter()
+        .filter(|(_, key)| *key == FAM_ACC_KEY)
+        .map(|(other, _)| other)
+        .find(|other| {
+            *other != data_type
+                && Path::new(&bgz_path(join_path(assembly_path, &[other]), fam)).exists()
+        })
+}
+
+// Output columns of each data type, in BED order, and of the download format. Columns are
+// selected by these names, the leading '*' of some header names is not part of the name.
+const ANNOTATION_COLUMNS: [&str; 18] = [
+    "seq_id",
+    "seq_start",
+    "seq_end",
+    "family_accession",
+    "hit_bit_score",
+    "strand",
+    "bias",
+    "ali_start",
+    "ali_end",
+    "model_start",
+    "model_end",
+    "hit_evalue_score",
+    "nrph_hit",
+    "divergence",
+    "*family_name",
+    "seq_len",
+    "*cigar",
+    "*caf",
+];
+const BENCHMARK_COLUMNS: [&str; 15] = [
+    "seq_id",
+    "seq_start",
+    "seq_end",
+    "family_accession",
+    "hit_bit_score",
+    "strand",
+    "bias",
+    "ali_start",
+    "ali_end",
+    "model_start",
+    "model_end",
+    "hit_evalue_score",
+    "*family_name",
+    "seq_len",
+    "*cigar",
+];
+const MASK_COLUMNS: [&str; 7] = [
+    "seq_id",
+    "seq_start",
+    "seq_end",
+    "repeat_str",
+    "repeat_length",
+    "period",
+    "repeat_class",
+];
+// Masks exported without the period and repeat class have only the columns before them
+const MASK_BASE_COLUMNS: usize = 5;
+// BED column of the repeat class of masks that have one
+const REPEAT_CLASS_COLUMN: usize = 6;
+const DL_COLUMNS: [&str; 14] = [
+    "sequence name",
+    "model accession",
+    "model name",
+    "bit score",
+    "e-value",
+    "hmm start",
+    "hmm end",
+    "hmm length",
+    "strand",
+    "alignment start",
+    "alignment end",
+    "envelope start",
+    "envelope end",
+    "sequence length",
+];
+
+pub const PUBLIC_PROFILE: &str = "public";
+pub const INTERNAL_PROFILE: &str = "internal";
+pub const COLUMN_PROFILES: [&str; 2] = [PUBLIC_PROFILE, INTERNAL_PROFILE];
+// Left out of public downloads for licensing and size reasons
+const PUBLIC_EXCLUDED_COLUMNS: [&str; 2] = ["cigar", "caf"];
+
+fn output_columns(data_type: &str, dl_fmt: bool) -> &'static [&'static str] {
+    match data_type {
+        _ if dl_fmt => &DL_COLUMNS,
+        ASSEMBLY_DIR => &ANNOTATION_COLUMNS,
+        BENCHMARK_DIR => &BENCHMARK_COLUMNS,
+        MASKS_DIR => &MASK_COLUMNS,
+        _ => panic!("Can't Format!"),
     }
+}
 
-    let worker_count: NonZeroUsize = match NonZeroUsize::new(5) {
-        Some(n) => n,
-        None => unreachable!(),
-    };
-    let in_f = File::open(fam_file).expect("Could Not Open Input File");
-    let reader = bgzf::MultithreadedReader::with_worker_count(worker_count, in_f);
-    let mut writer: Box<dyn Write> = match outfile {
-        Some(outfile) => Box::new(bgzf::MultithreadedWriter::with_worker_count(
-            worker_count,
-            OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&outfile)?,
-        )),
-        None => Box::new(bgzf::Writer::new(stdout())),
+// BED column of the length of the record's sequence, masks have none
+pub fn seq_len_column(data_type: &str) -> Option<usize> {
+    let columns = match data_type {
+        ASSEMBLY_DIR | BENCHMARK_DIR => output_columns(data_type, false),
+        _ => return None,
     };
+    columns.iter().position(|column| *column == "seq_len")
+}
 
-    let header;
-    if dl_fmt {
-        header = "#sequence name	model accession	model name	bit score	e-value	hmm start	hmm end	hmm length	strand	alignment start	alignment end	envelope start	envelope end	sequence length";
-    } else {
-        header = "#seq_id\tseq_start\tseq_end\tfamily_accession\thit_bit_score\tstrand\tbias\tali_start\tali_end\tmodel_start\tmodel_end\thit_evalue_score\tnrph_hit\tdivergence\t*family_name\tseq_len\t*cigar\t*caf";
+// Whether a record of count columns is in the current layout. Masks may lack the trailing
+// period and repeat class columns, see MaskHit.
+fn column_count_fits(data_type: &str, count: usize) -> bool {
+    count == output_columns(data_type, false).len()
+        || (data_type == MASKS_DIR && count == MASK_BASE_COLUMNS)
+}
+
+// Why a prepared BED record's columns look shifted, if they do. Files prepared by releases
+// that split lines on whitespace lost empty fields such as an optional family name, moving
+// every later column along, e.g. CIGAR fragments into the NRPH column.
+pub fn misaligned_columns(fields: &[&str], data_type: &str) -> Option<String> {
+    let expected = output_columns(data_type, false).len();
+    if !column_count_fits(data_type, fields.len()) {
+        return Some(format!(
+            "Expected {} Columns, Found {}",
+            expected,
+            fields.len()
+        ));
+    }
+    if data_type == MASKS_DIR {
+        return None;
     }
+    if !["+", "-"].contains(&fields[idx::STRAND_COLUMN]) {
+        return Some(format!(
+            "Strand Column Holds \"{}\"",
+            fields[idx::STRAND_COLUMN]
+        ));
+    }
+    if data_type == ASSEMBLY_DIR && !["0", "1"].contains(&fields[idx::NRPH_COLUMN]) {
+        return Some(format!(
+            "NRPH Column Holds \"{}\"",
+            fields[idx::NRPH_COLUMN]
+        ));
+    }
+    None
+}
 
-    writer
-        .write_all(format!("{}\n", header).as_bytes())
-        .expect("Unable to write line");
+// The records a query read whose columns are misaligned, or in a legacy layout, see
+// layout::LEGACY_LAYOUTS. Records of a legacy layout are read with their fields moved to the
+// current columns.
+#[derive(Default)]
+struct ColumnCheck {
+    misaligned: usize,
+    reason: Option<String>,
+    legacy: BTreeMap<&'static str, usize>,
+}
 
-    let mut hmm_len = "0".to_string();
-    if dl_fmt {
-        hmm_len = match json_query(
-            &assembly,
-            &MOD_LEN_DIR.to_string(),
-            &fam.to_string(),
-            &Some("length".to_string()),
-            data_directory,
-        ) {
-            Ok(len) => len,
-            Err(e) => panic!("{}", e),
-        };
+impl ColumnCheck {
+    fn check<'a>(&mut self, fields: Vec<&'a str>, data_type: &str) -> Vec<&'a str> {
+        if let Some(layout) = layout::detect_layout(&fields, data_type) {
+            *self.legacy.entry(layout.name).or_insert(0) += 1;
+            return layout.current_fields(&fields);
+        }
+        if let Some(reason) = misaligned_columns(&fields, data_type) {
+            self.misaligned += 1;
+            self.reason.get_or_insert(reason);
+        }
+        fields
     }
 
-    let mut output;
-    for result in reader.lines() {
-        let line = result?;
-        let fields: Vec<_> = line.split("\t").collect();
-        let formatted_line = FormattableLine::from_bed(&fields, data_type);
-        if term.is_none()
-            || (fields.len() >= position - 1
-                && term.is_some()
-                && fields.get(position - 1).unwrap() == term.as_ref().unwrap())
-        {
-            if dl_fmt {
-                let chrom_id = &fields[0].to_string();
-                output = formatted_line.to_dl_fmt(chrom_id, &hmm_len);
-            } else {
-                // output = fields.drain(..16).map(|f| f.to_string()).collect(); // TODO readjust this!
-                output = formatted_line.to_filter_fmt()
-            }
-            writer
-                .write_all(format!("{}\n", &output.join("\t")).as_bytes())
-                .expect("Unable to write line");
+    // Warns once about each kind of record found, returning the warnings
+    fn warn(&self, assembly: &str, data_type: &str) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(reason) = &self.reason {
+            warnings.push(format!(
+                "{} {} Records Of {} Have Misaligned Columns ({}), The Files Were Likely Prepared By A Release That Dropped Empty Fields - Re-Run prep-beds For Them",
+                self.misaligned, data_type, assembly, reason
+            ));
         }
+        for (layout, records) in &self.legacy {
+            warnings.push(format!(
+                "{} {} Records Of {} Are In The {} Layout, Their Missing Columns Are Read As Empty - Run repair-layout For Them",
+                records, data_type, assembly, layout
+            ));
+        }
+        for warning in &warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        warnings
     }
-    Ok(())
 }
 
-// Setup Methods ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-pub fn prep_beds(
-    assembly: &String,
-    in_tsv: &String,
-    data_type: &String,
-    data_directory: &String,
-) -> Result<()> {
-    if !Path::new(&in_tsv).exists() {
-        panic!("Input TSV \"{}\" Not Found", &in_tsv);
+// Indices of the output columns left once the profile's and the requested exclusions are
+// applied. Requested columns must exist in the output, profile columns may not.
+fn selected_columns(
+    data_type: &str,
+    dl_fmt: bool,
+    profile: &str,
+    exclude_columns: &[String],
+) -> Result<Vec<usize>> {
+    let columns = output_columns(data_type, dl_fmt);
+    let names: Vec<&str> = columns.iter().map(|c| c.trim_start_matches('*')).collect();
+    for col in exclude_columns {
+        if !names.contains(&col.as_str()) {
+            return Err(invalid_option(format!(
+                "Unknown Column \"{}\", Expected One Of: {}",
+                col,
+                names.join(", ")
+            )));
+        }
     }
+    let profile_excluded: &[&str] = match profile {
+        PUBLIC_PROFILE => &PUBLIC_EXCLUDED_COLUMNS,
+        INTERNAL_PROFILE => &[],
+        _ => {
+            return Err(invalid_option(format!(
+                "Invalid Column Profile \"{}\", Expected One Of: {}",
+                profile,
+                COLUMN_PROFILES.join(", ")
+            )))
+        }
+    };
+    Ok((0..names.len())
+        .filter(|i| {
+            !profile_excluded.contains(&names[*i])
+                && !exclude_columns.iter().any(|c| c == names[*i])
+        })
+        .collect())
+}
 
-    let db_dir = format!("{}/{}", &data_directory, &assembly);
-    let target_dir = format!("{}/{}", &db_dir, &data_type);
-    if !Path::new(&db_dir).exists() {
-        create_dir_all(&db_dir)?;
+// Strand filters only apply to annotations and take "+" or "-"
+fn check_strand(strand: &Option<String>, data_type: &str) -> Result<()> {
+    if let Some(strand) = strand {
+        if data_type == MASKS_DIR {
+            return Err(invalid_option(
+                "Masks Have No Strand Column, Strand Filter Cannot Be Applied",
+            ));
+        }
+        if strand != "+" && strand != "-" {
+            return Err(invalid_option(format!(
+                "Invalid Strand \"{}\", Expected + or -",
+                strand
+            )));
+        }
     }
+    Ok(())
+}
 
-    if !Path::new(&target_dir).exists() {
-        create_dir_all(&target_dir)?;
+// Only masks carry a repeat class, see MaskHit
+fn check_repeat_class(repeat_class: &Option<String>, data_type: &str) -> Result<()> {
+    if repeat_class.is_some() && data_type != MASKS_DIR {
+        return Err(invalid_option(
+            "Only Masks Have A Repeat Class, Repeat Class Filter Cannot Be Applied",
+        ));
     }
+    Ok(())
+}
 
-    let worker_count: NonZeroUsize = match NonZeroUsize::new(10) {
-        Some(n) => n,
-        None => unreachable!(),
-    };
+fn check_bias(max_bias: &Option<f64>, data_type: &str) -> Result<()> {
+    if max_bias.is_some() && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No Bias Column, Bias Threshold Cannot Be Applied",
+        ));
+    }
+    Ok(())
+}
 
-    let in_f = File::open(in_tsv).expect("Could Not Open Input File");
-    let lines = BufReader::new(in_f).lines();
-    let mut current_acc = "".to_string();
-    let mut out_f = tempfile()?;
-    let mut out_writer = bgzf::MultithreadedWriter::with_worker_count(worker_count, out_f);
-    let mut seen_accs = Vec::new();
-    for result in lines {
-        let line = result?;
-        if !&line.starts_with('#') {
-            let fields: Vec<_> = line.split("\t").collect();
-            let output = FormattableLine::from_export_tsv(&fields, data_type);
-            let out_acc = output.get_acc();
-            if out_acc != current_acc {
-                if seen_accs.contains(&out_acc) {
-                    panic!("Input TSV is not sorted in accession order")
-                } else {
-                    seen_accs.push(out_acc.clone());
-                }
-                // assume accession order TODO confirm this
-                println!("\t{out_acc}");
-                current_acc = out_acc;
-                out_f = File::create(format!("{target_dir}/{current_acc}.bed.bgz",))
-                    .expect("Could Not Open Output File");
-                out_writer = bgzf::MultithreadedWriter::with_worker_count(worker_count, out_f);
-            };
-            out_writer
-                .write_all(format!("{}\n", output.to_bed_fmt().join("\t")).as_bytes())
-                .expect("Unable to write line");
+// An option value the query or filter cannot apply, answered as bad input rather than a panic
+fn invalid_option(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidInput, message.into())
+}
+
+// Compressed bytes per reader worker when filtering a file, beyond the first
+const FILTER_BYTES_PER_WORKER: u64 = 64 << 20;
+
+// Reader workers for a file of this size, one per FILTER_BYTES_PER_WORKER up to the cores
+// available, so small files skip the thread pool setup large files make up for
+pub fn filter_worker_count(file_size: u64) -> NonZeroUsize {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let workers = (file_size / FILTER_BYTES_PER_WORKER + 1).min(cores as u64) as usize;
+    NonZeroUsize::new(workers).unwrap_or(NonZeroUsize::MIN)
+}
+
+// Compression of filter output on the command line. Historical Dfam download files are plain
+// gzip, and some pipelines check for the gzip magic without the BGZF extra field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputCompression {
+    Bgzf,
+    GzipPlain,
+}
+
+// Unix in the gzip header OS field, as gzip itself writes on Linux
+const GZIP_OS_UNIX: u8 = 3;
+
+impl OutputCompression {
+    // Plain gzip when asked for. An outfile named .bgz would be taken for BGZF by readers that
+    // go by the name, so it is refused with plain gzip; a .gz name suits either.
+    pub fn for_outfile(outfile: &Option<String>, gzip_plain: bool) -> Result<OutputCompression> {
+        match (outfile, gzip_plain) {
+            (Some(outfile), true) if outfile.ends_with(".bgz") => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Plain Gzip Output {} Should End In .gz, .bgz Names BGZF Files",
+                    outfile
+                ),
+            )),
+            (_, true) => Ok(OutputCompression::GzipPlain),
+            (_, false) => Ok(OutputCompression::Bgzf),
         }
     }
+}
 
-    Ok(())
+// A single member gzip stream with the header gzip writes: the time written and the OS
+fn gzip_plain_writer<W: Write + 'static>(writer: W) -> Box<dyn Write> {
+    let mtime = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    Box::new(
+        GzBuilder::new()
+            .mtime(mtime)
+            .operating_system(GZIP_OS_UNIX)
+            .write(writer, Compression::default()),
+    )
 }
 
+// The writer filtered records go to on the command line: appended to the outfile as BGZF,
+// with as many workers as the input file was read with, else BGZF on stdout. Plain gzip is
+// written by one thread whatever the workers. At most max_buffered_bytes of compressed
+// output wait for the outfile, see BoundedWriter.
+pub fn filter_writer(
+    outfile: &Option<String>,
+    workers: NonZeroUsize,
+    compression: OutputCompression,
+    max_buffered_bytes: u64,
+) -> Result<Box<dyn Write>> {
+    let open = |outfile: &String| -> Result<BoundedWriter<File>> {
+        let out_f = OpenOptions::new().create(true).append(true).open(outfile)?;
+        Ok(BoundedWriter::new(out_f, max_buffered_bytes))
+    };
+    Ok(match (outfile, compression) {
+        (Some(outfile), OutputCompression::GzipPlain) => gzip_plain_writer(open(outfile)?),
+        (Some(outfile), OutputCompression::Bgzf) => {
+            let out_f = open(outfile)?;
+            if workers.get() > 1 {
+                Box::new(bgzf::MultithreadedWriter::with_worker_count(workers, out_f))
+            } else {
+                Box::new(bgzf::Writer::new(out_f))
+            }
+        }
+        (None, OutputCompression::GzipPlain) => gzip_plain_writer(stdout()),
+        (None, OutputCompression::Bgzf) => Box::new(bgzf::Writer::new(stdout())),
+    })
+}
+
+// The parameters of a filter as the query log and export manifests record them
+pub fn filter_parameters(data_type: &str, key: &str, options: &FilterOptions) -> Value {
+    json!({
+        "data_type": data_type,
+        "key": key,
+        "position": options.position,
+        "term": options.term,
+        "exact_version": options.exact_version,
+        "strand": options.strand,
+        "max_bias": options.max_bias,
+        "strict_bias": options.strict_bias,
+        "dl_fmt": options.dl_fmt,
+        "profile": options.profile,
+        "bed_format": options.bed_format.map(|f| format!("{:?}", f)),
+        "with_family_meta": options.with_family_meta,
+        "ignore_redaction": options.ignore_redaction,
+    })
+}
+
+// The parameters of an index query as the query log records them
+pub fn query_parameters(
+    data_type: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    options: &IdxQueryOptions,
+) -> Value {
+    json!({
+        "data_type": data_type,
+        "chrom": chrom,
+        "start": start,
+        "end": end,
+        "family": options.family,
+        "exact_version": options.exact_version,
+        "nrph": options.nrph,
+        "strand": options.strand,
+        "min_bit_score": options.min_bit_score,
+        "max_e_value": options.max_e_value,
+        "max_bias": options.max_bias,
+        "strict_bias": options.strict_bias,
+        "repeat_class": options.repeat_class,
+        "downsample": options.downsample,
+        "timeout_ms": options.timeout.map(|t| t.as_millis() as u64),
+        "partial": options.partial,
+        "with_model_length": options.with_model_length,
+        "with_family_meta": options.with_family_meta,
+        "json_seq_len": options.json_seq_len,
+        "subtract_masks": options.subtract_masks.as_ref().map(|s| json!({
+            "mode": format!("{:?}", s.mode),
+            "min_overlap": s.min_overlap,
+        })),
+        "group_by": options.group_by.as_ref().map(|g| json!({
+            "counts_only": g.counts_only,
+            "per_group_limit": g.per_group_limit,
+        })),
+        "bed_format": options.bed_format.map(|f| format!("{:?}", f)),
+        "fast_path": configured_fast_path(options.fast_path),
+        "allow_scan": configured_allow_scan(options.allow_scan),
+        "ignore_redaction": options.ignore_redaction,
+    })
+}
+
+// The identity of the index of a data type for etags: its format version, size, modification
+// time and the versioned file it points to. An index that cannot be read is identified by the
+// names, sizes and modification times of the bgz files a scan would read instead.
+fn index_identity(assembly: &str, data_type: &str, data_directory: &str) -> Result<Value> {
+    let (bgz_dir, index_file) = idx::idx_paths(assembly, data_type, data_directory);
+    let file_identity = |path: &str| -> Result<Value> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(json!([metadata.len(), modified.as_nanos().to_string()]))
+    };
+    match idx::index_format_version(&index_file) {
+        Ok(version) => {
+            let versioned = std::fs::read_link(&index_file).ok().and_then(|target| {
+                target
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            });
+            Ok(json!({
+                "format_version": version,
+                "file": file_identity(&index_file)?,
+                "versioned": versioned,
+            }))
+        }
+        Err(_) => {
+            let mut files = serde_json::Map::new();
+            for name in idx::stored_bgz_names(&bgz_dir)? {
+                let identity = file_identity(&stored_path(&bgz_dir, &name))?;
+                files.insert(name, identity);
+            }
+            Ok(json!({ "scanned": files }))
+        }
+    }
+}
+
+// A validator of an index query's response for caches, the hex SHA-256 of the identity of the
+// index and the normalized query. Nothing is searched, so a cached response can be checked
+// before the query is run. The same query of the same index always has the same etag, whatever
+// its with_etag and head, so a head query gives the etag of the full response.
+pub fn query_etag(
+    assembly: &str,
+    data_type: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    options: &IdxQueryOptions,
+    data_directory: impl AsRef<Path>,
+) -> Result<String> {
+    let data_directory = &path_string(data_directory);
+    check_allowed(assembly, Some(data_type), data_directory)?;
+    let mut query = query_parameters(data_type, chrom, start, end, options);
+    // Families in any order or repeated are the same filter, and the fast path the same data
+    let mut families = idx::query_families(&options.family).unwrap_or_default();
+    families.sort();
+    families.dedup();
+    query["family"] = json!(families);
+    query["fast_path"] = Value::Null;
+    query["legacy_json"] = json!(options.legacy_json);
+    query["with_warnings"] = json!(options.with_warnings);
+    query["strict"] = json!(options.strict);
+    query["resolve_names"] = json!(options.resolve_names);
+    let validated = json!({
+        "te_idx_version": env!("CARGO_PKG_VERSION"),
+        "assembly": assembly,
+        "index": index_identity(assembly, data_type, data_directory)?,
+        "query": query,
+    });
+    Ok(format!(
+        "{:x}",
+        Sha256::digest(validated.to_string().as_bytes())
+    ))
+}
+
+#[deprecated(note = "Use bgzf_filter_with and FilterOptions")]
+#[allow(clippy::too_many_arguments, clippy::ptr_arg)] // The old signature, kept as it was
+pub fn bgzf_filter(
+    assembly: &String,
+    data_type: &String,
+    key: &String,
+    position: &usize,
+    term: &Option<String>,
+    strand: &Option<String>,
+    writer: &mut dyn Write,
+    dl_fmt: bool,
+    profile: &str,
+    exclude_columns: &[String],
+    source_meta: bool,
+    prefilter: bool,
+    query_log: &Option<QueryLogger>,
+    data_directory: &String,
+) -> Result<usize> {
+    let options = FilterOptions {
+        position: *position,
+        term: term.clone(),
+        strand: strand.clone(),
+        dl_fmt,
+        profile: profile.to_string(),
+        exclude_columns: exclude_columns.to_vec(),
+        source_meta,
+        prefilter,
+        query_log: query_log.clone(),
+        ..FilterOptions::default()
+    };
+    bgzf_filter_with(assembly, data_type, key, writer, &options, data_directory)
+}
+
+// Writes the header and the matching records of one <key>.bed.bgz file to writer as plain
+// text, returning the number of records written. The key is a family accession, or a
+// sequence accession for masks, see PARTITION_KEYS. Compressing is up to the writer.
+// A bed_format writes the records in that layout without any header.
+pub fn bgzf_filter_with(
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    writer: &mut dyn Write,
+    options: &FilterOptions,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let (assembly, data_type, key) = (
+        &assembly.to_string(),
+        &data_type.to_string(),
+        &key.to_string(),
+    );
+    let data_directory = &path_string(data_directory);
+    logged(
+        &options.query_log,
+        "bgzf_filter",
+        assembly,
+        filter_parameters(data_type, key, options),
+        |count: &usize| *count,
+        || bgzf_filter_inner(assembly, data_type, key, writer, options, data_directory),
+    )
+}
+
+fn bgzf_filter_inner(
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    writer: &mut dyn Write,
+    options: &FilterOptions,
+    data_directory: &str,
+) -> Result<usize> {
+    let FilterOptions {
+        position,
+        term,
+        exact_version,
+        strand,
+        max_bias,
+        strict_bias,
+        profile,
+        exclude_columns,
+        bed_format,
+        with_family_meta,
+        ignore_redaction,
+        ..
+    } = options;
+    let (dl_fmt, source_meta, prefilter) = (options.dl_fmt, options.source_meta, options.prefilter);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    check_layout(assembly, data_directory)?;
+    let assembly_path: String = join_path(data_directory, &[assembly, data_type]);
+    if !Path::new(&assembly_path).exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Data \"{}\" Does Not Exist", assembly_path),
+        ));
+    }
+    check_strand(strand, data_type)?;
+    check_bias(max_bias, data_type)?;
+    check_partition_key(data_type, key)?;
+    if term.is_some() && *position == 0 {
+        return Err(invalid_option(
+            "Invalid Term Position 0, Columns Count From 1",
+        ));
+    }
+    if *with_family_meta && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No Family Column, Family Metadata Cannot Be Added",
+        ));
+    }
+    check_bed_format(
+        bed_format,
+        options.resolve_names,
+        &[
+            ("Web Format", dl_fmt),
+            ("Source Info", source_meta),
+            ("Excluded Columns", !exclude_columns.is_empty()),
+            ("Family Metadata", *with_family_meta),
+        ],
+    )?;
+    let redact = redacts_accessions(assembly, *ignore_redaction, data_directory)?;
+    let chrom_names = bed_chrom_names(assembly, options.resolve_names || redact, data_directory)?;
+    let columns = output_columns(data_type, dl_fmt);
+    let keep = selected_columns(data_type, dl_fmt, profile, exclude_columns)?;
+    let key_file = key_bgz_path(&assembly_path, data_type, key, *exact_version)?;
+    if !Path::new(&key_file).exists() {
+        let label = partition_key_label(data_type);
+        let message =
+            match family_data_type_hint(&join_path(data_directory, &[assembly]), key, data_type) {
+                Some(other) => format!(
+                    "{} {} Not Found In Assembly {} - found under {} — did you mean -d {}?",
+                    label, &key, assembly_path, other, other
+                ),
+                None => format!("{} {} Not Found In Assembly {}", label, &key, assembly_path),
+            };
+        return Err(Error::new(ErrorKind::NotFound, message));
+    }
+    // A family missing from the data type is not found whatever its coordinates
+    check_half_open(assembly, data_type, data_directory)?;
+
+    if bgz_is_empty(&key_file)? {
+        eprintln!(
+            "Warning: {} {} Has No Records In Assembly {}",
+            partition_key_label(data_type),
+            key,
+            assembly
+        );
+    }
+
+    let in_f = File::open(&key_file)?;
+    let worker_count = filter_worker_count(in_f.metadata()?.len());
+    let records = BedRecordStream::new(
+        bgzf::MultithreadedReader::with_worker_count(worker_count, in_f),
+        &key_file,
+    );
+
+    let mut preamble = String::new();
+    if source_meta {
+        for meta in source_info(assembly, data_type, data_directory)? {
+            let meta = meta.trim_start_matches('#').trim();
+            if !meta.is_empty() {
+                preamble.push_str(&format!("##{}\n", meta));
+            }
+        }
+    }
+    writer.write_all(preamble.as_bytes())?;
+
+    let mut out = DlWriter::new(&mut *writer, columns).select(&keep);
+    // Every record of the file is of its family, the fields are looked up once
+    if *with_family_meta {
+        let family_fields = families::FamilyMetadata::load(assembly, data_directory)?.fields(key);
+        out = out.trailing(&families::FAMILY_META_COLUMNS, family_fields.to_vec());
+    }
+    if bed_format.is_none() {
+        out.write_header()?;
+    }
+
+    let mut hmm_len = "0".to_string();
+    if dl_fmt {
+        let assembly_data = AssemblyData::load(assembly, data_directory)?;
+        hmm_len = json_lookup(
+            assembly_data.data(MOD_LEN_DIR),
+            key,
+            &Some("length".to_string()),
+        )
+        .unwrap_or("-1".to_string());
+    }
+
+    // A term on the family column matches as idx_query's family filter does, see
+    // idx::acc_matches, so the prefilter only looks for its unversioned accession
+    let family_term =
+        *position == idx::FAMILY_COLUMN + 1 && partition_key(data_type) == FAM_ACC_KEY;
+    let term_matches = |field: &str, term: &str| match family_term {
+        true => idx::acc_matches(term, field, *exact_version),
+        false => field == term,
+    };
+    let prefilter_term = term.as_deref().map(|term| match family_term {
+        true => idx::unversioned_acc(term.trim()),
+        false => term,
+    });
+
+    let mut column_check = ColumnCheck::default();
+    for record in records {
+        let record = record?;
+        let line = &record.line;
+        // A line without the term anywhere cannot match it. This only skips lines, the
+        // column comparison below still decides every line that contains the term.
+        if prefilter && prefilter_term.is_some_and(|term| !line.contains(term)) {
+            continue;
+        }
+        let mut fields = column_check.check(record.fields(), data_type);
+        if (term.is_none()
+            || term.as_ref().is_some_and(|term| {
+                fields
+                    .get(position - 1)
+                    .is_some_and(|field| term_matches(field, term))
+            }))
+            && (strand.is_none() || fields.get(idx::STRAND_COLUMN).copied() == strand.as_deref())
+            && max_bias
+                .is_none_or(|max_bias| idx::bias_within(&fields, max_bias).unwrap_or(!strict_bias))
+        {
+            if redact {
+                fields[0] = display_name(&chrom_names, fields[0])?;
+            }
+            if let Some(format) = bed_format {
+                let chrom = chrom_names.get(fields[0]).map_or(fields[0], String::as_str);
+                if let Some(bed_line) = standard_bed_line(&fields, data_type, *format, chrom) {
+                    out.write_line(&bed_line)?;
+                }
+            } else if dl_fmt {
+                let chrom_id = &fields[0].to_string();
+                let formatted_line = FormattableLine::from_bed(&fields, data_type);
+                out.write_record(&formatted_line.to_dl_fmt(chrom_id, &hmm_len))?;
+            } else {
+                out.write_record(&fields)?;
+            }
+        }
+    }
+    let count = out.finish()?;
+    column_check.warn(assembly, data_type);
+    Ok(count)
+}
+
+// Idx Query Fast Path ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// On a network filesystem every stat of a bgz file is a round trip, and a query checking the
+// whole data type folder against its index makes one per file. Data that is never changed in
+// place, e.g. a read-only production mount, can skip those checks with --fast-path or
+// $TE_IDX_ASSUME_IMMUTABLE=1: only the index and the files holding hits are opened, a missing
+// one is still skipped or fails a strict query, but stale files go unwarned about.
+pub const ASSUME_IMMUTABLE_ENV: &str = "TE_IDX_ASSUME_IMMUTABLE";
+
+// Whether a query takes the fast path, asked for by argument or environment
+pub fn configured_fast_path(fast_path: bool) -> bool {
+    fast_path
+        || std::env::var(ASSUME_IMMUTABLE_ENV)
+            .is_ok_and(|value| !matches!(value.as_str(), "" | "0"))
+}
+
+// A query of a data type whose index is missing or unreadable, as for a new assembly or after
+// a failed build, fails unless a scan of every bgz file is allowed with --allow-scan or
+// $TE_IDX_ALLOW_SCAN=1, see idx::scan_search. Scans are slow on large assemblies, so they are
+// never the default.
+pub const ALLOW_SCAN_ENV: &str = "TE_IDX_ALLOW_SCAN";
+
+// Whether a query may scan without the index, allowed by argument or environment
+pub fn configured_allow_scan(allow_scan: bool) -> bool {
+    allow_scan
+        || std::env::var(ALLOW_SCAN_ENV).is_ok_and(|value| !matches!(value.as_str(), "" | "0"))
+}
+
+// Scratch Space ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Temporary files go to the scratch directory, the one passed in, else $TE_IDX_SCRATCH_DIR,
+// else the data directory so finished files are moved into place without a copy
+pub const SCRATCH_DIR_ENV: &str = "TE_IDX_SCRATCH_DIR";
+pub const DEFAULT_MIN_SCRATCH_FREE_MB: u64 = 1024;
+
+// The scratch directory asked for, by argument or environment, if any
+pub fn configured_scratch_dir(scratch_directory: &Option<String>) -> Option<String> {
+    match scratch_directory {
+        Some(dir) => Some(dir.to_string()),
+        None => std::env::var(SCRATCH_DIR_ENV)
+            .ok()
+            .filter(|dir| !dir.is_empty()),
+    }
+}
+
+pub fn scratch_dir(scratch_directory: &Option<String>, data_directory: impl AsRef<Path>) -> String {
+    let data_directory = &path_string(data_directory);
+    configured_scratch_dir(scratch_directory).unwrap_or(data_directory.to_string())
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // The statvfs field types differ between platforms
+fn free_bytes(dir: &str) -> Result<u64> {
+    let path = CString::new(dir.as_bytes()).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// Free space is not measured elsewhere, the check only asks for the directory
+#[cfg(not(unix))]
+fn free_bytes(_dir: &str) -> Result<u64> {
+    Ok(u64::MAX)
+}
+
+// Fails unless the scratch directory exists with at least min_free_mb available on its
+// filesystem. Returns the free MB.
+pub fn check_scratch_dir(dir: impl AsRef<Path>, min_free_mb: u64) -> Result<u64> {
+    let dir = &path_string(dir);
+    if !Path::new(dir).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Scratch Directory \"{}\" Does Not Exist, Create It Or Choose Another With --scratch-dir",
+                dir
+            ),
+        ));
+    }
+    let free_mb = free_bytes(dir)? >> 20;
+    if free_mb < min_free_mb {
+        return Err(Error::new(
+            ErrorKind::StorageFull,
+            format!(
+                "Scratch Directory \"{}\" Has {} MB Free, Less Than The {} MB Required, Choose Another With --scratch-dir",
+                dir, free_mb, min_free_mb
+            ),
+        ));
+    }
+    Ok(free_mb)
+}
+
+// A temporary file in the scratch directory, removed when dropped unless persisted
+pub fn scratch_file(
+    scratch_directory: &Option<String>,
+    data_directory: impl AsRef<Path>,
+) -> Result<tempfile::NamedTempFile> {
+    let data_directory = &path_string(data_directory);
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".te_idx-").suffix(".tmp");
+    // As File::create would, so the permissions of persisted files follow the umask
+    #[cfg(unix)]
+    builder.permissions(std::fs::Permissions::from_mode(0o666));
+    builder.tempfile_in(scratch_dir(scratch_directory, data_directory))
+}
+
+// Moves a finished scratch file to its target, copying when the scratch directory is on
+// another filesystem
+pub fn persist_scratch(temp_path: TempPath, target: &str) -> Result<()> {
+    match temp_path.persist(target) {
+        Ok(()) => Ok(()),
+        Err(e) if e.error.kind() == ErrorKind::CrossesDevices => {
+            copy(&e.path, target)?;
+            Ok(())
+        }
+        Err(e) => Err(e.error),
+    }
+}
+
+// Setup Methods ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Record hashes kept per family when deduplicating, beyond this only adjacent duplicates are caught
+pub const DEDUPE_HASH_CAP: usize = 1_000_000;
+// Accessions prep_beds writes to one folder before switching the data type to shard folders
+pub const DEFAULT_SHARD_THRESHOLD: usize = 10_000;
+
+// Shard folder of an accession in the sharded layout, <data_type>/<shard>/<acc>.bed.bgz. The
+// first byte of a SHA-256 keeps the 256 shards even and stable across releases and platforms
+pub fn shard_dir(acc: &str) -> String {
+    format!("{:02x}", Sha256::digest(acc.as_bytes())[0])
+}
+
+// Path of an accession's BED file in a data type folder, in either layout
+pub fn bgz_path(data_path: impl AsRef<Path>, acc: &str) -> String {
+    let data_path = &path_string(data_path);
+    let flat = join_path(data_path, &[&format!("{}.bed.bgz", acc)]);
+    if Path::new(&flat).exists() {
+        return flat;
+    }
+    join_path(data_path, &[&shard_dir(acc), &format!("{}.bed.bgz", acc)])
+}
+
+// The BED file of a key in a data type folder. Family files are named by the accession as
+// exported, with or without its version, so a family key naming no file is looked for among
+// the files as idx::acc_matches compares them. A key matching several files, as two versions of
+// a family, is an error rather than one picked. Sequence keys are taken as they are.
+pub fn key_bgz_path(
+    data_path: impl AsRef<Path>,
+    data_type: &str,
+    key: &str,
+    exact_version: bool,
+) -> Result<String> {
+    let data_path = &path_string(data_path);
+    let path = bgz_path(data_path, key);
+    if Path::new(&path).exists()
+        || partition_key(data_type) != FAM_ACC_KEY
+        || !Path::new(data_path).is_dir()
+    {
+        return Ok(path);
+    }
+    let mut names: Vec<String> = idx::stored_bgz_names(data_path)?
+        .into_iter()
+        .filter(|name| idx::acc_matches(key, idx::stored_accession(name), exact_version))
+        .collect();
+    match names.len() {
+        0 => Ok(path),
+        1 => Ok(stored_path(data_path, &names.pop().unwrap())),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Family {} Matches Several Files In {}: {} - Pass The Versioned Accession With Exact Version",
+                key,
+                data_path,
+                names.join(", ")
+            ),
+        )),
+    }
+}
+
+// Whether a BED file holds no records, as left for a family without hits in the assembly by
+// filtered or appended data. The file may be no more than the BGZF end-of-file block.
+pub fn bgz_is_empty(bed_file: &str) -> Result<bool> {
+    Ok(BedRecordStream::open(bed_file)?.read_record()?.is_none())
+}
+
+// The families of the list without a BED file of a data type in an assembly, in the order
+// given, versions ignored. With empty_as_missing a file without records counts as missing.
+pub fn missing_families(
+    assembly: &str,
+    data_type: &str,
+    families: &[String],
+    empty_as_missing: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<String>> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    let data_path = join_path(data_directory, &[assembly, data_type]);
+    let mut missing = Vec::new();
+    for family in families {
+        check_partition_key(data_type, family)?;
+        let fam_file = key_bgz_path(&data_path, data_type, family.trim(), false)?;
+        if !Path::new(&fam_file).exists() || (empty_as_missing && bgz_is_empty(&fam_file)?) {
+            missing.push(family.to_string());
+        }
+    }
+    Ok(missing)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FamilyListing {
+    pub accession: String,
+    pub records: Option<u64>, // None when listed from the folder, counting reads every file
+    pub bytes: u64,
+    pub bed_idx: Option<u32>, // The file's number in the current index, None from the folder
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FamilyPage {
+    pub total: usize, // Families matching the prefix, before offset and limit
+    pub families: Vec<FamilyListing>,
+}
+
+fn family_listing_path(assembly: &str, data_type: &str, data_directory: &str) -> String {
+    idx::family_listing_file(&join_path(
+        data_directory,
+        &[assembly, &format!("{}_idx.dat", data_type)],
+    ))
+}
+
+// Dropped whenever the BED files of a data type change, until build_idx lists them again
+fn remove_family_listing(assembly: &str, data_type: &str, data_directory: &str) -> Result<()> {
+    let listing_file = family_listing_path(assembly, data_type, data_directory);
+    if Path::new(&listing_file).exists() {
+        std::fs::remove_file(&listing_file)?;
+    }
+    Ok(())
+}
+
+fn parse_family_listing(line: &str, listing_file: &str) -> Result<FamilyListing> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Malformed Line In Family Listing {}: {}",
+                listing_file, line
+            ),
+        )
+    };
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 4 {
+        return Err(invalid());
+    }
+    Ok(FamilyListing {
+        accession: fields[0].to_string(),
+        records: Some(fields[1].parse().map_err(|_| invalid())?),
+        bytes: fields[2].parse().map_err(|_| invalid())?,
+        bed_idx: Some(fields[3].parse().map_err(|_| invalid())?),
+    })
+}
+
+// The families, or sequences for masks, with a BED file of a data type in an assembly, sorted
+// by accession. Only accessions starting with prefix are counted, and of those offset are
+// skipped and at most limit returned. Read from the listing build_idx leaves beside the index;
+// without one, as after prep_beds or append_records until the index is rebuilt, the folder is
+// listed instead with a warning, and records and bed_idx are left out.
+pub fn list_families(
+    assembly: &str,
+    data_type: &str,
+    prefix: Option<&str>,
+    limit: Option<usize>,
+    offset: usize,
+    data_directory: impl AsRef<Path>,
+) -> Result<FamilyPage> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    let prefix = prefix.unwrap_or("");
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut page = FamilyPage::default();
+    let mut add = |listing: FamilyListing| {
+        if page.total >= offset && page.families.len() < limit {
+            page.families.push(listing);
+        }
+        page.total += 1;
+    };
+    let listing_file = family_listing_path(assembly, data_type, data_directory);
+    if Path::new(&listing_file).exists() {
+        for line in BufReader::new(File::open(&listing_file)?).lines() {
+            let line = line?;
+            if line.starts_with('#') || line.is_empty() || !line.starts_with(prefix) {
+                continue;
+            }
+            add(parse_family_listing(&line, &listing_file)?);
+        }
+        return Ok(page);
+    }
+    eprintln!(
+        "Warning: No Family Listing For {} Of {}, Listing The Folder Until The Index Is Rebuilt",
+        data_type, assembly
+    );
+    let data_path = join_path(data_directory, &[assembly, data_type]);
+    let mut families = Vec::new();
+    for name in idx::stored_bgz_names(&data_path)? {
+        let accession = idx::stored_accession(&name);
+        if accession.starts_with(prefix) {
+            let bytes = std::fs::metadata(paths::stored_path(&data_path, &name))?.len();
+            families.push(FamilyListing {
+                accession: accession.to_string(),
+                records: None,
+                bytes,
+                bed_idx: None,
+            });
+        }
+    }
+    families.sort_by(|a, b| a.accession.cmp(&b.accession));
+    families.into_iter().for_each(add);
+    Ok(page)
+}
+
+// Moves the flat BED files of these accessions into their shard folders
+fn shard_bgz_files(target_dir: &str, accs: &[String]) -> Result<()> {
+    for acc in accs {
+        let shard = join_path(target_dir, &[&shard_dir(acc)]);
+        create_dir_all(&shard)?;
+        rename(
+            join_path(target_dir, &[&format!("{}.bed.bgz", acc)]),
+            join_path(&shard, &[&format!("{}.bed.bgz", acc)]),
+        )?;
+    }
+    Ok(())
+}
+
+// Records kept in memory per accession while sorting, past this they are spilled to scratch
+pub const DEFAULT_SORT_BUFFER_BYTES: usize = 256 * 1024 * 1024;
+// Written in the assembly folder as <data_type>.sorted when the BED files of the data type were
+// prepared in sorted order, see beds_sorted. Kept out of the data type folder, which holds the
+// BED files only.
+pub const SORTED_FILE: &str = ".sorted";
+// Written in the assembly folder as <data_type>.half_open by prep_beds, whose BED files hold
+// 0-based, half open coordinates, see records::bed_coordinates. Data types prepared before
+// hold the exported coordinates until migrate-assembly converts them.
+pub const HALF_OPEN_FILE: &str = ".half_open";
+
+// Rank of each contig of a sequences JSON file, as contig_ranks gives them once it is the
+// assembly's, the assembly's contig_order.txt applied
+fn sequence_file_ranks(
+    assembly: &str,
+    sequences_file: &str,
+    data_directory: &str,
+) -> Result<HashMap<String, usize>> {
+    let sequences: Value = serde_json::from_str(&read_to_string(sequences_file)?).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{} - {}", sequences_file, e),
+        )
+    })?;
+    let sequences = parse_sequence_info(assembly, sequences.get("data").unwrap_or(&Value::Null));
+    Ok(contig_order::contig_order_of(assembly, &sequences, data_directory)?.ranks())
+}
+
+// Rank of each contig in the contig order of an assembly, see contig_order, empty before its
+// sequences JSON is prepared
+fn contig_ranks(assembly: &str, data_directory: &str) -> HashMap<String, usize> {
+    let assembly_path = join_path(data_directory, &[assembly]);
+    let sequences_file = join_path(
+        &assembly_path,
+        &[SEQUENCE_DIR, &format!("{}{}", assembly, SEQUENCE_FILE)],
+    );
+    if !Path::new(&join_path(&assembly_path, &[ASSEMBLY_DATA_FILE])).exists()
+        && !Path::new(&sequences_file).exists()
+    {
+        return HashMap::new();
+    }
+    match contig_order::contig_order(assembly, data_directory) {
+        Ok(order) => order.ranks(),
+        Err(e) => {
+            eprintln!(
+                "Warning: Contigs Sorted By Name, Contig Order Of {} Unreadable - {}",
+                assembly, e
+            );
+            HashMap::new()
+        }
+    }
+}
+
+// Contigs in their contig order, those missing from it after them, then records in
+// idx::RecordOrder
+fn contig_rank(ranks: &HashMap<String, usize>, contig: &str) -> usize {
+    ranks.get(contig).copied().unwrap_or(usize::MAX)
+}
+
+// The next line of a spilled run, ordered as contig_rank and idx::RecordOrder sort it
+struct RunHead {
+    rank: usize,
+    line: String,
+    run: usize,
+}
+
+impl Ord for RunHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.rank, idx::RecordOrder::new(&self.line), self.run).cmp(&(
+            other.rank,
+            idx::RecordOrder::new(&other.line),
+            other.run,
+        ))
+    }
+}
+
+impl PartialOrd for RunHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RunHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for RunHead {}
+
+// The BED lines of one accession, written sorted by contig_rank once all are added. Past
+// buffer_bytes the buffered lines are sorted and spilled to a scratch file, and the spilled
+// runs merged when the lines are written.
+struct BedSorter<'a> {
+    ranks: &'a HashMap<String, usize>,
+    buffer_bytes: usize,
+    scratch: (&'a Option<String>, &'a str),
+    buffered: Vec<String>,
+    bytes: usize,
+    runs: Vec<TempPath>,
+}
+
+impl<'a> BedSorter<'a> {
+    fn add(&mut self, line: String) -> Result<()> {
+        self.bytes += line.len() + std::mem::size_of::<String>();
+        self.buffered.push(line);
+        if self.bytes > self.buffer_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_buffered(&mut self) -> Vec<String> {
+        let mut lines = std::mem::take(&mut self.buffered);
+        idx::sort_records(&mut lines, |contig| contig_rank(self.ranks, contig));
+        self.bytes = 0;
+        lines
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        let lines = self.sort_buffered();
+        let (run_f, run_path) = scratch_file(self.scratch.0, self.scratch.1)?.into_parts();
+        let mut run = BufWriter::new(run_f);
+        for line in &lines {
+            writeln!(run, "{}", line)?;
+        }
+        run.flush()?;
+        self.runs.push(run_path);
+        Ok(())
+    }
+
+    fn run_head(&self, line: String, run: usize) -> RunHead {
+        let rank = contig_rank(self.ranks, idx::RecordOrder::new(&line).contig());
+        RunHead { rank, line, run }
+    }
+
+    // Writes every line added, in order, and makes the sorter ready for the next accession
+    fn write_sorted(&mut self, writer: &mut dyn Write) -> Result<()> {
+        let lines = self.sort_buffered();
+        if self.runs.is_empty() {
+            for line in lines {
+                writeln!(writer, "{}", line)?;
+            }
+            return Ok(());
+        }
+        // Identical lines are merged oldest run first, the buffered lines being the newest run
+        let mut runs: Vec<Box<dyn Iterator<Item = Result<String>>>> = Vec::new();
+        for run_path in &self.runs {
+            runs.push(Box::new(BufReader::new(File::open(run_path)?).lines()));
+        }
+        runs.push(Box::new(lines.into_iter().map(Ok)));
+        let mut heads = BinaryHeap::new();
+        for (run, lines) in runs.iter_mut().enumerate() {
+            if let Some(line) = lines.next() {
+                let line = line?;
+                heads.push(Reverse(self.run_head(line, run)));
+            }
+        }
+        while let Some(Reverse(RunHead { line, run, .. })) = heads.pop() {
+            writeln!(writer, "{}", line)?;
+            if let Some(next) = runs[run].next() {
+                heads.push(Reverse(self.run_head(next?, run)));
+            }
+        }
+        self.runs.clear();
+        Ok(())
+    }
+}
+
+fn sorted_file(assembly: &str, data_type: &str, data_directory: &str) -> String {
+    join_path(
+        data_directory,
+        &[assembly, &format!("{}{}", data_type, SORTED_FILE)],
+    )
+}
+
+// Whether the BED files of a data type were prepared sorted by contig and position, see
+// PrepOptions. Appending records to a file clears it.
+pub fn beds_sorted(assembly: &str, data_type: &str, data_directory: impl AsRef<Path>) -> bool {
+    let data_directory = &path_string(data_directory);
+    Path::new(&sorted_file(assembly, data_type, data_directory)).exists()
+}
+
+pub fn half_open_file(assembly: &str, data_type: &str, data_directory: &str) -> String {
+    join_path(
+        data_directory,
+        &[assembly, &format!("{}{}", data_type, HALF_OPEN_FILE)],
+    )
+}
+
+// Whether the BED files of a data type hold BED coordinates, as prep_beds writes them, rather
+// than the exported ones of data types prepared before, see migrate
+pub fn beds_half_open(assembly: &str, data_type: &str, data_directory: &str) -> bool {
+    Path::new(&half_open_file(assembly, data_type, data_directory)).exists()
+}
+
+// Fails with an Unsupported error for a data type still holding exported coordinates. Every
+// reader takes BED lines for BED coordinates, on older files its hits would be off by one.
+pub fn check_half_open(assembly: &str, data_type: &str, data_directory: &str) -> Result<()> {
+    if !beds_half_open(assembly, data_type, data_directory) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} Of {} Holds Exported Coordinates - Run migrate-assembly First",
+                data_type, assembly
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct PrepReport {
+    pub records: usize,
+    pub duplicates: BTreeMap<String, usize>, // Removed duplicates per accession, with --dedupe
+    pub buffer: BufferStats,                 // Compressed output waiting for the scratch files
+    pub sorted: bool, // Each BED file is in contig and position order, see beds_sorted
+    pub spilled: usize, // Accessions too large to sort in memory, sorted through scratch files
+}
+
+impl PrepReport {
+    pub fn duplicate_count(&self) -> usize {
+        self.duplicates.values().sum()
+    }
+}
+
+#[deprecated(note = "Use prep_beds_with and PrepOptions")]
+#[allow(clippy::ptr_arg)] // The old signature, kept as it was
+pub fn prep_beds(
+    assembly: &String,
+    in_tsv: &String,
+    data_type: &String,
+    dedupe: bool,
+    shard_threshold: usize,
+    scratch_directory: &Option<String>,
+    data_directory: &String,
+) -> Result<PrepReport> {
+    let options = PrepOptions {
+        dedupe,
+        shard_threshold,
+        scratch_directory: scratch_directory.clone(),
+        ..PrepOptions::default()
+    };
+    prep_beds_with(assembly, in_tsv, data_type, &options, data_directory)
+}
+
+// Splits an export TSV, in accession order, into one compressed BED file per accession.
+// Records are handed to the compressing writers through a BoundedWriter, so a slow scratch
+// folder stalls the split rather than filling memory.
+pub fn prep_beds_with(
+    assembly: &str,
+    in_tsv: impl AsRef<Path>,
+    data_type: &str,
+    options: &PrepOptions,
+    data_directory: impl AsRef<Path>,
+) -> Result<PrepReport> {
+    let in_tsv = &path_string(in_tsv);
+    let data_directory = &path_string(data_directory);
+    let PrepOptions {
+        dedupe,
+        shard_threshold,
+        scratch_directory,
+        max_buffered_bytes,
+        sort,
+        sort_buffer_bytes,
+        status,
+        sequences_file,
+    } = options;
+    let (dedupe, shard_threshold) = (*dedupe, *shard_threshold);
+    if !Path::new(&in_tsv).exists() {
+        panic!("Input TSV \"{}\" Not Found", &in_tsv);
+    }
+
+    let db_dir = join_path(data_directory, &[assembly]);
+    let target_dir = join_path(&db_dir, &[data_type]);
+    if !Path::new(&db_dir).exists() {
+        create_dir_all(&db_dir)?;
+    }
+
+    if !Path::new(&target_dir).exists() {
+        create_dir_all(&target_dir)?;
+    }
+
+    let worker_count: NonZeroUsize = match NonZeroUsize::new(10) {
+        Some(n) => n,
+        None => unreachable!(),
+    };
+
+    let in_f = File::open(in_tsv).expect("Could Not Open Input File");
+    if let Some(status) = status {
+        status.phase(
+            &format!("prep-beds {}", data_type),
+            Some(in_f.metadata()?.len()),
+            "bytes",
+        );
+    }
+    let mut bytes_read = 0;
+    let lines = BufReader::new(in_f).lines();
+    let prepped_path = |acc: &String, sharded: bool| {
+        if sharded {
+            join_path(&target_dir, &[&shard_dir(acc), &format!("{acc}.bed.bgz")])
+        } else {
+            join_path(&target_dir, &[&format!("{acc}.bed.bgz")])
+        }
+    };
+    let mut current_acc = "".to_string();
+    // Each accession is written to scratch and moved to its BED file once complete, so an
+    // interrupted prep leaves no truncated files
+    let mut out_file: Option<(BoundedWriter<bgzf::MultithreadedWriter>, TempPath)> = None;
+    let mut seen_accs = Vec::new();
+    let mut source_header = Vec::new();
+    let mut report = PrepReport {
+        sorted: *sort,
+        ..PrepReport::default()
+    };
+    report.buffer.cap_bytes = *max_buffered_bytes;
+    let ranks = match (sort, sequences_file) {
+        (true, Some(sequences_file)) => {
+            sequence_file_ranks(assembly, sequences_file, data_directory)?
+        }
+        (true, None) => contig_ranks(assembly, data_directory),
+        (false, _) => HashMap::new(),
+    };
+    let mut sorter = BedSorter {
+        ranks: &ranks,
+        buffer_bytes: *sort_buffer_bytes,
+        scratch: (scratch_directory, data_directory),
+        buffered: Vec::new(),
+        bytes: 0,
+        runs: Vec::new(),
+    };
+    // Duplicate tracking for the current accession
+    let mut previous_line = String::new();
+    let mut record_hashes: HashSet<u64> = HashSet::new();
+    let mut hashes_capped = false;
+    // Past shard_threshold accessions the files written so far move to shard folders
+    let mut sharded = shard_threshold == 0;
+    for result in lines {
+        let line = result?;
+        if let Some(status) = status {
+            bytes_read += line.len() as u64 + 1;
+            status.progress(bytes_read);
+        }
+        if line.starts_with('#') {
+            source_header.push(line);
+        } else {
+            let fields: Vec<_> = line.split("\t").collect();
+            let output = FormattableLine::from_export_tsv(&fields, data_type);
+            output.check_coordinates(&line)?;
+            let out_acc = output.get_acc();
+            if out_acc != current_acc {
+                if seen_accs.contains(&out_acc) {
+                    panic!("Input TSV is not sorted in accession order")
+                } else {
+                    seen_accs.push(out_acc.clone());
+                }
+                // assume accession order TODO confirm this
+                println!("\t{out_acc}");
+                if let Some((mut writer, temp_path)) = out_file.take() {
+                    if *sort {
+                        report.spilled += usize::from(!sorter.runs.is_empty());
+                        sorter.write_sorted(&mut writer)?;
+                    }
+                    let (mut writer, buffer) = writer.finish()?;
+                    writer.finish()?;
+                    report.buffer.add(&buffer);
+                    persist_scratch(temp_path, &prepped_path(&current_acc, sharded))?;
+                }
+                current_acc = out_acc;
+                if !sharded && seen_accs.len() > shard_threshold {
+                    println!("More Than {shard_threshold} Files, Switching To Shard Folders");
+                    shard_bgz_files(&target_dir, &seen_accs[..seen_accs.len() - 1])?;
+                    sharded = true;
+                }
+                if sharded {
+                    create_dir_all(join_path(&target_dir, &[&shard_dir(&current_acc)]))?;
+                }
+                let (out_f, temp_path) =
+                    scratch_file(scratch_directory, data_directory)?.into_parts();
+                out_file = Some((
+                    BoundedWriter::new(
+                        bgzf::MultithreadedWriter::with_worker_count(worker_count, out_f),
+                        *max_buffered_bytes,
+                    ),
+                    temp_path,
+                ));
+                previous_line.clear();
+                record_hashes.clear();
+                hashes_capped = false;
+            };
+            if dedupe {
+                // Duplicates are usually adjacent, only hash when they are not
+                let duplicate = if line == previous_line {
+                    true
+                } else {
+                    let mut hasher = DefaultHasher::new();
+                    line.hash(&mut hasher);
+                    let record_hash = hasher.finish();
+                    if record_hashes.len() < DEDUPE_HASH_CAP {
+                        !record_hashes.insert(record_hash)
+                    } else {
+                        if !hashes_capped {
+                            eprintln!("Duplicate Tracking Full For {current_acc}, Later Duplicates Are Only Removed When Adjacent Or Already Tracked");
+                            hashes_capped = true;
+                        }
+                        record_hashes.contains(&record_hash)
+                    }
+                };
+                if duplicate {
+                    *report.duplicates.entry(current_acc.clone()).or_insert(0) += 1;
+                    continue;
+                }
+                previous_line = line.clone();
+            }
+            report.records += 1;
+            let bed_line = output.to_bed_fmt().join("\t");
+            if *sort {
+                sorter.add(bed_line)?;
+            } else {
+                out_file
+                    .as_mut()
+                    .unwrap()
+                    .0
+                    .write_all(format!("{}\n", bed_line).as_bytes())
+                    .expect("Unable to write line");
+            }
+        }
+    }
+
+    if let Some((mut writer, temp_path)) = out_file {
+        if *sort {
+            report.spilled += usize::from(!sorter.runs.is_empty());
+            sorter.write_sorted(&mut writer)?;
+        }
+        let (mut writer, buffer) = writer.finish()?;
+        writer.finish()?;
+        report.buffer.add(&buffer);
+        persist_scratch(temp_path, &prepped_path(&current_acc, sharded))?;
+    }
+    println!(
+        "Peak Buffered {} Of {} Bytes, Stalled {} Times",
+        report.buffer.peak_bytes, report.buffer.cap_bytes, report.buffer.stalls
+    );
+
+    File::create(half_open_file(assembly, data_type, data_directory))?;
+    // Query-side code may rely on the order only when every file was written sorted
+    let sorted_file = sorted_file(assembly, data_type, data_directory);
+    if *sort {
+        File::create(&sorted_file)?;
+        println!("Records Sorted By Contig And Position");
+        if report.spilled > 0 {
+            println!("Sorted {} Accessions Through Scratch Files", report.spilled);
+        }
+    } else if Path::new(&sorted_file).exists() {
+        std::fs::remove_file(&sorted_file)?;
+    }
+    // The files it listed were replaced, the next build_idx lists them again
+    remove_family_listing(assembly, data_type, data_directory)?;
+
+    // Keep the export's comment lines so the source dump can be identified later
+    if !source_header.is_empty() {
+        let mut header_f = File::create(join_path(
+            &target_dir,
+            &[&format!("{}{}", data_type, SOURCE_HEADER_FILE)],
+        ))?;
+        header_f.write_all(format!("{}\n", source_header.join("\n")).as_bytes())?;
+    }
+
+    if dedupe {
+        println!("Removed {} Duplicate Records", report.duplicate_count());
+        for (acc, count) in &report.duplicates {
+            println!("\t{acc}\t{count}");
+        }
+    }
+
+    Ok(report)
+}
+
+// Rebuilds the export TSV a data type was prepared from, the reverse of prep_beds: the
+// comment lines kept from the export, then the records of every BED file in export column
+// order, accessions in sorted order as prep_beds needs them. Records removed by a --dedupe
+// prep are not restored. With compress the TSV is written as BGZF, which gzip also reads.
+// Returns the number of records written.
+pub fn regenerate_export(
+    assembly: &str,
+    data_type: &str,
+    out_tsv: impl AsRef<Path>,
+    compress: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let out_tsv = &path_string(out_tsv);
+    let data_directory = &path_string(data_directory);
+    let target_dir = join_path(data_directory, &[assembly, data_type]);
+    if !Path::new(&target_dir).exists() {
+        panic!("Data \"{}\" Does Not Exist", target_dir);
+    }
+    check_half_open(assembly, data_type, data_directory)?;
+    // Flat files and those in shard folders alike
+    let mut accs: Vec<String> = WalkDir::new(&target_dir)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            e.file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".bed.bgz"))
+                .map(|acc| acc.to_string())
+        })
+        .collect();
+    accs.sort();
+
+    let out_f = File::create(out_tsv)?;
+    let mut writer: Box<dyn Write> = if compress {
+        Box::new(bgzf::Writer::new(out_f))
+    } else {
+        Box::new(BufWriter::new(out_f))
+    };
+    for line in source_header_lines(assembly, data_type, data_directory)? {
+        writeln!(writer, "{}", line)?;
+    }
+    let mut count = 0;
+    for acc in accs {
+        let reader = bgzf::Reader::new(File::open(bgz_path(&target_dir, &acc))?);
+        for line in reader.lines() {
+            let line = line?;
+            let fields = idx::bed_fields(&line);
+            let output = FormattableLine::from_bed(&fields, data_type);
+            writeln!(writer, "{}", output.to_export_tsv().join("\t"))?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+// The empty block every bgzf file ends with (SAM spec 4.1.2)
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// Only one append or compaction per file at a time, a second one would lose the first one's
+// changes. Holds an exclusive flock on <file>.lock, with the holder's pid written in it, until
+// dropped once the file is replaced. The lock goes with the process, so a lock file left by
+// one that died is not locked and is taken over.
+struct BgzLock {
+    path: String,
+    _file: File,
+}
+
+impl Drop for BgzLock {
+    // Removed while still locked, a process that opened it meanwhile sees it is gone
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn lock_in_progress(bgz_path: &str, lock_path: &str) -> Error {
+    let holder = read_to_string(lock_path).unwrap_or_default();
+    Error::new(
+        ErrorKind::WouldBlock,
+        format!(
+            "Another Append Or Compaction Of {} Is In Progress ({}, pid {})",
+            bgz_path,
+            lock_path,
+            holder.trim()
+        ),
+    )
+}
+
+#[cfg(unix)]
+fn lock_bgz_file(bgz_path: &str) -> Result<BgzLock> {
+    let lock_path = format!("{}.lock", bgz_path);
+    loop {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = Error::last_os_error();
+            return Err(match e.kind() {
+                ErrorKind::WouldBlock => lock_in_progress(bgz_path, &lock_path),
+                _ => e,
+            });
+        }
+        // The holder removed the file between the open and the lock, try the current one
+        let opened = file.metadata()?;
+        match std::fs::metadata(&lock_path) {
+            Ok(current) if current.ino() == opened.ino() && current.dev() == opened.dev() => {}
+            _ => continue,
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        return Ok(BgzLock {
+            path: lock_path,
+            _file: file,
+        });
+    }
+}
+
+// Elsewhere the lock is the file existing, one left by a process that died is removed by hand
+#[cfg(not(unix))]
+fn lock_bgz_file(bgz_path: &str) -> Result<BgzLock> {
+    let lock_path = format!("{}.lock", bgz_path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => lock_in_progress(bgz_path, &lock_path),
+            _ => e,
+        })?;
+    writeln!(file, "{}", std::process::id())?;
+    Ok(BgzLock {
+        path: lock_path,
+        _file: file,
+    })
+}
+
+// Appends export TSV records to an existing <key>.bed.bgz. The old blocks are copied as they
+// are, minus the EOF marker, and the new records are compressed into blocks after them, so
+// virtual positions in the current index stay valid. The result replaces the file by a
+// rename, readers see either the old or the new file. The index then reports the file as
+// modified until it is rebuilt, which rebuild_index does straight away.
+pub fn append_records(
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    records_tsv: impl AsRef<Path>,
+    rebuild_index: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let records_tsv = &path_string(records_tsv);
+    let data_directory = &path_string(data_directory);
+    if !Path::new(&records_tsv).exists() {
+        panic!("Input TSV \"{}\" Not Found", &records_tsv);
+    }
+    check_partition_key(data_type, key)?;
+    let target_dir = join_path(data_directory, &[assembly, data_type]);
+    let bgz_path = bgz_path(&target_dir, key);
+    if !Path::new(&bgz_path).exists() {
+        panic!(
+            "File \"{}\" Not Found, Appending Needs An Existing File",
+            &bgz_path
+        );
+    }
+    // Converted records would be off by one among the exported coordinates of older files
+    check_half_open(assembly, data_type, data_directory)?;
+
+    // Convert and check every record before touching the file
+    let mut new_lines = Vec::new();
+    for result in BufReader::new(File::open(records_tsv)?).lines() {
+        let line = result?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<_> = line.split('\t').collect();
+        let output = FormattableLine::from_export_tsv(&fields, data_type);
+        output.check_coordinates(&line)?;
+        if output.get_acc() != *key {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Record For {} Cannot Be Appended To {}: {}",
+                    output.get_acc(),
+                    key,
+                    line
+                ),
+            ));
+        }
+        new_lines.push(output.to_bed_fmt().join("\t"));
+    }
+
+    let lock = lock_bgz_file(&bgz_path)?;
+    let appended = (|| -> Result<()> {
+        let mut old_bytes = std::fs::read(&bgz_path)?;
+        if old_bytes.ends_with(&BGZF_EOF) {
+            old_bytes.truncate(old_bytes.len() - BGZF_EOF.len());
+        }
+        let mut out_f = tempfile::NamedTempFile::new_in(&target_dir)?;
+        // Temp files are private, keep the permissions of the file being replaced
+        out_f
+            .as_file()
+            .set_permissions(std::fs::metadata(&bgz_path)?.permissions())?;
+        out_f.write_all(&old_bytes)?;
+        let mut writer = bgzf::Writer::new(out_f.as_file_mut());
+        for line in &new_lines {
+            writer.write_all(format!("{}\n", line).as_bytes())?;
+        }
+        writer.finish()?;
+        out_f.as_file().sync_all()?;
+        out_f.persist(&bgz_path).map_err(|e| e.error)?;
+        Ok(())
+    })();
+    drop(lock);
+    appended?;
+    // Appended records follow the old ones, the file is no longer in position order
+    let sorted_file = sorted_file(assembly, data_type, data_directory);
+    if Path::new(&sorted_file).exists() {
+        std::fs::remove_file(&sorted_file)?;
+    }
+    remove_family_listing(assembly, data_type, data_directory)?;
+
+    if rebuild_index {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            match idx::prep_idx(assembly, data_type, false, data_directory) {
+                Ok(res) => res,
+                Err(e) => panic!("Index Prep Failed - {:?}", e),
+            };
+        if let Err(e) = idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file) {
+            panic!("Indexing Failed - {:?}", e)
+        }
+    }
+    Ok(new_lines.len())
+}
+
+pub fn source_info(
+    assembly: &str,
+    data_type: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<String>> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    source_header_lines(assembly, data_type, data_directory)
+}
+
+// The comment lines prep_beds kept from a data type's export, none if it had none
+fn source_header_lines(
+    assembly: &str,
+    data_type: &str,
+    data_directory: &str,
+) -> Result<Vec<String>> {
+    let header_file = join_path(
+        data_directory,
+        &[
+            assembly,
+            data_type,
+            &format!("{}{}", data_type, SOURCE_HEADER_FILE),
+        ],
+    );
+    if !Path::new(&header_file).exists() {
+        return Ok(Vec::new());
+    }
+    Ok(read_to_string(&header_file)?
+        .lines()
+        .map(|l| l.to_string())
+        .collect())
+}
+
+// A BED record read back from a virtual position, with its fields named by the data type's
+// columns
+#[derive(Debug, Serialize)]
+pub struct PeekedRecord {
+    pub virtual_position: u64,
+    pub compressed_offset: u64,
+    pub uncompressed_offset: u16,
+    pub record: serde_json::Map<String, Value>,
+    pub raw: String,
+}
+
+// Reads the single line an index entry points at. Positions that land past the end of a
+// block or in the middle of a line error rather than returning a partial record.
+pub fn read_at(
+    bgz_path: impl AsRef<Path>,
+    virtual_position: u64,
+    data_type: &str,
+) -> Result<PeekedRecord> {
+    let bgz_path = &path_string(bgz_path);
+    let columns = output_columns(data_type, false);
+    let pos = bgzf::VirtualPosition::from(virtual_position);
+    let invalid = |reason: String| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "No {} Record At Virtual Position {} ({}:{}) In {} - {}",
+                data_type,
+                virtual_position,
+                pos.compressed(),
+                pos.uncompressed(),
+                bgz_path,
+                reason
+            ),
+        )
+    };
+
+    let mut reader = File::open(bgz_path).map(bgzf::Reader::new)?;
+    reader
+        .seek(pos)
+        .map_err(|e| invalid(format!("Could Not Seek: {}", e)))?;
+    // Positions past the data of a block report the start of the next one
+    if reader.virtual_position() != pos {
+        return Err(invalid("Past The End Of The Block".to_string()));
+    }
+    // Within a block the preceding byte must end a line, a line can only start a block
+    // when the previous one ended exactly at the block boundary
+    if pos.uncompressed() > 0 {
+        let prev = bgzf::VirtualPosition::try_from((pos.compressed(), pos.uncompressed() - 1))
+            .map_err(|e| invalid(format!("Could Not Seek: {}", e)))?;
+        reader.seek(prev)?;
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte)?;
+        if byte[0] != b'\n' {
+            return Err(invalid("Not The Start Of A Line".to_string()));
+        }
+    }
+    let mut raw = String::new();
+    if reader.read_line(&mut raw)? == 0 {
+        return Err(invalid("End Of File".to_string()));
+    }
+    let raw = raw.trim_end_matches(['\n', '\r']).to_string();
+    let fields: Vec<&str> = raw.split('\t').collect();
+    if !column_count_fits(data_type, fields.len()) {
+        return Err(invalid(format!(
+            "Expected {} Fields, Found {}",
+            columns.len(),
+            fields.len()
+        )));
+    }
+    if fields[1].parse::<u64>().is_err() || fields[2].parse::<u64>().is_err() {
+        return Err(invalid("Start And End Are Not Numbers".to_string()));
+    }
+
+    let record = columns
+        .iter()
+        .zip(&fields)
+        .map(|(col, val)| (col.trim_start_matches('*').to_string(), json!(val)))
+        .collect();
+    Ok(PeekedRecord {
+        virtual_position,
+        compressed_offset: pos.compressed(),
+        uncompressed_offset: pos.uncompressed(),
+        record,
+        raw,
+    })
+}
+
+// Prepares every data type the assembly's export has and the data directory does not. With
+// hmm_path, a .hmm file or folder of them, the model_lengths JSON is generated from the models
+// when the export has none. A status file is given a phase per data type and step. Indexes are
+// built deterministic when asked, see idx::ContigIndex::set_deterministic. Runs the actions of
+// plan::plan_assembly in order, then plan::finalize_assembly.
+#[allow(clippy::too_many_arguments)]
 pub fn prepare_assembly(
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
+    export_directory: impl AsRef<Path>,
+    consolidate_json: bool,
+    hmm_path: &Option<String>,
+    scratch_directory: &Option<String>,
+    max_buffered_bytes: u64,
+    status: &Option<StatusFile>,
+    deterministic: bool,
+) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    let export_directory = &path_string(export_directory);
+    let actions = match plan::plan_assembly(assembly, data_directory, export_directory, hmm_path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+        result => result?,
+    };
+    for element in DATA_ELEMENTS {
+        let queued = actions.iter().any(|action| action.element == element);
+        println!("\tQueued {}: {}", element, queued);
+    }
+
+    // Nothing is created until the sources are known to need preparing
+    let consolidate =
+        plan::consolidation_needed(assembly, data_directory, &actions, consolidate_json);
+    if !consolidate && actions.is_empty() {
+        println!("Nothing To Prepare For {}", &assembly);
+        return Ok(());
+    }
+    let working_dir = join_path(data_directory, &[assembly]);
+    if !Path::new(&working_dir).exists() {
+        println!(
+            "Target Assembly Directory Not Found, Creating {},",
+            &working_dir
+        );
+        create_dir_all(&working_dir)?;
+    }
+
+    // The JSON first, as planned. The families JSON is copied as the others are but is not a
+    // JSON data type: it is only read to join onto query output, see families::FamilyMetadata.
+    let options = ActionOptions {
+        scratch_directory: scratch_directory.clone(),
+        max_buffered_bytes,
+        status: status.clone(),
+        deterministic,
+    };
+    let mut reports = Vec::new();
+    for action in &actions {
+        reports.push(plan::run_action(action, &options)?);
+    }
+    plan::finalize_assembly(
+        assembly,
+        data_directory,
+        &actions,
+        &reports,
+        consolidate_json,
+        status,
+    )
+}
+
+// API Service Subprocesses ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Prepared assemblies in the data directory, folders holding any data type or consolidated
+// JSON, sorted. With an allowlist only the listed ones are returned unless include_hidden.
+pub fn list_assemblies(
+    include_hidden: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<String>> {
+    let data_directory = &path_string(data_directory);
+    let allowlist = match include_hidden {
+        true => None,
+        false => Allowlist::load(data_directory)?,
+    };
+    let mut assemblies = Vec::new();
+    for entry in std::fs::read_dir(data_directory)? {
+        let path = entry?.path();
+        let prepared = DATA_ELEMENTS
+            .iter()
+            .chain([ASSEMBLY_DATA_FILE].iter())
+            .any(|element| path.join(element).exists());
+        if !path.is_dir() || !prepared {
+            continue;
+        }
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if allowlist.as_ref().is_none_or(|a| a.allows(&name, None)) {
+            assemblies.push(name);
+        }
+    }
+    assemblies.sort();
+    Ok(assemblies)
+}
+
+pub fn read_family_assembly_annotations(
+    id: &str,
+    assembly_id: &str,
+    nrph: &bool,
+    writer: &mut dyn Write,
+    source_meta: &bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly_id, Some(ASSEMBLY_DIR), data_directory)?;
+    let assembly_path: String = join_path(data_directory, &[assembly_id]);
+    if !Path::new(&assembly_path).exists() {
+        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+    }
+    check_half_open(assembly_id, ASSEMBLY_DIR, data_directory)?;
+    let fam_file = bgz_path(join_path(&assembly_path, &[ASSEMBLY_DIR]), id);
+    if !Path::new(&fam_file).exists() {
+        match family_data_type_hint(&assembly_path, id, ASSEMBLY_DIR) {
+            Some(other) => panic!(
+                "Family {} Not Found In Assembly {} - found under {} — did you mean bgzf-filter -d {}?",
+                id, assembly_path, other, other
+            ),
+            None => panic!("Family {} Not Found In Assembly {}", id, assembly_path),
+        }
+    }
+    let mut options = FilterOptions::builder()
+        .dl_fmt(true)
+        .source_meta(*source_meta);
+    if *nrph {
+        options = options.term(idx::NRPH_COLUMN + 1, "1");
+    }
+    match bgzf_filter_with(
+        assembly_id,
+        ASSEMBLY_DIR,
+        id,
+        writer,
+        &options.build(),
+        data_directory,
+    ) {
+        Ok(count) => Ok(count),
+        Err(err) => {
+            panic!("Error Filtering File: {} - {}", fam_file, err);
+        }
+    }
+}
+
+// Sources of family_combined, each with the data type its records are read from
+pub const ASSEMBLY_SOURCE: &str = "assembly";
+pub const BENCHMARK_SOURCE: &str = "benchmark";
+pub const COMBINED_SOURCES: [(&str, &str); 2] = [
+    (ASSEMBLY_SOURCE, ASSEMBLY_DIR),
+    (BENCHMARK_SOURCE, BENCHMARK_DIR),
+];
+
+// One record of family_combined, the BED fields as read from its source
+#[derive(Clone, Debug, PartialEq)]
+pub struct CombinedHit {
+    pub source: &'static str,
+    pub sequence: String,
+    pub low: u64, // 1-based and fully closed, low <= high whatever the strand
+    pub high: u64,
+    fields: Vec<String>,
+}
+
+impl CombinedHit {
+    fn data_type(&self) -> &'static str {
+        match self.source {
+            ASSEMBLY_SOURCE => ASSEMBLY_DIR,
+            _ => BENCHMARK_DIR,
+        }
+    }
+
+    // NRPH and divergence of an assembly record, None for benchmark records
+    fn assembly_only(&self) -> Option<(&str, &str)> {
+        (self.source == ASSEMBLY_SOURCE).then(|| {
+            (
+                self.fields.get(idx::NRPH_COLUMN).map_or("", String::as_str),
+                self.fields
+                    .get(idx::NRPH_COLUMN + 1)
+                    .map_or("", String::as_str),
+            )
+        })
+    }
+
+    // The JSON of its data type with the source added. Both sources have nrph_hit and
+    // kimura_div, null for benchmark records.
+    pub fn to_json(&self) -> Value {
+        let fields: Vec<&str> = self.fields.iter().map(String::as_str).collect();
+        let mut hit = FormattableLine::from_bed(&fields, self.data_type()).to_json(false);
+        let (nrph_hit, kimura_div) = match self.assembly_only() {
+            Some((nrph, kimura)) => (
+                json_int("nrph_hit", nrph, false),
+                json_float("kimura_div", kimura, false),
+            ),
+            None => (Value::Null, Value::Null),
+        };
+        hit["source"] = json!(self.source);
+        hit["nrph_hit"] = nrph_hit;
+        hit["kimura_div"] = kimura_div;
+        hit
+    }
+
+    // The source, the download columns, then NRPH and divergence, empty for benchmark records
+    pub fn to_dl_fmt(&self, hmm_len: &str) -> Vec<String> {
+        let fields: Vec<&str> = self.fields.iter().map(String::as_str).collect();
+        let (nrph_hit, kimura_div) = self.assembly_only().unwrap_or(("", ""));
+        let mut line = vec![self.source.to_string()];
+        line.extend(
+            FormattableLine::from_bed(&fields, self.data_type()).to_dl_fmt(&self.sequence, hmm_len),
+        );
+        line.extend([nrph_hit.to_string(), kimura_div.to_string()]);
+        line
+    }
+}
+
+pub const COMBINED_DL_HEADER: &str = "#source\tsequence name\tmodel accession\tmodel name\tbit score\te-value\thmm start\thmm end\thmm length\tstrand\talignment start\talignment end\tenvelope start\tenvelope end\tsequence length\tnrph hit\tkimura divergence";
+
+// The column names of COMBINED_DL_HEADER
+pub fn combined_dl_columns() -> Vec<&'static str> {
+    COMBINED_DL_HEADER
+        .trim_start_matches('#')
+        .split('\t')
+        .collect()
+}
+
+// Assembly and benchmark hits of a family together, sorted by contig order then position, for
+// showing both in one table. Either source may be missing, the family has to be in one.
+// With nrph only NRPH assembly hits are kept, benchmark hits have no NRPH flag and all stay.
+pub fn family_combined(
+    assembly: &str,
+    fam: &str,
+    nrph: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<CombinedHit>> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, None, data_directory)?;
+    let assembly_path = join_path(data_directory, &[assembly]);
+    let mut hits = Vec::new();
+    let mut found = false;
+    for (source, data_type) in COMBINED_SOURCES {
+        let fam_file = bgz_path(join_path(&assembly_path, &[data_type]), fam);
+        if !Path::new(&fam_file).exists() {
+            continue;
+        }
+        check_assembly(assembly, Some(data_type), data_directory)?;
+        check_half_open(assembly, data_type, data_directory)?;
+        found = true;
+        for record in BedRecordStream::open(&fam_file)? {
+            let record = record?;
+            let fields = record.fields();
+            if fields[0].starts_with('#') || fields[0].is_empty() {
+                continue;
+            }
+            if nrph && source == ASSEMBLY_SOURCE && fields.get(idx::NRPH_COLUMN) != Some(&"1") {
+                continue;
+            }
+            let Some((low, high)) = bed_extent(&fields) else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Invalid Coordinates In {} - {}",
+                        fam_file,
+                        record.line.trim_end()
+                    ),
+                ));
+            };
+            hits.push(CombinedHit {
+                source,
+                sequence: fields[0].to_string(),
+                low,
+                high,
+                fields: fields.iter().map(|f| f.to_string()).collect(),
+            });
+        }
+    }
+    if !found {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Family {} Not Found In {} Or {} Of Assembly {}",
+                fam, ASSEMBLY_DIR, BENCHMARK_DIR, assembly
+            ),
+        ));
+    }
+    // Stable, so assembly hits stay ahead of benchmark hits at the same position
+    let ranks = contig_ranks(assembly, data_directory);
+    hits.sort_by(|a, b| {
+        (contig_rank(&ranks, &a.sequence), &a.sequence, a.low, a.high).cmp(&(
+            contig_rank(&ranks, &b.sequence),
+            &b.sequence,
+            b.low,
+            b.high,
+        ))
+    });
+    Ok(hits)
+}
+
+// Writes family_combined hits as a JSON array, or as download format TSV with a header and
+// the family's model length, returning the number of hits written
+pub fn write_family_combined(
+    assembly: &str,
+    fam: &str,
+    hits: &[CombinedHit],
+    dl_fmt: bool,
+    writer: &mut dyn Write,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let data_directory = &path_string(data_directory);
+    if dl_fmt {
+        let assembly_data = AssemblyData::load(assembly, data_directory)?;
+        let hmm_len = json_lookup(
+            assembly_data.data(MOD_LEN_DIR),
+            fam,
+            &Some("length".to_string()),
+        )
+        .unwrap_or("-1".to_string());
+        let mut out = DlWriter::new(writer, &combined_dl_columns());
+        out.write_header()?;
+        for hit in hits {
+            out.write_record(&hit.to_dl_fmt(&hmm_len))?;
+        }
+    } else {
+        let hits: Vec<Value> = hits.iter().map(CombinedHit::to_json).collect();
+        serde_json::to_writer(&mut *writer, &hits)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(hits.len())
+}
+
+// The spread of a numeric column over some records. Empty and non-numeric values are only
+// counted, in unparsable, and the figures are None until a value parses.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ColumnStats {
+    pub values: u64,
+    pub unparsable: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+impl ColumnStats {
+    pub fn add(&mut self, value: Option<&str>) {
+        let value = match value.and_then(|v| v.trim().parse::<f64>().ok()) {
+            Some(value) if value.is_finite() => value,
+            _ => {
+                self.unparsable += 1;
+                return;
+            }
+        };
+        self.values += 1;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        let mean = self.mean.unwrap_or(0.0);
+        self.mean = Some(mean + (value - mean) / self.values as f64);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FamilySummary {
+    pub accession: String,
+    pub hits: u64,
+    pub nrph_hits: u64,
+    pub contigs: BTreeMap<String, u64>, // Hits per contig
+    pub model_length: Option<u64>,
+    pub bias: ColumnStats,
+}
+
+// Counts of a family's annotations in an assembly, read from its BED file alone. A family
+// without annotations in the assembly is a NotFound error, one whose file holds no records
+// has every count zero.
+pub fn family_summary(
+    assembly: &str,
+    family: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<FamilySummary> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(ASSEMBLY_DIR), data_directory)?;
+    check_half_open(assembly, ASSEMBLY_DIR, data_directory)?;
+    let fam_file = bgz_path(join_path(data_directory, &[assembly, ASSEMBLY_DIR]), family);
+    if !Path::new(&fam_file).exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Family {} Not Found In Assembly {}", family, assembly),
+        ));
+    }
+    let mut summary = FamilySummary {
+        accession: family.to_string(),
+        hits: 0,
+        nrph_hits: 0,
+        contigs: BTreeMap::new(),
+        model_length: None,
+        bias: ColumnStats::default(),
+    };
+    for line in bgzf::Reader::new(File::open(&fam_file)?).lines() {
+        let line = line?;
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let fields = idx::bed_fields(&line);
+        summary.hits += 1;
+        if fields.get(idx::NRPH_COLUMN) == Some(&"1") {
+            summary.nrph_hits += 1;
+        }
+        *summary.contigs.entry(fields[0].to_string()).or_insert(0) += 1;
+        summary.bias.add(fields.get(idx::BIAS_COLUMN).copied());
+    }
+    let assembly_data = AssemblyData::load(assembly, data_directory)?;
+    summary.model_length = json_lookup(
+        assembly_data.data(MOD_LEN_DIR),
+        family,
+        &Some("length".to_string()),
+    )
+    .and_then(|length| length.parse().ok());
+    Ok(summary)
+}
+
+// A hit kept while downsampling, ranked by score with earlier hits winning ties
+struct RankedHit {
+    score: f64,
+    order: usize,
+    line: String,
+}
+
+impl PartialEq for RankedHit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedHit {}
+
+impl Ord for RankedHit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for RankedHit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Parses a downsample spec of the form "N:W" (keep N hits per W bp window)
+pub fn parse_downsample(spec: &str) -> Option<(usize, u64)> {
+    let (n, w) = spec.split_once(':')?;
+    let n: usize = n.trim().parse().ok()?;
+    let w: u64 = w.trim().parse().ok()?;
+    if n == 0 || w == 0 {
+        return None;
+    }
+    Some((n, w))
+}
+
+// Keeps the top `per_window` hits of every `window_bp` window, ranked by bit score for
+// annotations and by repeat length for masks. Returns the kept lines in their original
+// order along with the number of hits dropped from each window (keyed by window start).
+// idx_query passes the collected search results, after the filters and mask subtraction,
+// so downsampling bounds the size of the response, not the memory of the search.
+pub fn downsample_hits(
+    lines: impl IntoIterator<Item = String>,
+    per_window: usize,
+    window_bp: u64,
+) -> (Vec<String>, BTreeMap<u64, usize>) {
+    // Annotations keep their bit score and masks their repeat length in column 5
+    let score_col = 4;
+    let mut windows: HashMap<u64, BinaryHeap<Reverse<RankedHit>>> = HashMap::new();
+    let mut dropped: BTreeMap<u64, usize> = BTreeMap::new();
+    for (order, line) in lines.into_iter().enumerate() {
+        let fields = idx::bed_fields(&line);
+        // Windows are of the 1-based start, as queries give regions
+        let start = bed_extent(&fields).map_or(0, |(low, _)| low);
+        let score = fields
+            .get(score_col)
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(f64::NEG_INFINITY);
+        let window = start / window_bp;
+        let heap = windows.entry(window).or_default();
+        heap.push(Reverse(RankedHit { score, order, line }));
+        if heap.len() > per_window {
+            heap.pop();
+            *dropped.entry(window * window_bp).or_insert(0) += 1;
+        }
+    }
+
+    let mut kept: Vec<RankedHit> = windows
+        .into_values()
+        .flat_map(|heap| heap.into_iter().map(|Reverse(hit)| hit))
+        .collect();
+    kept.sort_unstable_by_key(|hit| hit.order);
+    (kept.into_iter().map(|hit| hit.line).collect(), dropped)
+}
+
+// What idx_query does with annotation hits under masked bases. Drop leaves out hits with at
+// least min_overlap of their bases masked, Clip does too and trims masked bases off the ends
+// of the rest, Annotate keeps every hit and adds its masked_overlap_bp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaskMode {
+    Drop,
+    Clip,
+    Annotate,
+}
+
+#[derive(Clone, Debug)]
+pub struct MaskSubtraction {
+    pub mode: MaskMode,
+    pub min_overlap: f64, // Fraction of a hit's bases, in (0, 1]
+}
+
+pub const DEFAULT_MASK_OVERLAP: f64 = 1.0;
+
+// Hits grouped by family accession, or by repeat_str for masks, as
+// {"<key>": {"count": n, "records": [...]}}. count is every hit of the group, records is cut
+// to per_group_limit and left out with counts_only.
+#[derive(Clone, Debug, Default)]
+pub struct ResultGrouping {
+    pub counts_only: bool,
+    pub per_group_limit: Option<usize>,
+}
+
+pub const GROUP_BY_FAMILY: &str = "family";
+
+// The field hits of a data type are grouped by
+fn group_key_field(data_type: &str) -> &'static str {
+    match data_type {
+        MASKS_DIR => "repeat_str",
+        _ => "accession",
+    }
+}
+
+pub fn group_hits(
+    hits: impl IntoIterator<Item = Value>,
+    data_type: &str,
+    grouping: &ResultGrouping,
+) -> Value {
+    let field = group_key_field(data_type);
+    let mut groups: BTreeMap<String, (usize, Vec<Value>)> = BTreeMap::new();
+    for hit in hits {
+        let key = hit[field].as_str().unwrap_or_default().to_string();
+        let (count, records) = groups.entry(key).or_default();
+        *count += 1;
+        if !grouping.counts_only && records.len() < grouping.per_group_limit.unwrap_or(usize::MAX) {
+            records.push(hit);
+        }
+    }
+    let mut grouped = serde_json::Map::new();
+    for (key, (count, records)) in groups {
+        grouped.insert(
+            key,
+            match grouping.counts_only {
+                true => json!({ "count": count }),
+                false => json!({ "count": count, "records": records }),
+            },
+        );
+    }
+    Value::Object(grouped)
+}
+
+const RESPONSE_HITS_KEY: &str = "hits";
+
+// A JSON array written one element at a time, as serde_json writes a whole one
+struct JsonArrayWriter<W: Write> {
+    out: W,
+    empty: bool,
+}
+
+impl<W: Write> JsonArrayWriter<W> {
+    fn new(mut out: W) -> Result<JsonArrayWriter<W>> {
+        out.write_all(b"[")?;
+        Ok(JsonArrayWriter { out, empty: true })
+    }
+
+    fn push(&mut self, value: &Value) -> Result<()> {
+        if !self.empty {
+            self.out.write_all(b",")?;
+        }
+        self.empty = false;
+        serde_json::to_writer(&mut self.out, value)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.out.write_all(b"]")
+    }
+}
+
+// A query response object is written around its hits in two parts, the fields sorting before
+// "hits" and then its key, the hits, then the fields sorting after it. serde_json sorts the
+// keys of an object, so the bytes are those of the whole object, but the fields sorting
+// before "hits" must be known before the hits are written. A response of the hits alone has
+// neither part.
+fn write_response_head<W: Write>(
+    out: &mut W,
+    response: &Option<serde_json::Map<String, Value>>,
+) -> Result<()> {
+    let Some(response) = response else {
+        return Ok(());
+    };
+    out.write_all(b"{")?;
+    for (key, value) in response
+        .iter()
+        .filter(|(key, _)| key.as_str() < RESPONSE_HITS_KEY)
+    {
+        serde_json::to_writer(&mut *out, key)?;
+        out.write_all(b":")?;
+        serde_json::to_writer(&mut *out, value)?;
+        out.write_all(b",")?;
+    }
+    serde_json::to_writer(&mut *out, RESPONSE_HITS_KEY)?;
+    out.write_all(b":")?;
+    Ok(())
+}
+
+fn write_response_tail<W: Write>(
+    out: &mut W,
+    response: &Option<serde_json::Map<String, Value>>,
+) -> Result<()> {
+    let Some(response) = response else {
+        return Ok(());
+    };
+    for (key, value) in response
+        .iter()
+        .filter(|(key, _)| key.as_str() > RESPONSE_HITS_KEY)
+    {
+        out.write_all(b",")?;
+        serde_json::to_writer(&mut *out, key)?;
+        out.write_all(b":")?;
+        serde_json::to_writer(&mut *out, value)?;
+    }
+    out.write_all(b"}")?;
+    Ok(())
+}
+
+// Standard BED layouts for genome browsers and bedtools, written in place of the extended
+// columns. Coordinates are 0-based and half open with start < end on either strand, name is
+// the family accession (repeat_str for masks) and score is the bit score as bed_score scales
+// it. Bed12 adds thickStart/thickEnd at the ends of the feature and one block spanning it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BedFormat {
+    Bed6,
+    Bed12,
+}
+
+pub const BED_FORMATS: [&str; 2] = ["bed6", "bed12"];
+
+impl BedFormat {
+    pub fn parse(name: &str) -> Option<BedFormat> {
+        match name {
+            "bed6" => Some(BedFormat::Bed6),
+            "bed12" => Some(BedFormat::Bed12),
+            _ => None,
+        }
+    }
+}
+
+// BED scores run 0-1000. Bit scores are rounded and capped at BED_SCORE_MAX, so hits up to
+// 1000 bits keep their score and order and stronger ones all shade darkest. Masks have no
+// score, they and negative or unparseable scores are 0.
+pub const BED_SCORE_MAX: u64 = 1000;
+
+pub fn bed_score(bit_score: &str) -> u64 {
+    match bit_score.parse::<f64>() {
+        Ok(score) if score > 0.0 => (score.round() as u64).min(BED_SCORE_MAX),
+        _ => 0,
+    }
+}
+
+// A prepared BED record in a standard layout, on the chrom named. None when the record has no
+// numeric coordinates.
+pub fn standard_bed_line(
+    fields: &[&str],
+    data_type: &str,
+    format: BedFormat,
+    chrom: &str,
+) -> Option<String> {
+    let (low, high) = bed_extent(fields)?;
+    let (start, end) = (low - 1, high);
+    let (score, strand) = match data_type {
+        MASKS_DIR => (0, "."),
+        _ => (
+            bed_score(
+                fields
+                    .get(idx::BIT_SCORE_COLUMN)
+                    .copied()
+                    .unwrap_or_default(),
+            ),
+            fields
+                .get(idx::STRAND_COLUMN)
+                .copied()
+                .filter(|strand| matches!(*strand, "+" | "-"))
+                .unwrap_or("."),
+        ),
+    };
+    let mut line = vec![
+        chrom.to_string(),
+        start.to_string(),
+        end.to_string(),
+        fields.get(3).copied().unwrap_or_default().to_string(),
+        score.to_string(),
+        strand.to_string(),
+    ];
+    if format == BedFormat::Bed12 {
+        line.extend([
+            start.to_string(),
+            end.to_string(),
+            "0".to_string(),
+            "1".to_string(),
+            format!("{},", end - start),
+            "0,".to_string(),
+        ]);
+    }
+    Some(line.join("\t"))
+}
+
+// Sequence ids by accession for --resolve-names, empty when the accessions are kept.
+// Sequences without an id keep their accession as well.
+fn bed_chrom_names(
+    assembly: &str,
+    resolve_names: bool,
+    data_directory: &str,
+) -> Result<HashMap<String, String>> {
+    let mut names = HashMap::new();
+    if !resolve_names {
+        return Ok(names);
+    }
+    let assembly_data = AssemblyData::load(assembly, data_directory)?;
+    for (acc, info) in assembly_data.sequence_info() {
+        if let Some(id) = &info.id {
+            names.insert(acc.to_string(), id.to_string());
+        }
+    }
+    Ok(names)
+}
+
+// Unreleased assemblies may keep their sequence accessions private with redact_accessions in
+// assembly_meta.json. Query output then names each sequence by its display name, the id of
+// the sequences JSON, in place of its accession, and fails for a sequence without one.
+// Preparing and indexing still use the accessions. ignore_redaction, for internal tooling,
+// keeps them in the output.
+pub fn redacts_accessions(
+    assembly: &str,
+    ignore_redaction: bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<bool> {
+    let data_directory = &path_string(data_directory);
+    Ok(!ignore_redaction && read_assembly_meta(assembly, data_directory)?.redact_accessions)
+}
+
+// The display name a redacted sequence is shown by, see redacts_accessions
+fn display_name<'a>(names: &'a HashMap<String, String>, accession: &str) -> Result<&'a str> {
+    names.get(accession).map(String::as_str).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Sequence {} Has No Display Name, Its Accession Is Redacted",
+                accession
+            ),
+        )
+    })
+}
+
+// A message of a redacted query, BED files named by a sequence accession, as masks files are,
+// named by the display name instead
+fn redact_file_names(message: &str, names: &HashMap<String, String>) -> String {
+    message
+        .split(' ')
+        .map(|word| match word.ends_with(".bgz") {
+            true => match names.get(idx::stored_accession(word)) {
+                Some(name) => format!("{}.bed.bgz", name),
+                None => word.to_string(),
+            },
+            false => word.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Options that only make sense with, or cannot be written in, a standard BED layout
+fn check_bed_format(
+    bed_format: &Option<BedFormat>,
+    resolve_names: bool,
+    conflicts: &[(&str, bool)],
+) -> Result<()> {
+    if bed_format.is_none() {
+        if resolve_names {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Names Can Only Be Resolved In A BED Format",
+            ));
+        }
+        return Ok(());
+    }
+    match conflicts.iter().find(|(_, set)| *set) {
+        Some((option, _)) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} Cannot Be Written In A BED Format", option),
+        )),
+        None => Ok(()),
+    }
+}
+
+// Sorts and merges fully closed intervals, joining ones that overlap or touch
+pub fn merge_intervals(mut intervals: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    intervals.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (low, high) in intervals {
+        match merged.last_mut() {
+            Some(last) if low <= last.1.saturating_add(1) => last.1 = last.1.max(high),
+            _ => merged.push((low, high)),
+        }
+    }
+    merged
+}
+
+// Bases of low..=high covered by merged intervals
+pub fn masked_bases(masks: &[(u64, u64)], low: u64, high: u64) -> u64 {
+    masks[masks.partition_point(|m| m.1 < low)..]
+        .iter()
+        .take_while(|m| m.0 <= high)
+        .map(|m| m.1.min(high) - m.0.max(low) + 1)
+        .sum()
+}
+
+// low..=high less masked bases at either end, None when every base is masked
+fn clip_masked(masks: &[(u64, u64)], low: u64, high: u64) -> Option<(u64, u64)> {
+    let covering = |pos: u64| {
+        masks
+            .get(masks.partition_point(|m| m.1 < pos))
+            .filter(|m| m.0 <= pos)
+    };
+    let low = covering(low).map_or(low, |m| m.1 + 1);
+    let high = match covering(high) {
+        Some(m) if m.0 == 0 => return None,
+        Some(m) => m.0 - 1,
+        None => high,
+    };
+    if low > high {
+        return None;
+    }
+    Some((low, high))
+}
+
+// Masks of the span of the hits, merged. Fetched over the hits rather than the query, so
+// hits running past either end of it are measured whole.
+fn hit_masks(
+    assembly: &str,
+    chrom: &str,
+    lines: &[String],
+    data_directory: &str,
+) -> Result<Vec<(u64, u64)>> {
+    let extents: Vec<(u64, u64)> = lines
+        .iter()
+        .filter_map(|line| bed_extent(&idx::bed_fields(line)))
+        .collect();
+    let (low, high) = match (
+        extents.iter().map(|e| e.0).min(),
+        extents.iter().map(|e| e.1).max(),
+    ) {
+        (Some(low), Some(high)) => (low, high),
+        _ => return Ok(Vec::new()),
+    };
+    let masks = idx::search_intervals(assembly, MASKS_DIR, chrom, low, high, data_directory)
+        .map_err(|e| Error::other(e.to_string()))?;
+    Ok(merge_intervals(masks))
+}
+
+// Applies Drop or Clip to annotation lines, Annotate is applied when formatting
+fn subtract_masks(
+    lines: Vec<String>,
+    masks: &[(u64, u64)],
+    subtraction: &MaskSubtraction,
+) -> Vec<String> {
+    if subtraction.mode == MaskMode::Annotate {
+        return lines;
+    }
+    let mut kept = Vec::new();
+    for line in lines {
+        let fields = idx::bed_fields(&line);
+        let (low, high) = match bed_extent(&fields) {
+            Some(extent) => extent,
+            None => {
+                kept.push(line);
+                continue;
+            }
+        };
+        let masked = masked_bases(masks, low, high);
+        if masked as f64 >= subtraction.min_overlap * (high - low + 1) as f64 {
+            continue;
+        }
+        if subtraction.mode == MaskMode::Clip && masked > 0 {
+            if let Some((clip_low, clip_high)) = clip_masked(masks, low, high) {
+                let mut clipped: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+                clipped[1] = (clip_low - 1).to_string();
+                clipped[2] = clip_high.to_string();
+                // The alignment coordinates are on the sequence too, and must stay within it
+                for column in [7, 8] {
+                    if let Some(pos) = fields.get(column).and_then(|f| f.parse::<u64>().ok()) {
+                        clipped[column] = pos.clamp(clip_low, clip_high).to_string();
+                    }
+                }
+                kept.push(clipped.join("\t"));
+                continue;
+            }
+        }
+        kept.push(line);
+    }
+    kept
+}
+
+#[deprecated(note = "Use idx_query_with and IdxQueryOptions")]
+#[allow(clippy::too_many_arguments, clippy::ptr_arg)] // The old signature, kept as it was
+pub fn idx_query(
     assembly: &String,
+    data_type: &String,
+    chrom: &String,
+    start: u64,
+    end: u64,
+    max_region: &Option<u64>,
+    family: &Option<String>,
+    nrph: &bool,
+    strand: &Option<String>,
+    min_bit_score: &Option<f64>,
+    max_e_value: &Option<f64>,
+    downsample: &Option<String>,
+    timeout: &Option<Duration>,
+    partial: &bool,
+    legacy_json: &bool,
+    with_warnings: &bool,
+    strict: &bool,
+    with_model_length: &bool,
+    subtract_masks: &Option<MaskSubtraction>,
+    group_by: &Option<ResultGrouping>,
+    query_log: &Option<QueryLogger>,
     data_directory: &String,
-    export_directory: &String,
+) -> Result<String> {
+    let options = IdxQueryOptions {
+        max_region: *max_region,
+        family: family.clone().map(|family| vec![family]),
+        nrph: *nrph,
+        strand: strand.clone(),
+        min_bit_score: *min_bit_score,
+        max_e_value: *max_e_value,
+        downsample: downsample.clone(),
+        timeout: *timeout,
+        partial: *partial,
+        legacy_json: *legacy_json,
+        with_warnings: *with_warnings,
+        strict: *strict,
+        with_model_length: *with_model_length,
+        subtract_masks: subtract_masks.clone(),
+        group_by: group_by.clone(),
+        query_log: query_log.clone(),
+        ..IdxQueryOptions::default()
+    };
+    idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        &options,
+        data_directory,
+    )
+}
+
+/// Searches the index of a data type for hits overlapping chrom:start-end (1-based) and
+/// returns them as a JSON array, or as an object when partial, downsample, with_warnings or
+/// with_model_length ask for more than the hits. group_by returns the hits as an object keyed
+/// by family instead of the array. A bed_format returns newline terminated BED lines instead
+/// of JSON. A family filter of several families returns the hits of any of them, each hit
+/// still naming its own accession.
+///
+/// ```
+/// use te_idx::fixtures::prepare_synthetic_assembly;
+/// use te_idx::options::IdxQueryOptions;
+/// use te_idx::{idx_query_with, ASSEMBLY_DIR};
+///
+/// // A small assembly generated from a fixed seed, prepared and indexed in a temp dir
+/// let working_dir = tempfile::TempDir::new().unwrap();
+/// let assembly = "synth".to_string();
+/// let (synth, data) =
+///     prepare_synthetic_assembly(&assembly, &working_dir.path().display().to_string()).unwrap();
+///
+/// let chrom = &synth.contigs[0].0;
+/// let options = IdxQueryOptions::builder().build();
+/// let json =
+///     idx_query_with(&assembly, ASSEMBLY_DIR, chrom, 20000, 60000, &options, &data).unwrap();
+/// let hits: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+/// let expected = synth.hits.iter().filter(|h| &h.seq_acc == chrom && h.overlaps(20000, 60000));
+/// assert_eq!(hits.len(), expected.count());
+///
+/// // Only the NRPH hits of one family, .family() again adds another
+/// let options = IdxQueryOptions::builder().family("DF000000001").nrph(true).build();
+/// let json =
+///     idx_query_with(&assembly, ASSEMBLY_DIR, chrom, 20000, 60000, &options, &data).unwrap();
+/// let hits: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+/// assert!(hits.iter().all(|hit| hit["accession"] == "DF000000001"));
+/// ```
+pub fn idx_query_with(
+    assembly: &str,
+    data_type: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    options: &IdxQueryOptions,
+    data_directory: impl AsRef<Path>,
+) -> Result<String> {
+    let mut out = Vec::new();
+    idx_query_into(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        options,
+        data_directory,
+        &mut out,
+    )?;
+    Ok(String::from_utf8(out).expect("Query Output Is Not UTF-8"))
+}
+
+/// As idx_query_with, writing the response to out as it is formatted rather than returning
+/// it, for responses too large to hold twice. JSON hits are formatted and written one at a
+/// time, the bytes are those idx_query_with returns. Nothing is written when the query fails
+/// before its hits are formatted, as it does for bad options, missing data and timeouts.
+#[allow(clippy::too_many_arguments)]
+pub fn idx_query_into<W: Write>(
+    assembly: &str,
+    data_type: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    options: &IdxQueryOptions,
+    data_directory: impl AsRef<Path>,
+    out: &mut W,
 ) -> Result<()> {
-    if !Path::new(&data_directory).exists() {
-        eprintln!("{} Not Found", &data_directory);
-        std::process::exit(1)
+    let (assembly, data_type, chrom) = (
+        &assembly.to_string(),
+        &data_type.to_string(),
+        &chrom.to_string(),
+    );
+    let data_directory = &path_string(data_directory);
+    let mut parameters = query_parameters(data_type, chrom, start, end, options);
+    parameters["with_etag"] = json!(options.with_etag);
+    parameters["head"] = json!(options.head);
+    logged_with(
+        &options.query_log,
+        "idx_query",
+        assembly,
+        parameters,
+        |(count, metadata): &(usize, idx::MetadataStats)| {
+            let metadata = json!({
+                "metadata_calls": metadata.calls,
+                "metadata_ms": metadata.time.as_secs_f64() * 1000.0,
+                "index_open_retries": metadata.open_retries,
+            });
+            (*count, Some(metadata))
+        },
+        || {
+            idx_query_inner(
+                assembly,
+                data_type,
+                chrom,
+                start,
+                end,
+                options,
+                data_directory,
+                out,
+            )
+        },
+    )
+    .map(|_| ())
+}
+
+// The response's hit count and index metadata stats, for the query log
+#[allow(clippy::too_many_arguments)]
+fn idx_query_inner<W: Write>(
+    assembly: &str,
+    data_type: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    options: &IdxQueryOptions,
+    data_directory: &str,
+    out: &mut W,
+) -> Result<(usize, idx::MetadataStats)> {
+    let IdxQueryOptions {
+        max_region,
+        chunk_size,
+        family,
+        nrph,
+        strand,
+        min_bit_score,
+        max_e_value,
+        max_bias,
+        strict_bias,
+        repeat_class,
+        downsample,
+        timeout,
+        partial,
+        legacy_json,
+        with_warnings,
+        strict,
+        with_model_length,
+        with_family_meta,
+        json_seq_len,
+        subtract_masks,
+        group_by,
+        bed_format,
+        resolve_names,
+        fast_path,
+        allow_scan,
+        ignore_redaction,
+        exact_version,
+        with_etag,
+        head,
+        ..
+    } = options;
+    let fast_path = configured_fast_path(*fast_path);
+    let allow_scan = configured_allow_scan(*allow_scan);
+    // The fast path trusts the data to be there, see configured_fast_path
+    if fast_path {
+        check_allowed(assembly, Some(data_type), data_directory)?;
+    } else {
+        check_assembly(assembly, Some(data_type), data_directory)?;
+        check_layout(assembly, data_directory)?;
+    }
+    // A single stat, on the fast path as well, older files would give wrong hits silently
+    check_half_open(assembly, data_type, data_directory)?;
+    if let Err(e) = idx::check_region(start, end, *max_region) {
+        return Err(Error::new(ErrorKind::InvalidInput, e));
+    }
+    let downsample = match downsample {
+        Some(spec) => match parse_downsample(spec) {
+            Some(parsed) => Some(parsed),
+            None => {
+                return Err(invalid_option(format!(
+                    "Invalid Downsample \"{}\", Expected N:W",
+                    spec
+                )))
+            }
+        },
+        None => None,
+    };
+    let family = &idx::query_families(family);
+    if family.is_some() && data_type == MASKS_DIR {
+        return Err(invalid_option(format!(
+            "Masks Have No Family Column, Family Filter {} Cannot Be Applied - did you mean -d {}?",
+            family.as_ref().unwrap().join(","),
+            ASSEMBLY_DIR
+        )));
+    }
+    check_strand(strand, data_type)?;
+    check_bias(max_bias, data_type)?;
+    check_repeat_class(repeat_class, data_type)?;
+    if (min_bit_score.is_some() || max_e_value.is_some()) && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No Score Columns, Score Thresholds Cannot Be Applied",
+        ));
+    }
+    if *with_model_length && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No Family Column, Model Lengths Cannot Be Added",
+        ));
     }
-    if !Path::new(&export_directory).exists() {
-        eprintln!("{} Not Found", &export_directory);
-        std::process::exit(1)
+    if *with_family_meta && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No Family Column, Family Metadata Cannot Be Added",
+        ));
+    }
+    if *json_seq_len && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No seq_len Column, It Cannot Be Taken From The Sequences JSON",
+        ));
+    }
+    let annotate_overlap = matches!(
+        subtract_masks,
+        Some(MaskSubtraction {
+            mode: MaskMode::Annotate,
+            ..
+        })
+    );
+    check_bed_format(
+        bed_format,
+        *resolve_names,
+        &[
+            ("Downsampling", downsample.is_some()),
+            ("Partial Results", *partial),
+            ("Warnings", *with_warnings),
+            ("Model Lengths", *with_model_length),
+            ("Family Metadata", *with_family_meta),
+            // BED lines have no seq_len column for the JSON length to replace
+            ("JSON Sequence Lengths", *json_seq_len),
+            ("Grouped Hits", group_by.is_some()),
+            ("Masked Overlap", annotate_overlap),
+            ("ETags", *with_etag),
+            ("Head Queries", *head),
+        ],
+    )?;
+    if let Some(subtraction) = subtract_masks {
+        if data_type != ASSEMBLY_DIR {
+            return Err(invalid_option(format!(
+                "Masks Can Only Be Subtracted From {}",
+                ASSEMBLY_DIR
+            )));
+        }
+        if !(subtraction.min_overlap > 0.0 && subtraction.min_overlap <= 1.0) {
+            return Err(invalid_option(format!(
+                "Invalid Mask Overlap {}, Expected A Fraction In (0, 1]",
+                subtraction.min_overlap
+            )));
+        }
+        check_assembly(assembly, Some(MASKS_DIR), data_directory)?;
+        check_half_open(assembly, MASKS_DIR, data_directory)?;
+        let masks_index = join_path(
+            data_directory,
+            &[assembly, &format!("{}_idx.dat", MASKS_DIR)],
+        );
+        if !Path::new(&masks_index).exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Assembly \"{}\" Is Not Indexed For {}, Masks Cannot Be Subtracted",
+                    assembly, MASKS_DIR
+                ),
+            ));
+        }
+    }
+    let assembly_path: String = join_path(data_directory, &[assembly]);
+    // confirm assembly_id and ensure that it accessable
+    if !fast_path && !Path::new(&assembly_path).exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Assembly \"{}\" Does Not Exist", assembly_path),
+        ));
+    }
+
+    // Before the search, from the index and query alone
+    let etag = match *with_etag || *head {
+        true => Some(query_etag(
+            assembly,
+            data_type,
+            chrom,
+            start,
+            end,
+            options,
+            data_directory,
+        )?),
+        false => None,
+    };
+
+    // The data type folder is listed first, missing it is not found as the index would be
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        idx::prep_search(assembly, data_type, fast_path, data_directory).map_err(|e| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("Search Prep Failed, Index may not exist - {}", e),
+            )
+        })?;
+
+    // Opened with retries, a rebuild can be swapping the index in. With a scan to fall
+    // back on the index is validated as well, and scanned without when unusable.
+    let open_retry = idx::configured_open_retry();
+    let index_error = if allow_scan {
+        idx::open_index(&index_file, &open_retry)
+            .and_then(|_| idx::check_index(&index_file))
+            .err()
+    } else if !fast_path {
+        idx::open_index(&index_file, &open_retry).err()
+    } else {
+        None
+    };
+    // Without a scan the open error is the query's, NotFound holding NotIndexed for an index
+    // still missing once the retries are spent
+    let index_error = match index_error {
+        Some(e) if !allow_scan => return Err(e),
+        index_error => index_error,
+    };
+    let scan = index_error.is_some();
+    let as_accession = || ResolvedSequence {
+        query: chrom.to_string(),
+        accession: chrom.to_string(),
+        resolution: SequenceResolution::Accession,
+        qualified: false,
+    };
+    // A chrom the index holds is an accession, anything else is resolved through
+    // the sequences JSON, see resolve_sequence. Without the index a chrom the
+    // sequences JSON cannot resolve is searched for as it is.
+    let sequence = match scan {
+        false => match idx::index_has_contig(&index_file, chrom) {
+            Ok(true) => as_accession(),
+            _ => resolve_sequence(assembly, chrom, data_directory)?,
+        },
+        true => {
+            resolve_sequence(assembly, chrom, data_directory).unwrap_or_else(|_| as_accession())
+        }
+    };
+    let chrom = &sequence.accession;
+    // Every hit is on chrom, shown by its display name when redacted
+    let redact = redacts_accessions(assembly, *ignore_redaction, data_directory)?;
+    let redacted_names = bed_chrom_names(assembly, redact, data_directory)?;
+    let seq_name = match redact {
+        true => Some(display_name(&redacted_names, chrom)?),
+        false => None,
+    };
+    if let Some(chunk_size) = chunk_size {
+        contig_index.set_chunk_size(*chunk_size);
+    }
+    contig_index.set_max_bias(*max_bias, *strict_bias);
+    contig_index.set_exact_version(*exact_version);
+    let mut scan_warning = None;
+    let results = match &index_error {
+        None => idx::search_idx(
+            &filenames,
+            &bgz_dir,
+            &mut contig_index,
+            &index_file,
+            chrom,
+            start,
+            end,
+            family,
+            *nrph,
+            strand,
+            *min_bit_score,
+            *max_e_value,
+            *timeout,
+            &None,
+            *strict,
+            true,
+        ),
+        Some(index_error) => {
+            let started = Instant::now();
+            let (results, files) = idx::scan_search(
+                &contig_index,
+                &bgz_dir,
+                chrom,
+                start,
+                end,
+                family,
+                *nrph,
+                strand,
+                *min_bit_score,
+                *max_e_value,
+            )?;
+            let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+            let warning = format!(
+                "No Usable {} Index ({}), All {} Files Were Scanned In {:.0} ms",
+                data_type, index_error, files, elapsed_ms
+            );
+            eprintln!("Warning: {}", warning);
+            scan_warning = Some((warning, files, elapsed_ms));
+            Ok(results)
+        }
+    };
+
+    // A timed out search is an error carrying the partial results, unless they were asked for
+    let mut truncated = false;
+    let mut results = match results {
+        Err(e) => match e.downcast::<idx::SearchTimeout>() {
+            Ok(timed_out) if *partial => {
+                truncated = true;
+                timed_out.partial_results
+            }
+            Ok(timed_out) => return Err(Error::new(ErrorKind::TimedOut, *timed_out)),
+            Err(e) => match e.downcast::<idx::MissingBgzFile>() {
+                Ok(missing) => return Err(Error::new(ErrorKind::NotFound, *missing)),
+                Err(e) => match e.downcast::<idx::NotIndexed>() {
+                    Ok(not_indexed) => return Err(Error::new(ErrorKind::NotFound, *not_indexed)),
+                    Err(e) => return Err(Error::other(format!("Index Search Failed - {}", e))),
+                },
+            },
+        },
+        Ok(l) => l,
+    };
+
+    if let Some(repeat_class) = repeat_class {
+        results.retain(|line| {
+            idx::bed_fields(line).get(REPEAT_CLASS_COLUMN) == Some(&repeat_class.as_str())
+        });
+    }
+
+    let mut masks = Vec::new();
+    if let Some(subtraction) = subtract_masks {
+        masks = hit_masks(assembly, chrom, &results, data_directory)?;
+        results = self::subtract_masks(results, &masks, subtraction);
+    }
+
+    let mut dropped = BTreeMap::new();
+    if let Some((per_window, window_bp)) = downsample {
+        (results, dropped) = downsample_hits(results, per_window, window_bp);
+    }
+
+    // The number of hits the full response would have, without formatting them
+    if *head {
+        let response = json!({ "etag": etag, "total": results.len() });
+        serde_json::to_writer(&mut *out, &response)?;
+        return Ok((results.len(), contig_index.metadata_stats()));
+    }
+
+    if let Some(format) = bed_format {
+        let chrom_names = bed_chrom_names(assembly, *resolve_names || redact, data_directory)?;
+        let mut bed_lines = Vec::new();
+        let mut column_check = ColumnCheck::default();
+        for line in &results {
+            let fields = column_check.check(idx::bed_fields(line), data_type);
+            let chrom = chrom_names.get(fields[0]).map_or(fields[0], String::as_str);
+            if let (Some((low, _)), Some(bed_line)) = (
+                bed_extent(&fields),
+                standard_bed_line(&fields, data_type, *format, chrom),
+            ) {
+                bed_lines.push((low, bed_line));
+            }
+        }
+        column_check.warn(assembly, data_type);
+        // Stable sorted by chromStart as bedtools sorts
+        bed_lines.sort_by_key(|(low, _)| *low);
+        for (_, line) in bed_lines {
+            writeln!(out, "{}", line)?;
+        }
+        return Ok((results.len(), contig_index.metadata_stats()));
+    }
+
+    // Every hit is on chrom, a chrom the JSON has no length for keeps its column
+    let json_length = match *json_seq_len {
+        true => {
+            let length = seqlens::sequence_lengths(assembly, data_directory)?
+                .get(chrom)
+                .map(u64::to_string);
+            if length.is_none() {
+                eprintln!(
+                        "Warning: The Sequences JSON Of {} Has No Length For {}, seq_len Is The Stored Column",
+                        assembly, chrom
+                    );
+            }
+            length
+        }
+        false => None,
+    };
+    // Read once for all the hits, before any of them is written
+    let assembly_data = match *with_model_length {
+        true => Some(AssemblyData::load(assembly, data_directory)?),
+        false => None,
+    };
+    let model_lengths = assembly_data
+        .as_ref()
+        .map(|assembly_data| assembly_data.data(MOD_LEN_DIR));
+    let family_meta = match *with_family_meta {
+        true => Some(families::FamilyMetadata::load(assembly, data_directory)?),
+        false => None,
+    };
+
+    // Fields of the object around the hits known before them, the rest are added
+    // once the hits are written
+    let wrapped = downsample.is_some()
+        || *partial
+        || *with_warnings
+        || *with_model_length
+        || *with_etag
+        || scan;
+    let mut response = match wrapped {
+        true => Some(serde_json::Map::new()),
+        false => None,
+    };
+    if let Some(response) = response.as_mut() {
+        if let Some((per_window, window_bp)) = downsample {
+            response.insert(
+                "downsample".to_string(),
+                json!({
+                    "per_window": per_window,
+                    "window_bp": window_bp,
+                    "dropped": dropped.values().sum::<usize>(),
+                    "dropped_per_window": dropped,
+                }),
+            );
+        }
+        if *partial {
+            response.insert("truncated".to_string(), Value::Bool(truncated));
+        }
+        if *with_etag {
+            response.insert("etag".to_string(), json!(etag));
+        }
+        // Scanned results are the same, but not as quick, callers see how they were found
+        if let Some((warning, files, elapsed_ms)) = &scan_warning {
+            response.insert(
+                "scan".to_string(),
+                json!({
+                    "warning": warning,
+                    "files": files,
+                    "elapsed_ms": elapsed_ms,
+                }),
+            );
+        }
+        if *with_warnings {
+            response.insert(
+                "sequence".to_string(),
+                match seq_name {
+                    Some(seq_name) => json!({
+                        "seq_name": seq_name,
+                        "resolution": sequence.resolution,
+                    }),
+                    None => json!(sequence),
+                },
+            );
+            let skipped: Vec<Value> = contig_index
+                .skipped_ranges()
+                .iter()
+                .map(|(file, ranges)| match redact {
+                    true => (redact_file_names(file, &redacted_names), ranges),
+                    false => (file.clone(), ranges),
+                })
+                .map(|(file, ranges)| json!({ "file": file, "ranges": ranges }))
+                .collect();
+            response.insert("skipped".to_string(), Value::Array(skipped));
+            // Every family filtered for has a file without records, there are no hits
+            // anywhere in the assembly rather than none in this region
+            if let Some(families) = family {
+                let data_path = join_path(data_directory, &[assembly, data_type]);
+                let mut empty = true;
+                for family in families {
+                    // A family with files of several versions is not known to be empty
+                    empty &= match key_bgz_path(&data_path, data_type, family, *exact_version) {
+                        Ok(fam_file) => Path::new(&fam_file).exists() && bgz_is_empty(&fam_file)?,
+                        Err(_) => false,
+                    };
+                }
+                response.insert("empty_family".to_string(), Value::Bool(empty));
+            }
+        }
+    }
+
+    // Each hit is formatted as it is written, the hits are never all held as JSON.
+    // Model lengths are looked up once per family.
+    let mut column_check = ColumnCheck::default();
+    let mut lengths: HashMap<String, Value> = HashMap::new();
+    let mut missing_lengths = 0;
+    let mut format_hit = |line: &String| {
+        let fields = column_check.check(idx::bed_fields(line), data_type);
+        let mut hit = FormattableLine::from_bed(&fields, data_type).to_json(*legacy_json);
+        // Hits have no seq_len field unless asked for the JSON length
+        if let (true, Some(column)) = (*json_seq_len, seq_len_column(data_type)) {
+            let stored = fields.get(column).copied().unwrap_or_default();
+            let length = json_length.as_deref().unwrap_or(stored);
+            hit["seq_len"] = json_int("seq_len", length, *legacy_json);
+        }
+        if let (Some(seq_name), Some(hit)) = (seq_name, hit.as_object_mut()) {
+            // Annotations name the accession sequence, masks seq_acc
+            hit.remove("sequence");
+            hit.remove("seq_acc");
+            hit.insert("seq_name".to_string(), json!(seq_name));
+        }
+        if let Some(MaskSubtraction {
+            mode: MaskMode::Annotate,
+            ..
+        }) = subtract_masks
+        {
+            if let Some((low, high)) = bed_extent(&fields) {
+                hit["masked_overlap_bp"] = json!(masked_bases(&masks, low, high));
+            }
+        }
+        if let Some(model_lengths) = model_lengths {
+            let fam = hit["accession"].as_str().unwrap_or_default().to_string();
+            let length = lengths.entry(fam).or_insert_with_key(|fam| {
+                match json_lookup(model_lengths, fam, &Some("length".to_string())) {
+                    Some(length) => json_int("model_length", &length, *legacy_json),
+                    None => {
+                        missing_lengths += 1;
+                        Value::Null
+                    }
+                }
+            });
+            hit["model_length"] = length.clone();
+        }
+        // Null fields for families it does not have
+        if let Some(family_meta) = &family_meta {
+            let fam = hit["accession"].as_str().unwrap_or_default().to_string();
+            family_meta.join(&fam, &mut hit);
+        }
+        hit
+    };
+    write_response_head(out, &response)?;
+    // Grouped hits take the place of the array, in the object too
+    match group_by {
+        Some(grouping) => {
+            let grouped = group_hits(results.iter().map(&mut format_hit), data_type, grouping);
+            serde_json::to_writer(&mut *out, &grouped)?;
+        }
+        None => {
+            let mut hits = JsonArrayWriter::new(&mut *out)?;
+            for line in &results {
+                hits.push(&format_hit(line))?;
+            }
+            hits.finish()?;
+        }
     }
 
-    let export_dir = format!("{}/{}", &export_directory, &assembly);
-    let working_dir = format!("{}/{}", &data_directory, &assembly);
-    if !Path::new(&export_dir).exists() {
-        eprintln!("Assembly Export Not Found - {}", &export_dir);
-        std::process::exit(1)
-    }
-    if !Path::new(&working_dir).exists() {
-        println!(
-            "Target Assembly Directory Not Found, Creating {},",
-            &working_dir
+    let column_warnings = column_check.warn(assembly, data_type);
+    let mut length_warning = None;
+    if *with_model_length && missing_lengths > 0 {
+        let warning = format!(
+            "{} Families Of The Hits Have No Model Length In {}",
+            missing_lengths, assembly
         );
-        create_dir_all(&working_dir)?;
+        eprintln!("Warning: {}", warning);
+        length_warning = Some(warning);
     }
-
-    fn file_to_source(s: &str) -> Option<&'static str> {
-        match s {
-            ASSEMBLY_DIR => Some(ASSEMBLY_FILE),
-            BENCHMARK_DIR => Some(BENCHMARK_FILE),
-            MASKS_DIR => Some(MASKS_FILE),
-            MOD_LEN_DIR => Some(MOD_LEN_FILE),
-            SEQUENCE_DIR => Some(SEQUENCE_FILE),
-            _ => None,
+    if let Some(response) = response.as_mut() {
+        if *with_model_length {
+            response.insert("missing_model_lengths".to_string(), json!(missing_lengths));
+        }
+        if *with_warnings {
+            let mut warnings = contig_index.file_warnings().clone();
+            warnings.extend(scan_warning.map(|(warning, _, _)| warning));
+            warnings.extend(column_warnings);
+            warnings.extend(length_warning);
+            if redact {
+                warnings = warnings
+                    .iter()
+                    .map(|warning| redact_file_names(warning, &redacted_names))
+                    .collect();
+            }
+            response.insert("warnings".to_string(), json!(warnings));
         }
     }
-    let mut planner = HashMap::new();
-    for element in DATA_ELEMENTS {
-        let source = format!(
-            "{}/{}{}",
-            export_dir,
-            &assembly,
-            file_to_source(&element).unwrap().to_string()
-        );
-        let target = format!("{}/{}", &working_dir, &element);
+    write_response_tail(out, &response)?;
+    Ok((results.len(), contig_index.metadata_stats()))
+}
 
-        let have_source = Path::new(&source).exists();
-        let have_target =
-            Path::new(&target).exists() && !Path::new(&target).read_dir()?.next().is_none();
-        let needed = have_source && !have_target;
+// Every record on a contig in ascending start order, read lazily from the index. The family
+// and nrph filters are those of idx_query.
+pub fn iter_contig(
+    assembly: &str,
+    data_type: &str,
+    chrom: &str,
+    family: &Option<String>,
+    nrph: &bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<idx::RecordIter> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    check_half_open(assembly, data_type, data_directory)?;
+    check_iter_filters(family, data_type)?;
+    idx::iter_records(
+        assembly,
+        data_type,
+        &[chrom.to_string()],
+        false,
+        family,
+        *nrph,
+        data_directory,
+    )
+    .map_err(|e| Error::other(e.to_string()))
+}
 
-        let info = HashMap::from([
-            ("source", source),
-            ("target", target),
-            ("needed", needed.to_string()),
-        ]);
-        planner.insert(element, info);
-        println!("\tQueued {}: {}", element, needed);
+// iter_contig over every indexed contig, in the assembly's contig order, see contig_order
+pub fn iter_assembly(
+    assembly: &str,
+    data_type: &str,
+    family: &Option<String>,
+    nrph: &bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<idx::RecordIter> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    check_half_open(assembly, data_type, data_directory)?;
+    check_iter_filters(family, data_type)?;
+    let contigs = contig_order::contig_order(assembly, data_directory)?.contigs;
+    idx::iter_records(
+        assembly,
+        data_type,
+        &contigs,
+        true,
+        family,
+        *nrph,
+        data_directory,
+    )
+    .map_err(|e| Error::other(e.to_string()))
+}
+
+fn check_iter_filters(family: &Option<String>, data_type: &str) -> Result<()> {
+    if family.is_some() && data_type == MASKS_DIR {
+        return Err(invalid_option(
+            "Masks Have No Family Column, Family Filter Cannot Be Applied",
+        ));
     }
+    Ok(())
+}
 
-    for element in DATA_ELEMENTS {
-        if matches!(
-            planner
-                .get(element)
-                .unwrap()
-                .get("needed")
-                .unwrap()
-                .as_str(),
-            "true"
-        ) {
-            println!("Preparing {}: ", element);
-            let target = planner.get(element).unwrap().get("target").unwrap();
-            if !Path::new(target).exists() {
-                println!("   Target Directory Not Found, Creating {},", &target);
-                create_dir_all(&target)?;
+// Writes every record of one contig, or of the whole assembly, to stdout as BED lines or as
+// one JSON object per line. Returns the number of records written.
+pub fn dump_records(
+    assembly: &str,
+    data_type: &str,
+    chrom: &Option<String>,
+    family: &Option<String>,
+    nrph: &bool,
+    ndjson: &bool,
+    data_directory: impl AsRef<Path>,
+) -> Result<usize> {
+    let data_directory = &path_string(data_directory);
+    let records = match chrom {
+        Some(chrom) => iter_contig(assembly, data_type, chrom, family, nrph, data_directory)?,
+        None => iter_assembly(assembly, data_type, family, nrph, data_directory)?,
+    };
+    let mut out = stdout().lock();
+    let mut count = 0;
+    let mut column_check = ColumnCheck::default();
+    for record in records {
+        let record = record?;
+        let fields = column_check.check(idx::bed_fields(&record), data_type);
+        if *ndjson {
+            let json = FormattableLine::from_bed(&fields, data_type).to_json(false);
+            writeln!(out, "{}", json)?;
+        } else {
+            writeln!(out, "{}", record)?;
+        }
+        count += 1;
+    }
+    column_check.warn(assembly, data_type);
+    Ok(count)
+}
+
+pub fn json_query(
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    target: &Option<String>,
+    query_log: &Option<QueryLogger>,
+    data_directory: impl AsRef<Path>,
+) -> Result<String> {
+    let data_directory = &path_string(data_directory);
+    let parameters = json!({ "data_type": data_type, "key": key, "target": target });
+    // One result when the key is found, none for the "-1" of a missing key
+    let found = |val: &String| usize::from(val != "-1");
+    logged(query_log, "json_query", assembly, parameters, found, || {
+        check_assembly(assembly, Some(data_type), data_directory)?;
+        let assembly_data = AssemblyData::load(assembly, data_directory)?;
+        let data = assembly_data.data(data_type);
+
+        match lookup_key(data, assembly_data.key_index(data_type), key, target) {
+            TargetLookup::Found(found) => {
+                if found.normalization != KeyNormalization::Exact {
+                    eprintln!(
+                        "Warning: {} Matched {} In The {} JSON Of {}, {:?}",
+                        key, found.key, data_type, assembly, found.normalization
+                    );
+                }
+                Ok(found.value)
             }
-            let source = planner.get(element).unwrap().get("source").unwrap();
-            if source.ends_with(".json") {
-                copy(
-                    source,
-                    format!(
-                        "{}/{}{}",
-                        target,
+            TargetLookup::Ambiguous(normalization, keys) => {
+                Err(ambiguous_key(key, normalization, &keys))
+            }
+            TargetLookup::MissingField(matched, field) => {
+                eprintln!(
+                    "Warning: {} Is In The {} JSON Of {} But Has No \"{}\"",
+                    matched, data_type, assembly, field
+                );
+                Ok("-1".to_string())
+            }
+            TargetLookup::Missing(near_misses) => {
+                if !near_misses.is_empty() {
+                    eprintln!(
+                        "Warning: {} Not Found In The {} JSON Of {}, Near Misses: {}",
+                        key,
+                        data_type,
                         assembly,
-                        file_to_source(element).unwrap()
-                    ),
-                )
-                .expect("Could Not Copy JSON");
-                println!("   {} Prep Complete", element);
-            } else if source.ends_with(".tsv") {
-                println!("   Splitting And Compressing BED Files For {}", element);
-                prep_beds(assembly, source, &element.to_string(), &data_directory)
-                    .expect("BED File Prep Failed");
-                println!("   Indexing {}", element);
-                let (filenames, bgz_dir, mut contig_index, index_file) =
-                    idx::prep_idx(&working_dir, &element.to_string()).expect("Index Prep Failed");
-                idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
-                    .expect("Indexing Failed");
-                println!("   {} Prep Complete", element);
-            } else {
-                eprintln!("Source type not recognized - {}", source)
+                        near_misses.join(", ")
+                    );
+                }
+                Ok("-1".to_string())
+            }
+        }
+    })
+}
+
+// How a json_query key was matched to a key of the JSON. Each is tried in this order and the
+// first to find a key is used.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyNormalization {
+    Exact,
+    Trimmed,         // Surrounding whitespace removed, as in keys copied from a spreadsheet
+    CaseInsensitive, // Trimmed and compared ignoring case
+    VersionStripped, // A family accession compared ignoring case and its .<version>
+}
+
+// The keys of a JSON "data" object by their normalized forms, built once per data type by
+// AssemblyData
+#[derive(Debug, Default)]
+pub struct JsonKeyIndex {
+    lowercase: HashMap<String, Vec<String>>,
+    unversioned: HashMap<String, Vec<String>>, // Family accessions only
+}
+
+// Near misses listed for a key that matches nothing, keys at most this many edits away
+const NEAR_MISS_EDITS: usize = 2;
+const NEAR_MISS_LIMIT: usize = 5;
+
+// A family accession, DF and digits in any case, without its .<version>
+fn unversioned_family(key: &str) -> Option<String> {
+    let key = key.to_uppercase();
+    let base = match key.split_once('.') {
+        Some((base, version))
+            if !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            base
+        }
+        Some(_) => return None,
+        None => &key,
+    };
+    Some(base.to_string()).filter(|base| is_family_accession(base))
+}
+
+impl JsonKeyIndex {
+    pub fn new(data: &Value) -> JsonKeyIndex {
+        let mut index = JsonKeyIndex::default();
+        for key in data.as_object().into_iter().flat_map(|map| map.keys()) {
+            index
+                .lowercase
+                .entry(key.to_lowercase())
+                .or_default()
+                .push(key.to_string());
+            if let Some(base) = unversioned_family(key) {
+                index
+                    .unversioned
+                    .entry(base)
+                    .or_default()
+                    .push(key.to_string());
             }
         }
+        index
+    }
+
+    // The key of data that key names, trying each KeyNormalization in turn. A key that
+    // normalizes to several keys of the JSON is ambiguous, they are all returned sorted
+    // rather than one guessed.
+    fn resolve(&self, data: &Value, key: &str) -> KeyLookup {
+        let trimmed = key.trim();
+        for (candidate, normalization) in [
+            (key, KeyNormalization::Exact),
+            (trimmed, KeyNormalization::Trimmed),
+        ] {
+            if data.get(candidate).is_some() {
+                return KeyLookup::Found(candidate.to_string(), normalization);
+            }
+        }
+        let normalized = [
+            (
+                self.lowercase.get(&trimmed.to_lowercase()),
+                KeyNormalization::CaseInsensitive,
+            ),
+            (
+                unversioned_family(trimmed).and_then(|base| self.unversioned.get(&base)),
+                KeyNormalization::VersionStripped,
+            ),
+        ];
+        for (keys, normalization) in normalized {
+            match keys.map(|keys| keys.as_slice()) {
+                None | Some([]) => continue,
+                Some([matched]) => return KeyLookup::Found(matched.to_string(), normalization),
+                Some(keys) => {
+                    let mut keys = keys.to_vec();
+                    keys.sort();
+                    return KeyLookup::Ambiguous(normalization, keys);
+                }
+            }
+        }
+        KeyLookup::Missing(self.near_misses(key))
+    }
+
+    // Keys within NEAR_MISS_EDITS of key ignoring case, closest first
+    fn near_misses(&self, key: &str) -> Vec<String> {
+        let key = key.trim().to_lowercase();
+        let mut near: Vec<(usize, &String)> = self
+            .lowercase
+            .iter()
+            .filter_map(|(lowercase, keys)| {
+                let edits = edit_distance(&key, lowercase);
+                (edits <= NEAR_MISS_EDITS).then_some(keys.iter().map(move |k| (edits, k)))
+            })
+            .flatten()
+            .collect();
+        near.sort();
+        near.into_iter()
+            .take(NEAR_MISS_LIMIT)
+            .map(|(_, key)| key.to_string())
+            .collect()
     }
-    Ok(())
 }
 
-// API Service Subprocesses ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-pub fn read_family_assembly_annotations(
-    id: &String,
-    assembly_id: &String,
-    nrph: &bool,
-    outfile: &Option<String>,
-    data_directory: &String,
-) -> Result<()> {
-    let assembly_path: String = format!("{}/{}", &data_directory, &assembly_id);
-    if !Path::new(&assembly_path).exists() {
-        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+// Levenshtein distance of two strings, by characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
     }
-    let fam_file: String = format!("{}/{}/{}.bed.bgz", &assembly_path, &ASSEMBLY_DIR, &id);
-    if !Path::new(&fam_file).exists() {
-        panic!("Family {} Not Found In Assembly {}", id, assembly_path);
-    }
-    let position: usize = 13;
-    let term: Option<String> = if *nrph { Some("1".to_string()) } else { None };
-    match bgzf_filter(
-        &assembly_id,
-        &ASSEMBLY_DIR.to_string(),
-        &id,
-        &position,
-        &term,
-        outfile,
-        true,
-        &data_directory,
-    ) {
-        Ok(()) => return Ok(()),
-        Err(err) => {
-            panic!("Error Filtering File: {} - {}", fam_file, err);
+    previous[b.len()]
+}
+
+// A json_query answer with the key of the JSON it was found under
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JsonQueryMatch {
+    pub value: String,
+    pub key: String,
+    pub normalization: KeyNormalization,
+}
+
+// Where a key was resolved to in the JSON
+enum KeyLookup {
+    Found(String, KeyNormalization),
+    Ambiguous(KeyNormalization, Vec<String>),
+    Missing(Vec<String>), // Near misses
+}
+
+// Where the target of a key was found, by looking up the resolved key
+enum TargetLookup {
+    Found(JsonQueryMatch),
+    MissingField(String, String), // The key matched and the target field it has no value for
+    Ambiguous(KeyNormalization, Vec<String>),
+    Missing(Vec<String>),
+}
+
+fn lookup_key(
+    data: &Value,
+    index: &JsonKeyIndex,
+    key: &str,
+    target: &Option<String>,
+) -> TargetLookup {
+    let (matched, normalization) = match index.resolve(data, key) {
+        KeyLookup::Found(matched, normalization) => (matched, normalization),
+        KeyLookup::Ambiguous(normalization, keys) => {
+            return TargetLookup::Ambiguous(normalization, keys)
         }
+        KeyLookup::Missing(near_misses) => return TargetLookup::Missing(near_misses),
+    };
+    match json_lookup(data, &matched, target) {
+        Some(value) => TargetLookup::Found(JsonQueryMatch {
+            value,
+            key: matched,
+            normalization,
+        }),
+        None => match missing_field(data, &matched, target) {
+            Some(field) => TargetLookup::MissingField(matched, field),
+            None => TargetLookup::Missing(Vec::new()),
+        },
     }
 }
 
-pub fn idx_query(
-    assembly: &String,
-    data_type: &String,
-    chrom: &String,
-    start: u64,
-    end: u64,
-    family: &Option<String>,
-    nrph: &bool,
-    data_directory: &String,
+// A key normalizing to several keys of the JSON, which lookups refuse to choose between
+fn ambiguous_key(key: &str, normalization: KeyNormalization, keys: &[String]) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!(
+            "Key \"{}\" Is Ambiguous, {:?} It Matches {}",
+            key,
+            normalization,
+            keys.join(", ")
+        ),
+    )
+}
+
+// json_query reporting how the key matched. A key matching nothing is a NotFound error
+// listing near misses, as is a key without the target field.
+pub fn json_query_match(
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    target: &Option<String>,
+    data_directory: impl AsRef<Path>,
+) -> Result<JsonQueryMatch> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    let assembly_data = AssemblyData::load(assembly, data_directory)?;
+    let data = assembly_data.data(data_type);
+    match lookup_key(data, assembly_data.key_index(data_type), key, target) {
+        TargetLookup::Found(found) => Ok(found),
+        TargetLookup::Ambiguous(normalization, keys) => {
+            Err(ambiguous_key(key, normalization, &keys))
+        }
+        TargetLookup::MissingField(matched, field) => Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "{} Is In The {} JSON Of {} But Has No \"{}\"",
+                matched, data_type, assembly, field
+            ),
+        )),
+        TargetLookup::Missing(near_misses) => Err(Error::new(
+            ErrorKind::NotFound,
+            match near_misses.is_empty() {
+                true => format!(
+                    "{} Not Found In The {} JSON Of {}",
+                    key, data_type, assembly
+                ),
+                false => format!(
+                    "{} Not Found In The {} JSON Of {}, Near Misses: {}",
+                    key,
+                    data_type,
+                    assembly,
+                    near_misses.join(", ")
+                ),
+            },
+        )),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct JsonQueryResults {
+    pub values: BTreeMap<String, String>,
+    pub missing: Vec<String>,
+    // Missing keys that are in the JSON, by the target field they lack
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub missing_fields: BTreeMap<String, String>,
+    // Keys found only once normalized, by the key of the JSON matched and how
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub normalized: BTreeMap<String, (String, KeyNormalization)>,
+    // Missing keys by their near misses, or by every key they ambiguously normalize to
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub candidates: BTreeMap<String, Vec<String>>,
+}
+
+// Answers several json_query lookups with a single parse of the JSON file
+pub fn json_query_many(
+    assembly: &str,
+    data_type: &str,
+    keys: &[String],
+    target: &Option<String>,
+    data_directory: impl AsRef<Path>,
+) -> Result<JsonQueryResults> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    let assembly_data = AssemblyData::load(assembly, data_directory)?;
+    Ok(json_lookup_many(
+        assembly_data.data(data_type),
+        assembly_data.key_index(data_type),
+        keys,
+        target,
+    ))
+}
+
+// Dumps the target value (or presence) of every key in the JSON file
+pub fn json_query_all(
+    assembly: &str,
+    data_type: &str,
+    target: &Option<String>,
+    data_directory: impl AsRef<Path>,
+) -> Result<JsonQueryResults> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    let assembly_data = AssemblyData::load(assembly, data_directory)?;
+    let data = assembly_data.data(data_type);
+    let keys: Vec<String> = match data {
+        Value::Object(map) => map.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    Ok(json_lookup_many(
+        data,
+        assembly_data.key_index(data_type),
+        &keys,
+        target,
+    ))
+}
+
+// How a sequence query found its accession, the key of the sequences JSON, the BED files and
+// the index. Tried in this order, the first to find a sequence is used.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceResolution {
+    Accession, // The accession as given
+    Dfamseq,   // DS000000001 for the bare accession 1, or 1 for DS000000001
+    Id,        // The display name, "id" of the sequences JSON
+}
+
+// The forms a sequence may be given in, for errors about ones that match none
+pub const SEQUENCE_FORMS: &str = "An Accession (1), A Dfamseq Accession (DS000000001), A Sequence Id (chr1), Any Of Them Qualified By The Assembly (hg38:chr1)";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ResolvedSequence {
+    pub query: String,
+    pub accession: String,
+    pub resolution: SequenceResolution,
+    pub qualified: bool, // The query named the assembly, <assembly>:<sequence>
+}
+
+// The other spelling of a Dfamseq accession: the bare number of DS<digits>, in any case and
+// with or without a .<version>, or DS and nine digits of a bare number
+fn dfamseq_alternate(query: &str) -> Option<String> {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if digits(query) {
+        return query.parse::<u64>().ok().map(|n| format!("DS{:09}", n));
+    }
+    let prefix = query.get(..2)?;
+    if !prefix.eq_ignore_ascii_case("DS") {
+        return None;
+    }
+    let number = match query[2..].split_once('.') {
+        Some((number, version)) if digits(version) => number,
+        Some(_) => return None,
+        None => &query[2..],
+    };
+    if !digits(number) {
+        return None;
+    }
+    number.parse::<u64>().ok().map(|n| n.to_string())
+}
+
+// The sequence of a query given as an accession, a Dfamseq accession, or a display name.
+// Surrounding whitespace is ignored. The query as given is tried as an accession first, then
+// without an <assembly>: qualifier naming the assembly folder or the "assembly" of the
+// sequences JSON, each by SequenceResolution in order. Ids compare exactly.
+fn find_sequence(
+    assembly_data: &AssemblyData,
+    assembly: &str,
+    query: &str,
+) -> Option<ResolvedSequence> {
+    let sequences = assembly_data.sequence_info();
+    let accessions: HashSet<&str> = sequences.iter().map(|(acc, _)| acc.as_str()).collect();
+    let trimmed = query.trim();
+    let resolved = |accession: &str, resolution, qualified| ResolvedSequence {
+        query: query.to_string(),
+        accession: accession.to_string(),
+        resolution,
+        qualified,
+    };
+    if accessions.contains(trimmed) {
+        return Some(resolved(trimmed, SequenceResolution::Accession, false));
+    }
+    let json_assembly = assembly_data
+        .json(SEQUENCE_DIR)
+        .get("assembly")
+        .and_then(|a| a.as_str());
+    let (name, qualified) = match trimmed.split_once(':') {
+        Some((qualifier, name))
+            if qualifier.eq_ignore_ascii_case(assembly)
+                || json_assembly.is_some_and(|a| qualifier.eq_ignore_ascii_case(a)) =>
+        {
+            (name, true)
+        }
+        _ => (trimmed, false),
+    };
+    if qualified && accessions.contains(name) {
+        return Some(resolved(name, SequenceResolution::Accession, true));
+    }
+    if let Some(alternate) = dfamseq_alternate(name) {
+        if accessions.contains(alternate.as_str()) {
+            return Some(resolved(&alternate, SequenceResolution::Dfamseq, qualified));
+        }
+    }
+    let mut without_id = 0;
+    for (acc, info) in sequences {
+        match &info.id {
+            Some(id) if id == name => {
+                return Some(resolved(acc, SequenceResolution::Id, qualified))
+            }
+            Some(_) => {}
+            None => without_id += 1,
+        }
+    }
+    if without_id > 0 {
+        eprintln!(
+            "Warning: {} Sequences Of {} Have No Id And Were Skipped",
+            without_id, assembly
+        );
+    }
+    None
+}
+
+fn sequence_not_found(assembly: &str, query: &str) -> String {
+    format!(
+        "Sequence \"{}\" Not Found In {}, Expected {}",
+        query, assembly, SEQUENCE_FORMS
+    )
+}
+
+// The accession of a sequence given in any of the forms find_sequence accepts, and how it
+// was found. Nothing matching is NotFound, listing the forms accepted.
+pub fn resolve_sequence(
+    assembly: &str,
+    query: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<ResolvedSequence> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(SEQUENCE_DIR), data_directory)?;
+    let assembly_data = contig_order::load_sequences(assembly, data_directory)?;
+    find_sequence(&assembly_data, assembly, query)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, sequence_not_found(assembly, query)))
+}
+
+// Exact lookup of a sequence by accession or by its "id", for scripting, resolved as
+// resolve_sequence does. Returns the accession, or "-1" with a warning if nothing matches, as
+// json_query does. Sequences without an id can only be found by accession, a failed lookup
+// warns how many there are.
+pub fn get_chrom_id(
+    assembly: &str,
+    query: &str,
+    data_directory: impl AsRef<Path>,
 ) -> Result<String> {
-    let assembly_path: String = format!("{}/{}", &data_directory, &assembly);
-    // confirm assembly_id and ensure that it accessable
-    if !Path::new(&assembly_path).exists() {
-        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(SEQUENCE_DIR), data_directory)?;
+    let assembly_data = contig_order::load_sequences(assembly, data_directory)?;
+    match find_sequence(&assembly_data, assembly, query) {
+        Some(resolved) => Ok(resolved.accession),
+        None => {
+            eprintln!("Warning: {}", sequence_not_found(assembly, query));
+            Ok("-1".to_string())
+        }
     }
+}
 
-    let (filenames, bgz_dir, mut contig_index, index_file) =
-        match idx::prep_idx(&assembly_path, &data_type) {
-            Ok(res) => res,
-            Err(e) => panic!("Search Prep Failed, Index may not exist - {:?}", e),
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SequenceMatch {
+    pub accession: String,
+    pub id: Option<String>,
+    pub length: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SequenceMatches {
+    pub matches: Vec<SequenceMatch>,
+    pub total: usize, // Matches found before the limit was applied
+    pub truncated: bool,
+}
+
+// Case-insensitive search of the sequence accessions and ids for pattern. Prefix matches
+// are listed before substring matches, each in accession order, and at most limit are kept.
+pub fn find_sequences(
+    assembly: &str,
+    pattern: &str,
+    limit: usize,
+    data_directory: impl AsRef<Path>,
+) -> Result<SequenceMatches> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(SEQUENCE_DIR), data_directory)?;
+    let assembly_data = contig_order::load_sequences(assembly, data_directory)?;
+    let pattern = pattern.to_lowercase();
+    let mut prefix_matches = Vec::new();
+    let mut substring_matches = Vec::new();
+    for (acc, info) in assembly_data.sequence_info() {
+        let names: Vec<String> = [Some(acc), info.id.as_ref()]
+            .iter()
+            .flatten()
+            .map(|n| n.to_lowercase())
+            .collect();
+        let found = SequenceMatch {
+            accession: acc.to_string(),
+            id: info.id.clone(),
+            length: info.length,
         };
+        if names.iter().any(|n| n.starts_with(&pattern)) {
+            prefix_matches.push(found);
+        } else if names.iter().any(|n| n.contains(&pattern)) {
+            substring_matches.push(found);
+        }
+    }
+    prefix_matches.sort_by(|a, b| a.accession.cmp(&b.accession));
+    substring_matches.sort_by(|a, b| a.accession.cmp(&b.accession));
+    let mut matches = prefix_matches;
+    matches.append(&mut substring_matches);
+    let total = matches.len();
+    matches.truncate(limit);
+    Ok(SequenceMatches {
+        matches,
+        total,
+        truncated: total > limit,
+    })
+}
 
-    if !Path::new(&index_file).exists() {
-        panic!(
-            "Assembly \"{}\" Is Not Indexed For {}",
-            assembly_path, &data_type
+// One entry of the sequences JSON "data". Exports differ in the fields they carry and some
+// have null entries, so every field is optional and others are ignored.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SequenceInfo {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub length: Option<u64>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+// The entries of a sequences "data" object by accession. Null entries and entries with
+// fields of the wrong type have every field missing, and are counted in a warning instead
+// of failing the lookup.
+pub fn parse_sequence_info(assembly: &str, data: &Value) -> Vec<(String, SequenceInfo)> {
+    let Some(data) = data.as_object() else {
+        return Vec::new();
+    };
+    let mut malformed = 0;
+    let entries = data
+        .iter()
+        .map(|(acc, vals)| {
+            let info = match serde_json::from_value::<Option<SequenceInfo>>(vals.clone()) {
+                Ok(Some(info)) => info,
+                Ok(None) | Err(_) => {
+                    malformed += 1;
+                    SequenceInfo::default()
+                }
+            };
+            (acc.to_string(), info)
+        })
+        .collect();
+    if malformed > 0 {
+        eprintln!(
+            "Warning: {} Sequences Of {} Are Null Or Malformed, Their Fields Are Treated As Missing",
+            malformed, assembly
         );
     }
-    let results = idx::search_idx(
-        &filenames,
-        &bgz_dir,
-        &mut contig_index,
-        &index_file,
-        &chrom,
-        start,
-        end,
-        family,
-        *nrph,
-        true,
-    );
+    entries
+}
 
-    let mut formatted = Vec::new();
-    match &results {
-        Err(e) => {
-            panic!("Index Search Failed - {}", e);
+// The model_lengths and sequences JSON of an assembly, each read at most once. A consolidated
+// assembly_data.json is read whole when present, otherwise each separate file on first use.
+pub struct AssemblyData {
+    assembly: String,
+    data_directory: String,
+    consolidated: bool,
+    model_lengths: OnceCell<Value>,
+    sequences: OnceCell<Value>,
+    sequence_info: OnceCell<Vec<(String, SequenceInfo)>>,
+    model_length_keys: OnceCell<JsonKeyIndex>,
+    sequence_keys: OnceCell<JsonKeyIndex>,
+}
+
+impl AssemblyData {
+    pub fn load(assembly: &str, data_directory: impl AsRef<Path>) -> Result<AssemblyData> {
+        let data_directory = &path_string(data_directory);
+        let assembly_data = AssemblyData {
+            assembly: assembly.to_string(),
+            data_directory: data_directory.to_string(),
+            consolidated: false,
+            model_lengths: OnceCell::new(),
+            sequences: OnceCell::new(),
+            sequence_info: OnceCell::new(),
+            model_length_keys: OnceCell::new(),
+            sequence_keys: OnceCell::new(),
+        };
+        let consolidated_file = join_path(data_directory, &[assembly, ASSEMBLY_DATA_FILE]);
+        if !Path::new(&consolidated_file).exists() {
+            return Ok(assembly_data);
         }
-        Ok(l) if l.as_slice().is_empty() => {
-            return Ok("[]".to_string());
+        let mut in_data: Value = serde_json::from_str(&read_to_string(&consolidated_file)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let version = in_data.get("version").and_then(|v| v.as_u64());
+        if version != Some(ASSEMBLY_DATA_VERSION) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} Has Version {:?}, Expected {}",
+                    consolidated_file, version, ASSEMBLY_DATA_VERSION
+                ),
+            ));
         }
-        Ok(l) => {
-            for i in 0..l.len() {
-                let fields = l[i].split("\t").collect::<Vec<&str>>();
-                formatted.push(FormattableLine::from_bed(&fields, data_type).to_json());
+        for (data_type, cell) in [
+            (MOD_LEN_DIR, &assembly_data.model_lengths),
+            (SEQUENCE_DIR, &assembly_data.sequences),
+        ] {
+            match in_data.get_mut(data_type) {
+                Some(json) => {
+                    let _ = cell.set(json.take());
+                }
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} Has No {}", consolidated_file, data_type),
+                    ))
+                }
             }
         }
-    };
-    match serde_json::to_string(&formatted) {
-        Err(e) => {
-            panic!("Error Converting Results to JSON - {e}");
-        }
-        Ok(json_str) => {
-            return Ok(json_str);
+        Ok(AssemblyData {
+            consolidated: true,
+            ..assembly_data
+        })
+    }
+
+    // Whether the JSON came from assembly_data.json rather than the separate files
+    pub fn is_consolidated(&self) -> bool {
+        self.consolidated
+    }
+
+    // The whole JSON of model_lengths or sequences, as found in its separate file
+    pub fn json(&self, data_type: &str) -> &Value {
+        let cell = match data_type {
+            MOD_LEN_DIR => &self.model_lengths,
+            SEQUENCE_DIR => &self.sequences,
+            _ => panic!("{} Is Not A JSON Data Type", data_type),
+        };
+        cell.get_or_init(|| load_json_data(&self.assembly, data_type, &self.data_directory))
+    }
+
+    // The "data" object of model_lengths or sequences, keyed by accession
+    pub fn data(&self, data_type: &str) -> &Value {
+        match self.json(data_type).get("data") {
+            Some(data) => data,
+            None => panic!("{} JSON Of {} Has No Data", data_type, self.assembly),
         }
     }
+
+    // The keys of the "data" object of model_lengths or sequences by their normalized forms,
+    // for json_query lookups of keys not given exactly
+    pub fn key_index(&self, data_type: &str) -> &JsonKeyIndex {
+        let cell = match data_type {
+            MOD_LEN_DIR => &self.model_length_keys,
+            SEQUENCE_DIR => &self.sequence_keys,
+            _ => panic!("{} Is Not A JSON Data Type", data_type),
+        };
+        cell.get_or_init(|| JsonKeyIndex::new(self.data(data_type)))
+    }
+
+    // The sequences "data" typed, see parse_sequence_info
+    pub fn sequence_info(&self) -> &Vec<(String, SequenceInfo)> {
+        self.sequence_info
+            .get_or_init(|| parse_sequence_info(&self.assembly, self.data(SEQUENCE_DIR)))
+    }
 }
 
-pub fn json_query(
-    assembly: &String,
-    data_type: &String,
-    key: &String,
-    target: &Option<String>,
-    data_directory: &String,
-) -> Result<String> {
-    let target_file = format!(
-        "{}/{}/{}/{}-{}.json",
-        &data_directory, &assembly, &data_type, &assembly, &data_type
+// Writes the model_lengths and sequences JSON of a prepared assembly into assembly_data.json,
+// which AssemblyData then reads instead of the separate files. Both must name one assembly.
+pub fn consolidate_assembly_data(assembly: &str, data_directory: impl AsRef<Path>) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    let model_lengths = load_json_data(assembly, MOD_LEN_DIR, data_directory);
+    let sequences = load_json_data(assembly, SEQUENCE_DIR, data_directory);
+    let names =
+        [&model_lengths, &sequences].map(|json| json.get("assembly").and_then(|a| a.as_str()));
+    if names[0].is_none() || names[0] != names[1] {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Model Lengths Of {} Are For Assembly {:?} But Sequences Are For {:?}, Not Consolidating",
+                assembly, names[0], names[1]
+            ),
+        ));
+    }
+    let consolidated = json!({
+        "version": ASSEMBLY_DATA_VERSION,
+        "assembly": names[0],
+        MOD_LEN_DIR: model_lengths,
+        SEQUENCE_DIR: sequences,
+    });
+    // Written beside the target, not in scratch, and renamed so a reader never sees a partial file
+    let target_file = join_path(data_directory, &[assembly, ASSEMBLY_DATA_FILE]);
+    let tmp_file = format!("{}.tmp", target_file);
+    serde_json::to_writer(File::create(&tmp_file)?, &consolidated)?;
+    rename(&tmp_file, &target_file)
+}
+
+fn load_json_data(assembly: &str, data_type: &str, data_directory: &str) -> Value {
+    let target_file = join_path(
+        data_directory,
+        &[
+            assembly,
+            data_type,
+            &format!("{}-{}.json", assembly, data_type),
+        ],
     );
     if !Path::new(&target_file).exists() {
         panic!("{} Not Found", &target_file);
     }
-
     let in_str = read_to_string(&target_file).expect("Could Not Read String");
-    let in_data: Value = serde_json::from_str(&in_str).expect("JSON was not well-formatted");
-    let data = in_data.get("data").unwrap();
+    serde_json::from_str(&in_str).expect("JSON was not well-formatted")
+}
 
-    let mut val = None;
-    let ret_val = Value::String("1".to_string());
-    match target {
-        Some(target) => val = data.get(key).and_then(|item| item.get(target)),
-        None => {
-            if let Value::Object(map) = data {
-                if map.contains_key(key) {
-                    val = Some(&ret_val)
+fn json_lookup_many(
+    data: &Value,
+    index: &JsonKeyIndex,
+    keys: &[String],
+    target: &Option<String>,
+) -> JsonQueryResults {
+    let mut results = JsonQueryResults {
+        values: BTreeMap::new(),
+        missing: Vec::new(),
+        missing_fields: BTreeMap::new(),
+        normalized: BTreeMap::new(),
+        candidates: BTreeMap::new(),
+    };
+    for key in keys {
+        match lookup_key(data, index, key, target) {
+            TargetLookup::Found(found) => {
+                if found.normalization != KeyNormalization::Exact {
+                    results
+                        .normalized
+                        .insert(key.clone(), (found.key, found.normalization));
+                }
+                results.values.insert(key.clone(), found.value);
+            }
+            TargetLookup::MissingField(_, field) => {
+                results.missing_fields.insert(key.clone(), field);
+                results.missing.push(key.clone())
+            }
+            TargetLookup::Missing(near_misses) => {
+                if !near_misses.is_empty() {
+                    results.candidates.insert(key.clone(), near_misses);
                 }
+                results.missing.push(key.clone())
+            }
+            TargetLookup::Ambiguous(_, keys) => {
+                results.candidates.insert(key.clone(), keys);
+                results.missing.push(key.clone())
             }
         }
     }
+    results
+}
 
-    match val {
-        Some(val) => {
-            return Ok(val.to_string().replace("\"", ""));
-        }
-        None => return Ok("-1".to_string()),
+// Returns the target value of key, or "1" if only the presence of key was asked for. A null
+// target is missing, as for a null entry.
+fn json_lookup(data: &Value, key: &str, target: &Option<String>) -> Option<String> {
+    match target {
+        Some(target) => data
+            .get(key)
+            .and_then(|item| item.get(target))
+            .filter(|val| !val.is_null())
+            .map(|val| val.to_string().replace("\"", "")),
+        None => match data {
+            Value::Object(map) if map.contains_key(key) => Some("1".to_string()),
+            _ => None,
+        },
     }
 }
 
+// The target field a key found in data has no value for, None when the key itself is missing
+fn missing_field(data: &Value, key: &str, target: &Option<String>) -> Option<String> {
+    data.get(key)?;
+    target.clone()
+}
+
 pub fn all_annotations(
-    assembly: &String,
+    assembly: &str,
     outfile: &Option<String>,
-    data_directory: &String,
+    profile: &str,
+    exclude_columns: &[String],
+    compression: OutputCompression,
+    max_buffered_bytes: u64,
+    data_directory: impl AsRef<Path>,
 ) -> Result<()> {
-    let assembly_path: String = format!("{}/{}", &data_directory, &assembly);
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(ASSEMBLY_DIR), data_directory)?;
+    let assembly_path: String = join_path(data_directory, &[assembly]);
     // confirm assembly_id and ensure that it accessable
     if !Path::new(&assembly_path).exists() {
         panic!("Assembly \"{}\" Does Not Exist", assembly_path);
     }
-    let annotation_files: Vec<_> = WalkDir::new(format!("{}/{}", &assembly_path, ASSEMBLY_DIR))
+    let annotation_files: Vec<_> = WalkDir::new(join_path(&assembly_path, &[ASSEMBLY_DIR]))
         .into_iter()
         .filter_map(|e| e.ok())
         .collect();
 
+    let mut writer = filter_writer(outfile, NonZeroUsize::MIN, compression, max_buffered_bytes)?;
+    let options = FilterOptions::builder()
+        .profile(profile)
+        .exclude_columns(exclude_columns)
+        .build();
     for file in annotation_files {
         let file = &file.file_name().to_str().unwrap().to_string();
         if file.ends_with(".bed.bgz") {
-            bgzf_filter(
+            bgzf_filter_with(
                 assembly,
-                &ASSEMBLY_DIR.to_string(),
-                &file.split(".").collect::<Vec<&str>>()[0].to_string(),
-                &1,
-                &None,
-                outfile,
-                false,
+                ASSEMBLY_DIR,
+                file.split(".").collect::<Vec<&str>>()[0],
+                &mut writer,
+                &options,
                 data_directory,
             )
             .expect("Filter Failed");
         }
     }
-    return Ok(());
+    Ok(())
 }
 
 pub fn assembly_data(
-    assembly: &String,
-    data_directory: &String,
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
     summary: &bool,
     annotations: &bool,
     benchmarks: &bool,
     masks: &bool,
     chromosomes: &bool,
 ) -> Result<()> {
-    let assembly_path: String = format!("{}/{}", &data_directory, &assembly);
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, None, data_directory)?;
+    let assembly_path: String = join_path(data_directory, &[assembly]);
     // confirm assembly_id and ensure that it accessable
     if !Path::new(&assembly_path).exists() {
         panic!("Assembly \"{}\" Does Not Exist", assembly_path);
     }
 
-    let annotation_files: Vec<_> = WalkDir::new(format!("{}/{}", &assembly_path, ASSEMBLY_DIR))
+    let annotation_files: Vec<_> = WalkDir::new(join_path(&assembly_path, &[ASSEMBLY_DIR]))
         .into_iter()
         .filter_map(|e| e.ok())
         .collect();
-    let benchmark_files: Vec<_> = WalkDir::new(format!("{}/{}", &assembly_path, BENCHMARK_DIR))
+    let benchmark_files: Vec<_> = WalkDir::new(join_path(&assembly_path, &[BENCHMARK_DIR]))
         .into_iter()
         .filter_map(|e| e.ok())
         .collect();
-    let mask_files: Vec<_> = WalkDir::new(format!("{}/{}", &assembly_path, MASKS_DIR))
+    let mask_files: Vec<_> = WalkDir::new(join_path(&assembly_path, &[MASKS_DIR]))
         .into_iter()
         .filter_map(|e| e.ok())
         .collect();
 
-    let in_str = read_to_string(format!(
-        "{}/{}/{}{}",
-        &assembly_path, SEQUENCE_DIR, &assembly, SEQUENCE_FILE
+    let in_str = read_to_string(join_path(
+        &assembly_path,
+        &[SEQUENCE_DIR, &format!("{}{}", assembly, SEQUENCE_FILE)],
     ))
     .expect("Could Not Read String");
     let in_data: Value = serde_json::from_str(&in_str).expect("JSON was not well-formatted");
@@ -962,7 +5334,7 @@ pub fn assembly_data(
         println!("Benchark Files Count: {}", benchmark_files.len());
         println!("Simple Repeat Files Count: {}", mask_files.len());
         println!("Chromosomes Count: {}", &chrom_data.len());
-        println!("");
+        println!();
     }
 
     if *annotations {
@@ -970,7 +5342,7 @@ pub fn assembly_data(
         for file in annotation_files {
             println!("{}", file.file_name().to_string_lossy());
         }
-        println!("");
+        println!();
     }
 
     if *benchmarks {
@@ -978,7 +5350,7 @@ pub fn assembly_data(
         for file in benchmark_files {
             println!("{}", file.file_name().to_string_lossy());
         }
-        println!("");
+        println!();
     }
 
     if *masks {
@@ -986,7 +5358,7 @@ pub fn assembly_data(
         for file in mask_files {
             println!("{}", file.file_name().to_string_lossy());
         }
-        println!("");
+        println!();
     }
 
     if *chromosomes {
@@ -994,41 +5366,13 @@ pub fn assembly_data(
         for chrom in chrom_data {
             println!("{}", chrom);
         }
-        println!("");
+        println!();
     }
 
-    return Ok(());
+    Ok(())
 }
 
 // OLD Methods ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-// pub fn get_chrom_id(assembly: &String, query: &String, data_directory: &String) -> String {
-
-//     let target_file = format!(
-//         "{}/{}/{}/{}-{}.json",
-//         &data_directory, &assembly, SEQUENCE_DIR, &assembly, SEQUENCE_DIR
-//     );
-//     if !Path::new(&target_file).exists() {
-//         eprintln!("{} Not Found", &target_file);
-//         std::process::exit(1)
-//     }
-//     let in_str = read_to_string(&target_file).expect("Could Not Read String");
-//     let in_json: Value = serde_json::from_str(&in_str).expect("JSON was not well-formatted");
-//     let data = in_json.get("data").expect("No Data");
-//     if let Some(data) = data.as_object() {
-//         for (acc, val) in data {
-//             if let Some(vals) = val.as_object() {
-//                 let id = vals.get("id").expect("Oh no");
-//                 if &id.to_string().replace("\"", "") == query {
-//                     return acc.to_string();
-//                 }
-//             }
-//         }
-//         panic!("Sequence ID Not Found")
-//     } else {
-//         panic!("Something Didn't Work")
-//     }
-// }
-
 // pub fn process_json(in_file: &String, key: &String, outfile: &Option<String>) -> Result<()> {
 //     if !Path::new(&in_file).exists() {
 //         eprintln!("{} Not Found", &in_file);
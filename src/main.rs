@@ -1,20 +1,78 @@
 use clap::builder::PossibleValuesParser;
-use clap::{Parser, Subcommand};
+use clap::{ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::fs::read_to_string;
+use std::io::{stderr, stdout, BufWriter, ErrorKind, IsTerminal, Write};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use te_idx::bgzf_filter;
-// use te_idx::get_chrom_id;
 use te_idx::all_annotations;
+use te_idx::append_records;
 use te_idx::assembly_data;
-use te_idx::idx_query;
+use te_idx::bgz_path;
+use te_idx::bgzf_filter_with;
+use te_idx::dump_records;
+use te_idx::family_combined;
+use te_idx::filter_parameters;
+use te_idx::find_sequences;
+use te_idx::get_chrom_id;
+use te_idx::idx_query_into;
 use te_idx::json_query;
-use te_idx::prep_beds;
+use te_idx::json_query_all;
+use te_idx::json_query_many;
+use te_idx::list_assemblies;
+use te_idx::list_families;
+use te_idx::missing_families;
+use te_idx::prep_beds_with;
 use te_idx::prepare_assembly;
+use te_idx::read_at;
 use te_idx::read_family_assembly_annotations;
+use te_idx::regenerate_export;
+use te_idx::resolve_sequence;
+use te_idx::source_info;
+use te_idx::write_family_combined;
+use te_idx::{filter_worker_count, filter_writer, OutputCompression};
+use te_idx::{BedFormat, BED_FORMATS};
+use te_idx::{MaskMode, MaskSubtraction, ResultGrouping, DEFAULT_MASK_OVERLAP, GROUP_BY_FAMILY};
 
-mod idx;
+use te_idx::{idx, paths};
 
-use te_idx::{DATA_DIR, EXPORT_DIR, INDEX_DATA_TYPES, JSON_DATA_TYPES};
+use idx::{
+    RegionError, DEFAULT_CHUNK_SIZE, DEFAULT_INDEX_RETENTION_MINS, DEFAULT_MAX_DUPLICATION,
+    DEFAULT_MAX_REGION, TILE_SIZE,
+};
+use paths::join_path;
+use te_idx::backpressure::configured_max_buffered_bytes;
+use te_idx::benchmark::{benchmark_report, DEFAULT_MIN_OVERLAP};
+use te_idx::compact::compact_family;
+use te_idx::contig_order::contig_order;
+use te_idx::cooccurrence::{cooccurrence, DEFAULT_TOP_N, DEFAULT_WINDOW_BP};
+use te_idx::defragment::{defragment, DEFAULT_MAX_GAP_BP, DEFAULT_MODEL_TOLERANCE};
+use te_idx::delta::{apply_delta, release_delta};
+use te_idx::fasta::export_family_fasta;
+#[cfg(feature = "http")]
+use te_idx::http::{serve, DEFAULT_BIND};
+use te_idx::layout::repair_layout;
+use te_idx::manifest::{export_inputs, verify_manifest, write_manifest};
+use te_idx::migrate::{build_info, migrate_assembly};
+use te_idx::options::{FilterOptions, IdxQueryOptions, PrepOptions};
+use te_idx::package::{package_assembly, unpack_assembly};
+use te_idx::plan::plan_assembly;
+use te_idx::querylog::{QueryLogger, DEFAULT_QUERY_LOG_MAX_MB};
+use te_idx::redundancy::{redundancy_scan, DEFAULT_MIN_PAIR_COUNT, DEFAULT_MIN_RECIPROCAL_OVERLAP};
+use te_idx::remap::{remap_contig_names, NameMapping};
+use te_idx::roots::{cache_status, configured_data_roots, resolve_root, warm_assembly};
+use te_idx::sample::{sample_records, validate_sample, DEFAULT_SAMPLE_SIZE};
+use te_idx::selftest::self_test;
+use te_idx::seqlens::{check_seq_lens, repair_seq_lens, DEFAULT_SEQ_LEN_EXAMPLES};
+use te_idx::status::StatusFile;
+use te_idx::usage::assembly_usage;
+use te_idx::{check_scratch_dir, configured_scratch_dir, scratch_dir};
+use te_idx::{
+    ASSEMBLY_DIR, COLUMN_PROFILES, DATA_DIR, DATA_ELEMENTS, DEFAULT_MIN_SCRATCH_FREE_MB,
+    DEFAULT_SHARD_THRESHOLD, DEFAULT_SORT_BUFFER_BYTES, EXPORT_DIR, INDEX_DATA_TYPES,
+    JSON_DATA_TYPES, PUBLIC_PROFILE, SEQUENCE_DIR,
+};
 
 #[derive(Parser)]
 #[command(author, version, about)]
@@ -23,14 +81,57 @@ pub struct Cli {
     #[clap(short, long, verbatim_doc_comment)]
     pub data_dir: Option<String>,
 
+    /// Data folders queries read an assembly from, in order of preference, repeated or comma
+    /// separated, e.g. an SSD cache before the archive. Default is $TE_IDX_DATA_ROOTS, else
+    /// --data-dir. Commands that write always write to --data-dir
+    #[clap(long, verbatim_doc_comment, value_delimiter = ',')]
+    pub data_root: Vec<String>,
+
     /// Name of export folder, default is /usr/local/Dfam-warehouse/releases/annotation_exports
     #[clap(short, long, verbatim_doc_comment)]
     pub exp_dir: Option<String>,
 
-    /// Name of assembly/assembly folder
-    #[arg(short, long, verbatim_doc_comment)]
+    /// Name of assembly/assembly folder, required by every command but list-assemblies
+    #[arg(
+        short,
+        long,
+        verbatim_doc_comment,
+        default_value = "",
+        hide_default_value = true
+    )]
     assembly: String,
 
+    /// Folder for temporary files, default is $TE_IDX_SCRATCH_DIR, else the data folder
+    #[clap(long, verbatim_doc_comment)]
+    pub scratch_dir: Option<String>,
+
+    /// Free space the scratch folder must have before writing, in MB
+    #[clap(long, verbatim_doc_comment, default_value_t = DEFAULT_MIN_SCRATCH_FREE_MB)]
+    pub min_scratch_free_mb: u64,
+
+    /// File to append one JSON line per bgzf-filter, idx-query or json-query key lookup to,
+    /// default is $TE_IDX_QUERY_LOG, else no log
+    #[clap(long, verbatim_doc_comment)]
+    pub query_log: Option<String>,
+
+    /// Size in MB at which the query log is renamed to <path>.<n> and a new one started, 0 never rotates
+    #[clap(long, verbatim_doc_comment, default_value_t = DEFAULT_QUERY_LOG_MAX_MB)]
+    pub query_log_max_mb: u64,
+
+    /// Output in MB that may wait for a slow destination before writing stalls, for prep
+    /// and exports, default is $TE_IDX_MAX_BUFFERED_MB, else 64
+    #[clap(long, verbatim_doc_comment)]
+    pub max_buffered_mb: Option<u64>,
+
+    /// JSON file kept up to date with the progress of prepare-assembly, prep-beds, build-idx,
+    /// regenerate-export and compact, rewritten at least every few seconds while they run
+    #[clap(long, verbatim_doc_comment)]
+    pub status_file: Option<String>,
+
+    /// No color or redrawn progress lines, as when output is not a terminal or NO_COLOR is set
+    #[clap(long, verbatim_doc_comment)]
+    pub plain: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -43,21 +144,73 @@ pub enum Commands {
         #[arg(short, long, verbatim_doc_comment)]
         #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
         data_type: String,
-        /// Family name, corresponds to compressed TSV file prefix
-        #[arg(long, short, verbatim_doc_comment)]
-        fam: String,
+        /// File to search: family accession, or sequence accession for masks.
+        /// --fam is accepted as well
+        #[arg(long, short = 'f', verbatim_doc_comment, alias = "fam")]
+        key: String,
         /// Column number to be searched. 1-indexed
         #[arg(long, short, verbatim_doc_comment)]
         position: usize,
         /// Term to be searched for. If absent, all rows will be returned
         #[arg(long, short, verbatim_doc_comment)]
         term: Option<String>,
+        /// Flag to only match a family key, or a term on the family column, given with a version to that version
+        #[arg(long, verbatim_doc_comment)]
+        exact_version: bool,
+        /// Optional: Only return hits on this strand, + or -
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(["+", "-"]))]
+        strand: Option<String>,
+        /// Optional: Only return records with a bias of at most this value
+        #[arg(long, verbatim_doc_comment)]
+        max_bias: Option<f64>,
+        /// Flag to leave out records with an empty or non-numeric bias under --max-bias, rather than keep them
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("max_bias"))]
+        strict_bias: bool,
         /// Path to file to save filtered data. Should end in .bed.bgz
         #[arg(long, short, verbatim_doc_comment)]
         outfile: Option<String>,
         /// Flag to reformat the feilds to match Dfam.org download file format
         #[arg(long, short, verbatim_doc_comment)]
         web_fmt: bool,
+        /// Column set to write: public leaves out cigar and caf, internal keeps every column
+        #[arg(long, verbatim_doc_comment, default_value = PUBLIC_PROFILE)]
+        #[clap(value_parser = PossibleValuesParser::new(COLUMN_PROFILES))]
+        profile: String,
+        /// Optional: Comma separated column names to leave out, on top of the profile
+        #[arg(long, verbatim_doc_comment, value_delimiter = ',')]
+        exclude_columns: Vec<String>,
+        /// Flag to prefix the output with the export's source metadata as ## lines
+        #[arg(long, verbatim_doc_comment)]
+        source_info: bool,
+        /// Flag to split and compare every line, rather than skipping lines without the term
+        #[arg(long, verbatim_doc_comment)]
+        no_prefilter: bool,
+        /// Flag to add classification and clades columns from the assembly's families JSON,
+        /// empty when it does not have the family
+        #[arg(long, verbatim_doc_comment)]
+        with_family_meta: bool,
+        /// Optional: Write standard BED6 or BED12 lines, without a header, instead of the BED columns
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(BED_FORMATS))]
+        #[clap(conflicts_with_all(["web_fmt", "exclude_columns", "source_info", "with_family_meta"]))]
+        format: Option<String>,
+        /// Name chromosomes by their sequence id rather than their accession
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("format"))]
+        resolve_names: bool,
+        /// Write plain gzip, as historical Dfam downloads are, instead of BGZF.
+        /// The outfile should then end in .gz rather than .bgz
+        #[arg(long, verbatim_doc_comment)]
+        gzip_plain: bool,
+        /// Flag to keep sequence accessions in the output of an assembly that redacts them, for internal tooling
+        #[arg(long, verbatim_doc_comment)]
+        ignore_redaction: bool,
+        /// Flag to write <outfile>.manifest.json recording the version, parameters, inputs and output checksum
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("outfile"))]
+        manifest: bool,
     },
     /// Build file for grouped .bed.bgz files
     BuildIdx {
@@ -65,6 +218,25 @@ pub enum Commands {
         #[arg(short, long, verbatim_doc_comment)]
         #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
         data_type: String,
+        /// Tile size in bp, larger tiles store long ranges in fewer tiles
+        #[arg(long, verbatim_doc_comment, default_value_t = TILE_SIZE)]
+        #[clap(value_parser = clap::value_parser!(u32).range(1..))]
+        tile_size: u32,
+        /// Store ranges spanning more than this many tiles once, in a per contig
+        /// overflow list read by every search of the contig (index format version 2)
+        #[arg(long, verbatim_doc_comment)]
+        overflow_tiles: Option<usize>,
+        /// Warn when ranges are stored more than this many times over on average
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_MAX_DUPLICATION)]
+        max_duplication: f64,
+        /// Flag to seek back to every record once its file is indexed and fail the build if the
+        /// line read there is not the one indexed
+        #[arg(long, verbatim_doc_comment)]
+        verify_positions: bool,
+        /// Store a content hash of each file in place of its modification time, so building
+        /// again over the same files writes the same index bytes (index format version 3)
+        #[arg(long, verbatim_doc_comment)]
+        deterministic: bool,
     },
     /// Split TSV files into compressed BED files by accession
     PrepBeds {
@@ -75,30 +247,267 @@ pub enum Commands {
         #[arg(short, long, verbatim_doc_comment)]
         #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
         data_type: String,
+        /// Skip exact duplicate records, reporting how many were removed per family
+        #[arg(long, verbatim_doc_comment)]
+        dedupe: bool,
+        /// Write files to shard folders once there are more than this many
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_SHARD_THRESHOLD)]
+        shard_threshold: usize,
+        /// Always write files to shard folders
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(conflicts_with("shard_threshold"))]
+        sharded: bool,
+        /// Keep the export order instead of sorting each file by contig and position
+        #[arg(long, verbatim_doc_comment)]
+        no_sort: bool,
+        /// MB of records per file sorted in memory, larger files are sorted through scratch files
+        #[arg(long, verbatim_doc_comment, default_value_t = (DEFAULT_SORT_BUFFER_BYTES >> 20) as u64)]
+        sort_buffer_mb: u64,
+    },
+    /// Rebuild the export TSV of a data type from its prepared BED files, the reverse of prep-beds
+    RegenerateExport {
+        /// Type of data to regenerate
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Path of the TSV to write
+        #[arg(short, long, verbatim_doc_comment)]
+        outfile: String,
+        /// Write the TSV BGZF compressed, readable by gzip
+        #[arg(short, long, verbatim_doc_comment)]
+        compress: bool,
+    },
+    /// Append records from an export TSV to an existing BED file, keeping the indexed positions
+    AppendRecords {
+        /// Input TSV in the export format, every record must belong to the file
+        #[arg(short, long, verbatim_doc_comment)]
+        in_tsv: String,
+        /// Type of data being appended
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// File to append to: family accession, or sequence accession for masks.
+        /// --fam is accepted as well
+        #[arg(long, short = 'f', verbatim_doc_comment, alias = "fam")]
+        key: String,
+        /// Rebuild the data type's index once the records are appended
+        #[arg(long, verbatim_doc_comment)]
+        rebuild_idx: bool,
     },
     /// Given an assembly name, check for and process all present exports
-    PrepareAssembly {},
+    PrepareAssembly {
+        /// Also write model_lengths and sequences into one assembly_data.json, read in their place
+        #[arg(long, verbatim_doc_comment)]
+        consolidate_json: bool,
+        /// HMMER .hmm file, or folder of them, to generate the model lengths from when the
+        /// export has no model_lengths JSON
+        #[arg(long, verbatim_doc_comment)]
+        hmm: Option<String>,
+        /// Build the indexes deterministic, as build-idx --deterministic
+        #[arg(long, verbatim_doc_comment)]
+        deterministic: bool,
+        /// Print the planned actions as JSON and prepare nothing
+        #[arg(long, verbatim_doc_comment)]
+        dry_run: bool,
+    },
     /// Search indexed BED files for all hits within a range
+    #[command(
+        after_help = "Example:\n  te_idx --assembly hg38 idx-query -d assembly_alignments -c chr1 -s 10000 -e 20000"
+    )]
     IdxQuery {
         /// Type of data to be searched
         #[arg(short, long, verbatim_doc_comment)]
         #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
         data_type: String,
-        /// chromosome number/accession
+        /// chromosome number/accession, DS<digits> Dfamseq accession or sequence id,
+        /// optionally <assembly>:<chrom>
         #[arg(short, long, verbatim_doc_comment)]
         chrom: String,
-        /// start position
+        /// start position, 1-based
         #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = parse_coordinate, allow_negative_numbers(true))]
         start: u64,
-        /// end position
+        /// end position, must be greater than start
         #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = parse_coordinate, allow_negative_numbers(true))]
         end: u64,
+        /// Longest region in bp that will be searched
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_MAX_REGION)]
+        max_region: u64,
+        /// Search regions of any length
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(conflicts_with("max_region"))]
+        no_max_region: bool,
+        /// Longer regions are searched in consecutive chunks of this many bp, one at a time,
+        /// with the same results. 0 searches the region at once
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_CHUNK_SIZE)]
+        chunk_size: u64,
+        /// Optional: Only return hits matching these accessions, repeated or comma separated.
+        /// Versions are ignored unless --exact-version
+        #[arg(short, long, verbatim_doc_comment, value_delimiter = ',')]
+        family: Vec<String>,
+        /// Flag to only match a --family given with a version to that version
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("family"))]
+        exact_version: bool,
+        /// Only return NRPH hits
+        #[arg(short, long, verbatim_doc_comment)]
+        nrph: bool,
+        /// Optional: Only return hits on this strand, + or -
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(["+", "-"]))]
+        strand: Option<String>,
+        /// Optional: Only return hits with a bit score of at least this value
+        #[arg(long, verbatim_doc_comment)]
+        min_score: Option<f64>,
+        /// Optional: Only return hits with an e-value of at most this value
+        #[arg(long, verbatim_doc_comment)]
+        max_evalue: Option<f64>,
+        /// Optional: Only return hits with a bias of at most this value
+        #[arg(long, verbatim_doc_comment)]
+        max_bias: Option<f64>,
+        /// Flag to leave out hits with an empty or non-numeric bias under --max-bias, rather than keep them
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("max_bias"))]
+        strict_bias: bool,
+        /// Optional: Only return masks of this repeat class, e.g. Simple_repeat.
+        /// Masks exported without a class never match
+        #[arg(long, verbatim_doc_comment)]
+        repeat_class: Option<String>,
+        /// Optional: Keep at most N hits per W bp window, given as N:W
+        #[arg(long, verbatim_doc_comment)]
+        downsample: Option<String>,
+        /// Optional: Give up on the search after this many seconds
+        #[arg(long, verbatim_doc_comment)]
+        timeout_secs: Option<f64>,
+        /// Return the hits found before the timeout with a "truncated" flag instead of failing
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("timeout_secs"))]
+        partial: bool,
+        /// Emit every JSON value as a string, as in previous releases
+        #[arg(long, verbatim_doc_comment)]
+        legacy_json: bool,
+        /// Return {"hits": [...], "warnings": [...]} with any bgz files changed since indexing
+        #[arg(long, verbatim_doc_comment)]
+        with_warnings: bool,
+        /// Fail if a bgz file in the index is missing, instead of skipping its records
+        #[arg(long, verbatim_doc_comment)]
+        strict: bool,
+        /// Add the model_length of each hit's family, returning {"hits": [...], "missing_model_lengths": n}
+        #[arg(long, verbatim_doc_comment)]
+        with_model_length: bool,
+        /// Add the classification and clades of each hit's family from the assembly's families
+        /// JSON, null for families it does not have
+        #[arg(long, verbatim_doc_comment)]
+        with_family_meta: bool,
+        /// Report each hit's seq_len as the assembly's sequences JSON gives it, rather than the
+        /// stored column
+        #[arg(long, verbatim_doc_comment)]
+        json_seq_len: bool,
+        /// Leave out annotation hits lying in masked regions of the assembly
+        #[arg(long, verbatim_doc_comment)]
+        subtract_masks: bool,
+        /// Trim masked bases off the ends of the hits that are kept
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("subtract_masks"))]
+        clip: bool,
+        /// Keep every hit and add its masked_overlap_bp instead
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("subtract_masks"), conflicts_with("clip"))]
+        annotate_overlap: bool,
+        /// Fraction of a hit's bases that must be masked for it to be left out
+        #[arg(long, default_value_t = DEFAULT_MASK_OVERLAP, verbatim_doc_comment)]
+        #[clap(requires("subtract_masks"))]
+        mask_overlap: f64,
+        /// Optional: Return {"<family>": {"count": n, "records": [...]}} instead of the array, masks by repeat_str
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new([GROUP_BY_FAMILY]))]
+        group_by: Option<String>,
+        /// Only return the count of each group, without its records
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("group_by"))]
+        counts_only: bool,
+        /// Optional: Return at most this many records of each group, the count is still of all of them
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(value_parser = clap::value_parser!(u64).range(1..))]
+        #[clap(requires("group_by"), conflicts_with("counts_only"))]
+        per_group_limit: Option<u64>,
+        /// Optional: Return standard BED6 or BED12 lines instead of JSON
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(BED_FORMATS))]
+        #[clap(conflicts_with_all([
+            "downsample",
+            "partial",
+            "with_warnings",
+            "with_model_length",
+            "with_family_meta",
+            "json_seq_len",
+            "annotate_overlap",
+            "group_by",
+            "with_etag",
+            "head",
+        ]))]
+        format: Option<String>,
+        /// Name chromosomes by their sequence id rather than their accession
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(requires("format"))]
+        resolve_names: bool,
+        /// Assume the data is never changed in place and skip checking its files against the index, see $TE_IDX_ASSUME_IMMUTABLE
+        #[arg(long, verbatim_doc_comment)]
+        fast_path: bool,
+        /// Read every file of the data type when its index is missing or unreadable, slow on
+        /// large assemblies, see $TE_IDX_ALLOW_SCAN
+        #[arg(long, verbatim_doc_comment)]
+        allow_scan: bool,
+        /// Flag to keep sequence accessions in the output of an assembly that redacts them, for internal tooling
+        #[arg(long, verbatim_doc_comment)]
+        ignore_redaction: bool,
+        /// Add the "etag" of the response, changed only by a rebuilt index or a different query
+        #[arg(long, verbatim_doc_comment)]
+        with_etag: bool,
+        /// Only return {"etag": ..., "total": n}, the number of hits the query would return
+        #[arg(long, verbatim_doc_comment)]
+        head: bool,
+    },
+    /// Print every record of a contig, or of the assembly, in ascending start order
+    Dump {
+        /// Type of data to be dumped
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// chromosome number/accession
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(required_unless_present("all"))]
+        chrom: Option<String>,
+        /// Dump every contig, in the assembly's contig order, see contig-order
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(conflicts_with("chrom"))]
+        all: bool,
         /// Optional: Only return hits matching accession
         #[arg(short, long, verbatim_doc_comment)]
         family: Option<String>,
         /// Only return NRPH hits
         #[arg(short, long, verbatim_doc_comment)]
         nrph: bool,
+        /// Print one JSON object per line, as idx-query formats hits, instead of BED lines
+        #[arg(long, verbatim_doc_comment)]
+        ndjson: bool,
+    },
+    /// Print the ranges stored in each tile of an index as TSV, for comparing against other indexes
+    DumpIdx {
+        /// Type of data whose index is dumped
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Optional: Only dump this contig
+        #[arg(short, long, verbatim_doc_comment)]
+        contig: Option<String>,
+        /// Optional: Only dump this tile, 0-based, of each contig
+        #[arg(short, long, verbatim_doc_comment)]
+        tile: Option<usize>,
+        /// Print only the number of ranges in each tile
+        #[arg(long, verbatim_doc_comment)]
+        summary: bool,
     },
     /// Retrieve information from a processed JSON file
     JsonQuery {
@@ -108,10 +517,37 @@ pub enum Commands {
         data_type: String,
         /// Key value to search by
         #[arg(short, long, verbatim_doc_comment)]
-        key: String,
+        #[clap(required_unless_present_any(["keys_file", "all"]))]
+        key: Option<String>,
         /// Target Attribute to return
         #[arg(short, long, verbatim_doc_comment)]
         target: Option<String>,
+        /// Optional: File of keys, one per line, answered together as JSON
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(conflicts_with_all(["key", "all"]))]
+        keys_file: Option<String>,
+        /// Return the target value of every key as JSON
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(conflicts_with("key"))]
+        all: bool,
+    },
+    /// Look up a sequence accession by accession or id, printing -1 if not found
+    GetChromId {
+        /// Sequence accession, id, or with --fuzzy part of one
+        #[arg(short, long, verbatim_doc_comment)]
+        query: String,
+        /// List every sequence whose accession or id starts with or contains the query
+        /// as JSON (accession, id, length), prefix matches first
+        #[arg(long, verbatim_doc_comment)]
+        fuzzy: bool,
+        /// Maximum number of --fuzzy matches to list
+        #[arg(long, verbatim_doc_comment, default_value_t = 50)]
+        #[clap(requires("fuzzy"))]
+        limit: usize,
+        /// Report to stderr how the query was resolved: accession, dfamseq or id
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(conflicts_with("fuzzy"))]
+        verbose: bool,
     },
     /// Read all or NRPH only family annotations for an assembly
     ReadFamilyAssemblyAnnotations {
@@ -124,6 +560,73 @@ pub enum Commands {
         /// Optional: Output file
         #[arg(long, short)]
         outfile: Option<String>,
+        /// Flag to prefix the output with the export's source metadata as ## lines
+        #[arg(long, verbatim_doc_comment)]
+        source_info: bool,
+        /// Write plain gzip, as historical Dfam downloads are, instead of BGZF.
+        /// The outfile should then end in .gz rather than .bgz
+        #[arg(long, verbatim_doc_comment)]
+        gzip_plain: bool,
+    },
+    /// Write the genomic sequence of every hit of a family as FASTA, from the assembly's genome/<assembly>.fa
+    FamilyFasta {
+        /// Family Accession
+        #[arg(short, long, verbatim_doc_comment)]
+        id: String,
+        /// Only Export NRPH hits
+        #[arg(short, long, verbatim_doc_comment)]
+        nrph: bool,
+        /// Bases added on both sides of each hit, clamped to the contig ends
+        #[arg(long, default_value_t = 0, verbatim_doc_comment)]
+        flank_bp: u64,
+        /// Output file, bgzf compressed if it ends in .gz or .bgz
+        #[arg(long, short)]
+        outfile: String,
+    },
+    /// Print the comment lines captured from the export TSV of a data type
+    SourceInfo {
+        /// Type of data to describe
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+    },
+    /// Print the record at a bgzf virtual position as JSON, followed by the raw line
+    PeekRecord {
+        /// Type of data the file holds
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Path to the .bed.bgz file, or its name within the assembly's data type folder
+        #[arg(short, long, verbatim_doc_comment)]
+        bgz_file: String,
+        /// Virtual position of the record, as stored in the index
+        #[arg(short, long, verbatim_doc_comment)]
+        virtual_position: u64,
+    },
+    /// List the prepared assemblies in the data folder, those in allowlist.json if it exists
+    ListAssemblies {
+        /// Also list assemblies left out of the allowlist
+        #[arg(long, verbatim_doc_comment)]
+        include_hidden: bool,
+    },
+    /// List the families with a BED file of a data type, sequences for masks, sorted by accession
+    ListFamilies {
+        /// Type of data to list
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Only list accessions starting with this
+        #[arg(long, verbatim_doc_comment)]
+        prefix: Option<String>,
+        /// List at most this many
+        #[arg(long, verbatim_doc_comment)]
+        limit: Option<usize>,
+        /// Skip this many of the matching accessions first
+        #[arg(long, verbatim_doc_comment, default_value_t = 0)]
+        offset: usize,
+        /// Print the page as JSON, with the total matching and the records, bytes and bed_idx of each
+        #[arg(long, verbatim_doc_comment)]
+        json: bool,
     },
     /// Display information about the given assembly
     MetaData {
@@ -143,28 +646,594 @@ pub enum Commands {
         #[arg(long, short)]
         chromosomes: bool,
     },
+    /// Bundle prepared data of an assembly into a .tar.gz with a checksum MANIFEST
+    Package {
+        /// Types of data to include, repeatable. Defaults to every type present
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(DATA_ELEMENTS))]
+        data_type: Vec<String>,
+        /// Path of the .tar.gz to create
+        #[arg(short, long, verbatim_doc_comment)]
+        outfile: String,
+        /// Include the <data_type>_idx.dat index files
+        #[arg(short, long, verbatim_doc_comment)]
+        include_indexes: bool,
+        /// Flag to write <outfile>.manifest.json recording the version, parameters and archive checksum
+        #[arg(long, verbatim_doc_comment)]
+        manifest: bool,
+    },
+    /// Extract a package into the data directory, validating the MANIFEST checksums
+    Unpack {
+        /// Path of the .tar.gz created by package
+        #[arg(short, long, verbatim_doc_comment)]
+        infile: String,
+    },
+    /// Write the files of the assembly that differ from a previous release into a delta folder
+    ReleaseDelta {
+        /// Data directory of the previous release, holding the assembly folder
+        #[arg(long, verbatim_doc_comment)]
+        old_dir: String,
+        /// Folder to write the delta to, empty or absent
+        #[arg(short, long, verbatim_doc_comment)]
+        outdir: String,
+    },
+    /// Bring the assembly, a copy of the previous release, up to date with a release-delta folder
+    ApplyDelta {
+        /// Folder written by release-delta
+        #[arg(long, verbatim_doc_comment)]
+        delta_dir: String,
+    },
+    /// Compare the benchmark annotations of a family with its assembly annotations
+    BenchmarkReport {
+        /// Family accession
+        #[arg(short, long, verbatim_doc_comment)]
+        fam: String,
+        /// Minimum overlap, as a fraction of both hit lengths, for two hits to match
+        #[arg(short, long, verbatim_doc_comment, default_value_t = DEFAULT_MIN_OVERLAP)]
+        min_overlap: f64,
+    },
+    /// Print the assembly and benchmark hits of a family in one position sorted list, tagged by source
+    FamilyCombined {
+        /// Family accession
+        #[arg(short, long, verbatim_doc_comment)]
+        fam: String,
+        /// Only keep NRPH assembly hits, benchmark hits are all kept
+        #[arg(short, long, verbatim_doc_comment)]
+        nrph: bool,
+        /// Print download format TSV, with source, NRPH and divergence columns, instead of JSON
+        #[arg(long, short, verbatim_doc_comment)]
+        web_fmt: bool,
+    },
+    /// Check that families have a file of a data type, listing those without one and exiting 1
+    ExpectFamilies {
+        /// Type of data to check
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Family accessions, repeated or comma separated
+        #[arg(
+            short,
+            long,
+            verbatim_doc_comment,
+            value_delimiter = ',',
+            required(true)
+        )]
+        fam: Vec<String>,
+        /// Also count a family whose file holds no records as missing
+        #[arg(long, verbatim_doc_comment)]
+        empty_as_missing: bool,
+    },
+    /// Join fragmented hits of a family into putative full-length insertions
+    Defragment {
+        /// Family accession
+        #[arg(short, long, verbatim_doc_comment)]
+        fam: String,
+        /// Largest genomic gap between two joined fragments
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_MAX_GAP_BP)]
+        max_gap_bp: u64,
+        /// Model positions a fragment may start before the previous one ends
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_MODEL_TOLERANCE)]
+        model_tolerance: u64,
+        /// Print one TSV line per insertion instead of the JSON report
+        #[arg(long, verbatim_doc_comment)]
+        tsv: bool,
+    },
+    /// Rank the family pairs whose annotations lie within a window of each other most often
+    Cooccurrence {
+        /// Largest gap between two hits that co-occur
+        #[arg(short, long, verbatim_doc_comment, default_value_t = DEFAULT_WINDOW_BP)]
+        window_bp: u64,
+        /// Optional: Comma separated family accessions to count, every family by default
+        #[arg(short, long, verbatim_doc_comment, value_delimiter = ',')]
+        families: Vec<String>,
+        /// Only count NRPH hits
+        #[arg(short, long, verbatim_doc_comment)]
+        nrph: bool,
+        /// Number of pairs to report
+        #[arg(short, long, verbatim_doc_comment, default_value_t = DEFAULT_TOP_N)]
+        top_n: usize,
+        /// Optional: Drop the rarest pairs whenever more distinct pairs than this are counted
+        #[arg(long, verbatim_doc_comment)]
+        max_pairs: Option<usize>,
+        /// Print one TSV line per pair instead of the JSON report
+        #[arg(long, verbatim_doc_comment)]
+        tsv: bool,
+    },
+    /// Find family pairs annotating nearly the same intervals, candidates for redundant models
+    RedundancyScan {
+        /// Fraction of each hit two hits of a pair must share
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_MIN_RECIPROCAL_OVERLAP)]
+        min_reciprocal_overlap: f64,
+        /// Times two families must pair to be reported
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_MIN_PAIR_COUNT)]
+        min_pair_count: u64,
+        /// Print one TSV line per pair instead of the JSON report
+        #[arg(long, verbatim_doc_comment)]
+        tsv: bool,
+    },
+    /// Rewrite a data type into a new assembly with the contig names mapped to another naming scheme
+    RemapNames {
+        /// Type of data to remap
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Two column TSV of current and new contig names.
+        /// If absent, the "id" fields of the sequences JSON are used
+        #[arg(short, long, verbatim_doc_comment)]
+        mapping: Option<String>,
+        /// Name of the assembly folder to write the remapped data to
+        #[arg(short, long, verbatim_doc_comment)]
+        out_assembly: String,
+        /// Leave out records on contigs without a mapping instead of failing
+        #[arg(long, verbatim_doc_comment)]
+        drop_unmapped: bool,
+    },
+    /// Rewrite BED files fragmented by appends sorted and in fresh bgzf blocks, and reindex
+    Compact {
+        /// Type of data to compact
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// File to compact: family accession, or sequence accession for masks.
+        /// --fam is accepted as well
+        #[arg(long, short = 'f', verbatim_doc_comment, alias = "fam")]
+        #[clap(required_unless_present = "all", conflicts_with = "all")]
+        key: Option<String>,
+        /// Compact every file of the data type
+        #[arg(long, verbatim_doc_comment)]
+        all: bool,
+    },
+    /// Rewrite the BED files of a data type still in an older column layout into the current one, and reindex
+    RepairLayout {
+        /// Type of data to repair
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+    },
+    /// Print a uniform random sample of the records of a data type, drawn through its index
+    Sample {
+        /// Type of data to sample
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Number of records to draw, every record when the data type has fewer
+        #[arg(short, long, verbatim_doc_comment, default_value_t = DEFAULT_SAMPLE_SIZE)]
+        count: usize,
+        /// Seed of the draw, the same seed draws the same records from the same index
+        #[arg(long, verbatim_doc_comment, default_value_t = 0)]
+        seed: u64,
+        /// Run basic checks on the records drawn, returning {"records": [...], "validation": {...}}
+        #[arg(long, verbatim_doc_comment)]
+        validate: bool,
+    },
+    /// Compare the seq_len column of a data type with the lengths of the assembly's sequences
+    /// JSON, printing the mismatches per contig as JSON. Exits non-zero on mismatches unless repaired
+    CheckSeqLens {
+        /// Type of data to check
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES), required(true))]
+        data_type: String,
+        /// Optional: Check a sample of this many records, drawn as sample draws them, rather than every record
+        #[arg(long, verbatim_doc_comment)]
+        #[clap(conflicts_with("repair"))]
+        sample: Option<usize>,
+        /// Seed of the sample
+        #[arg(long, verbatim_doc_comment, default_value_t = 0)]
+        #[clap(requires("sample"))]
+        seed: u64,
+        /// Mismatched records reported for each contig
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_SEQ_LEN_EXAMPLES)]
+        examples: usize,
+        /// Rewrite mismatched seq_len columns with the JSON lengths, and reindex
+        #[arg(long, verbatim_doc_comment)]
+        repair: bool,
+    },
+    /// Print the order the contigs of the assembly come in wherever an output covers several,
+    /// karyotype order unless the assembly folder has a contig_order.txt, as JSON
+    ContigOrder,
+    /// Bring a prepared assembly to a newer file layout. Run again to resume an interrupted migration
+    Migrate {
+        /// Optional: Layout version the assembly is expected to be at, checked before migrating
+        #[arg(long, verbatim_doc_comment)]
+        from_version: Option<u32>,
+        /// Optional: Layout version to migrate to, defaults to the newest
+        #[arg(long, verbatim_doc_comment)]
+        to_version: Option<u32>,
+    },
+    /// Print the version, supported index and layout versions and compiled features as JSON,
+    /// with a compatibility verdict for every assembly of the data folder when it exists
+    BuildInfo,
+    /// Copy the query-critical files of an assembly from the first cold data root holding it
+    /// into the hot root, the first --data-root, checksumming every file. Safe while queries run
+    WarmAssembly {
+        /// Indexed data types to copy, repeatable. Defaults to every one the cold root has
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES))]
+        data_type: Vec<String>,
+    },
+    /// Report which data roots hold each data element of an assembly, the copy queries read
+    /// and whether the copies agree
+    CacheStatus,
+    /// Check an export against the <output>.manifest.json written with --manifest, printing the
+    /// files that differ as JSON. Exits non-zero unless the output and every input match.
+    /// Needs no --assembly
+    VerifyManifest {
+        /// Path of the manifest
+        #[arg(verbatim_doc_comment)]
+        manifest: String,
+    },
+    /// Serve read-only JSON routes over HTTP for browsers: /assemblies,
+    /// /{assembly}/annotations, /{assembly}/families/{acc}/summary and
+    /// /{assembly}/sequences/{query}. Needs no --assembly
+    #[cfg(feature = "http")]
+    Serve {
+        /// Address and port to listen on
+        #[arg(long, verbatim_doc_comment, default_value = DEFAULT_BIND)]
+        bind: String,
+    },
+    /// Remove index files superseded by a rebuild, never the current one of a data type
+    CleanIndexes {
+        /// Optional: Data type to clean, every indexed data type by default
+        #[arg(short, long, verbatim_doc_comment)]
+        #[clap(value_parser = PossibleValuesParser::new(INDEX_DATA_TYPES))]
+        data_type: Option<String>,
+        /// Only remove files last modified at least this many minutes ago, queries may still read them
+        #[arg(long, verbatim_doc_comment, default_value_t = DEFAULT_INDEX_RETENTION_MINS)]
+        retention_mins: u64,
+    },
+    /// Report the disk space, file count and index range count of each data type of an assembly
+    Usage {
+        /// Report every prepared assembly of the data folder, with totals. Needs no --assembly
+        #[arg(long, verbatim_doc_comment)]
+        all: bool,
+        /// Print one TSV line per element instead of the JSON report
+        #[arg(long, verbatim_doc_comment)]
+        tsv: bool,
+    },
+    /// Build and query a small synthetic assembly end-to-end, printing PASS/FAIL per check.
+    /// The assembly name is used for the synthetic assembly, data and export paths are not needed
+    SelfTest {
+        /// Keep the generated exports and prepared data instead of removing them
+        #[arg(short, long, verbatim_doc_comment)]
+        keep: bool,
+    },
     /// Outputs all annotations in assembly in filter format. Essentailly the source TSV
     AllAnnotations {
         /// Path to file to save filtered data. Should end in .bed.bgz
         #[arg(long, short, verbatim_doc_comment)]
         outfile: Option<String>,
+        /// Column set to write: public leaves out cigar and caf, internal keeps every column
+        #[arg(long, verbatim_doc_comment, default_value = PUBLIC_PROFILE)]
+        #[clap(value_parser = PossibleValuesParser::new(COLUMN_PROFILES))]
+        profile: String,
+        /// Optional: Comma separated column names to leave out, on top of the profile
+        #[arg(long, verbatim_doc_comment, value_delimiter = ',')]
+        exclude_columns: Vec<String>,
+        /// Write plain gzip, as historical Dfam downloads are, instead of BGZF.
+        /// The outfile should then end in .gz rather than .bgz
+        #[arg(long, verbatim_doc_comment)]
+        gzip_plain: bool,
     },
 }
 
+// The data element a command reads, from the first data root holding it. None for commands
+// that write, or read more than one assembly, which use --data-dir as given.
+fn query_element(command: &Option<Commands>) -> Option<Option<&str>> {
+    match command.as_ref()? {
+        Commands::BgzfFilter { data_type, .. }
+        | Commands::IdxQuery { data_type, .. }
+        | Commands::Dump { data_type, .. }
+        | Commands::DumpIdx { data_type, .. }
+        | Commands::JsonQuery { data_type, .. }
+        | Commands::SourceInfo { data_type }
+        | Commands::PeekRecord { data_type, .. }
+        | Commands::ListFamilies { data_type, .. }
+        | Commands::ExpectFamilies { data_type, .. }
+        | Commands::Sample { data_type, .. } => Some(Some(data_type.as_str())),
+        Commands::GetChromId { .. } => Some(Some(SEQUENCE_DIR)),
+        Commands::ReadFamilyAssemblyAnnotations { .. }
+        | Commands::FamilyFasta { .. }
+        | Commands::FamilyCombined { .. }
+        | Commands::BenchmarkReport { .. }
+        | Commands::Defragment { .. }
+        | Commands::Cooccurrence { .. }
+        | Commands::RedundancyScan { .. }
+        | Commands::AllAnnotations { .. } => Some(Some(ASSEMBLY_DIR)),
+        Commands::MetaData { .. } => Some(None),
+        _ => None,
+    }
+}
+
+// Coordinates as 1-based positive integers, so "-5" or "0" get a message rather than a
+// u64 parse error
+fn parse_coordinate(value: &str) -> Result<u64, String> {
+    match value.trim().parse::<i128>() {
+        Ok(n) if n < 1 => Err(format!(
+            "Coordinates Are 1-Based Positive Integers, Got {}",
+            n
+        )),
+        Ok(n) => u64::try_from(n).map_err(|_| format!("Coordinate {} Is Too Large", n)),
+        Err(_) => Err(format!("\"{}\" Is Not A Whole Number", value)),
+    }
+}
+
+// Output compression from --gzip-plain, exiting on an outfile named for the other one
+fn output_compression(outfile: &Option<String>, gzip_plain: bool) -> OutputCompression {
+    match OutputCompression::for_outfile(outfile, gzip_plain) {
+        Ok(compression) => compression,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+}
+
+// Filter output for a family file, BGZF compressed with as many writer workers as its size
+// gets reader workers, or plain gzip
+fn filtered_output(
+    outfile: &Option<String>,
+    gzip_plain: bool,
+    max_buffered_bytes: u64,
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    data_directory: &str,
+) -> Box<dyn Write> {
+    let compression = output_compression(outfile, gzip_plain);
+    let key_file = bgz_path(
+        format!("{}/{}/{}", data_directory, assembly, data_type),
+        key,
+    );
+    let size = std::fs::metadata(key_file).map_or(0, |m| m.len());
+    filter_writer(
+        outfile,
+        filter_worker_count(size),
+        compression,
+        max_buffered_bytes,
+    )
+    .expect("Could Not Open Output File")
+}
+
+// How messages and progress are written to stderr. Standard output is data, listings, JSON
+// and TSV, and is never styled. Color and progress redrawn in place with carriage returns are
+// only for a terminal, and never with NO_COLOR set, TERM=dumb or --plain; otherwise progress
+// is a plain line every PLAIN_PROGRESS_SECS, which log aggregators take as it is.
+#[derive(Clone, Copy)]
+struct OutputStyle {
+    color: bool,
+    live: bool,
+}
+
+const PLAIN_PROGRESS_SECS: u64 = 10;
+
+impl OutputStyle {
+    fn detect(plain: bool) -> OutputStyle {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let dumb = std::env::var("TERM").is_ok_and(|term| term == "dumb");
+        let terminal = !plain && !dumb && stderr().is_terminal();
+        OutputStyle {
+            color: terminal && !no_color,
+            live: terminal,
+        }
+    }
+
+    // For clap's help and errors, which otherwise decide color for themselves
+    fn color_choice(&self) -> ColorChoice {
+        match self.color {
+            true => ColorChoice::Auto,
+            false => ColorChoice::Never,
+        }
+    }
+
+    fn paint(&self, ansi: &str, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{}\x1b[0m", ansi, text),
+            false => text.to_string(),
+        }
+    }
+
+    fn warning(&self, message: &str) {
+        eprintln!("{} {}", self.paint("33", "Warning:"), message);
+    }
+
+    fn error(&self, message: &str) {
+        eprintln!("{}", self.paint("31", message));
+    }
+}
+
+// Progress of a long command on stderr, counting up to total
+struct Progress {
+    label: String,
+    total: u64,
+    style: OutputStyle,
+    last_line: Mutex<Instant>,
+}
+
+impl Progress {
+    fn new(label: &str, total: u64, style: OutputStyle) -> Progress {
+        Progress {
+            label: label.to_string(),
+            total,
+            style,
+            last_line: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn update(&self, done: u64) {
+        let line = format!("{} {}/{}", self.label, done, self.total);
+        if self.style.live {
+            eprint!("\r{}", line);
+            return;
+        }
+        let mut last_line = self.last_line.lock().unwrap();
+        if last_line.elapsed() >= Duration::from_secs(PLAIN_PROGRESS_SECS) || done == self.total {
+            eprintln!("{}", line);
+            *last_line = Instant::now();
+        }
+    }
+
+    // Ends a redrawn line, plain lines already end with the last update
+    fn finish(&self) {
+        if self.style.live {
+            eprintln!();
+        }
+    }
+}
+
 fn main() {
-    let cli = Cli::parse();
+    // Read before parsing, so clap's own help and errors are plain too
+    let style = OutputStyle::detect(std::env::args_os().any(|arg| arg == "--plain"));
+    let command = Cli::command().color(style.color_choice());
+    let cli = Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
 
     let data_directory = match cli.data_dir {
         Some(dir) => dir,
         None => DATA_DIR.to_string(),
     };
 
+    let data_roots = configured_data_roots(&cli.data_root, &data_directory);
+
     let export_directory = match cli.exp_dir {
         Some(dir) => dir,
         None => EXPORT_DIR.to_string(),
     };
 
     let assembly = cli.assembly;
+    let needs_assembly = !matches!(
+        cli.command,
+        Some(Commands::ListAssemblies { .. })
+            | Some(Commands::BuildInfo)
+            | Some(Commands::VerifyManifest { .. })
+            | Some(Commands::Usage { all: true, .. })
+    );
+    #[cfg(feature = "http")]
+    let needs_assembly = needs_assembly && !matches!(cli.command, Some(Commands::Serve { .. }));
+    if assembly.is_empty() && needs_assembly {
+        Cli::command()
+            .color(style.color_choice())
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--assembly <ASSEMBLY> Is Required",
+            )
+            .exit()
+    }
+    // Queries read from the first data root holding what they query, see roots::resolve_root
+    let data_directory = match query_element(&cli.command) {
+        Some(element) => resolve_root(&data_roots, &assembly, element)
+            .cloned()
+            .unwrap_or(data_directory),
+        None => data_directory,
+    };
+    let scratch_directory = cli.scratch_dir;
+    let max_buffered_bytes = configured_max_buffered_bytes(cli.max_buffered_mb);
+    let query_log = QueryLogger::from_config(&cli.query_log, cli.query_log_max_mb);
+
+    // Commands writing large temporary files check their scratch space before starting
+    let scratch_check = match &cli.command {
+        Some(Commands::SelfTest { .. }) => configured_scratch_dir(&scratch_directory),
+        Some(Commands::PrepBeds { .. }) | Some(Commands::PrepareAssembly { .. }) => {
+            Some(scratch_dir(&scratch_directory, &data_directory))
+        }
+        _ => None,
+    };
+    if let Some(dir) = scratch_check {
+        if let Err(e) = check_scratch_dir(&dir, cli.min_scratch_free_mb) {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+
+    // The self-test brings its own data, so it runs before the data/export paths are checked
+    if let Some(Commands::SelfTest { keep }) = &cli.command {
+        match self_test(&assembly, *keep, &scratch_directory) {
+            Ok(true) => std::process::exit(0),
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Self-Test Could Not Run - {}", e);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if let Some(Commands::BuildInfo) = &cli.command {
+        let scanned = Some(data_directory.clone()).filter(|dir| Path::new(dir).is_dir());
+        match build_info(&scanned) {
+            Ok(info) => println!(
+                "{}",
+                serde_json::to_string_pretty(&info).expect("Cannot Serialize")
+            ),
+            Err(e) => {
+                eprintln!("Could Not Check Compatibility - {}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    // Manifests name their files by full path, they are checked wherever the data folder is
+    if let Some(Commands::VerifyManifest { manifest }) = &cli.command {
+        match verify_manifest(manifest) {
+            Ok(verification) => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&verification).expect("Cannot Serialize")
+                );
+                if !verification.verified {
+                    std::process::exit(1)
+                }
+            }
+            Err(e) => {
+                style.error(&format!("Could Not Verify Manifest - {}", e));
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    // Across the data roots rather than in the data folder
+    match &cli.command {
+        Some(Commands::WarmAssembly { data_type }) => {
+            match warm_assembly(&assembly, data_type, &data_roots) {
+                Ok(report) => println!("{}", serde_json::to_string(&report).unwrap()),
+                Err(e) => {
+                    style.error(&format!("Could Not Warm {} - {}", assembly, e));
+                    std::process::exit(1)
+                }
+            }
+            return;
+        }
+        Some(Commands::CacheStatus) => {
+            match cache_status(&assembly, &data_roots) {
+                Ok(status) => println!("{}", serde_json::to_string(&status).unwrap()),
+                Err(e) => {
+                    style.error(&format!("Cache Status Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
 
     if !Path::new(&data_directory).exists() {
         panic!(
@@ -173,86 +1242,623 @@ fn main() {
         );
     };
 
-    if !Path::new(&export_directory).exists() {
-        panic!("Export Directory \"{}\" Does Not Exist. An export path must be supplied if not run on dfam", &export_directory);
+    // Long operations report their progress to the status file, if one is asked for
+    let operation = match &cli.command {
+        Some(Commands::PrepareAssembly { .. }) => Some("prepare-assembly"),
+        Some(Commands::PrepBeds { .. }) => Some("prep-beds"),
+        Some(Commands::BuildIdx { .. }) => Some("build-idx"),
+        Some(Commands::RegenerateExport { .. }) => Some("regenerate-export"),
+        Some(Commands::Compact { .. }) => Some("compact"),
+        _ => None,
     };
+    let status = match (&cli.status_file, operation) {
+        (Some(path), Some(operation)) => match StatusFile::start(path, operation) {
+            Ok(status) => Some(status),
+            Err(e) => {
+                eprintln!("Could Not Write Status File {} - {}", path, e);
+                std::process::exit(1)
+            }
+        },
+        _ => None,
+    };
+
+    #[cfg(feature = "http")]
+    if let Some(Commands::Serve { bind }) = &cli.command {
+        let listener = match std::net::TcpListener::bind(bind) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Could Not Listen On {} - {}", bind, e);
+                std::process::exit(1)
+            }
+        };
+        eprintln!("Serving {} On http://{}", data_directory, bind);
+        if let Err(e) = serve(listener, &data_directory, &query_log) {
+            style.error(&format!("Server Failed - {}", e));
+            std::process::exit(1)
+        }
+        return;
+    }
 
     match &cli.command {
         Some(Commands::BgzfFilter {
             data_type,
-            fam,
+            key,
             position,
             term,
+            exact_version,
+            strand,
+            max_bias,
+            strict_bias,
             outfile,
             web_fmt,
+            profile,
+            exclude_columns,
+            source_info,
+            no_prefilter,
+            with_family_meta,
+            format,
+            resolve_names,
+            gzip_plain,
+            ignore_redaction,
+            manifest,
         }) => {
-            bgzf_filter(
+            let mut writer = filtered_output(
+                outfile,
+                *gzip_plain,
+                max_buffered_bytes,
                 &assembly,
                 data_type,
-                fam,
-                position,
-                term,
-                outfile,
-                *web_fmt,
+                key,
                 &data_directory,
-            )
-            .expect("Filter Failed");
+            );
+            let options = FilterOptions {
+                position: *position,
+                term: term.clone(),
+                exact_version: *exact_version,
+                strand: strand.clone(),
+                max_bias: *max_bias,
+                strict_bias: *strict_bias,
+                dl_fmt: *web_fmt,
+                profile: profile.clone(),
+                exclude_columns: exclude_columns.clone(),
+                source_meta: *source_info,
+                with_family_meta: *with_family_meta,
+                prefilter: !*no_prefilter,
+                bed_format: format.as_deref().and_then(BedFormat::parse),
+                resolve_names: *resolve_names,
+                ignore_redaction: *ignore_redaction,
+                query_log: query_log.clone(),
+            };
+            let count = bgzf_filter_with(
+                &assembly,
+                data_type,
+                key,
+                &mut writer,
+                &options,
+                &data_directory,
+            );
+            // Bad options and missing data are reported as the query's are
+            let count = match count {
+                Ok(count) => count,
+                Err(e)
+                    if e.kind() == ErrorKind::InvalidInput
+                        || e.kind() == ErrorKind::NotFound
+                        || e.kind() == ErrorKind::PermissionDenied
+                        || e.kind() == ErrorKind::Unsupported =>
+                {
+                    eprintln!("{}", e);
+                    std::process::exit(1)
+                }
+                Err(e) => panic!("Filter Failed - {:?}", e),
+            };
+            // The output is only complete once its writer is finished
+            drop(writer);
+            if let (true, Some(outfile)) = (*manifest, outfile) {
+                write_manifest(
+                    outfile,
+                    "bgzf-filter",
+                    &assembly,
+                    filter_parameters(data_type, key, &options),
+                    &export_inputs(&assembly, data_type, key, &data_directory),
+                    Some(count as u64),
+                )
+                .expect("Could Not Write Manifest");
+            }
         }
-        Some(Commands::BuildIdx { data_type }) => {
+        Some(Commands::BuildIdx {
+            data_type,
+            tile_size,
+            overflow_tiles,
+            max_duplication,
+            verify_positions,
+            deterministic,
+        }) => {
             let (filenames, bgz_dir, mut contig_index, index_file) =
-                match idx::prep_idx(&format!("{}/{}", &data_directory, &assembly), data_type) {
+                match idx::prep_idx(&assembly, data_type, false, &data_directory) {
                     Ok(res) => res,
                     Err(e) => panic!(
                         "Search Prep Failed, Assembly or Data Type May Not Exist - {:?}",
                         e
                     ),
                 };
-            idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
-                .expect("Indexing Failed")
+            contig_index.set_tile_size(*tile_size);
+            if let Some(overflow_tiles) = overflow_tiles {
+                contig_index.set_overflow_tiles(*overflow_tiles);
+            }
+            contig_index.set_max_duplication(*max_duplication);
+            contig_index.set_verify_positions(*verify_positions);
+            contig_index.set_deterministic(*deterministic);
+            if let Some(status) = &status {
+                status.phase(data_type, Some(filenames.len() as u64), "files");
+            }
+            let progress = Arc::new(Progress::new(
+                "Indexing Files",
+                filenames.len() as u64,
+                style,
+            ));
+            let (build_status, build_progress) = (status.clone(), progress.clone());
+            contig_index.set_build_progress(idx::BuildProgress(Arc::new(move |done| {
+                if let Some(status) = &build_status {
+                    status.progress(done);
+                }
+                build_progress.update(done);
+            })));
+            let result = idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file);
+            progress.finish();
+            if let Some(status) = &status {
+                status.finish(&result);
+            }
+            result.expect("Indexing Failed");
+            let stats = contig_index.build_stats();
+            println!(
+                "Indexed {} Ranges, Mean Span {:.2} Tiles, Max Span {} Tiles, Duplication {:.2}, {} In Overflow Lists",
+                stats.ranges,
+                stats.mean_span_tiles(),
+                stats.max_span_tiles,
+                stats.duplication_factor(),
+                stats.overflow_ranges
+            );
+            if let Some(warning) = contig_index.duplication_warning() {
+                style.warning(&warning);
+            }
+            let empty = contig_index.empty_files();
+            if !empty.is_empty() {
+                style.warning(&format!(
+                    "{} Files Have No Records, Indexed Without Ranges: {}",
+                    empty.len(),
+                    empty.join(", ")
+                ));
+            }
+            if *verify_positions {
+                println!(
+                    "Verified {} Record Positions",
+                    contig_index.verified_records()
+                );
+            }
         }
-        Some(Commands::PrepBeds { in_tsv, data_type }) => {
-            match prep_beds(&assembly, in_tsv, data_type, &data_directory) {
-                Ok(()) => println!("Bed Files Created - {}", data_type),
+        Some(Commands::PrepBeds {
+            in_tsv,
+            data_type,
+            dedupe,
+            shard_threshold,
+            sharded,
+            no_sort,
+            sort_buffer_mb,
+        }) => {
+            let result = prep_beds_with(
+                &assembly,
+                in_tsv,
+                data_type,
+                &PrepOptions {
+                    dedupe: *dedupe,
+                    shard_threshold: if *sharded { 0 } else { *shard_threshold },
+                    scratch_directory: scratch_directory.clone(),
+                    max_buffered_bytes,
+                    sort: !no_sort,
+                    sort_buffer_bytes: (*sort_buffer_mb).max(1) as usize * 1024 * 1024,
+                    status: status.clone(),
+                    sequences_file: None,
+                },
+                &data_directory,
+            );
+            if let Some(status) = &status {
+                status.finish(&result);
+            }
+            match result {
+                Ok(_) => println!("Bed Files Created - {}", data_type),
                 Err(e) => panic!("{:?}", e),
             }
         }
+        Some(Commands::RegenerateExport {
+            data_type,
+            outfile,
+            compress,
+        }) => {
+            if let Some(status) = &status {
+                status.phase(data_type, None, "");
+            }
+            let result =
+                regenerate_export(&assembly, data_type, outfile, *compress, &data_directory);
+            if let Some(status) = &status {
+                status.finish(&result);
+            }
+            let count = result.expect("Regenerating Export Failed");
+            println!("Wrote {} Records To {}", count, outfile);
+        }
+        Some(Commands::AppendRecords {
+            in_tsv,
+            data_type,
+            key,
+            rebuild_idx,
+        }) => match append_records(
+            &assembly,
+            data_type,
+            key,
+            in_tsv,
+            *rebuild_idx,
+            &data_directory,
+        ) {
+            Ok(count) => {
+                println!("Appended {} Records To {}", count, key);
+                if !rebuild_idx {
+                    println!("Run build-idx -d {} To Index Them", data_type);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        },
         Some(Commands::IdxQuery {
             data_type,
             chrom,
             start,
             end,
             family,
+            exact_version,
             nrph,
+            strand,
+            min_score,
+            max_evalue,
+            max_bias,
+            strict_bias,
+            repeat_class,
+            downsample,
+            timeout_secs,
+            partial,
+            legacy_json,
+            with_warnings,
+            strict,
+            with_model_length,
+            with_family_meta,
+            json_seq_len,
+            max_region,
+            no_max_region,
+            chunk_size,
+            subtract_masks,
+            clip,
+            annotate_overlap,
+            mask_overlap,
+            group_by,
+            counts_only,
+            per_group_limit,
+            format,
+            resolve_names,
+            fast_path,
+            allow_scan,
+            ignore_redaction,
+            with_etag,
+            head,
         }) => {
-            let result = idx_query(
+            let max_region = if *no_max_region {
+                None
+            } else {
+                Some(*max_region)
+            };
+            // Reported like clap's own errors, before any files are opened
+            if let Err(e) = idx::check_region(*start, *end, max_region) {
+                let msg = match e {
+                    RegionError::ZeroStart | RegionError::ZeroEnd => e.to_string(),
+                    RegionError::StartNotBeforeEnd { start, end } => format!(
+                        "--start {} Must Be Less Than --end {}",
+                        start, end
+                    ),
+                    RegionError::TooLong { length, max_region } => format!(
+                        "Region Of {} bp Is Longer Than --max-region {}, Split The Query, Raise --max-region Or Pass --no-max-region",
+                        length, max_region
+                    ),
+                };
+                Cli::command()
+                    .error(clap::error::ErrorKind::ValueValidation, msg)
+                    .exit()
+            }
+            let timeout = timeout_secs.map(|secs| match Duration::try_from_secs_f64(secs) {
+                Ok(timeout) => timeout,
+                Err(_) => panic!("Invalid Timeout {}", secs),
+            });
+            if *subtract_masks && !(*mask_overlap > 0.0 && *mask_overlap <= 1.0) {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::ValueValidation,
+                        format!("--mask-overlap {} Must Be In (0, 1]", mask_overlap),
+                    )
+                    .exit()
+            }
+            let subtraction = match subtract_masks {
+                true => Some(MaskSubtraction {
+                    mode: match (clip, annotate_overlap) {
+                        (true, _) => MaskMode::Clip,
+                        (_, true) => MaskMode::Annotate,
+                        _ => MaskMode::Drop,
+                    },
+                    min_overlap: *mask_overlap,
+                }),
+                false => None,
+            };
+            let grouping = group_by.as_ref().map(|_| ResultGrouping {
+                counts_only: *counts_only,
+                per_group_limit: per_group_limit.map(|limit| limit as usize),
+            });
+            let options = IdxQueryOptions {
+                max_region,
+                chunk_size: Some(*chunk_size),
+                family: Some(family.clone()),
+                exact_version: *exact_version,
+                nrph: *nrph,
+                strand: strand.clone(),
+                min_bit_score: *min_score,
+                max_e_value: *max_evalue,
+                max_bias: *max_bias,
+                strict_bias: *strict_bias,
+                repeat_class: repeat_class.clone(),
+                downsample: downsample.clone(),
+                timeout,
+                partial: *partial,
+                legacy_json: *legacy_json,
+                with_warnings: *with_warnings,
+                strict: *strict,
+                with_model_length: *with_model_length,
+                with_family_meta: *with_family_meta,
+                json_seq_len: *json_seq_len,
+                subtract_masks: subtraction,
+                group_by: grouping,
+                bed_format: format.as_deref().and_then(BedFormat::parse),
+                resolve_names: *resolve_names,
+                fast_path: *fast_path,
+                allow_scan: *allow_scan,
+                ignore_redaction: *ignore_redaction,
+                with_etag: *with_etag,
+                head: *head,
+                query_log: query_log.clone(),
+            };
+            // Written as the hits are formatted, BED lines end in a newline already
+            let mut out = BufWriter::new(stdout().lock());
+            let queried = idx_query_into(
                 &assembly,
                 data_type,
                 chrom,
                 *start,
                 *end,
-                family,
-                nrph,
+                &options,
                 &data_directory,
+                &mut out,
             )
-            .expect("Index Query Failed");
-            println!("{}", result)
+            .and_then(|_| match format {
+                Some(_) => Ok(()),
+                None => writeln!(out),
+            })
+            .and_then(|_| out.flush());
+            match queried {
+                Ok(()) => {}
+                Err(e)
+                    if e.kind() == ErrorKind::TimedOut
+                        || e.kind() == ErrorKind::InvalidInput
+                        || e.kind() == ErrorKind::NotFound
+                        || e.kind() == ErrorKind::PermissionDenied
+                        || e.kind() == ErrorKind::Unsupported =>
+                {
+                    eprintln!("{}", e);
+                    std::process::exit(1)
+                }
+                Err(e) => panic!("Index Query Failed - {:?}", e),
+            }
         }
         Some(Commands::JsonQuery {
             data_type,
             key,
             target,
+            keys_file,
+            all,
         }) => {
-            let ans = json_query(&assembly, data_type, key, target, &data_directory)
+            if let Some(key) = key {
+                let ans = json_query(
+                    &assembly,
+                    data_type,
+                    key,
+                    target,
+                    &query_log,
+                    &data_directory,
+                )
+                .expect("JSON Read Failed");
+                println!("{}", ans)
+            } else {
+                let results = match keys_file {
+                    Some(keys_file) => {
+                        let keys: Vec<String> = read_to_string(keys_file)
+                            .expect("Could Not Read Keys File")
+                            .lines()
+                            .map(|l| l.trim().to_string())
+                            .filter(|l| !l.is_empty())
+                            .collect();
+                        json_query_many(&assembly, data_type, &keys, target, &data_directory)
+                    }
+                    None if *all => json_query_all(&assembly, data_type, target, &data_directory),
+                    None => unreachable!(),
+                }
                 .expect("JSON Read Failed");
-            println!("{}", ans)
+                println!(
+                    "{}",
+                    serde_json::to_string(&results).expect("Error Converting Results to JSON")
+                )
+            }
+        }
+        Some(Commands::ReadFamilyAssemblyAnnotations {
+            id,
+            nrph,
+            outfile,
+            source_info,
+            gzip_plain,
+        }) => {
+            let mut writer = filtered_output(
+                outfile,
+                *gzip_plain,
+                max_buffered_bytes,
+                &assembly,
+                ASSEMBLY_DIR,
+                id,
+                &data_directory,
+            );
+            let _res = read_family_assembly_annotations(
+                id,
+                &assembly,
+                nrph,
+                &mut writer,
+                source_info,
+                &data_directory,
+            );
+        }
+        Some(Commands::FamilyFasta {
+            id,
+            nrph,
+            flank_bp,
+            outfile,
+        }) => match export_family_fasta(&assembly, id, nrph, *flank_bp, outfile, &data_directory) {
+            Ok(count) => println!("Wrote {} Sequences To {}", count, outfile),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        },
+        Some(Commands::Dump {
+            data_type,
+            chrom,
+            all: _,
+            family,
+            nrph,
+            ndjson,
+        }) => {
+            if let Err(e) = dump_records(
+                &assembly,
+                data_type,
+                chrom,
+                family,
+                nrph,
+                ndjson,
+                &data_directory,
+            ) {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
         }
-        Some(Commands::ReadFamilyAssemblyAnnotations { id, nrph, outfile }) => {
-            let _res =
-                read_family_assembly_annotations(id, &assembly, nrph, outfile, &data_directory);
+        Some(Commands::DumpIdx {
+            data_type,
+            contig,
+            tile,
+            summary,
+        }) => {
+            let mut out = BufWriter::new(stdout().lock());
+            let dumped = idx::dump_index(
+                &assembly,
+                data_type,
+                contig,
+                tile,
+                *summary,
+                &mut out,
+                &data_directory,
+            )
+            .and_then(|_| out.flush().map_err(|e| e.into()));
+            if let Err(e) = dumped {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        Some(Commands::SourceInfo { data_type }) => {
+            let lines = source_info(&assembly, data_type, &data_directory)
+                .expect("Source Info Read Failed");
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Some(Commands::PeekRecord {
+            data_type,
+            bgz_file,
+            virtual_position,
+        }) => {
+            // The index only records file names, so accept those as well as paths
+            let bgz_path = if Path::new(bgz_file).exists() {
+                bgz_file.to_string()
+            } else {
+                format!(
+                    "{}/{}/{}/{}",
+                    &data_directory, &assembly, data_type, bgz_file
+                )
+            };
+            match read_at(&bgz_path, *virtual_position, data_type) {
+                Ok(peeked) => {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&peeked).expect("Cannot Serialize")
+                    );
+                    println!("{}", peeked.raw);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1)
+                }
+            }
         }
-        Some(Commands::PrepareAssembly {}) => {
-            prepare_assembly(&assembly, &data_directory, &export_directory)
-                .expect(format!("Assembly Prep for {} Failed", &assembly).as_str())
+        Some(Commands::PrepareAssembly {
+            consolidate_json,
+            hmm,
+            deterministic,
+            dry_run,
+        }) => {
+            // Only preparing reads exports, other commands work from the data directory alone
+            if !Path::new(&export_directory).exists() {
+                panic!("Export Directory \"{}\" Does Not Exist. An export path must be supplied if not run on dfam", &export_directory);
+            };
+            if *dry_run {
+                let result = plan_assembly(&assembly, &data_directory, &export_directory, hmm);
+                if let Some(status) = &status {
+                    status.finish(&result);
+                }
+                match result {
+                    Ok(actions) => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&actions).expect("Cannot Serialize")
+                    ),
+                    Err(e) => {
+                        eprintln!("Could Not Plan {} - {}", assembly, e);
+                        std::process::exit(1)
+                    }
+                }
+                return;
+            }
+            let result = prepare_assembly(
+                &assembly,
+                &data_directory,
+                &export_directory,
+                *consolidate_json,
+                hmm,
+                &scratch_directory,
+                max_buffered_bytes,
+                &status,
+                *deterministic,
+            );
+            if let Some(status) = &status {
+                status.finish(&result);
+            }
+            result.unwrap_or_else(|e| panic!("Assembly Prep for {} Failed: {:?}", &assembly, e))
         }
         Some(Commands::MetaData {
             summary,
@@ -261,7 +1867,7 @@ fn main() {
             masks,
             chromosomes,
         }) => {
-            let _assem = assembly_data(
+            if let Err(e) = assembly_data(
                 &assembly,
                 &data_directory,
                 summary,
@@ -269,11 +1875,489 @@ fn main() {
                 benchmarks,
                 masks,
                 chromosomes,
+            ) {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        Some(Commands::ListAssemblies { include_hidden }) => {
+            for assembly in list_assemblies(*include_hidden, &data_directory)
+                .expect("Could Not List Assemblies")
+            {
+                println!("{}", assembly)
+            }
+        }
+        Some(Commands::ListFamilies {
+            data_type,
+            prefix,
+            limit,
+            offset,
+            json,
+        }) => {
+            let page = list_families(
+                &assembly,
+                data_type,
+                prefix.as_deref(),
+                *limit,
+                *offset,
+                &data_directory,
+            )
+            .expect("Could Not List Families");
+            if *json {
+                println!("{}", serde_json::to_string(&page).unwrap());
+            } else {
+                for family in &page.families {
+                    println!("{}", family.accession)
+                }
+            }
+        }
+        Some(Commands::Package {
+            data_type,
+            outfile,
+            include_indexes,
+            manifest,
+        }) => {
+            let data_types: Vec<String> = if data_type.is_empty() {
+                DATA_ELEMENTS
+                    .iter()
+                    .filter(|e| {
+                        Path::new(&format!("{}/{}/{}", &data_directory, &assembly, e)).exists()
+                    })
+                    .map(|e| e.to_string())
+                    .collect()
+            } else {
+                data_type.clone()
+            };
+            let buffer = package_assembly(
+                &assembly,
+                &data_types,
+                outfile,
+                *include_indexes,
+                max_buffered_bytes,
+                &data_directory,
+            )
+            .expect("Packaging Failed");
+            println!(
+                "Wrote {} Bytes To {}, Peak Buffered {} Of {} Bytes, Stalled {} Times",
+                buffer.written_bytes, outfile, buffer.peak_bytes, buffer.cap_bytes, buffer.stalls
+            );
+            // The archive's MANIFEST checksums every file packed, the inputs are not listed again
+            if *manifest {
+                let parameters = serde_json::json!({
+                    "data_types": data_types,
+                    "include_indexes": include_indexes,
+                });
+                write_manifest(outfile, "package", &assembly, parameters, &[], None)
+                    .expect("Could Not Write Manifest");
+            }
+        }
+        Some(Commands::Unpack { infile }) => {
+            unpack_assembly(&assembly, infile, &data_directory).expect("Unpacking Failed");
+        }
+        Some(Commands::ReleaseDelta { old_dir, outdir }) => {
+            match release_delta(
+                join_path(old_dir, &[&assembly]),
+                join_path(&data_directory, &[&assembly]),
+                outdir,
+            ) {
+                Ok(report) => println!("{}", serde_json::to_string(&report).unwrap()),
+                Err(e) => {
+                    style.error(&format!("Release Delta Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+        }
+        Some(Commands::ApplyDelta { delta_dir }) => {
+            match apply_delta(join_path(&data_directory, &[&assembly]), delta_dir) {
+                Ok(report) => println!("{}", serde_json::to_string(&report).unwrap()),
+                Err(e) => {
+                    style.error(&format!("Applying Delta Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+        }
+        Some(Commands::AllAnnotations {
+            outfile,
+            profile,
+            exclude_columns,
+            gzip_plain,
+        }) => {
+            let _res = all_annotations(
+                &assembly,
+                outfile,
+                profile,
+                exclude_columns,
+                output_compression(outfile, *gzip_plain),
+                max_buffered_bytes,
+                &data_directory,
             );
         }
-        Some(Commands::AllAnnotations { outfile }) => {
-            let _res = all_annotations(&assembly, outfile, &data_directory);
+        Some(Commands::GetChromId {
+            query,
+            fuzzy,
+            limit,
+            verbose,
+        }) => {
+            if *fuzzy {
+                let results = find_sequences(&assembly, query, *limit, &data_directory)
+                    .expect("JSON Read Failed");
+                if results.truncated {
+                    eprintln!(
+                        "Showing {} Of {} Matches, Raise --limit To See More",
+                        results.matches.len(),
+                        results.total
+                    );
+                }
+                println!(
+                    "{}",
+                    serde_json::to_string(&results).expect("Error Converting Results to JSON")
+                );
+            } else if *verbose {
+                match resolve_sequence(&assembly, query, &data_directory) {
+                    Ok(resolved) => {
+                        eprintln!(
+                            "Resolved \"{}\" To {} By {:?}{}",
+                            query,
+                            resolved.accession,
+                            resolved.resolution,
+                            if resolved.qualified {
+                                ", Assembly Qualifier Removed"
+                            } else {
+                                ""
+                            }
+                        );
+                        println!("{}", resolved.accession)
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        eprintln!("{}", e);
+                        println!("-1")
+                    }
+                    Err(e) => panic!("JSON Read Failed: {:?}", e),
+                }
+            } else {
+                let ans =
+                    get_chrom_id(&assembly, query, &data_directory).expect("JSON Read Failed");
+                println!("{}", ans)
+            }
+        }
+        Some(Commands::BenchmarkReport { fam, min_overlap }) => {
+            let report = benchmark_report(&assembly, fam, *min_overlap, &data_directory)
+                .expect("Benchmark Report Failed");
+            println!("{}", serde_json::to_string(&report).unwrap());
+        }
+        Some(Commands::FamilyCombined { fam, nrph, web_fmt }) => {
+            let hits = family_combined(&assembly, fam, *nrph, &data_directory)
+                .expect("Reading Family Hits Failed");
+            write_family_combined(
+                &assembly,
+                fam,
+                &hits,
+                *web_fmt,
+                &mut stdout().lock(),
+                &data_directory,
+            )
+            .expect("Writing Family Hits Failed");
+        }
+        Some(Commands::ExpectFamilies {
+            data_type,
+            fam,
+            empty_as_missing,
+        }) => {
+            let missing = missing_families(
+                &assembly,
+                data_type,
+                fam,
+                *empty_as_missing,
+                &data_directory,
+            )
+            .expect("Checking Families Failed");
+            if !missing.is_empty() {
+                for family in &missing {
+                    println!("{}", family);
+                }
+                style.error(&format!(
+                    "{} Of {} Families Missing From {} Of {}",
+                    missing.len(),
+                    fam.len(),
+                    data_type,
+                    assembly
+                ));
+                std::process::exit(1)
+            }
         }
+        Some(Commands::Defragment {
+            fam,
+            max_gap_bp,
+            model_tolerance,
+            tsv,
+        }) => {
+            let report = defragment(
+                &assembly,
+                fam,
+                *max_gap_bp,
+                *model_tolerance,
+                &data_directory,
+            )
+            .expect("Defragment Failed");
+            if *tsv {
+                for line in report.tsv_lines() {
+                    println!("{}", line);
+                }
+            } else {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            }
+        }
+        Some(Commands::Cooccurrence {
+            window_bp,
+            families,
+            nrph,
+            top_n,
+            max_pairs,
+            tsv,
+        }) => {
+            let families = if families.is_empty() {
+                None
+            } else {
+                Some(families.clone())
+            };
+            let report = cooccurrence(
+                &assembly,
+                *window_bp,
+                &families,
+                nrph,
+                *top_n,
+                *max_pairs,
+                &data_directory,
+            )
+            .expect("Co-occurrence Failed");
+            if *tsv {
+                for line in report.tsv_lines() {
+                    println!("{}", line);
+                }
+            } else {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            }
+        }
+        Some(Commands::RedundancyScan {
+            min_reciprocal_overlap,
+            min_pair_count,
+            tsv,
+        }) => {
+            let report = match redundancy_scan(
+                &assembly,
+                *min_reciprocal_overlap,
+                *min_pair_count,
+                &data_directory,
+            ) {
+                Ok(report) => report,
+                Err(e) => {
+                    style.error(&format!("Redundancy Scan Failed - {}", e));
+                    std::process::exit(1)
+                }
+            };
+            if *tsv {
+                for line in report.tsv_lines() {
+                    println!("{}", line);
+                }
+            } else {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            }
+        }
+        Some(Commands::RemapNames {
+            data_type,
+            mapping,
+            out_assembly,
+            drop_unmapped,
+        }) => {
+            let mapping = match mapping {
+                Some(in_tsv) => NameMapping::Tsv(in_tsv.to_string()),
+                None => NameMapping::SequenceIds,
+            };
+            let report = remap_contig_names(
+                &assembly,
+                data_type,
+                &mapping,
+                *drop_unmapped,
+                out_assembly,
+                &data_directory,
+            )
+            .expect("Remapping Failed");
+            println!("Remapped {} Records", report.records);
+            if report.dropped > 0 {
+                println!(
+                    "Dropped {} Records On Unmapped Contigs: {}",
+                    report.dropped,
+                    report
+                        .unmapped
+                        .into_iter()
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+            }
+        }
+        Some(Commands::Compact { data_type, key, .. }) => {
+            let result = compact_family(&assembly, data_type, key, &data_directory, &status);
+            if let Some(status) = &status {
+                status.finish(&result);
+            }
+            match result {
+                Ok(report) => {
+                    let before: u64 = report.files.iter().map(|f| f.bytes_before).sum();
+                    let after: u64 = report.files.iter().map(|f| f.bytes_after).sum();
+                    println!(
+                        "Compacted {} Files, {} -> {} Bytes",
+                        report.files.len(),
+                        before,
+                        after
+                    );
+                    if report.indexed {
+                        println!("Index Rebuilt");
+                    }
+                }
+                Err(e) => {
+                    style.error(&format!("Compaction Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+        }
+        Some(Commands::RepairLayout { data_type }) => {
+            match repair_layout(&assembly, data_type, &data_directory) {
+                Ok(report) => println!("{}", serde_json::to_string(&report).unwrap()),
+                Err(e) => {
+                    style.error(&format!("Layout Repair Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+        }
+        Some(Commands::Sample {
+            data_type,
+            count,
+            seed,
+            validate,
+        }) => {
+            let sampled = sample_records(&assembly, data_type, *count, *seed, &data_directory)
+                .and_then(|records| match *validate {
+                    true => {
+                        let validation =
+                            validate_sample(&assembly, data_type, &records, &data_directory)?;
+                        Ok(serde_json::json!({ "records": records, "validation": validation }))
+                    }
+                    false => Ok(serde_json::json!(records)),
+                });
+            match sampled {
+                Ok(sampled) => println!("{}", serde_json::to_string_pretty(&sampled).unwrap()),
+                Err(e) => {
+                    style.error(&format!("Sampling Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+        }
+        Some(Commands::CheckSeqLens {
+            data_type,
+            sample,
+            seed,
+            examples,
+            repair,
+        }) => {
+            let check = match repair {
+                true => repair_seq_lens(&assembly, data_type, *examples, &data_directory),
+                false => check_seq_lens(
+                    &assembly,
+                    data_type,
+                    sample.map(|n| (n, *seed)),
+                    *examples,
+                    &data_directory,
+                ),
+            };
+            match check {
+                Ok(check) => {
+                    println!("{}", serde_json::to_string_pretty(&check).unwrap());
+                    if check.mismatched > 0 && !*repair {
+                        std::process::exit(1)
+                    }
+                }
+                Err(e) => {
+                    style.error(&format!("Sequence Length Check Failed - {}", e));
+                    std::process::exit(1)
+                }
+            }
+        }
+        Some(Commands::ContigOrder) => match contig_order(&assembly, &data_directory) {
+            Ok(order) => println!("{}", serde_json::to_string_pretty(&order).unwrap()),
+            Err(e) => {
+                style.error(&format!("Contig Order Failed - {}", e));
+                std::process::exit(1)
+            }
+        },
+        Some(Commands::Migrate {
+            from_version,
+            to_version,
+        }) => {
+            let report =
+                match migrate_assembly(&assembly, from_version, to_version, &data_directory) {
+                    Ok(report) => report,
+                    Err(e) => {
+                        style.error(&format!("Migration Failed - {}", e));
+                        std::process::exit(1)
+                    }
+                };
+            println!(
+                "Migrated {} From Layout {} To {}: {} Migrated, {} Already Done",
+                assembly,
+                report.from_version,
+                report.to_version,
+                report.migrated.len(),
+                report.skipped.len()
+            );
+        }
+        Some(Commands::CleanIndexes {
+            data_type,
+            retention_mins,
+        }) => {
+            let data_types = match data_type {
+                Some(data_type) => vec![data_type.as_str()],
+                None => INDEX_DATA_TYPES.to_vec(),
+            };
+            let retention = Duration::from_secs(retention_mins * 60);
+            for data_type in data_types {
+                let index_file = format!("{}/{}/{}_idx.dat", data_directory, assembly, data_type);
+                match idx::clean_indexes(&index_file, retention) {
+                    Ok(removed) => {
+                        for file in removed {
+                            println!("Removed {}", file)
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Cleaning {} Indexes Failed - {}", data_type, e);
+                        std::process::exit(1)
+                    }
+                }
+            }
+        }
+        Some(Commands::Usage { all, tsv }) => {
+            let assembly = Some(&assembly).filter(|_| !*all);
+            let report = match assembly_usage(assembly, &data_directory) {
+                Ok(report) => report,
+                Err(e) => {
+                    style.error(&format!("Usage Report Failed - {}", e));
+                    std::process::exit(1)
+                }
+            };
+            if *tsv {
+                for line in report.tsv_lines() {
+                    println!("{}", line);
+                }
+            } else {
+                println!("{}", serde_json::to_string(&report).unwrap());
+            }
+        }
+        Some(Commands::SelfTest { .. })
+        | Some(Commands::BuildInfo)
+        | Some(Commands::VerifyManifest { .. })
+        | Some(Commands::WarmAssembly { .. })
+        | Some(Commands::CacheStatus) => unreachable!(),
+        #[cfg(feature = "http")]
+        Some(Commands::Serve { .. }) => unreachable!(),
         None => {}
     }
 }
@@ -293,18 +2377,6 @@ fn main() {
 //     outfile: Option<String>,
 // },
 
-// GetChromID {
-//     /// Name of assembly/assembly folder
-//     #[arg(short, long, verbatim_doc_comment)]
-//     assembly: String,
-//     /// Query to get ID for
-//     #[arg(long, short)]
-//     query: String,
-// },
-
-// Some(Commands::GetChromID { assembly, query }) => {
-//     println!("{}", get_chrom_id(assembly, query, &data_directory));
-// }
 // Some(Commands::ProcessJSON {
 //     in_file,
 //     key,
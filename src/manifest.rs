@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{read_to_string, rename, File};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::families::families_file;
+use crate::idx;
+use crate::package::file_digest;
+use crate::paths::join_path;
+use crate::querylog::utc_timestamp;
+use crate::{bgz_path, ASSEMBLY_DATA_FILE, MOD_LEN_DIR, MOD_LEN_FILE, SEQUENCE_DIR, SEQUENCE_FILE};
+
+// How an export was made, written next to it as <output>.manifest.json by --manifest so a bad
+// download can be traced to the te_idx version, parameters and input files that produced it,
+// and checked against its output later with verify_manifest.
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestFile {
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportManifest {
+    pub te_idx_version: String,
+    pub command: String,
+    pub assembly: String,
+    pub parameters: Value,
+    pub inputs: Vec<ManifestFile>,
+    pub records: Option<u64>, // None for exports that do not count records, as package
+    pub output: ManifestFile,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ManifestMismatch {
+    pub path: String,
+    pub input: bool, // False for the output
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct ManifestVerification {
+    pub verified: bool,       // Output and every input as recorded
+    pub output_matches: bool, // The output as recorded, whatever the inputs
+    pub mismatches: Vec<ManifestMismatch>,
+}
+
+impl ManifestFile {
+    pub fn read(path: &str) -> Result<ManifestFile> {
+        let (sha256, bytes) = file_digest(path)?;
+        Ok(ManifestFile {
+            path: path.to_string(),
+            bytes,
+            sha256,
+        })
+    }
+
+    // Why the file no longer is as recorded, None when it is
+    fn mismatch(&self) -> Option<String> {
+        if !Path::new(&self.path).exists() {
+            return Some("Missing".to_string());
+        }
+        match file_digest(&self.path) {
+            Err(e) => Some(format!("Unreadable - {}", e)),
+            Ok((_, bytes)) if bytes != self.bytes => {
+                Some(format!("{} Bytes, {} Recorded", bytes, self.bytes))
+            }
+            Ok((sha256, _)) if sha256 != self.sha256 => Some("Checksum Differs".to_string()),
+            Ok(_) => None,
+        }
+    }
+}
+
+// The manifest path of an export
+pub fn manifest_path(output: &str) -> String {
+    format!("{}{}", output, MANIFEST_SUFFIX)
+}
+
+// The files a filtered export of key is read from: its bgz file, the sequences, model lengths,
+// consolidated and families JSON and the index of the data type, those that exist
+pub fn export_inputs(
+    assembly: &str,
+    data_type: &str,
+    key: &str,
+    data_directory: &str,
+) -> Vec<String> {
+    let (bgz_dir, index_file) = idx::idx_paths(assembly, data_type, data_directory);
+    let json_file = |dir: &str, suffix: &str| {
+        join_path(
+            data_directory,
+            &[assembly, dir, &format!("{}{}", assembly, suffix)],
+        )
+    };
+    [
+        bgz_path(&bgz_dir, key),
+        json_file(SEQUENCE_DIR, SEQUENCE_FILE),
+        json_file(MOD_LEN_DIR, MOD_LEN_FILE),
+        join_path(data_directory, &[assembly, ASSEMBLY_DATA_FILE]),
+        families_file(assembly, data_directory),
+        index_file,
+    ]
+    .into_iter()
+    .filter(|path| Path::new(path).exists())
+    .collect()
+}
+
+// Checksums the finished output and inputs and writes the manifest next to the output,
+// through a temporary file renamed over any previous one
+pub fn write_manifest(
+    output: &str,
+    command: &str,
+    assembly: &str,
+    parameters: Value,
+    inputs: &[String],
+    records: Option<u64>,
+) -> Result<ExportManifest> {
+    let manifest = ExportManifest {
+        te_idx_version: env!("CARGO_PKG_VERSION").to_string(),
+        command: command.to_string(),
+        assembly: assembly.to_string(),
+        parameters,
+        inputs: inputs
+            .iter()
+            .map(|path| ManifestFile::read(path))
+            .collect::<Result<_>>()?,
+        records,
+        output: ManifestFile::read(output)?,
+        created_at: utc_timestamp(SystemTime::now()),
+    };
+    let path = manifest_path(output);
+    let tmp_file = format!("{}.{}.tmp", path, std::process::id());
+    let mut out = File::create(&tmp_file)?;
+    serde_json::to_writer_pretty(&mut out, &manifest)?;
+    out.write_all(b"\n")?;
+    drop(out);
+    rename(&tmp_file, &path)?;
+    Ok(manifest)
+}
+
+// Checks the output and inputs of a manifest against their recorded sizes and checksums. A
+// manifest that cannot be read is an error, files that differ are listed as mismatches.
+pub fn verify_manifest(path: &str) -> Result<ManifestVerification> {
+    let manifest: ExportManifest = serde_json::from_str(&read_to_string(path)?).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{} Is Not A Valid Manifest - {}", path, e),
+        )
+    })?;
+    let mut verification = ManifestVerification::default();
+    let files = std::iter::once((&manifest.output, false))
+        .chain(manifest.inputs.iter().map(|file| (file, true)));
+    for (file, input) in files {
+        if let Some(reason) = file.mismatch() {
+            verification.mismatches.push(ManifestMismatch {
+                path: file.path.clone(),
+                input,
+                reason,
+            });
+        }
+    }
+    verification.output_matches = !verification.mismatches.iter().any(|m| !m.input);
+    verification.verified = verification.mismatches.is_empty();
+    Ok(verification)
+}
@@ -0,0 +1,560 @@
+use noodles::bgzf;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::fs::{copy, create_dir_all, read_to_string, remove_dir_all, remove_file, rename, write};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::idx;
+use crate::paths::{join_path, path_string};
+use crate::records::{bed_coordinates, bed_fields, BedRecordStream};
+use crate::{
+    beds_half_open, half_open_file, list_assemblies, ASSEMBLY_DATA_VERSION, INDEX_DATA_TYPES,
+};
+
+// Each prepared assembly records the layout of its files in <assembly>/assembly_meta.json.
+// Assemblies prepared before the layout was recorded have no file and are layout 0.
+pub const ASSEMBLY_META_FILE: &str = "assembly_meta.json";
+
+// Layout written by this build. 0: indexes of format version 0, names in 40 byte fields,
+// 1: indexes of format version 1, names stored with their length, 2: BED files holding 0-based,
+// half open coordinates rather than the exported ones, see records::bed_coordinates. Indexes
+// built with overflow lists are format version 2 in any layout.
+pub const LAYOUT_VERSION: u32 = 2;
+
+// Oldest layout migrate_assembly still upgrades. Below layout 2 the BED files of a data type
+// hold exported coordinates until converted, and readers refuse them, see check_half_open.
+pub const MIN_LAYOUT_VERSION: u32 = 0;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AssemblyMeta {
+    pub layout_version: u32,
+    // A migration that was started and has not finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migration: Option<MigrationState>,
+    // Queries name sequences by display name only, see redacts_accessions
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub redact_accessions: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MigrationState {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub completed: Vec<String>, // Units already migrated, skipped when resumed
+}
+
+// A registered migration from one layout version to the next. The assembly is migrated a
+// unit at a time, e.g. one index per data type, and the units completed are recorded so an
+// interrupted migration resumes where it stopped. validate checks the whole assembly once
+// every unit is done, before the new layout version is recorded.
+pub struct Migration {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub description: &'static str,
+    units: fn(&str, &str) -> Result<Vec<String>>,
+    migrate: fn(&str, &str, &str) -> Result<()>,
+    validate: fn(&str, &str) -> Result<()>,
+}
+
+pub const MIGRATIONS: [Migration; 2] = [
+    Migration {
+        from_version: 0,
+        to_version: 1,
+        description: "Rewrite indexes in format version 1, with names stored with their length",
+        units: indexed_data_types,
+        migrate: upgrade_data_type_index,
+        validate: check_index_versions,
+    },
+    Migration {
+        from_version: 1,
+        to_version: 2,
+        description:
+            "Convert BED files to 0-based, half open coordinates and rebuild their indexes",
+        units: prepared_data_types,
+        migrate: convert_data_type,
+        validate: check_half_open,
+    },
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub migrated: Vec<String>, // Units migrated by this run, as "<from>-><to> <unit>"
+    pub skipped: Vec<String>,  // Units an earlier, interrupted run had migrated
+}
+
+fn meta_file(assembly: &str, data_directory: &str) -> String {
+    format!("{}/{}/{}", data_directory, assembly, ASSEMBLY_META_FILE)
+}
+
+fn index_file(assembly: &str, data_type: &str, data_directory: &str) -> String {
+    format!("{}/{}/{}_idx.dat", data_directory, assembly, data_type)
+}
+
+pub fn read_assembly_meta(
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<AssemblyMeta> {
+    let data_directory = &path_string(data_directory);
+    let path = meta_file(assembly, data_directory);
+    if !Path::new(&path).exists() {
+        return Ok(AssemblyMeta::default());
+    }
+    serde_json::from_str(&read_to_string(&path)?).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Malformed Assembly Meta {} - {}", path, e),
+        )
+    })
+}
+
+// Replaces the meta file by renaming a new one over it, so it is never seen half written
+pub fn write_assembly_meta(
+    assembly: &str,
+    meta: &AssemblyMeta,
+    data_directory: impl AsRef<Path>,
+) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    let path = meta_file(assembly, data_directory);
+    let partial = format!("{}.partial", path);
+    write(&partial, serde_json::to_string_pretty(meta)?)?;
+    rename(&partial, &path)
+}
+
+// Fails with an Unsupported error if the assembly has a newer layout than this build reads.
+// Layouts being migrated to a version this build supports can be read meanwhile.
+pub fn check_layout(assembly: &str, data_directory: impl AsRef<Path>) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    let meta = read_assembly_meta(assembly, data_directory)?;
+    let newest = meta.migration.as_ref().map_or(meta.layout_version, |m| {
+        m.to_version.max(meta.layout_version)
+    });
+    if newest > LAYOUT_VERSION {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "Assembly {} Has Layout Version {}, Newer Than The Supported {} - Upgrade te_idx",
+                assembly, newest, LAYOUT_VERSION
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Records the current layout for an assembly whose indexes were all written in the current
+// format and whose BED files all hold BED coordinates, as after preparing it from scratch.
+// Re-preparing some data types of an older assembly leaves its layout as it was, the rest
+// still need migrating.
+pub fn record_layout(assembly: &str, data_directory: impl AsRef<Path>) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    let mut meta = read_assembly_meta(assembly, data_directory)?;
+    if meta.layout_version >= LAYOUT_VERSION || meta.migration.is_some() {
+        return Ok(());
+    }
+    if prepared_data_types(assembly, data_directory)?
+        .iter()
+        .any(|data_type| !beds_half_open(assembly, data_type, data_directory))
+    {
+        return Ok(());
+    }
+    for data_type in indexed_data_types(assembly, data_directory)? {
+        let index = index_file(assembly, &data_type, data_directory);
+        if idx::index_format_version(&index)? < idx::FORMAT_VERSION {
+            return Ok(());
+        }
+    }
+    // The assembly's policies are kept
+    meta.layout_version = LAYOUT_VERSION;
+    write_assembly_meta(assembly, &meta, data_directory)
+}
+
+// Migrates an assembly from its recorded layout to to_version, LAYOUT_VERSION by default,
+// through each registered migration in turn. A from_version, when given, must match the
+// recorded layout. An interrupted migration is resumed by running the same command again.
+pub fn migrate_assembly(
+    assembly: &str,
+    from_version: &Option<u32>,
+    to_version: &Option<u32>,
+    data_directory: impl AsRef<Path>,
+) -> Result<MigrationReport> {
+    let data_directory = &path_string(data_directory);
+    if !Path::new(&format!("{}/{}", data_directory, assembly)).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Assembly \"{}/{}\" Does Not Exist",
+                data_directory, assembly
+            ),
+        ));
+    }
+    let mut meta = read_assembly_meta(assembly, data_directory)?;
+    let current = meta.layout_version;
+    if let Some(from_version) = from_version {
+        if *from_version != current {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Assembly {} Has Layout Version {}, Not {}",
+                    assembly, current, from_version
+                ),
+            ));
+        }
+    }
+    let target = to_version.unwrap_or(LAYOUT_VERSION);
+    if target > LAYOUT_VERSION || target < current {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Cannot Migrate Assembly {} From Layout Version {} To {}, Supported Versions Are {} To {}",
+                assembly, current, target, current, LAYOUT_VERSION
+            ),
+        ));
+    }
+
+    let mut report = MigrationReport {
+        from_version: current,
+        to_version: target,
+        ..Default::default()
+    };
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| m.from_version >= current && m.to_version <= target)
+    {
+        let step = format!("{}->{}", migration.from_version, migration.to_version);
+        let mut state = match meta.migration.take() {
+            Some(state)
+                if state.from_version == migration.from_version
+                    && state.to_version == migration.to_version =>
+            {
+                state
+            }
+            _ => MigrationState {
+                from_version: migration.from_version,
+                to_version: migration.to_version,
+                completed: Vec::new(),
+            },
+        };
+        eprintln!(
+            "Migrating {} From Layout {} To {}: {}",
+            assembly, migration.from_version, migration.to_version, migration.description
+        );
+        let units = (migration.units)(assembly, data_directory)?;
+        for (n, unit) in units.iter().enumerate() {
+            if state.completed.contains(unit) {
+                eprintln!("   [{}/{}] {} Already Migrated", n + 1, units.len(), unit);
+                report.skipped.push(format!("{} {}", step, unit));
+                continue;
+            }
+            eprintln!("   [{}/{}] Migrating {}", n + 1, units.len(), unit);
+            (migration.migrate)(assembly, unit, data_directory)?;
+            state.completed.push(unit.to_string());
+            meta.migration = Some(state.clone());
+            write_assembly_meta(assembly, &meta, data_directory)?;
+            report.migrated.push(format!("{} {}", step, unit));
+        }
+        eprintln!("   Validating Layout {}", migration.to_version);
+        (migration.validate)(assembly, data_directory)?;
+        meta.layout_version = migration.to_version;
+        meta.migration = None;
+        write_assembly_meta(assembly, &meta, data_directory)?;
+    }
+    Ok(report)
+}
+
+// What this build reads and writes, printed by build-info so a binary and a data volume can
+// be checked against each other
+#[derive(Serialize, Debug)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub index_format_version: u16,
+    pub min_index_format_version: u16,
+    pub max_index_format_version: u16, // Newest read, written for deterministic indexes
+    pub layout_version: u32,
+    pub min_layout_version: u32,
+    pub assembly_data_version: u64,
+    pub features: Vec<&'static str>, // Optional cargo features compiled in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compatibility: Option<Vec<AssemblyCompatibility>>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Readable,
+    NeedsMigration,
+    NewerVersion,
+    Unreadable,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AssemblyCompatibility {
+    pub assembly: String,
+    pub layout_version: Option<u32>,
+    pub migration_in_progress: bool,
+    pub index_versions: BTreeMap<String, u16>,
+    pub verdict: Verdict,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+// Every optional cargo feature, with whether this build has it
+const COMPILED_FEATURES: [(&str, bool); 1] = [("http", cfg!(feature = "http"))];
+
+pub fn build_info(data_directory: &Option<String>) -> Result<BuildInfo> {
+    let compatibility = match data_directory {
+        Some(data_directory) => Some(check_compatibility(data_directory)?),
+        None => None,
+    };
+    Ok(BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        index_format_version: idx::FORMAT_VERSION,
+        min_index_format_version: idx::MIN_FORMAT_VERSION,
+        max_index_format_version: idx::MAX_FORMAT_VERSION,
+        layout_version: LAYOUT_VERSION,
+        min_layout_version: MIN_LAYOUT_VERSION,
+        assembly_data_version: ASSEMBLY_DATA_VERSION,
+        features: COMPILED_FEATURES
+            .iter()
+            .filter(|(_, compiled)| *compiled)
+            .map(|(feature, _)| *feature)
+            .collect(),
+        compatibility,
+    })
+}
+
+// Reads the assembly meta and index headers of every prepared assembly, hidden ones included,
+// and says whether this build can read it as it is, after migrating it, or not at all
+pub fn check_compatibility(data_directory: impl AsRef<Path>) -> Result<Vec<AssemblyCompatibility>> {
+    let data_directory = &path_string(data_directory);
+    Ok(list_assemblies(true, data_directory)?
+        .into_iter()
+        .map(|assembly| assembly_compatibility(assembly, data_directory))
+        .collect())
+}
+
+fn assembly_compatibility(assembly: String, data_directory: &str) -> AssemblyCompatibility {
+    let mut report = AssemblyCompatibility {
+        assembly,
+        layout_version: None,
+        migration_in_progress: false,
+        index_versions: BTreeMap::new(),
+        verdict: Verdict::Unreadable,
+        detail: None,
+    };
+    let meta = match read_assembly_meta(&report.assembly, data_directory) {
+        Ok(meta) => meta,
+        Err(e) => {
+            report.detail = Some(e.to_string());
+            return report;
+        }
+    };
+    report.layout_version = Some(meta.layout_version);
+    report.migration_in_progress = meta.migration.is_some();
+    let data_types = match indexed_data_types(&report.assembly, data_directory) {
+        Ok(data_types) => data_types,
+        Err(e) => {
+            report.detail = Some(e.to_string());
+            return report;
+        }
+    };
+    for data_type in data_types {
+        let index = index_file(&report.assembly, &data_type, data_directory);
+        match idx::index_format_version(&index) {
+            Ok(version) => {
+                report.index_versions.insert(data_type, version);
+            }
+            Err(e) => {
+                report.detail = Some(format!("{} - {}", index, e));
+                return report;
+            }
+        }
+    }
+
+    let newest_layout = meta.migration.as_ref().map_or(meta.layout_version, |m| {
+        m.to_version.max(meta.layout_version)
+    });
+    let newest_index = report.index_versions.values().max().copied();
+    let oldest_index = report.index_versions.values().min().copied();
+    report.verdict = if newest_layout > LAYOUT_VERSION {
+        report.detail = Some(format!(
+            "Layout Version {} Is Newer Than The Supported {}",
+            newest_layout, LAYOUT_VERSION
+        ));
+        Verdict::NewerVersion
+    } else if newest_index.is_some_and(|v| v > idx::MAX_FORMAT_VERSION) {
+        report.detail = Some(format!(
+            "Index Format Version {} Is Newer Than The Supported {}",
+            newest_index.unwrap(),
+            idx::MAX_FORMAT_VERSION
+        ));
+        Verdict::NewerVersion
+    } else if meta.layout_version < LAYOUT_VERSION
+        || report.migration_in_progress
+        || oldest_index.is_some_and(|v| v < idx::FORMAT_VERSION)
+    {
+        Verdict::NeedsMigration
+    } else {
+        Verdict::Readable
+    };
+    report
+}
+
+// Migration 0 -> 1 ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+fn indexed_data_types(assembly: &str, data_directory: &str) -> Result<Vec<String>> {
+    Ok(INDEX_DATA_TYPES
+        .iter()
+        .filter(|data_type| Path::new(&index_file(assembly, data_type, data_directory)).exists())
+        .map(|data_type| data_type.to_string())
+        .collect())
+}
+
+// Writes the new index beside the old one and renames it over it, queries running meanwhile
+// keep reading the old file. An index already rebuilt in the new format is left as it is.
+fn upgrade_data_type_index(assembly: &str, data_type: &str, data_directory: &str) -> Result<()> {
+    let index = index_file(assembly, data_type, data_directory);
+    if idx::index_format_version(&index)? >= idx::FORMAT_VERSION {
+        return Ok(());
+    }
+    let upgraded = format!("{}.migrating", index);
+    if let Err(e) = idx::upgrade_index(&index, &upgraded) {
+        let _ = remove_file(&upgraded);
+        return Err(Error::other(format!("Upgrading {} Failed - {}", index, e)));
+    }
+    rename(&upgraded, &index)
+}
+
+fn check_index_versions(assembly: &str, data_directory: &str) -> Result<()> {
+    for data_type in indexed_data_types(assembly, data_directory)? {
+        let index = index_file(assembly, &data_type, data_directory);
+        let version = idx::check_index(&index)?;
+        if version < idx::FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} Has Format Version {} After Migration", index, version),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Migration 1 -> 2 ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Data types with BED files, indexed or not
+fn prepared_data_types(assembly: &str, data_directory: &str) -> Result<Vec<String>> {
+    Ok(INDEX_DATA_TYPES
+        .iter()
+        .filter(|data_type| Path::new(&join_path(data_directory, &[assembly, data_type])).is_dir())
+        .map(|data_type| data_type.to_string())
+        .collect())
+}
+
+// Rewrites a BED file with the chromStart and chromEnd of its exported coordinates. Comment
+// lines are kept as they are.
+fn convert_bed_file(from: &Path, to: &Path) -> Result<()> {
+    let source = from.to_string_lossy();
+    let mut writer = bgzf::Writer::new(File::create(to)?);
+    for (n, record) in BedRecordStream::open(&source)?.enumerate() {
+        let record = record?;
+        let line = record.line.trim_end_matches(['\n', '\r']);
+        if line.starts_with('#') || line.is_empty() {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let mut fields = bed_fields(line);
+        let (start, end) = fields
+            .get(1)
+            .zip(fields.get(2))
+            .and_then(|(start, end)| bed_coordinates(start, end))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Invalid Coordinates In {} Line {} - {}",
+                        source,
+                        n + 1,
+                        line
+                    ),
+                )
+            })?;
+        let (start, end) = (start.to_string(), end.to_string());
+        fields[1] = &start;
+        fields[2] = &end;
+        writeln!(writer, "{}", fields.join("\t"))?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+// Copies a data type folder, shard folders included, with every BED file converted and its
+// other files, such as the export's comment lines, as they are
+fn convert_folder(from: &str, to: &str) -> Result<()> {
+    create_dir_all(to)?;
+    for entry in WalkDir::new(from).min_depth(1) {
+        let entry = entry?;
+        let target = Path::new(to).join(entry.path().strip_prefix(from).unwrap());
+        if entry.file_type().is_dir() {
+            create_dir_all(&target)?;
+        } else if entry.file_name().to_string_lossy().ends_with(".bed.bgz") {
+            convert_bed_file(entry.path(), &target)?;
+        } else {
+            copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+// Converts the BED files of a data type into a hidden folder beside it, then swaps it in, so
+// queries meanwhile read whole files of one kind, and rebuilds the data type's index if it has
+// one. The exported folder is set aside until the converted one is in place and marked, so a
+// run stopped at any point converts every file once when resumed.
+fn convert_data_type(assembly: &str, data_type: &str, data_directory: &str) -> Result<()> {
+    let assembly_dir = join_path(data_directory, &[assembly]);
+    let folder = join_path(&assembly_dir, &[data_type]);
+    let exported = join_path(&assembly_dir, &[&format!(".{}.exported", data_type)]);
+    let converting = join_path(&assembly_dir, &[&format!(".{}.converting", data_type)]);
+    if !beds_half_open(assembly, data_type, data_directory) {
+        if !Path::new(&exported).exists() {
+            if Path::new(&converting).exists() {
+                remove_dir_all(&converting)?;
+            }
+            convert_folder(&folder, &converting)?;
+            rename(&folder, &exported)?;
+        }
+        if !Path::new(&folder).exists() {
+            rename(&converting, &folder)?;
+        }
+        File::create(half_open_file(assembly, data_type, data_directory))?;
+    }
+    if Path::new(&exported).exists() {
+        remove_dir_all(&exported)?;
+    }
+
+    if Path::new(&index_file(assembly, data_type, data_directory)).exists() {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            idx::prep_idx(assembly, data_type, false, data_directory)
+                .map_err(|e| Error::other(e.to_string()))?;
+        idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+            .map_err(|e| Error::other(format!("Rebuilding {} Failed - {}", index_file, e)))?;
+    }
+    Ok(())
+}
+
+fn check_half_open(assembly: &str, data_directory: &str) -> Result<()> {
+    for data_type in prepared_data_types(assembly, data_directory)? {
+        if !beds_half_open(assembly, &data_type, data_directory) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} Of {} Holds Exported Coordinates After Migration",
+                    data_type, assembly
+                ),
+            ));
+        }
+    }
+    check_index_versions(assembly, data_directory)
+}
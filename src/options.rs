@@ -0,0 +1,482 @@
+use std::time::Duration;
+
+use crate::backpressure::configured_max_buffered_bytes;
+use crate::querylog::QueryLogger;
+use crate::status::StatusFile;
+use crate::{
+    BedFormat, MaskSubtraction, ResultGrouping, DEFAULT_SHARD_THRESHOLD, DEFAULT_SORT_BUFFER_BYTES,
+    PUBLIC_PROFILE,
+};
+
+// Options of idx_query_with, bgzf_filter_with and prep_beds_with. Every option has a default,
+// so callers only name the ones they change:
+//   IdxQueryOptions::builder().family("DF000000001").nrph(true).build()
+// The fields are public as well, for callers that already hold the values in Options.
+
+// The filters and output shape of an index query. By default every hit of any region length
+// is returned as a JSON array.
+#[derive(Clone, Debug, Default)]
+pub struct IdxQueryOptions {
+    pub max_region: Option<u64>,
+    pub chunk_size: Option<u64>, // Span searched at once, default DEFAULT_CHUNK_SIZE, 0 never splits
+    pub family: Option<Vec<String>>, // Hits of any of these, see acc_matches. Empty keeps all
+    pub exact_version: bool,     // Versioned families match only that version
+    pub nrph: bool,
+    pub strand: Option<String>,
+    pub min_bit_score: Option<f64>,
+    pub max_e_value: Option<f64>,
+    pub max_bias: Option<f64>,
+    pub strict_bias: bool, // Hits without a numeric bias fail max_bias rather than pass
+    pub repeat_class: Option<String>, // Masks of this class, masks without one never match
+    pub downsample: Option<String>, // N:W, see parse_downsample
+    pub timeout: Option<Duration>,
+    pub partial: bool,
+    pub legacy_json: bool,
+    pub with_warnings: bool,
+    pub strict: bool,
+    pub with_model_length: bool,
+    pub with_family_meta: bool, // Classification and clades of each hit's family
+    pub json_seq_len: bool,     // seq_len from the sequences JSON, not the stored column
+    pub subtract_masks: Option<MaskSubtraction>,
+    pub group_by: Option<ResultGrouping>,
+    pub bed_format: Option<BedFormat>,
+    pub resolve_names: bool, // sequence ids as chrom names, bed_format only
+    pub fast_path: bool,     // Data assumed immutable, see configured_fast_path
+    pub allow_scan: bool,    // Files scanned when the index is unusable, see configured_allow_scan
+    pub ignore_redaction: bool, // Accessions kept for internal tooling, see redacts_accessions
+    pub with_etag: bool,     // The response's etag in the object, see query_etag
+    pub head: bool,          // Only {"etag", "total"}, the hits counted but not returned
+    pub query_log: Option<QueryLogger>,
+}
+
+impl IdxQueryOptions {
+    pub fn builder() -> IdxQueryOptionsBuilder {
+        IdxQueryOptionsBuilder::default()
+    }
+}
+
+#[derive(Default)]
+pub struct IdxQueryOptionsBuilder {
+    options: IdxQueryOptions,
+}
+
+impl IdxQueryOptionsBuilder {
+    pub fn max_region(mut self, max_region: u64) -> Self {
+        self.options.max_region = Some(max_region);
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.options.chunk_size = Some(chunk_size);
+        self
+    }
+
+    // Adds a family to those whose hits are returned
+    pub fn family(mut self, family: impl Into<String>) -> Self {
+        self.options
+            .family
+            .get_or_insert_with(Vec::new)
+            .push(family.into());
+        self
+    }
+
+    pub fn families<I: IntoIterator<Item = S>, S: Into<String>>(mut self, families: I) -> Self {
+        self.options
+            .family
+            .get_or_insert_with(Vec::new)
+            .extend(families.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn exact_version(mut self, exact_version: bool) -> Self {
+        self.options.exact_version = exact_version;
+        self
+    }
+
+    pub fn nrph(mut self, nrph: bool) -> Self {
+        self.options.nrph = nrph;
+        self
+    }
+
+    pub fn strand(mut self, strand: impl Into<String>) -> Self {
+        self.options.strand = Some(strand.into());
+        self
+    }
+
+    pub fn min_bit_score(mut self, min_bit_score: f64) -> Self {
+        self.options.min_bit_score = Some(min_bit_score);
+        self
+    }
+
+    pub fn max_e_value(mut self, max_e_value: f64) -> Self {
+        self.options.max_e_value = Some(max_e_value);
+        self
+    }
+
+    pub fn max_bias(mut self, max_bias: f64) -> Self {
+        self.options.max_bias = Some(max_bias);
+        self
+    }
+
+    pub fn strict_bias(mut self, strict_bias: bool) -> Self {
+        self.options.strict_bias = strict_bias;
+        self
+    }
+
+    pub fn repeat_class(mut self, repeat_class: impl Into<String>) -> Self {
+        self.options.repeat_class = Some(repeat_class.into());
+        self
+    }
+
+    pub fn downsample(mut self, downsample: impl Into<String>) -> Self {
+        self.options.downsample = Some(downsample.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn partial(mut self, partial: bool) -> Self {
+        self.options.partial = partial;
+        self
+    }
+
+    pub fn legacy_json(mut self, legacy_json: bool) -> Self {
+        self.options.legacy_json = legacy_json;
+        self
+    }
+
+    pub fn with_warnings(mut self, with_warnings: bool) -> Self {
+        self.options.with_warnings = with_warnings;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    pub fn with_model_length(mut self, with_model_length: bool) -> Self {
+        self.options.with_model_length = with_model_length;
+        self
+    }
+
+    pub fn with_family_meta(mut self, with_family_meta: bool) -> Self {
+        self.options.with_family_meta = with_family_meta;
+        self
+    }
+
+    pub fn json_seq_len(mut self, json_seq_len: bool) -> Self {
+        self.options.json_seq_len = json_seq_len;
+        self
+    }
+
+    pub fn subtract_masks(mut self, subtract_masks: MaskSubtraction) -> Self {
+        self.options.subtract_masks = Some(subtract_masks);
+        self
+    }
+
+    pub fn group_by(mut self, group_by: ResultGrouping) -> Self {
+        self.options.group_by = Some(group_by);
+        self
+    }
+
+    pub fn bed_format(mut self, bed_format: BedFormat) -> Self {
+        self.options.bed_format = Some(bed_format);
+        self
+    }
+
+    pub fn resolve_names(mut self, resolve_names: bool) -> Self {
+        self.options.resolve_names = resolve_names;
+        self
+    }
+
+    pub fn fast_path(mut self, fast_path: bool) -> Self {
+        self.options.fast_path = fast_path;
+        self
+    }
+
+    pub fn allow_scan(mut self, allow_scan: bool) -> Self {
+        self.options.allow_scan = allow_scan;
+        self
+    }
+
+    pub fn ignore_redaction(mut self, ignore_redaction: bool) -> Self {
+        self.options.ignore_redaction = ignore_redaction;
+        self
+    }
+
+    pub fn with_etag(mut self, with_etag: bool) -> Self {
+        self.options.with_etag = with_etag;
+        self
+    }
+
+    pub fn head(mut self, head: bool) -> Self {
+        self.options.head = head;
+        self
+    }
+
+    pub fn query_log(mut self, query_log: QueryLogger) -> Self {
+        self.options.query_log = Some(query_log);
+        self
+    }
+
+    pub fn build(self) -> IdxQueryOptions {
+        self.options
+    }
+}
+
+// What a filter of one bgz file matches and writes. By default every record is written with
+// the public column profile, prefiltered on the term.
+#[derive(Clone, Debug)]
+pub struct FilterOptions {
+    pub position: usize, // 1-based column compared with term
+    pub term: Option<String>,
+    pub exact_version: bool, // Versioned family keys and terms match only that version
+    pub strand: Option<String>,
+    pub max_bias: Option<f64>,
+    pub strict_bias: bool, // Records without a numeric bias fail max_bias rather than pass
+    pub dl_fmt: bool,      // Dfam.org download columns, --web-fmt
+    pub profile: String,
+    pub exclude_columns: Vec<String>,
+    pub source_meta: bool,
+    pub with_family_meta: bool, // Classification and clades columns of the file's family
+    pub prefilter: bool,
+    pub bed_format: Option<BedFormat>,
+    pub resolve_names: bool, // sequence ids as chrom names, bed_format only
+    pub ignore_redaction: bool, // Accessions kept for internal tooling, see redacts_accessions
+    pub query_log: Option<QueryLogger>,
+}
+
+impl Default for FilterOptions {
+    fn default() -> FilterOptions {
+        FilterOptions {
+            position: 1,
+            term: None,
+            exact_version: false,
+            strand: None,
+            max_bias: None,
+            strict_bias: false,
+            dl_fmt: false,
+            profile: PUBLIC_PROFILE.to_string(),
+            exclude_columns: Vec::new(),
+            source_meta: false,
+            with_family_meta: false,
+            prefilter: true,
+            bed_format: None,
+            resolve_names: false,
+            ignore_redaction: false,
+            query_log: None,
+        }
+    }
+}
+
+impl FilterOptions {
+    pub fn builder() -> FilterOptionsBuilder {
+        FilterOptionsBuilder::default()
+    }
+}
+
+#[derive(Default)]
+pub struct FilterOptionsBuilder {
+    options: FilterOptions,
+}
+
+impl FilterOptionsBuilder {
+    // Only records whose column at position (1-based) is term
+    pub fn term(mut self, position: usize, term: impl Into<String>) -> Self {
+        self.options.position = position;
+        self.options.term = Some(term.into());
+        self
+    }
+
+    pub fn exact_version(mut self, exact_version: bool) -> Self {
+        self.options.exact_version = exact_version;
+        self
+    }
+
+    pub fn strand(mut self, strand: impl Into<String>) -> Self {
+        self.options.strand = Some(strand.into());
+        self
+    }
+
+    pub fn max_bias(mut self, max_bias: f64) -> Self {
+        self.options.max_bias = Some(max_bias);
+        self
+    }
+
+    pub fn strict_bias(mut self, strict_bias: bool) -> Self {
+        self.options.strict_bias = strict_bias;
+        self
+    }
+
+    pub fn dl_fmt(mut self, dl_fmt: bool) -> Self {
+        self.options.dl_fmt = dl_fmt;
+        self
+    }
+
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.options.profile = profile.into();
+        self
+    }
+
+    pub fn exclude_columns(mut self, exclude_columns: &[String]) -> Self {
+        self.options.exclude_columns = exclude_columns.to_vec();
+        self
+    }
+
+    pub fn source_meta(mut self, source_meta: bool) -> Self {
+        self.options.source_meta = source_meta;
+        self
+    }
+
+    pub fn with_family_meta(mut self, with_family_meta: bool) -> Self {
+        self.options.with_family_meta = with_family_meta;
+        self
+    }
+
+    pub fn prefilter(mut self, prefilter: bool) -> Self {
+        self.options.prefilter = prefilter;
+        self
+    }
+
+    pub fn bed_format(mut self, bed_format: BedFormat) -> Self {
+        self.options.bed_format = Some(bed_format);
+        self
+    }
+
+    pub fn resolve_names(mut self, resolve_names: bool) -> Self {
+        self.options.resolve_names = resolve_names;
+        self
+    }
+
+    pub fn ignore_redaction(mut self, ignore_redaction: bool) -> Self {
+        self.options.ignore_redaction = ignore_redaction;
+        self
+    }
+
+    pub fn query_log(mut self, query_log: QueryLogger) -> Self {
+        self.options.query_log = Some(query_log);
+        self
+    }
+
+    pub fn build(self) -> FilterOptions {
+        self.options
+    }
+}
+
+// How an export TSV is split into BED files. By default duplicates are kept, files move to
+// shard folders past DEFAULT_SHARD_THRESHOLD accessions, scratch files go where scratch_dir
+// puts them and records in flight to the compressing writers are capped as configured.
+#[derive(Clone, Debug)]
+pub struct PrepOptions {
+    pub dedupe: bool,
+    pub shard_threshold: usize, // 0 always shards
+    pub scratch_directory: Option<String>,
+    pub max_buffered_bytes: u64,
+    pub sort: bool, // Each BED file in contig and position order, off keeps the export order
+    pub sort_buffer_bytes: usize, // Lines held in memory per accession before spilling to scratch
+    pub status: Option<StatusFile>, // Given the bytes of the TSV read
+    // A sequences JSON giving the contig order when sorting, the assembly's prepared one when
+    // None. For preparing an assembly's BED files before its sequences JSON is copied.
+    pub sequences_file: Option<String>,
+}
+
+impl Default for PrepOptions {
+    fn default() -> PrepOptions {
+        PrepOptions {
+            dedupe: false,
+            shard_threshold: DEFAULT_SHARD_THRESHOLD,
+            scratch_directory: None,
+            max_buffered_bytes: configured_max_buffered_bytes(None),
+            sort: true,
+            sort_buffer_bytes: DEFAULT_SORT_BUFFER_BYTES,
+            status: None,
+            sequences_file: None,
+        }
+    }
+}
+
+impl PrepOptions {
+    pub fn builder() -> PrepOptionsBuilder {
+        PrepOptionsBuilder::default()
+    }
+}
+
+#[derive(Default)]
+pub struct PrepOptionsBuilder {
+    options: PrepOptions,
+}
+
+impl PrepOptionsBuilder {
+    pub fn dedupe(mut self, dedupe: bool) -> Self {
+        self.options.dedupe = dedupe;
+        self
+    }
+
+    pub fn shard_threshold(mut self, shard_threshold: usize) -> Self {
+        self.options.shard_threshold = shard_threshold;
+        self
+    }
+
+    pub fn sharded(self) -> Self {
+        self.shard_threshold(0)
+    }
+
+    pub fn scratch_directory(mut self, scratch_directory: impl Into<String>) -> Self {
+        self.options.scratch_directory = Some(scratch_directory.into());
+        self
+    }
+
+    pub fn max_buffered_bytes(mut self, max_buffered_bytes: u64) -> Self {
+        self.options.max_buffered_bytes = max_buffered_bytes;
+        self
+    }
+
+    pub fn sort(mut self, sort: bool) -> Self {
+        self.options.sort = sort;
+        self
+    }
+
+    pub fn sort_buffer_bytes(mut self, sort_buffer_bytes: usize) -> Self {
+        self.options.sort_buffer_bytes = sort_buffer_bytes;
+        self
+    }
+
+    pub fn status(mut self, status: StatusFile) -> Self {
+        self.options.status = Some(status);
+        self
+    }
+
+    pub fn sequences_file(mut self, sequences_file: impl Into<String>) -> Self {
+        self.options.sequences_file = Some(sequences_file.into());
+        self
+    }
+
+    pub fn build(self) -> PrepOptions {
+        self.options
+    }
+}
+
+// How plan::run_action runs an action of preparing an assembly. BED files are split as
+// PrepOptions::default() splits them, in this scratch folder and under this cap.
+#[derive(Clone, Debug)]
+pub struct ActionOptions {
+    pub scratch_directory: Option<String>,
+    pub max_buffered_bytes: u64,
+    pub status: Option<StatusFile>,
+    pub deterministic: bool, // Indexes built as build-idx --deterministic builds them
+}
+
+impl Default for ActionOptions {
+    fn default() -> ActionOptions {
+        ActionOptions {
+            scratch_directory: None,
+            max_buffered_bytes: configured_max_buffered_bytes(None),
+            status: None,
+            deterministic: false,
+        }
+    }
+}
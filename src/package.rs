@@ -0,0 +1,258 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, rename, File};
+use std::io::{self, copy, Error, ErrorKind, Read, Result};
+use std::path::{Component, Path};
+use walkdir::WalkDir;
+
+use crate::backpressure::{BoundedWriter, BufferStats};
+use crate::idx::{family_listing_file, index_format_version, HASHED_FORMAT_VERSION};
+use crate::migrate::ASSEMBLY_META_FILE;
+use crate::paths::path_string;
+use crate::{HALF_OPEN_FILE, INDEX_DATA_TYPES};
+
+pub const MANIFEST_FILE: &str = "MANIFEST";
+
+// Checksums everything read through it, so files are hashed while they stream into
+// (or out of) the archive rather than in a second pass
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    fn digest(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+}
+
+// sha256 and size of a file, as a MANIFEST lists them
+pub(crate) fn file_digest(path: &str) -> Result<(String, u64)> {
+    let mut reader = HashingReader::new(File::open(path)?);
+    copy(&mut reader, &mut io::sink())?;
+    let bytes = reader.bytes;
+    Ok((reader.digest(), bytes))
+}
+
+// Streams the prepared files of the selected data types into a tar.gz, followed by a
+// MANIFEST listing "sha256<TAB>bytes<TAB>path" for every entry. At most max_buffered_bytes of
+// the compressed archive wait for the outfile, reading stalls beyond that. Returns what was
+// buffered. Only deterministic indexes are included: unpacked files get new modification
+// times, which an index recording them would report as modified files.
+pub fn package_assembly(
+    assembly: &str,
+    data_types: &[String],
+    out_tar_gz: impl AsRef<Path>,
+    include_indexes: bool,
+    max_buffered_bytes: u64,
+    data_directory: impl AsRef<Path>,
+) -> Result<BufferStats> {
+    let out_tar_gz = &path_string(out_tar_gz);
+    let data_directory = &path_string(data_directory);
+    let assembly_path = format!("{}/{}", &data_directory, &assembly);
+    if !Path::new(&assembly_path).exists() {
+        panic!("Assembly \"{}\" Does Not Exist", assembly_path);
+    }
+
+    // Entries are stored relative to the data directory, i.e. <assembly>/<data_type>/...
+    let mut entries: Vec<String> = Vec::new();
+    for data_type in data_types {
+        let data_path = format!("{}/{}", &assembly_path, &data_type);
+        if !Path::new(&data_path).exists() {
+            panic!("Data \"{}\" Does Not Exist", data_path);
+        }
+        for entry in WalkDir::new(&data_path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            let rel = entry.path().strip_prefix(data_directory).unwrap();
+            entries.push(rel.to_string_lossy().to_string());
+        }
+        // Says what coordinates the files hold, so it goes with them
+        let half_open = format!("{}/{}{}", &assembly, &data_type, HALF_OPEN_FILE);
+        if Path::new(&format!("{}/{}", &data_directory, &half_open)).exists() {
+            entries.push(half_open);
+        }
+        if include_indexes && INDEX_DATA_TYPES.contains(&data_type.as_str()) {
+            let index_file = format!("{}/{}_idx.dat", &assembly, &data_type);
+            let index_path = format!("{}/{}", &data_directory, &index_file);
+            if !Path::new(&index_path).exists() {
+                eprintln!("No Index Found For {}, Skipping", data_type);
+            } else if index_format_version(&index_path)? < HASHED_FORMAT_VERSION {
+                eprintln!(
+                    "Index Of {} Is Not Deterministic, Skipping - rebuild it with build-idx --deterministic",
+                    data_type
+                );
+            } else {
+                // The listing numbers files as the index does, it only goes with it
+                let listing_file = family_listing_file(&index_file);
+                if Path::new(&format!("{}/{}", &data_directory, &listing_file)).exists() {
+                    entries.push(listing_file);
+                }
+                entries.push(index_file);
+            }
+        }
+    }
+
+    // Indexes are read according to the assembly's layout, which goes with them
+    let meta_file = format!("{}/{}", &assembly, ASSEMBLY_META_FILE);
+    if include_indexes && Path::new(&format!("{}/{}", &data_directory, &meta_file)).exists() {
+        entries.push(meta_file);
+    }
+
+    let out_f = File::create(out_tar_gz)?;
+    let out_f = BoundedWriter::new(out_f, max_buffered_bytes);
+    let mut builder = tar::Builder::new(GzEncoder::new(out_f, Compression::default()));
+    let mut manifest = String::new();
+    for rel in &entries {
+        let in_f = File::open(format!("{}/{}", &data_directory, &rel))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&in_f.metadata()?);
+        let mut reader = HashingReader::new(in_f);
+        builder.append_data(&mut header, rel, &mut reader)?;
+        let bytes = reader.bytes;
+        manifest.push_str(&format!("{}\t{}\t{}\n", reader.digest(), bytes, rel));
+    }
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, MANIFEST_FILE, manifest.as_bytes())?;
+    let (_, buffer) = builder.into_inner()?.finish()?.finish()?;
+    Ok(buffer)
+}
+
+// Extracts a package_assembly archive into the data directory, checking every entry
+// against the MANIFEST. Only regular files under <assembly>/ are accepted. Entries are
+// extracted into a temporary folder beside the assembly and only moved into place once all
+// of them match the MANIFEST, so a bad archive leaves the data directory as it was.
+pub fn unpack_assembly(
+    assembly: &str,
+    in_tar_gz: impl AsRef<Path>,
+    data_directory: impl AsRef<Path>,
+) -> Result<()> {
+    let in_tar_gz = &path_string(in_tar_gz);
+    let data_directory = &path_string(data_directory);
+    if !Path::new(&in_tar_gz).exists() {
+        panic!("Package \"{}\" Not Found", &in_tar_gz);
+    }
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(in_tar_gz)?));
+    create_dir_all(data_directory)?;
+    let staging = tempfile::Builder::new()
+        .prefix(&format!(".{}.unpack", assembly))
+        .tempdir_in(data_directory)?;
+
+    // In archive order, so indexes are moved into place after the files they index
+    let mut order: Vec<String> = Vec::new();
+    let mut extracted: BTreeMap<String, (String, u64)> = BTreeMap::new();
+    let mut manifest: Option<String> = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let rel = entry.path()?.to_path_buf();
+        let rel_str = rel.to_string_lossy().to_string();
+        // Directories, symlinks and hard links could point outside the assembly
+        if !entry.header().entry_type().is_file() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Entry \"{}\" In Package Is Not A Regular File", rel_str),
+            ));
+        }
+        if rel_str == MANIFEST_FILE {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            manifest = Some(contents);
+            continue;
+        }
+        if !rel.starts_with(assembly)
+            || rel.components().any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unexpected Entry \"{}\" In Package", rel_str),
+            ));
+        }
+
+        let target = staging.path().join(&rel);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut out_f = File::create(&target)?;
+        let mut reader = HashingReader::new(&mut entry);
+        copy(&mut reader, &mut out_f)?;
+        let bytes = reader.bytes;
+        if extracted
+            .insert(rel_str.clone(), (reader.digest(), bytes))
+            .is_some()
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Entry \"{}\" Is In Package More Than Once", rel_str),
+            ));
+        }
+        order.push(rel_str);
+    }
+
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("No {} In Package", MANIFEST_FILE),
+            ))
+        }
+    };
+    let mut problems = Vec::new();
+    for line in manifest.lines().filter(|l| !l.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 3 {
+            problems.push(format!("Malformed Manifest Line \"{}\"", line));
+            continue;
+        }
+        match extracted.remove(fields[2]) {
+            Some((digest, bytes)) => {
+                if digest != fields[0] || bytes.to_string() != fields[1] {
+                    problems.push(format!("Checksum Mismatch For {}", fields[2]));
+                }
+            }
+            None => problems.push(format!("Missing {}", fields[2])),
+        }
+    }
+    for rel in extracted.keys() {
+        problems.push(format!("{} Not Listed In Manifest", rel));
+    }
+    if !problems.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, problems.join(", ")));
+    }
+
+    for rel in &order {
+        let target = Path::new(data_directory).join(rel);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        rename(staging.path().join(rel), &target)?;
+    }
+    staging.close()
+}
@@ -0,0 +1,26 @@
+use std::path::{Path, PathBuf};
+
+// Data paths are passed around as Strings. They are joined here with the platform separator
+// rather than formatted with "/", so the query path also works on Windows.
+
+// A path argument as the String the data directory is joined onto
+pub fn path_string(path: impl AsRef<Path>) -> String {
+    path.as_ref().to_string_lossy().into_owned()
+}
+
+// A path below base, one component per part:
+//   join_path(data_directory, &[assembly, data_type])
+pub fn join_path(base: &str, parts: &[&str]) -> String {
+    let mut path = PathBuf::from(base);
+    for part in parts {
+        path.push(part);
+    }
+    path.to_string_lossy().into_owned()
+}
+
+// The path of a file named in an index below its data type folder. Names are stored with "/"
+// between their components, e.g. "<shard>/<acc>.bed.bgz", and read with either separator.
+pub fn stored_path(base: &str, name: &str) -> String {
+    let parts: Vec<&str> = name.split(['/', '\\']).filter(|p| !p.is_empty()).collect();
+    join_path(base, &parts)
+}
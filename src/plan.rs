@@ -0,0 +1,335 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{copy, create_dir_all, rename};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::hmm;
+use crate::idx;
+use crate::migrate::record_layout;
+use crate::options::{ActionOptions, PrepOptions};
+use crate::paths::{join_path, path_string};
+use crate::status::StatusFile;
+use crate::{
+    beds_half_open, consolidate_assembly_data, prep_beds_with, ASSEMBLY_DATA_FILE, ASSEMBLY_DIR,
+    ASSEMBLY_FILE, BENCHMARK_DIR, BENCHMARK_FILE, FAMILIES_DIR, FAMILIES_FILE, INDEX_DATA_TYPES,
+    JSON_DATA_TYPES, MASKS_DIR, MASKS_FILE, MOD_LEN_DIR, MOD_LEN_FILE, SEQUENCE_DIR, SEQUENCE_FILE,
+};
+
+// The steps prepare_assembly takes, for workflow engines that schedule them themselves: the
+// plan of an assembly is one action per data element, each run on its own by run_action, in
+// any order and in parallel, then finalize_assembly once every action has succeeded.
+// prepare_assembly runs the plan in order in one process.
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrepStep {
+    CopyJson,     // The export's JSON copied into the element's folder
+    ModelLengths, // The model lengths JSON generated from HMM models, see hmm
+    PrepBeds,     // The export TSV split into BED files, then indexed
+    BuildIdx,     // BED files already split indexed, e.g. after an interrupted prep
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrepAction {
+    pub assembly: String,
+    pub element: String,
+    pub step: PrepStep,
+    pub source: Option<String>, // None when indexing
+    pub target: String,         // The element's folder
+    // The export's sequences JSON when the plan copies it, so the BED files are sorted in its
+    // contig order whether or not it is copied first
+    pub sequences_file: Option<String>,
+    pub data_directory: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActionReport {
+    pub element: String,
+    pub step: PrepStep,
+    pub skipped: bool,          // Its output was already in place, nothing was done
+    pub records: Option<usize>, // Records split into BED files, or models read
+    pub empty_files: Vec<String>, // BED files indexed without records
+}
+
+// The export file of a data element, after the assembly name
+fn export_file(element: &str) -> Option<&'static str> {
+    match element {
+        ASSEMBLY_DIR => Some(ASSEMBLY_FILE),
+        BENCHMARK_DIR => Some(BENCHMARK_FILE),
+        MASKS_DIR => Some(MASKS_FILE),
+        MOD_LEN_DIR => Some(MOD_LEN_FILE),
+        SEQUENCE_DIR => Some(SEQUENCE_FILE),
+        FAMILIES_DIR => Some(FAMILIES_FILE),
+        _ => None,
+    }
+}
+
+fn index_file(assembly: &str, element: &str, data_directory: &str) -> String {
+    join_path(data_directory, &[assembly, &format!("{}_idx.dat", element)])
+}
+
+// The JSON file a copy or generation writes
+fn json_target(action: &PrepAction) -> String {
+    join_path(
+        &action.target,
+        &[&format!(
+            "{}{}",
+            action.assembly,
+            export_file(&action.element).unwrap()
+        )],
+    )
+}
+
+fn prepared(target: &str) -> Result<bool> {
+    Ok(Path::new(target).exists() && Path::new(target).read_dir()?.next().is_some())
+}
+
+// One action per data element the assembly's export has and the data directory does not, the
+// JSON elements first. With hmm_path, a .hmm file or folder of them, the model lengths are
+// generated when the export has none. A data type whose BED files were split but never indexed
+// is indexed. Nothing is written.
+pub fn plan_assembly(
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
+    export_directory: impl AsRef<Path>,
+    hmm_path: &Option<String>,
+) -> Result<Vec<PrepAction>> {
+    let data_directory = &path_string(data_directory);
+    let export_directory = &path_string(export_directory);
+    let export_dir = join_path(export_directory, &[assembly]);
+    let working_dir = join_path(data_directory, &[assembly]);
+    for (dir, what) in [
+        (data_directory, "Data Directory"),
+        (export_directory, "Export Directory"),
+        (&export_dir, "Assembly Export"),
+    ] {
+        if !Path::new(dir).exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} Not Found - {}", what, dir),
+            ));
+        }
+    }
+
+    let mut plan = Vec::new();
+    for element in JSON_DATA_TYPES
+        .into_iter()
+        .chain([FAMILIES_DIR])
+        .chain(INDEX_DATA_TYPES)
+    {
+        let source = join_path(
+            &export_dir,
+            &[&format!("{}{}", assembly, export_file(element).unwrap())],
+        );
+        let target = join_path(&working_dir, &[element]);
+        let from_hmm = element == MOD_LEN_DIR && hmm_path.is_some() && !Path::new(&source).exists();
+        let source = match (from_hmm, hmm_path) {
+            (true, Some(hmm_path)) => hmm_path.to_string(),
+            _ => source,
+        };
+        let have_target = prepared(&target)?;
+        let step = match (Path::new(&source).exists() && !have_target, from_hmm) {
+            (true, true) => PrepStep::ModelLengths,
+            (true, false) if source.ends_with(".tsv") => PrepStep::PrepBeds,
+            (true, false) => PrepStep::CopyJson,
+            (false, _)
+                if INDEX_DATA_TYPES.contains(&element)
+                    && have_target
+                    && beds_half_open(assembly, element, data_directory)
+                    && !Path::new(&index_file(assembly, element, data_directory)).exists() =>
+            {
+                PrepStep::BuildIdx
+            }
+            (false, _) => continue,
+        };
+        plan.push(PrepAction {
+            assembly: assembly.to_string(),
+            element: element.to_string(),
+            step,
+            source: Some(source).filter(|_| step != PrepStep::BuildIdx),
+            target,
+            sequences_file: None,
+            data_directory: data_directory.to_string(),
+        });
+    }
+    let copied_sequences = plan
+        .iter()
+        .find(|action| action.element == SEQUENCE_DIR)
+        .and_then(|action| action.source.clone());
+    for action in plan.iter_mut() {
+        if action.step == PrepStep::PrepBeds {
+            action.sequences_file = copied_sequences.clone();
+        }
+    }
+    Ok(plan)
+}
+
+// Whether finalize_assembly writes the consolidated JSON, rewritten whenever either of its
+// sources is prepared again
+pub fn consolidation_needed(
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
+    plan: &[PrepAction],
+    consolidate_json: bool,
+) -> bool {
+    let data_directory = &path_string(data_directory);
+    consolidate_json
+        && (!Path::new(&join_path(data_directory, &[assembly, ASSEMBLY_DATA_FILE])).exists()
+            || plan
+                .iter()
+                .any(|action| JSON_DATA_TYPES.contains(&action.element.as_str())))
+}
+
+fn index_element(action: &PrepAction, options: &ActionOptions) -> Result<Vec<String>> {
+    println!("   Indexing {}", action.element);
+    let (filenames, bgz_dir, mut contig_index, index_file) = idx::prep_idx(
+        &action.assembly,
+        &action.element,
+        false,
+        &action.data_directory,
+    )
+    .map_err(|e| Error::other(format!("Index Prep Failed - {}", e)))?;
+    contig_index.set_deterministic(options.deterministic);
+    if let Some(status) = &options.status {
+        status.phase(
+            &format!("build-idx {}", action.element),
+            Some(filenames.len() as u64),
+            "files",
+        );
+        contig_index.set_build_progress(status.build_progress());
+    }
+    idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+        .map_err(|e| Error::other(format!("Indexing Failed - {}", e)))?;
+    let empty = contig_index.empty_files().clone();
+    if !empty.is_empty() {
+        println!(
+            "   {} Files Have No Records: {}",
+            empty.len(),
+            empty.join(", ")
+        );
+    }
+    Ok(empty)
+}
+
+// Runs one action of a plan. An action whose output is already in place is skipped, so an
+// action may be run again after it failed or was interrupted, or by a retried job. Actions of
+// different elements may run at once.
+pub fn run_action(action: &PrepAction, options: &ActionOptions) -> Result<ActionReport> {
+    let mut report = ActionReport {
+        element: action.element.clone(),
+        step: action.step,
+        skipped: false,
+        records: None,
+        empty_files: Vec::new(),
+    };
+    let (assembly, element, data_directory) =
+        (&action.assembly, &action.element, &action.data_directory);
+    let indexed = Path::new(&index_file(assembly, element, data_directory)).exists();
+    report.skipped = match action.step {
+        PrepStep::CopyJson | PrepStep::ModelLengths => Path::new(&json_target(action)).exists(),
+        PrepStep::PrepBeds => indexed && beds_half_open(assembly, element, data_directory),
+        PrepStep::BuildIdx => indexed,
+    };
+    if report.skipped {
+        println!("{} Of {} Already Prepared", element, assembly);
+        return Ok(report);
+    }
+    let source = || {
+        action.source.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} Of {} Has No Source", action.step, element),
+            )
+        })
+    };
+
+    println!("Preparing {}: ", element);
+    if !Path::new(&action.target).exists() {
+        println!(
+            "   Target Directory Not Found, Creating {},",
+            &action.target
+        );
+        create_dir_all(&action.target)?;
+    }
+    // prep_beds_with starts its own phase
+    if let Some(status) = options
+        .status
+        .as_ref()
+        .filter(|_| action.step != PrepStep::PrepBeds)
+    {
+        status.phase(element, None, "");
+    }
+    match action.step {
+        PrepStep::ModelLengths => {
+            let source = source()?;
+            println!("   Generating Model Lengths From {}", source);
+            let models = hmm::generate_model_lengths(source, assembly, &json_target(action))?;
+            println!("   {} Prep Complete, {} Models", element, models);
+            report.records = Some(models);
+        }
+        PrepStep::CopyJson => {
+            // Copied beside the target and renamed, a copy cut short is never taken for one
+            let target = json_target(action);
+            let partial = format!("{}.{}.copying", target, std::process::id());
+            copy(source()?, &partial)?;
+            rename(&partial, &target)?;
+            println!("   {} Prep Complete", element);
+        }
+        PrepStep::PrepBeds => {
+            let source = source()?;
+            println!("   Splitting And Compressing BED Files For {}", element);
+            let prep_options = PrepOptions {
+                scratch_directory: options.scratch_directory.clone(),
+                max_buffered_bytes: options.max_buffered_bytes,
+                status: options.status.clone(),
+                sequences_file: action.sequences_file.clone(),
+                ..PrepOptions::default()
+            };
+            let prep = prep_beds_with(assembly, source, element, &prep_options, data_directory)?;
+            report.records = Some(prep.records);
+            report.empty_files = index_element(action, options)?;
+            println!("   {} Prep Complete", element);
+        }
+        PrepStep::BuildIdx => {
+            report.empty_files = index_element(action, options)?;
+            println!("   {} Index Complete", element);
+        }
+    }
+    Ok(report)
+}
+
+// Writes what the assembly's data elements share once every action of the plan has a report:
+// the consolidated JSON when asked and needed, then the layout metadata, see
+// migrate::record_layout
+pub fn finalize_assembly(
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
+    plan: &[PrepAction],
+    reports: &[ActionReport],
+    consolidate_json: bool,
+    status: &Option<StatusFile>,
+) -> Result<()> {
+    let data_directory = &path_string(data_directory);
+    for action in plan {
+        if !reports
+            .iter()
+            .any(|report| report.element == action.element && report.step == action.step)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{:?} Of {} Has No Report, Every Action Must Succeed First",
+                    action.step, action.element
+                ),
+            ));
+        }
+    }
+    if consolidation_needed(assembly, data_directory, plan, consolidate_json) {
+        if let Some(status) = status {
+            status.phase("consolidate", None, "");
+        }
+        println!("Consolidating JSON Into {}", ASSEMBLY_DATA_FILE);
+        consolidate_assembly_data(assembly, data_directory)?;
+    }
+    record_layout(assembly, data_directory)
+}
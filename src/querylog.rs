@@ -0,0 +1,194 @@
+use serde_json::{json, Value};
+use std::fs::{metadata, rename, File, OpenOptions};
+use std::io::{Error, Result, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::time::{Instant, SystemTime};
+
+use crate::paths::path_string;
+
+// The query log is NDJSON, one line per query. Set by --query-log or $TE_IDX_QUERY_LOG.
+pub const QUERY_LOG_ENV: &str = "TE_IDX_QUERY_LOG";
+pub const DEFAULT_QUERY_LOG_MAX_MB: u64 = 100;
+
+// Appends query records to a log file shared by any number of processes. Every line is one
+// O_APPEND write made under an exclusive flock, and once the file reaches max_bytes it is
+// renamed to the first free <path>.<n> and a new one started. A max_bytes of 0 never rotates.
+#[derive(Clone, Debug)]
+pub struct QueryLogger {
+    path: String,
+    max_bytes: u64,
+}
+
+impl QueryLogger {
+    pub fn new(path: impl AsRef<Path>, max_bytes: u64) -> QueryLogger {
+        let path = &path_string(path);
+        QueryLogger {
+            path: path.to_string(),
+            max_bytes,
+        }
+    }
+
+    // The logger the command line asks for, by argument or environment, if any
+    pub fn from_config(path: &Option<String>, max_mb: u64) -> Option<QueryLogger> {
+        let path = match path {
+            Some(path) => Some(path.to_string()),
+            None => std::env::var(QUERY_LOG_ENV)
+                .ok()
+                .filter(|path| !path.is_empty()),
+        };
+        path.map(|path| QueryLogger::new(&path, max_mb << 20))
+    }
+
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn log(&self, entry: &Value) -> Result<()> {
+        let line = format!("{}\n", entry);
+        loop {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            if !self.lock_current(&file)? {
+                continue;
+            }
+            let opened = file.metadata()?;
+            if self.max_bytes > 0 && opened.len() >= self.max_bytes {
+                rename(&self.path, self.rotated_path())?;
+                continue;
+            }
+            // The lock is released when the file is closed
+            return file.write_all(line.as_bytes());
+        }
+    }
+
+    // Locks the opened file, false when another process rotated it between the open and the
+    // lock and the path now names a new file
+    #[cfg(unix)]
+    fn lock_current(&self, file: &File) -> Result<bool> {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        let opened = file.metadata()?;
+        Ok(match metadata(&self.path) {
+            Ok(current) => current.ino() == opened.ino() && current.dev() == opened.dev(),
+            Err(_) => false,
+        })
+    }
+
+    // Elsewhere lines are appended unlocked, so processes sharing a log may interleave rotation
+    #[cfg(not(unix))]
+    fn lock_current(&self, _file: &File) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn rotated_path(&self) -> String {
+        let mut n = 1;
+        while Path::new(&format!("{}.{}", self.path, n)).exists() {
+            n += 1;
+        }
+        format!("{}.{}", self.path, n)
+    }
+}
+
+// Runs a query, logging it when there is a logger. A failed query is logged with its error
+// kind and a panicking one as "Panic" before the panic carries on. Failing to write the log
+// is only warned about, the query result is returned either way.
+pub(crate) fn logged<T>(
+    query_log: &Option<QueryLogger>,
+    operation: &str,
+    assembly: &str,
+    parameters: Value,
+    count: impl Fn(&T) -> usize,
+    query: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    logged_with(
+        query_log,
+        operation,
+        assembly,
+        parameters,
+        |result| (count(result), None),
+        query,
+    )
+}
+
+// logged, with fields taken from a successful result added to the entry when there are any
+pub(crate) fn logged_with<T>(
+    query_log: &Option<QueryLogger>,
+    operation: &str,
+    assembly: &str,
+    parameters: Value,
+    summary: impl Fn(&T) -> (usize, Option<Value>),
+    query: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let logger = match query_log {
+        Some(logger) => logger,
+        None => return query(),
+    };
+    let started = Instant::now();
+    let outcome = catch_unwind(AssertUnwindSafe(query));
+    let (result_count, metrics, error) = match &outcome {
+        Ok(Ok(result)) => {
+            let (count, metrics) = summary(result);
+            (Some(count), metrics, None)
+        }
+        Ok(Err(e)) => (None, None, Some(format!("{:?}", e.kind()))),
+        Err(_) => (None, None, Some("Panic".to_string())),
+    };
+    let mut entry = json!({
+        "timestamp": utc_timestamp(SystemTime::now()),
+        "operation": operation,
+        "assembly": assembly,
+        "parameters": parameters,
+        "result_count": result_count,
+        "elapsed_ms": started.elapsed().as_millis() as u64,
+        "error": error,
+    });
+    if let Some(Value::Object(fields)) = metrics {
+        for (key, value) in fields {
+            entry[key] = value;
+        }
+    }
+    if let Err(e) = logger.log(&entry) {
+        eprintln!("Warning: Could Not Write Query Log {} - {}", logger.path, e);
+    }
+    match outcome {
+        Ok(result) => result,
+        Err(panic) => resume_unwind(panic),
+    }
+}
+
+// ISO 8601 UTC with milliseconds, e.g. 2024-03-01T17:04:09.123Z
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, day_secs) = ((secs / 86400) as i64, secs % 86400);
+    // Days to a proleptic Gregorian date, as in Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
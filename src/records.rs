@@ -0,0 +1,153 @@
+use noodles::bgzf;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek};
+
+// Records of a prepared BED file, read one line at a time. Every reader of the bgz files goes
+// through here, so lines are split, trimmed and reported on failure the same way everywhere.
+
+// The tab separated fields of a BED line, empty ones included. Only the line ending is
+// trimmed, fields may be empty at either end of the line.
+pub fn bed_fields(line: &str) -> Vec<&str> {
+    line.trim_end_matches(['\n', '\r']).split('\t').collect()
+}
+
+// Exports give seq_start and seq_end as nhmmer does, 1-based and fully closed, seq_start above
+// seq_end on the minus strand. Prepared BED files hold chromStart and chromEnd in columns 2 and
+// 3 as BED does, 0-based and half open, chromStart below chromEnd on either strand, the strand
+// being in its own column. prep-beds converts once and everything reading the files converts
+// back through these, so the index, searches and the files agree on where a record ends.
+
+// The chromStart and chromEnd of a record exported as seq_start..seq_end, None when either is
+// not a 1-based coordinate
+pub fn bed_coordinates(seq_start: &str, seq_end: &str) -> Option<(u64, u64)> {
+    let (start, end) = (seq_start.parse::<u64>().ok()?, seq_end.parse::<u64>().ok()?);
+    let (low, high) = (start.min(end), start.max(end));
+    (low > 0).then(|| (low - 1, high))
+}
+
+// The seq_start and seq_end a BED record was exported with, seq_start the higher on the minus
+// strand. None unless chromStart and chromEnd are numbers with chromStart below chromEnd.
+pub fn export_coordinates(chrom_start: &str, chrom_end: &str, strand: &str) -> Option<(u64, u64)> {
+    let (start, end) = (
+        chrom_start.parse::<u64>().ok()?,
+        chrom_end.parse::<u64>().ok()?,
+    );
+    if start >= end {
+        return None;
+    }
+    Some(match strand {
+        "-" => (end, start + 1),
+        _ => (start + 1, end),
+    })
+}
+
+// The 1-based, fully closed extent of a BED record, as queries give regions
+pub fn bed_extent(fields: &[&str]) -> Option<(u64, u64)> {
+    export_coordinates(fields.get(1)?, fields.get(2)?, "+")
+}
+
+// Readers that know where the next line starts, as a BGZF virtual position. The multithreaded
+// reader does not, its records have no position.
+pub trait RecordPosition {
+    fn record_position(&self) -> Option<u64>;
+}
+
+impl<R: Read> RecordPosition for bgzf::Reader<R> {
+    fn record_position(&self) -> Option<u64> {
+        Some(u64::from(self.virtual_position()))
+    }
+}
+
+impl RecordPosition for bgzf::MultithreadedReader<File> {
+    fn record_position(&self) -> Option<u64> {
+        None
+    }
+}
+
+// One line of a BED file as read, line ending included, and the virtual position it starts at
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BedRecord {
+    pub line: String,
+    pub virtual_position: Option<u64>,
+}
+
+impl BedRecord {
+    pub fn fields(&self) -> Vec<&str> {
+        bed_fields(&self.line)
+    }
+}
+
+pub struct BedRecordStream<R> {
+    reader: R,
+    source: String,             // the file read, for error messages
+    line_number: Option<usize>, // unknown after a seek
+}
+
+impl<R: BufRead + RecordPosition> BedRecordStream<R> {
+    pub fn new(reader: R, source: &str) -> BedRecordStream<R> {
+        BedRecordStream {
+            reader,
+            source: source.to_string(),
+            line_number: Some(0),
+        }
+    }
+
+    // Where the next record starts, the virtual position the index records for it. A record
+    // starting a block is at the start of that block rather than the end of the one before.
+    pub fn virtual_position(&self) -> Option<u64> {
+        self.reader.record_position()
+    }
+
+    // The next record, None at the end of the file. Errors name the file and the line, or the
+    // virtual position when the stream was positioned by a seek.
+    pub fn read_record(&mut self) -> io::Result<Option<BedRecord>> {
+        let virtual_position = self.reader.record_position();
+        let mut line = String::new();
+        if let Some(line_number) = self.line_number.as_mut() {
+            *line_number += 1;
+        }
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(BedRecord {
+                line,
+                virtual_position,
+            })),
+            Err(e) => {
+                let at = match (self.line_number, virtual_position) {
+                    (Some(line_number), _) => format!("Line {}", line_number),
+                    (None, Some(position)) => format!("Virtual Position {}", position),
+                    (None, None) => "Unknown Position".to_string(),
+                };
+                Err(io::Error::new(
+                    e.kind(),
+                    format!("{} At {}: {}", self.source, at, e),
+                ))
+            }
+        }
+    }
+}
+
+impl BedRecordStream<bgzf::Reader<File>> {
+    pub fn open(path: &str) -> io::Result<BedRecordStream<bgzf::Reader<File>>> {
+        File::open(path).map(|file| BedRecordStream::new(bgzf::Reader::new(file), path))
+    }
+}
+
+impl<R: Read + Seek> BedRecordStream<bgzf::Reader<R>> {
+    // Positions the stream at the record starting at a virtual position from the index. Line
+    // numbers are unknown from here on.
+    pub fn seek(&mut self, virtual_position: u64) -> io::Result<()> {
+        self.reader
+            .seek(bgzf::VirtualPosition::from(virtual_position))?;
+        self.line_number = None;
+        Ok(())
+    }
+}
+
+impl<R: BufRead + RecordPosition> Iterator for BedRecordStream<R> {
+    type Item = io::Result<BedRecord>;
+
+    fn next(&mut self) -> Option<io::Result<BedRecord>> {
+        self.read_record().transpose()
+    }
+}
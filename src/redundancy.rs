@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::idx::bed_fields;
+use crate::paths::path_string;
+use crate::records::bed_extent;
+use crate::{iter_assembly, ASSEMBLY_DIR};
+
+pub const DEFAULT_MIN_RECIPROCAL_OVERLAP: f64 = 0.9;
+pub const DEFAULT_MIN_PAIR_COUNT: u64 = 10;
+// Loci kept per pair to show where the families annotate the same interval
+pub const MAX_EXAMPLES: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RedundantPair {
+    pub family_a: String, // family_a sorts before family_b
+    pub family_b: String,
+    pub count: u64, // Hit pairs overlapping reciprocally
+    pub a_hits: u64,
+    pub b_hits: u64,
+    pub examples: Vec<String>, // <contig>:<start>-<end> spanning both hits, the first found
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RedundancyReport {
+    pub min_reciprocal_overlap: f64,
+    pub min_pair_count: u64,
+    pub hits: u64,
+    pub families: usize,
+    pub overlapping_pairs: u64, // Hit pairs of different families overlapping reciprocally
+    pub pairs: Vec<RedundantPair>,
+}
+
+pub const REDUNDANCY_TSV_HEADER: &str = "#family_a\tfamily_b\tcount\ta_hits\tb_hits\texamples";
+
+impl RedundancyReport {
+    pub fn tsv_lines(&self) -> Vec<String> {
+        let mut lines = vec![REDUNDANCY_TSV_HEADER.to_string()];
+        for pair in &self.pairs {
+            lines.push(format!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                pair.family_a,
+                pair.family_b,
+                pair.count,
+                pair.a_hits,
+                pair.b_hits,
+                pair.examples.join(",")
+            ));
+        }
+        lines
+    }
+}
+
+// A hit the ones after it may still overlap
+struct ActiveHit {
+    family: u32,
+    low: u64,
+    high: u64,
+}
+
+// Overlap of two hits as a fraction of the longer one, so both are covered at least as much
+fn reciprocal_overlap(a: &ActiveHit, low: u64, high: u64) -> f64 {
+    let overlap = a.high.min(high).saturating_sub(a.low.max(low));
+    let longer = (a.high - a.low).max(high - low).max(1);
+    overlap as f64 / longer as f64
+}
+
+// Finds family pairs annotating nearly the same intervals across the assembly, a sign of
+// redundant models. Every contig is streamed in position order, keeping only the hits that
+// still overlap the next one. Hits of two different families pair when their overlap covers
+// at least min_reciprocal_overlap of each, and pairs found at least min_pair_count times are
+// reported, most frequent first, with the first loci found.
+pub fn redundancy_scan(
+    assembly: &str,
+    min_reciprocal_overlap: f64,
+    min_pair_count: u64,
+    data_directory: impl AsRef<Path>,
+) -> Result<RedundancyReport> {
+    let data_directory = &path_string(data_directory);
+    if !(min_reciprocal_overlap > 0.0 && min_reciprocal_overlap <= 1.0) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Minimum Reciprocal Overlap {} Is Not Above 0 And At Most 1",
+                min_reciprocal_overlap
+            ),
+        ));
+    }
+    let records = iter_assembly(assembly, ASSEMBLY_DIR, &None, &false, data_directory)?;
+
+    let mut family_ids: HashMap<String, u32> = HashMap::new();
+    let mut family_hits: Vec<u64> = Vec::new();
+    let mut pairs: HashMap<(u32, u32), (u64, Vec<String>)> = HashMap::new();
+    let mut active: Vec<ActiveHit> = Vec::new();
+    let mut contig = String::new();
+    let (mut hits, mut overlapping_pairs) = (0, 0);
+    for record in records {
+        let record = record?;
+        let fields = bed_fields(&record);
+        let Some((low, high)) = bed_extent(&fields).filter(|_| fields.len() >= 4) else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid Record In {} - {}", assembly, record),
+            ));
+        };
+        if fields[0] != contig {
+            contig = fields[0].to_string();
+            active.clear();
+        }
+        let family = fields[3];
+        let id = match family_ids.get(family) {
+            Some(id) => *id,
+            None => {
+                let id = family_ids.len() as u32;
+                family_ids.insert(family.to_string(), id);
+                family_hits.push(0);
+                id
+            }
+        };
+        family_hits[id as usize] += 1;
+        hits += 1;
+
+        active.retain(|hit| hit.high > low);
+        for hit in active.iter().filter(|hit| hit.family != id) {
+            if reciprocal_overlap(hit, low, high) < min_reciprocal_overlap {
+                continue;
+            }
+            let (count, examples) = pairs
+                .entry((hit.family.min(id), hit.family.max(id)))
+                .or_default();
+            *count += 1;
+            if examples.len() < MAX_EXAMPLES {
+                examples.push(format!(
+                    "{}:{}-{}",
+                    contig,
+                    low.min(hit.low),
+                    high.max(hit.high)
+                ));
+            }
+            overlapping_pairs += 1;
+        }
+        active.push(ActiveHit {
+            family: id,
+            low,
+            high,
+        });
+    }
+
+    let mut names = vec![String::new(); family_ids.len()];
+    for (name, id) in family_ids {
+        names[id as usize] = name;
+    }
+    let mut pairs: Vec<RedundantPair> = pairs
+        .into_iter()
+        .filter(|(_, (count, _))| *count >= min_pair_count)
+        .map(|((a, b), (count, examples))| {
+            let (a, b) = if names[a as usize] <= names[b as usize] {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            RedundantPair {
+                family_a: names[a as usize].clone(),
+                family_b: names[b as usize].clone(),
+                count,
+                a_hits: family_hits[a as usize],
+                b_hits: family_hits[b as usize],
+                examples,
+            }
+        })
+        .collect();
+    pairs.sort_by(|x, y| {
+        (Reverse(x.count), &x.family_a, &x.family_b).cmp(&(
+            Reverse(y.count),
+            &y.family_a,
+            &y.family_b,
+        ))
+    });
+
+    Ok(RedundancyReport {
+        min_reciprocal_overlap,
+        min_pair_count,
+        hits,
+        families: names.len(),
+        overlapping_pairs,
+        pairs,
+    })
+}
@@ -0,0 +1,246 @@
+use noodles::bgzf;
+use serde_json::{Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{copy, create_dir_all, read_dir, read_to_string, File};
+use std::io::{BufRead, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+use crate::idx;
+use crate::paths::path_string;
+use crate::{
+    beds_half_open, half_open_file, parse_sequence_info, MASKS_DIR, MOD_LEN_DIR, SEQUENCE_DIR,
+    SOURCE_HEADER_FILE,
+};
+
+// Where the old -> new contig names come from
+pub enum NameMapping {
+    // Two column TSV of current name and new name
+    Tsv(String),
+    // The "id" field of each entry in the assembly's sequences JSON
+    SequenceIds,
+}
+
+#[derive(Debug, Default)]
+pub struct RemapReport {
+    pub records: usize,
+    pub dropped: usize,
+    pub unmapped: BTreeSet<String>, // Contigs without a mapping, only with drop_unmapped
+}
+
+fn sequences_file(assembly: &str, data_directory: &str) -> String {
+    format!(
+        "{}/{}/{}/{}-{}.json",
+        data_directory, assembly, SEQUENCE_DIR, assembly, SEQUENCE_DIR
+    )
+}
+
+fn load_sequences(assembly: &str, data_directory: &str) -> Result<Value> {
+    let target_file = sequences_file(assembly, data_directory);
+    if !Path::new(&target_file).exists() {
+        panic!("{} Not Found", &target_file);
+    }
+    serde_json::from_str(&read_to_string(&target_file)?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+pub fn load_name_mapping(
+    assembly: &str,
+    mapping: &NameMapping,
+    data_directory: impl AsRef<Path>,
+) -> Result<HashMap<String, String>> {
+    let data_directory = &path_string(data_directory);
+    let mut names = HashMap::new();
+    match mapping {
+        NameMapping::Tsv(in_tsv) => {
+            if !Path::new(&in_tsv).exists() {
+                panic!("Mapping File \"{}\" Not Found", &in_tsv);
+            }
+            for line in read_to_string(in_tsv)?.lines() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match line.split('\t').collect::<Vec<&str>>()[..] {
+                    [from, to] => {
+                        names.insert(from.to_string(), to.to_string());
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Malformed Mapping Line \"{}\", Expected 2 Columns", line),
+                        ))
+                    }
+                }
+            }
+        }
+        NameMapping::SequenceIds => {
+            let sequences = load_sequences(assembly, data_directory)?;
+            let data = sequences.get("data").unwrap_or(&Value::Null);
+            for (acc, info) in parse_sequence_info(assembly, data) {
+                if let Some(id) = info.id {
+                    names.insert(acc, id);
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+// Rewrites every .bed.bgz of a data type into <out_assembly> with the contig column
+// renamed, in the original record order, then writes a sequences JSON keyed by the new
+// names and indexes the result. Masks files are named by contig, so they are renamed too.
+// Unmapped contigs are an error unless drop_unmapped is set, in which case their records
+// are left out and reported.
+pub fn remap_contig_names(
+    assembly: &str,
+    data_type: &str,
+    mapping: &NameMapping,
+    drop_unmapped: bool,
+    out_assembly: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<RemapReport> {
+    let data_directory = &path_string(data_directory);
+    let data_path = format!("{}/{}/{}", data_directory, assembly, data_type);
+    if !Path::new(&data_path).exists() {
+        panic!("Data \"{}\" Does Not Exist", data_path);
+    }
+    if assembly == out_assembly {
+        panic!("Remapped Data Must Be Written To A New Assembly");
+    }
+    let names = load_name_mapping(assembly, mapping, data_directory)?;
+
+    let out_assembly_path = format!("{}/{}", data_directory, out_assembly);
+    let out_path = format!("{}/{}", out_assembly_path, data_type);
+    create_dir_all(&out_path)?;
+
+    let mut files: Vec<String> = read_dir(&data_path)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|f| f.ends_with(".bed.bgz"))
+        .collect();
+    files.sort();
+
+    let mut report = RemapReport::default();
+    for file in files {
+        let name = file.trim_end_matches(".bed.bgz");
+        let reader = bgzf::Reader::new(File::open(format!("{}/{}", data_path, file))?);
+        let out_name = if data_type == MASKS_DIR {
+            match names.get(name) {
+                Some(new_name) => new_name.as_str(),
+                None if drop_unmapped => {
+                    report.dropped += reader.lines().count();
+                    report.unmapped.insert(name.to_string());
+                    continue;
+                }
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Contig {} Has No Mapping", name),
+                    ))
+                }
+            }
+        } else {
+            name
+        };
+
+        let mut writer =
+            bgzf::Writer::new(File::create(format!("{}/{}.bed.bgz", out_path, out_name))?);
+        for line in reader.lines() {
+            let line = line?;
+            let (contig, rest) = line.split_once('\t').unwrap_or((&line, ""));
+            match names.get(contig) {
+                Some(new_name) => {
+                    writer.write_all(format!("{}\t{}\n", new_name, rest).as_bytes())?;
+                    report.records += 1;
+                }
+                None if drop_unmapped => {
+                    report.unmapped.insert(contig.to_string());
+                    report.dropped += 1;
+                }
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Contig {} In {} Has No Mapping", contig, file),
+                    ))
+                }
+            }
+        }
+        writer.finish()?;
+    }
+
+    let header_file = format!("{}{}", data_type, SOURCE_HEADER_FILE);
+    if Path::new(&format!("{}/{}", data_path, header_file)).exists() {
+        copy(
+            format!("{}/{}", data_path, header_file),
+            format!("{}/{}", out_path, header_file),
+        )?;
+    }
+
+    // Lines keep their coordinates, so the new assembly's files hold the same kind
+    if beds_half_open(assembly, data_type, data_directory) {
+        File::create(half_open_file(out_assembly, data_type, data_directory))?;
+    }
+
+    write_remapped_json(assembly, &names, out_assembly, data_directory)?;
+
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        match idx::prep_idx(out_assembly, data_type, false, data_directory) {
+            Ok(res) => res,
+            Err(e) => panic!("Index Prep Failed - {:?}", e),
+        };
+    if let Err(e) = idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file) {
+        panic!("Indexing Failed - {:?}", e)
+    }
+    Ok(report)
+}
+
+// The sequences JSON of the new assembly is keyed by the new names with the old name kept
+// as "id", so remapping back with SequenceIds restores the original names. Model lengths
+// don't depend on contig names and are copied as they are.
+fn write_remapped_json(
+    assembly: &str,
+    names: &HashMap<String, String>,
+    out_assembly: &str,
+    data_directory: &str,
+) -> Result<()> {
+    if Path::new(&sequences_file(assembly, data_directory)).exists() {
+        let mut sequences = load_sequences(assembly, data_directory)?;
+        if let Some(data) = sequences.get("data").and_then(|d| d.as_object()) {
+            let mut remapped = Map::new();
+            for (acc, vals) in data {
+                if let Some(new_name) = names.get(acc) {
+                    let mut vals = vals.clone();
+                    vals["id"] = Value::String(acc.to_string());
+                    remapped.insert(new_name.to_string(), vals);
+                }
+            }
+            sequences["data"] = Value::Object(remapped);
+        }
+        create_dir_all(format!(
+            "{}/{}/{}",
+            data_directory, out_assembly, SEQUENCE_DIR
+        ))?;
+        serde_json::to_writer(
+            File::create(sequences_file(out_assembly, data_directory))?,
+            &sequences,
+        )?;
+    }
+
+    let mod_len_file = format!(
+        "{}/{}/{}/{}-{}.json",
+        data_directory, assembly, MOD_LEN_DIR, assembly, MOD_LEN_DIR
+    );
+    if Path::new(&mod_len_file).exists() {
+        create_dir_all(format!(
+            "{}/{}/{}",
+            data_directory, out_assembly, MOD_LEN_DIR
+        ))?;
+        copy(
+            &mod_len_file,
+            format!(
+                "{}/{}/{}/{}-{}.json",
+                data_directory, out_assembly, MOD_LEN_DIR, out_assembly, MOD_LEN_DIR
+            ),
+        )?;
+    }
+    Ok(())
+}
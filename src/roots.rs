@@ -0,0 +1,325 @@
+use serde::Serialize;
+use std::fs::{self, create_dir_all, remove_dir_all, remove_file, rename};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::idx::family_listing_file;
+use crate::package::file_digest;
+use crate::paths::{join_path, stored_path};
+use crate::{DATA_ELEMENTS, HALF_OPEN_FILE, INDEX_DATA_TYPES, SORTED_FILE};
+
+// Several data folders in order of preference, e.g. a hot SSD cache over the cold HDD
+// archive. A query reads the data element it queries from the first root holding it, see
+// resolve_root, so an assembly only in the archive is read from there and a warmed one from
+// the cache. Prep commands write to the root --data-dir names, never to a resolved one.
+pub const DATA_ROOTS_ENV: &str = "TE_IDX_DATA_ROOTS";
+
+// The roots queries try in order: those given, else $TE_IDX_DATA_ROOTS separated as PATH is,
+// else the data directory alone
+pub fn configured_data_roots(data_roots: &[String], data_directory: &str) -> Vec<String> {
+    if !data_roots.is_empty() {
+        return data_roots.to_vec();
+    }
+    let configured: Vec<String> = std::env::var_os(DATA_ROOTS_ENV)
+        .map(|roots| {
+            std::env::split_paths(&roots)
+                .map(|root| root.to_string_lossy().into_owned())
+                .filter(|root| !root.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    match configured.is_empty() {
+        true => vec![data_directory.to_string()],
+        false => configured,
+    }
+}
+
+// Whether a root holds an element of an assembly, or the assembly's folder when element is None
+pub fn root_holds(root: &str, assembly: &str, element: Option<&str>) -> bool {
+    match element {
+        Some(element) => Path::new(&join_path(root, &[assembly, element])).exists(),
+        None => Path::new(&join_path(root, &[assembly])).is_dir(),
+    }
+}
+
+// The first root holding an element of an assembly
+pub fn resolve_root<'r>(
+    roots: &'r [String],
+    assembly: &str,
+    element: Option<&str>,
+) -> Option<&'r String> {
+    roots
+        .iter()
+        .find(|root| root_holds(root, assembly, element))
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WarmedFile {
+    pub path: String, // Below the assembly folder, with "/" between components
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct WarmReport {
+    pub assembly: String,
+    pub from: String,
+    pub to: String,
+    pub data_types: Vec<String>,
+    pub files: Vec<WarmedFile>,
+    pub bytes: u64,
+}
+
+// The marker files, family listing and index of a data type, below the assembly folder. The
+// markers say how its BED files were prepared, see HALF_OPEN_FILE.
+fn install_files(data_type: &str) -> [String; 4] {
+    let index_file = format!("{}_idx.dat", data_type);
+    [
+        format!("{}{}", data_type, SORTED_FILE),
+        format!("{}{}", data_type, HALF_OPEN_FILE),
+        family_listing_file(&index_file),
+        index_file,
+    ]
+}
+
+// Files below dir, relative to it with "/" between components, in name order
+fn files_below(dir: &str) -> Vec<String> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let rel = e.path().strip_prefix(dir).ok()?;
+            let parts: Vec<String> = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            Some(parts.join("/"))
+        })
+        .collect()
+}
+
+// The files and folders of an assembly every query may read, whatever its data type: the
+// consolidated, sequences, model lengths and families JSON, the layout metadata and anything
+// else that is not a data type or its index. Folders are flagged true.
+fn shared_entries(assembly_dir: &str) -> Result<Vec<(String, bool)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(assembly_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.')
+            || INDEX_DATA_TYPES
+                .iter()
+                .any(|data_type| name.starts_with(data_type))
+        {
+            continue;
+        }
+        entries.push((name, entry.file_type()?.is_dir()));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+// Copies a file to the same path below to_dir, through a temporary file renamed into place
+// once its checksum matches the original's. A published index is copied as the versioned
+// file it points at.
+fn copy_checked(from_dir: &str, to_dir: &str, rel: &str) -> Result<WarmedFile> {
+    let (from, to) = (stored_path(from_dir, rel), stored_path(to_dir, rel));
+    if let Some(parent) = Path::new(&to).parent() {
+        create_dir_all(parent)?;
+    }
+    let tmp_file = format!("{}.{}.warming", to, std::process::id());
+    fs::copy(&from, &tmp_file)?;
+    let (sha256, bytes) = file_digest(&from)?;
+    if file_digest(&tmp_file)? != (sha256.clone(), bytes) {
+        remove_file(&tmp_file)?;
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Copy Of {} Differs From The Original", from),
+        ));
+    }
+    rename(&tmp_file, &to)?;
+    Ok(WarmedFile {
+        path: rel.to_string(),
+        bytes,
+        sha256,
+    })
+}
+
+// Copies a folder of the cold assembly folder into a hidden folder of the hot one, then puts it
+// in place of the hot folder of that name. The install files, e.g. the index of a data type,
+// are copied once the old folder is gone, so until the new folder is in place queries fall
+// through to the cold copy instead of reading part of each.
+fn warm_folder(
+    from_dir: &str,
+    to_dir: &str,
+    name: &str,
+    install: &[String],
+    warmed: &mut Vec<WarmedFile>,
+) -> Result<()> {
+    let staging = join_path(
+        to_dir,
+        &[&format!(".{}.{}.warming", name, std::process::id())],
+    );
+    let folder = join_path(from_dir, &[name]);
+    for rel in files_below(&folder) {
+        let mut file = copy_checked(&folder, &staging, &rel)?;
+        file.path = format!("{}/{}", name, rel);
+        warmed.push(file);
+    }
+    create_dir_all(&staging)?;
+    let hot_folder = join_path(to_dir, &[name]);
+    if Path::new(&hot_folder).exists() {
+        remove_dir_all(&hot_folder)?;
+    }
+    for rel in install {
+        if Path::new(&join_path(from_dir, &[rel])).exists() {
+            warmed.push(copy_checked(from_dir, to_dir, rel)?);
+        }
+    }
+    rename(&staging, &hot_folder)
+}
+
+// Copies the query-critical files of an assembly's data types, every indexed type it has when
+// none are given, and its shared files from the first cold root holding the assembly into the
+// hot root, the first of the roots. Each file is checksummed against its original. Folders are
+// swapped in whole, see warm_folder, so it is safe to run while the hot root serves queries.
+// Copies already in the hot root are replaced.
+pub fn warm_assembly(
+    assembly: &str,
+    data_types: &[String],
+    roots: &[String],
+) -> Result<WarmReport> {
+    let Some((hot, cold_roots)) = roots.split_first().filter(|(_, cold)| !cold.is_empty()) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Warming Needs A Hot Root And At Least One Cold Root",
+        ));
+    };
+    let Some(cold) = resolve_root(cold_roots, assembly, None) else {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Assembly {} Not Found In The Cold Roots", assembly),
+        ));
+    };
+    let (from_dir, to_dir) = (join_path(cold, &[assembly]), join_path(hot, &[assembly]));
+    let data_types: Vec<String> = match data_types.is_empty() {
+        true => INDEX_DATA_TYPES
+            .iter()
+            .filter(|data_type| root_holds(cold, assembly, Some(data_type)))
+            .map(|data_type| data_type.to_string())
+            .collect(),
+        false => data_types.to_vec(),
+    };
+    for data_type in &data_types {
+        if !INDEX_DATA_TYPES.contains(&data_type.as_str()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} Is Not An Indexed Data Type, Its Files Are Warmed With Every Assembly",
+                    data_type
+                ),
+            ));
+        }
+        if !root_holds(cold, assembly, Some(data_type)) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} Of {} Not Found In {}", data_type, assembly, cold),
+            ));
+        }
+    }
+
+    let mut report = WarmReport {
+        assembly: assembly.to_string(),
+        from: cold.to_string(),
+        to: hot.to_string(),
+        data_types: data_types.clone(),
+        ..WarmReport::default()
+    };
+    for (name, folder) in shared_entries(&from_dir)? {
+        match folder {
+            true => warm_folder(&from_dir, &to_dir, &name, &[], &mut report.files)?,
+            false => report.files.push(copy_checked(&from_dir, &to_dir, &name)?),
+        }
+    }
+    for data_type in &data_types {
+        let install_files = install_files(data_type);
+        warm_folder(
+            &from_dir,
+            &to_dir,
+            data_type,
+            &install_files,
+            &mut report.files,
+        )?;
+    }
+    report.bytes = report.files.iter().map(|file| file.bytes).sum();
+    Ok(report)
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ElementStatus {
+    pub element: String,
+    pub roots: Vec<String>,          // Roots holding it, in order
+    pub served_from: Option<String>, // The first of them, the copy queries read
+    pub in_sync: Option<bool>, // Every copy has the same files of the same sizes, None for one copy
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheStatus {
+    pub assembly: String,
+    pub roots: Vec<String>,
+    pub elements: Vec<ElementStatus>,
+}
+
+// The files of an element in a root with their sizes, its index and marker files included
+fn element_listing(root: &str, assembly: &str, element: &str) -> Vec<(String, Option<u64>)> {
+    let assembly_dir = join_path(root, &[assembly]);
+    let mut files = files_below(&join_path(&assembly_dir, &[element]));
+    if INDEX_DATA_TYPES.contains(&element) {
+        files.extend(install_files(element));
+    }
+    files
+        .into_iter()
+        .map(|rel| {
+            let bytes = fs::metadata(stored_path(&assembly_dir, &rel)).ok();
+            (rel, bytes.map(|metadata| metadata.len()))
+        })
+        .collect()
+}
+
+// Which roots hold each data element of an assembly and the copy queries read. Copies are
+// compared by their files and sizes, not their checksums, a copy out of sync is a stale one.
+pub fn cache_status(assembly: &str, roots: &[String]) -> Result<CacheStatus> {
+    let mut elements = Vec::new();
+    for element in DATA_ELEMENTS {
+        let holding: Vec<String> = roots
+            .iter()
+            .filter(|root| root_holds(root, assembly, Some(element)))
+            .cloned()
+            .collect();
+        let in_sync = match holding.len() {
+            0 | 1 => None,
+            _ => {
+                let served = element_listing(&holding[0], assembly, element);
+                Some(
+                    holding[1..]
+                        .iter()
+                        .all(|root| element_listing(root, assembly, element) == served),
+                )
+            }
+        };
+        elements.push(ElementStatus {
+            element: element.to_string(),
+            served_from: holding.first().cloned(),
+            roots: holding,
+            in_sync,
+        });
+    }
+    Ok(CacheStatus {
+        assembly: assembly.to_string(),
+        roots: roots.to_vec(),
+        elements,
+    })
+}
@@ -0,0 +1,212 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::allowlist::check_assembly;
+use crate::idx::{self, bed_fields};
+use crate::paths::{join_path, path_string, stored_path};
+use crate::records::{bed_extent, BedRecordStream};
+use crate::{
+    misaligned_columns, output_columns, seq_len_column, AssemblyData, ASSEMBLY_DATA_FILE,
+    ASSEMBLY_DIR, BENCHMARK_DIR, SEQUENCE_DIR, SEQUENCE_FILE,
+};
+
+// Random samples of the records of a data type for QC, drawn through the index so only the
+// tiles and records picked are read, see idx::sample_ranges. The same seed draws the same
+// records from the same index.
+pub const DEFAULT_SAMPLE_SIZE: usize = 1000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SampledRecord {
+    pub contig: String,
+    pub file: String,
+    pub virtual_position: u64,
+    pub record: Map<String, Value>, // Fields named by the data type's columns, as peek-record
+    pub raw: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SampleFailure {
+    pub contig: String,
+    pub file: String,
+    pub virtual_position: u64,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SampleValidation {
+    pub checked: usize,
+    pub passed: usize,
+    pub pass_rate: f64,          // 1 for an empty sample
+    pub sequences_checked: bool, // False when the assembly has no sequences JSON
+    pub failures: Vec<SampleFailure>,
+}
+
+// Up to n records of a data type drawn uniformly at random with the seed, in index order
+pub fn sample_records(
+    assembly: &str,
+    data_type: &str,
+    n: usize,
+    seed: u64,
+    data_directory: impl AsRef<Path>,
+) -> Result<Vec<SampledRecord>> {
+    let data_directory = &path_string(data_directory);
+    check_assembly(assembly, Some(data_type), data_directory)?;
+    let (bgz_dir, index_file) = idx::idx_paths(assembly, data_type, data_directory);
+    if !Path::new(&index_file).exists() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Assembly \"{}\" Is Not Indexed For {}", assembly, data_type),
+        ));
+    }
+    let ranges = idx::sample_ranges(assembly, data_type, n, seed, data_directory)
+        .map_err(|e| Error::other(e.to_string()))?;
+
+    let columns = output_columns(data_type, false);
+    let mut readers = HashMap::new();
+    let mut records = Vec::new();
+    for range in ranges {
+        let reader = match readers.entry(range.file.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(BedRecordStream::open(&stored_path(&bgz_dir, &range.file))?)
+            }
+        };
+        reader.seek(range.bgzf_pos)?;
+        let Some(record) = reader.read_record()? else {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "No Record At Virtual Position {} Of {}",
+                    range.bgzf_pos, range.file
+                ),
+            ));
+        };
+        let raw = record.line.trim_end_matches(['\n', '\r']).to_string();
+        let fields = columns
+            .iter()
+            .zip(bed_fields(&raw))
+            .map(|(col, val)| (col.trim_start_matches('*').to_string(), json!(val)))
+            .collect();
+        records.push(SampledRecord {
+            contig: range.contig,
+            file: range.file,
+            virtual_position: range.bgzf_pos,
+            record: fields,
+            raw,
+        });
+    }
+    Ok(records)
+}
+
+// Whether a CIGAR string is made of counted operations only, e.g. 10M2I5M. Empty is valid,
+// not every record has an alignment.
+pub fn cigar_parses(cigar: &str) -> bool {
+    let mut count = false;
+    for c in cigar.chars() {
+        match c {
+            '0'..='9' => count = true,
+            'M' | 'I' | 'D' | 'N' | 'S' | 'H' | 'P' | '=' | 'X' if count => count = false,
+            _ => return false,
+        }
+    }
+    !count
+}
+
+// Why a sampled record fails the basic checks, empty when it passes: its columns line up, its
+// start and end are BED coordinates within the sequence length the record gives, its CIGAR
+// parses, and its contig is a sequence of the sequences JSON when sequences is given
+fn record_problems(
+    record: &SampledRecord,
+    data_type: &str,
+    sequences: Option<&Value>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let fields = bed_fields(&record.raw);
+    if let Some(reason) = misaligned_columns(&fields, data_type) {
+        problems.push(format!("Misaligned Columns - {}", reason));
+    }
+    let coordinate = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+    match bed_extent(&fields) {
+        Some((_, high)) => {
+            if let Some(seq_len) = seq_len_column(data_type).and_then(coordinate) {
+                if high > seq_len {
+                    problems.push(format!(
+                        "Ends At {} Past The Sequence Length {}",
+                        high, seq_len
+                    ));
+                }
+            }
+        }
+        None => problems.push(format!(
+            "Invalid Coordinates {}-{}",
+            fields.get(1).unwrap_or(&""),
+            fields.get(2).unwrap_or(&"")
+        )),
+    }
+    let cigar = match data_type {
+        ASSEMBLY_DIR => fields.get(16),
+        BENCHMARK_DIR => fields.get(14),
+        _ => None,
+    };
+    if let Some(cigar) = cigar.filter(|cigar| !cigar_parses(cigar)) {
+        problems.push(format!("Unparsable CIGAR \"{}\"", cigar));
+    }
+    if sequences.is_some_and(|sequences| sequences.get(&record.contig).is_none()) {
+        problems.push(format!("Sequence {} Not Found", record.contig));
+    }
+    problems
+}
+
+// Runs the basic record checks on a sample and reports its pass rate. Contigs are checked
+// against the sequences JSON only when the assembly has one.
+pub fn validate_sample(
+    assembly: &str,
+    data_type: &str,
+    records: &[SampledRecord],
+    data_directory: impl AsRef<Path>,
+) -> Result<SampleValidation> {
+    let data_directory = &path_string(data_directory);
+    let sequences_file = join_path(
+        data_directory,
+        &[
+            assembly,
+            SEQUENCE_DIR,
+            &format!("{}{}", assembly, SEQUENCE_FILE),
+        ],
+    );
+    let consolidated_file = join_path(data_directory, &[assembly, ASSEMBLY_DATA_FILE]);
+    let assembly_data =
+        match Path::new(&sequences_file).exists() || Path::new(&consolidated_file).exists() {
+            true => Some(AssemblyData::load(assembly, data_directory)?),
+            false => None,
+        };
+    let sequences = assembly_data.as_ref().map(|data| data.data(SEQUENCE_DIR));
+
+    let mut validation = SampleValidation {
+        checked: records.len(),
+        sequences_checked: sequences.is_some(),
+        ..SampleValidation::default()
+    };
+    for record in records {
+        let reasons = record_problems(record, data_type, sequences);
+        if reasons.is_empty() {
+            validation.passed += 1;
+            continue;
+        }
+        validation.failures.push(SampleFailure {
+            contig: record.contig.clone(),
+            file: record.file.clone(),
+            virtual_position: record.virtual_position,
+            reasons,
+        });
+    }
+    validation.pass_rate = match validation.checked {
+        0 => 1.0,
+        checked => validation.passed as f64 / checked as f64,
+    };
+    Ok(validation)
+}
@@ -0,0 +1,344 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs::create_dir_all;
+use std::io::{BufRead, Result};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use tempfile::TempDir;
+
+use crate::backpressure::configured_max_buffered_bytes;
+use crate::fixtures::{synthetic_assembly, write_synthetic_export, SyntheticAssembly};
+use crate::options::IdxQueryOptions;
+use crate::{
+    configured_scratch_dir, get_chrom_id, idx_query_with, json_query, prepare_assembly,
+    read_family_assembly_annotations, ASSEMBLY_DIR, MASKS_DIR, MOD_LEN_DIR, SEQUENCE_DIR,
+};
+
+type Check = std::result::Result<String, String>;
+
+// Runs one check, reporting a panic inside the library as a failure of that check
+fn run_check(name: &str, check: impl FnOnce() -> Check) -> bool {
+    match catch_unwind(AssertUnwindSafe(check)) {
+        Ok(Ok(detail)) => {
+            println!("PASS  {} - {}", name, detail);
+            true
+        }
+        Ok(Err(detail)) => {
+            println!("FAIL  {} - {}", name, detail);
+            false
+        }
+        Err(_) => {
+            println!("FAIL  {} - panicked", name);
+            false
+        }
+    }
+}
+
+fn compare<T: Ord + std::fmt::Debug>(expected: BTreeSet<T>, found: BTreeSet<T>) -> Check {
+    if expected == found {
+        Ok(format!("{} records", found.len()))
+    } else {
+        Err(format!(
+            "expected {} records, got {} ({} missing, {} unexpected)",
+            expected.len(),
+            found.len(),
+            expected.difference(&found).count(),
+            found.difference(&expected).count()
+        ))
+    }
+}
+
+fn hit_keys(json: &str, keys: [&str; 3]) -> BTreeSet<(String, u64, u64)> {
+    let hits: Vec<Value> = serde_json::from_str(json).expect("Cannot Deserialize Query Result");
+    hits.iter()
+        .map(|hit| {
+            (
+                hit[keys[0]].as_str().unwrap_or_default().to_string(),
+                hit[keys[1]].as_u64().unwrap_or_default(),
+                hit[keys[2]].as_u64().unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+fn region_check(
+    synth: &SyntheticAssembly,
+    data_directory: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+    family: Option<&str>,
+    nrph: bool,
+) -> Check {
+    let family = family.map(|f| f.to_string());
+    let expected = synth
+        .hits
+        .iter()
+        .filter(|h| h.seq_acc == chrom && h.overlaps(start, end))
+        .filter(|h| family.is_none() || family.as_ref() == Some(&h.fam_acc))
+        .filter(|h| !nrph || h.nrph)
+        .map(|h| (h.fam_acc.clone(), h.seq_start, h.seq_end))
+        .collect();
+    let found = idx_query_with(
+        &synth.assembly,
+        ASSEMBLY_DIR,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions {
+            family: family.map(|f| vec![f]),
+            nrph,
+            ..IdxQueryOptions::default()
+        },
+        data_directory,
+    )
+    .map_err(|e| e.to_string())?;
+    compare(
+        expected,
+        hit_keys(&found, ["accession", "seq_start", "seq_end"]),
+    )
+}
+
+fn masks_check(
+    synth: &SyntheticAssembly,
+    data_directory: &str,
+    chrom: &str,
+    start: u64,
+    end: u64,
+) -> Check {
+    let expected = synth
+        .masks
+        .iter()
+        .filter(|m| m.seq_acc == chrom && m.overlaps(start, end))
+        .map(|m| (m.repeat_str.clone(), m.seq_start, m.seq_end))
+        .collect();
+    let found = idx_query_with(
+        &synth.assembly,
+        MASKS_DIR,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions::default(),
+        data_directory,
+    )
+    .map_err(|e| e.to_string())?;
+    compare(
+        expected,
+        hit_keys(&found, ["repeat_str", "seq_start", "seq_end"]),
+    )
+}
+
+fn family_file_check(
+    synth: &SyntheticAssembly,
+    data_directory: &str,
+    fam: &str,
+    nrph: bool,
+) -> Check {
+    let expected = synth
+        .hits
+        .iter()
+        .filter(|h| h.fam_acc == fam && (!nrph || h.nrph))
+        .count();
+    let mut output = Vec::new();
+    read_family_assembly_annotations(
+        fam,
+        &synth.assembly,
+        &nrph,
+        &mut output,
+        &false,
+        data_directory,
+    )
+    .map_err(|e| e.to_string())?;
+    let mut found = 0;
+    for line in output.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if !line.is_empty() && !line.starts_with('#') {
+            found += 1;
+        }
+    }
+    if found == expected {
+        Ok(format!("{} records", found))
+    } else {
+        Err(format!("expected {} records, got {}", expected, found))
+    }
+}
+
+fn json_check(
+    synth: &SyntheticAssembly,
+    data_directory: &str,
+    data_type: &str,
+    key: &str,
+    target: Option<&str>,
+    expected: String,
+) -> Check {
+    let found = json_query(
+        &synth.assembly,
+        data_type,
+        key,
+        &target.map(|t| t.to_string()),
+        &None,
+        data_directory,
+    )
+    .map_err(|e| e.to_string())?;
+    if found == expected {
+        Ok(format!("{} = {}", key, found))
+    } else {
+        Err(format!("{}: expected {}, got {}", key, expected, found))
+    }
+}
+
+// Builds a small synthetic assembly in a temp dir, prepares and indexes it, and checks a
+// battery of queries against expectations computed from the generated records. Prints
+// PASS/FAIL per check and returns whether every check passed.
+pub fn self_test(assembly: &str, keep: bool, scratch_directory: &Option<String>) -> Result<bool> {
+    // There is no data directory yet, so without a scratch directory this uses the system's
+    let working_dir = match configured_scratch_dir(scratch_directory) {
+        Some(dir) => TempDir::new_in(dir)?,
+        None => TempDir::new()?,
+    };
+    let export_directory = format!("{}/exports", working_dir.path().display());
+    let data_directory = format!("{}/data", working_dir.path().display());
+    create_dir_all(&export_directory)?;
+    create_dir_all(&data_directory)?;
+
+    let synth = synthetic_assembly(assembly);
+    write_synthetic_export(&synth, &export_directory)?;
+    println!(
+        "Generated {} hits and {} masks over {} contigs",
+        synth.hits.len(),
+        synth.masks.len(),
+        synth.contigs.len()
+    );
+
+    let mut results = vec![run_check("prepare-assembly", || {
+        prepare_assembly(
+            assembly,
+            &data_directory,
+            &export_directory,
+            false,
+            &None,
+            scratch_directory,
+            configured_max_buffered_bytes(None),
+            &None,
+            false,
+        )
+        .map(|_| "prepared".to_string())
+        .map_err(|e| e.to_string())
+    })];
+
+    let (chrom, chrom_len) = synth.contigs[0].clone();
+    let (other_chrom, other_len) = synth.contigs[1].clone();
+    let (fam, _, fam_len) = synth.families[1].clone();
+    results.push(run_check("region", || {
+        region_check(&synth, &data_directory, &chrom, 20000, 60000, None, false)
+    }));
+    results.push(run_check("region (single tile)", || {
+        region_check(
+            &synth,
+            &data_directory,
+            &other_chrom,
+            1000,
+            5000,
+            None,
+            false,
+        )
+    }));
+    results.push(run_check("family filter", || {
+        region_check(
+            &synth,
+            &data_directory,
+            &chrom,
+            1,
+            chrom_len,
+            Some(&fam),
+            false,
+        )
+    }));
+    results.push(run_check("nrph filter", || {
+        region_check(
+            &synth,
+            &data_directory,
+            &other_chrom,
+            10000,
+            90000,
+            None,
+            true,
+        )
+    }));
+    results.push(run_check("masks region", || {
+        masks_check(&synth, &data_directory, &chrom, 1, chrom_len)
+    }));
+    results.push(run_check("family annotations", || {
+        family_file_check(&synth, &data_directory, &fam, false)
+    }));
+    results.push(run_check("family annotations (nrph)", || {
+        family_file_check(&synth, &data_directory, &fam, true)
+    }));
+    results.push(run_check("model length", || {
+        json_check(
+            &synth,
+            &data_directory,
+            MOD_LEN_DIR,
+            &fam,
+            Some("length"),
+            fam_len.to_string(),
+        )
+    }));
+    results.push(run_check("chrom id", || {
+        let found = get_chrom_id(&synth.assembly, &other_chrom, &data_directory)
+            .map_err(|e| e.to_string())?;
+        if found == other_chrom {
+            Ok(format!("{} = {}", other_chrom, found))
+        } else {
+            Err(format!(
+                "{}: expected {}, got {}",
+                other_chrom, other_chrom, found
+            ))
+        }
+    }));
+    results.push(run_check("sequence length", || {
+        json_check(
+            &synth,
+            &data_directory,
+            SEQUENCE_DIR,
+            &other_chrom,
+            Some("length"),
+            other_len.to_string(),
+        )
+    }));
+    results.push(run_check("sequence lookup", || {
+        json_check(
+            &synth,
+            &data_directory,
+            SEQUENCE_DIR,
+            &chrom,
+            None,
+            "1".to_string(),
+        )
+    }));
+    results.push(run_check("missing sequence lookup", || {
+        json_check(
+            &synth,
+            &data_directory,
+            SEQUENCE_DIR,
+            "chrUn_missing",
+            None,
+            "-1".to_string(),
+        )
+    }));
+
+    let passed = results.iter().filter(|r| **r).count();
+    let success = passed == results.len();
+    println!(
+        "Self-Test {}: {}/{} Checks Passed",
+        if success { "PASSED" } else { "FAILED" },
+        passed,
+        results.len()
+    );
+
+    if keep {
+        println!("Kept Self-Test Data In {}", working_dir.keep().display());
+    } else {
+        working_dir.close()?;
+    }
+    Ok(success)
+}
@@ -0,0 +1,250 @@
+use noodles::bgzf;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+use crate::contig_order::load_sequences;
+use crate::idx::{self, bed_fields};
+use crate::paths::{join_path, path_string, stored_path};
+use crate::records::BedRecordStream;
+use crate::sample::sample_records;
+use crate::{lock_bgz_file, remove_family_listing, seq_len_column};
+
+// Records whose seq_len column disagrees with the length the sequences JSON gives their
+// contig, as when the BED files of one patch level of an assembly are prepared beside the
+// sequences JSON of another. Coverage computed from seq_len is then wrong for every record of
+// the contig, so the column is checked against the JSON and can be rewritten from it.
+pub const DEFAULT_SEQ_LEN_EXAMPLES: usize = 5;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SeqLenExample {
+    pub file: String,
+    pub stored: String, // The seq_len column as the record has it
+    pub raw: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ContigSeqLens {
+    pub contig: String,
+    pub json_length: u64,
+    pub mismatched: usize,
+    pub examples: Vec<SeqLenExample>, // The first few of its mismatched records
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SeqLenCheck {
+    pub assembly: String,
+    pub data_type: String,
+    pub sampled: bool, // Only a sample of the records was checked, see sample::sample_records
+    pub checked: usize,
+    pub mismatched: usize,
+    pub unknown: usize, // Records of contigs the sequences JSON gives no length, not compared
+    pub contigs: Vec<ContigSeqLens>, // Contigs with mismatched records, by name
+    pub repaired: Vec<String>, // Files rewritten with the JSON lengths, by repair_seq_lens
+    pub records: usize, // Records rewritten
+    pub indexed: bool,  // Whether the index was rebuilt
+}
+
+// The length of each sequence of the assembly's sequences JSON that gives one
+pub fn sequence_lengths(
+    assembly: &str,
+    data_directory: impl AsRef<Path>,
+) -> Result<HashMap<String, u64>> {
+    let data_directory = &path_string(data_directory);
+    let assembly_data = load_sequences(assembly, data_directory)?;
+    Ok(assembly_data
+        .sequence_info()
+        .iter()
+        .filter_map(|(acc, info)| Some((acc.clone(), info.length?)))
+        .collect())
+}
+
+fn checked_column(data_type: &str) -> Result<usize> {
+    seq_len_column(data_type).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Records Of {} Have No seq_len Column", data_type),
+        )
+    })
+}
+
+// The JSON length of a record's contig when its seq_len column holds another, None when they
+// agree or the JSON has none, counted in unknown
+fn mismatch(
+    fields: &[&str],
+    column: usize,
+    lengths: &HashMap<String, u64>,
+    check: &mut SeqLenCheck,
+) -> Option<u64> {
+    check.checked += 1;
+    let Some(length) = lengths.get(fields[0]) else {
+        check.unknown += 1;
+        return None;
+    };
+    let stored = fields.get(column).and_then(|f| f.parse::<u64>().ok());
+    (stored != Some(*length)).then_some(*length)
+}
+
+// Compares the seq_len column of a data type's records with the lengths of the sequences JSON,
+// of every record, or of a sample of n drawn with the seed as sample-records draws them.
+// Mismatches are counted per contig with the first few records as examples.
+pub fn check_seq_lens(
+    assembly: &str,
+    data_type: &str,
+    sample: Option<(usize, u64)>,
+    examples: usize,
+    data_directory: impl AsRef<Path>,
+) -> Result<SeqLenCheck> {
+    let data_directory = &path_string(data_directory);
+    let column = checked_column(data_type)?;
+    let bgz_dir = join_path(data_directory, &[assembly, data_type]);
+    if !Path::new(&bgz_dir).is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("Data \"{}\" Does Not Exist", bgz_dir),
+        ));
+    }
+    let lengths = sequence_lengths(assembly, data_directory)?;
+    let mut check = SeqLenCheck {
+        assembly: assembly.to_string(),
+        data_type: data_type.to_string(),
+        sampled: sample.is_some(),
+        ..SeqLenCheck::default()
+    };
+    let mut contigs: BTreeMap<String, ContigSeqLens> = BTreeMap::new();
+    let mut record = |file: &str, raw: &str, check: &mut SeqLenCheck| {
+        let fields = bed_fields(raw);
+        let Some(json_length) = mismatch(&fields, column, &lengths, check) else {
+            return;
+        };
+        check.mismatched += 1;
+        let contig = contigs
+            .entry(fields[0].to_string())
+            .or_insert_with(|| ContigSeqLens {
+                contig: fields[0].to_string(),
+                json_length,
+                mismatched: 0,
+                examples: Vec::new(),
+            });
+        contig.mismatched += 1;
+        if contig.examples.len() < examples {
+            contig.examples.push(SeqLenExample {
+                file: file.to_string(),
+                stored: fields.get(column).unwrap_or(&"").to_string(),
+                raw: raw.to_string(),
+            });
+        }
+    };
+    match sample {
+        Some((n, seed)) => {
+            for sampled in sample_records(assembly, data_type, n, seed, data_directory)? {
+                record(&sampled.file, &sampled.raw, &mut check);
+            }
+        }
+        None => {
+            let mut names = idx::stored_bgz_names(&bgz_dir)?;
+            names.sort();
+            for name in names {
+                for bed_record in BedRecordStream::open(&stored_path(&bgz_dir, &name))? {
+                    let bed_record = bed_record?;
+                    let raw = bed_record.line.trim_end_matches(['\n', '\r']);
+                    if raw.starts_with('#') || raw.trim().is_empty() {
+                        continue;
+                    }
+                    record(&name, raw, &mut check);
+                }
+            }
+        }
+    }
+    check.contigs = contigs.into_values().collect();
+    Ok(check)
+}
+
+// Rewrites the seq_len column of every record that disagrees with the sequences JSON from the
+// JSON, after checking every record, and rebuilds the index when any file was rewritten. Each
+// file is rewritten whole under the append lock and replaces the old one by a rename, records
+// and comment lines otherwise kept as they are. Records of contigs the JSON has no length for
+// are left alone.
+pub fn repair_seq_lens(
+    assembly: &str,
+    data_type: &str,
+    examples: usize,
+    data_directory: impl AsRef<Path>,
+) -> Result<SeqLenCheck> {
+    let data_directory = &path_string(data_directory);
+    let column = checked_column(data_type)?;
+    let mut check = check_seq_lens(assembly, data_type, None, examples, data_directory)?;
+    if check.mismatched == 0 {
+        return Ok(check);
+    }
+    let lengths = sequence_lengths(assembly, data_directory)?;
+    let bgz_dir = join_path(data_directory, &[assembly, data_type]);
+    let mut names = idx::stored_bgz_names(&bgz_dir)?;
+    names.sort();
+    for name in names {
+        let path = stored_path(&bgz_dir, &name);
+        let lock = lock_bgz_file(&path)?;
+        let rewritten = rewrite_seq_lens(&path, column, &lengths);
+        drop(lock);
+        let rewritten = rewritten?;
+        if rewritten > 0 {
+            check.records += rewritten;
+            check.repaired.push(name);
+        }
+    }
+
+    // Rewritten records moved the ones after them, the index is rebuilt to find them
+    remove_family_listing(assembly, data_type, data_directory)?;
+    let (_, index_file) = idx::idx_paths(assembly, data_type, data_directory);
+    if Path::new(&index_file).exists() {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            idx::prep_idx(assembly, data_type, false, data_directory)
+                .map_err(|e| Error::other(e.to_string()))?;
+        idx::build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+            .map_err(|e| Error::other(e.to_string()))?;
+        check.indexed = true;
+    }
+    Ok(check)
+}
+
+// Rewrites one BED file with the JSON lengths in the seq_len column, returning the records
+// changed. A file with none is left as it is.
+fn rewrite_seq_lens(path: &str, column: usize, lengths: &HashMap<String, u64>) -> Result<usize> {
+    let dir = Path::new(path).parent().unwrap();
+    let mut out_f = tempfile::NamedTempFile::new_in(dir)?;
+    // Temp files are private, keep the permissions of the file being replaced
+    out_f
+        .as_file()
+        .set_permissions(fs::metadata(path)?.permissions())?;
+    let mut writer = bgzf::Writer::new(out_f.as_file_mut());
+    let reader = BufReader::new(bgzf::Reader::new(File::open(path)?));
+    let mut rewritten = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = bed_fields(&line);
+        let length = match lengths.get(fields[0]) {
+            Some(length) if !line.starts_with('#') && fields.len() > column => *length,
+            _ => {
+                writeln!(writer, "{}", line)?;
+                continue;
+            }
+        };
+        if fields[column].parse::<u64>().ok() == Some(length) {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let length = length.to_string();
+        fields[column] = &length;
+        writeln!(writer, "{}", fields.join("\t"))?;
+        rewritten += 1;
+    }
+    writer.finish()?;
+    if rewritten == 0 {
+        return Ok(0);
+    }
+    out_f.as_file().sync_all()?;
+    out_f.persist(path).map_err(|e| e.error)?;
+    Ok(rewritten)
+}
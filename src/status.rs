@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{rename, File};
+use std::io::{Result, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::idx::BuildProgress;
+use crate::querylog::utc_timestamp;
+
+// Progress of a long operation for workflow engines to poll, kept in a JSON file set by
+// --status-file. The file is rewritten whole, through a temporary file renamed over it, on
+// every phase change and otherwise every HEARTBEAT_SECS while the operation runs, so a reader
+// never sees a partial file and one whose updated_at is several heartbeats old belongs to a
+// process that died. The last write has state completed or failed.
+pub const HEARTBEAT_SECS: u64 = 2;
+pub const STATE_RUNNING: &str = "running";
+pub const STATE_COMPLETED: &str = "completed";
+pub const STATE_FAILED: &str = "failed";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OperationStatus {
+    pub operation: String,
+    pub state: String,
+    pub phase: String,
+    pub percent: Option<f64>, // Of the current phase, None while its total is unknown
+    pub done: u64,
+    pub total: Option<u64>,
+    pub unit: String, // What done and total count
+    pub pid: u32,
+    pub started_at: String,
+    pub updated_at: String,
+    pub heartbeat_secs: u64,
+    pub finished_at: Option<String>,
+    pub last_error: Option<String>,
+}
+
+struct StatusState {
+    phase: String,
+    total: Option<u64>,
+    unit: String,
+    state: &'static str,
+    finished_at: Option<String>,
+    last_error: Option<String>,
+    last_write: Instant,
+}
+
+struct StatusInner {
+    path: String,
+    operation: String,
+    started_at: String,
+    // Counted without the lock, progress is reported for every record of some phases
+    done: AtomicU64,
+    state: Mutex<StatusState>,
+}
+
+// A handle to a status file, cloned into whatever reports progress. The file is marked failed
+// if the last handle is dropped before finish, as when a panic unwinds past it.
+#[derive(Clone)]
+pub struct StatusFile {
+    inner: Arc<StatusInner>,
+}
+
+impl fmt::Debug for StatusFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StatusFile({})", self.inner.path)
+    }
+}
+
+impl StatusInner {
+    // Writes the status, called with the state locked so writes never interleave
+    fn write(&self, state: &mut StatusState) -> Result<()> {
+        let done = self.done.load(Ordering::Relaxed);
+        let status = OperationStatus {
+            operation: self.operation.clone(),
+            state: state.state.to_string(),
+            phase: state.phase.clone(),
+            percent: state
+                .total
+                .filter(|total| *total > 0)
+                .map(|total| (done.min(total) as f64 * 1000.0 / total as f64).round() / 10.0),
+            done,
+            total: state.total,
+            unit: state.unit.clone(),
+            pid: std::process::id(),
+            started_at: self.started_at.clone(),
+            updated_at: utc_timestamp(SystemTime::now()),
+            heartbeat_secs: HEARTBEAT_SECS,
+            finished_at: state.finished_at.clone(),
+            last_error: state.last_error.clone(),
+        };
+        let tmp_file = format!("{}.{}.tmp", self.path, std::process::id());
+        let mut out = File::create(&tmp_file)?;
+        serde_json::to_writer_pretty(&mut out, &status)?;
+        out.write_all(b"\n")?;
+        drop(out);
+        rename(&tmp_file, &self.path)?;
+        state.last_write = Instant::now();
+        Ok(())
+    }
+
+    fn write_or_warn(&self, state: &mut StatusState) {
+        if let Err(e) = self.write(state) {
+            eprintln!("Warning: Could Not Write Status File {} - {}", self.path, e);
+        }
+    }
+}
+
+impl Drop for StatusInner {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|e| e.into_inner());
+        let mut state = std::mem::replace(state, StatusState::new());
+        if state.state == STATE_RUNNING {
+            state.state = STATE_FAILED;
+            state.finished_at = Some(utc_timestamp(SystemTime::now()));
+            state.last_error = Some(match thread::panicking() {
+                true => "Panicked".to_string(),
+                false => "Stopped Before Finishing".to_string(),
+            });
+            self.write_or_warn(&mut state);
+        }
+    }
+}
+
+impl StatusState {
+    fn new() -> StatusState {
+        StatusState {
+            phase: String::new(),
+            total: None,
+            unit: String::new(),
+            state: STATE_RUNNING,
+            finished_at: None,
+            last_error: None,
+            last_write: Instant::now(),
+        }
+    }
+}
+
+impl StatusFile {
+    // Writes the running status of operation to path and starts the heartbeat
+    pub fn start(path: &str, operation: &str) -> Result<StatusFile> {
+        let inner = Arc::new(StatusInner {
+            path: path.to_string(),
+            operation: operation.to_string(),
+            started_at: utc_timestamp(SystemTime::now()),
+            done: AtomicU64::new(0),
+            state: Mutex::new(StatusState::new()),
+        });
+        inner.write(&mut inner.state.lock().unwrap())?;
+        // Holds the status weakly, so dropping the last handle ends it
+        let heartbeat = Arc::downgrade(&inner);
+        thread::spawn(move || heartbeat_loop(heartbeat));
+        Ok(StatusFile { inner })
+    }
+
+    pub fn path(&self) -> &String {
+        &self.inner.path
+    }
+
+    // Starts a phase of total units, if known, and writes it
+    pub fn phase(&self, phase: &str, total: Option<u64>, unit: &str) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.phase = phase.to_string();
+        state.total = total;
+        state.unit = unit.to_string();
+        self.inner.done.store(0, Ordering::Relaxed);
+        self.inner.write_or_warn(&mut state);
+    }
+
+    // Units of the current phase done so far, written with the next heartbeat
+    pub fn progress(&self, done: u64) {
+        self.inner.done.store(done, Ordering::Relaxed);
+    }
+
+    // Reports the files build_idx has indexed as progress
+    pub fn build_progress(&self) -> BuildProgress {
+        let status = self.clone();
+        BuildProgress(Arc::new(move |done| status.progress(done)))
+    }
+
+    // Writes the terminal state, completed or failed with the error
+    pub fn finish<T, E: fmt::Display>(&self, result: &std::result::Result<T, E>) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.state != STATE_RUNNING {
+            return;
+        }
+        match result {
+            Ok(_) => {
+                state.state = STATE_COMPLETED;
+                if let Some(total) = state.total {
+                    self.inner.done.store(total, Ordering::Relaxed);
+                }
+            }
+            Err(e) => {
+                state.state = STATE_FAILED;
+                state.last_error = Some(e.to_string());
+            }
+        }
+        state.finished_at = Some(utc_timestamp(SystemTime::now()));
+        self.inner.write_or_warn(&mut state);
+    }
+}
+
+fn heartbeat_loop(status: Weak<StatusInner>) {
+    let interval = Duration::from_secs(HEARTBEAT_SECS);
+    loop {
+        thread::sleep(Duration::from_millis(200));
+        let Some(inner) = status.upgrade() else {
+            return;
+        };
+        let mut state = inner.state.lock().unwrap();
+        if state.state != STATE_RUNNING {
+            return;
+        }
+        if state.last_write.elapsed() >= interval {
+            inner.write_or_warn(&mut state);
+        }
+    }
+}
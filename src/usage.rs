@@ -0,0 +1,194 @@
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::idx::{index_range_count, FAMILY_LISTING_SUFFIX};
+use crate::paths::path_string;
+use crate::{list_assemblies, DATA_ELEMENTS, HALF_OPEN_FILE, INDEX_DATA_TYPES, SORTED_FILE};
+
+// Disk usage of prepared assemblies, for capacity planning. Files are sized without following
+// symlinks, so a current index is counted once and not again through its link. Assemblies
+// left out of the allowlist take disk space all the same and are included.
+
+// Files of an assembly folder that are neither in a data type folder nor an index of one:
+// assembly_data.json, the source header, the layout version
+pub const OTHER_ELEMENT: &str = "other";
+
+pub const USAGE_TSV_HEADER: &str = "#assembly\telement\tfiles\tbytes\tranges";
+// Assembly column of the rows summed over every assembly of the report
+pub const USAGE_TOTAL: &str = "*";
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ElementUsage {
+    pub element: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub ranges: Option<u64>, // Ranges in the current index, indexed data types only
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct AssemblyUsage {
+    pub assembly: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub elements: Vec<ElementUsage>, // Largest first
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct UsageReport {
+    pub files: u64,
+    pub bytes: u64,
+    pub elements: Vec<ElementUsage>, // Summed over the assemblies, largest first
+    pub assemblies: Vec<AssemblyUsage>, // Largest first
+}
+
+impl UsageReport {
+    // One line per element of each assembly, then the assembly total. The report totals
+    // follow as USAGE_TOTAL rows. Ranges are "-" for elements without an index.
+    pub fn tsv_lines(&self) -> Vec<String> {
+        let mut lines = vec![USAGE_TSV_HEADER.to_string()];
+        let rows = self
+            .assemblies
+            .iter()
+            .map(|a| (a.assembly.as_str(), &a.elements, a.files, a.bytes))
+            .chain([(USAGE_TOTAL, &self.elements, self.files, self.bytes)]);
+        for (assembly, elements, files, bytes) in rows {
+            for element in elements {
+                lines.push(format!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    assembly,
+                    element.element,
+                    element.files,
+                    element.bytes,
+                    element.ranges.map_or("-".to_string(), |r| r.to_string())
+                ));
+            }
+            lines.push(format!("{}\ttotal\t{}\t{}\t-", assembly, files, bytes));
+        }
+        lines
+    }
+}
+
+// The element a file of an assembly folder counts towards, from its path in the folder
+fn file_element(relative: &Path) -> String {
+    let mut components = relative.components();
+    let first = components
+        .next()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .unwrap_or_default();
+    let nested = components.next().is_some();
+    if nested && DATA_ELEMENTS.contains(&first.as_str()) {
+        return first;
+    }
+    // Superseded <data_type>_idx.<timestamp>.dat files count with the current index, as do
+    // its family listing and the marker files of the data type
+    if !nested {
+        for data_type in INDEX_DATA_TYPES {
+            if first.starts_with(&format!("{}_idx.", data_type))
+                || [FAMILY_LISTING_SUFFIX, SORTED_FILE, HALF_OPEN_FILE]
+                    .iter()
+                    .any(|suffix| first == format!("{}{}", data_type, suffix))
+            {
+                return data_type.to_string();
+            }
+        }
+    }
+    OTHER_ELEMENT.to_string()
+}
+
+fn sorted_elements(elements: BTreeMap<String, ElementUsage>) -> Vec<ElementUsage> {
+    let mut elements: Vec<ElementUsage> = elements.into_values().collect();
+    elements.sort_by_key(|e| Reverse(e.bytes));
+    elements
+}
+
+// Walks one assembly folder, summing file sizes and counts per element as the entries are
+// read. Range counts come from the index headers, the range data is not read.
+fn usage_of(assembly: &str, data_directory: &str) -> Result<AssemblyUsage> {
+    let assembly_path = format!("{}/{}", data_directory, assembly);
+    let mut elements: BTreeMap<String, ElementUsage> = BTreeMap::new();
+    for entry in WalkDir::new(&assembly_path).min_depth(1) {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&assembly_path).unwrap();
+        let element = file_element(relative);
+        let usage = elements.entry(element.clone()).or_insert(ElementUsage {
+            element,
+            ..ElementUsage::default()
+        });
+        usage.files += 1;
+        usage.bytes += entry.metadata()?.len();
+    }
+    for data_type in INDEX_DATA_TYPES {
+        let index_file = format!("{}/{}_idx.dat", assembly_path, data_type);
+        if !Path::new(&index_file).exists() {
+            continue;
+        }
+        match index_range_count(&index_file) {
+            Ok(ranges) => {
+                if let Some(usage) = elements.get_mut(data_type) {
+                    usage.ranges = Some(ranges);
+                }
+            }
+            Err(e) => eprintln!("Warning: Cannot Read Index {}: {}", index_file, e),
+        }
+    }
+    let elements = sorted_elements(elements);
+    Ok(AssemblyUsage {
+        assembly: assembly.to_string(),
+        files: elements.iter().map(|e| e.files).sum(),
+        bytes: elements.iter().map(|e| e.bytes).sum(),
+        elements,
+    })
+}
+
+// Disk usage of one assembly, or of every prepared assembly of the data folder when assembly
+// is None. Each assembly is walked and summed before the next, only the totals are kept.
+pub fn assembly_usage(
+    assembly: Option<&String>,
+    data_directory: impl AsRef<Path>,
+) -> Result<UsageReport> {
+    let data_directory = &path_string(data_directory);
+    let assemblies = match assembly {
+        Some(assembly) => {
+            let assembly_path = format!("{}/{}", data_directory, assembly);
+            if !Path::new(&assembly_path).is_dir() {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Assembly \"{}\" Does Not Exist", assembly_path),
+                ));
+            }
+            vec![assembly.to_string()]
+        }
+        None => list_assemblies(true, data_directory)?,
+    };
+    let mut report = UsageReport::default();
+    let mut totals: BTreeMap<String, ElementUsage> = BTreeMap::new();
+    for assembly in &assemblies {
+        let usage = usage_of(assembly, data_directory)?;
+        for element in &usage.elements {
+            let total = totals
+                .entry(element.element.clone())
+                .or_insert(ElementUsage {
+                    element: element.element.clone(),
+                    ..ElementUsage::default()
+                });
+            total.files += element.files;
+            total.bytes += element.bytes;
+            if let Some(ranges) = element.ranges {
+                total.ranges = Some(total.ranges.unwrap_or(0) + ranges);
+            }
+        }
+        report.files += usage.files;
+        report.bytes += usage.bytes;
+        report.assemblies.push(usage);
+    }
+    report.elements = sorted_elements(totals);
+    report.assemblies.sort_by_key(|a| Reverse(a.bytes));
+    Ok(report)
+}
@@ -0,0 +1,138 @@
+use noodles::bgzf;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use te_idx::idx::{build_idx, prep_idx};
+use te_idx::options::IdxQueryOptions;
+use te_idx::{half_open_file, idx_query_into, idx_query_with, ResultGrouping, ASSEMBLY_DIR};
+use tempfile::TempDir;
+
+// A global allocator counting the largest single allocation of the measuring thread, kept to
+// its own test binary so the other tests allocate through the system allocator
+
+const ALLOC_ASSEMBLY: &str = "alloc_test";
+const HITS: u64 = 40000;
+
+struct LargestAllocation;
+
+thread_local! {
+    static MEASURING: Cell<bool> = const { Cell::new(false) };
+    static LARGEST: Cell<usize> = const { Cell::new(0) };
+}
+
+fn note_allocation(size: usize) {
+    let _ = MEASURING.try_with(|measuring| {
+        if measuring.get() {
+            LARGEST.with(|largest| largest.set(largest.get().max(size)));
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for LargestAllocation {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        note_allocation(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        note_allocation(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: LargestAllocation = LargestAllocation;
+
+fn largest_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    LARGEST.with(|largest| largest.set(0));
+    MEASURING.with(|measuring| measuring.set(true));
+    let result = f();
+    MEASURING.with(|measuring| measuring.set(false));
+    (result, LARGEST.with(|largest| largest.get()))
+}
+
+// HITS overlapping hits of three families on chr1, as prep_beds writes them, indexed
+fn build_assembly(data_directory: &String) {
+    let data_type = ASSEMBLY_DIR.to_string();
+    let fam_dir = format!("{}/{}/{}", data_directory, ALLOC_ASSEMBLY, data_type);
+    create_dir_all(&fam_dir).expect("Can't Create Dir");
+    let mut writer = bgzf::Writer::new(
+        File::create(format!("{}/DF000000001.bed.bgz", fam_dir)).expect("Can't Create File"),
+    );
+    for i in 0..HITS {
+        let start = 1 + i * 50;
+        writeln!(
+            writer,
+            "chr1\t{}\t{}\tDF00000000{}\t50.0\t+\t1.0\t{start}\t{}\t1\t100\t1e-10\t1\t10.5\tTEST\t1000000\t100M\tCAF",
+            start - 1,
+            start + 99,
+            1 + i % 3,
+            start + 99
+        )
+        .expect("Unable To Write Line");
+    }
+    writer.finish().expect("Unable To Finish File");
+    File::create(half_open_file(ALLOC_ASSEMBLY, &data_type, data_directory))
+        .expect("Can't Create File");
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(ALLOC_ASSEMBLY, &data_type, false, data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+}
+
+#[test]
+fn test_idx_query_into_allocation() {
+    let working_dir = TempDir::new().expect("Error Creating Working Directory");
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    build_assembly(&data_directory);
+    let end = HITS * 50 + 100;
+    let options = IdxQueryOptions::builder()
+        .group_by(ResultGrouping {
+            counts_only: false,
+            per_group_limit: Some(2),
+        })
+        .with_etag(true)
+        .build();
+    let json = idx_query_with(
+        ALLOC_ASSEMBLY,
+        ASSEMBLY_DIR,
+        "chr1",
+        1,
+        end,
+        &IdxQueryOptions::builder().build(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let hits: serde_json::Value = serde_json::from_str(&json).expect("Cannot Deserialize");
+    assert_eq!(hits.as_array().unwrap().len(), HITS as usize);
+
+    // Written into a sink, no allocation comes near the size of the response, while
+    // serializing it whole takes one at least as large
+    let (_, streamed) = largest_allocation(|| {
+        idx_query_into(
+            ALLOC_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            end,
+            &options,
+            &data_directory,
+            &mut std::io::sink(),
+        )
+        .expect("Index Query Failed")
+    });
+    let (whole, serialized) = largest_allocation(|| serde_json::to_string(&hits).unwrap());
+    assert!(serialized >= whole.len());
+    assert!(
+        streamed < json.len() / 2,
+        "Largest Allocation {} Of A {} Byte Response",
+        streamed,
+        json.len()
+    );
+
+    let _ = working_dir.close();
+}
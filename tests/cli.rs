@@ -0,0 +1,1423 @@
+use flate2::read::GzDecoder;
+use noodles::bgzf;
+use serde_json::{from_str, json, Value};
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, read, remove_file, write};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Output};
+use te_idx::fixtures::{
+    synthetic_assembly, synthetic_genome, write_fasta, write_synthetic_export, SyntheticAssembly,
+    SyntheticHit,
+};
+use te_idx::{ASSEMBLY_FILE, BENCHMARK_FILE};
+use tempfile::TempDir;
+
+// Drives the compiled binary over a synthetic export, so argument parsing, flag wiring and
+// output formatting of main.rs are covered along with the library
+
+const CLI_ASSEMBLY: &str = "cli_synth";
+
+type HitFilter = Box<dyn Fn(&SyntheticHit) -> bool>;
+
+struct Fixture {
+    working_dir: TempDir,
+    data_dir: String,
+    export_dir: String,
+    synth: SyntheticAssembly,
+}
+
+impl Fixture {
+    fn exported() -> Fixture {
+        let working_dir = TempDir::new().expect("Error Creating Working Directory");
+        let data_dir = format!("{}/data", working_dir.path().display());
+        let export_dir = format!("{}/exports", working_dir.path().display());
+        create_dir_all(&data_dir).expect("Can't Create Dir");
+        let synth = synthetic_assembly(CLI_ASSEMBLY);
+        write_synthetic_export(&synth, &export_dir).expect("Can't Write Export");
+        Fixture {
+            working_dir,
+            data_dir,
+            export_dir,
+            synth,
+        }
+    }
+
+    // An export prepared through the prepare-assembly subcommand
+    fn prepared() -> Fixture {
+        let fixture = Fixture::exported();
+        assert_success(&fixture.run(&["prepare-assembly"]));
+        fixture
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_in(&self.data_dir, CLI_ASSEMBLY, args)
+    }
+
+    // No --exp-dir, every command but prepare-assembly must work from the data directory alone
+    fn run_in(&self, data_dir: &str, assembly: &str, args: &[&str]) -> Output {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_te_idx"));
+        command.args(["--data-dir", data_dir, "--assembly", assembly]);
+        if args.first() == Some(&"prepare-assembly") {
+            command.args(["--exp-dir", &self.export_dir]);
+        }
+        command.args(args).output().expect("Could Not Run te_idx")
+    }
+
+    fn stdout(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert_success(&output);
+        String::from_utf8(output.stdout).expect("Output Is Not UTF-8")
+    }
+
+    fn json(&self, args: &[&str]) -> Value {
+        from_str(self.stdout(args).trim()).expect("Cannot Deserialize")
+    }
+
+    fn file(&self, name: &str) -> String {
+        format!("{}/{}", self.working_dir.path().display(), name)
+    }
+
+    fn data_path(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.data_dir, CLI_ASSEMBLY, path)
+    }
+}
+
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "exit {:?}\nstdout: {}\nstderr: {}",
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn assert_failure(output: &Output, message: &str) {
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(message), "{}", stderr);
+}
+
+fn bgzf_lines(bytes: &[u8]) -> Vec<String> {
+    bgzf::Reader::new(bytes)
+        .lines()
+        .map(|l| l.expect("Can't Read Line"))
+        .collect()
+}
+
+// Lines of a plain gzip file, read by a decoder that knows nothing of BGZF, after checking
+// the header is gzip's own: no extra field, a write time and the Unix OS byte
+fn gzip_plain_lines(bytes: &[u8]) -> Vec<String> {
+    assert_eq!(bytes[..3], [0x1f, 0x8b, 8]);
+    assert_eq!(bytes[3] & 0x04, 0, "Has An Extra Field");
+    assert_ne!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 0);
+    assert_eq!(bytes[9], 3);
+    BufReader::new(GzDecoder::new(bytes))
+        .lines()
+        .map(|l| l.expect("Can't Read Line"))
+        .collect()
+}
+
+fn records(lines: &[String]) -> Vec<Vec<&str>> {
+    lines
+        .iter()
+        .filter(|l| !l.starts_with('#'))
+        .map(|l| l.split('\t').collect())
+        .collect()
+}
+
+fn hit_keys(hits: &Value) -> BTreeSet<(String, u64, u64)> {
+    hits.as_array()
+        .expect("No Hits")
+        .iter()
+        .map(|hit| {
+            (
+                hit["accession"].as_str().unwrap().to_string(),
+                hit["seq_start"].as_u64().unwrap(),
+                hit["seq_end"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+// The e-value as written to the export, so thresholds compare against the same rounding
+fn e_value(hit: &SyntheticHit) -> f64 {
+    format!("{:.1e}", 10f64.powf(-hit.bit_score / 10.0))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_cli_prepare_assembly() {
+    let fixture = Fixture::exported();
+    let stdout = fixture.stdout(&["prepare-assembly"]);
+    assert!(stdout.contains("Queued assembly_alignments: true"));
+    assert!(stdout.contains("Queued masks: true"));
+    assert!(stdout.contains("Queued benchmark_alignments: false"));
+    for path in [
+        "assembly_alignments_idx.dat",
+        "masks_idx.dat",
+        "sequences/cli_synth-sequences.json",
+        "model_lengths/cli_synth-model_lengths.json",
+    ] {
+        assert!(Path::new(&fixture.data_path(path)).exists(), "{}", path);
+    }
+
+    // Prepared data is not redone
+    let stdout = fixture.stdout(&["prepare-assembly"]);
+    assert!(stdout.contains("Queued assembly_alignments: false"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_te_idx"))
+        .args(["--data-dir", &fixture.data_dir])
+        .args(["--exp-dir", &fixture.file("no_exports")])
+        .args(["--assembly", CLI_ASSEMBLY, "prepare-assembly"])
+        .output()
+        .expect("Could Not Run te_idx");
+    assert_failure(&output, "Export Directory");
+}
+
+#[test]
+fn test_cli_build_idx() {
+    let fixture = Fixture::prepared();
+    let index_file = fixture.data_path("assembly_alignments_idx.dat");
+    remove_file(&index_file).expect("Can't Remove Index");
+
+    assert_success(&fixture.run(&["build-idx", "-d", "assembly_alignments"]));
+    assert!(Path::new(&index_file).exists());
+    let hits = fixture.json(&[
+        "idx-query",
+        "-d",
+        "assembly_alignments",
+        "-c",
+        "chr2",
+        "-s",
+        "1000",
+        "-e",
+        "5000",
+    ]);
+    assert!(!hits.as_array().unwrap().is_empty());
+
+    // The index prepare-assembly wrote is superseded, only it is cleaned away
+    let output = fixture.run(&["clean-indexes", "-d", "assembly_alignments"]);
+    assert_success(&output);
+    assert!(output.stdout.is_empty());
+    let output = fixture.run(&["clean-indexes", "--retention-mins", "0"]);
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.starts_with(&format!("Removed {}.", index_file.trim_end_matches(".dat"))));
+    assert!(!fixture
+        .json(&[
+            "idx-query",
+            "-d",
+            "assembly_alignments",
+            "-c",
+            "chr2",
+            "-s",
+            "1000",
+            "-e",
+            "5000"
+        ])
+        .as_array()
+        .unwrap()
+        .is_empty());
+
+    let output = fixture.run(&["build-idx", "-d", "benchmark_alignments"]);
+    assert_failure(&output, "Does Not Exist");
+    let output = fixture.run(&["build-idx", "-d", "sequences"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+// An assembly that only ever lives in a custom data dir, built without prepare-assembly
+#[test]
+fn test_cli_build_idx_custom_data_dir() {
+    let fixture = Fixture::exported();
+    let data_dir = fixture.file("custom_data");
+    let assembly = "cli_custom";
+    create_dir_all(&data_dir).expect("Can't Create Dir");
+    let in_tsv = format!(
+        "{}/{}/{}{}",
+        fixture.export_dir, CLI_ASSEMBLY, CLI_ASSEMBLY, ASSEMBLY_FILE
+    );
+    let run = |args: &[&str]| {
+        let output = fixture.run_in(&data_dir, assembly, args);
+        assert_success(&output);
+        String::from_utf8(output.stdout).expect("Output Is Not UTF-8")
+    };
+
+    run(&["prep-beds", "-i", &in_tsv, "-d", "assembly_alignments"]);
+    run(&["build-idx", "-d", "assembly_alignments"]);
+    assert!(Path::new(&format!(
+        "{}/{}/assembly_alignments_idx.dat",
+        data_dir, assembly
+    ))
+    .exists());
+    assert!(!Path::new(&fixture.data_path("assembly_alignments_idx.dat")).exists());
+
+    let hits: Value = from_str(
+        run(&[
+            "idx-query",
+            "-d",
+            "assembly_alignments",
+            "-c",
+            "chr2",
+            "-s",
+            "10000",
+            "-e",
+            "90000",
+        ])
+        .trim(),
+    )
+    .expect("Cannot Deserialize");
+    let expected = fixture
+        .synth
+        .hits
+        .iter()
+        .filter(|h| h.seq_acc == "chr2" && h.overlaps(10000, 90000))
+        .count();
+    assert_eq!(hits.as_array().unwrap().len(), expected);
+}
+
+#[test]
+fn test_cli_idx_query_filters() {
+    let fixture = Fixture::prepared();
+    let (start, end) = (20000, 60000);
+    let region = [
+        "idx-query",
+        "-d",
+        "assembly_alignments",
+        "-c",
+        "chr1",
+        "-s",
+        "20000",
+        "-e",
+        "60000",
+    ];
+    let query = |args: &[&str]| fixture.json(&[&region[..], args].concat());
+
+    let filters: Vec<(Vec<&str>, HitFilter)> = vec![
+        (vec![], Box::new(|_| true)),
+        (
+            vec!["--family", "DF000000002"],
+            Box::new(|h| h.fam_acc == "DF000000002"),
+        ),
+        (vec!["--nrph"], Box::new(|h| h.nrph)),
+        (vec!["--strand", "-"], Box::new(|h| h.strand == "-")),
+        (
+            vec!["--min-score", "250"],
+            Box::new(|h| h.bit_score >= 250.0),
+        ),
+        (
+            vec!["--max-evalue", "1e-20"],
+            Box::new(|h| e_value(h) <= 1e-20),
+        ),
+        (
+            vec!["-f", "DF000000001,DF000000003"],
+            Box::new(|h| h.fam_acc == "DF000000001" || h.fam_acc == "DF000000003"),
+        ),
+        (
+            vec!["-f", "DF000000002", "--family", "DF000000004.1"],
+            Box::new(|h| h.fam_acc == "DF000000002" || h.fam_acc == "DF000000004"),
+        ),
+        (
+            vec!["-f", "DF000000001", "-n", "--strand", "+"],
+            Box::new(|h| h.fam_acc == "DF000000001" && h.nrph && h.strand == "+"),
+        ),
+    ];
+    for (args, filter) in &filters {
+        let expected: BTreeSet<(String, u64, u64)> = fixture
+            .synth
+            .hits
+            .iter()
+            .filter(|h| h.seq_acc == "chr1" && h.overlaps(start, end) && filter(h))
+            .map(|h| (h.fam_acc.clone(), h.seq_start, h.seq_end))
+            .collect();
+        assert!(!expected.is_empty(), "{:?}", args);
+        assert_eq!(hit_keys(&query(args)), expected, "{:?}", args);
+    }
+
+    let all = query(&[]);
+    assert!(all[0]["bit_score"].is_number());
+    let legacy = query(&["--legacy-json"]);
+    assert!(legacy[0]["seq_start"].is_string());
+
+    let downsampled = query(&["--downsample", "1:10000"]);
+    assert!(downsampled["hits"].as_array().unwrap().len() <= 5);
+    assert!(downsampled["downsample"]["dropped"].as_u64().unwrap() > 0);
+
+    let partial = query(&["--timeout-secs", "60", "--partial"]);
+    assert_eq!(partial["truncated"], false);
+    assert_eq!(hit_keys(&partial["hits"]), hit_keys(&all));
+    let output = fixture.run(&[&region[..], &["--partial"]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--timeout-secs"));
+
+    let counts = query(&["--group-by", "family", "--counts-only"]);
+    let counts = counts.as_object().unwrap();
+    for (fam, group) in counts {
+        let expected = all
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|hit| hit["accession"] == fam.as_str())
+            .count();
+        assert_eq!(group, &json!({ "count": expected }));
+    }
+    let limited = query(&["--group-by", "family", "--per-group-limit", "2"]);
+    for (fam, group) in limited.as_object().unwrap() {
+        assert_eq!(group["count"], counts[fam]["count"]);
+        assert!(group["records"].as_array().unwrap().len() <= 2);
+    }
+    let output = fixture.run(&[&region[..], &["--counts-only"]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--group-by"));
+
+    // One BED6 line per hit in place of the JSON
+    let bed = fixture.stdout(&[&region[..], &["--format", "bed6"]].concat());
+    assert_eq!(bed.lines().count(), all.as_array().unwrap().len());
+    assert!(bed.lines().all(|l| l.split('\t').count() == 6));
+    let output =
+        fixture.run(&[&region[..], &["--format", "bed6", "--group-by", "family"]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    let output = fixture.run(&[&region[..], &["--resolve-names"]].concat());
+    assert_eq!(output.status.code(), Some(2));
+
+    let (chrom, chrom_len) = &fixture.synth.contigs[0];
+    let masks = fixture.json(&[
+        "idx-query",
+        "-d",
+        "masks",
+        "-c",
+        chrom,
+        "-s",
+        "1",
+        "-e",
+        &chrom_len.to_string(),
+    ]);
+    let expected = fixture
+        .synth
+        .masks
+        .iter()
+        .filter(|m| &m.seq_acc == chrom)
+        .count();
+    assert_eq!(masks.as_array().unwrap().len(), expected);
+
+    let output = fixture.run(&[
+        "idx-query",
+        "-d",
+        "masks",
+        "-c",
+        "chr1",
+        "-s",
+        "1",
+        "-e",
+        "5000",
+        "-f",
+        "DF000000001",
+    ]);
+    assert_failure(&output, "Masks Have No Family Column");
+}
+
+#[test]
+fn test_cli_idx_query_bad_regions() {
+    let fixture = Fixture::prepared();
+    let query = |region: &[&str]| {
+        fixture.run(
+            &[
+                &["idx-query", "-d", "assembly_alignments", "-c", "chr1"][..],
+                region,
+            ]
+            .concat(),
+        )
+    };
+    for (region, message) in [
+        (
+            &["-s", "100", "-e", "50"][..],
+            "--start 100 Must Be Less Than --end 50",
+        ),
+        (&["-s", "100", "-e", "100"], "--start 100 Must Be Less Than --end 100"),
+        (
+            &["-s", "-5", "-e", "50"],
+            "invalid value '-5' for '--start <START>': Coordinates Are 1-Based Positive Integers, Got -5",
+        ),
+        (
+            &["-s", "1", "-e", "0"],
+            "invalid value '0' for '--end <END>': Coordinates Are 1-Based",
+        ),
+        (&["-s", "1k", "-e", "50"], "\"1k\" Is Not A Whole Number"),
+        (
+            &["-s", "1", "-e", "300000001"],
+            "Region Of 300000000 bp Is Longer Than --max-region 250000000",
+        ),
+        (
+            &["-s", "1", "-e", "20000", "--max-region", "10000"],
+            "Region Of 19999 bp Is Longer Than --max-region 10000",
+        ),
+        (
+            &["-s", "1", "-e", "20000", "--max-region", "1", "--no-max-region"],
+            "cannot be used with '--no-max-region'",
+        ),
+    ] {
+        let output = query(region);
+        assert_eq!(output.status.code(), Some(2), "{:?}", region);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{:?}: {}", region, stderr);
+    }
+
+    let (chrom, chrom_len) = &fixture.synth.contigs[0];
+    let whole = [
+        "idx-query",
+        "-d",
+        "masks",
+        "-c",
+        chrom,
+        "-s",
+        "1",
+        "-e",
+        &chrom_len.to_string(),
+    ];
+    let output = fixture.run(&[&whole[..], &["--max-region", "1000"]].concat());
+    assert_eq!(output.status.code(), Some(2));
+    let hits = fixture.json(&[&whole[..], &["--no-max-region"]].concat());
+    assert!(!hits.as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_cli_dump() {
+    let fixture = Fixture::prepared();
+    let hits = &fixture.synth.hits;
+    let (chrom, _) = &fixture.synth.contigs[0];
+    let (fam, _, _) = &fixture.synth.families[1];
+
+    let lines = |args: &[&str]| -> Vec<String> {
+        let mut full = vec!["dump", "-d", "assembly_alignments"];
+        full.extend_from_slice(args);
+        fixture
+            .stdout(&full)
+            .lines()
+            .map(|l| l.to_string())
+            .collect()
+    };
+    let bed = lines(&["-c", chrom]);
+    assert_eq!(
+        bed.len(),
+        hits.iter().filter(|h| &h.seq_acc == chrom).count()
+    );
+    let starts: Vec<u64> = records(&bed)
+        .iter()
+        .map(|r| r[1].parse().unwrap())
+        .collect();
+    assert!(starts.windows(2).all(|w| w[0] <= w[1]));
+
+    let all = lines(&["--all", "--ndjson", "-f", fam]);
+    assert_eq!(all.len(), hits.iter().filter(|h| &h.fam_acc == fam).count());
+    for line in &all {
+        let hit: Value = from_str(line).expect("Cannot Deserialize");
+        assert_eq!(hit["accession"], fam.as_str());
+    }
+
+    assert_failure(
+        &fixture.run(&["dump", "-d", "assembly_alignments", "-c", "chrZ"]),
+        "Contig chrZ Not Found",
+    );
+    let output = fixture.run(&["dump", "-d", "assembly_alignments"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_cli_family_fasta() {
+    let fixture = Fixture::prepared();
+    let (fam, _, _) = &fixture.synth.families[2];
+    let out_path = fixture.file("family.fa");
+    let args = [
+        "family-fasta",
+        "-i",
+        fam,
+        "-n",
+        "--flank-bp",
+        "5",
+        "-o",
+        &out_path,
+    ];
+    assert_failure(&fixture.run(&args), "Genome FASTA");
+
+    create_dir_all(fixture.data_path("genome")).expect("Can't Create Dir");
+    let genome = synthetic_genome(&fixture.synth.contigs);
+    write_fasta(
+        fixture.data_path(&format!("genome/{}.fa", CLI_ASSEMBLY)),
+        &genome,
+        60,
+    )
+    .expect("Can't Write FASTA");
+    let expected = fixture
+        .synth
+        .hits
+        .iter()
+        .filter(|h| &h.fam_acc == fam && h.nrph)
+        .count();
+    assert_eq!(
+        fixture.stdout(&args).trim(),
+        format!("Wrote {} Sequences To {}", expected, out_path)
+    );
+    let fasta = String::from_utf8(read(&out_path).unwrap()).unwrap();
+    let names: Vec<&str> = fasta.lines().filter(|l| l.starts_with('>')).collect();
+    assert_eq!(names.len(), expected);
+    assert!(names
+        .iter()
+        .all(|n| n.ends_with("(+)") || n.ends_with("(-)")));
+}
+
+#[test]
+fn test_cli_json_query() {
+    let fixture = Fixture::prepared();
+    let (chrom, chrom_len) = &fixture.synth.contigs[0];
+    let lookup = |args: &[&str]| fixture.stdout(args).trim().to_string();
+
+    assert_eq!(
+        lookup(&[
+            "json-query",
+            "-d",
+            "model_lengths",
+            "-k",
+            "DF000000002",
+            "-t",
+            "length"
+        ]),
+        "300"
+    );
+    assert_eq!(
+        lookup(&["json-query", "-d", "sequences", "-k", "chr2"]),
+        "1"
+    );
+    assert_eq!(
+        lookup(&["json-query", "-d", "sequences", "-k", "chrZ"]),
+        "-1"
+    );
+
+    let keys_file = fixture.file("keys.txt");
+    write(&keys_file, format!("{}\nchrZ\n", chrom)).expect("Can't Write Keys");
+    let many = fixture.json(&[
+        "json-query",
+        "-d",
+        "sequences",
+        "--keys-file",
+        &keys_file,
+        "-t",
+        "length",
+    ]);
+    assert_eq!(many["values"][chrom], chrom_len.to_string());
+    assert_eq!(many["missing"], serde_json::json!(["chrZ"]));
+
+    let all = fixture.json(&["json-query", "-d", "model_lengths", "--all", "-t", "length"]);
+    assert_eq!(all["values"].as_object().unwrap().len(), 4);
+
+    let output = fixture.run(&["json-query", "-d", "sequences"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_cli_list_assemblies() {
+    let fixture = Fixture::prepared();
+    create_dir_all(format!("{}/staging/masks", fixture.data_dir)).expect("Can't Create Dir");
+    let list = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_te_idx"))
+            .args(["--data-dir", &fixture.data_dir, "list-assemblies"])
+            .args(args)
+            .output()
+            .expect("Could Not Run te_idx");
+        assert_success(&output);
+        String::from_utf8(output.stdout).expect("Output Is Not UTF-8")
+    };
+    assert_eq!(list(&[]), format!("{}\nstaging\n", CLI_ASSEMBLY));
+
+    write(
+        format!("{}/allowlist.json", fixture.data_dir),
+        format!(r#"{{"assemblies": {{"{}": {{}}}}}}"#, CLI_ASSEMBLY),
+    )
+    .expect("Can't Write Allowlist");
+    assert_eq!(list(&[]), format!("{}\n", CLI_ASSEMBLY));
+    assert_eq!(
+        list(&["--include-hidden"]),
+        format!("{}\nstaging\n", CLI_ASSEMBLY)
+    );
+    let query = [
+        "idx-query",
+        "-d",
+        "masks",
+        "-c",
+        "chr1",
+        "-s",
+        "1",
+        "-e",
+        "1000",
+    ];
+    assert_failure(
+        &fixture.run_in(&fixture.data_dir, "staging", &query),
+        "Assembly staging Is Not Available",
+    );
+    assert_success(&fixture.run(&query));
+
+    // Every other command needs an assembly
+    let output = Command::new(env!("CARGO_BIN_EXE_te_idx"))
+        .args([
+            "--data-dir",
+            &fixture.data_dir,
+            "source-info",
+            "-d",
+            "masks",
+        ])
+        .output()
+        .expect("Could Not Run te_idx");
+    assert_failure(&output, "--assembly");
+}
+
+#[test]
+fn test_cli_get_chrom_id() {
+    let fixture = Fixture::prepared();
+    let lookup = |args: &[&str]| fixture.stdout(args).trim().to_string();
+
+    assert_eq!(lookup(&["get-chrom-id", "-q", "chr2"]), "chr2");
+    assert_eq!(lookup(&["get-chrom-id", "-q", "chr"]), "-1");
+
+    let fuzzy = fixture.json(&["get-chrom-id", "-q", "CHR", "--fuzzy"]);
+    assert_eq!(fuzzy["total"], 2);
+    assert_eq!(fuzzy["matches"][1]["accession"], "chr2");
+
+    let output = fixture.run(&["get-chrom-id", "-q", "chr", "--fuzzy", "--limit", "1"]);
+    assert_success(&output);
+    let limited: Value = from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
+    assert_eq!(limited["truncated"], true);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Showing 1 Of 2 Matches"));
+
+    let output = fixture.run(&["get-chrom-id", "-q", "chr", "--limit", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_cli_bgzf_filter() {
+    let fixture = Fixture::prepared();
+    let fam = "DF000000003";
+    let fam_hits: Vec<&SyntheticHit> = fixture
+        .synth
+        .hits
+        .iter()
+        .filter(|h| h.fam_acc == fam)
+        .collect();
+    let filter = |args: &[&str]| {
+        let output = fixture.run(
+            &[
+                &["bgzf-filter", "-d", "assembly_alignments", "-f", fam][..],
+                args,
+            ]
+            .concat(),
+        );
+        assert_success(&output);
+        bgzf_lines(&output.stdout)
+    };
+
+    // Without --outfile the compressed output goes to stdout
+    let lines = filter(&["-p", "1"]);
+    assert!(lines[0].starts_with("#seq_id\tseq_start"));
+    let rows = records(&lines);
+    assert_eq!(rows.len(), fam_hits.len());
+    assert!(rows.iter().all(|r| r.len() == 16 && r[3] == fam));
+
+    let outfile = fixture.file("filtered.bed.bgz");
+    assert!(filter(&["-p", "1", "--outfile", &outfile]).is_empty());
+    assert_eq!(bgzf_lines(&read(&outfile).expect("No Outfile")), lines);
+
+    let nrph = filter(&["-p", "13", "-t", "1"]);
+    assert_eq!(
+        records(&nrph).len(),
+        fam_hits.iter().filter(|h| h.nrph).count()
+    );
+
+    let minus = filter(&["-p", "1", "--strand", "-"]);
+    let minus_rows = records(&minus);
+    assert_eq!(
+        minus_rows.len(),
+        fam_hits.iter().filter(|h| h.strand == "-").count()
+    );
+    assert!(minus_rows.iter().all(|r| r[5] == "-"));
+
+    let web = filter(&["-p", "1", "--web-fmt"]);
+    assert!(web[0].starts_with("#sequence name\tmodel accession"));
+    assert!(records(&web).iter().all(|r| r.len() == 14 && r[7] == "800"));
+
+    let internal = filter(&["-p", "1", "--profile", "internal"]);
+    assert!(internal[0].ends_with("\t*cigar\t*caf"));
+    assert!(records(&internal)
+        .iter()
+        .all(|r| r.len() == 18 && r[17] == "CAF"));
+    let trimmed = filter(&["-p", "1", "--exclude-columns", "bias,divergence"]);
+    assert!(!trimmed[0].contains("bias"));
+    assert!(records(&trimmed).iter().all(|r| r.len() == 14));
+
+    let output = fixture.run(&[
+        "bgzf-filter",
+        "-d",
+        "assembly_alignments",
+        "-f",
+        "DF000000009",
+        "-p",
+        "1",
+    ]);
+    assert_failure(&output, "Not Found");
+}
+
+#[test]
+fn test_cli_annotation_exports() {
+    let fixture = Fixture::prepared();
+    let fam = "DF000000004";
+    let fam_hits = fixture.synth.hits.iter().filter(|h| h.fam_acc == fam);
+
+    let outfile = fixture.file("family.bed.bgz");
+    assert_success(&fixture.run(&[
+        "read-family-assembly-annotations",
+        "-i",
+        fam,
+        "--outfile",
+        &outfile,
+    ]));
+    let lines = bgzf_lines(&read(&outfile).expect("No Outfile"));
+    assert!(lines[0].starts_with("#sequence name"));
+    assert_eq!(records(&lines).len(), fam_hits.clone().count());
+
+    let outfile = fixture.file("family_nrph.bed.bgz");
+    assert_success(&fixture.run(&[
+        "read-family-assembly-annotations",
+        "-i",
+        fam,
+        "--nrph",
+        "--outfile",
+        &outfile,
+    ]));
+    let lines = bgzf_lines(&read(&outfile).expect("No Outfile"));
+    assert_eq!(records(&lines).len(), fam_hits.filter(|h| h.nrph).count());
+
+    // Plain gzip holds the same lines as the BGZF output
+    let outfile = fixture.file("family_plain.bed.gz");
+    assert_success(&fixture.run(&[
+        "read-family-assembly-annotations",
+        "-i",
+        fam,
+        "--nrph",
+        "--gzip-plain",
+        "--outfile",
+        &outfile,
+    ]));
+    assert_eq!(
+        gzip_plain_lines(&read(&outfile).expect("No Outfile")),
+        lines
+    );
+    let bgzf_outfile = fixture.file("family_filter.bed.bgz");
+    let plain_outfile = fixture.file("family_filter.bed.gz");
+    for (outfile, plain) in [(&bgzf_outfile, false), (&plain_outfile, true)] {
+        let mut args = vec![
+            "bgzf-filter",
+            "-d",
+            "assembly_alignments",
+            "-f",
+            fam,
+            "-p",
+            "4",
+            "--outfile",
+            outfile,
+        ];
+        if plain {
+            args.push("--gzip-plain");
+        }
+        assert_success(&fixture.run(&args));
+    }
+    assert_eq!(
+        gzip_plain_lines(&read(&plain_outfile).expect("No Outfile")),
+        bgzf_lines(&read(&bgzf_outfile).expect("No Outfile"))
+    );
+    let misnamed = fixture.file("family_plain.bed.bgz");
+    assert_failure(
+        &fixture.run(&[
+            "read-family-assembly-annotations",
+            "-i",
+            fam,
+            "--gzip-plain",
+            "--outfile",
+            &misnamed,
+        ]),
+        "Should End In .gz",
+    );
+    assert!(!Path::new(&misnamed).exists());
+
+    let outfile = fixture.file("all.bed.bgz");
+    assert_success(&fixture.run(&["all-annotations", "--outfile", &outfile]));
+    let lines = bgzf_lines(&read(&outfile).expect("No Outfile"));
+    assert_eq!(records(&lines).len(), fixture.synth.hits.len());
+    assert_eq!(lines.iter().filter(|l| l.starts_with('#')).count(), 4);
+    let plain_outfile = fixture.file("all.bed.gz");
+    assert_success(&fixture.run(&[
+        "all-annotations",
+        "--gzip-plain",
+        "--outfile",
+        &plain_outfile,
+    ]));
+    assert_eq!(
+        gzip_plain_lines(&read(&plain_outfile).expect("No Outfile")),
+        lines
+    );
+
+    let source = fixture.stdout(&["source-info", "-d", "assembly_alignments"]);
+    assert!(source.contains("# Synthetic export for cli_synth"));
+
+    let meta = fixture.stdout(&["meta-data", "--summary", "--chromosomes"]);
+    assert!(meta.contains("Chromosomes Count: 2"));
+    assert!(meta.contains("chr2"));
+}
+
+#[test]
+fn test_cli_peek_record() {
+    let fixture = Fixture::prepared();
+    let mask_file = fixture.data_path("masks/chr1.bed.bgz");
+    let first_line = bgzf_lines(&read(&mask_file).expect("No Mask File"))[0].clone();
+
+    // By file name within the assembly, as the index stores it
+    let stdout = fixture.stdout(&[
+        "peek-record",
+        "-d",
+        "masks",
+        "-b",
+        "chr1.bed.bgz",
+        "-v",
+        "0",
+    ]);
+    let (pretty, raw) = stdout.trim_end().rsplit_once('\n').expect("No Raw Line");
+    assert_eq!(raw, first_line);
+    let peeked: Value = from_str(pretty).expect("Cannot Deserialize");
+    assert_eq!(peeked["record"]["seq_id"], "chr1");
+    assert_eq!(
+        peeked["record"]["repeat_str"],
+        first_line.split('\t').nth(3).unwrap()
+    );
+
+    let stdout = fixture.stdout(&["peek-record", "-d", "masks", "-b", &mask_file, "-v", "0"]);
+    assert!(stdout.ends_with(&format!("{}\n", first_line)));
+
+    let output = fixture.run(&[
+        "peek-record",
+        "-d",
+        "masks",
+        "-b",
+        "chr1.bed.bgz",
+        "-v",
+        "2",
+    ]);
+    assert_failure(&output, "Not The Start Of A Line");
+    let output = fixture.run(&[
+        "peek-record",
+        "-d",
+        "assembly_alignments",
+        "-b",
+        &mask_file,
+        "-v",
+        "0",
+    ]);
+    assert_failure(&output, "Expected 18 Fields, Found 5");
+}
+
+#[test]
+fn test_cli_append_records() {
+    let fixture = Fixture::prepared();
+    let records_tsv = fixture.file("new_records.tsv");
+    write(
+        &records_tsv,
+        "chr2\tDF000000002\tSynthB\t99.00\t1.0e-10\t0.1\t1\t50\t+\t1001\t1050\t1001\t1050\t180224\t50M\t12.5\t1\tCAF\n",
+    )
+    .expect("Can't Write TSV");
+    let query = [
+        "idx-query",
+        "-d",
+        "assembly_alignments",
+        "-c",
+        "chr2",
+        "-s",
+        "1000",
+        "-e",
+        "1100",
+        "-f",
+        "DF000000002",
+    ];
+    let key = ("DF000000002".to_string(), 1001, 1050);
+    assert!(!hit_keys(&fixture.json(&query)).contains(&key));
+
+    let args = [
+        "append-records",
+        "-i",
+        &records_tsv,
+        "-d",
+        "assembly_alignments",
+        "-f",
+        "DF000000002",
+    ];
+    let stdout = fixture.stdout(&args);
+    assert!(stdout.contains("Appended 1 Records To DF000000002"));
+    assert!(stdout.contains("Run build-idx"));
+    let stdout = fixture.stdout(&[&args[..], &["--rebuild-idx"]].concat());
+    assert!(!stdout.contains("Run build-idx"));
+    let hits = fixture.json(&query);
+    assert!(hit_keys(&hits).contains(&key));
+    assert_eq!(
+        hits.as_array()
+            .unwrap()
+            .iter()
+            .filter(|h| h["seq_start"] == 1001)
+            .count(),
+        2
+    );
+
+    let output = fixture.run(
+        &[
+            &args[..3],
+            &["-d", "assembly_alignments", "-f", "DF000000001"],
+        ]
+        .concat(),
+    );
+    assert_failure(&output, "Cannot Be Appended To DF000000001");
+}
+
+#[test]
+fn test_cli_package_unpack() {
+    // Only deterministic indexes are packaged
+    let fixture = Fixture::exported();
+    assert_success(&fixture.run(&["prepare-assembly", "--deterministic"]));
+    let archive = fixture.file("cli_synth.tar.gz");
+    assert_success(&fixture.run(&["package", "--outfile", &archive, "--include-indexes"]));
+
+    let other_data_dir = fixture.file("unpacked");
+    create_dir_all(&other_data_dir).expect("Can't Create Dir");
+    assert_success(&fixture.run_in(
+        &other_data_dir,
+        CLI_ASSEMBLY,
+        &["unpack", "--infile", &archive],
+    ));
+
+    let query = [
+        "idx-query",
+        "-d",
+        "assembly_alignments",
+        "-c",
+        "chr2",
+        "-s",
+        "10000",
+        "-e",
+        "90000",
+    ];
+    let unpacked = fixture.run_in(&other_data_dir, CLI_ASSEMBLY, &query);
+    assert_success(&unpacked);
+    assert_eq!(
+        String::from_utf8_lossy(&unpacked.stdout),
+        fixture.stdout(&query)
+    );
+}
+
+#[test]
+fn test_cli_benchmark_report() {
+    let fixture = Fixture::prepared();
+    let fam = "DF000000001";
+    let (seq_lens, bench_hits): (Vec<u64>, Vec<&SyntheticHit>) = fixture
+        .synth
+        .hits
+        .iter()
+        .filter(|h| h.fam_acc == fam)
+        .step_by(2)
+        .map(|h| {
+            let seq_len = fixture
+                .synth
+                .contigs
+                .iter()
+                .find(|(name, _)| name == &h.seq_acc)
+                .unwrap()
+                .1;
+            (seq_len, h)
+        })
+        .unzip();
+
+    // Benchmark export columns: no divergence, NRPH or CAF
+    let mut tsv = String::from("# Synthetic benchmark export\n");
+    for (hit, seq_len) in bench_hits.iter().zip(&seq_lens) {
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\t{:.2}\t1e-10\t0.1\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}M\n",
+            hit.seq_acc,
+            hit.fam_acc,
+            hit.family_name,
+            hit.bit_score,
+            hit.model_start,
+            hit.model_end,
+            hit.strand,
+            hit.seq_start,
+            hit.seq_end,
+            hit.seq_start,
+            hit.seq_end,
+            seq_len,
+            hit.seq_start.abs_diff(hit.seq_end) + 1,
+        ));
+    }
+    // One repeated record for --dedupe to remove
+    let repeated = tsv.lines().last().unwrap().to_string();
+    tsv.push_str(&repeated);
+    tsv.push('\n');
+    let in_tsv = fixture.file(&format!("{}{}", CLI_ASSEMBLY, BENCHMARK_FILE));
+    write(&in_tsv, tsv).expect("Can't Write TSV");
+
+    let stdout = fixture.stdout(&[
+        "prep-beds",
+        "-i",
+        &in_tsv,
+        "-d",
+        "benchmark_alignments",
+        "--dedupe",
+    ]);
+    assert!(stdout.contains("Removed 1 Duplicate Records"));
+    assert!(stdout.contains("Bed Files Created - benchmark_alignments"));
+    assert_success(&fixture.run(&["build-idx", "-d", "benchmark_alignments"]));
+
+    let hit = bench_hits[0];
+    let (low, high) = (
+        hit.seq_start.min(hit.seq_end),
+        hit.seq_start.max(hit.seq_end),
+    );
+    let hits = fixture.json(&[
+        "idx-query",
+        "-d",
+        "benchmark_alignments",
+        "-c",
+        &hit.seq_acc,
+        "-s",
+        &(low - 1).to_string(),
+        "-e",
+        &(high + 1).to_string(),
+    ]);
+    assert!(hit_keys(&hits).contains(&(fam.to_string(), hit.seq_start, hit.seq_end)));
+
+    let report = fixture.json(&["benchmark-report", "-f", fam]);
+    assert_eq!(report["benchmark_hits"], bench_hits.len());
+    assert_eq!(report["recovered"], bench_hits.len());
+    assert_eq!(report["missed"], 0);
+    assert_eq!(
+        report["assembly_only"],
+        fixture
+            .synth
+            .hits
+            .iter()
+            .filter(|h| h.fam_acc == fam)
+            .count()
+            - bench_hits.len()
+    );
+
+    let output = fixture.run(&["benchmark-report", "-f", "DF000000002"]);
+    assert_failure(&output, "Nothing To Compare");
+
+    let combined = fixture.json(&["family-combined", "-f", fam]);
+    let combined = combined.as_array().unwrap();
+    let from = |source: &str| combined.iter().filter(|h| h["source"] == source).count();
+    assert_eq!(from("benchmark"), bench_hits.len());
+    assert_eq!(
+        from("assembly"),
+        fixture
+            .synth
+            .hits
+            .iter()
+            .filter(|h| h.fam_acc == fam)
+            .count()
+    );
+    assert_eq!(combined.len(), from("assembly") + from("benchmark"));
+    let stdout = fixture.stdout(&["family-combined", "-f", fam, "--web-fmt"]);
+    assert_eq!(stdout.lines().count(), combined.len() + 1);
+}
+
+#[test]
+fn test_cli_defragment() {
+    let fixture = Fixture::prepared();
+    let (fam, _, _) = &fixture.synth.families[0];
+    let report = fixture.json(&["defragment", "-f", fam, "--max-gap-bp", "1000"]);
+    assert_eq!(
+        report["hits"].as_u64().unwrap() as usize,
+        fixture
+            .synth
+            .hits
+            .iter()
+            .filter(|h| &h.fam_acc == fam)
+            .count()
+    );
+    assert_eq!(report["max_gap_bp"], 1000);
+    let joined: u64 = report["insertions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| i["members"].as_array().unwrap().len() as u64)
+        .sum();
+    assert_eq!(report["joined_hits"].as_u64().unwrap(), joined);
+
+    let tsv = fixture.stdout(&["defragment", "-f", fam, "--max-gap-bp", "1000", "--tsv"]);
+    let lines: Vec<&str> = tsv.lines().collect();
+    assert!(lines[0].starts_with("#sequence\tstart"));
+    assert_eq!(
+        lines.len() - 1,
+        report["insertions"].as_array().unwrap().len()
+    );
+}
+
+#[test]
+fn test_cli_remap_names() {
+    let fixture = Fixture::prepared();
+    let mapping = fixture.file("mapping.tsv");
+    write(&mapping, "chr1\tNC_000001.11\nchr2\tNC_000002.12\n").expect("Can't Write Mapping");
+    let stdout = fixture.stdout(&[
+        "remap-names",
+        "-d",
+        "assembly_alignments",
+        "-m",
+        &mapping,
+        "-o",
+        "cli_synth_refseq",
+    ]);
+    assert!(stdout.contains(&format!("Remapped {} Records", fixture.synth.hits.len())));
+
+    let region = ["-s", "20000", "-e", "60000"];
+    let original = fixture.json(
+        &[
+            &["idx-query", "-d", "assembly_alignments", "-c", "chr1"][..],
+            &region,
+        ]
+        .concat(),
+    );
+    let remapped = fixture.run_in(
+        &fixture.data_dir,
+        "cli_synth_refseq",
+        &[
+            &[
+                "idx-query",
+                "-d",
+                "assembly_alignments",
+                "-c",
+                "NC_000001.11",
+            ][..],
+            &region,
+        ]
+        .concat(),
+    );
+    assert_success(&remapped);
+    let remapped: Value = from_str(&String::from_utf8_lossy(&remapped.stdout)).unwrap();
+    assert_eq!(hit_keys(&remapped), hit_keys(&original));
+    assert!(remapped
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|h| h["sequence"] == "NC_000001.11"));
+
+    let output = fixture.run(&[
+        "remap-names",
+        "-d",
+        "assembly_alignments",
+        "-o",
+        CLI_ASSEMBLY,
+    ]);
+    assert_failure(&output, "New Assembly");
+}
+
+#[test]
+fn test_cli_migrate() {
+    let fixture = Fixture::prepared();
+    let meta_file = format!("{}/{}/assembly_meta.json", fixture.data_dir, CLI_ASSEMBLY);
+    let meta: Value = from_str(&String::from_utf8(read(&meta_file).unwrap()).unwrap())
+        .expect("Cannot Deserialize");
+    assert_eq!(meta["layout_version"], 2);
+
+    let output = fixture.run(&["migrate"]);
+    assert_success(&output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "Migrated {} From Layout 2 To 2: 0 Migrated, 0 Already Done\n",
+            CLI_ASSEMBLY
+        )
+    );
+    assert_failure(&fixture.run(&["migrate", "--from-version", "0"]), "Not 0");
+
+    write(&meta_file, r#"{"layout_version": 3}"#).expect("Can't Write Meta");
+    assert_failure(
+        &fixture.run(&[
+            "idx-query",
+            "-d",
+            "masks",
+            "-c",
+            "chr1",
+            "-s",
+            "1",
+            "-e",
+            "1000",
+        ]),
+        "Has Layout Version 3, Newer Than The Supported 2",
+    );
+}
+
+#[test]
+fn test_cli_build_info() {
+    let fixture = Fixture::prepared();
+    let output = Command::new(env!("CARGO_BIN_EXE_te_idx"))
+        .args(["--data-dir", &fixture.data_dir, "build-info"])
+        .output()
+        .expect("Could Not Run te_idx");
+    assert_success(&output);
+    let info: Value =
+        from_str(&String::from_utf8_lossy(&output.stdout)).expect("Cannot Deserialize");
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(info["index_format_version"], 1);
+    assert_eq!(info["min_index_format_version"], 0);
+    assert_eq!(info["max_index_format_version"], 3);
+    assert_eq!(info["layout_version"], 2);
+    assert_eq!(info["min_layout_version"], 0);
+    assert_eq!(info["assembly_data_version"], 1);
+    assert!(info["features"].is_array());
+    let compatibility = info["compatibility"].as_array().expect("No Compatibility");
+    assert_eq!(compatibility.len(), 1);
+    assert_eq!(compatibility[0]["assembly"], CLI_ASSEMBLY);
+    assert_eq!(compatibility[0]["layout_version"], 2);
+    assert_eq!(compatibility[0]["verdict"], "readable");
+    assert_eq!(compatibility[0]["index_versions"]["masks"], 1);
+}
+
+#[test]
+fn test_cli_expect_families() {
+    let fixture = Fixture::prepared();
+    let (fam, _, _) = &fixture.synth.families[0];
+    let data_type = "assembly_alignments";
+    assert_success(&fixture.run(&["expect-families", "-d", data_type, "-f", fam]));
+
+    let families = format!("{},DF099999999", fam);
+    let output = fixture.run(&["expect-families", "-d", data_type, "-f", &families]);
+    assert_failure(&output, "1 Of 2 Families Missing");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "DF099999999\n");
+
+    // A file of nothing but the end-of-file block is only missing when asked
+    let empty = fixture.data_path(&format!("{}/DF099999999.bed.bgz", data_type));
+    bgzf::Writer::new(std::fs::File::create(&empty).expect("Can't Create File"))
+        .finish()
+        .expect("Unable to finish file");
+    assert_success(&fixture.run(&["expect-families", "-d", data_type, "-f", &families]));
+    let output = fixture.run(&[
+        "expect-families",
+        "-d",
+        data_type,
+        "-f",
+        &families,
+        "--empty-as-missing",
+    ]);
+    assert_failure(&output, "1 Of 2 Families Missing");
+}
+
+#[test]
+fn test_cli_list_families() {
+    let fixture = Fixture::prepared();
+    let with_hits: BTreeSet<&String> = fixture.synth.hits.iter().map(|hit| &hit.fam_acc).collect();
+    let data_type = "assembly_alignments";
+    let listed = fixture.stdout(&["list-families", "-d", data_type]);
+    assert_eq!(
+        listed.lines().collect::<Vec<_>>(),
+        with_hits.iter().map(|f| f.as_str()).collect::<Vec<_>>()
+    );
+
+    let first = with_hits.first().unwrap();
+    let listed = fixture.stdout(&["list-families", "-d", data_type, "--prefix", first]);
+    assert_eq!(listed, format!("{}\n", first));
+
+    // A page of one, counted from the listing the index build left
+    let page = fixture.json(&[
+        "list-families",
+        "-d",
+        data_type,
+        "--limit",
+        "1",
+        "--offset",
+        "1",
+        "--json",
+    ]);
+    assert_eq!(page["total"], with_hits.len());
+    let families = page["families"].as_array().unwrap();
+    assert_eq!(families.len(), 1);
+    let second = with_hits.iter().nth(1).unwrap();
+    assert_eq!(families[0]["accession"], second.as_str());
+    assert_eq!(
+        families[0]["records"],
+        fixture
+            .synth
+            .hits
+            .iter()
+            .filter(|hit| hit.fam_acc == **second)
+            .count()
+    );
+}
+
+#[test]
+fn test_cli_plain_output() {
+    let fixture = Fixture::prepared();
+    let (fam, _, _) = &fixture.synth.families[0];
+    let families = format!("{},DF099999999", fam);
+    // Piped output is plain whatever the terminal type, and --plain and NO_COLOR change nothing
+    let commands: Vec<Vec<&str>> = vec![
+        vec!["list-families", "-d", "assembly_alignments"],
+        vec!["list-families", "-d", "assembly_alignments", "--json"],
+        vec!["usage", "--tsv"],
+        vec!["usage"],
+        vec!["build-idx", "-d", "assembly_alignments"],
+        vec![
+            "expect-families",
+            "-d",
+            "assembly_alignments",
+            "-f",
+            &families,
+        ],
+        vec!["list-families", "--no-such-option"],
+    ];
+    for args in &commands {
+        let mut outputs = Vec::new();
+        for (plain, no_color) in [(false, false), (true, false), (false, true)] {
+            let mut command = Command::new(env!("CARGO_BIN_EXE_te_idx"));
+            command.env("TERM", "xterm-256color").env_remove("NO_COLOR");
+            if no_color {
+                command.env("NO_COLOR", "1");
+            }
+            command.args(["--data-dir", &fixture.data_dir, "--assembly", CLI_ASSEMBLY]);
+            if plain {
+                command.arg("--plain");
+            }
+            let output = command.args(args).output().expect("Could Not Run te_idx");
+            for stream in [&output.stdout, &output.stderr] {
+                let text = String::from_utf8_lossy(stream);
+                assert!(!text.contains('\x1b'), "{:?}: {}", args, text);
+                assert!(!text.contains('\r'), "{:?}: {}", args, text);
+            }
+            outputs.push((output.status.code(), output.stdout));
+        }
+        assert!(
+            outputs.iter().all(|output| *output == outputs[0]),
+            "{:?}",
+            args
+        );
+    }
+
+    // Progress not on a terminal is a plain line, the last at the end
+    let output = fixture.run(&["build-idx", "-d", "assembly_alignments"]);
+    assert_success(&output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let files = std::fs::read_dir(fixture.data_path("assembly_alignments"))
+        .expect("Couldn't Read Dir")
+        .filter(|e| {
+            let name = e.as_ref().unwrap().file_name();
+            name.to_string_lossy().ends_with(".bed.bgz")
+        })
+        .count();
+    assert!(
+        stderr.contains(&format!("Indexing Files {}/{}\n", files, files)),
+        "{}",
+        stderr
+    );
+}
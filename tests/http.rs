@@ -0,0 +1,274 @@
+#![cfg(feature = "http")]
+
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::net::TcpListener;
+use std::thread;
+use te_idx::fixtures::{prepare_synthetic_assembly, SyntheticAssembly};
+use te_idx::http::serve;
+use te_idx::options::IdxQueryOptions;
+use te_idx::{idx_query_with, list_families, ASSEMBLY_DIR};
+use tempfile::TempDir;
+
+// Serves a prepared synthetic assembly on an ephemeral port and queries every route over
+// HTTP, as a browser would
+
+const HTTP_ASSEMBLY: &str = "http_synth";
+
+struct Server {
+    _working_dir: TempDir,
+    data_dir: String,
+    base_url: String,
+    synth: SyntheticAssembly,
+    client: Client,
+}
+
+impl Server {
+    fn start() -> Server {
+        let working_dir = TempDir::new().expect("Error Creating Working Directory");
+        let working_path = working_dir.path().to_str().unwrap().to_string();
+        let (synth, data_dir) = prepare_synthetic_assembly(HTTP_ASSEMBLY, &working_path)
+            .expect("Synthetic Prep Failed");
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Can't Bind");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let served = data_dir.clone();
+        thread::spawn(move || serve(listener, &served, &None));
+        Server {
+            _working_dir: working_dir,
+            data_dir,
+            base_url,
+            synth,
+            client: Client::new(),
+        }
+    }
+
+    fn get(&self, path: &str) -> Response {
+        self.client
+            .get(format!("{}{}", self.base_url, path))
+            .header("Origin", "http://example.org")
+            .send()
+            .expect("Request Failed")
+    }
+
+    fn json(&self, path: &str, status: StatusCode) -> Value {
+        let response = self.get(path);
+        assert_eq!(response.status(), status, "{}", path);
+        response.json().expect("Cannot Deserialize")
+    }
+}
+
+#[test]
+fn test_http_routes() {
+    let server = Server::start();
+
+    let response = server.get("/assemblies");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .map(|v| v.to_str().unwrap()),
+        Some("*")
+    );
+    let assemblies: Value = response.json().expect("Cannot Deserialize");
+    assert_eq!(assemblies, serde_json::json!([HTTP_ASSEMBLY]));
+
+    // The same JSON as idx_query itself
+    let hits = server.json(
+        &format!(
+            "/{}/annotations?chrom=chr1&start=1&end=20000",
+            HTTP_ASSEMBLY
+        ),
+        StatusCode::OK,
+    );
+    let direct = idx_query_with(
+        HTTP_ASSEMBLY,
+        ASSEMBLY_DIR,
+        "chr1",
+        1,
+        20000,
+        &IdxQueryOptions::default(),
+        &server.data_dir,
+    )
+    .expect("Index Query Failed");
+    assert!(!hits.as_array().expect("No Hits").is_empty());
+    assert_eq!(hits, serde_json::from_str::<Value>(&direct).unwrap());
+    let nrph = server.json(
+        &format!(
+            "/{}/annotations?chrom=chr1&start=1&end=20000&family=DF000000001&nrph=true",
+            HTTP_ASSEMBLY
+        ),
+        StatusCode::OK,
+    );
+    for hit in nrph.as_array().expect("No Hits") {
+        assert_eq!(hit["accession"], "DF000000001");
+    }
+    assert!(nrph.as_array().unwrap().len() < hits.as_array().unwrap().len());
+    let families = server.json(
+        &format!(
+            "/{}/annotations?chrom=chr1&start=1&end=20000&family=DF000000001,DF000000002",
+            HTTP_ASSEMBLY
+        ),
+        StatusCode::OK,
+    );
+    for hit in families.as_array().expect("No Hits") {
+        assert!(hit["accession"] == "DF000000001" || hit["accession"] == "DF000000002");
+    }
+    assert!(!families.as_array().unwrap().is_empty());
+
+    let (family, _, model_length) = &server.synth.families[0];
+    let summary = server.json(
+        &format!("/{}/families/{}/summary", HTTP_ASSEMBLY, family),
+        StatusCode::OK,
+    );
+    let family_hits: Vec<_> = server
+        .synth
+        .hits
+        .iter()
+        .filter(|hit| &hit.fam_acc == family)
+        .collect();
+    assert_eq!(summary["accession"], family.as_str());
+    assert_eq!(summary["hits"], family_hits.len());
+    assert_eq!(
+        summary["nrph_hits"],
+        family_hits.iter().filter(|hit| hit.nrph).count()
+    );
+    assert_eq!(summary["model_length"], *model_length);
+    assert_eq!(
+        summary["contigs"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|count| count.as_u64().unwrap())
+            .sum::<u64>(),
+        family_hits.len() as u64
+    );
+
+    // Pages of the listing the index build left, as list_families reads them
+    let listing = server.json(
+        &format!("/{}/families?limit=2&offset=1", HTTP_ASSEMBLY),
+        StatusCode::OK,
+    );
+    let direct = list_families(
+        HTTP_ASSEMBLY,
+        ASSEMBLY_DIR,
+        None,
+        Some(2),
+        1,
+        &server.data_dir,
+    )
+    .expect("Listing Failed");
+    assert_eq!(listing, serde_json::to_value(&direct).unwrap());
+    assert!(direct.total > 1);
+    assert!(direct.families.iter().all(|f| f.records.is_some()));
+
+    let accession = server.json(
+        &format!("/{}/sequences/chr2", HTTP_ASSEMBLY),
+        StatusCode::OK,
+    );
+    assert_eq!(accession, "chr2");
+}
+
+#[test]
+fn test_http_errors() {
+    let server = Server::start();
+    let not_found = [
+        "/missing/sequences/chr1".to_string(),
+        format!("/{}/sequences/chrNone", HTTP_ASSEMBLY),
+        format!("/{}/families/DF000000099/summary", HTTP_ASSEMBLY),
+        format!(
+            "/{}/annotations?chrom=chrNone&start=1&end=100",
+            HTTP_ASSEMBLY
+        ),
+        "/missing/annotations?chrom=chr1&start=1&end=100".to_string(),
+        "/missing/families".to_string(),
+    ];
+    for path in &not_found {
+        let error = server.json(path, StatusCode::NOT_FOUND);
+        assert!(error["error"].is_string(), "{}", path);
+    }
+    let bad_request = [
+        format!("/{}/annotations?chrom=chr1&start=1", HTTP_ASSEMBLY),
+        format!("/{}/annotations?chrom=chr1&start=x&end=100", HTTP_ASSEMBLY),
+        format!("/{}/annotations?chrom=chr1&start=100&end=10", HTTP_ASSEMBLY),
+        format!("/{}/annotations?chrom=chr1&start=0&end=10", HTTP_ASSEMBLY),
+        format!(
+            "/{}/annotations?chrom=chr1&start=1&end=100&nrph=maybe",
+            HTTP_ASSEMBLY
+        ),
+        format!("/{}/families?data_type=bogus", HTTP_ASSEMBLY),
+        format!("/{}/families?offset=-1", HTTP_ASSEMBLY),
+    ];
+    for path in &bad_request {
+        let error = server.json(path, StatusCode::BAD_REQUEST);
+        assert!(error["error"].is_string(), "{}", path);
+    }
+}
+
+#[test]
+fn test_http_conditional_requests() {
+    let server = Server::start();
+    let route = format!(
+        "/{}/annotations?chrom=chr1&start=1&end=20000&family=DF000000001",
+        HTTP_ASSEMBLY
+    );
+    let url = format!("{}{}", server.base_url, route);
+    let etag_of = |response: &Response| {
+        response
+            .headers()
+            .get("etag")
+            .map(|v| v.to_str().unwrap().to_string())
+            .expect("No ETag")
+    };
+
+    let response = server.get(&route);
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = etag_of(&response);
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+    let hits: Value = response.json().expect("Cannot Deserialize");
+    assert_eq!(etag_of(&server.get(&route)), etag);
+
+    // A cached response still current is not sent again
+    let conditional = |if_none_match: &str| {
+        server
+            .client
+            .get(&url)
+            .header("If-None-Match", if_none_match)
+            .send()
+            .expect("Request Failed")
+    };
+    for current in [
+        etag.clone(),
+        format!("W/{}", etag),
+        format!("\"x\", {}", etag),
+    ] {
+        let response = conditional(&current);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", current);
+        assert_eq!(etag_of(&response), etag);
+        assert!(response.text().unwrap().is_empty());
+    }
+    let response = conditional("\"stale\"");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<Value>().unwrap(), hits);
+
+    // HEAD counts the hits a GET returns, under the same etag
+    let response = server.client.head(&url).send().expect("Request Failed");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(etag_of(&response), etag);
+    assert_eq!(
+        response.headers().get("x-total-count").map(|v| v
+            .to_str()
+            .unwrap()
+            .parse::<usize>()
+            .unwrap()),
+        Some(hits.as_array().unwrap().len())
+    );
+
+    // Another query is another resource
+    let other = server.get(&format!(
+        "/{}/annotations?chrom=chr1&start=1&end=20000",
+        HTTP_ASSEMBLY
+    ));
+    assert_ne!(etag_of(&other), etag);
+}
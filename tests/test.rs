@@ -1,68 +1,257 @@
 use noodles::bgzf;
-use serde_json::from_str;
-use std::collections::HashMap;
-use std::fs::{read_dir, File};
-use std::io::{BufRead, BufReader};
+use proptest::prelude::{
+    any, prop, prop_assert, prop_assert_eq, prop_oneof, proptest, Just, ProptestConfig, Strategy,
+};
+use serde_json::{from_str, json, Value};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{
+    copy, create_dir_all, read_dir, read_link, read_to_string, remove_file, rename,
+    set_permissions, write, File, Permissions,
+};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
-use te_idx::idx::{build_idx, prep_idx};
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use te_idx::allowlist::{NotPermitted, ALLOWLIST_FILE};
+use te_idx::backpressure::{BoundedWriter, DEFAULT_MAX_BUFFERED_MB};
+use te_idx::benchmark::benchmark_report;
+use te_idx::compact::compact_family;
+use te_idx::contig_order::{contig_order, natural_cmp, CONTIG_ORDER_FILE};
+use te_idx::cooccurrence::{cooccurrence, COOCCURRENCE_TSV_HEADER};
+use te_idx::defragment::{defragment, DEFRAGMENT_TSV_HEADER};
+use te_idx::delta::{apply_delta, assembly_manifest, release_delta, DELTA_FILES_DIR};
+use te_idx::dl::{join_fields, sanitize_field, DlWriter};
+use te_idx::evalue::EValue;
+use te_idx::families::{families_file, FamilyMetadata};
+use te_idx::fasta::{export_family_fasta, genome_fasta_path, reverse_complement, GENOME_DIR};
+use te_idx::fixtures::{prepare_synthetic_assembly, synthetic_genome, write_fasta};
+use te_idx::hmm::{generate_model_lengths, read_hmm_file, read_hmm_models};
+use te_idx::idx::{
+    acc_matches, build_idx, check_index, check_region, clean_indexes, dump_index,
+    family_listing_file, idx_paths, index_count_field, index_format_version, is_versioned_index,
+    iter_records, line_hash, open_index, prep_idx, range_data_offsets, search_idx,
+    search_intervals, sort_records, upgrade_index, verify_record_positions, BuildStats,
+    IndexHandle, NotIndexed, OpenRetry, RegionError, SearchTimeout, DEFAULT_MAX_DUPLICATION,
+    DEFAULT_OPEN_ATTEMPTS, FORMAT_VERSION, HASHED_FORMAT_VERSION, MAX_INDEX_COUNT,
+    OVERFLOW_FORMAT_VERSION, TILE_SIZE,
+};
+use te_idx::layout::{detect_layout, file_layout, repair_layout, CURRENT_LAYOUT};
+use te_idx::manifest::{
+    export_inputs, manifest_path, verify_manifest, write_manifest, MANIFEST_SUFFIX,
+};
+use te_idx::migrate::{
+    check_compatibility, migrate_assembly, read_assembly_meta, write_assembly_meta, AssemblyMeta,
+    MigrationState, Verdict, LAYOUT_VERSION,
+};
+use te_idx::options::{ActionOptions, FilterOptions, IdxQueryOptions, PrepOptions};
+use te_idx::package::{package_assembly, unpack_assembly};
+use te_idx::paths::{join_path, stored_path};
+use te_idx::plan::{
+    finalize_assembly, plan_assembly, run_action, ActionReport, PrepAction, PrepStep,
+};
+use te_idx::querylog::QueryLogger;
+use te_idx::records::{bed_fields, BedRecord, BedRecordStream};
+use te_idx::redundancy::{redundancy_scan, RedundantPair, REDUNDANCY_TSV_HEADER};
+use te_idx::remap::{remap_contig_names, NameMapping};
+use te_idx::roots::{
+    cache_status, configured_data_roots, resolve_root, warm_assembly, CacheStatus, ElementStatus,
+    WarmReport,
+};
+use te_idx::sample::{cigar_parses, sample_records, validate_sample};
+use te_idx::seqlens::{check_seq_lens, repair_seq_lens, sequence_lengths};
+use te_idx::status::{OperationStatus, StatusFile, STATE_COMPLETED, STATE_FAILED, STATE_RUNNING};
+use te_idx::usage::{assembly_usage, AssemblyUsage, OTHER_ELEMENT, USAGE_TOTAL};
 use te_idx::{
-    bgzf_filter, idx_query, json_query, prep_beds, prepare_assembly,
-    read_family_assembly_annotations, ASSEMBLY_DIR, BENCHMARK_DIR, MASKS_DIR, MOD_LEN_DIR,
-    SEQUENCE_DIR,
+    append_records, bed_score, beds_half_open, beds_sorted, bgz_is_empty, bgz_path,
+    bgzf_filter_with, check_partition_key, check_scratch_dir, consolidate_assembly_data,
+    family_combined, family_summary, filter_parameters, filter_worker_count, find_sequences,
+    get_chrom_id, half_open_file, idx_query_into, idx_query_with, iter_assembly, iter_contig,
+    json_query, json_query_all, json_query_many, json_query_match, key_bgz_path, list_assemblies,
+    list_families, misaligned_columns, missing_families, partition_key, prep_beds_with,
+    prepare_assembly, query_etag, read_at, read_family_assembly_annotations, redacts_accessions,
+    regenerate_export, resolve_sequence, seq_len_column, source_info, write_family_combined,
+    Annotation, AssemblyData, BedFormat, ColumnStats, FamilyPage, Formattable, JsonQueryMatch,
+    KeyNormalization, MaskMode, MaskSubtraction, ResultGrouping, SequenceInfo, SequenceResolution,
+    ASSEMBLY_DATA_FILE, ASSEMBLY_DATA_VERSION, ASSEMBLY_DIR, ASSEMBLY_FILE, ASSEMBLY_SOURCE,
+    BENCHMARK_DIR, BENCHMARK_SOURCE, COMBINED_DL_HEADER, DEFAULT_SHARD_THRESHOLD, FAMILIES_DIR,
+    FAMILIES_FILE, FAM_ACC_KEY, INTERNAL_PROFILE, MASKS_DIR, MASKS_FILE, MOD_LEN_DIR, MOD_LEN_FILE,
+    PUBLIC_PROFILE, SEQUENCE_DIR, SEQUENCE_FILE, SEQUENCE_FORMS, SEQ_ACC_KEY, SORTED_FILE,
 };
-// get_chrom_id,
-use tempfile::{NamedTempFile, TempDir};
+use tempfile::TempDir;
+use walkdir::WalkDir;
 
-pub const TEST_DIR: &'static str = "/home/agray/te_idx/tests";
-pub const TEST_DATA_DIR: &'static str = "/home/agray/te_idx/tests/test_data";
-pub const TEST_EXPORT_DIR: &'static str = "/home/agray/te_idx/tests/test_exports";
+pub const TEST_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests");
+pub const TEST_DATA_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_data");
+pub const TEST_EXPORT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_exports");
 
-pub const TEST_ASSEMBLY: &'static str = "test_ex";
+pub const TEST_ASSEMBLY: &str = "test_ex";
 
 fn gen_working_dir() -> TempDir {
-    return TempDir::new_in(TEST_DIR).expect("Error Creating Working Directory");
+    TempDir::new_in(TEST_DIR).expect("Error Creating Working Directory")
+}
+
+// An 18 column assembly_alignments BED line of a hit exported as start..end, start above end
+// on the minus strand, its chromStart and chromEnd written as prep_beds writes them
+fn annotation_line(
+    chrom: &str,
+    start: u64,
+    end: u64,
+    fam: &str,
+    score: &str,
+    strand: &str,
+    nrph: &str,
+) -> String {
+    let (chrom_start, chrom_end) = (start.min(end) - 1, start.max(end));
+    format!(
+        "{chrom}\t{chrom_start}\t{chrom_end}\t{fam}\t{score}\t{strand}\t1.0\t{start}\t{end}\t1\t100\t1e-10\t{nrph}\t10.5\tTEST\t1000000\t100M\tCAF"
+    )
+}
+
+fn write_bed_bgz(path: &str, lines: &[String]) {
+    let mut writer = bgzf::Writer::new(File::create(path).expect("Can't Create File"));
+    for line in lines {
+        writer
+            .write_all(format!("{}\n", line).as_bytes())
+            .expect("Unable to write line");
+    }
+    writer.finish().expect("Unable to finish file");
+}
+
+// As write_bed_bgz, ending a block every block_bytes bytes wherever that falls in a line
+fn write_bed_bgz_blocks(path: &str, lines: &[String], block_bytes: usize) {
+    let mut writer = bgzf::Writer::new(File::create(path).expect("Can't Create File"));
+    let data: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    for block in data.as_bytes().chunks(block_bytes) {
+        writer.write_all(block).expect("Unable to write line");
+        writer.flush().expect("Unable to end block");
+    }
+    writer.finish().expect("Unable to finish file");
+}
+
+// Copies a data type of the test assembly into another data directory
+// The checked in test assembly is layout 0, its BED files holding the exported coordinates.
+// Tests read a copy migrated to the current layout, made once per run and left in the system
+// temporary folder. TEST_DATA_DIR itself is kept for the migration tests.
+fn test_data_dir() -> &'static str {
+    static MIGRATED: OnceLock<String> = OnceLock::new();
+    MIGRATED.get_or_init(|| {
+        let data_directory = TempDir::new()
+            .expect("Error Creating Working Directory")
+            .keep()
+            .to_string_lossy()
+            .into_owned();
+        let source = format!("{}/{}", TEST_DATA_DIR, TEST_ASSEMBLY);
+        for entry in WalkDir::new(&source).into_iter().filter_map(|e| e.ok()) {
+            let target = format!(
+                "{}/{}/{}",
+                data_directory,
+                TEST_ASSEMBLY,
+                entry.path().strip_prefix(&source).unwrap().display()
+            );
+            match entry.file_type().is_dir() {
+                true => create_dir_all(&target).expect("Can't Create Dir"),
+                false => {
+                    copy(entry.path(), &target).expect("Can't Copy File");
+                }
+            }
+        }
+        migrate_assembly(TEST_ASSEMBLY, &None, &None, &data_directory).expect("Migration Failed");
+        data_directory
+    })
+}
+
+fn copy_test_data(data_directory: &str, data_type: &str) {
+    copy_test_data_from(test_data_dir(), data_directory, data_type)
+}
+
+// Copies a data type of the test assembly, with its marker files when it has them
+fn copy_test_data_from(source: &str, data_directory: &str, data_type: &str) {
+    let target_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, data_type);
+    create_dir_all(&target_dir).expect("Can't Create Dir");
+    for entry in read_dir(format!("{}/{}/{}", source, TEST_ASSEMBLY, data_type))
+        .expect("Can't Read Dir")
+        .filter_map(|e| e.ok())
+    {
+        copy(
+            entry.path(),
+            format!("{}/{}", target_dir, entry.file_name().to_string_lossy()),
+        )
+        .expect("Can't Copy File");
+    }
+    let half_open = half_open_file(TEST_ASSEMBLY, data_type, source);
+    if Path::new(&half_open).exists() {
+        copy(
+            &half_open,
+            half_open_file(TEST_ASSEMBLY, data_type, data_directory),
+        )
+        .expect("Can't Copy File");
+    }
+}
+
+// Writes synthetic <name>.bed.bgz files for a data type of the test assembly and indexes them.
+// The lines hold BED coordinates, as annotation_line writes them, and the data type is marked so.
+fn build_test_assembly(data_directory: &str, data_type: &str, files: &[(&str, Vec<String>)]) {
+    let assembly_dir = format!("{}/{}", data_directory, TEST_ASSEMBLY);
+    create_dir_all(format!("{}/{}", assembly_dir, data_type)).expect("Can't Create Dir");
+    for (name, lines) in files {
+        write_bed_bgz(
+            &format!("{}/{}/{}.bed.bgz", assembly_dir, data_type, name),
+            lines,
+        );
+    }
+    File::create(half_open_file(TEST_ASSEMBLY, data_type, data_directory))
+        .expect("Can't Create File");
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(TEST_ASSEMBLY, data_type, false, data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
 }
 
 #[test]
 fn test_bgzf_filter_nrph() {
-    let out_f = NamedTempFile::new_in(TEST_DATA_DIR).expect("Couldn't Open Output File");
-    let f_name = out_f.path().to_str().unwrap();
-
     let assembly = &TEST_ASSEMBLY.to_string();
     let data_type = &ASSEMBLY_DIR.to_string();
     let fam = &"DF000000001".to_string();
     let position: usize = 13;
-    let term: Option<String> = Some("1".to_string());
-    let outfile = Some(f_name.to_string());
+    let mut output = Vec::new();
     let dl_fmt = false;
-    let data_directory = TEST_DATA_DIR.to_string();
+    let data_directory = test_data_dir().to_string();
 
     // Test for NRPH filter
-    match bgzf_filter(
+    match bgzf_filter_with(
         assembly,
         data_type,
         fam,
-        &position,
-        &term,
-        &outfile,
-        dl_fmt,
+        &mut output,
+        &FilterOptions::builder()
+            .term(position, "1")
+            .dl_fmt(dl_fmt)
+            .build(),
         &data_directory,
     ) {
-        Ok(()) => {
-            let orig_count = BufReader::new(
+        Ok(count) => {
+            let orig_count = bgzf::Reader::new(
                 File::open(format!(
                     "{}/{}/{}/{}.bed.bgz",
-                    TEST_DATA_DIR, TEST_ASSEMBLY, ASSEMBLY_DIR, fam
+                    test_data_dir(),
+                    TEST_ASSEMBLY,
+                    ASSEMBLY_DIR,
+                    fam
                 ))
                 .expect("Can't Open File"),
             )
             .lines()
             .count();
-            let filter_count = BufReader::new(File::open(out_f).expect("Can't Open File"))
-                .lines()
-                .count();
-            // check that filtered file is smaller and contains expected lines
-            assert_eq!(filter_count, 20959);
+            let filter_count = output.lines().count();
+            // check that filtered file is smaller and contains the NRPH records plus the header
+            assert_eq!(filter_count, 195024);
+            assert_eq!(count, filter_count - 1);
             assert_ne!(orig_count, filter_count);
         }
         Err(e) => panic!("{}", e),
@@ -71,41 +260,40 @@ fn test_bgzf_filter_nrph() {
 
 #[test]
 fn test_bgzf_filter_dl_fmt() {
-    let out_f = NamedTempFile::new_in(TEST_DATA_DIR).expect("Couldn't Open Output File");
-    let f_name = out_f.path().to_str().unwrap();
-
     let assembly = &TEST_ASSEMBLY.to_string();
     let data_type = &ASSEMBLY_DIR.to_string();
     let fam = &"DF000000001".to_string();
     let position = 7;
-    let term = Some("14.7".to_string());
-    let outfile = Some(f_name.to_string());
+    let mut output = Vec::new();
     let dl_fmt = true;
-    let data_directory = TEST_DATA_DIR.to_string();
+    let data_directory = test_data_dir().to_string();
 
     // Test for download format
-    match bgzf_filter(
+    match bgzf_filter_with(
         assembly,
         data_type,
         fam,
-        &position,
-        &term,
-        &outfile,
-        dl_fmt,
+        &mut output,
+        &FilterOptions::builder()
+            .term(position, "14.7")
+            .dl_fmt(dl_fmt)
+            .build(),
         &data_directory,
     ) {
-        Ok(()) => {
+        Ok(_) => {
             let orig_count = BufReader::new(
                 File::open(format!(
                     "{}/{}/{}/{}.bed.bgz",
-                    TEST_DATA_DIR, TEST_ASSEMBLY, ASSEMBLY_DIR, fam
+                    test_data_dir(),
+                    TEST_ASSEMBLY,
+                    ASSEMBLY_DIR,
+                    fam
                 ))
                 .expect("Can't Open File"),
             )
             .lines()
             .count();
-            let mut filter_lines =
-                bgzf::Reader::new(File::open(out_f).expect("Can't Open File")).lines();
+            let mut filter_lines = output.lines();
 
             let first = filter_lines
                 .next()
@@ -129,14 +317,374 @@ fn test_bgzf_filter_dl_fmt() {
     }
 }
 
+#[test]
+fn test_bgzf_filter_profiles() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            fam,
+            vec![
+                annotation_line("chr1", 100, 500, fam, "300", "+", "0"),
+                annotation_line("chr1", 2600, 2000, fam, "250", "-", "1"),
+            ],
+        )],
+    );
+
+    let filter = |profile: &str, exclude_columns: &[String]| {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            fam,
+            &mut output,
+            &FilterOptions::builder()
+                .profile(profile)
+                .exclude_columns(exclude_columns)
+                .build(),
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        let lines: Vec<Vec<String>> = output
+            .lines()
+            .map(|l| l.unwrap().split('\t').map(|f| f.to_string()).collect())
+            .collect();
+        lines
+    };
+
+    let public = filter(PUBLIC_PROFILE, &[]);
+    let header = &public[0];
+    assert!(!header
+        .iter()
+        .any(|c| c.contains("cigar") || c.contains("caf")));
+    assert_eq!(header.len(), 16);
+    for row in &public[1..] {
+        assert_eq!(row.len(), header.len());
+        assert!(!row.contains(&"100M".to_string()) && !row.contains(&"CAF".to_string()));
+    }
+
+    let internal = filter(INTERNAL_PROFILE, &[]);
+    assert_eq!(internal[0].len(), 18);
+    assert_eq!(internal[0][16..], ["*cigar", "*caf"]);
+    assert_eq!(internal[1][16..], ["100M", "CAF"]);
+
+    let trimmed = filter(INTERNAL_PROFILE, &["bias".to_string(), "caf".to_string()]);
+    assert_eq!(trimmed[0].len(), 16);
+    assert!(!trimmed[0].contains(&"bias".to_string()));
+    assert_eq!(trimmed[0].last().unwrap(), "*cigar");
+    assert!(trimmed[1..].iter().all(|row| row.len() == 16));
+}
+
+// Option values a filter or query cannot apply are InvalidInput errors, not panics
+fn assert_invalid_option<T>(result: std::io::Result<T>, message: &str) {
+    let err = result.err().expect("Invalid Option Accepted");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains(message), "{}", err);
+}
+
+#[test]
+fn test_bgzf_filter_unknown_column() {
+    assert_invalid_option(
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            MASKS_DIR,
+            "chr1",
+            &mut Vec::new(),
+            &FilterOptions::builder()
+                .exclude_columns(&["cigars".to_string()])
+                .build(),
+            test_data_dir(),
+        ),
+        "Unknown Column \"cigars\"",
+    );
+    assert_invalid_option(
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            MASKS_DIR,
+            "chr1",
+            &mut Vec::new(),
+            &FilterOptions::builder().profile("secret").build(),
+            test_data_dir(),
+        ),
+        "Invalid Column Profile \"secret\"",
+    );
+    assert_invalid_option(
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            MASKS_DIR,
+            "chr1",
+            &mut Vec::new(),
+            &FilterOptions::builder().strand("+").build(),
+            test_data_dir(),
+        ),
+        "Masks Have No Strand Column",
+    );
+}
+
+#[test]
+fn test_bgzf_filter_masks_key() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let masks = vec![
+        "chr1\t900\t1000\tTC\t2".to_string(),
+        "chr1\t2150\t2300\tA\t1".to_string(),
+        "chr1\t2950\t3050\tTC\t2".to_string(),
+    ];
+    build_test_assembly(&data_directory, MASKS_DIR, &[("chr1", masks)]);
+    assert_eq!(partition_key(MASKS_DIR), SEQ_ACC_KEY);
+    assert_eq!(partition_key(ASSEMBLY_DIR), FAM_ACC_KEY);
+    assert_eq!(partition_key(BENCHMARK_DIR), FAM_ACC_KEY);
+
+    let filter = |key: &str, term: Option<String>| {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            MASKS_DIR,
+            key,
+            &mut output,
+            &FilterOptions {
+                position: 4,
+                term,
+                ..FilterOptions::default()
+            },
+            &data_directory,
+        )
+        .map(|count| (count, String::from_utf8(output).unwrap()))
+    };
+
+    // Masks files are keyed by sequence accession
+    let (count, output) = filter("chr1", Some("TC".to_string())).expect("Filter Failed");
+    assert_eq!(count, 2);
+    assert!(output.lines().skip(1).all(|l| l.starts_with("chr1\t")));
+
+    // A family accession can never name a masks file
+    let err = filter("DF000000001", None).expect_err("Family Accession Accepted For Masks");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains(SEQ_ACC_KEY));
+    assert!(check_partition_key(ASSEMBLY_DIR, "DF000000001").is_ok());
+    assert!(check_partition_key(MASKS_DIR, "DFAM_chr1").is_ok());
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_build_idx_sparse_contig() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let chrom = "chrBig";
+    // Three records, the middle one spanning three tiles, on a 2 Gb contig
+    let records = [
+        (100, 200),
+        (1_000_000_000, 1_000_040_000),
+        (2_000_000_000, 2_000_000_100),
+    ];
+    let mask_dir = format!("{}/{}/{}", data_directory, assembly, data_type);
+    create_dir_all(&mask_dir).expect("Can't Create Dir");
+    write_bed_bgz(
+        &format!("{}/{}.bed.bgz", mask_dir, chrom),
+        &records
+            .iter()
+            .map(|(start, end)| format!("{}\t{}\t{}\t(CA)n\t{}", chrom, start, end, end - start))
+            .collect::<Vec<String>>(),
+    );
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    // Only the tiles holding ranges were allocated, out of the 122k the contig spans
+    let tile_count = (2_000_000_100 - 1) / TILE_SIZE as usize + 1;
+    assert_eq!(contig_index.built_tiles(), (5, tile_count));
+    check_index(&index_file).expect("Index Is Malformed");
+
+    let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    let search = |start: u64, end: u64| {
+        handle
+            .search(
+                chrom, start, end, &None, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed")
+            .len()
+    };
+    assert_eq!(search(1, 1000), 1);
+    assert_eq!(search(1_000_030_000, 1_000_030_010), 1);
+    assert_eq!(search(1_500_000_000, 1_600_000_000), 0);
+    assert_eq!(search(1_999_999_000, 2_000_001_000), 1);
+    assert_eq!(search(1, 2_100_000_000), 3);
+}
+
+#[test]
+fn test_build_idx_duplication() {
+    let stats = BuildStats {
+        ranges: 10,
+        stored_ranges: 70,
+        overflow_ranges: 0,
+        max_span_tiles: 20,
+        total_span_tiles: 70,
+    };
+    assert_eq!(stats.duplication_factor(), 7.0);
+    assert_eq!(stats.mean_span_tiles(), 7.0);
+    // (7 - 1) / (4 - 1) times the tile size
+    assert_eq!(stats.suggested_tile_size(TILE_SIZE, 4.0), 2 * TILE_SIZE);
+    assert_eq!(stats.suggested_tile_size(TILE_SIZE, 7.0), TILE_SIZE);
+    assert_eq!(BuildStats::default().duplication_factor(), 1.0);
+
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let chrom = "chrLong".to_string();
+    // Eight 300 kb features spanning about 19 tiles each, among short ones
+    let mut records: Vec<(u64, u64)> = (0..8)
+        .map(|i| (250_000 + i * 500_000, 550_000 + i * 500_000))
+        .chain((0..20).map(|j| (500 + j * 200_000, 600 + j * 200_000)))
+        .collect();
+    records.sort();
+    let mask_dir = format!("{}/{}/{}", data_directory, assembly, data_type);
+    create_dir_all(&mask_dir).expect("Can't Create Dir");
+    write_bed_bgz(
+        &format!("{}/{}.bed.bgz", mask_dir, chrom),
+        &records
+            .iter()
+            .map(|(start, end)| format!("{}\t{}\t{}\t(CA)n\t{}", chrom, start, end, end - start))
+            .collect::<Vec<String>>(),
+    );
+    let tile_size = TILE_SIZE as u64;
+    let spans: Vec<u64> = records
+        .iter()
+        .map(|(start, end)| (end - 1) / tile_size - start / tile_size + 1)
+        .collect();
+    let long_spans: Vec<u64> = spans.iter().copied().filter(|span| *span > 4).collect();
+    assert_eq!(long_spans.len(), 8);
+
+    let build = |overflow_tiles: Option<usize>| {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+        if let Some(overflow_tiles) = overflow_tiles {
+            contig_index.set_overflow_tiles(overflow_tiles);
+        }
+        build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+        check_index(&index_file).expect("Index Is Malformed");
+        (contig_index, index_file)
+    };
+    let contents = || {
+        let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+            .expect("Can't Open Index");
+        let regions = [
+            (1, 100_000),
+            (400_000, 410_000),
+            (1_000_000, 1_001_000),
+            (3_950_000, 3_960_000),
+            (3_900_000, 4_500_000),
+            (1, 5_000_000),
+        ];
+        let hits: Vec<Vec<String>> = regions
+            .iter()
+            .map(|(start, end)| {
+                handle
+                    .search(
+                        &chrom, *start, *end, &None, false, &None, None, None, None, &None,
+                    )
+                    .expect("Index Search Failed")
+            })
+            .collect();
+        let records: Vec<String> = iter_records(
+            assembly,
+            data_type,
+            std::slice::from_ref(&chrom),
+            false,
+            &None,
+            false,
+            &data_directory,
+        )
+        .expect("Can't Iterate Records")
+        .map(|record| record.expect("Can't Read Record"))
+        .collect();
+        let mut intervals = search_intervals(
+            assembly,
+            data_type,
+            &chrom,
+            300_000,
+            700_000,
+            &data_directory,
+        )
+        .expect("Interval Search Failed");
+        intervals.sort();
+        intervals.dedup();
+        (hits, records, intervals)
+    };
+
+    // Every range in every tile it overlaps, well over the default maximum duplication
+    let (contig_index, index_file) = build(None);
+    let stats = contig_index.build_stats();
+    assert_eq!(stats.ranges, 28);
+    assert_eq!(stats.stored_ranges, spans.iter().sum::<u64>());
+    assert_eq!(stats.max_span_tiles, *spans.iter().max().unwrap());
+    assert_eq!(stats.overflow_ranges, 0);
+    assert!(stats.duplication_factor() > DEFAULT_MAX_DUPLICATION);
+    let suggested = stats.suggested_tile_size(TILE_SIZE, DEFAULT_MAX_DUPLICATION);
+    assert!(suggested > TILE_SIZE);
+    let warning = contig_index
+        .duplication_warning()
+        .expect("No Duplication Warning");
+    assert!(warning.contains(&format!("Tile Size Of {}", suggested)));
+    assert_eq!(index_format_version(&index_file).unwrap(), FORMAT_VERSION);
+    let tiled_bytes = std::fs::metadata(&index_file).unwrap().len();
+    let tiled = contents();
+    assert_eq!(tiled.0[3].len(), 1);
+    assert_eq!(tiled.1.len(), 28);
+
+    // Long ranges stored once, with the same hits, records and intervals as before
+    let (contig_index, index_file) = build(Some(4));
+    let stats = contig_index.build_stats();
+    assert_eq!(stats.overflow_ranges, 8);
+    assert_eq!(
+        stats.stored_ranges,
+        8 + spans.iter().filter(|span| **span <= 4).sum::<u64>()
+    );
+    assert!(contig_index.duplication_warning().is_none());
+    assert_eq!(
+        index_format_version(&index_file).unwrap(),
+        OVERFLOW_FORMAT_VERSION
+    );
+    assert!(std::fs::metadata(&index_file).unwrap().len() < tiled_bytes);
+    assert_eq!(contents(), tiled);
+
+    let mut summary = Vec::new();
+    dump_index(
+        assembly,
+        data_type,
+        &Some(chrom.clone()),
+        &None,
+        true,
+        &mut summary,
+        &data_directory,
+    )
+    .expect("Dump Failed");
+    assert!(String::from_utf8(summary)
+        .unwrap()
+        .lines()
+        .any(|line| line == "chrLong\toverflow\t8"));
+
+    // Rewriting keeps the overflow lists
+    let upgraded = format!("{}/upgraded_idx.dat", data_directory);
+    upgrade_index(&index_file, &upgraded).expect("Upgrade Failed");
+    assert_eq!(check_index(&upgraded).unwrap(), OVERFLOW_FORMAT_VERSION);
+}
+
 #[test]
 fn test_build_idx() {
-    let data_dir = TEST_DATA_DIR;
+    let data_dir = test_data_dir();
     let assembly = TEST_ASSEMBLY;
     let data_type = &MASKS_DIR.to_string();
 
     let (filenames, bgz_dir, mut contig_index, index_file) =
-        match prep_idx(&format!("{}/{}", &data_dir, &assembly), data_type) {
+        match prep_idx(assembly, data_type, false, data_dir) {
             Ok(res) => res,
             Err(e) => panic!(
                 "Search Prep Failed, Assembly or Data Type May Not Exist - {:?}",
@@ -144,11 +692,8 @@ fn test_build_idx() {
             ),
         };
     assert_eq!(filenames.len(), 19);
-    assert_eq!(bgz_dir, "/home/agray/te_idx/tests/test_data/test_ex/masks");
-    assert_eq!(
-        index_file,
-        "/home/agray/te_idx/tests/test_data/test_ex/masks_idx.dat"
-    );
+    assert_eq!(bgz_dir, format!("{}/test_ex/masks", data_dir));
+    assert_eq!(index_file, format!("{}/test_ex/masks_idx.dat", data_dir));
 
     let working_directory = gen_working_dir();
     let test_data_dir = working_directory.path().to_str().unwrap();
@@ -162,155 +707,8372 @@ fn test_build_idx() {
 }
 
 #[test]
-fn test_idx_query() {
+fn test_deterministic_index() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
     let assembly = &TEST_ASSEMBLY.to_string();
     let data_type = &ASSEMBLY_DIR.to_string();
-    let chrom = &"chr10".to_string();
-    let start = 10000;
-    let end = 100000;
-    let family: &Option<String> = &None;
-    let nrph = &false;
-    let data_directory = TEST_DATA_DIR.to_string();
+    let families = ["DF000000003", "DF000000001", "DF000000002"];
+    let file_lines = |fam: &str| -> Vec<String> {
+        (0..30)
+            .map(|i| annotation_line("chr1", 100 + i * 900, 900 + i * 900, fam, "25.5", "+", "1"))
+            .collect()
+    };
+    let files: Vec<(&str, Vec<String>)> =
+        families.iter().map(|fam| (*fam, file_lines(fam))).collect();
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &files);
+    let bgz_path = |fam: &str| {
+        format!(
+            "{}/{}/{}/{}.bed.bgz",
+            data_directory, assembly, data_type, fam
+        )
+    };
+    // Touching every file changes its modification time and nothing else
+    let touch = |seconds: u64| {
+        for fam in families {
+            File::options()
+                .write(true)
+                .open(bgz_path(fam))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+                .expect("Can't Set Modification Time");
+        }
+    };
+    let build = |deterministic: bool| {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+        contig_index.set_deterministic(deterministic);
+        build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+        let bytes = std::fs::read(&index_file).expect("Can't Read Index");
+        (index_file, format!("{:x}", Sha256::digest(&bytes)))
+    };
 
-    let res1 = idx_query(
-        assembly,
-        data_type,
-        chrom,
-        start,
-        end,
-        family,
-        nrph,
-        &data_directory,
-    )
-    .expect("Index Query Failed");
-    let vals1: Vec<HashMap<String, String>> = from_str(&res1).expect("Cannot Deserialize");
-    assert_eq!(vals1.len(), 4);
+    touch(1_600_000_000);
+    let (index_file, first) = build(true);
+    assert_eq!(
+        index_format_version(&index_file).unwrap(),
+        HASHED_FORMAT_VERSION
+    );
+    assert_eq!(check_index(&index_file).unwrap(), HASHED_FORMAT_VERSION);
+    touch(1_700_000_000);
+    let (_, second) = build(true);
+    assert_eq!(first, second);
+    // Modification times make the default index differ
+    let (_, plain_first) = build(false);
+    touch(1_600_000_000);
+    let (_, plain_second) = build(false);
+    assert_ne!(plain_first, plain_second);
+
+    // A file written again after the index, but unchanged, is not reported modified
+    let (_, rebuilt) = build(true);
+    assert_eq!(rebuilt, first);
+    let query = || {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            100000,
+            &IdxQueryOptions::builder().with_warnings(true).build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str::<Value>(&res).expect("Cannot Deserialize")
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    write_bed_bgz(&bgz_path(families[0]), &file_lines(families[0]));
+    let res = query();
+    assert_eq!(res["hits"].as_array().unwrap().len(), 90);
+    assert_eq!(res["warnings"], Value::Array(vec![]));
+
+    // One whose records changed is
+    let mut changed = file_lines(families[0]);
+    changed[0] = annotation_line("chr1", 100, 900, families[0], "26.5", "+", "1");
+    write_bed_bgz(&bgz_path(families[0]), &changed);
+    let warnings = query()["warnings"].as_array().unwrap().clone();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0]
+        .as_str()
+        .unwrap()
+        .contains(&format!("{}.bed.bgz has been modified", families[0])));
+
+    // Upgrading keeps the hashes
+    let upgraded = format!("{}/upgraded_idx.dat", data_directory);
+    upgrade_index(&index_file, &upgraded).expect("Upgrade Failed");
+    assert_eq!(check_index(&upgraded).unwrap(), HASHED_FORMAT_VERSION);
+    assert_eq!(
+        format!(
+            "{:x}",
+            Sha256::digest(std::fs::read(&upgraded).expect("Can't Read Index"))
+        ),
+        first
+    );
 }
 
 #[test]
-fn test_idx_query_fam() {
+fn test_build_idx_save_error() {
+    let (filenames, bgz_dir, mut contig_index, _) =
+        prep_idx(TEST_ASSEMBLY, MASKS_DIR, false, test_data_dir()).expect("Index Prep Failed");
+    let working_directory = gen_working_dir();
+    let missing_dir_index = format!(
+        "{}/missing/masks_idx.dat",
+        working_directory.path().display()
+    );
+    assert!(build_idx(&filenames, &bgz_dir, &mut contig_index, &missing_dir_index).is_err());
+}
+
+#[test]
+fn test_range_data_offsets() {
+    let max = u32::MAX as u64;
+    // Two contigs, 5 tiles, header of 20 + 2*56 + 2*44 + 5*4 bytes
+    let header = 20 + 2 * 56 + 2 * 44 + 5 * 4;
+    let offsets = range_data_offsets(2, &[3, 2], &[vec![u32::MAX, u32::MAX, 0], vec![1, 5]]);
+    assert_eq!(
+        offsets,
+        vec![
+            vec![header, header + max * 28, header + 2 * max * 28],
+            vec![header + 2 * max * 28, header + 2 * max * 28 + 28],
+        ]
+    );
+    assert!(offsets[1][1] > 50 * max);
+
+    // Header fields at their limits alone push the range data past 4 GB
+    let offsets = range_data_offsets(u32::MAX, &[u32::MAX], &[vec![0]]);
+    assert_eq!(offsets[0][0], 20 + max * 56 + 44 + max * 4);
+}
+
+#[test]
+fn test_bulk_tile_reads() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    // Hits in tiles 0, 1, 2, 4 and 5, tile 3 is left empty
+    let lines = [0, 1, 2, 4, 5]
+        .iter()
+        .flat_map(|tile| {
+            let start = tile * 16384 + 100;
+            vec![
+                annotation_line("chr1", start, start + 400, fam, "300", "+", "0"),
+                annotation_line("chr1", start + 2600, start + 2000, fam, "250", "-", "1"),
+            ]
+        })
+        .collect();
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines)]);
+
+    let search = |bulk_read_limit: Option<usize>| {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(TEST_ASSEMBLY, ASSEMBLY_DIR, false, &data_directory)
+                .expect("Index Prep Failed");
+        if let Some(limit) = bulk_read_limit {
+            contig_index.set_bulk_read_limit(limit);
+        }
+        let results = search_idx(
+            &filenames,
+            &bgz_dir,
+            &mut contig_index,
+            &index_file,
+            "chr1",
+            1,
+            100000,
+            &None,
+            false,
+            &None,
+            None,
+            None,
+            None,
+            &None,
+            false,
+            true,
+        )
+        .expect("Search Failed");
+        (results, contig_index.tile_reads())
+    };
+
+    let (per_tile, per_tile_reads) = search(Some(0));
+    let (bulk, bulk_reads) = search(None);
+    assert_eq!(per_tile.len(), 10);
+    assert_eq!(per_tile, bulk);
+    assert_eq!(per_tile_reads, 5);
+    assert_eq!(bulk_reads, 1);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_index_count_field() {
+    assert_eq!(
+        index_count_field(MAX_INDEX_COUNT as usize, "Range").unwrap(),
+        u32::MAX
+    );
+    let err = index_count_field(MAX_INDEX_COUNT as usize + 1, "Range (chr1)").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "Range (chr1) Count 4294967296 Exceeds The Index Format Limit Of 4294967295"
+    );
+}
+
+#[test]
+fn test_dump_index() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
     let assembly = &TEST_ASSEMBLY.to_string();
-    let data_type = &ASSEMBLY_DIR.to_string();
-    let chrom = &"chr10".to_string();
-    let start = 10000;
-    let end = 100000;
-    let family: &Option<String> = &Some("DF000000001".to_string());
-    let nrph = &false;
-    let data_directory = TEST_DATA_DIR.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    copy_test_data(&data_directory, MASKS_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    let dump = |contig: Option<&str>, tile: Option<usize>, summary: bool| {
+        let mut out = Vec::new();
+        let lines = dump_index(
+            assembly,
+            data_type,
+            &contig.map(|c| c.to_string()),
+            &tile,
+            summary,
+            &mut out,
+            &data_directory,
+        )
+        .expect("Dump Failed");
+        let text = String::from_utf8(out).expect("Output Is Not UTF-8");
+        assert_eq!(text.lines().count(), lines + 1);
+        text
+    };
 
-    let res = idx_query(
+    // Tile 1 of chr1 holds the first mask, chr1:30863-30959, stored from chromStart 30862
+    let tile = dump(Some("chr1"), Some(1), false);
+    let mut rows = tile.lines();
+    assert_eq!(
+        rows.next(),
+        Some("#contig\ttile\tstart\tend\tbed_idx\tfile\tbgzf_pos")
+    );
+    let rows: Vec<Vec<&str>> = rows.map(|row| row.split('\t').collect()).collect();
+    assert!(!rows.is_empty());
+    assert!(rows.iter().all(|row| row[0] == "chr1" && row[1] == "1"));
+    let first = &rows[0];
+    assert_eq!(first[2..4], ["30862", "30959"]);
+    assert_eq!(first[5], "chr1.bed.bgz");
+
+    // The range points at the record idx_query returns for it
+    let bgz_path = format!("{}/{}", bgz_dir, first[5]);
+    let peeked = read_at(&bgz_path, first[6].parse().unwrap(), data_type).expect("Read Failed");
+    assert!(peeked.raw.starts_with("chr1\t30862\t30959\t"));
+    let res = idx_query_with(
         assembly,
         data_type,
-        chrom,
-        start,
-        end,
-        family,
-        nrph,
+        "chr1",
+        30863,
+        30959,
+        &IdxQueryOptions::default(),
         &data_directory,
     )
     .expect("Index Query Failed");
-    println!("{:?}", res);
-    let vals: Vec<HashMap<String, String>> = from_str(&res).expect("Cannot Deserialize");
-    assert_eq!(vals.len(), 4);
+    let hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert!(hits.iter().any(|hit| hit["seq_start"] == 30863
+        && hit["seq_end"] == 30959
+        && hit["repeat_str"] == peeked.record["repeat_str"]));
+
+    let summary = dump(Some("chr1"), None, true);
+    assert!(summary.contains(&format!("\nchr1\t1\t{}\n", rows.len())));
+    assert!(dump(None, Some(1), true)
+        .lines()
+        .skip(1)
+        .all(|row| row.split('\t').nth(1) == Some("1")));
+
+    let mut out = Vec::new();
+    assert!(dump_index(
+        assembly,
+        data_type,
+        &Some("chrNone".to_string()),
+        &None,
+        false,
+        &mut out,
+        &data_directory
+    )
+    .is_err());
+
+    let _ = working_dir.close();
 }
 
 #[test]
-fn test_idx_query_nrph() {
-    let assembly = &TEST_ASSEMBLY.to_string();
+fn test_sample_records() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
     let data_type = &ASSEMBLY_DIR.to_string();
-    let chrom = &"chr10".to_string();
-    let start = 10000;
-    let end = 100000;
-    let family: &Option<String> = &Some("DF000000001".to_string());
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
 
-    let nrph = &true;
-    let data_directory = TEST_DATA_DIR.to_string();
+    // Every record with its position, by a scan of the files
+    let bgz_dir = format!("{}/{}/{}", data_directory, assembly, data_type);
+    let mut scanned = HashSet::new();
+    for entry in WalkDir::new(&bgz_dir).into_iter().filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".bed.bgz") {
+            continue;
+        }
+        for (line, position) in read_line_records(entry.path().to_str().unwrap()) {
+            scanned.insert((name.clone(), line.trim_end().to_string(), position));
+        }
+    }
+    assert_eq!(scanned.len(), synth.hits.len());
+
+    let sample = |n: usize, seed: u64| {
+        sample_records(assembly, data_type, n, seed, &data_directory).expect("Sampling Failed")
+    };
+    let drawn = sample(50, 7);
+    assert_eq!(drawn.len(), 50);
+    for record in &drawn {
+        assert!(scanned.contains(&(
+            record.file.clone(),
+            record.raw.clone(),
+            record.virtual_position
+        )));
+        assert_eq!(record.record["seq_id"], json!(record.contig));
+    }
+    let distinct: HashSet<(String, u64)> = drawn
+        .iter()
+        .map(|record| (record.file.clone(), record.virtual_position))
+        .collect();
+    assert_eq!(distinct.len(), drawn.len());
+    // The same seed draws the same records, another seed others
+    assert_eq!(sample(50, 7), drawn);
+    assert_ne!(sample(50, 8), drawn);
+
+    // Asking for more than there are gives every record once
+    let all = sample(synth.hits.len() + 10, 1);
+    assert_eq!(all.len(), synth.hits.len());
+    let all: HashSet<(String, String, u64)> = all
+        .into_iter()
+        .map(|record| (record.file, record.raw, record.virtual_position))
+        .collect();
+    assert_eq!(all, scanned);
+    assert!(sample(0, 1).is_empty());
 
-    let res = idx_query(
+    let validation =
+        validate_sample(assembly, data_type, &drawn, &data_directory).expect("Validation Failed");
+    assert_eq!(validation.checked, 50);
+    assert!(validation.sequences_checked);
+    assert_eq!(validation.passed, 50);
+    assert_eq!(validation.pass_rate, 1.0);
+
+    // A record out of its sequence and with a broken CIGAR fails both checks
+    let mut broken = drawn[0].clone();
+    let mut fields: Vec<String> = broken.raw.split('\t').map(str::to_string).collect();
+    fields[2] = (fields[15].parse::<u64>().unwrap() + 1).to_string();
+    fields[16] = "12Q".to_string();
+    broken.raw = fields.join("\t");
+    let validation = validate_sample(
         assembly,
         data_type,
-        chrom,
-        start,
-        end,
-        family,
-        nrph,
+        &[broken, drawn[1].clone()],
         &data_directory,
     )
-    .expect("Index Query Failed");
-    let vals: Vec<HashMap<String, String>> = from_str(&res).expect("Cannot Deserialize");
-    assert_eq!(vals.len(), 4);
+    .expect("Validation Failed");
+    assert_eq!(validation.passed, 1);
+    assert_eq!(validation.pass_rate, 0.5);
+    assert_eq!(validation.failures[0].reasons.len(), 2);
+    assert!(cigar_parses("10M2I5M") && cigar_parses(""));
+    assert!(!cigar_parses("M10") && !cigar_parses("10"));
+
+    let _ = working_directory.close();
 }
 
 #[test]
-fn test_json_query() {
-    let assembly = &TEST_ASSEMBLY.to_string();
-    let data_type = &SEQUENCE_DIR.to_string();
-    let key = &"chr1".to_string();
-    let target = &Some("length".to_string());
-    let data_directory = TEST_DATA_DIR.to_string();
+fn test_check_seq_lens() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
+    let check =
+        check_seq_lens(assembly, data_type, None, 3, &data_directory).expect("Check Failed");
+    assert_eq!(check.checked, synth.hits.len());
+    assert_eq!((check.mismatched, check.unknown), (0, 0));
+    assert!(check.contigs.is_empty());
 
-    let ans =
-        json_query(assembly, data_type, key, target, &data_directory).expect("JSON Read Failed");
-    assert_eq!(ans, "248956422");
+    // The sequences JSON of another patch level, one contig longer
+    let (contig, length) = synth.contigs[0].clone();
+    let doctored = length + 7;
+    let sequences_file = format!(
+        "{}/{}/{}/{}-{}.json",
+        data_directory, assembly, SEQUENCE_DIR, assembly, SEQUENCE_DIR
+    );
+    let mut sequences: Value =
+        from_str(&read_to_string(&sequences_file).expect("Can't Read JSON")).unwrap();
+    sequences["data"][&contig]["length"] = json!(doctored);
+    write(&sequences_file, sequences.to_string()).expect("Can't Write JSON");
+    assert_eq!(
+        sequence_lengths(assembly, &data_directory).unwrap()[&contig],
+        doctored
+    );
+    let on_contig = synth
+        .hits
+        .iter()
+        .filter(|hit| hit.seq_acc == contig)
+        .count();
+    assert!(on_contig > 3);
 
-    let just_key =
-        json_query(assembly, data_type, key, &None, &data_directory).expect("JSON Read Failed");
-    assert_eq!(&just_key, "1");
+    let check =
+        check_seq_lens(assembly, data_type, None, 3, &data_directory).expect("Check Failed");
+    assert_eq!(check.checked, synth.hits.len());
+    assert_eq!(check.mismatched, on_contig);
+    assert_eq!(check.contigs.len(), 1);
+    assert_eq!(check.contigs[0].contig, contig);
+    assert_eq!(check.contigs[0].json_length, doctored);
+    assert_eq!(check.contigs[0].mismatched, on_contig);
+    assert_eq!(check.contigs[0].examples.len(), 3);
+    for example in &check.contigs[0].examples {
+        assert_eq!(example.stored, length.to_string());
+        assert!(example.raw.starts_with(&format!("{}\t", contig)));
+    }
+    // A sample only finds the mismatches it draws
+    let sampled = check_seq_lens(assembly, data_type, Some((50, 3)), 3, &data_directory)
+        .expect("Check Failed");
+    assert!(sampled.sampled);
+    assert_eq!(sampled.checked, 50);
+    assert!(sampled.mismatched <= on_contig);
+    assert!(check_seq_lens(assembly, MASKS_DIR, None, 3, &data_directory).is_err());
 
-    let bad_key = json_query(
+    // Queries add the JSON length only when asked for it
+    let seq_lens = |json_seq_len: bool| -> Vec<Option<u64>> {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            &contig,
+            1,
+            length,
+            &IdxQueryOptions::builder()
+                .json_seq_len(json_seq_len)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        assert_eq!(hits.len(), on_contig);
+        hits.iter()
+            .map(|hit| hit.get("seq_len").and_then(Value::as_u64))
+            .collect()
+    };
+    assert!(seq_lens(false).iter().all(Option::is_none));
+    // The BED files keep storing their own
+    let stored_seq_lens = || -> Vec<u64> {
+        let column = seq_len_column(data_type).unwrap();
+        read_dir(join_path(&data_directory, &[assembly, data_type]))
+            .expect("Can't Read Dir")
+            .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+            .filter(|path| path.ends_with(".bed.bgz"))
+            .flat_map(|path| read_line_records(&path))
+            .filter(|(line, _)| bed_fields(line)[0] == contig)
+            .map(|(line, _)| bed_fields(&line)[column].parse().unwrap())
+            .collect()
+    };
+    assert_eq!(stored_seq_lens(), vec![length; on_contig]);
+    assert!(seq_lens(true)
+        .iter()
+        .all(|seq_len| *seq_len == Some(doctored)));
+    // BED lines have no seq_len column, asking for the JSON lengths there is refused
+    let err = idx_query_with(
         assembly,
         data_type,
-        &"bad".to_string(),
-        &None,
+        &contig,
+        1,
+        length,
+        &IdxQueryOptions::builder()
+            .json_seq_len(true)
+            .bed_format(BedFormat::Bed6)
+            .build(),
         &data_directory,
     )
-    .expect("JSON Read Failed");
-    assert_eq!(&bad_key, "-1");
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("JSON Sequence Lengths"));
+
+    let repaired = repair_seq_lens(assembly, data_type, 3, &data_directory).expect("Repair Failed");
+    assert_eq!(repaired.records, on_contig);
+    assert!(!repaired.repaired.is_empty());
+    assert!(repaired.indexed);
+    let check =
+        check_seq_lens(assembly, data_type, None, 3, &data_directory).expect("Check Failed");
+    assert_eq!((check.checked, check.mismatched), (synth.hits.len(), 0));
+    assert_eq!(stored_seq_lens(), vec![doctored; on_contig]);
+    // Nothing left to repair
+    let again = repair_seq_lens(assembly, data_type, 3, &data_directory).expect("Repair Failed");
+    assert!(again.repaired.is_empty() && !again.indexed);
+
+    let _ = working_directory.close();
 }
 
 #[test]
-fn test_prep_beds() {
-    let working_directory = gen_working_dir();
+fn test_bed_record_stream() {
+    let working_dir = gen_working_dir();
+    let path = format!("{}/records.bed.bgz", working_dir.path().display());
+    let mut writer = bgzf::Writer::new(File::create(&path).expect("Can't Create File"));
+    // An empty last field, a CRLF ending and a last line without an ending
+    writer
+        .write_all(b"chr1\t1\t2\tA\t\nchr1\t3\t4\tB\t1\r\nchr1\t5\t6\tC\t2")
+        .expect("Unable to write line");
+    writer.finish().expect("Unable to finish");
 
-    let assembly = &TEST_ASSEMBLY.to_string();
-    let in_tsv = format!("{}/{}/test_ex-mask.tsv", TEST_EXPORT_DIR, TEST_ASSEMBLY);
-    let data_type = &MASKS_DIR.to_string();
-    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let records: Vec<BedRecord> = BedRecordStream::open(&path)
+        .expect("Can't Open")
+        .map(|record| record.expect("Read Failed"))
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].line, "chr1\t1\t2\tA\t\n");
+    assert_eq!(records[0].fields(), ["chr1", "1", "2", "A", ""]);
+    assert_eq!(records[1].fields(), ["chr1", "3", "4", "B", "1"]);
+    assert_eq!(records[2].line, "chr1\t5\t6\tC\t2");
+    assert_eq!(records[0].virtual_position, Some(0));
 
-    match prep_beds(assembly, &in_tsv, data_type, &data_directory) {
-        Ok(()) => {
-            let mask_dir = format!("{}/{}/{}", data_directory, &TEST_ASSEMBLY, &data_type);
-            // check that new folder was created and contains expected number of files
-            assert_eq!(true, Path::new(&mask_dir).exists());
-            assert_eq!(
-                19,
-                read_dir(&mask_dir)
-                    .map(|entries| entries
-                        .filter_map(|entry| entry.ok())
-                        .filter(|entry| entry.file_type().map_or(false, |ft| ft.is_file()))
-                        .count())
-                    .unwrap_or(0)
-            );
-        }
-        Err(_e) => {
-            let _ = working_directory.close();
-            panic!()
-        }
+    // Seeking to a record's position reads it again
+    let mut stream = BedRecordStream::open(&path).expect("Can't Open");
+    stream
+        .seek(records[1].virtual_position.unwrap())
+        .expect("Seek Failed");
+    assert_eq!(stream.read_record().unwrap().as_ref(), Some(&records[1]));
+
+    // Errors name the file and the line
+    let mut writer = bgzf::Writer::new(File::create(&path).expect("Can't Create File"));
+    writer
+        .write_all(b"chr1\t1\t2\tA\t1\nchr1\t\xff\n")
+        .expect("Unable to write line");
+    writer.finish().expect("Unable to finish");
+    let err = BedRecordStream::open(&path)
+        .expect("Can't Open")
+        .find_map(|record| record.err())
+        .expect("Invalid UTF-8 Read");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with(&format!("{} At Line 2", path)));
+
+    let _ = working_dir.close();
+}
+
+// Records and positions as the bgz files were read before BedRecordStream, with read_line and
+// the virtual position ahead of each line
+fn read_line_records(path: &str) -> Vec<(String, u64)> {
+    let mut reader = bgzf::Reader::new(File::open(path).expect("Can't Open"));
+    let mut records = Vec::new();
+    let mut position = u64::from(reader.virtual_position());
+    let mut line = String::new();
+    while reader.read_line(&mut line).expect("Read Failed") > 0 {
+        records.push((line.clone(), position));
+        line.clear();
+        position = u64::from(reader.virtual_position());
     }
-    let _ = working_directory.close();
+    records
 }
 
-#[test]
-fn test_prepare_assembly() {
-    let working_directory = gen_working_dir();
-    let test_data_dir = working_directory.path().to_str().unwrap();
+// 1-based, fully closed extent of a BED line, as queries give regions
+fn line_extent(line: &str) -> (u64, u64) {
+    let fields = bed_fields(line);
+    let (start, end): (u64, u64) = (fields[1].parse().unwrap(), fields[2].parse().unwrap());
+    (start + 1, end)
+}
 
-    let assembly = &TEST_ASSEMBLY.to_string();
-    let data_directory = test_data_dir.to_string();
-    let export_directory = TEST_EXPORT_DIR.to_string();
+// The highest coordinate of each contig of lines
+fn contig_extents(lines: &[String]) -> HashMap<String, u64> {
+    let mut extents: HashMap<String, u64> = HashMap::new();
+    for line in lines {
+        let extent = extents.entry(bed_fields(line)[0].to_string()).or_default();
+        *extent = (*extent).max(line_extent(line).1);
+    }
+    extents
+}
 
-    let _ = prepare_assembly(assembly, &data_directory, &export_directory);
-    let assembly_dir = format!("{}/{}", data_directory, TEST_ASSEMBLY);
+// The lines a search of start..end on chrom should return, by scanning them all, sorted
+fn brute_force_overlaps(lines: &[String], chrom: &str, start: u64, end: u64) -> Vec<String> {
+    let mut overlapping: Vec<String> = lines
+        .iter()
+        .filter(|line| {
+            let (low, high) = line_extent(line);
+            bed_fields(line)[0] == chrom && low <= end && high >= start
+        })
+        .cloned()
+        .collect();
+    overlapping.sort();
+    overlapping
+}
+
+#[test]
+fn test_record_stream_outputs_unchanged() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let fam = "DF000000001";
+    copy_test_data(&data_directory, ASSEMBLY_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    // The stream reads every line and position that read_line does
+    let bgz_file = format!("{}/{}.bed.bgz", bgz_dir, fam);
+    let expected = read_line_records(&bgz_file);
+    assert!(!expected.is_empty());
+    let streamed: Vec<(String, u64)> = BedRecordStream::open(&bgz_file)
+        .expect("Can't Open")
+        .map(|record| {
+            let record = record.expect("Read Failed");
+            (record.line, record.virtual_position.unwrap())
+        })
+        .collect();
+    assert_eq!(streamed, expected);
+
+    // The index holds a range at the position of every record, and nothing else
+    let mut dump = Vec::new();
+    dump_index(
+        assembly,
+        data_type,
+        &None,
+        &None,
+        false,
+        &mut dump,
+        &data_directory,
+    )
+    .expect("Dump Failed");
+    let indexed: HashSet<u64> = String::from_utf8(dump)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|row| row.split('\t').nth(6).unwrap().parse().unwrap())
+        .collect();
+    let positions: HashSet<u64> = expected.iter().map(|(_, position)| *position).collect();
+    assert_eq!(indexed, positions);
+
+    // Queries of the rebuilt index over every contig find every record of it, minus strand
+    // records crossing a tile boundary included
+    let lines: Vec<String> = expected.iter().map(|(line, _)| line.clone()).collect();
+    let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    for (chrom, high) in contig_extents(&lines) {
+        let mut found = handle
+            .search(
+                &chrom,
+                1,
+                high + 1,
+                &None,
+                false,
+                &None,
+                None,
+                None,
+                None,
+                &None,
+            )
+            .expect("Index Search Failed");
+        found.sort();
+        assert_eq!(found, brute_force_overlaps(&lines, &chrom, 1, high + 1));
+    }
+
+    // Filtered records are the lines without their endings
+    let mut output = Vec::new();
+    bgzf_filter_with(
+        assembly,
+        data_type,
+        fam,
+        &mut output,
+        &FilterOptions::builder().profile(INTERNAL_PROFILE).build(),
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    let output = String::from_utf8(output).unwrap();
+    let filtered: Vec<&str> = output.lines().skip(1).collect();
+    let lines: Vec<&str> = expected
+        .iter()
+        .map(|(line, _)| line.trim_end_matches(['\n', '\r']))
+        .collect();
+    assert_eq!(filtered, lines);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_search_excludes_ranges_ending_before_query() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let tile = TILE_SIZE as u64;
+    let hit = |start: u64, end: u64, strand: &str| {
+        annotation_line("chr1", start, end, "DF000000001", "100.0", strand, "1")
+    };
+    let lines = vec![
+        hit(900, 999, "+"),                       // Ends just before 1000
+        hit(950, 1000, "+"),                      // Ends on 1000, a base of the query
+        hit(990, 1010, "+"),                      // Runs into 1000
+        hit(999, 900, "-"),                       // Ends just before 1000, minus strand
+        hit(1100, 990, "-"),                      // Runs into 1000, minus strand
+        hit(2100, 1900, "-"),                     // Runs past 2000, minus strand
+        hit(tile + 100, tile - 100, "-"),         // Crosses a tile boundary
+        hit(3 * tile - 500, 2 * tile + 500, "-"), // Spans a whole tile
+        hit(tile + 300, tile + 400, "+"),
+    ];
+    build_test_assembly(
+        &data_directory,
+        data_type,
+        &[("DF000000001", lines.clone())],
+    );
+    let lines: Vec<String> = lines.iter().map(|line| format!("{}\n", line)).collect();
+    let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    let search = |start: u64, end: u64| {
+        let mut found = handle
+            .search(
+                "chr1", start, end, &None, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed");
+        found.sort();
+        found
+    };
+
+    let found = search(1000, 2000);
+    assert_eq!(found.len(), 4);
+    for excluded in [&lines[0], &lines[3]] {
+        assert!(!found.contains(excluded), "{}", excluded);
+    }
+    for window in [
+        (1000, 2000),
+        (1, 1000),
+        (tile - 50, tile + 50),
+        (tile + 350, tile + 360),
+        (2 * tile + 1000, 2 * tile + 2000),
+        (2 * tile + 501, 3 * tile),
+        (1, 4 * tile),
+    ] {
+        assert_eq!(
+            search(window.0, window.1),
+            brute_force_overlaps(&lines, "chr1", window.0, window.1),
+            "{:?}",
+            window
+        );
+    }
+}
+
+#[test]
+fn test_search_matches_brute_force_scan() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    copy_test_data(&data_directory, ASSEMBLY_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    let lines: Vec<String> = read_line_records(&format!("{}/DF000000001.bed.bgz", bgz_dir))
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect();
+    let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+
+    // Windows of several lengths spread over two contigs, some on tile boundaries
+    let extents = contig_extents(&lines);
+    let tile = TILE_SIZE as u64;
+    for chrom in ["chr10", "chr11"] {
+        let extent = extents[chrom];
+        let mut windows = vec![(tile - 200, tile + 200), (5 * tile - 1, 5 * tile + 1)];
+        for k in 0..8 {
+            let start = extent / 8 * k + 1;
+            for length in [100, 5000, 40000, 1000000] {
+                windows.push((start, start + length));
+            }
+        }
+        for (start, end) in windows {
+            let mut found = handle
+                .search(
+                    chrom, start, end, &None, false, &None, None, None, None, &None,
+                )
+                .expect("Index Search Failed");
+            found.sort();
+            assert_eq!(
+                found,
+                brute_force_overlaps(&lines, chrom, start, end),
+                "{}:{}-{}",
+                chrom,
+                start,
+                end
+            );
+        }
+    }
+}
+
+#[test]
+fn test_build_idx_positions_across_blocks() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let lines: Vec<String> = (0..50)
+        .map(|i| {
+            let strand = if i % 2 == 0 { "+" } else { "-" };
+            annotation_line(
+                "chr1",
+                1001 + i * 150,
+                1101 + i * 150,
+                "DF000000001",
+                "25.5",
+                strand,
+                "0",
+            )
+        })
+        .collect();
+    let line_bytes = lines[0].len() + 1;
+    assert!(lines.iter().all(|line| line.len() + 1 == line_bytes));
+
+    // Blocks ending inside lines, and at line_bytes on every line end
+    for block_bytes in [
+        1,
+        7,
+        line_bytes - 1,
+        line_bytes,
+        line_bytes + 1,
+        3 * line_bytes,
+    ] {
+        let working_dir = gen_working_dir();
+        let data_directory = working_dir.path().to_str().unwrap().to_string();
+        let bgz_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR);
+        create_dir_all(&bgz_dir).unwrap();
+        let bgz_file = format!("{}/DF000000001.bed.bgz", bgz_dir);
+        write_bed_bgz_blocks(&bgz_file, &lines, block_bytes);
+
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+        contig_index.set_verify_positions(true);
+        build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).unwrap_or_else(|e| {
+            panic!(
+                "Verified Build Failed With {} Byte Blocks - {}",
+                block_bytes, e
+            )
+        });
+        assert_eq!(contig_index.verified_records(), lines.len() as u64);
+
+        // A line starting a block is recorded at the start of that block
+        let positions: Vec<(u64, u64)> = read_line_records(&bgz_file)
+            .iter()
+            .map(|(line, position)| (*position, line_hash(line)))
+            .collect();
+        if block_bytes == line_bytes {
+            assert!(positions.iter().all(|(position, _)| position & 0xffff == 0));
+        }
+
+        // Every record is found by seeking to it, whole, by searches and scans alike
+        let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+            .expect("Can't Open Index");
+        let mut found = handle
+            .search(
+                "chr1", 1, 100000, &None, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed");
+        found.sort();
+        let mut expected: Vec<String> = lines.iter().map(|line| format!("{}\n", line)).collect();
+        expected.sort();
+        assert_eq!(found, expected, "{} Byte Blocks", block_bytes);
+        let scanned: Vec<String> = iter_records(
+            assembly,
+            data_type,
+            &["chr1".to_string()],
+            false,
+            &None,
+            false,
+            &data_directory,
+        )
+        .expect("Can't Iterate Records")
+        .map(|record| record.expect("Can't Read Record"))
+        .collect();
+        assert_eq!(scanned, lines, "{} Byte Blocks", block_bytes);
+
+        // A position one byte off reads a different line, or none
+        let mut shifted = positions.clone();
+        shifted[1].0 += 1;
+        let err =
+            verify_record_positions(&bgz_file, &shifted).expect_err("Shifted Position Verified");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Record 2 Of"));
+        let _ = working_dir.close();
+    }
+}
+
+#[test]
+fn test_chunked_search_matches_single_search() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    copy_test_data(&data_directory, ASSEMBLY_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    let lines: Vec<String> = read_line_records(&format!("{}/DF000000001.bed.bgz", bgz_dir))
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect();
+    let open = |chunk_size: u64| {
+        let mut handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+            .expect("Can't Open Index");
+        handle.set_chunk_size(chunk_size);
+        handle
+    };
+    let (whole, chunked) = (open(0), open(TILE_SIZE as u64 * 3 + 7));
+    let search = |handle: &IndexHandle, chrom: &str, start: u64, end: u64| {
+        handle
+            .search(
+                chrom, start, end, &None, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed")
+    };
+    let extents = contig_extents(&lines);
+    for chrom in ["chr10", "chr11"] {
+        let extent = extents[chrom];
+        // The whole contig, a middle third and a region running past the indexed end
+        for (start, end) in [
+            (1, extent + 1),
+            (extent / 3, extent / 3 * 2),
+            (extent / 2, extent * 2),
+        ] {
+            let found = search(&chunked, chrom, start, end);
+            assert_eq!(found, search(&whole, chrom, start, end));
+            let mut found = found;
+            found.sort();
+            assert_eq!(found, brute_force_overlaps(&lines, chrom, start, end));
+        }
+    }
+
+    // Records planted across and on the boundaries of 10 kb chunks
+    let synthetic_dir = gen_working_dir();
+    let synthetic_data = synthetic_dir.path().to_str().unwrap().to_string();
+    let hit = |start: u64, end: u64, strand: &str| {
+        annotation_line("chr1", start, end, "DF000000001", "100.0", strand, "1")
+    };
+    let planted = vec![
+        hit(5000, 35000, "+"),  // Spans several chunks
+        hit(9000, 10000, "+"),  // Ends on a boundary
+        hit(9990, 10010, "+"),  // Crosses a boundary
+        hit(10000, 10100, "+"), // Starts on a boundary
+        hit(20010, 19990, "-"), // Crosses a boundary, minus strand
+        hit(29999, 30001, "+"),
+        hit(39000, 39500, "+"),
+    ];
+    build_test_assembly(
+        &synthetic_data,
+        data_type,
+        &[("DF000000001", planted.clone())],
+    );
+    let query = |chunk_size: u64| {
+        let json = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            40000,
+            &IdxQueryOptions::builder().chunk_size(chunk_size).build(),
+            &synthetic_data,
+        )
+        .expect("Index Query Failed");
+        from_str::<Vec<Value>>(&json).expect("Cannot Deserialize")
+    };
+    let single = query(0);
+    assert_eq!(single.len(), planted.len());
+    for chunk_size in [10000, 9999, 1] {
+        assert_eq!(query(chunk_size), single, "chunk size {}", chunk_size);
+    }
+}
+
+// Positions within eight tiles, tile boundaries and the bp either side of them chosen far more
+// often than chance would
+fn tile_position(tile_size: u64) -> impl Strategy<Value = u64> {
+    prop_oneof![
+        0..tile_size * 8,
+        (0..=8u64, 0..3u64)
+            .prop_map(move |(tile, offset)| (tile * tile_size + offset).saturating_sub(1)),
+    ]
+}
+
+// Lengths of one bp, of exactly a tile, or of up to three tiles
+fn tile_length(tile_size: u64) -> impl Strategy<Value = u64> {
+    prop_oneof![Just(1u64), Just(tile_size), 1..tile_size * 3]
+}
+
+// Records as (contig, file, low, length, minus strand) and queries as (contig, position,
+// length), over an index of the tile size, overflow list and chunk size drawn
+#[allow(clippy::type_complexity)]
+fn index_case() -> impl Strategy<
+    Value = (
+        u32,
+        Option<usize>,
+        u64,
+        Vec<(usize, usize, u64, u64, bool)>,
+        Vec<(usize, u64, u64)>,
+    ),
+> {
+    (
+        prop_oneof![Just(64u32), Just(100u32), Just(TILE_SIZE)],
+        prop_oneof![Just(None), Just(Some(1usize)), Just(Some(2usize))],
+        0..3usize,
+    )
+        .prop_flat_map(|(tile_size, overflow_tiles, chunks)| {
+            let tile = tile_size as u64;
+            let chunk_size = [0, tile / 2 + 1, tile * 3 + 7][chunks];
+            (
+                Just(tile_size),
+                Just(overflow_tiles),
+                Just(chunk_size),
+                prop::collection::vec(
+                    (
+                        0..2usize,
+                        0..3usize,
+                        tile_position(tile),
+                        tile_length(tile),
+                        any::<bool>(),
+                    ),
+                    1..40,
+                ),
+                prop::collection::vec(
+                    (
+                        0..2usize,
+                        tile_position(tile),
+                        prop_oneof![Just(0u64), tile_length(tile)],
+                    ),
+                    1..16,
+                ),
+            )
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    // Any set of records and any query give what a scan of every record gives, through an
+    // index loaded once and through a search reading the index file, shrinking to the fewest
+    // records and queries that disagree
+    #[test]
+    fn test_index_matches_brute_force(
+        (tile_size, overflow_tiles, chunk_size, records, queries) in index_case()
+    ) {
+        let working_dir = gen_working_dir();
+        let data_directory = working_dir.path().to_str().unwrap().to_string();
+        let assembly = &TEST_ASSEMBLY.to_string();
+        let data_type = &ASSEMBLY_DIR.to_string();
+        let contigs = ["chr1", "chr2"];
+        let families = ["DF000000001", "DF000000002", "DF000000003"];
+
+        // The bit score numbers the records, so no two lines are the same. Records are
+        // chromStart low to chromEnd low + length.
+        let lines: Vec<(usize, String)> = records
+            .iter()
+            .enumerate()
+            .map(|(i, (contig, file, low, length, minus))| {
+                let (start, end, strand) = match minus {
+                    true => (low + length, low + 1, "-"),
+                    false => (low + 1, low + length, "+"),
+                };
+                let line = annotation_line(
+                    contigs[*contig],
+                    start,
+                    end,
+                    families[*file],
+                    &i.to_string(),
+                    strand,
+                    "1",
+                );
+                (*file, line)
+            })
+            .collect();
+        let bgz_dir = join_path(&data_directory, &[TEST_ASSEMBLY, ASSEMBLY_DIR]);
+        create_dir_all(&bgz_dir).expect("Can't Create Dir");
+        for (file, family) in families.iter().enumerate() {
+            let file_lines: Vec<String> = lines
+                .iter()
+                .filter(|(f, _)| *f == file)
+                .map(|(_, line)| line.clone())
+                .collect();
+            if !file_lines.is_empty() {
+                write_bed_bgz(&format!("{}/{}.bed.bgz", bgz_dir, family), &file_lines);
+            }
+        }
+        // Searches give lines as read, newline included
+        let lines: Vec<String> = lines.into_iter().map(|(_, line)| line + "\n").collect();
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+        contig_index.set_tile_size(tile_size);
+        if let Some(tiles) = overflow_tiles {
+            contig_index.set_overflow_tiles(tiles);
+        }
+        build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+        let mut handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+            .expect("Can't Open Index");
+        handle.set_chunk_size(chunk_size);
+        let extents = contig_extents(&lines);
+        let tile = tile_size as u64;
+        for (contig, position, length) in queries {
+            let chrom = contigs[contig].to_string();
+            // Searching a contig without records is an error of its own
+            let Some(extent) = extents.get(&chrom) else {
+                continue;
+            };
+            let (start, end) = (position.max(1), position.max(1) + length);
+            let loaded = handle.search(&chrom, start, end, &None, false, &None, None, None, None, &None);
+            let (filenames, bgz_dir, mut contig_index, index_file) =
+                prep_idx(assembly, data_type, true, &data_directory).expect("Index Prep Failed");
+            contig_index.set_chunk_size(chunk_size);
+            let read = search_idx(
+                &filenames, &bgz_dir, &mut contig_index, &index_file, &chrom, start, end, &None,
+                false, &None, None, None, None, &None, false, true,
+            );
+
+            // Zero-length queries, and those starting past the contig's last tile, are refused
+            let indexed_end = ((extent - 1) / tile + 1) * tile;
+            if length == 0 || start > indexed_end {
+                prop_assert!(loaded.is_err(), "{}:{}-{} Not Refused", chrom, start, end);
+                prop_assert!(read.is_err(), "{}:{}-{} Not Refused", chrom, start, end);
+                continue;
+            }
+            let expected = brute_force_overlaps(&lines, &chrom, start, end);
+            for found in [loaded, read] {
+                let mut found = found.expect("Index Search Failed");
+                found.sort();
+                prop_assert_eq!(&found, &expected, "{}:{}-{}", chrom, start, end);
+            }
+        }
+        let _ = working_dir.close();
+    }
+}
+
+#[test]
+fn test_read_at() {
+    let bgz_path = format!(
+        "{}/{}/{}/chr10.bed.bgz",
+        test_data_dir(),
+        TEST_ASSEMBLY,
+        MASKS_DIR
+    );
+    let data_type = &MASKS_DIR.to_string();
+
+    // Capture positions the way build_idx does, one from a later block
+    let mut reader = File::open(&bgz_path).map(bgzf::Reader::new).unwrap();
+    let mut positions = Vec::new();
+    let mut line = String::new();
+    loop {
+        let virt_pos = u64::from(reader.virtual_position());
+        line.clear();
+        if reader.read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        positions.push((virt_pos, line.trim_end().to_string()));
+    }
+    let (virt_pos, expected) = positions
+        .iter()
+        .find(|(pos, _)| bgzf::VirtualPosition::from(*pos).compressed() > 0)
+        .expect("Single Block File");
+
+    let peeked = read_at(&bgz_path, *virt_pos, data_type).expect("Read Failed");
+    assert_eq!(&peeked.raw, expected);
+    let fields: Vec<&str> = expected.split('\t').collect();
+    assert_eq!(peeked.record["seq_id"], "chr10");
+    assert_eq!(peeked.record["seq_start"], fields[1]);
+    assert_eq!(peeked.record["repeat_length"], fields[4]);
+    assert_eq!(
+        peeked.compressed_offset,
+        bgzf::VirtualPosition::from(*virt_pos).compressed()
+    );
+
+    for (virt_pos, expected) in positions.iter().step_by(97) {
+        assert_eq!(
+            &read_at(&bgz_path, *virt_pos, data_type).unwrap().raw,
+            expected
+        );
+    }
+
+    // Mid-line, past the block end, and not at a block start
+    for (bad_pos, reason) in [
+        (virt_pos + 3, "Not The Start Of A Line"),
+        (virt_pos | 0xFFFF, "Past The End Of The Block"),
+        (
+            u64::from(bgzf::VirtualPosition::try_from((1, 0)).unwrap()),
+            "Could Not Seek",
+        ),
+    ] {
+        let err = read_at(&bgz_path, bad_pos, data_type).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+        assert!(err
+            .to_string()
+            .starts_with("No masks Record At Virtual Position"));
+        assert!(err.to_string().contains(reason), "{}", err);
+    }
+}
+
+#[test]
+fn test_idx_query() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr10".to_string();
+    let start = 10000;
+    let end = 100000;
+    let data_directory = test_data_dir().to_string();
+
+    let res1 = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions::default(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let vals1: Vec<Value> = from_str(&res1).expect("Cannot Deserialize");
+    assert_eq!(vals1.len(), 4);
+}
+
+#[test]
+fn test_idx_query_bad_region() {
+    assert_eq!(check_region(1, 2, Some(1)), Ok(()));
+    assert_eq!(check_region(0, 2, None), Err(RegionError::ZeroStart));
+    assert_eq!(check_region(1, 0, None), Err(RegionError::ZeroEnd));
+    assert_eq!(
+        check_region(5, 5, None),
+        Err(RegionError::StartNotBeforeEnd { start: 5, end: 5 })
+    );
+    assert_eq!(
+        check_region(1, 12, Some(10)),
+        Err(RegionError::TooLong {
+            length: 11,
+            max_region: 10
+        })
+    );
+
+    let query = |start: u64, end: u64, max_region: Option<u64>| {
+        idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr10",
+            start,
+            end,
+            &IdxQueryOptions {
+                max_region,
+                ..IdxQueryOptions::default()
+            },
+            test_data_dir(),
+        )
+    };
+    for (start, end, max_region) in [
+        (100000, 10000, None),
+        (0, 10000, None),
+        (10000, 100000, Some(1000)),
+    ] {
+        let err = query(start, end, max_region).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+    assert_eq!(
+        query(100000, 10000, None).unwrap_err().to_string(),
+        "Invalid Region, Start 100000 Must Be Less Than End 10000"
+    );
+    assert!(query(10000, 100000, Some(90000)).is_ok());
+}
+
+#[test]
+fn test_idx_query_subtract_masks() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    let lines = vec![
+        // Inside two touching masks
+        annotation_line("chr1", 1000, 1100, fam, "300", "+", "0"),
+        // 51 of 201 bases masked at the end
+        annotation_line("chr1", 2000, 2200, fam, "300", "+", "0"),
+        // 51 of 301 bases masked at the low end, on the minus strand
+        annotation_line("chr1", 3300, 3000, fam, "300", "-", "0"),
+        // Unmasked
+        annotation_line("chr1", 5000, 5100, fam, "300", "+", "0"),
+    ];
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines)]);
+    // Bases 900 to 1000, 1001 to 1200, 2150 to 2300 and 2950 to 3050
+    let masks = vec![
+        "chr1\t899\t1000\tTC\t2".to_string(),
+        "chr1\t1000\t1200\tTC\t2".to_string(),
+        "chr1\t2149\t2300\tA\t1".to_string(),
+        "chr1\t2949\t3050\tCA\t2".to_string(),
+    ];
+    build_test_assembly(&data_directory, MASKS_DIR, &[("chr1", masks)]);
+
+    let query = |mode: MaskMode, min_overlap: f64| -> Vec<Value> {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            500,
+            6000,
+            &IdxQueryOptions::builder()
+                .subtract_masks(MaskSubtraction { mode, min_overlap })
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+    let coords = |hits: &Vec<Value>| -> Vec<(u64, u64)> {
+        hits.iter()
+            .map(|hit| {
+                (
+                    hit["seq_start"].as_u64().unwrap(),
+                    hit["seq_end"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    };
+
+    let dropped = query(MaskMode::Drop, 1.0);
+    assert_eq!(
+        coords(&dropped),
+        vec![(2000, 2200), (3300, 3000), (5000, 5100)]
+    );
+    let dropped = query(MaskMode::Drop, 0.2);
+    assert_eq!(coords(&dropped), vec![(3300, 3000), (5000, 5100)]);
+
+    let clipped = query(MaskMode::Clip, 1.0);
+    assert_eq!(
+        coords(&clipped),
+        vec![(2000, 2149), (3300, 3051), (5000, 5100)]
+    );
+    assert_eq!(clipped[0]["ali_end"], 2149);
+    assert_eq!(clipped[1]["model_start"], 1);
+
+    let annotated = query(MaskMode::Annotate, 1.0);
+    let overlaps: Vec<u64> = annotated
+        .iter()
+        .map(|hit| hit["masked_overlap_bp"].as_u64().unwrap())
+        .collect();
+    assert_eq!(overlaps, vec![101, 51, 51, 0]);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_idx_query_warnings() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    let mut lines = vec![annotation_line("chr1", 100, 500, fam, "300", "+", "0")];
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines.clone())]);
+
+    let query = |with_warnings: bool| {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            1000,
+            &IdxQueryOptions::builder()
+                .with_warnings(with_warnings)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str::<Value>(&res).expect("Cannot Deserialize")
+    };
+    assert_eq!(query(true)["warnings"], Value::Array(vec![]));
+
+    // Grow the bgz file without re-indexing it
+    lines.push(annotation_line("chr1", 2000, 2600, fam, "250", "+", "1"));
+    write_bed_bgz(
+        &format!(
+            "{}/{}/{}/{}.bed.bgz",
+            data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR, fam
+        ),
+        &lines,
+    );
+    let res = query(true);
+    assert_eq!(res["hits"].as_array().unwrap().len(), 1);
+    let warnings = res["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    let warning = warnings[0].as_str().unwrap();
+    assert!(warning.contains(&format!("{}.bed.bgz has been modified", fam)));
+    // Without the flag the response stays a plain array of hits
+    assert_eq!(query(false).as_array().unwrap().len(), 1);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_idx_query_fast_path() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let log_path = format!("{}/queries.ndjson", data_directory);
+    let (fam, other) = ("DF000000001", "DF000000002");
+    let other_lines = vec![annotation_line("chr2", 100, 500, other, "200", "-", "0")];
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (
+                fam,
+                vec![annotation_line("chr1", 100, 500, fam, "300", "+", "0")],
+            ),
+            (other, other_lines.clone()),
+        ],
+    );
+    // Change a file the query never reads without re-indexing it
+    let mut other_lines = other_lines;
+    other_lines.push(annotation_line("chr2", 2000, 2600, other, "250", "-", "1"));
+    write_bed_bgz(
+        &format!(
+            "{}/{}/{}/{}.bed.bgz",
+            data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR, other
+        ),
+        &other_lines,
+    );
+
+    let query = |fast_path: bool| {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            1000,
+            &IdxQueryOptions::builder()
+                .with_warnings(true)
+                .fast_path(fast_path)
+                .query_log(QueryLogger::new(&log_path, 0))
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str::<Value>(&res).expect("Cannot Deserialize")
+    };
+    let checked = query(false);
+    let warnings = checked["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0]
+        .as_str()
+        .unwrap()
+        .contains(&format!("{}.bed.bgz has been modified", other)));
+    // The same hits without looking at the other files
+    let fast = query(true);
+    assert_eq!(fast["hits"], checked["hits"]);
+    assert_eq!(fast["warnings"], Value::Array(vec![]));
+
+    let entries: Vec<Value> = read_to_string(&log_path)
+        .expect("Can't Read Query Log")
+        .lines()
+        .map(|l| from_str(l).expect("Malformed Log Line"))
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["parameters"]["fast_path"], false);
+    assert!(entries[0]["metadata_calls"].as_u64().unwrap() >= 4);
+    assert!(entries[0]["metadata_ms"].is_f64());
+    assert_eq!(entries[1]["parameters"]["fast_path"], true);
+    assert_eq!(entries[1]["metadata_calls"], 0);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_idx_query_missing_bgz() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let (kept, deleted) = ("DF000000001", "DF000000002");
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (
+                kept,
+                vec![annotation_line("chr1", 100, 500, kept, "300", "+", "0")],
+            ),
+            (
+                deleted,
+                vec![
+                    annotation_line("chr1", 200, 700, deleted, "250", "-", "1"),
+                    annotation_line("chr1", 900, 1200, deleted, "200", "+", "2"),
+                ],
+            ),
+        ],
+    );
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    // Loaded before the file goes, so the handle only finds out when it opens it mid-query
+    let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    remove_file(format!(
+        "{}/{}/{}/{}.bed.bgz",
+        data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR, deleted
+    ))
+    .expect("Can't Remove BGZ File");
+
+    let query = |strict: bool| {
+        idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            2000,
+            &IdxQueryOptions::builder()
+                .with_warnings(true)
+                .strict(strict)
+                .build(),
+            &data_directory,
+        )
+    };
+    let res =
+        from_str::<Value>(&query(false).expect("Index Query Failed")).expect("Cannot Deserialize");
+    let hits = res["hits"].as_array().unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["accession"], kept);
+    assert_eq!(
+        res["skipped"],
+        json!([{ "file": format!("{}.bed.bgz", deleted), "ranges": 2 }])
+    );
+
+    let err = query(true).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err.to_string().contains(&format!("{}.bed.bgz", deleted)));
+
+    let searched = handle
+        .search(
+            "chr1", 1, 2000, &None, false, &None, None, None, None, &None,
+        )
+        .expect("Search Failed");
+    assert_eq!(searched.len(), 1);
+    assert!(searched[0].contains(kept));
+
+    let _ = working_dir.close();
+}
+
+// (name, sequence) of each FASTA record, line breaks removed
+fn read_fasta(reader: impl BufRead) -> Vec<(String, String)> {
+    let mut records: Vec<(String, String)> = Vec::new();
+    for line in reader.lines() {
+        let line = line.unwrap();
+        match line.strip_prefix('>') {
+            Some(name) => records.push((name.to_string(), String::new())),
+            None => records.last_mut().unwrap().1.push_str(&line),
+        }
+    }
+    records
+}
+
+#[test]
+fn test_export_family_fasta() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let fam = "DF000000001";
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            fam,
+            vec![
+                annotation_line("chr1", 11, 40, fam, "300", "+", "1"),
+                annotation_line("chr1", 230, 151, fam, "250", "-", "0"),
+                annotation_line("chr2", 5, 20, fam, "200", "-", "1"),
+            ],
+        )],
+    );
+    let genome = synthetic_genome(&[("chr1".to_string(), 300), ("chr2".to_string(), 130)]);
+    let genome_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, GENOME_DIR);
+    create_dir_all(&genome_dir).expect("Can't Create Dir");
+    let fasta_path = genome_fasta_path(assembly, &data_directory);
+    write_fasta(&fasta_path, &genome, 50).expect("Can't Write FASTA");
+
+    // Expected sequence of a 1-based closed region, complemented independently of the library
+    let region = |contig: usize, start: usize, end: usize, minus: bool| {
+        let seq = &genome[contig].1[start - 1..end];
+        if !minus {
+            return seq.to_string();
+        }
+        seq.chars()
+            .rev()
+            .map(|b| match b {
+                'A' => 'T',
+                'C' => 'G',
+                'G' => 'C',
+                'T' => 'A',
+                'a' => 't',
+                'c' => 'g',
+                'g' => 'c',
+                't' => 'a',
+                other => other,
+            })
+            .collect()
+    };
+    let export = |nrph: bool, flank_bp: u64, out_name: &str| {
+        let out_path = format!("{}/{}", data_directory, out_name);
+        let count = export_family_fasta(assembly, fam, &nrph, flank_bp, &out_path, &data_directory)
+            .expect("Export Failed");
+        let in_f = File::open(&out_path).expect("Can't Open Output");
+        let records = if out_name.ends_with(".bgz") {
+            read_fasta(BufReader::new(bgzf::Reader::new(in_f)))
+        } else {
+            read_fasta(BufReader::new(in_f))
+        };
+        assert_eq!(records.len(), count);
+        records
+    };
+
+    let records = export(false, 0, "all.fa");
+    assert_eq!(
+        records,
+        vec![
+            ("chr1:11-40(+)".to_string(), region(0, 11, 40, false)),
+            ("chr1:151-230(-)".to_string(), region(0, 151, 230, true)),
+            ("chr2:5-20(-)".to_string(), region(1, 5, 20, true)),
+        ]
+    );
+    // Spans a soft masked stretch, so case has to survive the complement
+    assert!(records[1].1.chars().any(|b| b.is_ascii_lowercase()));
+
+    // Flanks are clamped to the contig ends, the .fai built on open matches samtools
+    let flanked = export(true, 10, "flanked.fa.bgz");
+    assert_eq!(
+        flanked,
+        vec![
+            ("chr1:1-50(+)".to_string(), region(0, 1, 50, false)),
+            ("chr2:1-30(-)".to_string(), region(1, 1, 30, true)),
+        ]
+    );
+    write(
+        format!("{}.fai", fasta_path),
+        "chr1\t300\t16\t50\t51\nchr2\t130\t338\t50\t51\n",
+    )
+    .expect("Can't Write FAI");
+    assert_eq!(export(true, 10, "indexed.fa"), flanked);
+
+    assert_eq!(reverse_complement(b"ACGTNacgtnRYkm"), b"kmRYnacgtNACGT");
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_record_order() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let (fam1, fam2, fam3) = ("DF000000001", "DF000000002", "DF000000003");
+    // Every record has the extent 100-500, in two files holding three families
+    let fam3_300 = annotation_line("chr1", 100, 500, fam3, "300", "+", "0");
+    let fam1_200_nrph = annotation_line("chr1", 100, 500, fam1, "200", "+", "1");
+    let fam1_200 = annotation_line("chr1", 100, 500, fam1, "200", "+", "0");
+    let fam2_250 = annotation_line("chr1", 500, 100, fam2, "250", "-", "0");
+    let fam1_400 = annotation_line("chr1", 100, 500, fam1, "400", "-", "0");
+    let expected = vec![
+        fam1_400.clone(),
+        fam1_200.clone(),
+        fam1_200_nrph.clone(),
+        fam2_250.clone(),
+        fam3_300.clone(),
+    ];
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (fam1, vec![fam3_300, fam1_200_nrph, fam1_400]),
+            (fam2, vec![fam2_250, fam1_200]),
+        ],
+    );
+
+    let mut shuffled = expected.clone();
+    shuffled.reverse();
+    sort_records(&mut shuffled, |_| 0);
+    assert_eq!(shuffled, expected);
+
+    let (assembly, data_type) = (&TEST_ASSEMBLY.to_string(), &ASSEMBLY_DIR.to_string());
+    for _ in 0..3 {
+        let records: Vec<String> =
+            iter_contig(assembly, data_type, "chr1", &None, &false, &data_directory)
+                .expect("Iteration Failed")
+                .map(|record| record.expect("Record Read Failed"))
+                .collect();
+        assert_eq!(records, expected);
+
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            1000,
+            &IdxQueryOptions::default(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        // Hits leave out the NRPH flag telling the two 200 bit hits apart
+        let hits: Vec<(String, f64, String)> = from_str::<Vec<Value>>(&res)
+            .expect("Cannot Deserialize")
+            .iter()
+            .map(|hit| {
+                (
+                    hit["accession"].as_str().unwrap().to_string(),
+                    hit["bit_score"].as_f64().unwrap(),
+                    hit["strand"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect();
+        let expected_hits: Vec<(String, f64, String)> = expected
+            .iter()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (
+                    fields[3].to_string(),
+                    fields[4].parse().unwrap(),
+                    fields[5].to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(hits, expected_hits);
+    }
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_iter_contig() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    copy_test_data(&data_directory, MASKS_DIR);
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    let chrom = &"chr1".to_string();
+
+    let starts: Vec<u64> = iter_contig(assembly, data_type, chrom, &None, &false, &data_directory)
+        .expect("Iteration Failed")
+        .map(|record| {
+            let record = record.expect("Record Read Failed");
+            assert!(record.starts_with("chr1\t"));
+            record.split('\t').nth(1).unwrap().parse().unwrap()
+        })
+        .collect();
+    assert!(!starts.is_empty());
+    assert!(starts.windows(2).all(|w| w[0] <= w[1]));
+
+    // Every record of the contig is also found by a query spanning all of it. Searches find
+    // nothing when their first tile is empty, so the query starts at the first record
+    let res = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        starts[0].max(1),
+        248956422,
+        &IdxQueryOptions::default(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(starts.len(), vals.len());
+    assert!(iter_contig(
+        assembly,
+        data_type,
+        "chrUn_missing",
+        &None,
+        &false,
+        &data_directory
+    )
+    .is_err());
+
+    // The whole assembly has every chr1 record, contigs in their contig order
+    let mut chroms: Vec<String> = Vec::new();
+    let mut chr1_count = 0;
+    for record in iter_assembly(assembly, data_type, &None, &false, &data_directory)
+        .expect("Iteration Failed")
+    {
+        let record = record.expect("Record Read Failed");
+        let chrom = record.split('\t').next().unwrap().to_string();
+        if chrom == "chr1" {
+            chr1_count += 1;
+        }
+        if chroms.last() != Some(&chrom) {
+            assert!(!chroms.contains(&chrom));
+            chroms.push(chrom);
+        }
+    }
+    assert_eq!(chr1_count, starts.len());
+    let ranks = contig_order(assembly, &data_directory)
+        .expect("Contig Order Failed")
+        .ranks();
+    assert!(chroms.windows(2).all(|w| ranks[&w[0]] < ranks[&w[1]]));
+
+    // Iteration is lazy and the filters match idx_query's
+    let nrph: Vec<String> = iter_contig(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        "chr10",
+        &Some("DF000000001".to_string()),
+        &true,
+        test_data_dir(),
+    )
+    .expect("Iteration Failed")
+    .take(3)
+    .map(|record| record.expect("Record Read Failed"))
+    .collect();
+    assert_eq!(nrph.len(), 3);
+    assert!(nrph.iter().all(|r| r.split('\t').nth(12) == Some("1")));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_contig_order() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    assert_eq!(natural_cmp("chr2", "chr10"), Ordering::Less);
+    assert_eq!(natural_cmp("chr10", "chr9"), Ordering::Greater);
+    assert_eq!(natural_cmp("chr1", "chr1_random"), Ordering::Less);
+    assert_eq!(natural_cmp("scaffold_02", "scaffold_2"), Ordering::Less);
+    assert_eq!(natural_cmp("chrX", "chrX"), Ordering::Equal);
+
+    // The sequences JSON keys come sorted by name, chr10 before chr2. CM000665.2 is chr3 by id.
+    let seq_dir = format!("{}/{}/{}", data_directory, assembly, SEQUENCE_DIR);
+    create_dir_all(&seq_dir).expect("Can't Create Dir");
+    let sequences = json!({
+        "assembly": assembly,
+        "data": {
+            "chr10": {"length": 100},
+            "chr2": {"length": 200},
+            "chrM": {"length": 16},
+            "chrX": {"length": 150},
+            "chr1": {"length": 300},
+            "CM000665.2": {"id": "chr3", "length": 250},
+            "chrUn_A": {"length": 5000},
+            "chr1_B_random": {"length": 8000},
+            "scaffold_9": {},
+        }
+    });
+    write(
+        format!("{}/{}-{}.json", seq_dir, assembly, SEQUENCE_DIR),
+        sequences.to_string(),
+    )
+    .expect("Can't Write JSON");
+    let order = contig_order(assembly, &data_directory).expect("Contig Order Failed");
+    assert_eq!(order.order_file, None);
+    assert_eq!(
+        order.contigs,
+        [
+            "chr1",
+            "chr2",
+            "CM000665.2",
+            "chr10",
+            "chrX",
+            "chrM",
+            "chr1_B_random",
+            "chrUn_A",
+            "scaffold_9",
+        ]
+    );
+    assert_eq!(order.ranks()["chr10"], 3);
+
+    // The override file wins, the contigs it leaves out follow in the default order
+    let order_file = format!("{}/{}/{}", data_directory, assembly, CONTIG_ORDER_FILE);
+    write(
+        &order_file,
+        "# Karyotype of the new release\nchrX\n\nchr3\nchr10\n",
+    )
+    .unwrap();
+    let order = contig_order(assembly, &data_directory).expect("Contig Order Failed");
+    assert_eq!(order.order_file, Some(order_file.clone()));
+    assert_eq!(
+        order.contigs,
+        [
+            "chrX",
+            "CM000665.2",
+            "chr10",
+            "chr1",
+            "chr2",
+            "chrM",
+            "chr1_B_random",
+            "chrUn_A",
+            "scaffold_9",
+        ]
+    );
+
+    // Records are prepared in that order
+    let tsv = format!("{}/order.tsv", data_directory);
+    let export_lines: Vec<String> = ["chr1", "chr10", "chrX", "chr2"]
+        .iter()
+        .map(|chrom| {
+            format!(
+                "{}\tDF000000001\tTEST\t50.0\t1e-10\t0.1\t1\t100\t+\t100\t200\t100\t200\t1000\t100M\t10.5\t1\tCAF",
+                chrom
+            )
+        })
+        .collect();
+    write(&tsv, export_lines.join("\n") + "\n").unwrap();
+    prep_beds_with(
+        assembly,
+        &tsv,
+        ASSEMBLY_DIR,
+        &PrepOptions::default(),
+        &data_directory,
+    )
+    .expect("Prep Failed");
+    let prepared = bgz_path(
+        format!("{}/{}/{}", data_directory, assembly, ASSEMBLY_DIR),
+        "DF000000001",
+    );
+    let chroms: Vec<String> = BedRecordStream::open(&prepared)
+        .unwrap()
+        .map(|record| record.unwrap().fields()[0].to_string())
+        .collect();
+    assert_eq!(chroms, ["chrX", "chr10", "chr1", "chr2"]);
+
+    for (contents, error) in [
+        ("chrX\nchr2\nchrX\n", "Listed More Than Once"),
+        ("chr2\nchr99\n", "Not In The Sequences"),
+    ] {
+        write(&order_file, contents).unwrap();
+        let e = contig_order(assembly, &data_directory).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(e.to_string().contains(error), "{}", e);
+    }
+
+    let _ = working_dir.close();
+}
+
+// Every file and directory under a path with its size and modification time
+fn tree_snapshot(path: &str) -> Vec<(String, u64, SystemTime)> {
+    WalkDir::new(path)
+        .sort_by_file_name()
+        .into_iter()
+        .map(|entry| {
+            let entry = entry.expect("Can't Walk Dir");
+            let metadata = entry.metadata().expect("Can't Stat");
+            (
+                entry.path().display().to_string(),
+                metadata.len(),
+                metadata.modified().expect("No Modification Time"),
+            )
+        })
+        .collect()
+}
+
+fn set_tree_mode(path: &str, dir_mode: u32, file_mode: u32) {
+    for entry in WalkDir::new(path).contents_first(true) {
+        let entry = entry.expect("Can't Walk Dir");
+        let mode = if entry.file_type().is_dir() {
+            dir_mode
+        } else {
+            file_mode
+        };
+        set_permissions(entry.path(), Permissions::from_mode(mode)).expect("Can't Set Mode");
+    }
+}
+
+#[test]
+fn test_read_only_queries() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let output_dir = gen_working_dir();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let fam = &"DF000000001".to_string();
+    let chrom = &"chr1".to_string();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            fam,
+            vec![
+                annotation_line("chr1", 100, 500, fam, "300", "+", "1"),
+                annotation_line("chr1", 2600, 2000, fam, "250", "-", "0"),
+            ],
+        )],
+    );
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    copy_test_data(&data_directory, MOD_LEN_DIR);
+    let assembly_path = format!("{}/{}", data_directory, TEST_ASSEMBLY);
+    let before = tree_snapshot(&assembly_path);
+    set_tree_mode(&assembly_path, 0o555, 0o444);
+
+    let query = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        1,
+        10000,
+        &IdxQueryOptions::builder().with_warnings(true).build(),
+        &data_directory,
+    );
+    let iterated = iter_contig(assembly, data_type, chrom, &None, &false, &data_directory)
+        .map(|records| records.count());
+    let handle = IndexHandle::open(assembly, data_type, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    let searched = handle.search(
+        chrom, 1, 10000, &None, false, &None, None, None, None, &None,
+    );
+    let family = read_family_assembly_annotations(
+        fam,
+        assembly,
+        &false,
+        &mut Vec::new(),
+        &false,
+        &data_directory,
+    );
+    let sequence = json_query(
+        assembly,
+        SEQUENCE_DIR,
+        chrom,
+        &Some("length".to_string()),
+        &None,
+        &data_directory,
+    );
+    let found = find_sequences(assembly, "chr1", 10, &data_directory);
+    let chrom_id = get_chrom_id(assembly, chrom, &data_directory);
+    // Rebuilding through a query-side index is refused before touching the file
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, true, &data_directory).expect("Index Prep Failed");
+    let rebuild = build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file);
+
+    let after = tree_snapshot(&assembly_path);
+    set_tree_mode(&assembly_path, 0o755, 0o644);
+
+    let query: Value = from_str(&query.expect("Index Query Failed")).expect("Cannot Deserialize");
+    assert_eq!(query["hits"].as_array().unwrap().len(), 2);
+    assert_eq!(query["warnings"], Value::Array(vec![]));
+    assert_eq!(iterated.expect("Iteration Failed"), 2);
+    assert_eq!(searched.expect("Index Search Failed").len(), 2);
+    family.expect("Family Annotations Failed");
+    assert_eq!(sequence.expect("JSON Query Failed"), "248956422");
+    assert!(!found.expect("Sequence Search Failed").matches.is_empty());
+    chrom_id.expect("Chrom Lookup Failed");
+    let err = rebuild.expect_err("Read-Only Index Was Saved");
+    assert!(err.to_string().contains("Opened Read-Only"));
+    assert_eq!(before, after);
+
+    let _ = working_dir.close();
+    let _ = output_dir.close();
+}
+
+#[test]
+fn test_idx_query_strand() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr10".to_string();
+    let data_directory = test_data_dir().to_string();
+
+    let query = |family: &Option<String>, nrph: &bool, strand: Option<&str>| {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            chrom,
+            1,
+            1000000,
+            &IdxQueryOptions {
+                family: family.clone().map(|family| vec![family]),
+                nrph: *nrph,
+                strand: strand.map(|s| s.to_string()),
+                ..IdxQueryOptions::default()
+            },
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        vals
+    };
+
+    let family = Some("DF000000001".to_string());
+    for (family, nrph) in [(&None, &false), (&family, &false), (&None, &true)] {
+        let all = query(family, nrph, None);
+        let plus = query(family, nrph, Some("+"));
+        let minus = query(family, nrph, Some("-"));
+        assert!(!plus.is_empty() && !minus.is_empty());
+        assert!(plus.iter().all(|v| v["strand"] == "+"));
+        assert!(minus.iter().all(|v| v["strand"] == "-"));
+        // The two strands partition the unfiltered results exactly
+        let mut joined: Vec<String> = plus.iter().chain(&minus).map(|v| v.to_string()).collect();
+        let mut all: Vec<String> = all.iter().map(|v| v.to_string()).collect();
+        joined.sort();
+        all.sort();
+        assert_eq!(joined, all);
+    }
+}
+
+// Open file descriptors of the test process on files under a folder, to catch readers left
+// open by a stopped search. Other tests open files elsewhere, so give each test its own folder.
+fn open_fds_under(folder: &str) -> usize {
+    let folder = std::fs::canonicalize(folder).expect("Can't Resolve Folder");
+    read_dir("/proc/self/fd")
+        .expect("Can't Read /proc/self/fd")
+        .filter_map(|e| e.ok())
+        .filter_map(|e| read_link(e.path()).ok())
+        .filter(|target| target.starts_with(&folder))
+        .count()
+}
+
+#[test]
+fn test_idx_query_timeout() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr11".to_string();
+    let data_directory = test_data_dir().to_string();
+    // A timeout of zero has passed at the first check, whatever the machine's speed
+    let query = |partial: &bool| {
+        idx_query_with(
+            assembly,
+            data_type,
+            chrom,
+            89000,
+            135086622,
+            &IdxQueryOptions::builder()
+                .timeout(Duration::ZERO)
+                .partial(*partial)
+                .build(),
+            &data_directory,
+        )
+    };
+
+    let err = query(&false).expect_err("Query Should Time Out");
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let timed_out = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<SearchTimeout>())
+        .expect("Not A Search Timeout");
+    assert!(!timed_out.cancelled);
+
+    let res = query(&true).expect("Partial Query Failed");
+    let val: Value = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(val["truncated"], true);
+    let hits = val["hits"].as_array().expect("No Hits");
+    assert!(hits.iter().all(|h| h["sequence"] == "chr11"));
+
+    // Each stopped search drops its readers, the handle keeps only its index open. Searches
+    // stopping anywhere, or finishing, leave the same files open.
+    let working_dir = gen_working_dir();
+    let own_data = working_dir.path().to_str().unwrap().to_string();
+    copy_test_data(&own_data, data_type);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &own_data).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    let handle = IndexHandle::open(assembly, data_type, &own_data, Duration::from_secs(60))
+        .expect("Can't Open Index");
+    let held = open_fds_under(&own_data);
+    assert_eq!(held, 1);
+    for millis in 0..30 {
+        let _ = handle.search(
+            chrom,
+            89000,
+            135086622,
+            &None,
+            false,
+            &None,
+            None,
+            None,
+            Some(Duration::from_millis(millis)),
+            &None,
+        );
+        assert_eq!(open_fds_under(&own_data), held);
+    }
+    drop(handle);
+    assert_eq!(open_fds_under(&own_data), 0);
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_index_handle_cancel() {
+    let handle = IndexHandle::open(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        test_data_dir(),
+        Duration::from_secs(60),
+    )
+    .expect("Can't Open Index");
+    let cancel = Arc::new(AtomicBool::new(false));
+    let search = |cancel: &Option<Arc<AtomicBool>>| {
+        handle.search(
+            "chr11", 89000, 135086622, &None, false, &None, None, None, None, cancel,
+        )
+    };
+
+    // A token tripped from another thread stops a search already running
+    let tripper = {
+        let cancel = Arc::clone(&cancel);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        })
+    };
+    let started = Instant::now();
+    let err = search(&Some(Arc::clone(&cancel))).expect_err("Search Should Be Cancelled");
+    tripper.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(10));
+    let stopped = err
+        .downcast_ref::<SearchTimeout>()
+        .expect("Not A Search Timeout");
+    assert!(stopped.cancelled);
+
+    // The handle is still usable afterwards
+    let hits = handle
+        .search(
+            "chr11", 89000, 100000, &None, false, &None, None, None, None, &None,
+        )
+        .expect("Index Search Failed");
+    assert!(!hits.is_empty());
+}
+
+#[test]
+fn test_idx_query_score_thresholds() {
+    let query = |data_directory: &String,
+                 chrom: &str,
+                 min_bit_score: Option<f64>,
+                 max_e_value: Option<f64>| {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            chrom,
+            1,
+            1000000,
+            &IdxQueryOptions {
+                min_bit_score,
+                max_e_value,
+                ..IdxQueryOptions::default()
+            },
+            data_directory,
+        )
+        .expect("Index Query Failed");
+        let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        vals
+    };
+
+    let data_directory = test_data_dir().to_string();
+    let all = query(&data_directory, "chr10", None, None);
+    let mut scores: Vec<f64> = all
+        .iter()
+        .map(|v| v["bit_score"].as_f64().unwrap())
+        .collect();
+    let mut e_values: Vec<f64> = all.iter().map(|v| v["e_value"].as_f64().unwrap()).collect();
+    scores.sort_by(|a, b| a.total_cmp(b));
+    e_values.sort_by(|a, b| a.total_cmp(b));
+    // Thresholds taken from the data so records sit exactly on the boundary
+    let min_bit_score = scores[scores.len() / 2];
+    let max_e_value = e_values[e_values.len() / 2];
+
+    for (min, max) in [
+        (Some(min_bit_score), None),
+        (None, Some(max_e_value)),
+        (Some(min_bit_score), Some(max_e_value)),
+    ] {
+        let expected: Vec<&Value> = all
+            .iter()
+            .filter(|v| min.is_none_or(|min| v["bit_score"].as_f64().unwrap() >= min))
+            .filter(|v| max.is_none_or(|max| v["e_value"].as_f64().unwrap() <= max))
+            .collect();
+        let filtered = query(&data_directory, "chr10", min, max);
+        assert!(!filtered.is_empty() && filtered.len() < all.len());
+        assert_eq!(filtered.iter().collect::<Vec<&Value>>(), expected);
+    }
+    let at_boundary = query(&data_directory, "chr10", Some(min_bit_score), None);
+    assert!(at_boundary
+        .iter()
+        .any(|v| v["bit_score"].as_f64() == Some(min_bit_score)));
+
+    // Unparsable scores never pass a threshold
+    let working_directory = gen_working_dir();
+    let synthetic_directory = working_directory.path().to_str().unwrap().to_string();
+    build_test_assembly(
+        &synthetic_directory,
+        ASSEMBLY_DIR,
+        &[(
+            "DF000000001",
+            vec![
+                annotation_line("chr1", 100, 200, "DF000000001", "25.5", "+", "1"),
+                annotation_line("chr1", 300, 400, "DF000000001", "n/a", "-", "0"),
+                annotation_line("chr1", 500, 600, "DF000000001", "NaN", "+", "0"),
+                annotation_line("chr1", 700, 800, "DF000000001", "30", "+", "0"),
+            ],
+        )],
+    );
+    assert_eq!(query(&synthetic_directory, "chr1", None, None).len(), 4);
+    assert_eq!(
+        query(&synthetic_directory, "chr1", Some(25.5), None).len(),
+        2
+    );
+    assert_eq!(
+        query(&synthetic_directory, "chr1", Some(25.6), None).len(),
+        1
+    );
+    assert_eq!(
+        query(&synthetic_directory, "chr1", None, Some(1e-10)).len(),
+        4
+    );
+    assert_eq!(
+        query(&synthetic_directory, "chr1", None, Some(1e-11)).len(),
+        0
+    );
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_bias_threshold() {
+    let max_bias = 1.0;
+    let data_directory = test_data_dir().to_string();
+    let bias = |line: &String| -> Option<f64> {
+        line.trim_end()
+            .split('\t')
+            .nth(6)
+            .and_then(|b| b.parse().ok())
+    };
+    let biases: Vec<Option<f64>> = read_line_records(&format!(
+        "{}/{}/{}/DF000000001.bed.bgz",
+        test_data_dir(),
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR
+    ))
+    .iter()
+    .filter(|(line, _)| !line.starts_with('#'))
+    .map(|(line, _)| bias(line))
+    .collect();
+    let expected = biases
+        .iter()
+        .filter(|b| b.is_none_or(|b| b <= max_bias))
+        .count();
+    assert!(expected > 0 && expected < biases.len());
+
+    let filter = |data_directory: &String, max_bias: Option<f64>, strict_bias: bool| {
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "DF000000001",
+            &mut std::io::sink(),
+            &FilterOptions {
+                max_bias,
+                strict_bias,
+                ..FilterOptions::default()
+            },
+            data_directory,
+        )
+        .expect("Filter Failed")
+    };
+    let query = |data_directory: &String, max_bias: Option<f64>, strict_bias: bool| {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr10",
+            1,
+            1000000,
+            &IdxQueryOptions {
+                max_bias,
+                strict_bias,
+                ..IdxQueryOptions::default()
+            },
+            data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str::<Vec<Value>>(&res).expect("Cannot Deserialize")
+    };
+
+    // The filter keeps what a scan of the file keeps, the query what its own JSON bias keeps
+    assert_eq!(filter(&data_directory, Some(max_bias), false), expected);
+    let all = query(&data_directory, None, false);
+    assert!(all.iter().all(|v| v["bias"].is_f64()));
+    let expected_hits: Vec<&Value> = all
+        .iter()
+        .filter(|v| v["bias"].as_f64().unwrap() <= max_bias)
+        .collect();
+    let filtered = query(&data_directory, Some(max_bias), false);
+    assert!(!filtered.is_empty() && filtered.len() < all.len());
+    assert_eq!(filtered.iter().collect::<Vec<&Value>>(), expected_hits);
+
+    // The family summary spans the same values
+    let summary =
+        family_summary(TEST_ASSEMBLY, "DF000000001", &data_directory).expect("Summary Failed");
+    let parsed: Vec<f64> = biases.iter().flatten().copied().collect();
+    assert_eq!(summary.bias.values, parsed.len() as u64);
+    assert_eq!(
+        summary.bias.unparsable,
+        (biases.len() - parsed.len()) as u64
+    );
+    assert_eq!(summary.bias.min, parsed.iter().copied().reduce(f64::min));
+    assert_eq!(summary.bias.max, parsed.iter().copied().reduce(f64::max));
+
+    // Empty and non-numeric biases pass unless strict
+    let working_directory = gen_working_dir();
+    let synthetic_directory = working_directory.path().to_str().unwrap().to_string();
+    let with_bias = |start: u64, bias: &str| {
+        annotation_line("chr1", start, start + 100, "DF000000001", "25.5", "+", "0").replacen(
+            "\t1.0\t",
+            &format!("\t{}\t", bias),
+            1,
+        )
+    };
+    build_test_assembly(
+        &synthetic_directory,
+        ASSEMBLY_DIR,
+        &[(
+            "DF000000001",
+            vec![
+                with_bias(100, "0.5"),
+                with_bias(300, ""),
+                with_bias(500, "n/a"),
+                with_bias(700, "2.0"),
+            ],
+        )],
+    );
+    copy_test_data(&synthetic_directory, SEQUENCE_DIR);
+    copy_test_data(&synthetic_directory, MOD_LEN_DIR);
+    let query = |max_bias: Option<f64>, strict_bias: bool| {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            1000,
+            &IdxQueryOptions {
+                max_bias,
+                strict_bias,
+                ..IdxQueryOptions::default()
+            },
+            &synthetic_directory,
+        )
+        .expect("Index Query Failed");
+        from_str::<Vec<Value>>(&res).expect("Cannot Deserialize")
+    };
+    let all = query(None, false);
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[0]["bias"], 0.5);
+    assert!(all[1]["bias"].is_null() && all[2]["bias"].is_null());
+    assert_eq!(query(Some(max_bias), false).len(), 3);
+    assert_eq!(query(Some(max_bias), true).len(), 1);
+    assert_eq!(filter(&synthetic_directory, Some(max_bias), false), 3);
+    assert_eq!(filter(&synthetic_directory, Some(max_bias), true), 1);
+
+    let summary =
+        family_summary(TEST_ASSEMBLY, "DF000000001", &synthetic_directory).expect("Summary Failed");
+    assert_eq!(
+        summary.bias,
+        ColumnStats {
+            values: 2,
+            unparsable: 2,
+            min: Some(0.5),
+            max: Some(2.0),
+            mean: Some(1.25),
+        }
+    );
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_index_handle_reload() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap();
+    let release_dir = format!("{}/release", data_directory);
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr1".to_string();
+
+    build_test_assembly(
+        data_directory,
+        data_type,
+        &[(
+            "DF000000001",
+            vec![annotation_line(
+                "chr1",
+                100,
+                500,
+                "DF000000001",
+                "300",
+                "+",
+                "0",
+            )],
+        )],
+    );
+    let handle = IndexHandle::open(TEST_ASSEMBLY, data_type, data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    let query = || {
+        handle
+            .search(
+                chrom, 1, 10000, &None, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed")
+    };
+    assert_eq!(query().len(), 1);
+    assert_eq!(handle.generation(), 0);
+
+    // A release swaps in new beds and a new index under the open handle
+    build_test_assembly(
+        &release_dir,
+        data_type,
+        &[(
+            "DF000000001",
+            vec![
+                annotation_line("chr1", 100, 500, "DF000000001", "300", "+", "0"),
+                annotation_line("chr1", 2000, 2600, "DF000000001", "250", "-", "1"),
+                annotation_line("chr1", 4000, 4400, "DF000000001", "200", "+", "0"),
+            ],
+        )],
+    );
+    // The index is a link to the versioned file it was written to, which moves with it
+    let versioned = read_link(format!(
+        "{}/{}/{}_idx.dat",
+        release_dir, TEST_ASSEMBLY, ASSEMBLY_DIR
+    ))
+    .expect("Index Is Not A Link");
+    for file in [
+        format!("{}/DF000000001.bed.bgz", ASSEMBLY_DIR),
+        versioned.to_string_lossy().to_string(),
+        format!("{}_idx.dat", ASSEMBLY_DIR),
+    ] {
+        rename(
+            format!("{}/{}/{}", release_dir, TEST_ASSEMBLY, file),
+            format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, file),
+        )
+        .expect("Can't Replace File");
+    }
+    assert_eq!(query().len(), 3);
+    assert_eq!(handle.generation(), 1);
+    // Unchanged files are not reloaded again
+    assert_eq!(query().len(), 3);
+    assert_eq!(handle.generation(), 1);
+}
+
+#[test]
+fn test_index_rebuild_open_handle() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr1".to_string();
+    let index_file = format!("{}/{}/{}_idx.dat", data_directory, assembly, ASSEMBLY_DIR);
+    fn single_hit(fam: &str) -> (&str, Vec<String>) {
+        (
+            fam,
+            vec![annotation_line("chr1", 100, 500, fam, "300", "+", "0")],
+        )
+    }
+
+    // An index written by an earlier release is a regular file
+    build_test_assembly(&data_directory, data_type, &[single_hit("DF000000001")]);
+    let legacy = read_link(&index_file).expect("Index Is Not A Link");
+    copy(
+        format!("{}/{}/{}", data_directory, assembly, legacy.display()),
+        format!("{}.copy", index_file),
+    )
+    .expect("Can't Copy Index");
+    rename(format!("{}.copy", index_file), &index_file).expect("Can't Replace Index");
+    remove_file(format!(
+        "{}/{}/{}",
+        data_directory,
+        assembly,
+        legacy.display()
+    ))
+    .expect("Can't Remove Index");
+
+    let open = || {
+        IndexHandle::open(
+            assembly,
+            data_type,
+            &data_directory,
+            Duration::from_secs(3600),
+        )
+        .expect("Can't Open Index")
+    };
+    let search = |handle: &IndexHandle| {
+        handle
+            .search(
+                chrom, 1, 10000, &None, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed")
+            .len()
+    };
+    let old = open();
+    assert_eq!(search(&old), 1);
+
+    // Rebuilt with a second family while the handle is open, the old bgz file is unchanged
+    build_test_assembly(&data_directory, data_type, &[single_hit("DF000000002")]);
+    let current = read_link(&index_file).expect("Index Is Not A Link");
+    assert_eq!(search(&old), 1);
+    let new = open();
+    assert_eq!(search(&new), 2);
+
+    // Superseded files are kept for the retention window, the current one always
+    build_test_assembly(&data_directory, data_type, &[single_hit("DF000000003")]);
+    assert!(clean_indexes(&index_file, Duration::from_secs(3600))
+        .expect("Clean Failed")
+        .is_empty());
+    let removed = clean_indexes(&index_file, Duration::ZERO).expect("Clean Failed");
+    assert_eq!(
+        removed,
+        [format!(
+            "{}/{}/{}",
+            data_directory,
+            assembly,
+            current.display()
+        )]
+    );
+    let latest = read_link(&index_file).expect("Index Is Not A Link");
+    assert!(Path::new(&format!(
+        "{}/{}/{}",
+        data_directory,
+        assembly,
+        latest.display()
+    ))
+    .exists());
+    assert!(clean_indexes(&index_file, Duration::ZERO)
+        .expect("Clean Failed")
+        .is_empty());
+
+    // Handles opened before the clean read the files they opened to the end
+    assert_eq!(search(&old), 1);
+    assert_eq!(search(&new), 2);
+    assert_eq!(search(&open()), 3);
+}
+
+#[test]
+fn test_index_open_retry() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    copy_test_data(&data_directory, MASKS_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    let query = || {
+        idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            100000,
+            &IdxQueryOptions::default(),
+            &data_directory,
+        )
+    };
+    let expected = query().expect("Index Query Failed");
+
+    // Another thread takes the index away for a ms at a time, as a reader can see it while a
+    // writer swaps it, and every query still finds it within the retries. The index is back
+    // for long enough that five attempts at the retry delays all missing it is improbable.
+    let stop = Arc::new(AtomicBool::new(false));
+    let swapper = {
+        let (index_file, stop) = (index_file.clone(), Arc::clone(&stop));
+        std::thread::spawn(move || {
+            let aside = format!("{}.aside", index_file);
+            let mut swaps = 0;
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                rename(&index_file, &aside).expect("Can't Move Index");
+                std::thread::sleep(Duration::from_millis(1));
+                rename(&aside, &index_file).expect("Can't Restore Index");
+                swaps += 1;
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            swaps
+        })
+    };
+    for _ in 0..100 {
+        assert_eq!(query().expect("Query Failed While Swapped"), expected);
+        let (_, retries) = open_index(&index_file, &OpenRetry::default()).expect("Open Failed");
+        assert!(retries < DEFAULT_OPEN_ATTEMPTS);
+    }
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    assert!(swapper.join().unwrap() > 0);
+
+    // An index that stays missing fails once the attempts are spent
+    remove_file(&index_file).expect("Can't Remove Index");
+    let retry = OpenRetry {
+        attempts: 3,
+        delay: Duration::from_millis(1),
+    };
+    let started = Instant::now();
+    let err = open_index(&index_file, &retry).expect_err("Missing Index Opened");
+    assert!(started.elapsed() >= Duration::from_millis(3));
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let not_indexed = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<NotIndexed>())
+        .expect("Not A NotIndexed Error");
+    assert_eq!(not_indexed.attempts, 3);
+    // And so does a query, with the same error rather than a panic
+    let err = query().expect_err("Missing Index Queried");
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let not_indexed = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<NotIndexed>())
+        .expect("Not A NotIndexed Error");
+    assert_eq!(not_indexed.attempts, DEFAULT_OPEN_ATTEMPTS);
+
+    // Other failures are returned at once, a path under a file cannot appear by waiting
+    let slow_retry = OpenRetry {
+        attempts: 3,
+        delay: Duration::from_secs(10),
+    };
+    let plain_file = format!("{}/plain", data_directory);
+    write(&plain_file, "").expect("Can't Write File");
+    let started = Instant::now();
+    let err = open_index(&format!("{}/idx.dat", plain_file), &slow_retry)
+        .expect_err("Path Under A File Opened");
+    assert_ne!(err.kind(), ErrorKind::NotFound);
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    let _ = working_dir.close();
+}
+
+// Every element's files and bytes add up to the assembly's
+fn assert_usage_sums(usage: &AssemblyUsage) {
+    assert_eq!(
+        usage.elements.iter().map(|e| e.files).sum::<u64>(),
+        usage.files
+    );
+    assert_eq!(
+        usage.elements.iter().map(|e| e.bytes).sum::<u64>(),
+        usage.bytes
+    );
+}
+
+#[test]
+fn test_assembly_usage() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    for data_type in [ASSEMBLY_DIR, MOD_LEN_DIR, SEQUENCE_DIR] {
+        copy_test_data(&data_directory, data_type);
+    }
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, ASSEMBLY_DIR, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    write(
+        format!("{}/{}/notes.txt", data_directory, assembly),
+        "staging notes\n",
+    )
+    .expect("Can't Write File");
+
+    let report = assembly_usage(Some(assembly), &data_directory).expect("Usage Failed");
+    assert_eq!(report.assemblies.len(), 1);
+    let usage = &report.assemblies[0];
+    assert!(usage.files > 0 && usage.bytes > 0);
+    assert_usage_sums(usage);
+    assert_eq!((report.files, report.bytes), (usage.files, usage.bytes));
+    let element = |name: &str| {
+        usage
+            .elements
+            .iter()
+            .find(|e| e.element == name)
+            .unwrap_or_else(|| panic!("No {} Usage", name))
+    };
+
+    // The index, its versioned file, its family listing and the half open marker count with the
+    // annotations, the link sized as a link
+    let annotations = element(ASSEMBLY_DIR);
+    let bgz_bytes = std::fs::metadata(format!("{}/DF000000001.bed.bgz", bgz_dir))
+        .unwrap()
+        .len();
+    let index_bytes = std::fs::metadata(&index_file).unwrap().len();
+    let listing_bytes = std::fs::metadata(family_listing_file(&index_file))
+        .unwrap()
+        .len();
+    assert_eq!(annotations.files, 5);
+    assert!(annotations.bytes >= bgz_bytes + index_bytes + listing_bytes);
+    assert!(annotations.bytes < bgz_bytes + 2 * index_bytes + listing_bytes);
+    // Ranges spanning tiles are stored once per tile
+    let records = read_line_records(&format!("{}/DF000000001.bed.bgz", bgz_dir)).len() as u64;
+    assert!(annotations.ranges.is_some_and(|ranges| ranges >= records));
+    for json in [MOD_LEN_DIR, SEQUENCE_DIR] {
+        let json_bytes = std::fs::metadata(format!(
+            "{}/{}/{}/{}-{}.json",
+            data_directory, assembly, json, assembly, json
+        ))
+        .unwrap()
+        .len();
+        assert_eq!(element(json).bytes, json_bytes);
+        assert_eq!(element(json).ranges, None);
+    }
+    assert_eq!(element(OTHER_ELEMENT).files, 1);
+    assert!(usage
+        .elements
+        .windows(2)
+        .all(|pair| pair[0].bytes >= pair[1].bytes));
+
+    // Every assembly of the data folder, totals per element across them
+    let second = format!("{}/second_ex", data_directory);
+    create_dir_all(format!("{}/{}", second, SEQUENCE_DIR)).expect("Can't Create Dir");
+    copy(
+        format!(
+            "{}/{}/{}/{}-{}.json",
+            data_directory, assembly, SEQUENCE_DIR, assembly, SEQUENCE_DIR
+        ),
+        format!(
+            "{}/{}/second_ex-{}.json",
+            second, SEQUENCE_DIR, SEQUENCE_DIR
+        ),
+    )
+    .expect("Can't Copy File");
+    let all = assembly_usage(None, &data_directory).expect("Usage Failed");
+    assert_eq!(all.assemblies.len(), 2);
+    assert_eq!(all.assemblies[0], *usage);
+    for usage in &all.assemblies {
+        assert_usage_sums(usage);
+    }
+    assert_eq!(
+        all.files,
+        all.assemblies.iter().map(|a| a.files).sum::<u64>()
+    );
+    assert_eq!(
+        all.bytes,
+        all.assemblies.iter().map(|a| a.bytes).sum::<u64>()
+    );
+    assert_eq!(all.elements.iter().map(|e| e.bytes).sum::<u64>(), all.bytes);
+    assert_eq!(
+        all.elements
+            .iter()
+            .find(|e| e.element == SEQUENCE_DIR)
+            .unwrap()
+            .bytes,
+        2 * element(SEQUENCE_DIR).bytes
+    );
+    let lines = all.tsv_lines();
+    assert!(lines.contains(&format!(
+        "{}\ttotal\t{}\t{}\t-",
+        USAGE_TOTAL, all.files, all.bytes
+    )));
+
+    assert_eq!(
+        assembly_usage(Some(&"missing".to_string()), &data_directory)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn test_bgzf_filter_strand() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let fam = &"DF000000001".to_string();
+    let data_directory = test_data_dir().to_string();
+
+    let filter = |strand: Option<&str>| {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            assembly,
+            data_type,
+            fam,
+            &mut output,
+            &FilterOptions {
+                position: 13,
+                term: Some("1".to_string()),
+                strand: strand.map(|s| s.to_string()),
+                ..FilterOptions::default()
+            },
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        let lines: Vec<String> = output
+            .lines()
+            .map(|l| l.unwrap())
+            .filter(|l| !l.starts_with('#'))
+            .collect();
+        lines
+    };
+
+    let all = filter(None);
+    let plus = filter(Some("+"));
+    let minus = filter(Some("-"));
+    assert!(!plus.is_empty() && !minus.is_empty());
+    assert!(minus.iter().all(|l| l.split('\t').nth(5) == Some("-")));
+    assert_eq!(plus.len() + minus.len(), all.len());
+}
+
+#[test]
+fn test_bgzf_filter_prefilter() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    // Terms that are substrings of other fields, or of the searched field, on most lines
+    let lines: Vec<String> = (0..300)
+        .map(|i| {
+            let chrom = ["chr1", "chr10", "chr11"][i % 3];
+            let nrph = ["1", "0", "10"][i / 3 % 3];
+            annotation_line(chrom, 100 + i as u64, 1100 + i as u64, fam, "31", "+", nrph)
+        })
+        .collect();
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines.clone())]);
+    let filter = |position: usize, term: &str, prefilter: bool| {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            fam,
+            &mut output,
+            &FilterOptions::builder()
+                .term(position, term)
+                .profile(INTERNAL_PROFILE)
+                .prefilter(prefilter)
+                .build(),
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        output
+            .lines()
+            .map(|l| l.expect("Output Is Not Valid UTF-8"))
+            .collect::<Vec<String>>()
+    };
+
+    for (position, term) in [
+        (1, "chr1"),
+        (13, "1"),
+        (13, "10"),
+        (5, "31"),
+        (5, "3"),
+        (1, "TEST"),
+        (1, "absent"),
+    ] {
+        let expected = lines
+            .iter()
+            .filter(|l| l.split('\t').nth(position - 1) == Some(term))
+            .count();
+        let fast = filter(position, term, true);
+        assert_eq!(fast, filter(position, term, false), "{} {}", position, term);
+        // Every output has its header, even when nothing matched
+        assert!(fast[0].starts_with('#'));
+        assert_eq!(fast.len() - 1, expected, "{} {}", position, term);
+    }
+
+    assert_eq!(filter_worker_count(0).get(), 1);
+    let cores = std::thread::available_parallelism().unwrap().get();
+    assert_eq!(filter_worker_count(1 << 40).get(), cores);
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_idx_query_masks_strand() {
+    assert_invalid_option(
+        idx_query_with(
+            TEST_ASSEMBLY,
+            MASKS_DIR,
+            "chr1",
+            30000,
+            31000,
+            &IdxQueryOptions::builder().strand("-").build(),
+            test_data_dir(),
+        ),
+        "Masks Have No Strand Column",
+    );
+}
+
+#[test]
+fn test_idx_query_fam() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr10".to_string();
+    let start = 10000;
+    let end = 100000;
+    let data_directory = test_data_dir().to_string();
+
+    let res = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions::builder().family("DF000000001").build(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    println!("{:?}", res);
+    let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(vals.len(), 4);
+}
+
+#[test]
+fn test_idx_query_nrph() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr10".to_string();
+    let start = 10000;
+    let end = 100000;
+    let data_directory = test_data_dir().to_string();
+
+    let res = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions::builder()
+            .family("DF000000001")
+            .nrph(true)
+            .build(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(vals.len(), 4);
+}
+
+#[test]
+fn test_idx_query_downsample() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let chrom = &"chr10".to_string();
+    let start = 1;
+    let end = 5000000;
+    let data_directory = test_data_dir().to_string();
+
+    let full = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions::default(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let full: Vec<Value> = from_str(&full).expect("Cannot Deserialize");
+
+    let res = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        start,
+        end,
+        &IdxQueryOptions::builder().downsample("2:1000000").build(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let res: Value = from_str(&res).expect("Cannot Deserialize");
+    let hits = res["hits"].as_array().unwrap();
+    let dropped = res["downsample"]["dropped"].as_u64().unwrap() as usize;
+    assert_eq!(hits.len() + dropped, full.len());
+
+    let window_of = |hit: &Value| -> u64 {
+        let s = hit["seq_start"].as_u64().unwrap();
+        let e = hit["seq_end"].as_u64().unwrap();
+        s.min(e) / 1000000
+    };
+    let score_of = |hit: &Value| -> f64 { hit["bit_score"].as_f64().unwrap() };
+    let mut per_window: HashMap<u64, Vec<f64>> = HashMap::new();
+    for hit in &full {
+        per_window
+            .entry(window_of(hit))
+            .or_default()
+            .push(score_of(hit));
+    }
+    for (window, scores) in per_window.iter_mut() {
+        scores.sort_by(|a, b| b.total_cmp(a));
+        let mut kept: Vec<f64> = hits
+            .iter()
+            .filter(|h| window_of(h) == *window)
+            .map(score_of)
+            .collect();
+        kept.sort_by(|a, b| b.total_cmp(a));
+        // every window is capped and only its top scores survive
+        assert_eq!(kept.len(), scores.len().min(2));
+        assert_eq!(kept, scores[..kept.len()].to_vec());
+    }
+}
+
+#[test]
+fn test_e_value() {
+    let e = |text: &str| EValue::parse(text).expect("E-Value Not Parsed");
+    for bad in [
+        "", "n/a", "-1e-5", "1e", "e-5", "1e+-5", "1.2.3", "inf", "NaN",
+    ] {
+        assert!(EValue::parse(bad).is_none(), "{} Parsed", bad);
+    }
+    assert_eq!(e(" 2.3e-101 ").as_str(), "2.3e-101");
+    assert_eq!(e("1e-3"), e("0.001"));
+    assert_eq!(e("1E+03"), e("1000"));
+    assert_eq!(e("0"), e("0.0e-400"));
+
+    // Ordered exactly, below the range of f64 too
+    let sorted = [
+        "0", "1e-400", "2.3e-400", "1e-320", "1.5e-320", "2.3e-101", "0.001", "0.5", "12", "1e5",
+    ];
+    for pair in sorted.windows(2) {
+        assert!(e(pair[0]) < e(pair[1]), "{} Not Below {}", pair[0], pair[1]);
+    }
+    let mut shuffled: Vec<EValue> = sorted.iter().rev().map(|text| e(text)).collect();
+    shuffled.sort();
+    let texts: Vec<&str> = shuffled.iter().map(EValue::as_str).collect();
+    assert_eq!(texts, sorted);
+    // Values f64 rounds together still differ
+    assert_ne!(e("1e-400"), e("2e-400"));
+    assert_eq!(e("1e-400").to_f64(), e("2e-400").to_f64());
+
+    // Numbers when a double holds them, the string as written otherwise
+    assert_eq!(e("2.3e-101").to_json(false), json!(2.3e-101));
+    assert_eq!(e("0").to_json(false), json!(0.0));
+    assert_eq!(e("1e-320").to_json(false), json!("1e-320"));
+    assert_eq!(e("2.3e-400").to_json(false), json!("2.3e-400"));
+    assert_eq!(e("1e400").to_json(false), json!("1e400"));
+    assert_eq!(e("2.3e-101").to_json(true), json!("2.3e-101"));
+    assert_eq!(
+        serde_json::to_string(&[e("1e-320"), e("0.5")]).unwrap(),
+        r#"["1e-320",0.5]"#
+    );
+}
+
+#[test]
+fn test_idx_query_typed_json() {
+    // Expected JSON type of every field, null is tolerated for malformed source values
+    let schema: HashMap<&str, &str> = HashMap::from([
+        ("sequence", "string"),
+        ("accession", "string"),
+        ("bit_score", "number"),
+        ("e_value", "e_value"),
+        ("bias", "number"),
+        ("seq_start", "integer"),
+        ("seq_end", "integer"),
+        ("strand", "string"),
+        ("ali_start", "integer"),
+        ("ali_end", "integer"),
+        ("model_start", "integer"),
+        ("model_end", "integer"),
+    ]);
+    let matches_schema = |hit: &Value| -> bool {
+        let obj = hit.as_object().unwrap();
+        obj.len() == schema.len()
+            && obj.iter().all(|(k, v)| match schema.get(k.as_str()) {
+                Some(&"string") => v.is_string(),
+                Some(&"integer") => v.is_u64() || v.is_null(),
+                Some(&"number") => v.is_number() || v.is_null(),
+                // A string when too small for a double, see EValue
+                Some(&"e_value") => v.is_number() || v.is_string() || v.is_null(),
+                _ => false,
+            })
+    };
+
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            "DF000000001",
+            vec![
+                annotation_line("chr1", 100, 200, "DF000000001", "25.5", "+", "1"),
+                annotation_line("chr1", 300, 400, "DF000000001", "n/a", "-", "0"),
+            ],
+        )],
+    );
+
+    let res = idx_query_with(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        "chr1",
+        1,
+        1000,
+        &IdxQueryOptions::default(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(vals.len(), 2);
+    assert!(vals.iter().all(matches_schema));
+    assert_eq!(vals[0]["seq_start"], 100);
+    assert_eq!(vals[0]["bit_score"], 25.5);
+    assert!(vals[1]["bit_score"].is_null());
+
+    // The legacy flag keeps every value a string
+    let legacy = idx_query_with(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        "chr1",
+        1,
+        1000,
+        &IdxQueryOptions::builder().legacy_json(true).build(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let legacy: Vec<HashMap<String, String>> = from_str(&legacy).expect("Cannot Deserialize");
+    assert_eq!(legacy[0]["seq_start"], "100");
+    assert_eq!(legacy[1]["bit_score"], "n/a");
+
+    let _ = working_directory.close();
+}
+
+// Sequences JSON with NCBI style ids, as the Dfamseq exports have
+fn write_sequences_json(data_directory: &str) {
+    let seq_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, SEQUENCE_DIR);
+    create_dir_all(&seq_dir).expect("Can't Create Dir");
+    let data = serde_json::json!({
+        "assembly": "hg38",
+        "data": {
+            "chr1": {"id": "CM000663.2", "length": 248956422},
+            "chr11_KI270721v1_random": {"id": "KI270721.1", "length": 100316},
+            "chr14_KI270722v1_random": {"id": "KI270722.1", "length": 194050},
+            "chrUn_KI270302v1": {"id": "KI270302.1", "length": 2274},
+            "chrUn_GL000195v1": {"id": "GL000195.1", "length": 182896},
+        }
+    });
+    write(
+        format!("{}/{}-{}.json", seq_dir, TEST_ASSEMBLY, SEQUENCE_DIR),
+        data.to_string(),
+    )
+    .expect("Can't Write JSON");
+}
+
+#[test]
+fn test_get_chrom_id() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    write_sequences_json(&data_directory);
+    let assembly = &TEST_ASSEMBLY.to_string();
+
+    let lookup =
+        |query: &str| get_chrom_id(assembly, query, &data_directory).expect("JSON Read Failed");
+    assert_eq!(lookup("chr1"), "chr1");
+    assert_eq!(lookup("KI270721.1"), "chr11_KI270721v1_random");
+    // Exact mode never falls back to partial matches
+    assert_eq!(lookup("KI270"), "-1");
+    assert_eq!(lookup("chr"), "-1");
+}
+
+#[test]
+fn test_resolve_sequence_forms() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let hit = |chrom: &str, start: u64, fam: &str| {
+        annotation_line(chrom, start, start + 200, fam, "100.0", "+", "1")
+    };
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (
+                "DF000000001",
+                vec![hit("1", 1000, "DF000000001"), hit("2", 1000, "DF000000001")],
+            ),
+            (
+                "DF000000002",
+                vec![
+                    hit("1", 5000, "DF000000002"),
+                    hit("DS000000003", 100, "DF000000002"),
+                ],
+            ),
+        ],
+    );
+    let seq_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, SEQUENCE_DIR);
+    create_dir_all(&seq_dir).expect("Can't Create Dir");
+    let data = json!({
+        "assembly": "hg38",
+        "data": {
+            "1": {"id": "chr1", "length": 248956422},
+            "2": {"id": "chr2", "length": 242193529},
+            "DS000000003": {"id": "chr3", "length": 198295559},
+        }
+    });
+    write(
+        format!("{}/{}-{}.json", seq_dir, TEST_ASSEMBLY, SEQUENCE_DIR),
+        data.to_string(),
+    )
+    .expect("Can't Write JSON");
+
+    let resolve = |query: &str| {
+        let resolved =
+            resolve_sequence(assembly, query, &data_directory).expect("Sequence Not Resolved");
+        (resolved.accession, resolved.resolution, resolved.qualified)
+    };
+    assert_eq!(
+        resolve("1"),
+        ("1".to_string(), SequenceResolution::Accession, false)
+    );
+    assert_eq!(
+        resolve(" 1\t"),
+        ("1".to_string(), SequenceResolution::Accession, false)
+    );
+    assert_eq!(
+        resolve("hg38:1"),
+        ("1".to_string(), SequenceResolution::Accession, true)
+    );
+    for dfamseq in ["DS000000001", "ds1", "DS000000001.2"] {
+        assert_eq!(
+            resolve(dfamseq),
+            ("1".to_string(), SequenceResolution::Dfamseq, false)
+        );
+    }
+    assert_eq!(
+        resolve("test_ex:DS000000001"),
+        ("1".to_string(), SequenceResolution::Dfamseq, true)
+    );
+    assert_eq!(
+        resolve("3"),
+        (
+            "DS000000003".to_string(),
+            SequenceResolution::Dfamseq,
+            false
+        )
+    );
+    assert_eq!(
+        resolve("chr2"),
+        ("2".to_string(), SequenceResolution::Id, false)
+    );
+    assert_eq!(
+        resolve("HG38:chr2"),
+        ("2".to_string(), SequenceResolution::Id, true)
+    );
+
+    // Unknown forms and qualifiers of other assemblies are not found, listing the forms
+    for unknown in ["chrX", "DS1x", "mm10:1", "4", "CHR1"] {
+        let err = resolve_sequence(assembly, unknown, &data_directory)
+            .expect_err("Unknown Sequence Resolved");
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains(SEQUENCE_FORMS), "{}", err);
+        assert_eq!(
+            get_chrom_id(assembly, unknown, &data_directory).unwrap(),
+            "-1"
+        );
+    }
+    assert_eq!(
+        get_chrom_id(assembly, "DS000000002", &data_directory).unwrap(),
+        "2"
+    );
+
+    // Every form of a sequence queries the same hits
+    let query = |chrom: &str, options: &IdxQueryOptions| {
+        idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            chrom,
+            1,
+            10000,
+            options,
+            &data_directory,
+        )
+    };
+    let options = IdxQueryOptions::default();
+    let expected = query("1", &options).expect("Index Query Failed");
+    assert_eq!(from_str::<Vec<Value>>(&expected).unwrap().len(), 2);
+    for chrom in [
+        "DS000000001",
+        "chr1",
+        "hg38:1",
+        "test_ex:chr1",
+        "ds000000001.1",
+    ] {
+        assert_eq!(
+            query(chrom, &options).expect("Index Query Failed"),
+            expected,
+            "{}",
+            chrom
+        );
+    }
+    assert_eq!(
+        query("3", &options).expect("Index Query Failed"),
+        query("DS000000003", &options).expect("Index Query Failed")
+    );
+    let err = query("chrX", &options).expect_err("Unknown Sequence Queried");
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+
+    // With warnings the response says how the chrom was resolved
+    let options = IdxQueryOptions::builder().with_warnings(true).build();
+    let response: Value = from_str(&query("chr1", &options).unwrap()).unwrap();
+    assert_eq!(response["sequence"]["accession"], "1");
+    assert_eq!(response["sequence"]["resolution"], "id");
+    let response: Value = from_str(&query("1", &options).unwrap()).unwrap();
+    assert_eq!(response["sequence"]["resolution"], "accession");
+}
+
+#[test]
+fn test_redact_accessions() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let hit = |chrom: &str, fam: &str| annotation_line(chrom, 1000, 1200, fam, "100.0", "+", "1");
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (
+                "DF000000001",
+                vec![
+                    hit("DS000000011", "DF000000001"),
+                    hit("DS000000012", "DF000000001"),
+                ],
+            ),
+            ("DF000000002", vec![hit("DS000000013", "DF000000002")]),
+        ],
+    );
+    build_test_assembly(
+        &data_directory,
+        MASKS_DIR,
+        &[(
+            "DS000000011",
+            vec!["DS000000011\t900\t1100\tTC\t2".to_string()],
+        )],
+    );
+    // DS000000013 has no display name
+    let seq_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, SEQUENCE_DIR);
+    create_dir_all(&seq_dir).expect("Can't Create Dir");
+    let data = json!({
+        "assembly": TEST_ASSEMBLY,
+        "data": {
+            "DS000000011": {"id": "chrA", "length": 100000},
+            "DS000000012": {"id": "chrB", "length": 100000},
+            "DS000000013": {"length": 100000},
+        }
+    });
+    write(
+        format!("{}/{}-{}.json", seq_dir, TEST_ASSEMBLY, SEQUENCE_DIR),
+        data.to_string(),
+    )
+    .expect("Can't Write JSON");
+    write_assembly_meta(
+        assembly,
+        &AssemblyMeta {
+            layout_version: LAYOUT_VERSION,
+            redact_accessions: true,
+            ..AssemblyMeta::default()
+        },
+        &data_directory,
+    )
+    .expect("Can't Write Meta");
+    assert!(redacts_accessions(assembly, false, &data_directory).unwrap());
+    assert!(!redacts_accessions(assembly, true, &data_directory).unwrap());
+
+    let query = |data_type: &str, chrom: &str, options: &IdxQueryOptions| {
+        idx_query_with(
+            assembly,
+            data_type,
+            chrom,
+            1,
+            10000,
+            options,
+            &data_directory,
+        )
+    };
+    let filter = |key: &str, options: &FilterOptions| {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            assembly,
+            ASSEMBLY_DIR,
+            key,
+            &mut output,
+            options,
+            &data_directory,
+        )
+        .map(|_| String::from_utf8(output).unwrap())
+    };
+
+    // No accession in any output, each sequence is named by its id
+    let warned = IdxQueryOptions::builder().with_warnings(true).build();
+    let res = query(ASSEMBLY_DIR, "chrA", &warned).expect("Index Query Failed");
+    assert!(!res.contains("DS0000000"), "{}", res);
+    let res: Value = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(res["hits"][0]["seq_name"], "chrA");
+    assert!(res["hits"][0].get("sequence").is_none());
+    assert_eq!(
+        res["sequence"],
+        json!({"seq_name": "chrA", "resolution": "id"})
+    );
+    let res = query(MASKS_DIR, "chrA", &warned).expect("Index Query Failed");
+    assert!(!res.contains("DS0000000"), "{}", res);
+    let res: Value = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(res["hits"][0]["seq_name"], "chrA");
+    assert!(res["hits"][0].get("seq_acc").is_none());
+    let bed = IdxQueryOptions::builder()
+        .bed_format(BedFormat::Bed6)
+        .build();
+    let res = query(ASSEMBLY_DIR, "chrA", &bed).expect("Index Query Failed");
+    assert!(res.starts_with("chrA\t"), "{}", res);
+
+    for options in [
+        FilterOptions::default(),
+        FilterOptions::builder().bed_format(BedFormat::Bed6).build(),
+    ] {
+        let output = filter("DF000000001", &options).expect("Filter Failed");
+        assert!(!output.contains("DS0000000"), "{}", output);
+        assert!(output.contains("chrA\t") && output.contains("chrB\t"));
+    }
+
+    // A sequence without a display name is refused rather than shown by accession
+    let err = query(ASSEMBLY_DIR, "DS000000013", &IdxQueryOptions::default())
+        .expect_err("Accession Shown");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let err = filter("DF000000002", &FilterOptions::default()).expect_err("Accession Shown");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // Internal tooling sees the accessions
+    let res = query(
+        ASSEMBLY_DIR,
+        "chrA",
+        &IdxQueryOptions::builder().ignore_redaction(true).build(),
+    )
+    .expect("Index Query Failed");
+    let res: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(res[0]["sequence"], "DS000000011");
+    let output = filter(
+        "DF000000002",
+        &FilterOptions::builder().ignore_redaction(true).build(),
+    )
+    .expect("Filter Failed");
+    assert!(output.contains("DS000000013\t"));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_export_manifest() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            "DF000000001",
+            vec![
+                annotation_line("DS000000011", 1000, 1200, "DF000000001", "100.0", "+", "1"),
+                annotation_line("DS000000012", 3000, 3400, "DF000000001", "90.0", "-", "0"),
+            ],
+        )],
+    );
+    let outfile = format!("{}/DF000000001.bed", data_directory);
+    let options = FilterOptions::default();
+    let mut writer = File::create(&outfile).expect("Can't Create Output");
+    let count = bgzf_filter_with(
+        assembly,
+        ASSEMBLY_DIR,
+        "DF000000001",
+        &mut writer,
+        &options,
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    drop(writer);
+
+    let inputs = export_inputs(assembly, ASSEMBLY_DIR, "DF000000001", &data_directory);
+    let bgz_file = format!(
+        "{}/{}/{}/DF000000001.bed.bgz",
+        data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR
+    );
+    assert!(inputs.contains(&bgz_file), "{:?}", inputs);
+    assert!(inputs.iter().any(|path| path.ends_with("_idx.dat")));
+    let manifest = write_manifest(
+        &outfile,
+        "bgzf-filter",
+        assembly,
+        filter_parameters(ASSEMBLY_DIR, "DF000000001", &options),
+        &inputs,
+        Some(count as u64),
+    )
+    .expect("Can't Write Manifest");
+    assert_eq!(manifest.records, Some(2));
+    assert_eq!(manifest.te_idx_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(manifest.parameters["key"], "DF000000001");
+    let digest = format!("{:x}", Sha256::digest(std::fs::read(&outfile).unwrap()));
+    assert_eq!(manifest.output.sha256, digest);
+
+    let manifest_file = manifest_path(&outfile);
+    assert_eq!(manifest_file, format!("{}{}", outfile, MANIFEST_SUFFIX));
+    let written: Value = from_str(&read_to_string(&manifest_file).unwrap()).unwrap();
+    assert_eq!(written["output"]["path"], outfile);
+    let verification = verify_manifest(&manifest_file).expect("Can't Verify Manifest");
+    assert!(verification.verified, "{:?}", verification);
+    assert!(verification.mismatches.is_empty());
+
+    // A tampered output fails, the same size or not
+    let original = read_to_string(&outfile).unwrap();
+    write(&outfile, original.replacen("DS000000011", "DS000000019", 1)).unwrap();
+    let verification = verify_manifest(&manifest_file).expect("Can't Verify Manifest");
+    assert!(!verification.verified);
+    assert!(!verification.output_matches);
+    assert_eq!(verification.mismatches.len(), 1);
+    assert_eq!(verification.mismatches[0].path, outfile);
+    assert_eq!(verification.mismatches[0].reason, "Checksum Differs");
+    write(&outfile, format!("{}extra\n", original)).unwrap();
+    let verification = verify_manifest(&manifest_file).expect("Can't Verify Manifest");
+    assert!(!verification.output_matches);
+
+    // A changed input fails with the output intact
+    write(&outfile, &original).unwrap();
+    remove_file(&bgz_file).unwrap();
+    let verification = verify_manifest(&manifest_file).expect("Can't Verify Manifest");
+    assert!(!verification.verified);
+    assert!(verification.output_matches);
+    assert_eq!(verification.mismatches[0].path, bgz_file);
+    assert!(verification.mismatches[0].input);
+    assert_eq!(verification.mismatches[0].reason, "Missing");
+
+    write(&manifest_file, "{}").unwrap();
+    let err = verify_manifest(&manifest_file).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_family_versions_match_consistently() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let hit =
+        |start: u64, fam: &str| annotation_line("chr1", start, start + 200, fam, "50.0", "+", "1");
+    // Exported with a version, without one, and two versions of the same family
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            ("DF000000001.2", vec![hit(1000, "DF000000001.2")]),
+            ("DF000000002", vec![hit(2000, "DF000000002")]),
+            ("DF000000003.1", vec![hit(3000, "DF000000003.1")]),
+            ("DF000000003.2", vec![hit(4000, "DF000000003.2")]),
+        ],
+    );
+
+    assert!(acc_matches("DF000000001", "DF000000001.2", false));
+    assert!(acc_matches("DF000000001.3", "DF000000001.2", false));
+    assert!(acc_matches(" DF000000001.2", "DF000000001", false));
+    assert!(!acc_matches("DF000000001", "DF0000000012", false));
+    assert!(acc_matches("DF000000001", "DF000000001.2", true));
+    assert!(acc_matches("DF000000001.2", "DF000000001.2", true));
+    assert!(!acc_matches("DF000000001.3", "DF000000001.2", true));
+    assert!(!acc_matches("DF000000001.2", "DF000000001", true));
+
+    // The accessions of the hits idx_query returns for a family
+    let query = |family: &str, exact_version: bool| -> Vec<String> {
+        let res = idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            10000,
+            &IdxQueryOptions::builder()
+                .family(family)
+                .exact_version(exact_version)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        hits.iter()
+            .map(|hit| hit["accession"].as_str().unwrap().to_string())
+            .collect()
+    };
+    // The family column of the records bgzf_filter writes for a key
+    let filter = |key: &str, options: &FilterOptions| -> Vec<String> {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            assembly,
+            ASSEMBLY_DIR,
+            key,
+            &mut output,
+            options,
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split('\t').nth(3).unwrap().to_string())
+            .collect()
+    };
+    let exact = FilterOptions::builder().exact_version(true).build();
+    for (family, exact_version, expected) in [
+        ("DF000000001", false, vec!["DF000000001.2"]),
+        ("DF000000001.3", false, vec!["DF000000001.2"]),
+        ("DF000000001", true, vec!["DF000000001.2"]),
+        ("DF000000001.2", true, vec!["DF000000001.2"]),
+        ("DF000000002", false, vec!["DF000000002"]),
+        ("DF000000002.1", false, vec!["DF000000002"]),
+        ("DF000000003.2", true, vec!["DF000000003.2"]),
+    ] {
+        assert_eq!(query(family, exact_version), expected, "{}", family);
+        let options = match exact_version {
+            true => exact.clone(),
+            false => FilterOptions::default(),
+        };
+        assert_eq!(filter(family, &options), expected, "{}", family);
+    }
+    // No hits and no file for a version the assembly does not have
+    assert!(query("DF000000001.3", true).is_empty());
+    assert!(query("DF000000002.1", true).is_empty());
+    let data_path = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR);
+    let path =
+        key_bgz_path(&data_path, ASSEMBLY_DIR, "DF000000001.3", true).expect("Lookup Failed");
+    assert!(!Path::new(&path).exists());
+    // Both versions of a family are returned by the query, the filter asks for one
+    assert_eq!(
+        query("DF000000003", false),
+        vec!["DF000000003.1", "DF000000003.2"]
+    );
+    let err = key_bgz_path(&data_path, ASSEMBLY_DIR, "DF000000003", false).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("DF000000003.1.bed.bgz"), "{}", err);
+
+    // A term on the family column matches as the family filter does
+    let by_term = |term: &str, exact_version: bool| {
+        FilterOptions::builder()
+            .term(4, term)
+            .exact_version(exact_version)
+            .build()
+    };
+    assert_eq!(
+        filter("DF000000001", &by_term("DF000000001", false)),
+        vec!["DF000000001.2"]
+    );
+    assert!(filter("DF000000001", &by_term("DF000000001.3", true)).is_empty());
+    assert_eq!(
+        filter("DF000000002", &by_term("DF000000002.4", false)),
+        vec!["DF000000002"]
+    );
+    assert_eq!(
+        missing_families(
+            assembly,
+            ASSEMBLY_DIR,
+            &["DF000000001".to_string(), "DF000000002.1".to_string()],
+            false,
+            &data_directory,
+        )
+        .expect("Missing Families Failed"),
+        Vec::<String>::new()
+    );
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_idx_query_scan_fallback() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let lines = |fam: &str, offset: u64| -> Vec<String> {
+        (0..40)
+            .map(|i| {
+                let start = offset + i * 700;
+                match i % 3 {
+                    0 => annotation_line("chr1", start, start + 900, fam, "30.5", "+", "1"),
+                    1 => annotation_line("chr1", start + 900, start, fam, "12.0", "-", "0"),
+                    _ => annotation_line("chr2", start, start + 300, fam, "40.0", "+", "1"),
+                }
+            })
+            .collect()
+    };
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            ("DF000000001", lines("DF000000001", 100)),
+            ("DF000000002", lines("DF000000002", 350)),
+            ("DF000000003", lines("DF000000003", 5000)),
+        ],
+    );
+    let query = |options: &IdxQueryOptions| {
+        idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr1",
+            2000,
+            20000,
+            options,
+            &data_directory,
+        )
+        .expect("Index Query Failed")
+    };
+    let filters = [
+        IdxQueryOptions::default(),
+        IdxQueryOptions::builder().family("DF000000002").build(),
+        IdxQueryOptions::builder()
+            .nrph(true)
+            .strand("+")
+            .min_bit_score(20.0)
+            .build(),
+    ];
+    let indexed: Vec<String> = filters.iter().map(query).collect();
+    let allowing = |options: &IdxQueryOptions| IdxQueryOptions {
+        allow_scan: true,
+        ..options.clone()
+    };
+    // A usable index is searched as before, scan allowed or not
+    for (options, expected) in filters.iter().zip(&indexed) {
+        assert_eq!(&query(&allowing(options)), expected);
+    }
+
+    let (_, index_file) = idx_paths(assembly, ASSEMBLY_DIR, &data_directory);
+    let scanned = |options: &IdxQueryOptions| -> Value {
+        from_str(&query(&allowing(options))).expect("Cannot Deserialize")
+    };
+    // Only the file of a filtered family is read
+    let files_read = [3, 1, 3];
+    let check_scan = || {
+        for ((options, expected), files) in filters.iter().zip(&indexed).zip(files_read) {
+            let res = scanned(options);
+            let expected: Value = from_str(expected).unwrap();
+            assert!(!expected.as_array().unwrap().is_empty());
+            assert_eq!(res["hits"], expected);
+            assert_eq!(res["scan"]["files"], files);
+            assert!(res["scan"]["elapsed_ms"].as_f64().is_some());
+            assert!(res["scan"]["warning"]
+                .as_str()
+                .unwrap()
+                .contains("Files Were Scanned"));
+        }
+        let warned = scanned(&IdxQueryOptions::builder().with_warnings(true).build());
+        assert!(warned["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|w| w.as_str().unwrap().contains("Files Were Scanned")));
+    };
+
+    // A corrupt index, then none at all
+    write(&index_file, b"not an index").unwrap();
+    check_scan();
+    remove_file(&index_file).unwrap();
+    check_scan();
+
+    // Bed output is the same as well
+    let bed = IdxQueryOptions::builder()
+        .bed_format(BedFormat::Bed6)
+        .allow_scan(true)
+        .build();
+    let scanned_bed = query(&bed);
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[]);
+    assert_eq!(scanned_bed, query(&bed));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_query_etag() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let lines = |fam: &str, count: u64| -> Vec<String> {
+        (0..count)
+            .map(|i| annotation_line("chr1", 100 + i * 500, 600 + i * 500, fam, "30.5", "+", "1"))
+            .collect()
+    };
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            ("DF000000001", lines("DF000000001", 20)),
+            ("DF000000002", lines("DF000000002", 10)),
+        ],
+    );
+    let query = |options: &IdxQueryOptions| -> Value {
+        from_str(
+            &idx_query_with(
+                assembly,
+                ASSEMBLY_DIR,
+                "chr1",
+                1,
+                8000,
+                options,
+                &data_directory,
+            )
+            .expect("Index Query Failed"),
+        )
+        .expect("Cannot Deserialize")
+    };
+    let etag = |options: &IdxQueryOptions| {
+        query_etag(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            8000,
+            options,
+            &data_directory,
+        )
+        .expect("No ETag")
+    };
+
+    // Stable across repeated queries, and the same in the response, the head query and alone
+    let with_etag = IdxQueryOptions::builder().with_etag(true).build();
+    let first = query(&with_etag);
+    let current = first["etag"].as_str().expect("No ETag").to_string();
+    assert_eq!(current.len(), 64);
+    assert_eq!(query(&with_etag), first);
+    assert_eq!(etag(&IdxQueryOptions::default()), current);
+    let head = query(&IdxQueryOptions::builder().head(true).build());
+    assert_eq!(head, json!({ "etag": current, "total": 26 }));
+    assert_eq!(first["hits"].as_array().unwrap().len(), 26);
+
+    // The normalized query: families in any order are the same, other filters are not
+    let families = |families: &[&str]| {
+        etag(
+            &IdxQueryOptions::builder()
+                .families(families.to_vec())
+                .build(),
+        )
+    };
+    assert_eq!(
+        families(&["DF000000001", "DF000000002"]),
+        families(&["DF000000002", "DF000000001", "DF000000002"])
+    );
+    assert_ne!(families(&["DF000000001"]), current);
+    assert_ne!(
+        etag(&IdxQueryOptions::builder().nrph(true).build()),
+        current
+    );
+    let head = query(
+        &IdxQueryOptions::builder()
+            .family("DF000000002")
+            .head(true)
+            .build(),
+    );
+    assert_eq!(head["etag"].as_str().unwrap(), families(&["DF000000002"]));
+    assert_eq!(head["total"], 10);
+    assert!(idx_query_with(
+        assembly,
+        ASSEMBLY_DIR,
+        "chr1",
+        1,
+        8000,
+        &IdxQueryOptions::builder()
+            .head(true)
+            .bed_format(BedFormat::Bed6)
+            .build(),
+        &data_directory,
+    )
+    .is_err());
+
+    // Changed by an index rebuilt from changed data
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[("DF000000002", lines("DF000000002", 12))],
+    );
+    let rebuilt = query(&with_etag);
+    assert_ne!(rebuilt["etag"], first["etag"]);
+    assert_eq!(rebuilt["hits"].as_array().unwrap().len(), 28);
+    assert_eq!(query(&with_etag)["etag"], rebuilt["etag"]);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_dl_writer() {
+    assert_eq!(sanitize_field("Alu\tY"), "Alu Y");
+    assert_eq!(sanitize_field("L1\r\nHS\n"), "L1  HS ");
+    assert!(matches!(sanitize_field("MIR b"), Cow::Borrowed("MIR b")));
+    assert_eq!(join_fields(["a\tb", "", "c"]), "a b\t\tc");
+
+    // Selected columns in the given order, missing ones empty, then the trailing fields
+    let mut out = Vec::new();
+    let mut writer = DlWriter::new(&mut out, &["one", "two", "th\tree", "four"])
+        .select(&[2, 0, 5])
+        .trailing(&["extra"], vec!["x\ny".to_string()]);
+    assert_eq!(writer.header(), ["th\tree", "one", "", "extra"]);
+    writer.write_header().unwrap();
+    writer.write_record(&["1", "2", "3\t3", "4"]).unwrap();
+    writer.write_record(&Vec::<String>::new()).unwrap();
+    writer.write_line("chr1\t0\t10").unwrap();
+    assert_eq!(writer.records(), 3);
+    assert_eq!(writer.finish().unwrap(), 3);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "#th ree\tone\t\textra\n3 3\t1\t\tx y\n\t\t\tx y\nchr1\t0\t10\n"
+    );
+
+    let mut out = Vec::new();
+    let mut writer = DlWriter::new(&mut out, &["one", "two"]);
+    writer.write_record(&["1", "2", "3"]).unwrap();
+    assert_eq!(writer.finish().unwrap(), 1);
+    assert_eq!(out, b"1\t2\t3\n");
+}
+
+#[test]
+fn test_dl_family_name_with_tab() {
+    // A family name from curation holding a tab and a newline is still one field of one row
+    let mut fields: Vec<&str> = "chr1\t1000\t1200\tDF000000001\t104.0\t+\t0.0\t1000\t1200\t55\t262\t1.3e-26\t1\t12.5\tMIR\t248956422\t200M\tcaf"
+        .split('\t')
+        .collect();
+    fields[14] = "MIR\tcore\nx";
+    let hit = Annotation::from_bed(&fields);
+    let dl_columns = 14;
+    let mut out = Vec::new();
+    let mut writer = DlWriter::new(&mut out, &vec!["column"; dl_columns]);
+    writer.write_header().unwrap();
+    writer.write_record(&hit.to_dl_fmt("chr1", "262")).unwrap();
+    writer.finish().unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for line in &lines {
+        assert_eq!(line.split('\t').count(), dl_columns);
+    }
+    assert_eq!(lines[1].split('\t').nth(2), Some("MIR core x"));
+
+    // As are family metadata fields written by a filter
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            "DF000000001",
+            vec![
+                annotation_line("chr1", 100, 200, "DF000000001", "50", "+", "1"),
+                annotation_line("chr1", 300, 400, "DF000000001", "50", "-", "0"),
+            ],
+        )],
+    );
+    let families = families_file(assembly, &data_directory);
+    create_dir_all(Path::new(&families).parent().unwrap()).unwrap();
+    write(
+        &families,
+        json!({
+            "DF000000001": {
+                "name": "MIR",
+                "classification": "Interspersed_Repeat;\tSINE\nMIR",
+                "clades": ["Mammalia", "Homo\tsapiens"],
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    let count = bgzf_filter_with(
+        assembly,
+        ASSEMBLY_DIR,
+        "DF000000001",
+        &mut out,
+        &FilterOptions::builder().with_family_meta(true).build(),
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    assert_eq!(count, 2);
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3);
+    let columns = lines[0].split('\t').count();
+    for line in &lines[1..] {
+        let fields: Vec<&str> = line.split('\t').collect();
+        assert_eq!(fields.len(), columns);
+        assert_eq!(
+            fields[columns - 2..],
+            ["Interspersed_Repeat; SINE MIR", "Mammalia,Homo sapiens"]
+        );
+    }
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_data_roots() {
+    let working_dir = gen_working_dir();
+    let working_path = working_dir.path().to_str().unwrap().to_string();
+    let (hot, cold) = (
+        format!("{}/hot", working_path),
+        format!("{}/cold", working_path),
+    );
+    create_dir_all(&hot).expect("Can't Create Dir");
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let lines = |fam: &str| -> Vec<String> {
+        (0..10)
+            .map(|i| annotation_line("chr1", 100 + i * 400, 500 + i * 400, fam, "30.5", "+", "1"))
+            .collect()
+    };
+    build_test_assembly(
+        &cold,
+        ASSEMBLY_DIR,
+        &[
+            ("DF000000001", lines("DF000000001")),
+            ("DF000000002", lines("DF000000002")),
+        ],
+    );
+    let families = families_file(assembly, &cold);
+    create_dir_all(Path::new(&families).parent().unwrap()).unwrap();
+    write(
+        &families,
+        json!({ "DF000000001": { "name": "MIR" } }).to_string(),
+    )
+    .unwrap();
+    let roots = vec![hot.clone(), cold.clone()];
+    assert_eq!(configured_data_roots(&roots, "/unused"), roots);
+    let query = |root: &String| -> Vec<Value> {
+        from_str(
+            &idx_query_with(
+                assembly,
+                ASSEMBLY_DIR,
+                "chr1",
+                1,
+                10000,
+                &IdxQueryOptions::default(),
+                root,
+            )
+            .expect("Index Query Failed"),
+        )
+        .unwrap()
+    };
+    let element = |status: &CacheStatus, element: &str| -> ElementStatus {
+        status
+            .elements
+            .iter()
+            .find(|e| e.element == element)
+            .cloned()
+            .expect("No Element")
+    };
+
+    // Only in the cold root, queries resolve through to it
+    assert_eq!(
+        resolve_root(&roots, assembly, Some(ASSEMBLY_DIR)),
+        Some(&cold)
+    );
+    assert_eq!(resolve_root(&roots, assembly, None), Some(&cold));
+    assert_eq!(resolve_root(&roots, assembly, Some(MASKS_DIR)), None);
+    let hits = query(resolve_root(&roots, assembly, Some(ASSEMBLY_DIR)).unwrap());
+    assert_eq!(hits.len(), 20);
+    let status = cache_status(assembly, &roots).unwrap();
+    assert_eq!(status.roots, roots);
+    let alignments = element(&status, ASSEMBLY_DIR);
+    assert_eq!(alignments.roots, std::slice::from_ref(&cold));
+    assert_eq!(alignments.served_from, Some(cold.clone()));
+    assert_eq!(alignments.in_sync, None);
+    assert!(element(&status, MASKS_DIR).roots.is_empty());
+
+    // Warmed, the hot root is preferred and holds the same data, checksummed
+    let report = warm_assembly(assembly, &[], &roots).expect("Warming Failed");
+    assert_eq!((&report.from, &report.to), (&cold, &hot));
+    assert_eq!(report.data_types, [ASSEMBLY_DIR]);
+    let warmed: HashSet<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+    for path in [
+        "assembly_alignments_idx.dat",
+        "assembly_alignments/DF000000001.bed.bgz",
+        "assembly_alignments/DF000000002.bed.bgz",
+        &format!("{}/{}{}", FAMILIES_DIR, TEST_ASSEMBLY, FAMILIES_FILE),
+    ] {
+        assert!(warmed.contains(path), "{}", path);
+    }
+    for file in &report.files {
+        let copied = std::fs::read(format!("{}/{}/{}", hot, TEST_ASSEMBLY, file.path)).unwrap();
+        assert_eq!(copied.len() as u64, file.bytes);
+        assert_eq!(format!("{:x}", Sha256::digest(&copied)), file.sha256);
+    }
+    assert_eq!(
+        report.bytes,
+        report.files.iter().map(|f| f.bytes).sum::<u64>()
+    );
+    assert!(read_dir(format!("{}/{}", hot, TEST_ASSEMBLY))
+        .unwrap()
+        .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with('.')));
+    assert_eq!(
+        resolve_root(&roots, assembly, Some(ASSEMBLY_DIR)),
+        Some(&hot)
+    );
+    assert_eq!(
+        resolve_root(&roots, assembly, Some(FAMILIES_DIR)),
+        Some(&hot)
+    );
+    assert_eq!(query(&hot), hits);
+    let status = cache_status(assembly, &roots).unwrap();
+    for name in [ASSEMBLY_DIR, FAMILIES_DIR] {
+        let copies = element(&status, name);
+        assert_eq!(copies.roots, roots);
+        assert_eq!(copies.served_from, Some(hot.clone()));
+        assert_eq!(copies.in_sync, Some(true));
+    }
+
+    // The archive changed, the hot copy is stale until warmed again
+    build_test_assembly(
+        &cold,
+        ASSEMBLY_DIR,
+        &[("DF000000003", lines("DF000000003"))],
+    );
+    let status = cache_status(assembly, &roots).unwrap();
+    assert_eq!(element(&status, ASSEMBLY_DIR).in_sync, Some(false));
+    assert_eq!(query(&hot).len(), 20);
+    warm_assembly(assembly, &[ASSEMBLY_DIR.to_string()], &roots).expect("Warming Failed");
+    let status = cache_status(assembly, &roots).unwrap();
+    assert_eq!(element(&status, ASSEMBLY_DIR).in_sync, Some(true));
+    assert_eq!(query(&hot).len(), 30);
+
+    let error = |result: std::io::Result<_>| result.map(|_: WarmReport| ()).unwrap_err().kind();
+    assert_eq!(
+        error(warm_assembly(assembly, &[], &roots[..1])),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        error(warm_assembly("missing", &[], &roots)),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        error(warm_assembly(assembly, &[MASKS_DIR.to_string()], &roots)),
+        ErrorKind::NotFound
+    );
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_search_region_boundaries() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    // chromStart 1000 and chromEnd 2000, bases 1001 to 2000, on either strand
+    let lines = vec![
+        annotation_line("chr1", 1001, 2000, fam, "300", "+", "1"),
+        annotation_line("chr1", 2000, 1001, fam, "200", "-", "1"),
+    ];
+    assert!(lines
+        .iter()
+        .all(|line| line.starts_with("chr1\t1000\t2000\t")));
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines.clone())]);
+
+    let query = |start: u64, end: u64, allow_scan: bool| -> Vec<(u64, u64)> {
+        let res = idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            start,
+            end,
+            &IdxQueryOptions {
+                allow_scan,
+                ..IdxQueryOptions::default()
+            },
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let res: Value = from_str(&res).expect("Cannot Deserialize");
+        // A scan wraps the hits in a report of it
+        let hits = res.get("hits").unwrap_or(&res).as_array().unwrap().clone();
+        hits.iter()
+            .map(|hit| {
+                (
+                    hit["seq_start"].as_u64().unwrap(),
+                    hit["seq_end"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    };
+    let check = |allow_scan: bool| {
+        for (start, end) in [(1, 1000), (2001, 3000)] {
+            assert!(
+                query(start, end, allow_scan).is_empty(),
+                "{}-{}",
+                start,
+                end
+            );
+        }
+        for (start, end) in [(1, 1001), (2000, 3000), (1500, 1600)] {
+            assert_eq!(
+                query(start, end, allow_scan),
+                vec![(1001, 2000), (2000, 1001)],
+                "{}-{}",
+                start,
+                end
+            );
+        }
+    };
+    check(false);
+    // The scan of the files without the index finds the same
+    let (_, index_file) = idx_paths(TEST_ASSEMBLY, ASSEMBLY_DIR, &data_directory);
+    remove_file(&index_file).unwrap();
+    check(true);
+
+    // A line spoiled after indexing, at the same offsets, is left out rather than failing
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines.clone())]);
+    write_bed_bgz(
+        &format!(
+            "{}/{}/{}/{}.bed.bgz",
+            data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR, fam
+        ),
+        &[
+            lines[0].clone(),
+            lines[1].replacen("\t2000\t", "\t2x00\t", 1),
+        ],
+    );
+    assert_eq!(query(1, 3000, false), vec![(1001, 2000)]);
+
+    // Files holding chromStart at or above chromEnd, as exported minus strand records, are
+    // refused rather than indexed by the wrong extent
+    let bgz_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR);
+    let mut exported: Vec<String> = lines[1].split('\t').map(|f| f.to_string()).collect();
+    (exported[1], exported[2]) = (exported[7].clone(), exported[8].clone());
+    write_bed_bgz(
+        &format!("{}/{}.bed.bgz", bgz_dir, fam),
+        &[lines[0].clone(), exported.join("\t")],
+    );
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(TEST_ASSEMBLY, ASSEMBLY_DIR, false, &data_directory).expect("Index Prep Failed");
+    let err = build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+        .expect_err("Reversed Coordinates Indexed");
+    assert!(err
+        .to_string()
+        .contains("Line 2, chromStart Must Be Below chromEnd"));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_migrate_half_open() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let fam = "DF000000001";
+    let converted = vec![
+        annotation_line("chr1", 1001, 2000, fam, "300", "+", "1"),
+        annotation_line("chr1", 2600, 2001, fam, "200", "-", "0"),
+    ];
+    // As prepared before, columns 2 and 3 as exported, alignment start and end being the same
+    let exported: Vec<String> = converted
+        .iter()
+        .map(|line| {
+            let mut fields: Vec<&str> = line.split('\t').collect();
+            (fields[1], fields[2]) = (fields[7], fields[8]);
+            fields.join("\t")
+        })
+        .collect();
+    let assembly_dir = format!("{}/{}", data_directory, assembly);
+    create_dir_all(format!("{}/{}", assembly_dir, ASSEMBLY_DIR)).expect("Can't Create Dir");
+    write_bed_bgz(
+        &format!("{}/{}/{}.bed.bgz", assembly_dir, ASSEMBLY_DIR, fam),
+        &exported,
+    );
+    write_assembly_meta(
+        assembly,
+        &AssemblyMeta {
+            layout_version: 1,
+            migration: None,
+            ..AssemblyMeta::default()
+        },
+        &data_directory,
+    )
+    .expect("Can't Write Meta");
+    // Left by a run stopped while converting, started again from the exported files
+    let converting = format!("{}/.{}.converting", assembly_dir, ASSEMBLY_DIR);
+    create_dir_all(&converting).expect("Can't Create Dir");
+    write(format!("{}/{}.bed.bgz", converting, fam), "partial").expect("Can't Write File");
+
+    let report =
+        migrate_assembly(assembly, &Some(1), &None, &data_directory).expect("Migration Failed");
+    assert_eq!(report.migrated, vec![format!("1->2 {}", ASSEMBLY_DIR)]);
+    assert!(beds_half_open(assembly, ASSEMBLY_DIR, &data_directory));
+    assert!(!Path::new(&converting).exists());
+    let lines: Vec<String> = BufReader::new(bgzf::Reader::new(
+        File::open(format!("{}/{}/{}.bed.bgz", assembly_dir, ASSEMBLY_DIR, fam)).unwrap(),
+    ))
+    .lines()
+    .map(|l| l.unwrap())
+    .collect();
+    assert_eq!(lines, converted);
+    assert_eq!(
+        read_assembly_meta(assembly, &data_directory)
+            .unwrap()
+            .layout_version,
+        LAYOUT_VERSION
+    );
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_find_sequences() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    write_sequences_json(&data_directory);
+    let assembly = &TEST_ASSEMBLY.to_string();
+
+    let find = |pattern: &str, limit: usize| {
+        find_sequences(assembly, pattern, limit, &data_directory).expect("JSON Read Failed")
+    };
+
+    // Prefix of the ids, and a substring of the accessions
+    let results = find("KI270", 50);
+    let accessions: Vec<&str> = results
+        .matches
+        .iter()
+        .map(|m| m.accession.as_str())
+        .collect();
+    assert_eq!(
+        accessions,
+        [
+            "chr11_KI270721v1_random",
+            "chr14_KI270722v1_random",
+            "chrUn_KI270302v1"
+        ]
+    );
+    assert_eq!(results.matches[0].id.as_deref(), Some("KI270721.1"));
+    assert_eq!(results.matches[0].length, Some(100316));
+    assert_eq!(results.total, 3);
+    assert!(!results.truncated);
+
+    // Prefix matches come before substring matches, case is ignored
+    let results = find("chrun", 50);
+    let accessions: Vec<&str> = results
+        .matches
+        .iter()
+        .map(|m| m.accession.as_str())
+        .collect();
+    assert_eq!(accessions, ["chrUn_GL000195v1", "chrUn_KI270302v1"]);
+    let results = find("random", 50);
+    assert_eq!(results.total, 2);
+    let results = find("_KI270722", 50);
+    assert_eq!(results.matches[0].accession, "chr14_KI270722v1_random");
+
+    let results = find("chr", 2);
+    assert_eq!(results.matches.len(), 2);
+    assert_eq!(results.total, 5);
+    assert!(results.truncated);
+
+    assert!(find("chrY", 50).matches.is_empty());
+}
+
+#[test]
+fn test_sequences_json_malformed_entries() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &SEQUENCE_DIR.to_string();
+    let seq_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, SEQUENCE_DIR);
+    create_dir_all(&seq_dir).expect("Can't Create Dir");
+    let data = json!({
+        "assembly": "hg38",
+        "data": {
+            "chr1": {"id": "CM000663.2", "length": 248956422},
+            "chrUn_null": null,
+            "chrUn_no_length": {"id": "KI270302.1"},
+            "chrUn_bad_length": {"id": "GL000195.1", "length": "n/a"},
+        }
+    });
+    write(
+        format!("{}/{}-{}.json", seq_dir, TEST_ASSEMBLY, SEQUENCE_DIR),
+        data.to_string(),
+    )
+    .expect("Can't Write JSON");
+
+    // Bad entries keep their accession, with every field missing
+    let assembly_data = AssemblyData::load(assembly, &data_directory).expect("Load Failed");
+    let info: HashMap<&str, &SequenceInfo> = assembly_data
+        .sequence_info()
+        .iter()
+        .map(|(acc, info)| (acc.as_str(), info))
+        .collect();
+    assert_eq!(info.len(), 4);
+    assert_eq!(info["chrUn_null"], &SequenceInfo::default());
+    assert_eq!(info["chrUn_bad_length"], &SequenceInfo::default());
+    assert_eq!(info["chrUn_no_length"].id.as_deref(), Some("KI270302.1"));
+    assert_eq!(info["chrUn_no_length"].length, None);
+
+    let lookup =
+        |query: &str| get_chrom_id(assembly, query, &data_directory).expect("JSON Read Failed");
+    assert_eq!(lookup("KI270302.1"), "chrUn_no_length");
+    assert_eq!(lookup("CM000663.2"), "chr1");
+    assert_eq!(lookup("chrUn_null"), "chrUn_null");
+    assert_eq!(lookup("GL000195.1"), "-1");
+
+    let found = find_sequences(assembly, "chrUn", 10, &data_directory).expect("Search Failed");
+    assert_eq!(found.total, 3);
+    assert!(found
+        .matches
+        .iter()
+        .all(|m| m.length.is_none() && m.accession != "chr1"));
+
+    // A key without the target field is missing, and says which field it lacks
+    let target = &Some("length".to_string());
+    let ans = json_query(
+        assembly,
+        data_type,
+        "chrUn_no_length",
+        target,
+        &None,
+        &data_directory,
+    )
+    .expect("JSON Read Failed");
+    assert_eq!(ans, "-1");
+    let keys: Vec<String> = ["chr1", "chrUn_null", "chrUn_no_length", "chrUn_absent"]
+        .iter()
+        .map(|k| k.to_string())
+        .collect();
+    let res = json_query_many(assembly, data_type, &keys, target, &data_directory)
+        .expect("JSON Read Failed");
+    assert_eq!(res.values["chr1"], "248956422");
+    assert_eq!(res.missing, keys[1..]);
+    assert_eq!(
+        res.missing_fields.keys().collect::<Vec<_>>(),
+        ["chrUn_no_length", "chrUn_null"]
+    );
+    assert!(res.missing_fields.values().all(|field| field == "length"));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_json_query() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &SEQUENCE_DIR.to_string();
+    let key = &"chr1".to_string();
+    let target = &Some("length".to_string());
+    let data_directory = test_data_dir().to_string();
+
+    let ans = json_query(assembly, data_type, key, target, &None, &data_directory)
+        .expect("JSON Read Failed");
+    assert_eq!(ans, "248956422");
+
+    let just_key = json_query(assembly, data_type, key, &None, &None, &data_directory)
+        .expect("JSON Read Failed");
+    assert_eq!(&just_key, "1");
+
+    let bad_key = json_query(assembly, data_type, "bad", &None, &None, &data_directory)
+        .expect("JSON Read Failed");
+    assert_eq!(&bad_key, "-1");
+}
+
+#[test]
+fn test_json_query_many() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &SEQUENCE_DIR.to_string();
+    let target = &Some("length".to_string());
+    let data_directory = test_data_dir().to_string();
+    let keys: Vec<String> = vec!["chr1", "chr10", "chr17", "bad"]
+        .into_iter()
+        .map(|k| k.to_string())
+        .collect();
+
+    let res = json_query_many(assembly, data_type, &keys, target, &data_directory)
+        .expect("JSON Read Failed");
+    assert_eq!(res.values.len(), 3);
+    assert_eq!(res.missing, vec!["bad".to_string()]);
+    for key in &keys[..3] {
+        let single = json_query(assembly, data_type, key, target, &None, &data_directory)
+            .expect("JSON Read Failed");
+        assert_eq!(res.values[key], single);
+    }
+
+    let all =
+        json_query_all(assembly, data_type, target, &data_directory).expect("JSON Read Failed");
+    assert!(all.missing.is_empty());
+    assert_eq!(all.values["chr1"], "248956422");
+    assert!(all.values.len() >= res.values.len());
+}
+
+#[test]
+fn test_json_query_normalized_keys() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MOD_LEN_DIR.to_string();
+    let target = &Some("length".to_string());
+    let mod_len_dir = format!("{}/{}/{}", data_directory, assembly, MOD_LEN_DIR);
+    create_dir_all(&mod_len_dir).expect("Can't Create Dir");
+    let data = json!({
+        "assembly": "hg38",
+        "data": {
+            "DF000000001": {"length": 262},
+            "DF000000002.3": {"length": 311},
+            "Mixed_Case": {"length": 5},
+            "dup": {"length": 1},
+            "DUP": {"length": 2},
+        }
+    });
+    write(
+        format!("{}/{}-{}.json", mod_len_dir, assembly, MOD_LEN_DIR),
+        data.to_string(),
+    )
+    .expect("Can't Write JSON");
+    let query = |key: &str| json_query_match(assembly, data_type, key, target, &data_directory);
+
+    // Each fallback level, the first to match wins
+    for (key, matched, value, normalization) in [
+        ("DF000000001", "DF000000001", "262", KeyNormalization::Exact),
+        (
+            "DF000000001 \t",
+            "DF000000001",
+            "262",
+            KeyNormalization::Trimmed,
+        ),
+        (
+            " df000000001",
+            "DF000000001",
+            "262",
+            KeyNormalization::CaseInsensitive,
+        ),
+        (
+            "mixed_case",
+            "Mixed_Case",
+            "5",
+            KeyNormalization::CaseInsensitive,
+        ),
+        (
+            "DF000000001.2",
+            "DF000000001",
+            "262",
+            KeyNormalization::VersionStripped,
+        ),
+        (
+            "df000000002",
+            "DF000000002.3",
+            "311",
+            KeyNormalization::VersionStripped,
+        ),
+        (
+            "DF000000002.1",
+            "DF000000002.3",
+            "311",
+            KeyNormalization::VersionStripped,
+        ),
+        ("dup", "dup", "1", KeyNormalization::Exact),
+    ] {
+        let found = query(key).expect("Key Not Matched");
+        assert_eq!(
+            found,
+            JsonQueryMatch {
+                value: value.to_string(),
+                key: matched.to_string(),
+                normalization,
+            },
+            "{}",
+            key
+        );
+        assert_eq!(
+            json_query(assembly, data_type, key, target, &None, &data_directory)
+                .expect("JSON Read Failed"),
+            value
+        );
+    }
+
+    // Keys differing only by case are not guessed between
+    let ambiguous = query("Dup").unwrap_err();
+    assert_eq!(ambiguous.kind(), ErrorKind::InvalidInput);
+    assert!(ambiguous.to_string().contains("DUP, dup"));
+    assert_eq!(
+        json_query(assembly, data_type, "Dup", target, &None, &data_directory)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+
+    // Nothing matched, near misses are listed. A version is only stripped from accessions.
+    let missing = query("DF00000001").unwrap_err();
+    assert_eq!(missing.kind(), ErrorKind::NotFound);
+    assert!(missing.to_string().contains("Near Misses: DF000000001"));
+    assert_eq!(
+        query("Mixed_Case.2").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        json_query(
+            assembly,
+            data_type,
+            "DF00000001",
+            target,
+            &None,
+            &data_directory
+        )
+        .expect("JSON Read Failed"),
+        "-1"
+    );
+
+    let keys: Vec<String> = [" df000000001", "Dup", "DF00000001", "DF000000001"]
+        .iter()
+        .map(|k| k.to_string())
+        .collect();
+    let res = json_query_many(assembly, data_type, &keys, target, &data_directory)
+        .expect("JSON Read Failed");
+    assert_eq!(res.values.len(), 2);
+    assert_eq!(res.values[" df000000001"], "262");
+    assert_eq!(
+        res.normalized,
+        BTreeMap::from([(
+            " df000000001".to_string(),
+            ("DF000000001".to_string(), KeyNormalization::CaseInsensitive)
+        )])
+    );
+    assert_eq!(res.missing, ["Dup", "DF00000001"]);
+    assert_eq!(res.candidates["Dup"], ["DUP", "dup"]);
+    assert_eq!(res.candidates["DF00000001"][0], "DF000000001");
+}
+
+#[test]
+fn test_query_log() {
+    let working_dir = gen_working_dir();
+    let log_path = format!("{}/queries.ndjson", working_dir.path().display());
+    let query_log = &Some(QueryLogger::new(&log_path, 0));
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let data_directory = test_data_dir().to_string();
+    let region = |start: u64, end: u64| {
+        idx_query_with(
+            assembly,
+            data_type,
+            "chr10",
+            start,
+            end,
+            &IdxQueryOptions {
+                query_log: query_log.clone(),
+                ..IdxQueryOptions::default()
+            },
+            &data_directory,
+        )
+    };
+
+    region(10000, 100000).expect("Index Query Failed");
+    assert!(region(100000, 10000).is_err());
+    let sequences = &SEQUENCE_DIR.to_string();
+    for key in ["chr1", "bad"] {
+        json_query(assembly, sequences, key, &None, query_log, &data_directory)
+            .expect("JSON Read Failed");
+    }
+    let mut output = Vec::new();
+    let filtered = bgzf_filter_with(
+        assembly,
+        data_type,
+        "DF000000001",
+        &mut output,
+        &FilterOptions {
+            position: 13,
+            term: Some("1".to_string()),
+            query_log: query_log.clone(),
+            ..FilterOptions::default()
+        },
+        &data_directory,
+    )
+    .expect("Filter Failed");
+
+    let entries: Vec<Value> = read_to_string(&log_path)
+        .expect("Can't Read Query Log")
+        .lines()
+        .map(|l| from_str(l).expect("Malformed Log Line"))
+        .collect();
+    assert_eq!(entries.len(), 5);
+    let operations: Vec<&str> = entries
+        .iter()
+        .map(|e| e["operation"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        operations,
+        [
+            "idx_query",
+            "idx_query",
+            "json_query",
+            "json_query",
+            "bgzf_filter"
+        ]
+    );
+    for entry in &entries {
+        assert_eq!(entry["assembly"], TEST_ASSEMBLY);
+        assert!(entry["elapsed_ms"].is_u64());
+        let timestamp = entry["timestamp"].as_str().unwrap();
+        assert!(timestamp.len() == 24 && timestamp.ends_with('Z'));
+    }
+    assert_eq!(entries[0]["parameters"]["chrom"], "chr10");
+    assert_eq!(entries[0]["parameters"]["start"], 10000);
+    assert_eq!(entries[0]["result_count"], 4);
+    assert_eq!(entries[0]["error"], Value::Null);
+    assert_eq!(entries[1]["result_count"], Value::Null);
+    assert_eq!(entries[1]["error"], "InvalidInput");
+    assert_eq!(entries[2]["parameters"]["key"], "chr1");
+    assert_eq!(entries[2]["result_count"], 1);
+    assert_eq!(entries[3]["result_count"], 0);
+    assert_eq!(entries[4]["parameters"]["key"], "DF000000001");
+    assert_eq!(entries[4]["result_count"], filtered);
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_query_log_rotation() {
+    let working_dir = gen_working_dir();
+    let log_path = format!("{}/queries.ndjson", working_dir.path().display());
+    let logger = QueryLogger::new(&log_path, 2000);
+    // Several processes share a log in production, threads with their own file handles here
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let logger = logger.clone();
+            std::thread::spawn(move || {
+                for n in 0..50 {
+                    logger
+                        .log(&json!({ "writer": writer, "n": n, "padding": "x".repeat(40) }))
+                        .expect("Can't Write Query Log");
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let mut files = vec![log_path.clone()];
+    while Path::new(&format!("{}.{}", log_path, files.len())).exists() {
+        files.push(format!("{}.{}", log_path, files.len()));
+    }
+    assert!(files.len() > 2);
+    let mut seen = Vec::new();
+    for file in &files {
+        let contents = read_to_string(file).unwrap();
+        // A file is only rotated once it reaches the limit, so by at most one line over
+        assert!(contents.len() < 2000 + 100);
+        for line in contents.lines() {
+            let entry: Value = from_str(line).expect("Malformed Log Line");
+            seen.push((
+                entry["writer"].as_u64().unwrap(),
+                entry["n"].as_u64().unwrap(),
+            ));
+        }
+    }
+    seen.sort();
+    let expected: Vec<(u64, u64)> = (0..4).flat_map(|w| (0..50).map(move |n| (w, n))).collect();
+    assert_eq!(seen, expected);
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_allowlist() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            "DF000000001",
+            vec![annotation_line(
+                "chr1",
+                100,
+                500,
+                "DF000000001",
+                "300",
+                "+",
+                "1",
+            )],
+        )],
+    );
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    // A staging assembly that exists but is not listed
+    create_dir_all(format!("{}/staging/{}", data_directory, SEQUENCE_DIR)).unwrap();
+    copy(
+        format!(
+            "{}/{}/{}/{}{}",
+            test_data_dir(),
+            TEST_ASSEMBLY,
+            SEQUENCE_DIR,
+            TEST_ASSEMBLY,
+            SEQUENCE_FILE
+        ),
+        format!(
+            "{}/staging/{}/staging{}",
+            data_directory, SEQUENCE_DIR, SEQUENCE_FILE
+        ),
+    )
+    .expect("Can't Copy File");
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let sequences = &SEQUENCE_DIR.to_string();
+    let lookup = |assembly: &str| get_chrom_id(assembly, "chr1", &data_directory);
+    let query = |assembly: &str| {
+        idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            1000,
+            &IdxQueryOptions::default(),
+            &data_directory,
+        )
+    };
+    let not_permitted = |e: Error| -> String {
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        let e = e.into_inner().unwrap().downcast::<NotPermitted>().unwrap();
+        e.to_string()
+    };
+
+    // Without an allowlist everything can be queried
+    assert_eq!(lookup("staging").expect("Lookup Failed"), "chr1");
+    assert_eq!(
+        list_assemblies(false, &data_directory).unwrap(),
+        ["staging", TEST_ASSEMBLY]
+    );
+
+    write(
+        format!("{}/{}", data_directory, ALLOWLIST_FILE),
+        json!({
+            "assemblies": {
+                TEST_ASSEMBLY: { "data_types": [ASSEMBLY_DIR] },
+                "listed_missing": {},
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let hits: Vec<Value> = from_str(&query(TEST_ASSEMBLY).expect("Index Query Failed")).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(
+        not_permitted(lookup(TEST_ASSEMBLY).unwrap_err()),
+        "sequences Of Assembly test_ex Is Not Available"
+    );
+    assert!(
+        json_query(assembly, sequences, "chr1", &None, &None, &data_directory)
+            .is_err_and(|e| e.kind() == ErrorKind::PermissionDenied)
+    );
+
+    // Hidden and missing assemblies are rejected alike, so neither gives away what exists
+    let hidden = not_permitted(query("staging").unwrap_err());
+    assert_eq!(hidden, "Assembly staging Is Not Available");
+    assert_eq!(not_permitted(lookup("staging").unwrap_err()), hidden);
+    assert_eq!(
+        not_permitted(query("nowhere").unwrap_err()),
+        hidden.replace("staging", "nowhere")
+    );
+    // Only a listed assembly can be found missing
+    assert_eq!(
+        lookup("listed_missing").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    assert_eq!(
+        list_assemblies(false, &data_directory).unwrap(),
+        [TEST_ASSEMBLY]
+    );
+    assert_eq!(
+        list_assemblies(true, &data_directory).unwrap(),
+        ["staging", TEST_ASSEMBLY]
+    );
+
+    // A broken allowlist fails closed
+    write(
+        format!("{}/{}", data_directory, ALLOWLIST_FILE),
+        "{\"hg38\": []}",
+    )
+    .unwrap();
+    assert_eq!(
+        lookup(TEST_ASSEMBLY).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_assembly_data_consolidated() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
+    let (contig, contig_len) = &synth.contigs[0];
+    let (fam, _, fam_len) = &synth.families[0];
+    let length = &Some("length".to_string());
+
+    let answers = || {
+        let mut answers = Vec::new();
+        for (data_type, key) in [(SEQUENCE_DIR, contig), (MOD_LEN_DIR, fam)] {
+            for target in [length, &None] {
+                answers.push(
+                    json_query(assembly, data_type, key, target, &None, &data_directory)
+                        .expect("JSON Read Failed"),
+                );
+            }
+            let all = json_query_all(assembly, data_type, length, &data_directory)
+                .expect("JSON Read Failed");
+            answers.push(format!("{:?}", all));
+        }
+        answers.push(get_chrom_id(assembly, contig, &data_directory).expect("Lookup Failed"));
+        let found = find_sequences(assembly, "chr", 10, &data_directory).expect("Search Failed");
+        answers.push(format!("{:?}", found));
+
+        // The dl format carries the model length of the family
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            assembly,
+            ASSEMBLY_DIR,
+            fam,
+            &mut output,
+            &FilterOptions::builder().dl_fmt(true).build(),
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        answers.extend(output.lines().map(|l| l.unwrap()));
+        answers
+    };
+
+    let legacy = answers();
+    assert_eq!(legacy[0], contig_len.to_string());
+    assert_eq!(legacy[3], fam_len.to_string());
+    assert!(!AssemblyData::load(assembly, &data_directory)
+        .expect("Load Failed")
+        .is_consolidated());
+
+    consolidate_assembly_data(assembly, &data_directory).expect("Consolidation Failed");
+    // Moved away so only the consolidated file can answer
+    for data_type in [MOD_LEN_DIR, SEQUENCE_DIR] {
+        let data_path = format!("{}/{}/{}", data_directory, assembly, data_type);
+        rename(&data_path, format!("{}.moved", data_path)).expect("Can't Move JSON");
+    }
+    let assembly_data = AssemblyData::load(assembly, &data_directory).expect("Load Failed");
+    assert!(assembly_data.is_consolidated());
+    assert_eq!(
+        assembly_data.data(SEQUENCE_DIR)[contig]["length"],
+        json!(contig_len)
+    );
+    assert_eq!(answers(), legacy);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_assembly_data_mismatch() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    copy_test_data(&data_directory, MOD_LEN_DIR);
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let consolidated_file = format!("{}/{}/{}", data_directory, assembly, ASSEMBLY_DATA_FILE);
+
+    // The test model lengths are for hg38_df38, the sequences for hg38
+    let err = consolidate_assembly_data(assembly, &data_directory).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("\"hg38_df38\""));
+    assert!(!Path::new(&consolidated_file).exists());
+
+    write(
+        &consolidated_file,
+        json!({"version": ASSEMBLY_DATA_VERSION + 1}).to_string(),
+    )
+    .expect("Can't Write File");
+    let err = AssemblyData::load(assembly, &data_directory)
+        .err()
+        .expect("Unknown Version Loaded");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_bgzf_filter_wrong_data_type() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    copy_test_data(&data_directory, ASSEMBLY_DIR);
+    create_dir_all(format!(
+        "{}/{}/{}",
+        data_directory, TEST_ASSEMBLY, BENCHMARK_DIR
+    ))
+    .expect("Can't Create Dir");
+
+    let err = bgzf_filter_with(
+        TEST_ASSEMBLY,
+        BENCHMARK_DIR,
+        "DF000000001",
+        &mut Vec::new(),
+        &FilterOptions::default(),
+        &data_directory,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err
+        .to_string()
+        .contains("found under assembly_alignments — did you mean -d assembly_alignments?"));
+}
+
+#[test]
+fn test_idx_query_masks_family() {
+    assert_invalid_option(
+        idx_query_with(
+            TEST_ASSEMBLY,
+            MASKS_DIR,
+            "chr1",
+            30000,
+            31000,
+            &IdxQueryOptions::builder().family("DF000000001").build(),
+            test_data_dir(),
+        ),
+        "Masks Have No Family Column",
+    );
+}
+
+#[test]
+fn test_idx_query_with_model_length() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    copy_test_data(&data_directory, ASSEMBLY_DIR);
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    copy(
+        format!("{}/{}/{}_idx.dat", test_data_dir(), assembly, ASSEMBLY_DIR),
+        format!("{}/{}/{}_idx.dat", data_directory, assembly, ASSEMBLY_DIR),
+    )
+    .expect("Can't Copy Index");
+    let model_lengths_file = format!("{}/{}{}", MOD_LEN_DIR, TEST_ASSEMBLY, MOD_LEN_FILE);
+    let mut model_lengths: Value = from_str(
+        &read_to_string(format!(
+            "{}/{}/{}",
+            test_data_dir(),
+            TEST_ASSEMBLY,
+            model_lengths_file
+        ))
+        .expect("Can't Read Model Lengths"),
+    )
+    .expect("Cannot Deserialize");
+    let query = |with_model_length: bool| -> Value {
+        let res = idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr10",
+            10000,
+            100000,
+            &IdxQueryOptions::builder()
+                .with_model_length(with_model_length)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+    let plain = query(false);
+    assert!(plain.as_array().unwrap()[0].get("model_length").is_none());
+
+    // One family of the region is left out of the model lengths
+    let hits = plain.as_array().unwrap();
+    let dropped = hits[0]["accession"].as_str().unwrap().to_string();
+    let lengths = model_lengths["data"].as_object_mut().unwrap();
+    let expected = lengths.clone();
+    lengths.remove(&dropped);
+    create_dir_all(format!(
+        "{}/{}/{}",
+        data_directory, TEST_ASSEMBLY, MOD_LEN_DIR
+    ))
+    .expect("Can't Create Dir");
+    write(
+        format!(
+            "{}/{}/{}",
+            data_directory, TEST_ASSEMBLY, model_lengths_file
+        ),
+        model_lengths.to_string(),
+    )
+    .expect("Can't Write Model Lengths");
+
+    let with_lengths = query(true);
+    let annotated = with_lengths["hits"].as_array().unwrap();
+    assert_eq!(annotated.len(), hits.len());
+    for hit in annotated {
+        let fam = hit["accession"].as_str().unwrap();
+        if fam == dropped {
+            assert_eq!(hit["model_length"], Value::Null);
+        } else {
+            assert_eq!(hit["model_length"], expected[fam]["length"]);
+        }
+    }
+    assert_eq!(with_lengths["missing_model_lengths"], 1);
+}
+
+#[test]
+fn test_idx_query_masks_model_length() {
+    let query = |options: &IdxQueryOptions, data_type: &str| {
+        idx_query_with(
+            TEST_ASSEMBLY,
+            data_type,
+            "chr1",
+            30000,
+            31000,
+            options,
+            test_data_dir(),
+        )
+    };
+    for (options, message) in [
+        (
+            IdxQueryOptions::builder().with_model_length(true).build(),
+            "Model Lengths Cannot Be Added",
+        ),
+        (
+            IdxQueryOptions::builder().with_family_meta(true).build(),
+            "Family Metadata Cannot Be Added",
+        ),
+        (
+            IdxQueryOptions::builder().json_seq_len(true).build(),
+            "Masks Have No seq_len Column",
+        ),
+        (
+            IdxQueryOptions::builder().min_bit_score(10.0).build(),
+            "Score Thresholds Cannot Be Applied",
+        ),
+    ] {
+        assert_invalid_option(query(&options, MASKS_DIR), message);
+    }
+    // Options checked whatever the data type
+    assert_invalid_option(
+        query(
+            &IdxQueryOptions::builder().downsample("5").build(),
+            ASSEMBLY_DIR,
+        ),
+        "Invalid Downsample \"5\"",
+    );
+    let subtraction = MaskSubtraction {
+        mode: MaskMode::Annotate,
+        min_overlap: 1.5,
+    };
+    assert_invalid_option(
+        query(
+            &IdxQueryOptions::builder()
+                .subtract_masks(subtraction.clone())
+                .build(),
+            ASSEMBLY_DIR,
+        ),
+        "Invalid Mask Overlap 1.5",
+    );
+    assert_invalid_option(
+        query(
+            &IdxQueryOptions::builder()
+                .subtract_masks(subtraction)
+                .build(),
+            MASKS_DIR,
+        ),
+        "Masks Can Only Be Subtracted From",
+    );
+}
+
+#[test]
+fn test_idx_query_families() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
+    let chrom = &synth.contigs[0].0;
+    let query = |family: Option<Vec<&str>>| -> Vec<String> {
+        let res = idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            chrom,
+            1,
+            100000,
+            &IdxQueryOptions {
+                family: family.map(|f| f.iter().map(|f| f.to_string()).collect()),
+                ..IdxQueryOptions::default()
+            },
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        let mut hits: Vec<String> = hits.iter().map(|hit| hit.to_string()).collect();
+        hits.sort();
+        hits
+    };
+
+    // Two families return the union of their single family queries, each hit naming its own
+    let first = query(Some(vec!["DF000000001"]));
+    let second = query(Some(vec!["DF000000003"]));
+    assert!(!first.is_empty() && !second.is_empty());
+    let mut union: Vec<String> = first.iter().chain(&second).cloned().collect();
+    union.sort();
+    let both = query(Some(vec!["DF000000001", "DF000000003"]));
+    assert_eq!(both, union);
+    for hit in &both {
+        let accession = from_str::<Value>(hit).unwrap()["accession"].clone();
+        assert!(accession == "DF000000001" || accession == "DF000000003");
+    }
+
+    // Versions are ignored and repeats are harmless, no families is no filter
+    assert_eq!(
+        query(Some(vec!["DF000000003.2", "DF000000001", "DF000000001.1"])),
+        both
+    );
+    assert_eq!(query(Some(vec![])), query(None));
+    assert_eq!(query(Some(vec!["DF000000099"])), Vec::<String>::new());
+
+    // The builder adds families one at a time or together
+    let built = IdxQueryOptions::builder()
+        .family("DF000000001")
+        .families(["DF000000003"])
+        .build();
+    assert_eq!(
+        built.family,
+        Some(vec!["DF000000001".to_string(), "DF000000003".to_string()])
+    );
+
+    // Searches of an open index filter on the set the same way
+    let handle = IndexHandle::open(assembly, ASSEMBLY_DIR, &data_directory, Duration::ZERO)
+        .expect("Can't Open Index");
+    let search = |family: Option<Vec<String>>| {
+        handle
+            .search(
+                chrom, 1, 100000, &family, false, &None, None, None, None, &None,
+            )
+            .expect("Index Search Failed")
+            .len()
+    };
+    assert_eq!(
+        search(Some(vec![
+            "DF000000001".to_string(),
+            "DF000000003".to_string()
+        ])),
+        both.len()
+    );
+    assert_eq!(search(Some(vec![])), search(None));
+}
+
+#[test]
+fn test_idx_query_group_by() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
+    let chrom = &synth.contigs[0].0;
+    let query = |data_type: &str, group_by: Option<ResultGrouping>| -> Value {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            chrom,
+            1,
+            100000,
+            &IdxQueryOptions {
+                group_by,
+                ..IdxQueryOptions::default()
+            },
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+
+    for (data_type, field) in [(ASSEMBLY_DIR, "accession"), (MASKS_DIR, "repeat_str")] {
+        let flat = query(data_type, None);
+        let flat = flat.as_array().unwrap();
+        assert!(!flat.is_empty());
+        let grouped = query(data_type, Some(ResultGrouping::default()));
+        let groups = grouped.as_object().unwrap();
+        assert!(groups.len() > 1);
+        let mut total = 0;
+        for (key, group) in groups {
+            let records = group["records"].as_array().unwrap();
+            assert_eq!(group["count"], records.len());
+            for record in records {
+                assert_eq!(record[field], key.as_str());
+            }
+            // In the order of the flat array
+            let expected: Vec<&Value> = flat
+                .iter()
+                .filter(|hit| hit[field] == key.as_str())
+                .collect();
+            assert_eq!(records.iter().collect::<Vec<_>>(), expected);
+            total += records.len();
+        }
+        assert_eq!(total, flat.len());
+
+        let counts = query(
+            data_type,
+            Some(ResultGrouping {
+                counts_only: true,
+                per_group_limit: None,
+            }),
+        );
+        for (key, group) in groups {
+            assert_eq!(counts[key], json!({ "count": group["count"] }));
+        }
+
+        let limited = query(
+            data_type,
+            Some(ResultGrouping {
+                counts_only: false,
+                per_group_limit: Some(1),
+            }),
+        );
+        for (key, group) in groups {
+            assert_eq!(limited[key]["count"], group["count"]);
+            assert_eq!(limited[key]["records"], json!([group["records"][0]]));
+        }
+    }
+}
+
+#[test]
+fn test_idx_query_into() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let lines: Vec<String> = (0..40000)
+        .map(|i| {
+            let start = 1 + i * 50;
+            let fam = format!("DF00000000{}", 1 + i % 3);
+            annotation_line("chr1", start, start + 99, &fam, "50.0", "+", "1")
+        })
+        .collect();
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[("DF000000001", lines)]);
+    let end = 40000 * 50 + 100;
+    let query = |options: &IdxQueryOptions| {
+        idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            end,
+            options,
+            &data_directory,
+        )
+        .expect("Index Query Failed")
+    };
+    let into = |options: &IdxQueryOptions, out: &mut Vec<u8>| {
+        idx_query_into(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            end,
+            options,
+            &data_directory,
+            out,
+        )
+        .expect("Index Query Failed")
+    };
+
+    // The bytes serializing the whole response at once gives, hits alone, around them or grouped
+    let options = IdxQueryOptions::builder().build();
+    let json = query(&options);
+    let hits: Value = from_str(&json).expect("Cannot Deserialize");
+    assert_eq!(hits.as_array().unwrap().len(), 40000);
+    assert_eq!(serde_json::to_string(&hits).unwrap(), json);
+    let mut written = Vec::new();
+    into(&options, &mut written);
+    assert_eq!(written, json.as_bytes());
+    for options in [
+        IdxQueryOptions::builder()
+            .downsample("5:10000")
+            .partial(true)
+            .with_warnings(true)
+            .with_etag(true)
+            .build(),
+        IdxQueryOptions::builder().with_warnings(true).build(),
+        IdxQueryOptions::builder()
+            .group_by(ResultGrouping {
+                counts_only: false,
+                per_group_limit: Some(2),
+            })
+            .with_etag(true)
+            .build(),
+    ] {
+        let json = query(&options);
+        let response: Value = from_str(&json).expect("Cannot Deserialize");
+        assert!(response.get("hits").is_some());
+        assert_eq!(serde_json::to_string(&response).unwrap(), json);
+    }
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_standard_bed_formats() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    // In order of the lower coordinate, as the sorted export writes them
+    let lines = vec![
+        annotation_line("chr1", 1000, 1200, fam, "104.4", "+", "1"),
+        annotation_line("chr1", 2000, 2500, fam, "250.6", "+", "0"),
+        annotation_line("chr1", 3400, 3001, fam, "1523.0", "-", "1"),
+        annotation_line("chr1", 3100, 3300, fam, "-5.0", "+", "0"),
+    ];
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines)]);
+    let masks = vec![
+        "chr1\t899\t1000\tTC\t2".to_string(),
+        "chr1\t2949\t3050\tCA\t2".to_string(),
+    ];
+    build_test_assembly(&data_directory, MASKS_DIR, &[("chr1", masks)]);
+    write_sequences_json(&data_directory);
+
+    let filter = |data_type: &str, key: &str, options: FilterOptions| -> Vec<String> {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            TEST_ASSEMBLY,
+            data_type,
+            key,
+            &mut output,
+            &options,
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect()
+    };
+    let query = |data_type: &str, options: IdxQueryOptions| -> Vec<String> {
+        idx_query_with(
+            TEST_ASSEMBLY,
+            data_type,
+            "chr1",
+            1,
+            5000,
+            &options,
+            &data_directory,
+        )
+        .expect("Index Query Failed")
+        .lines()
+        .map(|l| l.to_string())
+        .collect()
+    };
+
+    // 0-based half open on either strand, bit scores rounded and capped at 1000
+    let bed6 = [
+        "chr1\t999\t1200\tDF000000001\t104\t+",
+        "chr1\t1999\t2500\tDF000000001\t251\t+",
+        "chr1\t3000\t3400\tDF000000001\t1000\t-",
+        "chr1\t3099\t3300\tDF000000001\t0\t+",
+    ];
+    let filtered = filter(
+        ASSEMBLY_DIR,
+        fam,
+        FilterOptions::builder().bed_format(BedFormat::Bed6).build(),
+    );
+    assert_eq!(filtered, bed6);
+    // Sorted by chromStart, whatever the strand
+    assert_eq!(
+        query(
+            ASSEMBLY_DIR,
+            IdxQueryOptions::builder()
+                .bed_format(BedFormat::Bed6)
+                .build()
+        ),
+        bed6
+    );
+    let starts: Vec<u64> = filtered
+        .iter()
+        .map(|l| l.split('\t').nth(1).unwrap().parse().unwrap())
+        .collect();
+    assert!(starts.windows(2).all(|w| w[0] <= w[1]));
+
+    let bed12 = filter(
+        ASSEMBLY_DIR,
+        fam,
+        FilterOptions::builder()
+            .bed_format(BedFormat::Bed12)
+            .build(),
+    );
+    assert_eq!(
+        bed12[2],
+        "chr1\t3000\t3400\tDF000000001\t1000\t-\t3000\t3400\t0\t1\t400,\t0,"
+    );
+    assert!(bed12.iter().all(|l| l.split('\t').count() == 12));
+
+    // Masks have no score or strand
+    let mask_options = IdxQueryOptions::builder()
+        .bed_format(BedFormat::Bed12)
+        .resolve_names(true)
+        .build();
+    assert_eq!(
+        query(MASKS_DIR, mask_options),
+        [
+            "CM000663.2\t899\t1000\tTC\t0\t.\t899\t1000\t0\t1\t101,\t0,",
+            "CM000663.2\t2949\t3050\tCA\t0\t.\t2949\t3050\t0\t1\t101,\t0,",
+        ]
+    );
+    assert_eq!(
+        filter(
+            MASKS_DIR,
+            "chr1",
+            FilterOptions::builder()
+                .bed_format(BedFormat::Bed6)
+                .resolve_names(true)
+                .build()
+        )[0],
+        "CM000663.2\t899\t1000\tTC\t0\t."
+    );
+
+    // Options that have no place in a BED line
+    let err = idx_query_with(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        "chr1",
+        1,
+        5000,
+        &IdxQueryOptions::builder()
+            .bed_format(BedFormat::Bed6)
+            .group_by(ResultGrouping::default())
+            .build(),
+        &data_directory,
+    )
+    .expect_err("Grouped BED Accepted");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let err = bgzf_filter_with(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        fam,
+        &mut Vec::new(),
+        &FilterOptions::builder().resolve_names(true).build(),
+        &data_directory,
+    )
+    .expect_err("Names Resolved Without A BED Format");
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    assert_eq!(bed_score("999.5"), 1000);
+    assert_eq!(bed_score("n/a"), 0);
+    assert_eq!(BedFormat::parse("bed12"), Some(BedFormat::Bed12));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_options_builders() {
+    // Unset options keep the defaults the positional arguments were always given
+    let query = IdxQueryOptions::builder().build();
+    assert_eq!(query.max_region, None);
+    assert!(query.family.is_none() && query.strand.is_none() && query.downsample.is_none());
+    assert!(query.min_bit_score.is_none() && query.max_e_value.is_none());
+    assert!(query.timeout.is_none() && query.query_log.is_none());
+    assert!(query.subtract_masks.is_none() && query.group_by.is_none());
+    assert!(!query.nrph && !query.partial && !query.legacy_json && !query.with_warnings);
+    assert!(!query.strict && !query.with_model_length);
+    let query = IdxQueryOptions::builder()
+        .family("DF000000001")
+        .nrph(true)
+        .max_region(5000)
+        .build();
+    assert_eq!(
+        query.family.as_deref(),
+        Some(&["DF000000001".to_string()][..])
+    );
+    assert!(query.nrph);
+    assert_eq!(query.max_region, Some(5000));
+    assert!(query.strand.is_none() && !query.with_warnings);
+
+    let filter = FilterOptions::builder().build();
+    assert_eq!(filter.profile, PUBLIC_PROFILE);
+    assert!(filter.prefilter);
+    assert!(filter.term.is_none() && filter.strand.is_none() && filter.query_log.is_none());
+    assert!(!filter.dl_fmt && !filter.source_meta && filter.exclude_columns.is_empty());
+    let filter = FilterOptions::builder().term(13, "1").dl_fmt(true).build();
+    assert_eq!((filter.position, filter.term.as_deref()), (13, Some("1")));
+    assert!(filter.dl_fmt && filter.prefilter);
+    assert_eq!(filter.profile, PUBLIC_PROFILE);
+
+    let prep = PrepOptions::builder().build();
+    assert_eq!(prep.shard_threshold, DEFAULT_SHARD_THRESHOLD);
+    assert!(!prep.dedupe && prep.scratch_directory.is_none());
+    let prep = PrepOptions::builder().dedupe(true).build();
+    assert_eq!(prep.shard_threshold, DEFAULT_SHARD_THRESHOLD);
+    assert_eq!(PrepOptions::builder().sharded().build().shard_threshold, 0);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_deprecated_wrappers() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
+    let (synth, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
+    let chrom = &synth.contigs[0].0;
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let family = &"DF000000001".to_string();
+
+    let old = te_idx::idx_query(
+        assembly,
+        data_type,
+        chrom,
+        1,
+        100000,
+        &None,
+        &Some(family.clone()),
+        &true,
+        &None,
+        &None,
+        &None,
+        &None,
+        &None,
+        &false,
+        &false,
+        &true,
+        &false,
+        &false,
+        &None,
+        &None,
+        &None,
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let options = IdxQueryOptions::builder()
+        .family(family)
+        .nrph(true)
+        .with_warnings(true)
+        .build();
+    let new = idx_query_with(
+        assembly,
+        data_type,
+        chrom,
+        1,
+        100000,
+        &options,
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    assert_eq!(old, new);
+
+    let mut old = Vec::new();
+    te_idx::bgzf_filter(
+        assembly,
+        data_type,
+        family,
+        &13,
+        &Some("1".to_string()),
+        &None,
+        &mut old,
+        true,
+        PUBLIC_PROFILE,
+        &[],
+        false,
+        true,
+        &None,
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    let mut new = Vec::new();
+    let options = FilterOptions::builder().term(13, "1").dl_fmt(true).build();
+    bgzf_filter_with(
+        assembly,
+        data_type,
+        family,
+        &mut new,
+        &options,
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    assert!(old.lines().count() > 1);
+    assert_eq!(old, new);
+
+    let in_tsv = format!(
+        "{}/exports/{}/{}{}",
+        working_path, assembly, assembly, ASSEMBLY_FILE
+    );
+    let prepped = |data_directory: &String| -> Vec<String> {
+        let mut files: Vec<String> =
+            read_dir(format!("{}/{}/{}", data_directory, assembly, data_type))
+                .expect("Can't Read Dir")
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+        files.sort();
+        files
+    };
+    let (old_directory, new_directory) = (
+        format!("{}/old", working_path),
+        format!("{}/new", working_path),
+    );
+    let old = te_idx::prep_beds(assembly, &in_tsv, data_type, true, 0, &None, &old_directory)
+        .expect("BED Prep Failed");
+    let options = PrepOptions::builder().dedupe(true).sharded().build();
+    let new = prep_beds_with(assembly, &in_tsv, data_type, &options, &new_directory)
+        .expect("BED Prep Failed");
+    assert_eq!(old.records, new.records);
+    assert_eq!(prepped(&old_directory), prepped(&new_directory));
+}
+
+#[test]
+fn test_self_test() {
+    let output = Command::new(env!("CARGO_BIN_EXE_te_idx"))
+        .args(["--assembly", "selftest", "self-test"])
+        .output()
+        .expect("Could Not Run Self-Test");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Self-Test PASSED"));
+    assert!(stdout.contains("PASS  chrom id"));
+    assert!(!stdout.contains("FAIL "));
+}
+
+#[test]
+fn test_benchmark_report() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    copy_test_data(&data_directory, ASSEMBLY_DIR);
+    let fam = &"DF000000001".to_string();
+    let assembly_lines: Vec<String> = BufReader::new(bgzf::Reader::new(
+        File::open(format!(
+            "{}/{}/{}/{}.bed.bgz",
+            data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR, fam
+        ))
+        .unwrap(),
+    ))
+    .lines()
+    .map(|l| l.unwrap())
+    .collect();
+
+    // Benchmark hits in the 15 column layout: exact copies of assembly hits, hits shifted
+    // by 80% of their length, hits on the other strand and hits on a sequence without
+    // assembly hits
+    let mut bench_lines = Vec::new();
+    for (i, line) in assembly_lines.iter().take(200).enumerate() {
+        let mut fields: Vec<String> = line.split('\t').map(|f| f.to_string()).collect();
+        let (start, end): (u64, u64) = (fields[1].parse().unwrap(), fields[2].parse().unwrap());
+        let shift = start.abs_diff(end) * 4 / 5;
+        match i {
+            150..=169 => {
+                fields[1] = (start + shift).to_string();
+                fields[2] = (end + shift).to_string();
+            }
+            170..=179 => fields[5] = if fields[5] == "+" { "-" } else { "+" }.to_string(),
+            180..=184 => fields[0] = "chrUn_bench".to_string(),
+            _ => {}
+        }
+        let mut bench: Vec<String> = fields[..12].to_vec();
+        bench.extend([fields[14].clone(), fields[15].clone(), fields[16].clone()]);
+        bench_lines.push(bench.join("\t"));
+    }
+    create_dir_all(format!(
+        "{}/{}/{}",
+        data_directory, TEST_ASSEMBLY, BENCHMARK_DIR
+    ))
+    .unwrap();
+    write_bed_bgz(
+        &format!(
+            "{}/{}/{}/{}.bed.bgz",
+            data_directory, TEST_ASSEMBLY, BENCHMARK_DIR, fam
+        ),
+        &bench_lines,
+    );
+    File::create(half_open_file(
+        TEST_ASSEMBLY,
+        BENCHMARK_DIR,
+        &data_directory,
+    ))
+    .expect("Can't Create File");
+
+    // Brute force matching over every pair
+    let parse = |line: &String| {
+        let f: Vec<&str> = line.split('\t').collect();
+        let (a, b): (u64, u64) = (f[1].parse().unwrap(), f[2].parse().unwrap());
+        (f[0].to_string(), f[5].to_string(), a.min(b), a.max(b))
+    };
+    let bench: Vec<_> = bench_lines.iter().map(parse).collect();
+    let full: Vec<_> = assembly_lines.iter().map(parse).collect();
+    let matches = |x: &(String, String, u64, u64), y: &(String, String, u64, u64)| {
+        let overlap = (x.3.min(y.3) + 1).saturating_sub(x.2.max(y.2)) as f64;
+        x.0 == y.0
+            && x.1 == y.1
+            && overlap / (x.3 - x.2 + 1) as f64 >= 0.5
+            && overlap / (y.3 - y.2 + 1) as f64 >= 0.5
+    };
+    let recovered = bench
+        .iter()
+        .filter(|b| full.iter().any(|a| matches(a, b)))
+        .count();
+    let assembly_only = full
+        .iter()
+        .filter(|a| !bench.iter().any(|b| matches(a, b)))
+        .count();
+
+    let report = benchmark_report(TEST_ASSEMBLY, fam, 0.5, &data_directory)
+        .expect("Benchmark Report Failed");
+    assert_eq!(report.benchmark_hits, 200);
+    assert_eq!(report.assembly_hits, assembly_lines.len());
+    assert_eq!(report.recovered, recovered);
+    assert_eq!(report.missed, 200 - recovered);
+    assert_eq!(report.assembly_only, assembly_only);
+    assert!((165..185).contains(&recovered));
+    assert!(report.bit_score_pearson.unwrap() > 0.5);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_family_combined() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let fam = &"DF000000001".to_string();
+    let assembly_lines = vec![
+        annotation_line("chr1", 100, 500, fam, "300", "+", "1"),
+        annotation_line("chr1", 2600, 2000, fam, "250", "-", "0"),
+        annotation_line("chr2", 50, 90, fam, "40", "+", "1"),
+    ];
+    // Benchmark records in the 15 column layout, without NRPH, divergence and CAF
+    let bench_lines: Vec<String> = [
+        annotation_line("chr1", 90, 480, fam, "280", "+", "1"),
+        annotation_line("chr1", 3000, 3400, fam, "120", "+", "1"),
+    ]
+    .iter()
+    .map(|line| {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let mut bench = fields[..12].to_vec();
+        bench.extend(&fields[14..17]);
+        bench.join("\t")
+    })
+    .collect();
+    for (data_type, lines) in [
+        (ASSEMBLY_DIR, &assembly_lines),
+        (BENCHMARK_DIR, &bench_lines),
+    ] {
+        let dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, data_type);
+        create_dir_all(&dir).unwrap();
+        write_bed_bgz(&format!("{}/{}.bed.bgz", dir, fam), lines);
+        File::create(half_open_file(TEST_ASSEMBLY, data_type, &data_directory))
+            .expect("Can't Create File");
+    }
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    copy_test_data(&data_directory, MOD_LEN_DIR);
+
+    let hits = family_combined(assembly, fam, false, &data_directory).expect("Combining Failed");
+    assert_eq!(hits.len(), assembly_lines.len() + bench_lines.len());
+    let order: Vec<(&str, &str, u64)> = hits
+        .iter()
+        .map(|h| (h.source, h.sequence.as_str(), h.low))
+        .collect();
+    assert_eq!(
+        order,
+        [
+            (BENCHMARK_SOURCE, "chr1", 90),
+            (ASSEMBLY_SOURCE, "chr1", 100),
+            (ASSEMBLY_SOURCE, "chr1", 2000),
+            (BENCHMARK_SOURCE, "chr1", 3000),
+            (ASSEMBLY_SOURCE, "chr2", 50),
+        ]
+    );
+    for hit in &hits {
+        let json = hit.to_json();
+        assert_eq!(json["source"], hit.source);
+        assert_eq!(json["accession"], fam.as_str());
+        if hit.source == ASSEMBLY_SOURCE {
+            assert!(json["nrph_hit"].is_i64());
+            assert_eq!(json["kimura_div"], 10.5);
+        } else {
+            assert!(json["nrph_hit"].is_null());
+            assert!(json["kimura_div"].is_null());
+        }
+    }
+
+    // NRPH only drops the assembly hits without the flag
+    let nrph = family_combined(assembly, fam, true, &data_directory).expect("Combining Failed");
+    assert_eq!(nrph.len(), 4);
+    assert!(!nrph
+        .iter()
+        .any(|h| h.source == ASSEMBLY_SOURCE && h.low == 2000));
+
+    let mut tsv = Vec::new();
+    let written = write_family_combined(assembly, fam, &hits, true, &mut tsv, &data_directory)
+        .expect("Writing Failed");
+    assert_eq!(written, hits.len());
+    let tsv = String::from_utf8(tsv).unwrap();
+    let lines: Vec<&str> = tsv.lines().collect();
+    assert_eq!(lines[0], COMBINED_DL_HEADER);
+    assert_eq!(lines.len(), hits.len() + 1);
+    for (line, hit) in lines[1..].iter().zip(&hits) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        assert_eq!(fields.len(), COMBINED_DL_HEADER.split('\t').count());
+        assert_eq!(fields[0], hit.source);
+        assert_eq!(fields[15].is_empty(), hit.source == BENCHMARK_SOURCE);
+    }
+    let mut json = Vec::new();
+    write_family_combined(assembly, fam, &hits, false, &mut json, &data_directory)
+        .expect("Writing Failed");
+    let json: Vec<Value> = from_str(&String::from_utf8(json).unwrap()).unwrap();
+    assert_eq!(json.len(), hits.len());
+
+    // Either source alone is enough, neither is NotFound
+    remove_file(bgz_path(
+        format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, BENCHMARK_DIR),
+        fam,
+    ))
+    .unwrap();
+    let hits = family_combined(assembly, fam, false, &data_directory).expect("Combining Failed");
+    assert_eq!(hits.len(), assembly_lines.len());
+    let missing = family_combined(assembly, "DF000000099", false, &data_directory);
+    assert_eq!(missing.unwrap_err().kind(), ErrorKind::NotFound);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_empty_family_file() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let (fam, empty_fam) = (&"DF000000001".to_string(), &"DF000000002".to_string());
+    let data_path = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR);
+    create_dir_all(&data_path).unwrap();
+    write_bed_bgz(
+        &format!("{}/{}.bed.bgz", data_path, fam),
+        &[annotation_line("chr1", 100, 500, fam, "300", "+", "1")],
+    );
+    // Nothing but the end-of-file block
+    write_bed_bgz(&format!("{}/{}.bed.bgz", data_path, empty_fam), &[]);
+    File::create(half_open_file(TEST_ASSEMBLY, ASSEMBLY_DIR, &data_directory))
+        .expect("Can't Create File");
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    copy_test_data(&data_directory, MOD_LEN_DIR);
+    assert!(bgz_is_empty(&bgz_path(&data_path, empty_fam)).unwrap());
+    assert!(!bgz_is_empty(&bgz_path(&data_path, fam)).unwrap());
+
+    // Indexed without ranges, and listed
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    assert_eq!(
+        contig_index.empty_files(),
+        &vec![format!("{}.bed.bgz", empty_fam)]
+    );
+    assert_eq!(contig_index.build_stats().ranges, 1);
+
+    // Filtering writes the header alone
+    let mut out = Vec::new();
+    let count = bgzf_filter_with(
+        assembly,
+        data_type,
+        empty_fam,
+        &mut out,
+        &FilterOptions::default(),
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    assert_eq!(count, 0);
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.lines().count(), 1);
+    assert!(out.starts_with('#'));
+    let count = read_family_assembly_annotations(
+        empty_fam,
+        assembly,
+        &false,
+        &mut Vec::new(),
+        &false,
+        &data_directory,
+    )
+    .expect("Reading Annotations Failed");
+    assert_eq!(count, 0);
+
+    // Zeroed counts
+    let summary = family_summary(assembly, empty_fam, &data_directory).expect("Summary Failed");
+    assert_eq!(summary.hits, 0);
+    assert_eq!(summary.nrph_hits, 0);
+    assert!(summary.contigs.is_empty());
+
+    // The hint tells an empty family from one without hits in the region
+    let query = |family: &String| -> Value {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            5000,
+            &IdxQueryOptions::builder()
+                .family(family.as_str())
+                .with_warnings(true)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+    let empty = query(empty_fam);
+    assert_eq!(empty["hits"], json!([]));
+    assert_eq!(empty["empty_family"], true);
+    let found = query(fam);
+    assert_eq!(found["hits"].as_array().unwrap().len(), 1);
+    assert_eq!(found["empty_family"], false);
+
+    let families = [
+        empty_fam.to_string(),
+        "DF000000003".to_string(),
+        format!("{}.4", fam),
+    ];
+    assert_eq!(
+        missing_families(assembly, data_type, &families, false, &data_directory).unwrap(),
+        vec!["DF000000003".to_string()]
+    );
+    assert_eq!(
+        missing_families(assembly, data_type, &families, true, &data_directory).unwrap(),
+        vec![empty_fam.to_string(), "DF000000003".to_string()]
+    );
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_list_families() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    copy_test_data(&data_directory, MASKS_DIR);
+    let data_path = join_path(&data_directory, &[TEST_ASSEMBLY, MASKS_DIR]);
+    let mut on_disk: Vec<String> = read_dir(&data_path)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .strip_suffix(".bed.bgz")
+                .map(|acc| acc.to_string())
+        })
+        .collect();
+    on_disk.sort();
+    let list = |prefix: Option<&str>, limit: Option<usize>, offset: usize| {
+        list_families(assembly, data_type, prefix, limit, offset, &data_directory)
+            .expect("Listing Failed")
+    };
+    let accessions = |page: &FamilyPage| -> Vec<String> {
+        page.families.iter().map(|f| f.accession.clone()).collect()
+    };
+
+    // Before the index is built the folder is listed, without record counts
+    let scanned = list(None, None, 0);
+    assert_eq!(accessions(&scanned), on_disk);
+    assert_eq!(scanned.total, on_disk.len());
+    assert!(scanned
+        .families
+        .iter()
+        .all(|f| f.records.is_none() && f.bed_idx.is_none()));
+
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    assert!(Path::new(&family_listing_file(&index_file)).exists());
+    let listed = list(None, None, 0);
+    assert_eq!(accessions(&listed), on_disk);
+    for family in &listed.families {
+        let path = bgz_path(&data_path, &family.accession);
+        assert_eq!(family.bytes, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(family.records, Some(read_line_records(&path).len() as u64));
+        let bed_idx = family.bed_idx.expect("No bed_idx") as usize;
+        assert_eq!(
+            Path::new(&filenames[bed_idx]),
+            Path::new(&format!("{}.bed.bgz", family.accession))
+        );
+    }
+    // The same files either way
+    for (listed, scanned) in listed.families.iter().zip(&scanned.families) {
+        assert_eq!(listed.bytes, scanned.bytes);
+    }
+
+    // Prefixes count only their matches, pages are cut from those
+    let chr14 = list(Some("chr14"), None, 0);
+    assert_eq!(chr14.total, 8);
+    assert!(chr14
+        .families
+        .iter()
+        .all(|f| f.accession.starts_with("chr14")));
+    let page = list(Some("chr14"), Some(3), 2);
+    assert_eq!(page.total, 8);
+    assert_eq!(accessions(&page), accessions(&chr14)[2..5]);
+    assert!(list(Some("chr14"), Some(3), 8).families.is_empty());
+    assert_eq!(list(None, Some(0), 0).total, on_disk.len());
+    assert_eq!(list(Some("chrX"), None, 0), FamilyPage::default());
+
+    // Appending drops the listing until the index is rebuilt
+    let in_tsv = join_path(&data_directory, &["append.tsv"]);
+    write(&in_tsv, "chr1\t100\t150\tTC\t2\n").expect("Can't Write TSV");
+    append_records(assembly, data_type, "chr1", &in_tsv, false, &data_directory)
+        .expect("Append Failed");
+    assert!(!Path::new(&family_listing_file(&index_file)).exists());
+    assert!(list(Some("chr1"), Some(1), 0).families[0].records.is_none());
+
+    let _ = working_directory.close();
+}
+
+#[test]
+#[should_panic(expected = "Has No benchmark_alignments")]
+fn test_benchmark_report_missing_benchmark() {
+    let _ = benchmark_report(TEST_ASSEMBLY, "DF000000001", 0.5, test_data_dir());
+}
+
+#[test]
+fn test_defragment() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let fam = "DF000000001";
+    let fragment = |chrom, seq_start, seq_end, strand, model_start: u64, model_end: u64| {
+        let line = annotation_line(chrom, seq_start, seq_end, fam, "300", strand, "0");
+        let mut fields: Vec<String> = line.split('\t').map(|f| f.to_string()).collect();
+        fields[9] = model_start.to_string();
+        fields[10] = model_end.to_string();
+        fields.join("\t")
+    };
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[(
+            fam,
+            vec![
+                // Joined, the second piece overlaps the first on the model within tolerance
+                fragment("chr1", 100, 199, "+", 1, 100),
+                fragment("chr1", 250, 349, "+", 95, 195),
+                // Close by but back on the model, then too far away
+                fragment("chr1", 380, 450, "+", 50, 120),
+                fragment("chr1", 1000, 1100, "+", 200, 300),
+                // Minus strand hits run along the model with descending position
+                fragment("chr1", 2000, 1901, "-", 1, 100),
+                fragment("chr1", 1850, 1751, "-", 101, 200),
+                fragment("chr1", 1700, 1650, "-", 10, 60),
+                // Model order ascending with position is not collinear on the minus strand
+                fragment("chr1", 3000, 2901, "-", 1, 100),
+                fragment("chr1", 3150, 3051, "-", 101, 200),
+                // A nested fragment does not split the pieces around it
+                fragment("chr2", 100, 199, "+", 1, 100),
+                fragment("chr2", 220, 260, "+", 30, 70),
+                fragment("chr2", 280, 380, "+", 101, 200),
+            ],
+        )],
+    );
+
+    let report =
+        defragment(TEST_ASSEMBLY, fam, 200, 20, &data_directory).expect("Defragment Failed");
+    assert_eq!(report.hits, 12);
+    assert_eq!(report.joined_hits, 6);
+    let chains: Vec<(String, Vec<(u64, u64)>)> = report
+        .insertions
+        .iter()
+        .map(|i| {
+            (
+                format!("{}{}", i.sequence, i.strand),
+                i.members.iter().map(|m| (m.seq_start, m.seq_end)).collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        chains,
+        vec![
+            ("chr1+".to_string(), vec![(100, 199), (250, 349)]),
+            ("chr1-".to_string(), vec![(2000, 1901), (1850, 1751)]),
+            ("chr2+".to_string(), vec![(100, 199), (280, 380)]),
+        ]
+    );
+    // A sorted file is chained a sequence at a time, with the same result
+    write(
+        format!(
+            "{}/{}/{}{}",
+            data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR, SORTED_FILE
+        ),
+        "",
+    )
+    .expect("Can't Write File");
+    let streamed =
+        defragment(TEST_ASSEMBLY, fam, 200, 20, &data_directory).expect("Defragment Failed");
+    assert_eq!(streamed, report);
+
+    let first = &report.insertions[0];
+    assert_eq!((first.start, first.end), (100, 349));
+    assert_eq!((first.model_start, first.model_end), (1, 195));
+    assert_eq!((first.genome_coverage, first.model_coverage), (200, 195));
+    assert_eq!(
+        (report.insertions[1].start, report.insertions[1].end),
+        (1751, 2000)
+    );
+
+    // A smaller gap leaves every fragment on its own
+    let strict =
+        defragment(TEST_ASSEMBLY, fam, 10, 20, &data_directory).expect("Defragment Failed");
+    assert!(strict.insertions.is_empty());
+    assert_eq!(strict.tsv_lines(), vec![DEFRAGMENT_TSV_HEADER.to_string()]);
+    assert_eq!(
+        report.tsv_lines()[1],
+        "chr1\t100\t349\t+\t1\t195\t200\t195\t100-199:1-100,250-349:95-195"
+    );
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_cooccurrence() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    let (fam_a, fam_b, fam_c, fam_d, fam_e) = (
+        "DF000000001",
+        "DF000000002",
+        "DF000000003",
+        "DF000000004",
+        "DF000000005",
+    );
+    let mut files: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut hit = |chrom: &str, start: u64, end: u64, fam: &'static str, strand: &str| {
+        files
+            .entry(fam)
+            .or_default()
+            .push(annotation_line(chrom, start, end, fam, "300", strand, "1"));
+    };
+    // The planted pair, 200 bp apart, with A upstream at all but two loci. A lone background
+    // hit lies between loci, too far from either to co-occur
+    for i in 1..=10 {
+        let p = 10_000 * i;
+        let (up, down) = if i > 8 {
+            (fam_b, fam_a)
+        } else {
+            (fam_a, fam_b)
+        };
+        if i == 1 {
+            hit("chr1", p + 300, p, up, "-");
+        } else {
+            hit("chr1", p, p + 300, up, "+");
+        }
+        hit("chr1", p + 500, p + 800, down, "+");
+        hit("chr1", p + 5000, p + 5300, fam_e, "+");
+    }
+    // A weaker pair, C nesting D, and a single co-occurrence of E and C
+    for i in 1..=3 {
+        let q = 10_000 * i;
+        hit("chr2", q, q + 1000, fam_c, "+");
+        hit("chr2", q + 400, q + 600, fam_d, "+");
+    }
+    hit("chr2", 50_000, 50_300, fam_e, "+");
+    hit("chr2", 50_500, 50_800, fam_c, "+");
+    let files: Vec<(&str, Vec<String>)> = files.into_iter().collect();
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &files);
+    let assembly = &TEST_ASSEMBLY.to_string();
+
+    let report = cooccurrence(assembly, 1000, &None, &false, 20, None, &data_directory)
+        .expect("Co-occurrence Failed");
+    assert_eq!(report.hits, 38);
+    assert_eq!(report.families, 5);
+    assert_eq!(report.windowed_pairs, 14);
+    assert_eq!(report.pruned_pairs, 0);
+    let ranked: Vec<(&str, &str, u64, u64, u64)> = report
+        .pairs
+        .iter()
+        .map(|p| {
+            (
+                p.family_a.as_str(),
+                p.family_b.as_str(),
+                p.count,
+                p.a_first,
+                p.b_first,
+            )
+        })
+        .collect();
+    assert_eq!(
+        ranked,
+        vec![
+            (fam_a, fam_b, 10, 8, 2),
+            (fam_c, fam_d, 3, 3, 0),
+            (fam_c, fam_e, 1, 0, 1),
+        ]
+    );
+    let planted = &report.pairs[0];
+    assert!((planted.expected - 2.0 * 14.0 * (10.0 / 38.0) * (10.0 / 38.0)).abs() < 1e-9);
+    assert!(planted.enrichment > 5.0);
+    let tsv = report.tsv_lines();
+    assert_eq!(tsv[0], COOCCURRENCE_TSV_HEADER);
+    assert!(tsv[1].starts_with("DF000000001\tDF000000002\t10\t8\t2\t"));
+
+    // Nothing co-occurs once the window is smaller than every gap but the nested hits'
+    let report = cooccurrence(assembly, 100, &None, &false, 20, None, &data_directory)
+        .expect("Co-occurrence Failed");
+    assert_eq!(report.pairs.len(), 1);
+    assert_eq!(
+        (report.pairs[0].family_a.as_str(), report.pairs[0].count),
+        (fam_c, 3)
+    );
+
+    // Only the families asked for are counted, and top_n cuts the ranking
+    let families = Some(vec![fam_c.to_string(), fam_d.to_string()]);
+    let report = cooccurrence(assembly, 1000, &families, &false, 20, None, &data_directory)
+        .expect("Co-occurrence Failed");
+    assert_eq!(
+        (report.hits, report.families, report.windowed_pairs),
+        (7, 2, 3)
+    );
+    let report = cooccurrence(assembly, 1000, &None, &false, 1, None, &data_directory)
+        .expect("Co-occurrence Failed");
+    assert_eq!(report.pairs.len(), 1);
+    assert_eq!(report.pairs[0].count, 10);
+
+    // Pruning drops the rarest pairs first, keeping the planted one
+    let report = cooccurrence(assembly, 1000, &None, &false, 20, Some(2), &data_directory)
+        .expect("Co-occurrence Failed");
+    assert_eq!(report.pruned_pairs, 2);
+    assert_eq!(report.pairs.len(), 1);
+    assert_eq!(report.pairs[0].count, 10);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_redundancy_scan() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    let (fam_a, fam_b, fam_c) = ("DF000000001", "DF000000002", "DF000000003");
+    let mut files: HashMap<&str, Vec<String>> = HashMap::new();
+    let mut hit = |chrom: &str, start: u64, end: u64, fam: &'static str| {
+        files
+            .entry(fam)
+            .or_default()
+            .push(annotation_line(chrom, start, end, fam, "300", "+", "1"));
+    };
+    // The planted pair, B within 20 bp of A at every locus. The control family overlaps both
+    // by about 70%
+    for i in 1..=6 {
+        let p = 10_000 * i;
+        hit("chr1", p, p + 1000, fam_a);
+        hit("chr1", p + 20, p + 1010, fam_b);
+        hit("chr1", p + 300, p + 1300, fam_c);
+    }
+    // The control matches B once, and A overlaps itself, which never pairs
+    hit("chr2", 5000, 6000, fam_b);
+    hit("chr2", 5000, 6000, fam_c);
+    hit("chr2", 80_000, 81_000, fam_a);
+    hit("chr2", 80_010, 81_000, fam_a);
+    let files: Vec<(&str, Vec<String>)> = files.into_iter().collect();
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &files);
+    let assembly = &TEST_ASSEMBLY.to_string();
+
+    let report = redundancy_scan(assembly, 0.9, 3, &data_directory).expect("Scan Failed");
+    assert_eq!((report.hits, report.families), (22, 3));
+    assert_eq!(report.overlapping_pairs, 7);
+    assert_eq!(
+        report.pairs,
+        vec![RedundantPair {
+            family_a: fam_a.to_string(),
+            family_b: fam_b.to_string(),
+            count: 6,
+            a_hits: 8,
+            b_hits: 7,
+            examples: vec![
+                "chr1:10000-11010".to_string(),
+                "chr1:20000-21010".to_string(),
+                "chr1:30000-31010".to_string(),
+            ],
+        }]
+    );
+    let tsv = report.tsv_lines();
+    assert_eq!(tsv[0], REDUNDANCY_TSV_HEADER);
+    assert!(tsv[1].starts_with("DF000000001\tDF000000002\t6\t8\t7\tchr1:10000-11010,"));
+
+    // A lower count brings in the single match, a looser overlap the control
+    let report = redundancy_scan(assembly, 0.9, 1, &data_directory).expect("Scan Failed");
+    assert_eq!(report.pairs.len(), 2);
+    assert_eq!(
+        (report.pairs[1].family_a.as_str(), report.pairs[1].count),
+        (fam_b, 1)
+    );
+    let report = redundancy_scan(assembly, 0.6, 3, &data_directory).expect("Scan Failed");
+    let pairs: Vec<(&str, &str, u64)> = report
+        .pairs
+        .iter()
+        .map(|p| (p.family_a.as_str(), p.family_b.as_str(), p.count))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![(fam_b, fam_c, 7), (fam_a, fam_b, 6), (fam_a, fam_c, 6)]
+    );
+    assert!(redundancy_scan(assembly, 7.0, 3, &data_directory).is_err());
+    assert!(redundancy_scan(assembly, 0.0, 3, &data_directory).is_err());
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_remap_contig_names() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    copy_test_data(&data_directory, MASKS_DIR);
+    copy_test_data(&data_directory, SEQUENCE_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, MASKS_DIR, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    // Give every sequence a display name, chrN -> NC_N
+    let sequences_file = format!(
+        "{}/{}/{}/{}-{}.json",
+        data_directory, TEST_ASSEMBLY, SEQUENCE_DIR, TEST_ASSEMBLY, SEQUENCE_DIR
+    );
+    let mut sequences: Value =
+        from_str(&read_to_string(&sequences_file).expect("Can't Read File")).unwrap();
+    for (acc, vals) in sequences["data"].as_object_mut().unwrap() {
+        vals["id"] = Value::String(acc.replace("chr", "NC_"));
+    }
+    write(&sequences_file, sequences.to_string()).expect("Can't Write File");
+
+    let remapped = &"test_ex_ncbi".to_string();
+    let report = remap_contig_names(
+        assembly,
+        MASKS_DIR,
+        &NameMapping::SequenceIds,
+        false,
+        remapped,
+        &data_directory,
+    )
+    .expect("Remapping Failed");
+    assert_eq!(report.dropped, 0);
+    assert!(Path::new(&format!(
+        "{}/{}/{}/NC_10.bed.bgz",
+        data_directory, remapped, MASKS_DIR
+    ))
+    .exists());
+
+    let query = |assembly: &String, chrom: &str| {
+        idx_query_with(
+            assembly,
+            MASKS_DIR,
+            chrom,
+            30000,
+            200000,
+            &IdxQueryOptions::default(),
+            &data_directory,
+        )
+        .expect("Index Query Failed")
+    };
+    for (chrom, new_chrom) in [("chr1", "NC_1"), ("chr10", "NC_10")] {
+        let original = query(assembly, chrom);
+        assert_ne!(original, "[]");
+        assert_eq!(
+            query(remapped, new_chrom),
+            original.replace(&format!("\"{}\"", chrom), &format!("\"{}\"", new_chrom))
+        );
+    }
+    assert_eq!(
+        json_query(
+            remapped,
+            SEQUENCE_DIR,
+            "NC_1",
+            &Some("id".to_string()),
+            &None,
+            &data_directory
+        )
+        .unwrap(),
+        "chr1"
+    );
+
+    // A mapping file that only covers chr1
+    let mapping = NameMapping::Tsv(format!("{}/mapping.tsv", data_directory));
+    write(
+        format!("{}/mapping.tsv", data_directory),
+        "chr1\tNC_000001.11\n",
+    )
+    .unwrap();
+    let partial = &"test_ex_partial".to_string();
+    assert!(remap_contig_names(
+        assembly,
+        MASKS_DIR,
+        &mapping,
+        false,
+        partial,
+        &data_directory
+    )
+    .is_err());
+    let report = remap_contig_names(
+        assembly,
+        MASKS_DIR,
+        &mapping,
+        true,
+        partial,
+        &data_directory,
+    )
+    .expect("Remapping Failed");
+    assert_eq!(report.unmapped.len(), 18);
+    assert!(!report.unmapped.contains("chr1"));
+    assert_eq!(
+        read_dir(format!("{}/{}/{}", data_directory, partial, MASKS_DIR))
+            .unwrap()
+            .count(),
+        1
+    );
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_migrate_assembly() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    copy_test_data_from(TEST_DATA_DIR, &data_directory, ASSEMBLY_DIR);
+    copy_test_data_from(TEST_DATA_DIR, &data_directory, MASKS_DIR);
+    let index_file = format!("{}/{}/{}_idx.dat", data_directory, assembly, ASSEMBLY_DIR);
+    copy(
+        format!("{}/{}/{}_idx.dat", TEST_DATA_DIR, assembly, ASSEMBLY_DIR),
+        &index_file,
+    )
+    .expect("Can't Copy Index");
+    // The masks index is built by this release, in the new format
+    let (filenames, bgz_dir, mut contig_index, masks_index) =
+        prep_idx(assembly, MASKS_DIR, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &masks_index).expect("Indexing Failed");
+    assert_eq!(index_format_version(&index_file).unwrap(), 0);
+    assert_eq!(index_format_version(&masks_index).unwrap(), FORMAT_VERSION);
+    assert_eq!(
+        read_assembly_meta(assembly, &data_directory)
+            .unwrap()
+            .layout_version,
+        0
+    );
+
+    let query_in = |data_directory: &str| {
+        idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr10",
+            10000,
+            100000,
+            &IdxQueryOptions::default(),
+            data_directory,
+        )
+    };
+    let query = || query_in(&data_directory);
+
+    // Exported coordinates are refused rather than read as BED coordinates
+    for data_directory in [TEST_DATA_DIR, &data_directory] {
+        let err = query_in(data_directory).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} Of {} Holds Exported Coordinates - Run migrate-assembly First",
+                ASSEMBLY_DIR, assembly
+            )
+        );
+    }
+    let err = bgzf_filter_with(
+        assembly,
+        ASSEMBLY_DIR,
+        "DF000000001",
+        &mut Vec::new(),
+        &FilterOptions::default(),
+        TEST_DATA_DIR,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    let err = iter_contig(
+        assembly,
+        ASSEMBLY_DIR,
+        "chr10",
+        &None,
+        &false,
+        &data_directory,
+    )
+    .err()
+    .expect("Exported Coordinates Iterated");
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+    let err = migrate_assembly(assembly, &Some(1), &None, &data_directory).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // Resume a migration interrupted after the masks index
+    let interrupted = AssemblyMeta {
+        layout_version: 0,
+        migration: Some(MigrationState {
+            from_version: 0,
+            to_version: 1,
+            completed: vec![MASKS_DIR.to_string()],
+        }),
+        ..AssemblyMeta::default()
+    };
+    write_assembly_meta(assembly, &interrupted, &data_directory).unwrap();
+    let report =
+        migrate_assembly(assembly, &Some(0), &None, &data_directory).expect("Migration Failed");
+    assert_eq!(
+        report.migrated,
+        vec![
+            format!("0->1 {}", ASSEMBLY_DIR),
+            format!("1->2 {}", ASSEMBLY_DIR),
+            format!("1->2 {}", MASKS_DIR)
+        ]
+    );
+    assert_eq!(report.skipped, vec![format!("0->1 {}", MASKS_DIR)]);
+    for data_type in [ASSEMBLY_DIR, MASKS_DIR] {
+        assert!(beds_half_open(assembly, data_type, &data_directory));
+        assert!(!Path::new(&format!(
+            "{}/{}/.{}.exported",
+            data_directory, assembly, data_type
+        ))
+        .exists());
+    }
+    assert_eq!(index_format_version(&index_file).unwrap(), FORMAT_VERSION);
+    assert_eq!(check_index(&index_file).unwrap(), FORMAT_VERSION);
+    assert!(!Path::new(&format!("{}.migrating", index_file)).exists());
+    assert_eq!(
+        read_assembly_meta(assembly, &data_directory).unwrap(),
+        AssemblyMeta {
+            layout_version: LAYOUT_VERSION,
+            migration: None,
+            ..AssemblyMeta::default()
+        }
+    );
+    // Hits give the exported coordinates, as from an assembly migrated in one run
+    assert_eq!(
+        query().expect("Index Query Failed"),
+        query_in(test_data_dir()).expect("Index Query Failed")
+    );
+
+    // Already at the newest layout
+    let report = migrate_assembly(assembly, &None, &None, &data_directory).unwrap();
+    assert!(report.migrated.is_empty() && report.skipped.is_empty());
+    let err = migrate_assembly(assembly, &None, &Some(0), &data_directory).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // A truncated index no longer matches its header
+    let truncated = format!("{}/truncated_idx.dat", data_directory);
+    let bytes = std::fs::read(&index_file).unwrap();
+    write(&truncated, &bytes[..bytes.len() - 1]).unwrap();
+    assert_eq!(
+        check_index(&truncated).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+
+    // Layouts newer than this build are refused
+    let newer = AssemblyMeta {
+        layout_version: LAYOUT_VERSION + 1,
+        migration: None,
+        ..AssemblyMeta::default()
+    };
+    write_assembly_meta(assembly, &newer, &data_directory).unwrap();
+    let err = query().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Assembly {} Has Layout Version {}, Newer Than The Supported {} - Upgrade te_idx",
+            assembly,
+            LAYOUT_VERSION + 1,
+            LAYOUT_VERSION
+        )
+    );
+
+    let _ = working_dir.close();
+}
+#[test]
+fn test_check_compatibility() {
+    // The checked in test assembly predates recorded layouts and has a version 0 index
+    let report = check_compatibility(TEST_DATA_DIR).expect("Check Failed");
+    let test_ex = report
+        .iter()
+        .find(|a| a.assembly == TEST_ASSEMBLY)
+        .expect("Test Assembly Not Checked");
+    assert_eq!(test_ex.verdict, Verdict::NeedsMigration);
+    assert_eq!(test_ex.layout_version, Some(0));
+    assert_eq!(test_ex.index_versions[ASSEMBLY_DIR], 0);
+
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    copy_test_data_from(TEST_DATA_DIR, &data_directory, ASSEMBLY_DIR);
+    copy(
+        format!("{}/{}/{}_idx.dat", TEST_DATA_DIR, assembly, ASSEMBLY_DIR),
+        format!("{}/{}/{}_idx.dat", data_directory, assembly, ASSEMBLY_DIR),
+    )
+    .expect("Can't Copy Index");
+    migrate_assembly(assembly, &None, &None, &data_directory).expect("Migration Failed");
+    let verdict = || check_compatibility(&data_directory).expect("Check Failed")[0].verdict;
+    assert_eq!(verdict(), Verdict::Readable);
+
+    write_assembly_meta(
+        assembly,
+        &AssemblyMeta {
+            layout_version: LAYOUT_VERSION + 1,
+            migration: None,
+            ..AssemblyMeta::default()
+        },
+        &data_directory,
+    )
+    .expect("Can't Write Meta");
+    assert_eq!(verdict(), Verdict::NewerVersion);
+    write(
+        format!("{}/{}/assembly_meta.json", data_directory, assembly),
+        "{",
+    )
+    .expect("Can't Write Meta");
+    assert_eq!(verdict(), Verdict::Unreadable);
+}
+
+#[test]
+fn test_index_long_names() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    // Longer than the 40 byte name fields of format version 0
+    let fam = "DF000000001_with_a_file_name_well_over_forty_bytes_long";
+    let chrom = "chrUn_a_contig_name_that_is_also_over_forty_bytes_long";
+    let lines = vec![annotation_line(chrom, 100, 500, fam, "300", "+", "1")];
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines.clone())]);
+    let res = idx_query_with(
+        TEST_ASSEMBLY,
+        ASSEMBLY_DIR,
+        chrom,
+        1,
+        1000,
+        &IdxQueryOptions::default(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    assert_eq!(vals.len(), 1);
+    assert_eq!(vals[0]["sequence"], chrom);
+    assert_eq!(vals[0]["accession"], fam);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_index_file_name_collisions() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    // One name in the 40 byte fields of format version 0
+    let prefix = "DF000000001_a_family_name_sharing_its_fi";
+    assert_eq!(prefix.len(), 40);
+    let fams = [
+        format!("{}rst_forty_bytes", prefix),
+        format!("{}rst_40_bytes", prefix),
+    ];
+    let files: Vec<(&str, Vec<String>)> = fams
+        .iter()
+        .enumerate()
+        .map(|(i, fam)| {
+            let start = 100 + i as u64 * 1000;
+            let lines = vec![annotation_line(
+                "chr1",
+                start,
+                start + 400,
+                fam,
+                "300",
+                "+",
+                "1",
+            )];
+            (fam.as_str(), lines)
+        })
+        .collect();
+    // Stored whole, each range reads its own file
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &files);
+    let index_file = format!("{}/{}/{}_idx.dat", data_directory, assembly, data_type);
+    check_index(&index_file).expect("Index Check Failed");
+    for (i, fam) in fams.iter().enumerate() {
+        let start = 100 + i as u64 * 1000;
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            start,
+            start + 400,
+            &IdxQueryOptions::default(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let vals: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        assert_eq!(vals.len(), 1);
+        assert_eq!(vals[0]["accession"], fam.as_str());
+    }
+
+    // A file listed twice is refused rather than written, the published index is kept
+    let (mut filenames, bgz_dir, mut contig_index, _) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    filenames.push(filenames[0].clone());
+    let err = build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file)
+        .expect_err("Duplicate File Names Indexed");
+    assert!(err.to_string().contains(&filenames[0]));
+    check_index(&index_file).expect("Index Check Failed");
+
+    // A format version 0 index of both files stores them under one name: one contig of one
+    // empty tile, names in 40 byte fields, then each file's modification time and size
+    let name_field = |name: &str| {
+        let mut field = name.as_bytes()[..name.len().min(40)].to_vec();
+        field.resize(40, 0);
+        field
+    };
+    let mut v0 = b"#R_IDX".to_vec();
+    v0.extend(0u16.to_le_bytes());
+    v0.extend(TILE_SIZE.to_le_bytes());
+    v0.extend(1u32.to_le_bytes());
+    v0.extend(2u32.to_le_bytes());
+    v0.extend(1u32.to_le_bytes());
+    v0.extend(0u32.to_le_bytes());
+    v0.extend(name_field("chr1"));
+    for fam in &fams {
+        v0.extend(name_field(&format!("{}.bed.bgz", fam)));
+        v0.extend(0f64.to_le_bytes());
+        v0.extend(0u64.to_le_bytes());
+    }
+    assert_eq!(
+        v0.len() as u64,
+        range_data_offsets(2, &[1], &[vec![0]])[0][0]
+    );
+    let v0_file = format!("{}/v0_idx.dat", data_directory);
+    write(&v0_file, &v0).expect("Can't Write Index");
+    let err = check_index(&v0_file).expect_err("Colliding Names Accepted");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains(prefix));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_package_unpack() {
+    let source_directory = gen_working_dir();
+    let source_data = source_directory.path().to_str().unwrap().to_string();
+    copy_test_data(&source_data, MASKS_DIR);
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(TEST_ASSEMBLY, MASKS_DIR, false, &source_data).expect("Index Prep Failed");
+    // Only deterministic indexes are packaged
+    contig_index.set_deterministic(true);
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let package = format!("{}/test_ex.tar.gz", source_data);
+    let max_buffered_bytes = 64 * 1024;
+    let buffer = package_assembly(
+        assembly,
+        &[MASKS_DIR.to_string()],
+        &package,
+        true,
+        max_buffered_bytes,
+        &source_data,
+    )
+    .expect("Packaging Failed");
+    assert_eq!(buffer.cap_bytes, max_buffered_bytes);
+    assert!(buffer.peak_bytes > 0 && buffer.peak_bytes <= max_buffered_bytes);
+    assert_eq!(
+        buffer.written_bytes,
+        std::fs::metadata(&package).unwrap().len()
+    );
+
+    let target_directory = gen_working_dir();
+    let target_data = target_directory.path().to_str().unwrap().to_string();
+    unpack_assembly(assembly, &package, &target_data).expect("Unpacking Failed");
+    assert_eq!(
+        read_dir(format!("{}/{}/{}", target_data, TEST_ASSEMBLY, MASKS_DIR))
+            .unwrap()
+            .count(),
+        19
+    );
+    assert!(beds_half_open(assembly, MASKS_DIR, &target_data));
+
+    let query = |data_directory: &String| {
+        idx_query_with(
+            assembly,
+            MASKS_DIR,
+            "chr1",
+            30000,
+            31000,
+            &IdxQueryOptions::default(),
+            data_directory,
+        )
+        .expect("Index Query Failed")
+    };
+    let unpacked = query(&target_data);
+    let vals: Vec<Value> = from_str(&unpacked).expect("Cannot Deserialize");
+    assert!(!vals.is_empty());
+    assert_eq!(unpacked, query(&source_data));
+
+    // The unpacked files are newer than when indexed, but hash the same
+    let response = idx_query_with(
+        assembly,
+        MASKS_DIR,
+        "chr1",
+        30000,
+        31000,
+        &IdxQueryOptions::builder().with_warnings(true).build(),
+        &target_data,
+    )
+    .expect("Index Query Failed");
+    let response: Value = from_str(&response).expect("Cannot Deserialize");
+    assert_eq!(response["warnings"], json!([]));
+    // Nothing is left of the folder the files were extracted into
+    assert_eq!(read_dir(&target_data).unwrap().count(), 1);
+
+    let _ = source_directory.close();
+    let _ = target_directory.close();
+}
+
+// Copies a folder, reading links as the files they point at
+fn copy_tree(from: &str, to: &str) {
+    for entry in WalkDir::new(from).into_iter().filter_map(|e| e.ok()) {
+        let target = Path::new(to).join(entry.path().strip_prefix(from).unwrap());
+        if entry.path().is_dir() {
+            create_dir_all(&target).expect("Can't Create Dir");
+        } else {
+            copy(entry.path(), &target).expect("Can't Copy File");
+        }
+    }
+}
+
+#[test]
+fn test_release_delta() {
+    let working_dir = gen_working_dir();
+    let root = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let [old_data, new_data, mirror_data, stale_data] =
+        ["old", "new", "mirror", "stale"].map(|name| format!("{}/{}", root, name));
+    let assembly_dir = |data: &String| format!("{}/{}", data, TEST_ASSEMBLY);
+    let family = |fam: &str, hits: u64| -> Vec<String> {
+        (0..hits)
+            .map(|i| {
+                annotation_line(
+                    "chr1",
+                    1000 + i * 200,
+                    1100 + i * 200,
+                    fam,
+                    "25.5",
+                    "+",
+                    "0",
+                )
+            })
+            .collect()
+    };
+    let build = |data: &String| {
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, false, data).expect("Index Prep Failed");
+        build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    };
+
+    // The previous release, with two families, mirrored as it was
+    copy_test_data(&old_data, MASKS_DIR);
+    copy_test_data(&old_data, MOD_LEN_DIR);
+    build_test_assembly(
+        &old_data,
+        ASSEMBLY_DIR,
+        &[
+            ("DF000000001", family("DF000000001", 5)),
+            ("DF000000002", family("DF000000002", 3)),
+        ],
+    );
+    copy_tree(&assembly_dir(&old_data), &assembly_dir(&new_data));
+    copy_tree(&assembly_dir(&old_data), &assembly_dir(&mirror_data));
+    copy_tree(&assembly_dir(&old_data), &assembly_dir(&stale_data));
+
+    // The new release changes both families, adds one and drops a masks file
+    let bgz_dir = format!("{}/{}", assembly_dir(&new_data), ASSEMBLY_DIR);
+    write_bed_bgz(
+        &format!("{}/DF000000001.bed.bgz", bgz_dir),
+        &family("DF000000001", 7),
+    );
+    write_bed_bgz(
+        &format!("{}/DF000000002.bed.bgz", bgz_dir),
+        &family("DF000000002", 2),
+    );
+    write_bed_bgz(
+        &format!("{}/DF000000003.bed.bgz", bgz_dir),
+        &family("DF000000003", 1),
+    );
+    let masks_file = "masks/chr1.bed.bgz";
+    remove_file(format!("{}/{}", assembly_dir(&new_data), masks_file)).unwrap();
+    build(&new_data);
+
+    let delta = format!("{}/delta", root);
+    let report = release_delta(assembly_dir(&old_data), assembly_dir(&new_data), &delta)
+        .expect("Release Delta Failed");
+    let index_files = [
+        "assembly_alignments_families.idx".to_string(),
+        "assembly_alignments_idx.dat".to_string(),
+    ];
+    let mut changed = vec![
+        format!("{}/DF000000001.bed.bgz", ASSEMBLY_DIR),
+        format!("{}/DF000000002.bed.bgz", ASSEMBLY_DIR),
+    ];
+    changed.extend(index_files.iter().cloned());
+    assert_eq!(report.changed, changed);
+    assert_eq!(
+        report.added,
+        vec![format!("{}/DF000000003.bed.bgz", ASSEMBLY_DIR)]
+    );
+    assert_eq!(report.deleted, vec![masks_file.to_string()]);
+    assert!(report.unchanged > 0);
+    assert_eq!(
+        read_dir(format!("{}/{}/{}", delta, DELTA_FILES_DIR, ASSEMBLY_DIR))
+            .unwrap()
+            .count(),
+        3
+    );
+    let err = release_delta(assembly_dir(&old_data), assembly_dir(&new_data), &delta)
+        .expect_err("Delta Written Over Another");
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    // A mirror that is not the previous release is left as it was
+    let stale_mask = format!("{}/masks/chr10.bed.bgz", assembly_dir(&stale_data));
+    write_bed_bgz(&stale_mask, &["chr10\t1\t100\tA\t1".to_string()]);
+    let err = apply_delta(assembly_dir(&stale_data), &delta).expect_err("Stale Mirror Updated");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("masks/chr10.bed.bgz Differs"));
+    let stale_family = format!(
+        "{}/{}/DF000000001.bed.bgz",
+        assembly_dir(&stale_data),
+        ASSEMBLY_DIR
+    );
+    let old_family = format!(
+        "{}/{}/DF000000001.bed.bgz",
+        assembly_dir(&old_data),
+        ASSEMBLY_DIR
+    );
+    assert_eq!(
+        std::fs::read(stale_family).unwrap(),
+        std::fs::read(old_family).unwrap()
+    );
+
+    // The mirror becomes the new release byte for byte, its index swapped to
+    let applied = apply_delta(assembly_dir(&mirror_data), &delta).expect("Applying Delta Failed");
+    assert_eq!(applied, report);
+    let new_manifest = assembly_manifest(&assembly_dir(&new_data)).unwrap();
+    assert_eq!(
+        assembly_manifest(&assembly_dir(&mirror_data)).unwrap(),
+        new_manifest
+    );
+    for rel in new_manifest.keys() {
+        let read =
+            |data: &String| std::fs::read(format!("{}/{}", assembly_dir(data), rel)).unwrap();
+        assert_eq!(read(&mirror_data), read(&new_data), "{}", rel);
+    }
+    assert!(read_link(format!("{}/{}", assembly_dir(&mirror_data), index_files[1])).is_ok());
+    let query = |data: &String| {
+        idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            5000,
+            &IdxQueryOptions::builder().with_warnings(true).build(),
+            data,
+        )
+        .expect("Index Query Failed")
+    };
+    // Modification times came with the files, so the index finds none modified
+    assert_eq!(query(&mirror_data), query(&new_data));
+
+    let _ = working_dir.close();
+}
+
+// A destination that takes a millisecond per write, such as a congested NFS mount
+struct SlowWriter {
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for SlowWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::thread::sleep(Duration::from_millis(1));
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_bounded_writer_caps_buffered_bytes() {
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let cap = 4096;
+    let mut writer = BoundedWriter::new(
+        SlowWriter {
+            written: Arc::clone(&written),
+        },
+        cap,
+    );
+    let mut expected = Vec::new();
+    for i in 0..2000 {
+        let line = annotation_line(
+            "chr1",
+            i * 10 + 1,
+            i * 10 + 10,
+            "DF000000001",
+            "10",
+            "+",
+            "1",
+        );
+        expected.extend_from_slice(line.as_bytes());
+        writer.write_all(line.as_bytes()).unwrap();
+        assert!(writer.stats().peak_bytes <= cap);
+    }
+    let (_, stats) = writer.finish().expect("Writing Failed");
+    assert_eq!(stats.cap_bytes, cap);
+    assert!(stats.peak_bytes > 0 && stats.peak_bytes <= cap);
+    assert!(stats.stalls > 0);
+    assert_eq!(stats.written_bytes, expected.len() as u64);
+    assert_eq!(*written.lock().unwrap(), expected);
+}
+
+#[test]
+fn test_bounded_writer_reports_destination_error() {
+    struct FailingWriter;
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(Error::new(ErrorKind::PermissionDenied, "Read Only"))
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut writer = BoundedWriter::new(FailingWriter, 1024);
+    let mut result = Ok(());
+    for _ in 0..100 {
+        result = writer.write_all(&[b'x'; 512]);
+        if result.is_err() {
+            break;
+        }
+    }
+    let error = result
+        .and_then(|_| writer.finish().map(|_| ()))
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_prep_beds() {
+    let working_directory = gen_working_dir();
+
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let in_tsv = format!("{}/{}/test_ex-mask.tsv", TEST_EXPORT_DIR, TEST_ASSEMBLY);
+    let data_type = &MASKS_DIR.to_string();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+
+    match prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::default(),
+        &data_directory,
+    ) {
+        Ok(_) => {
+            let mask_dir = format!("{}/{}/{}", data_directory, &TEST_ASSEMBLY, &data_type);
+            // check that new folder was created and contains expected number of files
+            assert!(Path::new(&mask_dir).exists());
+            assert_eq!(
+                19,
+                read_dir(&mask_dir)
+                    .map(|entries| entries
+                        .filter_map(|entry| entry.ok())
+                        .filter(|entry| entry.file_type().is_ok_and(|ft| ft.is_file()))
+                        .count())
+                    .unwrap_or(0)
+            );
+        }
+        Err(_e) => {
+            let _ = working_directory.close();
+            panic!()
+        }
+    }
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_prep_beds_empty_fields() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let fam = "DF000000001";
+    // Export column order, the first record has no family name and no kimura divergence
+    let in_tsv = format!("{}/empty-full_region.tsv", data_directory);
+    write(
+        &in_tsv,
+        format!(
+            "chr1\t{fam}\t\t300\t1e-30\t0.0\t1\t100\t+\t1000\t1100\t1000\t1100\t1000000\t100M\t\t1\tCAF\n\
+             chr1\t{fam}\tMIR\t250\t1e-20\t0.0\t1\t100\t+\t2000\t2100\t2000\t2100\t1000000\t100M\t10.5\t0\tCAF\n"
+        ),
+    )
+    .expect("Can't Write TSV");
+    prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::default(),
+        &data_directory,
+    )
+    .expect("BED Prep Failed");
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    // The empty fields keep their columns, so the NRPH flag is read from the right one
+    let query = |nrph: bool, with_warnings: bool| -> Value {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            5000,
+            &IdxQueryOptions::builder()
+                .nrph(nrph)
+                .with_warnings(with_warnings)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+    assert_eq!(query(false, false).as_array().unwrap().len(), 2);
+    let nrph = query(true, false);
+    assert_eq!(nrph.as_array().unwrap().len(), 1);
+    assert_eq!(nrph[0]["seq_start"], 1000);
+    assert_eq!(query(false, true)["warnings"], json!([]));
+
+    let mut output = Vec::new();
+    bgzf_filter_with(
+        assembly,
+        data_type,
+        fam,
+        &mut output,
+        &FilterOptions::builder()
+            .term(13, "1")
+            .profile(INTERNAL_PROFILE)
+            .build(),
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    let lines: Vec<String> = output.lines().map(|l| l.unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    let fields: Vec<&str> = lines[1].split('\t').collect();
+    assert_eq!(fields.len(), 18);
+    assert_eq!((fields[12], fields[13], fields[14]), ("1", "", ""));
+    assert_eq!(fields[17], "CAF");
+
+    // Records prepared by splitting on whitespace lost the empty fields
+    let collapsed = "chr1\t3000\t3100\tDF000000002\t300\t+\t0.0\t3000\t3100\t1\t100\t1e-30\t1\t1000000\t100M\tCAF";
+    assert_eq!(
+        misaligned_columns(&collapsed.split('\t').collect::<Vec<&str>>(), ASSEMBLY_DIR),
+        Some("Expected 18 Columns, Found 16".to_string())
+    );
+    let shifted = annotation_line("chr1", 3000, 3100, "DF000000002", "300", "+", "90M");
+    assert_eq!(
+        misaligned_columns(&shifted.split('\t').collect::<Vec<&str>>(), ASSEMBLY_DIR),
+        Some("NRPH Column Holds \"90M\"".to_string())
+    );
+    assert_eq!(
+        misaligned_columns(&lines[1].split('\t').collect::<Vec<&str>>(), ASSEMBLY_DIR),
+        None
+    );
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[("DF000000002", vec![collapsed.to_string()])],
+    );
+    let warnings = query(false, true)["warnings"].clone();
+    assert_eq!(warnings.as_array().unwrap().len(), 1);
+    assert!(warnings[0]
+        .as_str()
+        .unwrap()
+        .starts_with("1 assembly_alignments Records Of test_ex Have Misaligned Columns (Expected 18 Columns, Found 16)"));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_legacy_layout() {
+    let working_dir = gen_working_dir();
+    let data_directory = working_dir.path().to_str().unwrap().to_string();
+    let (assembly, data_type, fam) = (
+        &TEST_ASSEMBLY.to_string(),
+        &ASSEMBLY_DIR.to_string(),
+        "DF000000002",
+    );
+    // Prepared before the cigar and caf columns, 16 columns
+    let pre_caf = |start: u64, nrph: &str| -> String {
+        let line = annotation_line("chr1", start, start + 100, fam, "300", "+", nrph);
+        line.split('\t').take(16).collect::<Vec<&str>>().join("\t")
+    };
+    let lines = vec![pre_caf(1000, "1"), pre_caf(2000, "0")];
+    let fields: Vec<&str> = lines[0].split('\t').collect();
+    assert_eq!(
+        detect_layout(&fields, ASSEMBLY_DIR).unwrap().name,
+        "pre-caf"
+    );
+    assert!(detect_layout(&fields, BENCHMARK_DIR).is_none());
+    // Same count, but the strand column holds a score, so misaligned rather than old
+    let collapsed = "chr1\t3000\t3100\tDF000000002\t300\t+\t0.0\t3000\t3100\t1\t100\t1e-30\t1\t1000000\t100M\tCAF";
+    assert!(detect_layout(&collapsed.split('\t').collect::<Vec<&str>>(), ASSEMBLY_DIR).is_none());
+    build_test_assembly(&data_directory, ASSEMBLY_DIR, &[(fam, lines)]);
+    let bgz_file = bgz_path(
+        join_path(&data_directory, &[TEST_ASSEMBLY, ASSEMBLY_DIR]),
+        fam,
+    );
+    assert_eq!(
+        file_layout(&bgz_file, ASSEMBLY_DIR).unwrap(),
+        Some("pre-caf")
+    );
+
+    let query = |nrph: bool| -> Value {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            5000,
+            &IdxQueryOptions::builder()
+                .nrph(nrph)
+                .with_warnings(true)
+                .build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+    let filter = || -> Vec<String> {
+        let mut output = Vec::new();
+        bgzf_filter_with(
+            assembly,
+            data_type,
+            fam,
+            &mut output,
+            &FilterOptions::builder().profile(INTERNAL_PROFILE).build(),
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        output.lines().map(|l| l.unwrap()).collect()
+    };
+
+    // Read with the missing columns empty, and warned about
+    let res = query(false);
+    assert_eq!(res["hits"].as_array().unwrap().len(), 2);
+    assert_eq!(res["warnings"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        res["warnings"][0],
+        "2 assembly_alignments Records Of test_ex Are In The pre-caf Layout, Their Missing Columns Are Read As Empty - Run repair-layout For Them"
+    );
+    let res = query(true);
+    assert_eq!(res["hits"].as_array().unwrap().len(), 1);
+    assert_eq!(res["hits"][0]["seq_start"], 1000);
+    let lines = filter();
+    assert_eq!(lines.len(), 3);
+    let fields: Vec<&str> = lines[1].split('\t').collect();
+    assert_eq!(fields.len(), 18);
+    assert_eq!((fields[15], fields[16], fields[17]), ("1000000", "", ""));
+
+    let report = repair_layout(assembly, data_type, &data_directory).expect("Repair Failed");
+    assert_eq!(report.files, 1);
+    assert_eq!(
+        report
+            .repaired
+            .get(&format!("{}.bed.bgz", fam))
+            .map(String::as_str),
+        Some("pre-caf")
+    );
+    assert_eq!(report.records, 2);
+    assert!(report.indexed);
+    assert_eq!(
+        file_layout(&bgz_file, ASSEMBLY_DIR).unwrap(),
+        Some(CURRENT_LAYOUT)
+    );
+    assert_eq!(filter(), lines);
+    let res = query(false);
+    assert_eq!(res["hits"].as_array().unwrap().len(), 2);
+    assert_eq!(res["warnings"], json!([]));
+
+    // Nothing left to repair
+    let report = repair_layout(assembly, data_type, &data_directory).expect("Repair Failed");
+    assert!(report.repaired.is_empty());
+    assert!(!report.indexed);
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_prep_beds_sharded() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let in_tsv = format!("{}/many-mask.tsv", data_directory);
+    let accs: Vec<String> = (0..50).map(|i| format!("scaffold_{}", i)).collect();
+    let lines: Vec<String> = accs
+        .iter()
+        .flat_map(|acc| {
+            vec![
+                format!("{}	100	150	TC	2", acc),
+                format!("{}	20000	20040	AAAT	4", acc),
+            ]
+        })
+        .collect();
+    write(&in_tsv, format!("{}\n", lines.join("\n"))).expect("Can't Write TSV");
+
+    // The first files are written flat and moved once the threshold is passed
+    prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::builder().shard_threshold(10).build(),
+        &data_directory,
+    )
+    .expect("BED Prep Failed");
+    let mask_dir = join_path(&data_directory, &[TEST_ASSEMBLY, MASKS_DIR]);
+    let entries: Vec<_> = read_dir(&mask_dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .collect();
+    assert!(entries.iter().all(|e| e.path().is_dir()));
+    for acc in &accs {
+        let path = bgz_path(&mask_dir, acc);
+        assert!(Path::new(&path).exists());
+        assert!(!Path::new(&path).ends_with(Path::new(MASKS_DIR).join(format!("{}.bed.bgz", acc))));
+    }
+
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    assert_eq!(filenames.len(), accs.len());
+    // Stored with "/" whatever the platform, and found on disk through stored_path
+    assert!(filenames.iter().all(|f| f.contains('/')));
+    assert!(filenames
+        .iter()
+        .all(|f| Path::new(&stored_path(&bgz_dir, f)).exists()));
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    for acc in [&accs[0], &accs[49]] {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            acc,
+            1,
+            30000,
+            &IdxQueryOptions::builder().with_warnings(true).build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let res: Value = from_str(&res).expect("Cannot Deserialize");
+        assert_eq!(res["hits"].as_array().unwrap().len(), 2);
+        assert_eq!(res["warnings"], Value::Array(vec![]));
+    }
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_path_helpers() {
+    let base = Path::new("data").join("nested");
+    let base = base.to_str().unwrap();
+    assert_eq!(
+        join_path(base, &[TEST_ASSEMBLY, MASKS_DIR]),
+        Path::new(base)
+            .join(TEST_ASSEMBLY)
+            .join(MASKS_DIR)
+            .to_string_lossy()
+    );
+    assert_eq!(join_path(base, &[]), base);
+
+    // Names stored in an index read the same with either separator
+    let expected = Path::new(base).join("ab").join("DF000000001.bed.bgz");
+    for name in [
+        "ab/DF000000001.bed.bgz",
+        "ab\\DF000000001.bed.bgz",
+        "ab//DF000000001.bed.bgz",
+    ] {
+        assert_eq!(Path::new(&stored_path(base, name)), expected);
+    }
+    assert_eq!(
+        Path::new(&stored_path(base, "DF000000001.bed.bgz")),
+        Path::new(base).join("DF000000001.bed.bgz")
+    );
+}
+
+#[test]
+fn test_prep_beds_scratch_dir() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let scratch_directory = format!("{}/scratch", data_directory);
+    create_dir_all(&scratch_directory).expect("Can't Create Scratch Dir");
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let scratch_files = || -> Vec<String> {
+        read_dir(&scratch_directory)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .collect()
+    };
+
+    // A pipe holds prep_beds mid-run, while it is writing the first accession
+    let in_tsv = format!("{}/pipe-mask.tsv", data_directory);
+    let status = Command::new("mkfifo")
+        .arg(&in_tsv)
+        .status()
+        .expect("Can't Run mkfifo");
+    assert!(status.success());
+    let prep = {
+        let (assembly, in_tsv, data_type, data_directory, scratch_directory) = (
+            assembly.clone(),
+            in_tsv.clone(),
+            data_type.clone(),
+            data_directory.clone(),
+            scratch_directory.clone(),
+        );
+        std::thread::spawn(move || {
+            prep_beds_with(
+                &assembly,
+                &in_tsv,
+                &data_type,
+                &PrepOptions::builder()
+                    .scratch_directory(scratch_directory)
+                    .build(),
+                &data_directory,
+            )
+        })
+    };
+    let mut pipe = File::create(&in_tsv).expect("Can't Open Pipe");
+    writeln!(pipe, "chr1\t100\t150\tTC\t2").unwrap();
+    pipe.flush().unwrap();
+    let started = Instant::now();
+    while scratch_files().is_empty() && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let during = scratch_files();
+    assert_eq!(during.len(), 1);
+    assert!(during[0].starts_with(".te_idx-") && during[0].ends_with(".tmp"));
+
+    writeln!(pipe, "chr1\t300\t350\tAAAT\t4").unwrap();
+    writeln!(pipe, "chr2\t100\t150\tTC\t2").unwrap();
+    drop(pipe);
+    let report = prep.join().unwrap().expect("BED Prep Failed");
+    assert_eq!(report.records, 3);
+    assert!(scratch_files().is_empty());
+    let mask_dir = format!("{}/{}/{}", data_directory, TEST_ASSEMBLY, MASKS_DIR);
+    for acc in ["chr1", "chr2"] {
+        assert!(Path::new(&bgz_path(&mask_dir, acc)).exists());
+    }
+    // Nothing else was left next to the finished files
+    assert_eq!(read_dir(&mask_dir).unwrap().count(), 2);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_check_scratch_dir() {
+    let working_directory = gen_working_dir();
+    let scratch_directory = working_directory.path().to_str().unwrap().to_string();
+    assert!(check_scratch_dir(&scratch_directory, 0).is_ok());
+    let err = check_scratch_dir(format!("{}/missing", scratch_directory), 0).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let err = check_scratch_dir(&scratch_directory, u64::MAX >> 20).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    assert!(err.to_string().contains("--scratch-dir"));
+    let _ = working_directory.close();
+}
+
+// Holds <bgz file>.lock as a running append or compaction does, until dropped
+fn hold_bgz_lock(bgz_path: &str) -> File {
+    let lock = File::create(format!("{}.lock", bgz_path)).expect("Can't Create Lock");
+    assert_eq!(
+        unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) },
+        0
+    );
+    lock
+}
+
+#[test]
+fn test_append_records() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let fam = &"DF000000001".to_string();
+    let old_lines: Vec<String> = (0..20)
+        .map(|i| {
+            annotation_line(
+                "chr1",
+                100 + i * 1000,
+                600 + i * 1000,
+                fam,
+                "25.5",
+                "+",
+                "1",
+            )
+        })
+        .collect();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            ("DF000000001", old_lines.clone()),
+            (
+                "DF000000002",
+                vec![annotation_line(
+                    "chr1",
+                    150,
+                    250,
+                    "DF000000002",
+                    "30.0",
+                    "-",
+                    "0",
+                )],
+            ),
+        ],
+    );
+    let bgz_path = format!(
+        "{}/{}/{}/{}.bed.bgz",
+        data_directory, assembly, data_type, fam
+    );
+    let query = || {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            1000000,
+            &IdxQueryOptions::builder().family(fam).build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        let hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+        hits.iter()
+            .map(|h| h["seq_start"].as_u64().unwrap())
+            .collect::<Vec<u64>>()
+    };
+    let file_lines = || {
+        BufReader::new(bgzf::Reader::new(File::open(&bgz_path).unwrap()))
+            .lines()
+            .map(|l| l.unwrap())
+            .collect::<Vec<String>>()
+    };
+
+    // Export column order: seq fam name bit evalue bias mstart mend strand ali_s ali_e
+    // seq_s seq_e seq_len cigar div nrph caf
+    let export_line = |fam: &str, start: u64| {
+        format!(
+            "chr1\t{fam}\tTEST\t40.0\t1e-12\t1.0\t1\t100\t+\t{start}\t{end}\t{start}\t{end}\t1000000\t100M\t10.5\t1\tCAF",
+            end = start + 99
+        )
+    };
+    let records_tsv = format!("{}/new_records.tsv", data_directory);
+    let mut tsv = String::from("# Curated additions\n");
+    for i in 0..10 {
+        tsv.push_str(&format!("{}\n", export_line(fam, 50000 + i * 1000)));
+    }
+    write(&records_tsv, tsv).expect("Can't Write File");
+
+    let appended = append_records(
+        assembly,
+        data_type,
+        fam,
+        &records_tsv,
+        false,
+        &data_directory,
+    )
+    .expect("Append Failed");
+    assert_eq!(appended, 10);
+    let lines = file_lines();
+    assert_eq!(lines.len(), 30);
+    assert_eq!(lines[..20], old_lines[..]);
+    // Written with BED coordinates, seq_start 50000 is chromStart 49999
+    assert!(lines[20].starts_with("chr1\t49999\t50099\tDF000000001\t40.0"));
+    // The old blocks are kept, so the existing index still finds the old records
+    let old_starts: Vec<u64> = (0..20).map(|i| 100 + i * 1000).collect();
+    assert_eq!(query(), old_starts);
+
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+    let mut all_starts = old_starts.clone();
+    all_starts.extend((0..10).map(|i| 50000 + i * 1000));
+    assert_eq!(query(), all_starts);
+
+    // Records for another family are refused and the file is left alone
+    write(&records_tsv, export_line("DF000000002", 70000)).expect("Can't Write File");
+    let err = append_records(
+        assembly,
+        data_type,
+        fam,
+        &records_tsv,
+        true,
+        &data_directory,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(file_lines().len(), 30);
+
+    // A held lock blocks a second append
+    write(&records_tsv, export_line(fam, 70000)).expect("Can't Write File");
+    let lock_path = format!("{}.lock", bgz_path);
+    let held = hold_bgz_lock(&bgz_path);
+    let err = append_records(
+        assembly,
+        data_type,
+        fam,
+        &records_tsv,
+        true,
+        &data_directory,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    // The lock file its holder left behind on dying is taken over
+    drop(held);
+    assert!(Path::new(&lock_path).exists());
+
+    append_records(
+        assembly,
+        data_type,
+        fam,
+        &records_tsv,
+        true,
+        &data_directory,
+    )
+    .expect("Append Failed");
+    assert!(!Path::new(&lock_path).exists());
+    all_starts.push(70000);
+    assert_eq!(query(), all_starts);
+
+    // Files of exported coordinates are refused until migrated, rather than mixing the two
+    remove_file(half_open_file(assembly, data_type, &data_directory)).expect("Can't Remove");
+    write(&records_tsv, export_line(fam, 80000)).expect("Can't Write File");
+    let err = append_records(
+        assembly,
+        data_type,
+        fam,
+        &records_tsv,
+        false,
+        &data_directory,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(file_lines().len(), 31);
+}
+
+#[test]
+fn test_compact_family() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let fam = &"DF000000001".to_string();
+    let other = "DF000000002";
+    let old_lines: Vec<String> = (0..20)
+        .map(|i| {
+            annotation_line(
+                "chr1",
+                100 + i * 1000,
+                600 + i * 1000,
+                fam,
+                "25.5",
+                "+",
+                "1",
+            )
+        })
+        .collect();
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (fam, old_lines),
+            (
+                other,
+                vec![annotation_line("chr1", 150, 250, other, "30.0", "-", "0")],
+            ),
+        ],
+    );
+    let bgz_path = format!(
+        "{}/{}/{}/{}.bed.bgz",
+        data_directory, assembly, data_type, fam
+    );
+    let file_lines = || {
+        BufReader::new(bgzf::Reader::new(File::open(&bgz_path).unwrap()))
+            .lines()
+            .map(|l| l.unwrap())
+            .collect::<Vec<String>>()
+    };
+    let positions = |lines: &[String]| {
+        lines
+            .iter()
+            .map(|line| {
+                let fields = bed_fields(line);
+                (fields[0].to_string(), fields[1].parse::<u64>().unwrap())
+            })
+            .collect::<Vec<(String, u64)>>()
+    };
+
+    // Two batches of records between and before the old ones, each appended in its own blocks
+    let records_tsv = format!("{}/new_records.tsv", data_directory);
+    for batch in [[9500, 2500, 500], [15500, 50, 7500]] {
+        let tsv: String = batch
+            .iter()
+            .map(|start| {
+                format!(
+                    "chr1\t{fam}\tTEST\t40.0\t1e-12\t1.0\t1\t100\t+\t{start}\t{end}\t{start}\t{end}\t1000000\t100M\t10.5\t1\tCAF\n",
+                    end = start + 99
+                )
+            })
+            .collect();
+        write(&records_tsv, tsv).expect("Can't Write File");
+        append_records(
+            assembly,
+            data_type,
+            fam,
+            &records_tsv,
+            false,
+            &data_directory,
+        )
+        .expect("Append Failed");
+    }
+    let before = file_lines();
+    assert_eq!(before.len(), 26);
+    assert!(!beds_sorted(assembly, data_type, &data_directory));
+
+    // A held lock blocks compaction as it does appends
+    let lock_path = format!("{}.lock", bgz_path);
+    let held = hold_bgz_lock(&bgz_path);
+    let err = compact_family(
+        assembly,
+        data_type,
+        &Some(fam.clone()),
+        &data_directory,
+        &None,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    drop(held);
+
+    let report = compact_family(
+        assembly,
+        data_type,
+        &Some(fam.clone()),
+        &data_directory,
+        &None,
+    )
+    .expect("Compaction Failed");
+    assert!(report.indexed);
+    assert_eq!(report.files.len(), 1);
+    let compacted = &report.files[0];
+    assert_eq!(compacted.key, *fam);
+    assert_eq!(compacted.records, 26);
+    // 9500, 2500 and 500 each fall back, 15500 follows 500, then 50 falls back
+    assert_eq!(compacted.out_of_order, 4);
+    assert_eq!(
+        compacted.bytes_after,
+        std::fs::metadata(&bgz_path).unwrap().len()
+    );
+    assert!(!Path::new(&lock_path).exists());
+
+    // The same records, in position order
+    let after = file_lines();
+    let after_positions = positions(&after);
+    let mut sorted_positions = after_positions.clone();
+    sorted_positions.sort();
+    assert_eq!(after_positions, sorted_positions);
+    let mut before_sorted = before.clone();
+    before_sorted.sort();
+    let mut after_sorted = after.clone();
+    after_sorted.sort();
+    assert_eq!(before_sorted, after_sorted);
+
+    // The rebuilt index finds every record, the appended ones included
+    let res = idx_query_with(
+        assembly,
+        data_type,
+        "chr1",
+        1,
+        1000000,
+        &IdxQueryOptions::builder().family(fam).build(),
+        &data_directory,
+    )
+    .expect("Index Query Failed");
+    let hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+    let mut starts: Vec<u64> = hits
+        .iter()
+        .map(|h| h["seq_start"].as_u64().unwrap())
+        .collect();
+    starts.sort();
+    // Hits give the exported seq_start, one past the chromStart of the file
+    let expected: Vec<u64> = sorted_positions
+        .iter()
+        .map(|(_, start)| start + 1)
+        .collect();
+    assert_eq!(starts, expected);
+    // Other files are left alone until all are compacted, which marks the data type sorted
+    assert!(!beds_sorted(assembly, data_type, &data_directory));
+    let report = compact_family(assembly, data_type, &None, &data_directory, &None)
+        .expect("Compaction Failed");
+    assert_eq!(report.files.len(), 2);
+    assert!(report.files.iter().all(|f| f.out_of_order == 0));
+    assert_eq!(file_lines(), after);
+    assert!(beds_sorted(assembly, data_type, &data_directory));
+
+    let err = compact_family(
+        assembly,
+        data_type,
+        &Some("DF000000009".to_string()),
+        &data_directory,
+        &None,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn test_prep_beds_dedupe() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let in_tsv = format!("{}/duplicated-mask.tsv", data_directory);
+    let source = read_to_string(format!(
+        "{}/{}/test_ex-mask.tsv",
+        TEST_EXPORT_DIR, TEST_ASSEMBLY
+    ))
+    .expect("Can't Read File");
+    // 300 chr1 records followed by chr10, with two adjacent and two non-adjacent duplicates
+    let mut lines: Vec<&str> = source.lines().take(300).collect();
+    lines.insert(11, lines[10]);
+    lines.insert(12, lines[10]);
+    lines.insert(200, lines[50]);
+    lines.push(lines[0]);
+    let chr10: Vec<&str> = source
+        .lines()
+        .filter(|l| l.starts_with("chr10\t"))
+        .collect();
+    lines.extend(&chr10);
+    write(&in_tsv, format!("{}\n", lines.join("\n"))).expect("Can't Write File");
+
+    let count_records = |data_directory: &String, chrom: &str| {
+        BufReader::new(bgzf::Reader::new(
+            File::open(format!(
+                "{}/{}/{}/{}.bed.bgz",
+                data_directory, TEST_ASSEMBLY, MASKS_DIR, chrom
+            ))
+            .expect("Can't Open File"),
+        ))
+        .lines()
+        .count()
+    };
+
+    let report = prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::builder().dedupe(true).build(),
+        &data_directory,
+    )
+    .expect("BED Prep Failed");
+    assert_eq!(report.duplicate_count(), 4);
+    assert_eq!(report.duplicates.get("chr1"), Some(&4));
+    assert_eq!(report.duplicates.get("chr10"), None);
+    assert_eq!(report.records, 300 + chr10.len());
+    assert_eq!(count_records(&data_directory, "chr1"), 300);
+    assert_eq!(count_records(&data_directory, "chr10"), chr10.len());
+
+    // Off by default, duplicates are kept
+    let report = prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::default(),
+        &data_directory,
+    )
+    .expect("BED Prep Failed");
+    assert_eq!(report.duplicate_count(), 0);
+    assert_eq!(count_records(&data_directory, "chr1"), 304);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_prep_beds_sorted() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &ASSEMBLY_DIR.to_string();
+    let source = read_to_string(format!(
+        "{}/{}/{}{}",
+        TEST_EXPORT_DIR, TEST_ASSEMBLY, TEST_ASSEMBLY, ASSEMBLY_FILE
+    ))
+    .expect("Can't Read File");
+    let records: Vec<&str> = source
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .step_by(50)
+        .collect();
+    // Shuffled within the family, the export stays in accession order
+    let mut shuffled = records.clone();
+    let mut state: u64 = 42;
+    for i in (1..shuffled.len()).rev() {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        shuffled.swap(i, (state >> 33) as usize % (i + 1));
+    }
+    assert_ne!(shuffled, records);
+
+    let prepped = |name: &str, lines: &[&str], options: &PrepOptions| -> String {
+        let data_directory = format!("{}/{}", working_path, name);
+        copy_test_data(&data_directory, SEQUENCE_DIR);
+        let in_tsv = format!("{}/{}.tsv", working_path, name);
+        write(&in_tsv, format!("{}\n", lines.join("\n"))).expect("Can't Write File");
+        let report = prep_beds_with(assembly, &in_tsv, data_type, options, &data_directory)
+            .expect("BED Prep Failed");
+        assert_eq!(report.sorted, options.sort);
+        assert_eq!(
+            beds_sorted(assembly, data_type, &data_directory),
+            options.sort
+        );
+        let (filenames, bgz_dir, mut contig_index, index_file) =
+            prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+        build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+        data_directory
+    };
+    // A small buffer, so the family is sorted through merged scratch runs
+    let sorted = prepped(
+        "sorted",
+        &shuffled,
+        &PrepOptions::builder().sort_buffer_bytes(16 * 1024).build(),
+    );
+    let unsorted = prepped(
+        "unsorted",
+        &records,
+        &PrepOptions::builder().sort(false).build(),
+    );
+
+    // Files are sorted in the assembly's contig order
+    let ranks = contig_order(assembly, &sorted)
+        .expect("Contig Order Failed")
+        .ranks();
+    let bed_file = |data_directory: &String| {
+        format!(
+            "{}/{}/{}/DF000000001.bed.bgz",
+            data_directory, TEST_ASSEMBLY, ASSEMBLY_DIR
+        )
+    };
+    let lines: Vec<String> = BufReader::new(bgzf::Reader::new(
+        File::open(bed_file(&sorted)).expect("Can't Open File"),
+    ))
+    .lines()
+    .map(|l| l.unwrap())
+    .collect();
+    assert_eq!(lines.len(), records.len());
+    let keys: Vec<(usize, u64, u64)> = lines
+        .iter()
+        .map(|line| {
+            let fields = bed_fields(line);
+            let (start, end) = line_extent(line);
+            (ranks[fields[0]], start, end)
+        })
+        .collect();
+    assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert!(lines.iter().any(|l| bed_fields(l)[5] == "-"));
+
+    // The same hits are found either way
+    for (chrom, start, end) in [
+        ("chr10", 1, 50000000),
+        ("chr11", 10000000, 90000000),
+        ("chr12", 1, 100000000),
+    ] {
+        let query = |data_directory: &String| -> Vec<Value> {
+            let res = idx_query_with(
+                assembly,
+                data_type,
+                chrom,
+                start,
+                end,
+                &IdxQueryOptions::default(),
+                data_directory,
+            )
+            .expect("Index Query Failed");
+            let mut hits: Vec<Value> = from_str(&res).expect("Cannot Deserialize");
+            hits.sort_by_key(|hit| hit.to_string());
+            hits
+        };
+        let hits = query(&sorted);
+        assert!(!hits.is_empty());
+        assert_eq!(hits, query(&unsorted));
+    }
+
+    // Appended records are not in position order, the mark goes
+    let extra = format!("{}/extra.tsv", working_path);
+    write(&extra, format!("{}\n", records[0])).expect("Can't Write File");
+    append_records(assembly, data_type, "DF000000001", &extra, false, &sorted)
+        .expect("Append Failed");
+    assert!(!beds_sorted(assembly, data_type, &sorted));
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_prep_beds_source_header() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+    let in_tsv = format!("{}/commented-mask.tsv", data_directory);
+    let source = read_to_string(format!(
+        "{}/{}/test_ex-mask.tsv",
+        TEST_EXPORT_DIR, TEST_ASSEMBLY
+    ))
+    .expect("Can't Read File");
+    let body: Vec<&str> = source.lines().take(500).collect();
+    write(
+        &in_tsv,
+        format!(
+            "#\n# buildMask\n# Dfam 3.8, generated 2024-01-01\n{}\n",
+            body.join("\n")
+        ),
+    )
+    .expect("Can't Write File");
+
+    prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::default(),
+        &data_directory,
+    )
+    .expect("BED Prep Failed");
+    let captured = source_info(assembly, data_type, &data_directory).expect("No Source Info");
+    assert_eq!(
+        captured,
+        vec!["#", "# buildMask", "# Dfam 3.8, generated 2024-01-01"]
+    );
+
+    let mut output = Vec::new();
+    bgzf_filter_with(
+        assembly,
+        data_type,
+        "chr1",
+        &mut output,
+        &FilterOptions::builder().source_meta(true).build(),
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    let mut lines = output.lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "##buildMask");
+    assert_eq!(
+        lines.next().unwrap().unwrap(),
+        "##Dfam 3.8, generated 2024-01-01"
+    );
+    assert!(lines.next().unwrap().unwrap().starts_with("#seq_id"));
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_regenerate_export() {
+    let working_directory = gen_working_dir();
+    let working_path = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &"synth".to_string();
+    let (_, data_directory) =
+        prepare_synthetic_assembly(assembly, &working_path).expect("Synthetic Prep Failed");
+
+    // Comment lines included, each regenerated export matches the one it was prepared from
+    for (data_type, export_file) in [(ASSEMBLY_DIR, ASSEMBLY_FILE), (MASKS_DIR, MASKS_FILE)] {
+        let original = read_to_string(format!(
+            "{}/exports/{}/{}{}",
+            working_path, assembly, assembly, export_file
+        ))
+        .expect("Can't Read Export");
+        let out_tsv = format!("{}/{}.tsv", working_path, data_type);
+        let count = regenerate_export(assembly, data_type, &out_tsv, false, &data_directory)
+            .expect("Regenerating Export Failed");
+        let regenerated = read_to_string(&out_tsv).expect("Can't Read Regenerated Export");
+        assert_eq!(regenerated, original);
+        assert_eq!(
+            count,
+            original.lines().filter(|l| !l.starts_with('#')).count()
+        );
+    }
+
+    // Preparing the regenerated export again, sharded this time, and regenerating it
+    // compressed gives the same records back
+    let in_tsv = format!("{}/{}.tsv", working_path, ASSEMBLY_DIR);
+    let resharded_directory = format!("{}/resharded", working_path);
+    create_dir_all(&resharded_directory).expect("Can't Create Dir");
+    prep_beds_with(
+        assembly,
+        &in_tsv,
+        ASSEMBLY_DIR,
+        &PrepOptions::builder().sharded().build(),
+        &resharded_directory,
+    )
+    .expect("BED Prep Failed");
+    let out_gz = format!("{}/{}.tsv.gz", working_path, ASSEMBLY_DIR);
+    regenerate_export(assembly, ASSEMBLY_DIR, &out_gz, true, &resharded_directory)
+        .expect("Regenerating Export Failed");
+    let decompressed: Vec<String> = bgzf::Reader::new(File::open(&out_gz).expect("Can't Open"))
+        .lines()
+        .map(|l| l.expect("Can't Read Line"))
+        .collect();
+    let original: Vec<String> = read_to_string(&in_tsv)
+        .expect("Can't Read File")
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+    assert_eq!(decompressed, original);
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_masks_repeat_class() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_type = &MASKS_DIR.to_string();
+
+    // Masks exported before and after the period and class columns were added, in one file
+    let lines = [
+        "chr1\t100\t200\tTC\t2",
+        "chr1\t300\t400\tAT\t2\t2\tSimple_repeat",
+        "chr1\t500\t600\tA\t1\t1\tLow_complexity",
+        "chr1\t700\t800\tCAG\t3\t3\tSimple_repeat",
+    ];
+    let in_tsv = format!("{}/masks.tsv", data_directory);
+    write(&in_tsv, lines.map(|l| format!("{}\n", l)).concat()).expect("Can't Write Export");
+    prep_beds_with(
+        assembly,
+        &in_tsv,
+        data_type,
+        &PrepOptions::default(),
+        &data_directory,
+    )
+    .expect("BED Prep Failed");
+
+    // Both widths round trip
+    let out_tsv = format!("{}/regenerated.tsv", data_directory);
+    regenerate_export(assembly, data_type, &out_tsv, false, &data_directory)
+        .expect("Regenerating Export Failed");
+    let regenerated = read_to_string(&out_tsv).expect("Can't Read Regenerated Export");
+    assert_eq!(regenerated.lines().collect::<Vec<_>>(), lines);
+
+    let (filenames, bgz_dir, mut contig_index, index_file) =
+        prep_idx(assembly, data_type, false, &data_directory).expect("Index Prep Failed");
+    build_idx(&filenames, &bgz_dir, &mut contig_index, &index_file).expect("Indexing Failed");
+
+    let query = |options: &IdxQueryOptions| -> Vec<Value> {
+        let res = idx_query_with(
+            assembly,
+            data_type,
+            "chr1",
+            1,
+            1000,
+            options,
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+
+    // Only 7 column masks have a period and class
+    let hits = query(&IdxQueryOptions::default());
+    assert_eq!(hits.len(), 4);
+    assert!(hits[0].get("period").is_none() && hits[0].get("repeat_class").is_none());
+    assert_eq!(hits[1]["period"], 2);
+    assert_eq!(hits[1]["repeat_class"], "Simple_repeat");
+
+    let hits = query(
+        &IdxQueryOptions::builder()
+            .repeat_class("Simple_repeat")
+            .build(),
+    );
+    let starts: Vec<&Value> = hits.iter().map(|h| &h["seq_start"]).collect();
+    assert_eq!(starts, [300, 700]);
+    assert!(query(&IdxQueryOptions::builder().repeat_class("Satellite").build()).is_empty());
+
+    // The filtered TSV has the class columns, empty for masks without them, and the stored
+    // BED coordinates
+    let mut output = Vec::new();
+    bgzf_filter_with(
+        assembly,
+        data_type,
+        "chr1",
+        &mut output,
+        &FilterOptions::default(),
+        &data_directory,
+    )
+    .expect("Filter Failed");
+    let output = String::from_utf8(output).unwrap();
+    let mut output = output.lines();
+    assert!(output.next().unwrap().ends_with("\tperiod\trepeat_class"));
+    assert_eq!(output.next(), Some("chr1\t99\t200\tTC\t2\t\t"));
+
+    let _ = working_directory.close();
+}
+
+#[test]
+fn test_idx_query_repeat_class_annotations() {
+    assert_invalid_option(
+        idx_query_with(
+            TEST_ASSEMBLY,
+            ASSEMBLY_DIR,
+            "chr1",
+            30000,
+            31000,
+            &IdxQueryOptions::builder()
+                .repeat_class("Simple_repeat")
+                .build(),
+            test_data_dir(),
+        ),
+        "Only Masks Have A Repeat Class",
+    );
+}
+
+#[test]
+fn test_prepare_assembly() {
+    let working_directory = gen_working_dir();
+    let test_data_dir = working_directory.path().to_str().unwrap();
+
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_directory = test_data_dir.to_string();
+    let export_directory = TEST_EXPORT_DIR.to_string();
+
+    let _ = prepare_assembly(
+        assembly,
+        &data_directory,
+        &export_directory,
+        false,
+        &None,
+        &None,
+        DEFAULT_MAX_BUFFERED_MB * 1024 * 1024,
+        &None,
+        false,
+    );
+    let assembly_dir = format!("{}/{}", data_directory, TEST_ASSEMBLY);
 
     let align_dir = &format!("{}/{}", assembly_dir, ASSEMBLY_DIR);
     let mut align_contents = Path::new(&align_dir).read_dir().expect("Couldn't Read Dir");
@@ -331,59 +9093,531 @@ fn test_prepare_assembly() {
     assert!(Path::new(&format!("{}/{}_idx.dat", assembly_dir, ASSEMBLY_DIR)).exists());
     assert!(Path::new(&format!("{}/{}_idx.dat", assembly_dir, BENCHMARK_DIR)).exists());
     assert!(Path::new(&format!("{}/{}_idx.dat", assembly_dir, MASKS_DIR)).exists());
-    assert!(!align_contents.next().is_none());
-    assert!(!bench_contents.next().is_none());
-    assert!(!mask_contents.next().is_none());
-    assert!(!mlen_contents.next().is_none());
-    assert!(!seq_contents.next().is_none());
+    assert!(align_contents.next().is_some());
+    assert!(bench_contents.next().is_some());
+    assert!(mask_contents.next().is_some());
+    assert!(mlen_contents.next().is_some());
+    assert!(seq_contents.next().is_some());
+
+    let _c = working_directory.close();
+}
+
+// The SHA-256 of every file below a folder, by its path relative to the folder
+fn tree_digests(path: &str) -> BTreeMap<String, String> {
+    WalkDir::new(path)
+        .into_iter()
+        .map(|entry| entry.expect("Can't Walk Dir"))
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let bytes = std::fs::read(entry.path()).expect("Can't Read File");
+            let mut name = entry
+                .path()
+                .strip_prefix(path)
+                .unwrap()
+                .display()
+                .to_string();
+            // Versioned index files are named for when they were written
+            if is_versioned_index(&name) {
+                let stem = name.trim_end_matches(".dat").rsplit_once('.').unwrap().0;
+                name = format!("{}.dat", stem);
+            }
+            (name, format!("{:x}", Sha256::digest(&bytes)))
+        })
+        .collect()
+}
+
+#[test]
+fn test_plan_actions_match_prepare_assembly() {
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let export_directory = TEST_EXPORT_DIR.to_string();
+    let monolithic_directory = gen_working_dir();
+    let monolithic = monolithic_directory.path().to_str().unwrap().to_string();
+    // Not consolidated, the test model lengths and sequences name different assemblies
+    prepare_assembly(
+        assembly,
+        &monolithic,
+        &export_directory,
+        false,
+        &None,
+        &None,
+        DEFAULT_MAX_BUFFERED_MB * 1024 * 1024,
+        &None,
+        true,
+    )
+    .expect("Assembly Prep Failed");
+
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let plan = plan_assembly(assembly, &data_directory, &export_directory, &None)
+        .expect("Planning Failed");
+    assert!(plan
+        .iter()
+        .any(|action| action.element == SEQUENCE_DIR && action.step == PrepStep::CopyJson));
+    for action in plan
+        .iter()
+        .filter(|action| action.step == PrepStep::PrepBeds)
+    {
+        assert!(action.sequences_file.is_some());
+    }
+    // Planning writes nothing
+    assert!(!Path::new(&format!("{}/{}", data_directory, assembly)).exists());
+
+    let options = ActionOptions {
+        deterministic: true,
+        ..ActionOptions::default()
+    };
+    let run_all = |actions: Vec<PrepAction>| -> Vec<ActionReport> {
+        let handles: Vec<_> = actions
+            .into_iter()
+            .map(|action| {
+                let options = options.clone();
+                std::thread::spawn(move || run_action(&action, &options).expect("Action Failed"))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Action Panicked"))
+            .collect()
+    };
+    // The index data types first, before the sequences JSON they are sorted by is copied
+    let reports = run_all(plan.iter().rev().cloned().collect());
+    assert!(reports.iter().all(|report| !report.skipped));
+
+    // Finalizing needs a report of every action
+    assert_eq!(
+        finalize_assembly(
+            assembly,
+            &data_directory,
+            &plan,
+            &reports[1..],
+            false,
+            &None
+        )
+        .unwrap_err()
+        .kind(),
+        ErrorKind::InvalidInput
+    );
+    finalize_assembly(assembly, &data_directory, &plan, &reports, false, &None)
+        .expect("Finalizing Failed");
+    assert_eq!(tree_digests(&data_directory), tree_digests(&monolithic));
+
+    // Running the actions again changes nothing
+    let again = run_all(plan.clone());
+    assert!(again.iter().all(|report| report.skipped));
+    assert_eq!(tree_digests(&data_directory), tree_digests(&monolithic));
+    assert!(
+        plan_assembly(assembly, &data_directory, &export_directory, &None)
+            .expect("Planning Failed")
+            .is_empty()
+    );
+
+    // BED files split without an index are indexed
+    remove_file(format!(
+        "{}/{}/{}_idx.dat",
+        data_directory, assembly, MASKS_DIR
+    ))
+    .expect("Can't Remove Index");
+    let plan = plan_assembly(assembly, &data_directory, &export_directory, &None)
+        .expect("Planning Failed");
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].step, PrepStep::BuildIdx);
+    assert_eq!(plan[0].element, MASKS_DIR);
+    assert!(plan[0].source.is_none());
+    let reports = run_all(plan.clone());
+    finalize_assembly(assembly, &data_directory, &plan, &reports, false, &None)
+        .expect("Finalizing Failed");
+    assert_eq!(tree_digests(&data_directory), tree_digests(&monolithic));
 
     let _c = working_directory.close();
+    let _m = monolithic_directory.close();
+}
+
+// A HMMER3 text file of two models, the second without an accession, match states cut short
+const TEST_HMM: &str = "HMMER3/f [3.3.2 | Nov 2020]
+NAME  MIR
+ACC   DF000000001.4
+DESC  Mammalian-wide interspersed repeat
+LENG  262
+ALPH  DNA
+HMM          A        C        G        T
+            m->m     m->i     m->d     i->m     i->i     d->m     d->d
+  COMPO   1.35398  1.40599  1.32512  1.46752
+//
+HMMER3/f [3.3.2 | Nov 2020]
+NAME  myRepeat-1
+LENG  1340
+ALPH  DNA
+HMM          A        C        G        T
+  COMPO   1.30000  1.40000  1.40000  1.45000
+//
+";
+
+#[test]
+fn test_generate_model_lengths() {
+    let working_dir = gen_working_dir();
+    let working_path = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let hmm_dir = format!("{}/hmm", working_path);
+    create_dir_all(&hmm_dir).expect("Can't Create Dir");
+    let hmm_file = format!("{}/models.hmm", hmm_dir);
+    write(&hmm_file, TEST_HMM).expect("Can't Write HMM");
+    // Left by hmmpress, binary
+    write(
+        format!("{}/models.hmm.h3m", hmm_dir),
+        [0xe3, 0x80, 0xff, 0x00],
+    )
+    .expect("Can't Write");
+
+    let models = read_hmm_models(&hmm_file).expect("Can't Read HMM");
+    assert_eq!(
+        models.iter().map(|m| m.key()).collect::<Vec<&str>>(),
+        vec!["DF000000001", "myRepeat-1"]
+    );
+    assert_eq!(models[0].accession.as_deref(), Some("DF000000001.4"));
+    assert_eq!(
+        read_hmm_models(&hmm_dir).expect("Can't Read HMM Folder"),
+        models
+    );
+    let err = read_hmm_file(&format!("{}/models.hmm.h3m", hmm_dir)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let cut = format!("{}/cut.txt", working_path);
+    write(&cut, &TEST_HMM[..TEST_HMM.len() - 3]).expect("Can't Write HMM");
+    assert!(read_hmm_file(&cut)
+        .unwrap_err()
+        .to_string()
+        .contains("Ends Inside A Model"));
+
+    // Generated while preparing an assembly whose export has no model lengths
+    let data_directory = format!("{}/data", working_path);
+    let export_directory = format!("{}/exports", working_path);
+    create_dir_all(&data_directory).expect("Can't Create Dir");
+    create_dir_all(format!("{}/{}", export_directory, TEST_ASSEMBLY)).expect("Can't Create Dir");
+    prepare_assembly(
+        assembly,
+        &data_directory,
+        &export_directory,
+        false,
+        &Some(hmm_dir.clone()),
+        &None,
+        DEFAULT_MAX_BUFFERED_MB * 1024 * 1024,
+        &None,
+        false,
+    )
+    .expect("Assembly Prep Failed");
+    let json_file = format!(
+        "{}/{}/{}/{}{}",
+        data_directory, TEST_ASSEMBLY, MOD_LEN_DIR, TEST_ASSEMBLY, MOD_LEN_FILE
+    );
+    let json: Value = from_str(&read_to_string(&json_file).expect("No Model Lengths"))
+        .expect("Cannot Deserialize");
+    assert_eq!(
+        json,
+        json!({
+            "assembly": TEST_ASSEMBLY,
+            "version": "hmm",
+            "data": {
+                "DF000000001": {"length": 262, "name": "MIR"},
+                "myRepeat-1": {"length": 1340, "name": "myRepeat-1"},
+            }
+        })
+    );
+    let length = json_query(
+        assembly,
+        MOD_LEN_DIR,
+        "DF000000001",
+        &Some("length".to_string()),
+        &None,
+        &data_directory,
+    )
+    .expect("JSON Read Failed");
+    assert_eq!(length, "262");
+
+    // A model given twice is refused
+    write(format!("{}/again.hmm", hmm_dir), TEST_HMM).expect("Can't Write HMM");
+    let err = generate_model_lengths(&hmm_dir, assembly, &format!("{}/out.json", working_path))
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Model DF000000001 Appears More Than Once"));
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_family_meta() {
+    let working_dir = gen_working_dir();
+    let working_path = working_dir.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let data_directory = format!("{}/data", working_path);
+    build_test_assembly(
+        &data_directory,
+        ASSEMBLY_DIR,
+        &[
+            (
+                "DF000000001",
+                vec![annotation_line(
+                    "chr1",
+                    100,
+                    200,
+                    "DF000000001",
+                    "50",
+                    "+",
+                    "1",
+                )],
+            ),
+            (
+                "DF000000002",
+                vec![annotation_line(
+                    "chr1",
+                    300,
+                    400,
+                    "DF000000002",
+                    "50",
+                    "+",
+                    "1",
+                )],
+            ),
+        ],
+    );
+    let query = || -> Vec<Value> {
+        let res = idx_query_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "chr1",
+            1,
+            1000,
+            &IdxQueryOptions::builder().with_family_meta(true).build(),
+            &data_directory,
+        )
+        .expect("Index Query Failed");
+        from_str(&res).expect("Cannot Deserialize")
+    };
+    let filter = || -> Vec<String> {
+        let mut out = Vec::new();
+        bgzf_filter_with(
+            assembly,
+            ASSEMBLY_DIR,
+            "DF000000001",
+            &mut out,
+            &FilterOptions::builder().with_family_meta(true).build(),
+            &data_directory,
+        )
+        .expect("Filter Failed");
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    };
+
+    // Without a families JSON every family has null metadata
+    let hits = query();
+    assert_eq!(hits.len(), 2);
+    assert!(hits
+        .iter()
+        .all(|hit| hit["classification"].is_null() && hit["clades"].is_null()));
+    let lines = filter();
+    assert!(lines[0].ends_with("\tclassification\tclades"));
+    assert!(lines[1].ends_with("\t\t"));
+
+    // Copied in by prepare_assembly, keyed by versioned accession, one family left out
+    let export_directory = format!("{}/exports", working_path);
+    create_dir_all(format!("{}/{}", export_directory, TEST_ASSEMBLY)).expect("Can't Create Dir");
+    let families = json!({
+        "assembly": TEST_ASSEMBLY,
+        "data": {
+            "DF000000001.4": {
+                "name": "MIR",
+                "classification": "Interspersed_Repeat;Transposable_Element;Retrotransposon;SINE",
+                "clades": ["Mammalia", "Aves"],
+            },
+        },
+    });
+    write(
+        format!(
+            "{}/{}/{}{}",
+            export_directory, TEST_ASSEMBLY, TEST_ASSEMBLY, FAMILIES_FILE
+        ),
+        families.to_string(),
+    )
+    .expect("Can't Write Families");
+    prepare_assembly(
+        assembly,
+        &data_directory,
+        &export_directory,
+        false,
+        &None,
+        &None,
+        DEFAULT_MAX_BUFFERED_MB * 1024 * 1024,
+        &None,
+        false,
+    )
+    .expect("Assembly Prep Failed");
+    assert!(Path::new(&format!(
+        "{}/{}/{}/{}{}",
+        data_directory, TEST_ASSEMBLY, FAMILIES_DIR, TEST_ASSEMBLY, FAMILIES_FILE
+    ))
+    .exists());
+
+    let hits = query();
+    for hit in &hits {
+        match hit["accession"].as_str().unwrap() {
+            "DF000000001" => {
+                assert_eq!(
+                    hit["classification"],
+                    families["data"]["DF000000001.4"]["classification"]
+                );
+                assert_eq!(hit["clades"], json!(["Mammalia", "Aves"]));
+            }
+            _ => {
+                assert!(hit["classification"].is_null());
+                assert!(hit["clades"].is_null());
+            }
+        }
+    }
+    let lines = filter();
+    assert!(lines[1].ends_with(
+        "\tInterspersed_Repeat;Transposable_Element;Retrotransposon;SINE\tMammalia,Aves"
+    ));
+    let metadata = FamilyMetadata::load(assembly, &data_directory).expect("Can't Load Families");
+    assert_eq!(
+        metadata
+            .get("DF000000001.2")
+            .and_then(|m| m.name.as_deref()),
+        Some("MIR")
+    );
+    assert!(metadata.get("DF000000002").is_none());
+
+    let _ = working_dir.close();
+}
+
+#[test]
+fn test_status_file() {
+    let working_directory = gen_working_dir();
+    let data_directory = working_directory.path().to_str().unwrap().to_string();
+    let assembly = &TEST_ASSEMBLY.to_string();
+    let status_path = format!("{}/status.json", data_directory);
+    let read_status = || -> OperationStatus {
+        from_str(&read_to_string(&status_path).expect("No Status File"))
+            .expect("Cannot Deserialize")
+    };
+
+    let status = StatusFile::start(&status_path, "prepare-assembly").expect("Can't Start Status");
+    let started = read_status();
+    assert_eq!(started.operation, "prepare-assembly");
+    assert_eq!(started.state, STATE_RUNNING);
+    assert_eq!(started.pid, std::process::id());
+    assert!(started.finished_at.is_none());
+
+    let result = prepare_assembly(
+        assembly,
+        &data_directory,
+        TEST_EXPORT_DIR,
+        false,
+        &None,
+        &None,
+        DEFAULT_MAX_BUFFERED_MB * 1024 * 1024,
+        &Some(status.clone()),
+        false,
+    );
+    status.finish(&result);
+    result.expect("Assembly Prep Failed");
+    let finished = read_status();
+    assert_eq!(finished.state, STATE_COMPLETED);
+    assert!(!finished.phase.is_empty());
+    assert!(finished.finished_at.is_some());
+    assert!(finished.last_error.is_none());
+    assert_eq!(finished.started_at, started.started_at);
+    assert!(finished.updated_at >= started.updated_at);
+    // Nothing is left of the temporary files
+    let leftover = Path::new(&data_directory)
+        .read_dir()
+        .expect("Couldn't Read Dir")
+        .filter_map(|e| e.ok())
+        .any(|e| e.file_name().to_string_lossy().ends_with(".tmp"));
+    assert!(!leftover);
+
+    // A finished status is not written again
+    status.finish(&Err::<(), _>("Late"));
+    assert_eq!(read_status().state, STATE_COMPLETED);
+    drop(status);
+    assert_eq!(read_status().state, STATE_COMPLETED);
+
+    // Phases report their progress with the heartbeat
+    let status = StatusFile::start(&status_path, "build-idx").expect("Can't Start Status");
+    status.phase("build-idx", Some(4), "files");
+    status.progress(1);
+    std::thread::sleep(Duration::from_millis(2500));
+    let running = read_status();
+    assert_eq!((running.done, running.total), (1, Some(4)));
+    assert_eq!(running.percent, Some(25.0));
+    assert_eq!(running.unit, "files");
+
+    status.finish(&Err::<(), _>("Indexing Failed"));
+    let failed = read_status();
+    assert_eq!(failed.state, STATE_FAILED);
+    assert_eq!(failed.last_error, Some("Indexing Failed".to_string()));
+
+    // Stopping before finish leaves a failed status rather than a running one
+    let status = StatusFile::start(&status_path, "prep-beds").expect("Can't Start Status");
+    drop(status);
+    let stopped = read_status();
+    assert_eq!(stopped.state, STATE_FAILED);
+    assert_eq!(
+        stopped.last_error,
+        Some("Stopped Before Finishing".to_string())
+    );
+
+    let _ = working_directory.close();
 }
 
 #[test]
 fn test_read_family_assembly_annotation() {
-    let out_f = NamedTempFile::new_in(TEST_DATA_DIR).expect("Couldn't Open Output File");
     let id = &"DF000000001".to_string();
     let assembly_id = &TEST_ASSEMBLY.to_string();
     let nrph = &false;
-    let outfile = &Some(out_f.path().to_str().unwrap().to_string());
-    let data_directory = TEST_DATA_DIR.to_string();
+    let mut output = Vec::new();
+    let data_directory = test_data_dir().to_string();
 
-    let _ = read_family_assembly_annotations(id, assembly_id, nrph, outfile, &data_directory);
+    let count = read_family_assembly_annotations(
+        id,
+        assembly_id,
+        nrph,
+        &mut output,
+        &false,
+        &data_directory,
+    )
+    .expect("Filter Failed");
 
-    let reader = bgzf::Reader::new(File::open(out_f).expect("can't open"));
-    let line_count = reader.lines().count();
+    let line_count = output.lines().count();
+    assert_eq!(count, line_count - 1);
     assert_eq!(line_count, 195256);
 }
 
 #[test]
 fn test_read_family_assembly_annotation_nrph() {
-    let out_f = NamedTempFile::new_in(TEST_DATA_DIR).expect("Couldn't Open Output File");
-
     let id = &"DF000000001".to_string();
     let assembly_id = &TEST_ASSEMBLY.to_string();
     let nrph = &true;
-    let outfile = &Some(out_f.path().to_str().unwrap().to_string());
-    let data_directory = TEST_DATA_DIR.to_string();
+    let mut output = Vec::new();
+    let data_directory = test_data_dir().to_string();
 
-    let _ = read_family_assembly_annotations(id, assembly_id, nrph, outfile, &data_directory);
+    let count = read_family_assembly_annotations(
+        id,
+        assembly_id,
+        nrph,
+        &mut output,
+        &false,
+        &data_directory,
+    )
+    .expect("Filter Failed");
 
-    let reader = bgzf::Reader::new(File::open(out_f).expect("can't open"));
-    let line_count = reader.lines().count();
+    let line_count = output.lines().count();
+    assert_eq!(count, line_count - 1);
     assert_eq!(line_count, 195024);
 }
 
 // OLD
-// #[test]
-// fn test_get_chrom_id() {
-//     let assembly = &TEST_ASSEMBLY.to_string();
-//     let data_directory = TEST_DATA_DIR.to_string();
-//     let query = &"chr5".to_string();
-
-//     let val = get_chrom_id(assembly, query, &data_directory);
-//     assert_eq!(val, "54");
-// }
-
 // #[test]
 // fn test_process_json() {
 //     let out_f = NamedTempFile::new_in(TEST_DATA_DIR).expect("Couldn't Open Output File");